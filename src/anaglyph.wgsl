@@ -0,0 +1,31 @@
+/*
+Purpose: Red/cyan anaglyph composite of the two eye targets (stereo.rs)
+Responsibilites:
+    - fs_main: red from the left eye, green and blue from the right, pixel for pixel
+*/
+
+// Fullscreen triangle, no vertex buffer needed
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_left: texture_2d<f32>;
+@group(0) @binding(1)
+var t_right: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let left = textureLoad(t_left, pixel, 0);
+    let right = textureLoad(t_right, pixel, 0);
+    return vec4<f32>(left.r, right.g, right.b, 1.0);
+}
@@ -0,0 +1,154 @@
+/*
+Purpose: Show where keyframed objects are headed
+Responsibilities:
+    - Sample position tracks into polylines, looping tracks close their loop
+    - Tick the keyframes and mark the current playback position with a brighter cross
+    - Give every animated entity its own color, hashed from its id
+    - Rebuild the line buffer only when a track or the sampling changed, the marker moves every frame
+    - ex: the flight path on the seat-back screen, the little plane moving along it
+*/
+
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use cgmath::{Vector3, VectorSpace};
+
+use crate::{debug_lines::{DebugLinePipeline, LineBuffer, LineVertex, cross}, light_anim::Track};
+
+pub const DEFAULT_SAMPLES_PER_SECOND: f32 = 30.0;
+// Never fewer segments than this, however short the track
+const MIN_SAMPLES: usize = 8;
+// Cap for very long tracks, the buffer stays small
+const MAX_SAMPLES: usize = 4096;
+// Half-length of the keyframe ticks and of the playback marker's arms, in world units
+const TICK_SIZE: f32 = 0.08;
+const MARKER_SIZE: f32 = 0.45;
+const MARKER_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+type PositionTrack = Track<Vector3<f32>>;
+
+// Something with a position track, `id` only has to be unique and stable between frames
+pub struct PathEntity<'a> {
+    pub id: u64,
+    pub track: &'a PositionTrack,
+    // Where playback is on the track, in the track's seconds
+    pub time: f32,
+}
+
+pub struct AnimationPaths {
+    pub enabled: bool,
+    pub samples_per_second: f32,
+    hidden: HashSet<u64>,
+    // What the line buffer was last built from, compared every frame to catch edits
+    built: Option<(f32, Vec<(u64, PositionTrack)>)>,
+    lines: LineBuffer,
+    markers: LineBuffer,
+}
+
+impl Default for AnimationPaths {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            samples_per_second: DEFAULT_SAMPLES_PER_SECOND,
+            hidden: HashSet::new(),
+            built: None,
+            lines: LineBuffer::new("Animation Path Buffer"),
+            markers: LineBuffer::new("Animation Path Marker Buffer"),
+        }
+    }
+}
+
+impl AnimationPaths {
+    pub fn is_visible(&self, id: u64) -> bool {
+        !self.hidden.contains(&id)
+    }
+
+    pub fn set_visible(&mut self, id: u64, visible: bool) {
+        if visible {
+            self.hidden.remove(&id);
+        } else {
+            self.hidden.insert(id);
+        }
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, entities: &[PathEntity]) {
+        if !self.enabled {
+            return;
+        }
+        let visible: Vec<_> = entities.iter().filter(|entity| self.is_visible(entity.id)).collect();
+        let unchanged = self.built.as_ref().is_some_and(|(samples_per_second, tracks)| {
+            *samples_per_second == self.samples_per_second
+                && tracks.len() == visible.len()
+                && tracks.iter().zip(&visible).all(|((id, track), entity)| *id == entity.id && track == entity.track)
+        });
+        if !unchanged {
+            let mut vertices = Vec::new();
+            for entity in &visible {
+                path_lines(entity.track, self.samples_per_second, path_color(entity.id), &mut vertices);
+            }
+            self.lines.upload(device, queue, &vertices);
+            let tracks = visible.iter().map(|entity| (entity.id, entity.track.clone())).collect();
+            self.built = Some((self.samples_per_second, tracks));
+        }
+
+        let mut markers = Vec::new();
+        for entity in &visible {
+            if let Some(position) = entity.track.sample(entity.time, lerp) {
+                cross(position, MARKER_SIZE, MARKER_COLOR, &mut markers);
+            }
+        }
+        self.markers.upload(device, queue, &markers);
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, pipeline: &DebugLinePipeline, camera_bind_group: &wgpu::BindGroup) {
+        if !self.enabled {
+            return;
+        }
+        self.lines.draw(render_pass, pipeline, camera_bind_group);
+        self.markers.draw(render_pass, pipeline, camera_bind_group);
+    }
+}
+
+// Bright and saturated, so paths stand out against the scene and from each other
+pub fn path_color(id: u64) -> [f32; 3] {
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    let hue = (hasher.finish() % 360) as f32 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+fn lerp(a: Vector3<f32>, b: Vector3<f32>, t: f32) -> Vector3<f32> {
+    a.lerp(b, t)
+}
+
+fn path_lines(track: &PositionTrack, samples_per_second: f32, color: [f32; 3], out: &mut Vec<LineVertex>) {
+    let duration = track.duration();
+    let samples = ((duration * samples_per_second).ceil() as usize).clamp(MIN_SAMPLES, MAX_SAMPLES);
+    // A looping track sampled at its duration wraps to the start, the last key ends the path instead
+    let points: Vec<_> = (0..samples)
+        .filter_map(|i| track.sample(duration * i as f32 / samples as f32, lerp))
+        .chain(track.keys.last().map(|(_, position)| *position))
+        .collect();
+    let vertex = |position: Vector3<f32>| LineVertex { position: position.into(), color };
+    for pair in points.windows(2) {
+        out.extend([vertex(pair[0]), vertex(pair[1])]);
+    }
+    // Playback jumps from the last key back to the first, usually the same point for a seamless loop
+    if track.looping
+        && let (Some(&(_, first)), Some(&(_, last))) = (track.keys.first(), track.keys.last())
+        && first != last
+    {
+        out.extend([vertex(last), vertex(first)]);
+    }
+    for &(_, position) in &track.keys {
+        cross(position, TICK_SIZE, color, out);
+    }
+}
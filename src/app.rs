@@ -1,41 +1,264 @@
-
-use crate::state::{State};
+use crate::{benchmark::Benchmark, camera::Camera, camera_controller::ControllerProfile, config::{EngineConfig, RenderMode}, engine_events::EngineEvent, error_log::Severity, frame_pacer, gui_window, input_map::Action, render_context::RenderContext, state::State, title_bar, transform_gizmo::GizmoMode, view_window::{ViewKind, ViewWindow}};
 use pollster::FutureExt;
+use std::collections::{HashMap, HashSet};
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
-    event::{DeviceEvent, ElementState, KeyEvent, WindowEvent},
-    event_loop::ActiveEventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{CursorGrabMode, WindowAttributes},
+    event::{DeviceEvent, KeyEvent, MouseButton, StartCause, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow},
+    keyboard::{ModifiersState, PhysicalKey},
+    window::{CursorGrabMode, Window, WindowAttributes, WindowId},
 };
 
+// Try to confine or lock the cursor to the window
+fn grab_cursor(window: &Window) {
+    if window.set_cursor_grab(CursorGrabMode::Confined).is_err() {
+        // Fallback if platform doesn't support confinement
+        let _ = window.set_cursor_grab(CursorGrabMode::Locked);
+    }
+}
+
+// Grab or free the cursor of the primary window, what L toggles
+fn set_cursor_lock(view: &mut ViewWindow, locked: bool) {
+    if locked {
+        grab_cursor(view.window());
+    } else {
+        let _ = view.window().set_cursor_grab(CursorGrabMode::None);
+        view.release_input();
+    }
+    view.cursor_grabbed = locked;
+}
+
+// Snap `size` to the width:height `ratio`, keeping the side that changed most since `previous`
+// and never going below `min_size`
+fn aspect_corrected(size: PhysicalSize<u32>, previous: PhysicalSize<u32>, ratio: (u32, u32), min_size: (u32, u32)) -> PhysicalSize<u32> {
+    let (ratio_width, ratio_height) = (ratio.0 as f32, ratio.1 as f32);
+    let width_for = |height: u32| (height as f32 * ratio_width / ratio_height).round() as u32;
+    let height_for = |width: u32| (width as f32 * ratio_height / ratio_width).round() as u32;
+
+    let width_change = size.width.abs_diff(previous.width) as f32 / previous.width.max(1) as f32;
+    let height_change = size.height.abs_diff(previous.height) as f32 / previous.height.max(1) as f32;
+    let mut corrected = if width_change >= height_change {
+        PhysicalSize::new(size.width, height_for(size.width))
+    } else {
+        PhysicalSize::new(width_for(size.height), size.height)
+    };
+    if corrected.width < min_size.0 {
+        corrected = PhysicalSize::new(min_size.0, height_for(min_size.0));
+    }
+    if corrected.height < min_size.1 {
+        corrected = PhysicalSize::new(width_for(min_size.1), min_size.1);
+    }
+    corrected
+}
+
+// Ask the platform for a new window size. The view only ever follows sizes the window really
+// has: an immediately applied request is resized here, anything else waits for Resized, and a
+// denied request changes nothing.
+fn request_view_size(view: &mut ViewWindow, context: &RenderContext, size: PhysicalSize<u32>) {
+    if let Some(applied) = view.window().request_inner_size(size) {
+        view.resize(context, applied.width, applied.height);
+    }
+}
+
+// Draw one frame into `view`, recovering from a lost surface. The caller updates the scene first.
+fn render_frame(state: &mut State, view: &mut ViewWindow, event_loop: &ActiveEventLoop) {
+    match state.render(view) {
+        Ok(_) => view.pacer.presented(std::time::Instant::now()),
+        // Reconfigure the surface if it's lost or outdated
+        Err(
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+        ) => view.resize(&state.context, view.size.width, view.size.height),
+        // The system is out of memory, we should probably quit
+        Err(wgpu::SurfaceError::OutOfMemory) => {
+            log::error!("OutOfMemory");
+            state.report_error(Severity::Error, "Surface out of memory");
+            event_loop.exit();
+        }
+        // This happens when the a frame takes too long to present
+        Err(wgpu::SurfaceError::Timeout) => {
+            log::warn!("Surface timeout");
+            state.report_error(Severity::Warning, "Surface timeout");
+        }
+        // Default error
+        Err(e) => {
+            log::error!("Unable to render {}", e);
+            state.report_error(Severity::Error, format!("Unable to render: {}", e));
+        }
+    }
+}
+
 pub struct App {
+    config: EngineConfig,
+    // Present while running with --benchmark, input is ignored during the run
+    benchmark: Option<Benchmark>,
+    // Set when the window/surface couldn't be created and the benchmark should run offscreen
+    pub headless_fallback: bool,
+    pub failed: bool,
     state: Option<State>,
+    windows: HashMap<WindowId, ViewWindow>,
+    primary_window: Option<WindowId>,
+    focused_window: Option<WindowId>,
+    // What the user asked for with L, restored when the primary window gets focus back
     cursor_locked: bool,
+    // While the shortcut help is open: whether the cursor was locked when it opened
+    help_cursor_lock: Option<bool>,
+    // Windows drawn since the event loop last went idle. A drag-resize sends a storm of
+    // Resized events, each window renders at most once per loop iteration.
+    rendered_this_iteration: HashSet<WindowId>,
+    // Held modifier keys, for the Ctrl shortcuts
+    modifiers: ModifiersState,
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(config: EngineConfig) -> Self {
         Self {
+            benchmark: config.benchmark_seconds.map(Benchmark::new),
+            config,
+            headless_fallback: false,
+            failed: false,
             state: None,
+            windows: HashMap::new(),
+            primary_window: None,
+            focused_window: None,
             cursor_locked: false,
+            help_cursor_lock: None,
+            rendered_this_iteration: HashSet::new(),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    // Opens a secondary window looking at the scene from another angle.
+    // It shares the primary window's device, pipelines, and loaded assets.
+    pub fn open_inspector_window(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+        let window_attributes = WindowAttributes::default()
+            .with_title("Rusty Engine - Inspector")
+            .with_inner_size(PhysicalSize::new(480, 360));
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => window,
+            Err(e) => {
+                log::error!("Unable to open inspector window: {}", e);
+                return;
+            }
+        };
+        // Look down on the scene from above and to the side
+        let distance = 8.0 * state.units().units_per_meter();
+        let camera = Camera::new((distance, distance, distance), cgmath::Deg(-135.0), cgmath::Deg(-35.0));
+        let view = match state.create_view(window, ViewKind::Inspector, camera) {
+            Ok(view) => view,
+            Err(e) => {
+                log::error!("Unable to create the inspector window's surface: {}", e);
+                return;
+            }
+        };
+        view.window().request_redraw();
+        self.windows.insert(view.window().id(), view);
+    }
+
+    // Window or surface creation failed. Benchmarks can continue offscreen, anything else is fatal.
+    fn startup_failed(&mut self, event_loop: &ActiveEventLoop, error: &dyn std::fmt::Display) {
+        if self.config.diagnostics {
+            log::warn!("Unable to create a window surface ({}), reporting without one", error);
+            self.headless_fallback = true;
+        } else if self.benchmark.is_some() {
+            log::warn!("Unable to create a window surface ({}), running the benchmark offscreen", error);
+            self.headless_fallback = true;
+        } else {
+            log::error!("Unable to start: {}", error);
+            self.failed = true;
+        }
+        event_loop.exit();
+    }
+
+    // The shortcut help frees the cursor to type into its filter, and locks it again once it is
+    // closed, however that happened
+    fn sync_help_cursor(&mut self) {
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+        let Some(view) = self.primary_window.and_then(|id| self.windows.get_mut(&id)) else {
+            return;
+        };
+        match (state.show_help, self.help_cursor_lock) {
+            (true, None) => {
+                self.help_cursor_lock = Some(self.cursor_locked);
+                if self.cursor_locked {
+                    self.cursor_locked = false;
+                    set_cursor_lock(view, false);
+                }
+            }
+            (false, Some(locked)) => {
+                self.help_cursor_lock = None;
+                if locked && !self.cursor_locked {
+                    self.cursor_locked = true;
+                    set_cursor_lock(view, true);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn close_window(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId) {
+        if Some(window_id) == self.primary_window {
+            event_loop.exit();
+        } else {
+            // Dropping the view releases its surface, depth texture, and egui renderer
+            self.windows.remove(&window_id);
+            if self.focused_window == Some(window_id) {
+                self.focused_window = None;
+            }
         }
     }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.state.is_some() {
+            return;
+        }
+        let (min_width, min_height) = self.config.min_inner_size;
+        let mut inner_size = PhysicalSize::new(800u32.max(min_width), 600u32.max(min_height));
+        if let Some(ratio) = self.config.aspect_ratio_lock {
+            inner_size = aspect_corrected(inner_size, PhysicalSize::new(0, inner_size.height), ratio, self.config.min_inner_size);
+        }
+        let custom_title_bar = self.config.custom_titlebar && title_bar::supported();
+        if self.config.custom_titlebar && !custom_title_bar {
+            log::info!("Moving an undecorated window isn't supported here, keeping the system title bar");
+        }
         let window_attributes = WindowAttributes::default()
             .with_title("Rusty Engine")
-            .with_inner_size(PhysicalSize::new(800, 600));
-        let window = event_loop.create_window(window_attributes).unwrap();
-        // Try to confine or lock the cursor to the window
-        if window.set_cursor_grab(CursorGrabMode::Confined).is_err() {
-            // Fallback if platform doesn't support confinement
-            let _ = window.set_cursor_grab(CursorGrabMode::Locked);
+            .with_inner_size(inner_size)
+            .with_min_inner_size(PhysicalSize::new(min_width, min_height))
+            .with_decorations(!custom_title_bar);
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => window,
+            Err(e) => return self.startup_failed(event_loop, &e),
+        };
+        // Grab the cursor (not while benchmarking, input is ignored anyway)
+        if self.benchmark.is_none() {
+            grab_cursor(&window);
+            self.cursor_locked = true;
+        }
+        let (state, mut view) = match State::new(window, &self.config).block_on() {
+            Ok(result) => result,
+            Err(e) => return self.startup_failed(event_loop, &e),
+        };
+        view.custom_title_bar = custom_title_bar;
+        view.cursor_grabbed = self.cursor_locked;
+        if self.config.diagnostics {
+            println!("{}", state.diagnostics_report(Some(&view)));
+            event_loop.exit();
+            return;
         }
-        self.state = Some(State::new(window).block_on());
+        view.window().request_redraw();
+        let id = view.window().id();
+        self.primary_window = Some(id);
+        self.focused_window = Some(id);
+        self.windows.insert(id, view);
+        self.state = Some(state);
     }
 
     fn device_event(
@@ -44,137 +267,350 @@ impl ApplicationHandler for App {
             _device_id: winit::event::DeviceId,
             event: DeviceEvent,
         ) {
-        let state = if let Some(state) = &mut self.state {
-            state
-        } else {
+        // Ignored during benchmarks and while a video export holds the camera
+        if self.benchmark.is_some() || self.state.as_ref().is_some_and(State::is_exporting_video) {
+            return;
+        }
+        // Raw mouse motion has no window, so it drives whichever window has focus
+        let Some(view) = self.focused_window.and_then(|id| self.windows.get_mut(&id)) else {
             return;
         };
-        match event {
-            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-                if state.mouse_pressed {
-                    state.controller.handle_mouse(dx, dy);
-                }
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event
+            && view.is_dragging() {
+                view.controller.handle_mouse(dx, dy);
+                view.window().request_redraw();
+            }
+    }
+
+    // Woken up for an egui repaint or a capped frame that was scheduled for later
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            let now = std::time::Instant::now();
+            let due = |view: &&ViewWindow| view.needs_redraw() || view.pacer.wake_at().is_some_and(|at| at <= now);
+            for view in self.windows.values().filter(due) {
+                view.window().request_redraw();
+            }
+        }
+    }
+
+    // Decide how long the event loop may sleep. Continuous rendering keeps requesting redraws
+    // itself, on demand it waits for the next event or egui's next scheduled repaint.
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        self.rendered_this_iteration.clear();
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        state.end_iteration();
+        if state.take_redraw_request() {
+            for view in self.windows.values() {
+                view.window().request_redraw();
             }
-            _ => {}
         }
+        let wake_at = self
+            .windows
+            .values()
+            .flat_map(|view| [view.egui_repaint_at(), view.pacer.wake_at()])
+            .flatten()
+            .min();
+        event_loop.set_control_flow(wake_at.map_or(ControlFlow::Wait, ControlFlow::WaitUntil));
     }
 
     fn window_event(
             &mut self,
             event_loop: &ActiveEventLoop,
-            _window_id: winit::window::WindowId,
+            window_id: WindowId,
             event: WindowEvent,
         ) {
-            if let Some(state) = self.state.as_mut() {
-                // Let egui process the event, capture flag tells us if it "ate" it
-                let captured = state.handle_input(&state.window.clone(), &event);
+            let Some(view) = self.windows.get_mut(&window_id) else {
+                return;
+            };
 
-                if captured {
-                    // Do NOT forward to camera/light/game if egui is using this input
-                    return;
-                }
+            // Benchmarks run without user input so every run is comparable
+            let is_input = matches!(
+                event,
+                WindowEvent::KeyboardInput { .. } | WindowEvent::MouseInput { .. } | WindowEvent::MouseWheel { .. }
+            );
+            if self.benchmark.is_some() && is_input {
+                return;
+            }
+
+            // On demand, anything that happens to a window (input, resize, focus) is worth a frame
+            if !matches!(event, WindowEvent::RedrawRequested) {
+                view.window().request_redraw();
+            }
+
+            // Let egui process the event, capture flag tells us if it "ate" it
+            let captured = view.handle_input(&event);
 
-                match event {
-                    WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => event_loop.exit(),
-                    WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::KeyL),
-                                ..
-                            },
-                        ..
-                    } => {
+            if captured {
+                // Do NOT forward to camera/light/game if egui is using this input
+                return;
+            }
+            // A video export holds the camera until it ends, egui still gets its Cancel button
+            if is_input && self.state.as_ref().is_some_and(State::is_exporting_video) {
+                return;
+            }
+
+            match event {
+                WindowEvent::CloseRequested => self.close_window(event_loop, window_id),
+                WindowEvent::Focused(focused) => {
+                    let owns_grab = view.kind == ViewKind::Primary && self.cursor_locked;
+                    if focused {
+                        self.focused_window = Some(window_id);
+                        if owns_grab {
+                            grab_cursor(view.window());
+                        }
+                    } else {
+                        if self.focused_window == Some(window_id) {
+                            self.focused_window = None;
+                        }
+                        view.release_input();
                         if let Some(state) = self.state.as_mut() {
-                            let window = state.window();
-                            if self.cursor_locked {
-                                // Unlock
-                                let _ = window.set_cursor_grab(CursorGrabMode::None);
-                                self.cursor_locked = false;
-                            } else {
-                                // Lock
-                                if window.set_cursor_grab(CursorGrabMode::Confined).is_err() {
-                                    let _ = window.set_cursor_grab(CursorGrabMode::Locked);
-                                }
-                                self.cursor_locked = true;
+                            state.release_keys();
+                        }
+                        if owns_grab {
+                            let _ = view.window().set_cursor_grab(CursorGrabMode::None);
+                        }
+                    }
+                    // Moving focus between our own windows doesn't pause, only leaving the app does
+                    if self.benchmark.is_none()
+                        && let Some(state) = self.state.as_mut() {
+                            state.paused = state.pause_on_focus_loss && self.focused_window.is_none();
+                            state.in_background = self.focused_window.is_none();
+                        }
+                }
+                WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+                WindowEvent::CursorLeft { .. } => view.release_input(),
+                // Dragged onto another monitor maybe, refresh pacing follows its rate
+                WindowEvent::Moved(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                    view.pacer.set_refresh_rate(frame_pacer::monitor_refresh_rate(view.window()));
+                }
+                WindowEvent::RedrawRequested => {
+                    let Some(state) = self.state.as_mut() else {
+                        return;
+                    };
+                    // Too early for the FPS cap or refresh pacing, about_to_wait sleeps until the
+                    // frame is due. Benchmarks measure the uncapped rate.
+                    let pace = if self.benchmark.is_some() { None } else { state.frame_pace(view.pacer.refresh_rate()) };
+                    if view.pacer.defer(std::time::Instant::now(), pace) {
+                        return;
+                    }
+                    // Already drawn by a resize in this iteration, draw again in the next one
+                    if !self.rendered_this_iteration.insert(window_id) {
+                        view.window().request_redraw();
+                        return;
+                    }
+                    state.update_for_view();
+                    render_frame(state, view, event_loop);
+                    if state.take_quit_request() {
+                        event_loop.exit();
+                        return;
+                    }
+
+                    // Menu edits in the primary window can change what every other window shows
+                    let ui_active = view.egui_repaint_at().is_some_and(|at| at <= std::time::Instant::now());
+                    if view.kind == ViewKind::Primary && ui_active {
+                        state.request_redraw();
+                    }
+                    // Continuous mode, a running benchmark, and anything still animating keep drawing every frame
+                    let continuous = self.benchmark.is_some()
+                        || state.render_mode == RenderMode::Continuous
+                        || state.is_animating();
+                    if continuous || view.needs_redraw() {
+                        view.window().request_redraw();
+                    }
+
+                    // Resolution presets from the menu, applied once the frame is out
+                    if let Some(mut size) = state.take_window_size_request() {
+                        if let Some(ratio) = self.config.aspect_ratio_lock {
+                            size = aspect_corrected(size, PhysicalSize::new(0, size.height), ratio, self.config.min_inner_size);
+                        }
+                        request_view_size(view, &state.context, size);
+                    }
+
+                    if view.kind == ViewKind::Primary
+                        && let Some(benchmark) = self.benchmark.as_mut()
+                        && benchmark.record_frame() {
+                            println!("{}", benchmark.report_json("windowed"));
+                            event_loop.exit();
+                        }
+                    // The help overlay can close itself
+                    self.sync_help_cursor();
+                }
+                // Presses look up a shortcut in the InputMap, held keys that aren't one drive the camera
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(code),
+                            state: key_state,
+                            repeat,
+                            ..
+                        },
+                    ..
+                } => {
+                    let Some(state) = self.state.as_mut() else {
+                        return;
+                    };
+                    state.set_key_held(code, key_state.is_pressed());
+                    let primary = view.kind == ViewKind::Primary;
+                    // Gizmo modes only while the cursor is free to drag handles, otherwise W flies forward
+                    let selection = primary && !self.cursor_locked && state.has_selection();
+                    let action = key_state.is_pressed().then(|| state.input_map.pressed(code, self.modifiers, selection)).flatten();
+                    let Some(action) = action else {
+                        if let Some(action) = state.input_map.held(code) {
+                            view.handle_move(action, key_state.is_pressed());
+                        }
+                        return;
+                    };
+                    // Repeats are swallowed too, holding a gizmo key doesn't start flying
+                    if repeat {
+                        return;
+                    }
+                    match action {
+                        Action::CloseWindow if state.is_placing() => state.cancel_placement(),
+                        Action::CloseWindow if state.show_help => state.show_help = false,
+                        Action::CloseWindow if state.console.open => state.console.open = false,
+                        Action::CloseWindow => return self.close_window(event_loop, window_id),
+                        Action::ToggleCursorLock if primary => {
+                            self.cursor_locked = !self.cursor_locked;
+                            set_cursor_lock(view, self.cursor_locked);
+                        }
+                        Action::ToggleMenu => {
+                            state.toggle_gui_window(gui_window::SETTINGS_WINDOW);
+                        }
+                        Action::ToggleHelp => state.show_help = !state.show_help,
+                        Action::ToggleConsole if primary => state.console.toggle(),
+                        Action::ToggleFrameStats => {
+                            state.toggle_gui_window(gui_window::STATS_WINDOW);
+                        }
+                        // Into the working directory
+                        Action::SaveDepth => {
+                            let seconds = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map_or(0, |elapsed| elapsed.as_secs());
+                            let path = std::path::PathBuf::from(format!("depth-{}.png", seconds));
+                            if let Err(e) = state.capture_depth(view, &path) {
+                                log::error!("Could not capture the depth buffer: {}", e);
                             }
                         }
-                    },
-                    WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::KeyT),
-                                ..
-                            },
-                        ..
-                    } => {
-                        if let Some(state) = self.state.as_mut() {
-                            state.show_menu = !state.show_menu; // Toggle menu on/off
+                        Action::OpenInspector => return self.open_inspector_window(event_loop),
+                        Action::Duplicate if primary => {
+                            state.duplicate_selected();
                         }
+                        Action::Despawn if primary => {
+                            state.despawn_selected();
+                        }
+                        Action::Undo if primary => {
+                            state.undo();
+                        }
+                        Action::Redo if primary => {
+                            state.redo();
+                        }
+                        // Either frames everything when nothing is selected
+                        Action::FrameSelection => {
+                            state.frame_selection(view);
+                        }
+                        Action::FrameModel => {
+                            state.frame_selected_model(view);
+                        }
+                        Action::GizmoMove => state.transform_gizmo.mode = GizmoMode::Translate,
+                        Action::GizmoRotate => state.transform_gizmo.mode = GizmoMode::Rotate,
+                        Action::GizmoScale => state.transform_gizmo.mode = GizmoMode::Scale,
+                        Action::PlaceLight if primary => state.begin_light_placement(),
+                        Action::Measure if primary => state.toggle_measuring(),
+                        Action::PasteTexture if primary => {
+                            state.paste_clipboard_texture();
+                        }
+                        Action::CameraFreeFly => state.set_controller_profile(ControllerProfile::FreeFly),
+                        Action::CameraOrbit => state.set_controller_profile(ControllerProfile::Orbit),
+                        Action::CameraTopDown => state.set_controller_profile(ControllerProfile::TopDown),
+                        Action::CameraWalk => state.set_controller_profile(ControllerProfile::Walk),
+                        Action::ResetRoll => view.reset_roll(),
+                        _ => {}
                     }
-                    WindowEvent::RedrawRequested => {
-                                state.window().request_redraw();
-                                state.update();
-                            match state.render(state.window.clone(), &state.device.clone(), &state.queue.clone()) {
-                                Ok(_) => {}
-                                // Reconfigure the surface if it's lost or outdated
-                                Err(
-                                    wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
-                                ) => state.resize(state.size.width, state.size.height),
-                                // The system is out of memory, we should probably quit
-                                Err(wgpu::SurfaceError::OutOfMemory) => {
-                                    log::error!("OutOfMemory");
-                                    event_loop.exit();
-                                }
-                                // This happens when the a frame takes too long to present
-                                Err(wgpu::SurfaceError::Timeout) => {
-                                    log::warn!("Surface timeout")
-                                }
-                                // Default error
-                                Err(e) => {
-                                    log::error!("Unable to render {}", e)
+                    self.sync_help_cursor();
+                }
+                WindowEvent::Resized(physical_size) => {
+                    if let Some(state) = self.state.as_mut() {
+                        let previous = view.size;
+                        // The surface and projection always follow the size the window has now,
+                        // even if it is about to be corrected below
+                        view.resize(&state.context, physical_size.width, physical_size.height);
+                        if view.kind == ViewKind::Primary && physical_size != previous {
+                            state.push_event(EngineEvent::WindowResized { width: physical_size.width, height: physical_size.height });
+                        }
+                        if view.kind == ViewKind::Primary
+                            && let Some(ratio) = self.config.aspect_ratio_lock {
+                                let corrected = aspect_corrected(physical_size, previous, ratio, self.config.min_inner_size);
+                                // Off by a pixel is rounding, asking again would never settle
+                                let settled = corrected.width.abs_diff(physical_size.width) <= 1
+                                    && corrected.height.abs_diff(physical_size.height) <= 1;
+                                if !settled {
+                                    request_view_size(view, &state.context, corrected);
                                 }
                             }
+                        // Draw at the new size right away instead of letting the compositor
+                        // stretch the old frame until a redraw arrives, which some platforms
+                        // hold back for the whole drag. The projection was updated above.
+                        if self.rendered_this_iteration.insert(window_id) {
+                            state.update_for_view();
+                            render_frame(state, view, event_loop);
+                        }
                     }
-                    WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                physical_key: PhysicalKey::Code(code),
-                                state: key_state,
-                                ..
-                            },
-                        ..
-                    } => {
-                        state.handle_key(event_loop, code, key_state.is_pressed());
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    view.handle_cursor_moved(position.x, position.y);
+                }
+                WindowEvent::MouseInput {
+                    state: btn_state,
+                    button,
+                    ..
+                } => {
+                    // Clicks on the orientation gizmo snap the camera instead of starting a mouse look
+                    let gizmo_shown = self.state.as_ref().is_some_and(|state| state.show_gizmo);
+                    if gizmo_shown && button == MouseButton::Left && btn_state.is_pressed() && view.click_gizmo() {
+                        return;
                     }
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(physical_size.width, physical_size.height);
+                    // While placing, clicks in the main window's scene place or cancel instead of turning the camera
+                    if view.kind == ViewKind::Primary
+                        && btn_state.is_pressed()
+                        && let Some(state) = self.state.as_mut()
+                        && state.is_placing()
+                    {
+                        match button {
+                            MouseButton::Left => state.place_at_cursor(view, self.modifiers.shift_key()),
+                            MouseButton::Right => state.cancel_placement(),
+                            _ => {}
+                        }
+                        return;
                     }
-                    WindowEvent::MouseInput {
-                        state: btn_state,
-                        button,
-                        ..
-                    } => {
-                        state.handle_mouse_button(button, btn_state.is_pressed());
+                    view.handle_mouse_button(button, btn_state.is_pressed());
+                    // A left click that didn't turn the camera selects what is under the cursor
+                    if view.kind == ViewKind::Primary
+                        && button == MouseButton::Left
+                        && !btn_state.is_pressed()
+                        && let Some(position) = view.take_click()
+                        && let Some(state) = self.state.as_mut()
+                    {
+                        state.select_at(view, position);
                     }
-                    WindowEvent::MouseWheel {
-                        delta,
-                        ..
-                    } => {
-                        state.handle_mouse_scroll(&delta);
+                    // A right click sends the click-to-move demo's actor there, when it is shown
+                    if view.kind == ViewKind::Primary
+                        && button == MouseButton::Right
+                        && !btn_state.is_pressed()
+                        && let Some(position) = view.take_right_click()
+                        && let Some(state) = self.state.as_mut()
+                    {
+                        state.move_actor_to(view, position);
                     }
-                    _ => {}
                 }
+                WindowEvent::MouseWheel {
+                    delta,
+                    ..
+                } => {
+                    view.handle_mouse_scroll(&delta);
+                }
+                _ => {}
             }
-            
     }
 }
@@ -0,0 +1,156 @@
+/*
+Purpose: Find asset files no matter where the engine was started from
+Responsibilities:
+    - Keep an ordered list of folders to look in: --asset-root folders, res/ next to the executable,
+      and in debug builds the repository's res/ and the copy the build script makes
+    - Fall back to the default cube, its material and textures compiled into the binary
+    - Log where each request was answered from
+    - ex: the phone book, try every number in order and keep the operator as the last resort
+*/
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// The bare minimum for the default scene, so the engine starts without any res/ folder
+const EMBEDDED: [(&str, &[u8]); 4] = [
+    ("cube.obj", include_bytes!("../res/cube.obj")),
+    ("cube.mtl", include_bytes!("../res/cube.mtl")),
+    ("cube-diffuse.jpg", include_bytes!("../res/cube-diffuse.jpg")),
+    ("cube-normal.png", include_bytes!("../res/cube-normal.png")),
+];
+
+static SOURCE: OnceLock<AssetSource> = OnceLock::new();
+
+// Where a file was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetOrigin {
+    Directory(PathBuf),
+    Embedded,
+}
+
+impl std::fmt::Display for AssetOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetOrigin::Directory(root) => write!(f, "{}", root.display()),
+            AssetOrigin::Embedded => write!(f, "the assets built into the binary"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetSource {
+    // Searched in order, the first folder holding the file wins
+    roots: Vec<PathBuf>,
+}
+
+impl AssetSource {
+    // `user_roots` come first so they can override any bundled file
+    pub fn new(user_roots: &[PathBuf]) -> Self {
+        let mut roots = user_roots.to_vec();
+        if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+            roots.push(exe_dir.join("res"));
+        }
+        // Paths on the machine that built the binary, only worth trying during development
+        if cfg!(debug_assertions) {
+            roots.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("res"));
+            roots.push(Path::new(env!("OUT_DIR")).join("res"));
+        }
+        roots.dedup();
+        Self { roots }
+    }
+
+    // `file_name` is relative to a root, e.g. "cube.obj" or "fonts/ui.ttf"
+    pub fn read(&self, file_name: &str) -> anyhow::Result<(Vec<u8>, AssetOrigin)> {
+        for root in &self.roots {
+            match std::fs::read(root.join(file_name)) {
+                Ok(data) => return Ok((data, AssetOrigin::Directory(root.clone()))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => log::warn!("Could not read {} from {}: {}", file_name, root.display(), e),
+            }
+        }
+        if let Some((_, data)) = EMBEDDED.iter().find(|(name, _)| Path::new(name) == Path::new(file_name)) {
+            return Ok((data.to_vec(), AssetOrigin::Embedded));
+        }
+        anyhow::bail!("{} not found in {} and not built into the binary", file_name, self.describe_roots())
+    }
+
+    // The file on disk a read would use, None when it only exists built into the binary
+    pub fn resolve(&self, file_name: &str) -> Option<PathBuf> {
+        self.roots.iter().map(|root| root.join(file_name)).find(|path| path.is_file())
+    }
+
+    // Names of the files directly in `dir` (relative to a root, "" for the roots themselves) in
+    // any root or built into the binary, sorted
+    pub fn list(&self, dir: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .roots
+            .iter()
+            .filter_map(|root| std::fs::read_dir(root.join(dir)).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().filter(|entry| entry.path().is_file()))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        let embedded = EMBEDDED.iter().map(|(name, _)| Path::new(name)).filter(|name| name.parent() == Some(Path::new(dir)));
+        names.extend(embedded.filter_map(|name| name.file_name()).map(|name| name.to_string_lossy().into_owned()));
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn describe_roots(&self) -> String {
+        let roots: Vec<String> = self.roots.iter().map(|root| root.display().to_string()).collect();
+        if roots.is_empty() { "any asset folder".to_string() } else { roots.join(", ") }
+    }
+}
+
+// Set once at startup, before anything loads. Later calls are ignored.
+pub fn install(source: AssetSource) {
+    log::info!("Looking for assets in {}, then the built-in ones", source.describe_roots());
+    if SOURCE.set(source).is_err() {
+        log::warn!("Asset source already set, keeping the first one");
+    }
+}
+
+// The installed source, or the default search path without user folders
+pub fn current() -> &'static AssetSource {
+    SOURCE.get_or_init(|| AssetSource::new(&[]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty folder of its own under the system temp dir
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rusty-engine-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn a_root_without_the_file_falls_back_to_the_embedded_one() {
+        let root = temp_root("assets");
+        let previous = std::env::current_dir().unwrap();
+        // Started from somewhere else entirely
+        std::env::set_current_dir(&root).unwrap();
+        let source = AssetSource { roots: vec![root.clone()] };
+        let read = source.read("cube.obj");
+        let resolved = source.resolve("cube.obj");
+        let missing = source.read("missing.obj");
+        std::env::set_current_dir(previous).unwrap();
+
+        let (data, origin) = read.unwrap();
+        assert_eq!((data.as_slice(), origin), (EMBEDDED[0].1, AssetOrigin::Embedded));
+        // Nothing on disk to watch or hand to a file dialog
+        assert_eq!(resolved, None);
+        assert!(missing.unwrap_err().to_string().contains(&root.display().to_string()));
+        assert_eq!(source.list(""), ["cube-diffuse.jpg", "cube-normal.png", "cube.mtl", "cube.obj"]);
+
+        // A copy in the root wins
+        std::fs::write(root.join("cube.obj"), "o Override\n").unwrap();
+        assert_eq!(source.read("cube.obj").unwrap(), (b"o Override\n".to_vec(), AssetOrigin::Directory(root.clone())));
+        assert_eq!(source.resolve("cube.obj"), Some(root.join("cube.obj")));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+/*
+Purpose: Benchmark mode
+Responsibilities:
+    - Collect frame times while the engine runs without input
+    - Summarize them (avg/p50/p99) as JSON when the run ends
+    - Render offscreen when no window surface can be created, culling the instances like the
+      main window and logging what the culling dropped
+    - ex: the stopwatch held next to the engine
+*/
+
+use crate::{camera::{Camera, CameraUniform, Projection}, config::EngineConfig, gpu_memory, gpu_timer::{GpuPass, GpuTimer}, hdr::HdrTargets, hiz::HiZTargets, shape_lod::LodView, state::State, texture};
+use pollster::FutureExt;
+use std::time::{Duration, Instant};
+
+// Size of the offscreen target used by the headless fallback
+const HEADLESS_WIDTH: u32 = 800;
+const HEADLESS_HEIGHT: u32 = 600;
+
+pub struct Benchmark {
+    duration: Duration,
+    started: Option<Instant>,
+    last_frame: Option<Instant>,
+    frame_times_ms: Vec<f32>,
+}
+
+impl Benchmark {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            duration: Duration::from_secs_f32(seconds),
+            started: None,
+            last_frame: None,
+            frame_times_ms: Vec::new(),
+        }
+    }
+
+    // Record that a frame was finished. Returns true once the benchmark has run long enough.
+    pub fn record_frame(&mut self) -> bool {
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(now);
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times_ms.push(now.duration_since(last_frame).as_secs_f32() * 1000.0);
+        }
+        self.last_frame = Some(now);
+        now.duration_since(started) >= self.duration
+    }
+
+    pub fn report_json(&self, mode: &str) -> String {
+        let mut sorted = self.frame_times_ms.clone();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| {
+            if sorted.is_empty() {
+                0.0
+            } else {
+                sorted[((sorted.len() - 1) as f32 * p).round() as usize]
+            }
+        };
+        let avg = if sorted.is_empty() {
+            0.0
+        } else {
+            sorted.iter().sum::<f32>() / sorted.len() as f32
+        };
+
+        format!(
+            "{{\"mode\":\"{}\",\"frames\":{},\"avg_ms\":{:.3},\"p50_ms\":{:.3},\"p99_ms\":{:.3},\"avg_fps\":{:.1}}}",
+            mode,
+            sorted.len(),
+            avg,
+            percentile(0.5),
+            percentile(0.99),
+            if avg > 0.0 { 1000.0 / avg } else { 0.0 },
+        )
+    }
+}
+
+// Runs the benchmark against an offscreen target. Used when there is no display
+// or the window surface can't be created. Returns the JSON report.
+pub fn run_headless(config: &EngineConfig, seconds: f32) -> anyhow::Result<String> {
+    let mut state = State::new_headless(config).block_on()?;
+    let context = state.context.clone();
+    let device = &context.device;
+    let sample_count = context.settings.msaa_samples;
+
+    // Stand-in for a surface configuration so the usual texture helpers can be reused
+    let target_config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: context.surface_format,
+        width: HEADLESS_WIDTH,
+        height: HEADLESS_HEIGHT,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+        desired_maximum_frame_latency: 2,
+    };
+    let color_texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+        label: Some("Headless Color Target"),
+        size: wgpu::Extent3d {
+            width: HEADLESS_WIDTH,
+            height: HEADLESS_HEIGHT,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: context.surface_format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let msaa_view = (sample_count > 1)
+        .then(|| texture::Texture::create_msaa_texture(device, &target_config, context.scene_format, sample_count));
+    let mut hdr_targets = context
+        .hdr
+        .as_ref()
+        .map(|pipelines| HdrTargets::new(device, pipelines, HEADLESS_WIDTH, HEADLESS_HEIGHT));
+    let depth_texture = texture::Texture::create_depth_texture(device, &target_config, sample_count, "headless_depth_texture");
+    let hiz_targets = context
+        .hiz
+        .as_ref()
+        .zip(context.instance_cull.as_ref())
+        .map(|(hiz, cull)| HiZTargets::new(device, hiz, cull, &depth_texture.texture));
+
+    let scale = config.units.units_per_meter();
+    let mut camera = Camera::new((0.0, 5.0 * scale, 10.0 * scale), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+    let (znear, zfar) = config.units.depth_range();
+    let projection = Projection::new(HEADLESS_WIDTH, HEADLESS_HEIGHT, cgmath::Deg(45.0), znear, zfar);
+    let mut camera_uniform = CameraUniform::new();
+    camera_uniform.update_view_proj(&camera, &projection);
+    let camera_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+        label: Some("Headless Camera Buffer"),
+        contents: bytemuck::cast_slice(&[camera_uniform]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &context.camera_bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera_buffer.as_entire_binding(),
+        }],
+        label: Some("Headless Camera Bind Group"),
+    });
+
+    let mut gpu_timer = GpuTimer::new(device, &context.queue);
+    state.set_lod_view(LodView::new(&camera, &projection, HEADLESS_HEIGHT));
+    let mut benchmark = Benchmark::new(seconds);
+    loop {
+        state.update();
+        if let Some(timer) = gpu_timer.as_mut() {
+            timer.poll(device);
+        }
+        state.poll_cull_readbacks();
+        // The occluder wall goes up with the grid on the first update, then it is looked over
+        if let Some((eye, target)) = state.occluder_wall_view() {
+            camera.position = eye;
+            camera.look_at(target);
+            camera_uniform.update_view_proj(&camera, &projection);
+            context.queue.write_buffer(&camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Headless Encoder") });
+        let depth_load = state.encode_depth_prepass(&mut encoder, &depth_texture.view, &camera_bind_group, gpu_timer.as_ref());
+        let hiz = match (&hiz_targets, &context.hiz) {
+            (Some(targets), Some(pipelines)) if state.occlusion_culling_active() => {
+                targets.encode(&mut encoder, pipelines);
+                Some(targets)
+            }
+            _ => None,
+        };
+        state.cull_instances(&mut encoder, projection.calc_matrix() * camera.calc_matrix(), hiz);
+        {
+            // With HDR on the scene goes to the float target first, then gets tonemapped below
+            let scene_view = hdr_targets.as_ref().map_or(&color_view, |targets| targets.color_view());
+            let (view, resolve_target) = match &msaa_view {
+                Some(msaa_view) => (&**msaa_view, Some(scene_view)),
+                None => (scene_view, None),
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Headless Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(state.clear_color()),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: depth_load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: gpu_timer.as_ref().and_then(|timer| timer.pass_writes(GpuPass::Main)),
+            });
+            state.draw_scene(&mut render_pass, &camera_bind_group);
+        }
+        if let Some(timer) = gpu_timer.as_mut() {
+            timer.resolve(&mut encoder);
+        }
+        if let (Some(targets), Some(pipelines)) = (hdr_targets.as_mut(), context.hdr.as_ref()) {
+            targets.encode_tonemap(&mut encoder, &context.queue, pipelines, &state.hdr_settings, &color_view);
+        }
+        context.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = gpu_timer.as_mut() {
+            timer.request_readback();
+        }
+        state.request_cull_readbacks();
+        // Wait for the GPU so frame times measure real work, like presenting would
+        device.poll(wgpu::PollType::Wait)?;
+
+        if benchmark.record_frame() {
+            match &gpu_timer {
+                Some(timer) => {
+                    for pass in GpuPass::ALL {
+                        if let Some(ms) = timer.pass_ms(pass) {
+                            log::info!("{}: {:.3} ms GPU", pass.label(), ms);
+                        }
+                    }
+                }
+                None => log::info!("No GPU pass times, the adapter has no timestamp queries"),
+            }
+            if let Some(counts) = state.cull_counts() {
+                log::info!(
+                    "Culling: {} of {} instances drawn, {} outside the frustum, {} occluded",
+                    counts.drawn,
+                    counts.total,
+                    counts.outside_frustum,
+                    counts.occluded
+                );
+            }
+            return Ok(benchmark.report_json("headless"));
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use std::{f32::consts::FRAC_PI_2};
-use cgmath::{perspective, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
-use winit::{dpi::PhysicalPosition, event::MouseScrollDelta, keyboard::KeyCode};
+use std::f32::consts::{FRAC_PI_2, PI};
+use cgmath::{ortho, perspective, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector2, Vector3};
+use winit::{dpi::PhysicalPosition, event::MouseScrollDelta};
+
+use crate::{gpu_layout::{UniformCheck, rust_layout}, input_map::Action, math::Aabb};
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_cols(
@@ -9,13 +11,30 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::from_co
     cgmath::Vector4::new(0.0, 0.0, 0.5, 0.0),
     cgmath::Vector4::new(0.0, 0.0, 0.5, 1.0),
 );
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+pub const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+// Seconds a CameraFlight takes
+const FLIGHT_DURATION: f32 = 0.4;
+// Space left around a framed box, as a fraction of the view
+const FRAMING_MARGIN: f32 = 0.1;
+// Boxes smaller than this (a point, a line seen end-on) are framed as if they were this big
+const MIN_FRAMING_SIZE: f32 = 1.0;
+// Closest scrolling brings a following camera to its target
+const MIN_FOLLOW_DISTANCE: f32 = 0.5;
+// The scroll wheel while right-dragging steps the speed multiplier by this factor per line
+const SPEED_STEP: f32 = 1.25;
+pub const SPEED_MULTIPLIER_RANGE: std::ops::RangeInclusive<f32> = 0.01..=100.0;
+// While Shift (sprint) or Ctrl (slow) is held
+const SPRINT_FACTOR: f32 = 4.0;
+const SLOW_FACTOR: f32 = 0.25;
 
 #[derive(Debug)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
+    // Around the view axis, positive rolls right (the horizon tips the other way on screen).
+    // Kept within -PI..PI.
+    roll: Rad<f32>,
 }
 
 
@@ -29,18 +48,161 @@ impl Camera {
             position: position.into(),
             yaw: yaw.into(),
             pitch: pitch.into(),
+            roll: Rad(0.0),
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn with_roll<R: Into<Rad<f32>>>(mut self, roll: R) -> Self {
+        self.set_roll(roll.into());
+        self
+    }
+
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    pub fn roll(&self) -> Rad<f32> {
+        self.roll
+    }
+
+    // Wrapped into -PI..PI, so rolling all the way round levels again
+    pub fn set_roll(&mut self, roll: Rad<f32>) {
+        self.roll = Rad((roll.0 + PI).rem_euclid(2.0 * PI) - PI);
+    }
+
+    // Point the camera without moving it, pitch is kept short of straight up/down
+    pub fn set_orientation(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.yaw = yaw;
+        self.pitch = Rad(pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+    }
+
+    // Turn to face a point, staying where it is. Roll is kept.
+    pub fn look_at(&mut self, target: Point3<f32>) {
+        let to_target = target - self.position;
+        if to_target.magnitude2() > 0.0 {
+            let yaw = Rad(to_target.z.atan2(to_target.x));
+            let pitch = Rad(to_target.y.atan2((to_target.x * to_target.x + to_target.z * to_target.z).sqrt()));
+            self.set_orientation(yaw, pitch);
+        }
+    }
+
+    // Unit vector the camera looks along
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
 
-        Matrix4::look_to_rh(
-            self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
-            Vector3::unit_y(),
-        )
+    // The screen's right, level with the ground until the camera rolls. Pitch never reaches
+    // straight up/down, so the cross product doesn't vanish.
+    pub fn right(&self) -> Vector3<f32> {
+        let level = self.forward().cross(Vector3::unit_y()).normalize();
+        let (sin_roll, cos_roll) = self.roll.0.sin_cos();
+        level * cos_roll - self.level_up() * sin_roll
+    }
+
+    // The screen's up
+    pub fn up(&self) -> Vector3<f32> {
+        let level = self.forward().cross(Vector3::unit_y()).normalize();
+        let (sin_roll, cos_roll) = self.roll.0.sin_cos();
+        self.level_up() * cos_roll + level * sin_roll
+    }
+
+    // Up with no roll, square to forward and in the plane of it and the world's up
+    fn level_up(&self) -> Vector3<f32> {
+        let forward = self.forward();
+        forward.cross(Vector3::unit_y()).normalize().cross(forward)
+    }
+
+    // Built from the yaw/pitch/roll basis, the up vector isn't the world's once the camera rolls
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), self.up())
+    }
+}
+
+// Where the camera has to stand, looking the way it does now, for `bounds` to fill the view
+// with FRAMING_MARGIN to spare on the tighter side
+pub fn framing_position(camera: &Camera, projection: &Projection, bounds: &Aabb) -> Point3<f32> {
+    let forward = camera.forward();
+    let (right, up) = (camera.right(), camera.up());
+    let (tan_x, tan_y) = projection.half_fov_tangents();
+    let (tan_x, tan_y) = (tan_x / (1.0 + FRAMING_MARGIN), tan_y / (1.0 + FRAMING_MARGIN));
+    let center = bounds.center();
+
+    // Each corner sits at depth distance + z, and needs |x| <= depth * tan_x (same for y)
+    let mut distance: f32 = 0.0;
+    let mut nearest = f32::MAX;
+    for corner in bounds.corners() {
+        let offset = corner - center;
+        let (x, y, z) = (offset.dot(right), offset.dot(up), offset.dot(forward));
+        distance = distance.max(x.abs() / tan_x - z).max(y.abs() / tan_y - z);
+        nearest = nearest.min(z);
+    }
+    // Tiny boxes still get some room, and the whole box stays past the near plane
+    let (znear, _) = projection.depth_range();
+    let distance = distance
+        .max(MIN_FRAMING_SIZE * 0.5 / tan_x.min(tan_y))
+        .max(2.0 * znear - nearest);
+    Point3::from_vec(center) - forward * distance
+}
+
+// Moves the camera to a new position, easing in and out, without turning it
+pub struct CameraFlight {
+    from: Point3<f32>,
+    to: Point3<f32>,
+    elapsed: f32,
+}
+
+impl CameraFlight {
+    pub fn new(camera: &Camera, to: Point3<f32>) -> Self {
+        Self { from: camera.position, to, elapsed: 0.0 }
+    }
+
+    // Move the camera along, returns false once it has arrived
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        self.elapsed += dt;
+        let t = (self.elapsed / FLIGHT_DURATION).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        camera.position = self.from + (self.to - self.from) * t;
+        self.elapsed < FLIGHT_DURATION
+    }
+}
+
+// Keeps the camera at an offset from a moving target, looking at it. The camera eases toward
+// target + offset on a critically damped spring, so it catches up without overshooting.
+pub struct CameraFollow {
+    // From the target to where the camera wants to be, orbiting with the mouse swings it around
+    pub offset: Vector3<f32>,
+    // How fast the camera catches up (the spring's angular frequency, per second)
+    pub stiffness: f32,
+    target: Point3<f32>,
+    velocity: Vector3<f32>,
+}
+
+impl CameraFollow {
+    pub fn new(target: Point3<f32>, offset: Vector3<f32>, stiffness: f32) -> Self {
+        Self { offset, stiffness, target, velocity: Vector3::new(0.0, 0.0, 0.0) }
+    }
+
+    pub fn set_target(&mut self, target: Point3<f32>) {
+        self.target = target;
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, controller: &mut Controller, dt: f32) {
+        self.offset = controller.orbit(self.offset, dt);
+        // Exact step of the damped spring, stable however long the frame was
+        let goal = self.target + self.offset;
+        let omega = self.stiffness.max(0.0);
+        let decay = (-omega * dt).exp();
+        let change = camera.position - goal;
+        let pull = (self.velocity + change * omega) * dt;
+        self.velocity = (self.velocity - pull * omega) * decay;
+        camera.position = goal + (change + pull) * decay;
+        camera.look_at(self.target);
     }
 }
 
@@ -51,6 +213,24 @@ pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
 }
 
+pub const CAMERA_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(CameraUniform, [view_position, view_proj]),
+    wgsl: &[
+        ("shader.wgsl", "CameraUniform"),
+        ("debug_lines.wgsl", "CameraUniform"),
+        ("depth_prepass.wgsl", "CameraUniform"),
+        ("grass.wgsl", "CameraUniform"),
+        ("outline.wgsl", "CameraUniform"),
+        ("overlay.wgsl", "CameraUniform"),
+        ("particles.wgsl", "CameraUniform"),
+        ("pick.wgsl", "CameraUniform"),
+        ("probe.wgsl", "CameraUniform"),
+        ("shape.wgsl", "CameraUniform"),
+        ("ssao_normals.wgsl", "CameraUniform"),
+        ("light.wgsl", "Camera"),
+    ],
+};
+
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
@@ -59,11 +239,24 @@ impl CameraUniform {
         }
     }
 
+    // For cameras that aren't a Camera, like the faces of a reflection probe
+    pub fn from_view_proj(position: Point3<f32>, view_proj: Matrix4<f32>) -> Self {
+        Self {
+            view_position: position.to_homogeneous().into(),
+            view_proj: view_proj.into(),
+        }
+    }
+
     // UPDATED!
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
     }
+
+    // Shift the view by `offset`, a translation in clip space, e.g. TAA's sub-pixel jitter
+    pub fn jitter(&mut self, offset: Matrix4<f32>) {
+        self.view_proj = (offset * Matrix4::from(self.view_proj)).into();
+    }
 }
 
 
@@ -88,11 +281,74 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    // tan of half the horizontal and half the vertical field of view
+    pub fn half_fov_tangents(&self) -> (f32, f32) {
+        let tan_y = (self.fovy.0 * 0.5).tan();
+        (tan_y * self.aspect, tan_y)
+    }
+
+    // Near and far plane distances
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    pub fn set_depth_range(&mut self, (znear, zfar): (f32, f32)) {
+        self.znear = znear;
+        self.zfar = zfar;
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
     }
 }
 
+// Orthographic camera of the 2D pass (quad_2d.rs), in pixels of the surface: origin in the
+// top-left corner, y down. Scrolling and zooming move the world under the window.
+pub struct Camera2D {
+    width: f32,
+    height: f32,
+    // World pixel shown in the window's top-left corner
+    pub offset: Vector2<f32>,
+    // Screen pixels per world pixel, 1 draws textures at their own size
+    pub zoom: f32,
+}
+
+impl Camera2D {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width as f32,
+            height: height as f32,
+            offset: Vector2::new(0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width as f32;
+        self.height = height as f32;
+    }
+
+    pub fn size(&self) -> Vector2<f32> {
+        Vector2::new(self.width, self.height)
+    }
+
+    // Where a window pixel (e.g. the cursor) lands in the 2D world
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        self.offset + screen / self.zoom
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        let visible = self.size() / self.zoom;
+        let (left, top) = (self.offset.x, self.offset.y);
+        // Bottom below top flips y, so it grows down the window
+        OPENGL_TO_WGPU_MATRIX * ortho(left, left + visible.x, top + visible.y, top, -1.0, 1.0)
+    }
+}
+
 pub struct Controller {
     amount_left: f32,
     amount_right: f32,
@@ -100,11 +356,23 @@ pub struct Controller {
     amount_backward: f32,
     amount_up: f32,
     amount_down: f32,
+    // Only the top-down profile turns with a key, see camera_controller.rs
+    amount_turn_right: f32,
+    // Only free fly rolls with keys
+    amount_roll_left: f32,
+    amount_roll_right: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
     scroll: f32,
+    // Units per second, set from the scene units
     speed: f32,
+    // Scaled by the user on top of `speed`, kept when the units change
+    pub speed_multiplier: f32,
+    sprint: bool,
+    slow: bool,
     sensitivity: f32,
+    // Moving the mouse up looks down, like a flight stick
+    pub invert_y: bool,
 }
 
 impl Controller {
@@ -116,52 +384,180 @@ impl Controller {
             amount_backward: 0.0,
             amount_up: 0.0,
             amount_down: 0.0,
+            amount_turn_right: 0.0,
+            amount_roll_left: 0.0,
+            amount_roll_right: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
             scroll: 0.0,
             speed,
+            speed_multiplier: 1.0,
+            sprint: false,
+            slow: false,
             sensitivity,
+            invert_y: false,
         }
     }
 
-    pub fn handle_key(&mut self, key: KeyCode, is_pressed: bool) -> bool {
+    // A held move from the InputMap, keys are looked up there
+    pub fn handle_move(&mut self, action: Action, is_pressed: bool) -> bool {
         let amount = if is_pressed {
             1.0
         } else {
             0.0
         };
-        match key {
-            KeyCode::Space => {
+        match action {
+            Action::MoveUp => {
                 self.amount_up = amount;
                 true
             }
-            KeyCode::ShiftLeft => {
+            Action::MoveDown => {
                 self.amount_down = amount;
                 true
             }
-            KeyCode::KeyW | KeyCode::ArrowUp => {
+            Action::MoveForward => {
                 self.amount_forward = amount;
                 true
             }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
+            Action::MoveLeft => {
                 self.amount_left = amount;
                 true
             }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
+            Action::MoveBackward => {
                 self.amount_backward = amount;
                 true
             }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
+            Action::MoveRight => {
                 self.amount_right = amount;
                 true
             }
+            Action::TurnRight => {
+                self.amount_turn_right = amount;
+                true
+            }
+            Action::RollLeft => {
+                self.amount_roll_left = amount;
+                true
+            }
+            Action::RollRight => {
+                self.amount_roll_right = amount;
+                true
+            }
+            Action::Sprint => {
+                self.sprint = is_pressed;
+                true
+            }
+            Action::SlowMove => {
+                self.slow = is_pressed;
+                true
+            }
             _ => false,
         }
     }
 
+    // Whether the next update_camera would move the camera
+    pub fn is_moving(&self) -> bool {
+        [
+            self.amount_left,
+            self.amount_right,
+            self.amount_forward,
+            self.amount_backward,
+            self.amount_up,
+            self.amount_down,
+            self.amount_turn_right,
+            self.amount_roll_left,
+            self.amount_roll_right,
+            self.rotate_horizontal,
+            self.rotate_vertical,
+            self.scroll,
+        ]
+        .iter()
+        .any(|&amount| amount != 0.0)
+    }
+
+    // Whether a movement key is held, mouse look and scrolling don't count
+    pub fn is_translating(&self) -> bool {
+        [
+            self.amount_left,
+            self.amount_right,
+            self.amount_forward,
+            self.amount_backward,
+            self.amount_up,
+            self.amount_down,
+        ]
+        .iter()
+        .any(|&amount| amount != 0.0)
+    }
+
+    // Follow mode: mouse motion swings `offset` around the target instead of turning the camera
+    // and scrolling changes its length. Uses up the motion like update_camera does.
+    pub fn orbit(&mut self, offset: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let distance = offset.magnitude();
+        let yaw = offset.z.atan2(offset.x) + self.rotate_horizontal * self.sensitivity * dt;
+        // The camera looks back at the target, raising the offset pitches the view down
+        let elevation = (offset.y / distance.max(f32::EPSILON)).clamp(-1.0, 1.0).asin() + self.rotate_vertical * self.sensitivity * dt;
+        let elevation = elevation.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        let distance = (distance + self.scroll * self.speed() * self.sensitivity * dt * 5.0).max(MIN_FOLLOW_DISTANCE);
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        let (sin_yaw, cos_yaw) = yaw.sin_cos();
+        Vector3::new(cos_elevation * cos_yaw, sin_elevation, cos_elevation * sin_yaw) * distance
+    }
+
+    // Forget every held key and pending mouse/scroll movement. Used when the window
+    // stops receiving input (focus lost, cursor released) so nothing stays "pressed"
+    pub fn reset_input(&mut self) {
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.amount_turn_right = 0.0;
+        self.amount_roll_left = 0.0;
+        self.amount_roll_right = 0.0;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+        self.scroll = 0.0;
+        self.sprint = false;
+        self.slow = false;
+    }
+
+    pub fn set_base_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    // Units per second right now, with the multiplier and any held sprint or slow key
+    pub fn speed(&self) -> f32 {
+        let mut speed = self.speed * self.speed_multiplier;
+        if self.sprint {
+            speed *= SPRINT_FACTOR;
+        }
+        if self.slow {
+            speed *= SLOW_FACTOR;
+        }
+        speed
+    }
+
+    // The scroll wheel while right-dragging: faster up, slower down
+    pub fn handle_speed_scroll(&mut self, delta: &MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, lines) => *lines,
+            MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => *y as f32 / 100.0,
+        };
+        self.speed_multiplier = (self.speed_multiplier * SPEED_STEP.powf(lines))
+            .clamp(*SPEED_MULTIPLIER_RANGE.start(), *SPEED_MULTIPLIER_RANGE.end());
+    }
+
+    // The only entry point for mouse look. Horizontal motion turns (yaw), vertical motion
+    // tilts (pitch). Several motion events can arrive between two frames, they add up.
     pub fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        let vertical_sense = if self.invert_y { -1.0 } else { 1.0 };
+        self.rotate_horizontal += mouse_dx as f32;
+        self.rotate_vertical += mouse_dy as f32 * vertical_sense;
     }
 
     pub fn handle_scroll(&mut self, delta: &MouseScrollDelta) {
@@ -177,12 +573,12 @@ impl Controller {
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed() * dt;
+        camera.position += right * (self.amount_right - self.amount_left) * self.speed() * dt;
 
-        // Move up/down. Since we don't use roll, we can just
-        // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        // Move up/down. Straight up even when the camera rolls, so
+        // we can just modify the y coordinate directly.
+        camera.position.y += (self.amount_up - self.amount_down) * self.speed() * dt;
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
@@ -191,12 +587,49 @@ impl Controller {
         let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
         let scrollward =
             Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position -= scrollward * self.scroll * self.speed * self.sensitivity * dt * 5.0;
+        camera.position -= scrollward * self.scroll * self.speed() * self.sensitivity * dt * 5.0;
         self.scroll = 0.0;
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+        self.turn_camera(camera, dt);
+    }
+
+    // Held keys as axes: x right, y up, z forward, each -1 to 1
+    pub fn move_axes(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.amount_right - self.amount_left,
+            self.amount_up - self.amount_down,
+            self.amount_forward - self.amount_backward,
+        )
+    }
+
+    pub fn turn_right(&self) -> f32 {
+        self.amount_turn_right
+    }
+
+    // -1 to 1, positive rolls right
+    pub fn roll_right(&self) -> f32 {
+        self.amount_roll_right - self.amount_roll_left
+    }
+
+    // Scroll since the last frame, used up
+    pub fn take_scroll(&mut self) -> f32 {
+        std::mem::take(&mut self.scroll)
+    }
+
+    // Mouse motion since the last frame, for profiles that don't look around with it
+    pub fn discard_look(&mut self) {
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+    }
+
+    // Mouse look, the turning half of update_camera
+    pub fn turn_camera(&mut self, camera: &mut Camera, dt: f32) {
+        // Rotate. Motion is along the screen, which is tipped when the camera rolls.
+        let (sin_roll, cos_roll) = camera.roll.0.sin_cos();
+        let horizontal = self.rotate_horizontal * cos_roll - self.rotate_vertical * sin_roll;
+        let vertical = self.rotate_vertical * cos_roll + self.rotate_horizontal * sin_roll;
+        camera.yaw += Rad(horizontal) * self.sensitivity * dt;
+        camera.pitch += Rad(-vertical) * self.sensitivity * dt;
 
         // If process_mouse isn't called every frame, these values
         // will not get set to zero, and the camera will rotate
@@ -211,4 +644,189 @@ impl Controller {
             camera.pitch = Rad(SAFE_FRAC_PI_2);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Deg;
+
+    const TOLERANCE: f32 = 1e-5;
+
+    fn camera() -> Camera {
+        Camera::new((1.0, 2.0, 3.0), Deg(-90.0), Deg(0.0))
+    }
+
+    #[test]
+    fn input_held_when_focus_is_lost_stops_moving() {
+        let mut controller = Controller::new(4.0, 1.0);
+        for action in [Action::MoveForward, Action::MoveRight, Action::MoveUp, Action::RollLeft, Action::Sprint] {
+            assert!(controller.handle_move(action, true));
+        }
+        controller.handle_mouse(30.0, -10.0);
+        controller.handle_scroll(&MouseScrollDelta::LineDelta(0.0, 2.0));
+        assert!(controller.is_moving());
+
+        // What ViewWindow::release_input does to the controller
+        controller.reset_input();
+        assert!(!controller.is_moving());
+        assert_eq!((controller.roll_right(), controller.speed()), (0.0, 4.0));
+        let mut camera = camera();
+        controller.update_camera(&mut camera, 0.5);
+        assert_eq!(camera.position, Point3::new(1.0, 2.0, 3.0));
+        assert_eq!((camera.yaw(), camera.pitch()), (Deg(-90.0).into(), Rad(0.0)));
+    }
+
+    #[test]
+    fn mouse_motion_turns_by_sensitivity_and_adds_up() {
+        let mut controller = Controller::new(1.0, 0.5);
+        let mut camera = camera();
+        // Two events before one frame
+        controller.handle_mouse(30.0, 0.0);
+        controller.handle_mouse(10.0, 20.0);
+        controller.turn_camera(&mut camera, 0.1);
+        assert!((camera.yaw().0 - (Rad::from(Deg(-90.0)).0 + 40.0 * 0.5 * 0.1)).abs() < TOLERANCE, "{:?}", camera.yaw());
+        // Moving the mouse down looks down
+        assert!((camera.pitch().0 + 20.0 * 0.5 * 0.1).abs() < TOLERANCE, "{:?}", camera.pitch());
+
+        // Used up by the frame
+        let turned = (camera.yaw(), camera.pitch());
+        controller.turn_camera(&mut camera, 0.1);
+        assert_eq!((camera.yaw(), camera.pitch()), turned);
+
+        controller.invert_y = true;
+        controller.handle_mouse(0.0, 20.0);
+        controller.turn_camera(&mut camera, 0.1);
+        assert!(camera.pitch().0.abs() < TOLERANCE, "{:?}", camera.pitch());
+    }
+
+    #[test]
+    fn pitch_stops_short_of_straight_up_and_down() {
+        let mut controller = Controller::new(1.0, 1.0);
+        let mut camera = camera();
+        controller.handle_mouse(0.0, -1e4);
+        controller.turn_camera(&mut camera, 1.0);
+        assert_eq!(camera.pitch(), Rad(SAFE_FRAC_PI_2));
+        controller.handle_mouse(0.0, 1e4);
+        controller.turn_camera(&mut camera, 1.0);
+        assert_eq!(camera.pitch(), Rad(-SAFE_FRAC_PI_2));
+        assert!(camera.forward().x.is_finite() && camera.right().magnitude() > 0.99);
+    }
+
+    fn assert_matrix_eq(actual: Matrix4<f32>, expected: Matrix4<f32>) {
+        let difference = actual - expected;
+        assert!((0..4).all(|column| difference[column].magnitude() < TOLERANCE), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn rolled_view_turns_around_the_view_axis() {
+        // Looking down -Z from the origin is the identity view
+        let level = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        assert_matrix_eq(level.calc_matrix(), Matrix4::identity());
+
+        // Rolling right tips the world the other way on screen: world x becomes screen up
+        let rolled = camera().with_roll(Deg(90.0));
+        assert!((rolled.up() - Vector3::unit_x()).magnitude() < TOLERANCE, "{:?}", rolled.up());
+        assert!((rolled.right() + Vector3::unit_y()).magnitude() < TOLERANCE, "{:?}", rolled.right());
+        let translation = Matrix4::from_translation(Vector3::new(-1.0, -2.0, -3.0));
+        assert_matrix_eq(rolled.calc_matrix(), Matrix4::from_angle_z(Deg(90.0)) * translation);
+        assert_matrix_eq(camera().with_roll(Deg(-30.0)).calc_matrix(), Matrix4::from_angle_z(Deg(-30.0)) * translation);
+
+        // Wrapped into -PI..PI
+        assert!((camera().with_roll(Deg(270.0)).roll().0 - Rad::from(Deg(-90.0)).0).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn pitch_clamps_while_rolled() {
+        let mut controller = Controller::new(1.0, 1.0);
+        // On its side, moving the mouse sideways pitches the camera
+        let mut camera = camera().with_roll(Deg(90.0));
+        controller.handle_mouse(1e4, 0.0);
+        controller.turn_camera(&mut camera, 1.0);
+        assert_eq!(camera.pitch(), Rad(-SAFE_FRAC_PI_2));
+        assert!((camera.yaw().0 - Rad::from(Deg(-90.0)).0).abs() < 1e-3, "{:?}", camera.yaw());
+
+        // Still a rotation with the camera's position at the origin
+        let view = camera.calc_matrix();
+        assert!((0..4).all(|column| view[column].magnitude().is_finite()));
+        let (right, up, forward) = (camera.right(), camera.up(), camera.forward());
+        assert!(right.dot(up).abs() < TOLERANCE && right.dot(forward).abs() < TOLERANCE && up.dot(forward).abs() < TOLERANCE);
+        assert!((view * camera.position.to_homogeneous()).truncate().magnitude() < TOLERANCE);
+    }
+
+    // Largest |x| / (depth * tan_x) or |y| / (depth * tan_y) over the box's corners, 1.0 being
+    // the edge of the view, and the depth of the nearest corner
+    fn view_extent(camera: &Camera, projection: &Projection, bounds: &Aabb) -> (f32, f32) {
+        let view = camera.calc_matrix();
+        let (tan_x, tan_y) = projection.half_fov_tangents();
+        bounds.corners().iter().fold((0.0f32, f32::MAX), |(extent, nearest), corner| {
+            let p = view * Point3::from_vec(*corner).to_homogeneous();
+            let depth = -p.z;
+            (extent.max((p.x / (depth * tan_x)).abs()).max((p.y / (depth * tan_y)).abs()), nearest.min(depth))
+        })
+    }
+
+    #[test]
+    fn framing_fits_the_box_with_the_margin_to_spare() {
+        let projection = Projection::new(160, 90, Deg(45.0), 0.1, 100.0);
+        let tall = Aabb::new(Vector3::new(-1.0, -4.0, -2.0), Vector3::new(1.0, 6.0, 0.5));
+        let wide = Aabb::new(Vector3::new(-8.0, 0.0, -1.0), Vector3::new(9.0, 1.0, 1.0));
+        let looking_down = Camera::new((0.0, 0.0, 0.0), Deg(-60.0), Deg(-35.0)).with_roll(Deg(20.0));
+        for mut camera in [camera(), looking_down] {
+            for bounds in [tall, wide] {
+                let (yaw, pitch, roll) = (camera.yaw(), camera.pitch(), camera.roll());
+                camera.position = framing_position(&camera, &projection, &bounds);
+                // Only moved, and the box is centered
+                assert_eq!((camera.yaw(), camera.pitch(), camera.roll()), (yaw, pitch, roll));
+                let to_center = bounds.center() - camera.position.to_vec();
+                assert!(to_center.normalize().dot(camera.forward()) > 1.0 - TOLERANCE, "{:?}", to_center);
+
+                // The tighter side touches the margin
+                let (extent, nearest) = view_extent(&camera, &projection, &bounds);
+                assert!((extent - 1.0 / (1.0 + FRAMING_MARGIN)).abs() < 1e-4, "{extent} for {:?}", bounds);
+                assert!(nearest > 0.1, "{nearest}");
+            }
+        }
+    }
+
+    #[test]
+    fn framing_a_point_or_a_box_behind_the_near_plane_backs_off() {
+        let projection = Projection::new(160, 90, Deg(45.0), 0.1, 100.0);
+        let camera = camera();
+        let point = Aabb::new(Vector3::new(5.0, 1.0, -3.0), Vector3::new(5.0, 1.0, -3.0));
+        let position = framing_position(&camera, &projection, &point);
+        // As if it were MIN_FRAMING_SIZE across, on the narrower vertical side
+        let (_, tan_y) = projection.half_fov_tangents();
+        let expected = MIN_FRAMING_SIZE * 0.5 * (1.0 + FRAMING_MARGIN) / tan_y;
+        assert!(((Point3::new(5.0, 1.0, -3.0) - position).magnitude() - expected).abs() < 1e-4, "{:?}", position);
+
+        // A thin slab seen edge-on would fit with its front face up against the camera, keep it
+        // twice the near plane away instead
+        let projection = Projection::new(160, 90, Deg(45.0), 2.0, 100.0);
+        let slab = Aabb::new(Vector3::new(-0.1, -0.1, -20.0), Vector3::new(0.1, 0.1, 0.0));
+        let mut camera = camera;
+        camera.position = framing_position(&camera, &projection, &slab);
+        let (_, nearest) = view_extent(&camera, &projection, &slab);
+        assert!((nearest - 4.0).abs() < 1e-4, "{nearest}");
+    }
+
+    #[test]
+    fn flight_eases_to_its_end_without_turning() {
+        let mut camera = camera();
+        let to = Point3::new(-3.0, 0.0, 7.0);
+        let mut flight = CameraFlight::new(&camera, to);
+        let (yaw, pitch) = (camera.yaw(), camera.pitch());
+
+        // Slow at the start, halfway at half time
+        assert!(flight.update(&mut camera, FLIGHT_DURATION * 0.1));
+        let early = (camera.position - Point3::new(1.0, 2.0, 3.0)).magnitude() / (to - Point3::new(1.0, 2.0, 3.0)).magnitude();
+        assert!(early > 0.0 && early < 0.1, "{early}");
+        assert!(flight.update(&mut camera, FLIGHT_DURATION * 0.4));
+        let halfway = Point3::new(-1.0, 1.0, 5.0);
+        assert!((camera.position - halfway).magnitude() < 1e-4, "{:?}", camera.position);
+
+        // Lands exactly, even overshooting the time
+        assert!(!flight.update(&mut camera, FLIGHT_DURATION));
+        assert_eq!(camera.position, to);
+        assert_eq!((camera.yaw(), camera.pitch()), (yaw, pitch));
+    }
+}
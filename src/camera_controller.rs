@@ -0,0 +1,389 @@
+/*
+Purpose: Interchangeable camera control schemes for the fly camera of a window
+Responsibilities:
+    - Define the CameraController trait every scheme implements, on top of the held keys and
+      mouse motion a Controller collects
+    - Free fly (the Controller's own movement), orbit around a point ahead, top-down panning
+      and walking on the ground with gravity
+    - Roll the free-fly camera with its keys, and ease any roll back to level when auto-level
+      is on
+    - Take over the camera where the previous scheme left it, easing into the new scheme's
+      pose instead of snapping to it
+    - Keep every scheme's tunables and save them through the user settings file
+    - ex: the gear stick, the car and the road stay the same, how the pedals drive it changes
+*/
+
+use cgmath::{Deg, Point3, Rad, Vector3};
+use winit::event::MouseScrollDelta;
+
+use crate::{camera::{Camera, Controller}, input_map::Action, user_settings::UserSettings};
+
+// Per second, how fast a scheme eases the camera into its pose after taking over
+const HANDOVER_RATE: f32 = 8.0;
+// One scroll line zooms the top-down height by about this factor
+const TOP_DOWN_ZOOM_STEP: f32 = 0.2;
+// Walking camera counts as standing within this many meters of its eye height
+const GROUND_TOLERANCE: f32 = 0.01;
+// Auto-level snaps to level under this many radians, and covers all but e^-3 (5%) of the roll in
+// its time
+const LEVEL_EPSILON: f32 = 1e-4;
+const LEVEL_TIME_CONSTANTS: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerProfile {
+    FreeFly,
+    Orbit,
+    TopDown,
+    Walk,
+}
+
+impl ControllerProfile {
+    pub const ALL: [ControllerProfile; 4] = [ControllerProfile::FreeFly, ControllerProfile::Orbit, ControllerProfile::TopDown, ControllerProfile::Walk];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ControllerProfile::FreeFly => "Free fly",
+            ControllerProfile::Orbit => "Orbit",
+            ControllerProfile::TopDown => "Top-down pan",
+            ControllerProfile::Walk => "Walk",
+        }
+    }
+
+    // As written to the settings file
+    fn key(self) -> &'static str {
+        match self {
+            ControllerProfile::FreeFly => "free-fly",
+            ControllerProfile::Orbit => "orbit",
+            ControllerProfile::TopDown => "top-down",
+            ControllerProfile::Walk => "walk",
+        }
+    }
+
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let key = settings.get("camera.profile").unwrap_or(ControllerProfile::FreeFly.key());
+        ControllerProfile::ALL.into_iter().find(|profile| profile.key() == key).unwrap_or_else(|| {
+            log::warn!("Unknown camera profile '{}', using free fly", key);
+            ControllerProfile::FreeFly
+        })
+    }
+
+    pub fn write_settings(self, settings: &mut UserSettings) {
+        settings.set("camera.profile", self.key());
+    }
+}
+
+// Distances in meters, scaled by the scene units when applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControllerTunables {
+    // How far ahead the orbit point is placed when the orbit scheme takes over
+    pub orbit_distance: f32,
+    // Degrees below the horizon the top-down camera looks
+    pub top_down_pitch: f32,
+    pub top_down_min_height: f32,
+    pub top_down_max_height: f32,
+    // Degrees per second while Q or E is held
+    pub top_down_turn_speed: f32,
+    pub walk_eye_height: f32,
+    // Height of the ground the walking camera stands on
+    pub walk_ground_height: f32,
+    // Meters per second squared
+    pub walk_gravity: f32,
+    // Meters per second upward when Space jumps
+    pub walk_jump_speed: f32,
+    // Degrees per second while a roll key is held in free fly
+    pub roll_speed: f32,
+    // Ease the roll back to level whenever no roll key is held, in every scheme
+    pub auto_level: bool,
+    // Seconds auto-level takes to get the horizon (nearly) level
+    pub auto_level_time: f32,
+}
+
+impl Default for ControllerTunables {
+    fn default() -> Self {
+        Self {
+            orbit_distance: 10.0,
+            top_down_pitch: 60.0,
+            top_down_min_height: 2.0,
+            top_down_max_height: 200.0,
+            top_down_turn_speed: 90.0,
+            walk_eye_height: 1.7,
+            walk_ground_height: 0.0,
+            walk_gravity: 9.81,
+            walk_jump_speed: 4.0,
+            roll_speed: 60.0,
+            auto_level: true,
+            auto_level_time: 1.0,
+        }
+    }
+}
+
+impl ControllerTunables {
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let default = Self::default();
+        Self {
+            orbit_distance: settings.parse("camera.orbit.distance").unwrap_or(default.orbit_distance),
+            top_down_pitch: settings.parse("camera.top_down.pitch").unwrap_or(default.top_down_pitch),
+            top_down_min_height: settings.parse("camera.top_down.min_height").unwrap_or(default.top_down_min_height),
+            top_down_max_height: settings.parse("camera.top_down.max_height").unwrap_or(default.top_down_max_height),
+            top_down_turn_speed: settings.parse("camera.top_down.turn_speed").unwrap_or(default.top_down_turn_speed),
+            walk_eye_height: settings.parse("camera.walk.eye_height").unwrap_or(default.walk_eye_height),
+            walk_ground_height: settings.parse("camera.walk.ground_height").unwrap_or(default.walk_ground_height),
+            walk_gravity: settings.parse("camera.walk.gravity").unwrap_or(default.walk_gravity),
+            walk_jump_speed: settings.parse("camera.walk.jump_speed").unwrap_or(default.walk_jump_speed),
+            roll_speed: settings.parse("camera.roll.speed").unwrap_or(default.roll_speed),
+            auto_level: settings.parse("camera.roll.auto_level").unwrap_or(default.auto_level),
+            auto_level_time: settings.parse("camera.roll.auto_level_time").unwrap_or(default.auto_level_time),
+        }
+    }
+
+    pub fn write_settings(&self, settings: &mut UserSettings) {
+        settings.set("camera.orbit.distance", self.orbit_distance);
+        settings.set("camera.top_down.pitch", self.top_down_pitch);
+        settings.set("camera.top_down.min_height", self.top_down_min_height);
+        settings.set("camera.top_down.max_height", self.top_down_max_height);
+        settings.set("camera.top_down.turn_speed", self.top_down_turn_speed);
+        settings.set("camera.walk.eye_height", self.walk_eye_height);
+        settings.set("camera.walk.ground_height", self.walk_ground_height);
+        settings.set("camera.walk.gravity", self.walk_gravity);
+        settings.set("camera.walk.jump_speed", self.walk_jump_speed);
+        settings.set("camera.roll.speed", self.roll_speed);
+        settings.set("camera.roll.auto_level", self.auto_level);
+        settings.set("camera.roll.auto_level_time", self.auto_level_time);
+    }
+
+    // The tunables of `profile`, then auto-level which every scheme shares
+    pub fn draw(&mut self, ui: &mut egui::Ui, profile: ControllerProfile) {
+        match profile {
+            ControllerProfile::FreeFly => {
+                ui.add(egui::Slider::new(&mut self.roll_speed, 5.0..=360.0).text("Roll speed (deg/s)"));
+            }
+            ControllerProfile::Orbit => {
+                ui.add(egui::Slider::new(&mut self.orbit_distance, 0.5..=100.0).logarithmic(true).text("Orbit distance (m)"));
+            }
+            ControllerProfile::TopDown => {
+                ui.add(egui::Slider::new(&mut self.top_down_pitch, 20.0..=89.0).text("View angle (deg)"));
+                ui.add(egui::Slider::new(&mut self.top_down_min_height, 0.5..=50.0).logarithmic(true).text("Lowest height (m)"));
+                ui.add(egui::Slider::new(&mut self.top_down_max_height, 10.0..=2000.0).logarithmic(true).text("Highest height (m)"));
+                ui.add(egui::Slider::new(&mut self.top_down_turn_speed, 10.0..=360.0).text("Turn speed (deg/s)"));
+            }
+            ControllerProfile::Walk => {
+                ui.add(egui::Slider::new(&mut self.walk_eye_height, 0.2..=5.0).text("Eye height (m)"));
+                ui.add(egui::Slider::new(&mut self.walk_ground_height, -50.0..=50.0).text("Ground height (m)"));
+                ui.add(egui::Slider::new(&mut self.walk_gravity, 0.5..=30.0).text("Gravity (m/s²)"));
+                ui.add(egui::Slider::new(&mut self.walk_jump_speed, 0.0..=15.0).text("Jump speed (m/s)"));
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.auto_level, "Auto-level");
+            ui.add_enabled(self.auto_level, egui::Slider::new(&mut self.auto_level_time, 0.1..=10.0).logarithmic(true).text("s"));
+        });
+    }
+}
+
+// One control scheme. The Controller underneath collects the input, the scheme decides what it
+// does to the camera. `meter` is scene units per meter.
+pub trait CameraController {
+    fn profile(&self) -> ControllerProfile;
+
+    fn input(&self) -> &Controller;
+
+    fn input_mut(&mut self) -> &mut Controller;
+
+    // A held key from the InputMap, true when it drives the camera
+    fn handle_key(&mut self, action: Action, is_pressed: bool) -> bool {
+        self.input_mut().handle_move(action, is_pressed)
+    }
+
+    fn handle_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.input_mut().handle_mouse(mouse_dx, mouse_dy);
+    }
+
+    fn handle_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.input_mut().handle_scroll(delta);
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, tunables: &ControllerTunables, meter: f32, dt: f32);
+}
+
+// The scheme for `profile`, starting from the camera as it is
+pub fn new_controller(profile: ControllerProfile, input: Controller, camera: &Camera, tunables: &ControllerTunables, meter: f32) -> Box<dyn CameraController> {
+    match profile {
+        ControllerProfile::FreeFly => Box::new(FreeFly { input }),
+        ControllerProfile::Orbit => Box::new(Orbit { input, target: camera.position + camera.forward() * tunables.orbit_distance * meter }),
+        ControllerProfile::TopDown => Box::new(TopDown { input, height: camera.position.y }),
+        ControllerProfile::Walk => Box::new(Walk { input, vertical_speed: 0.0 }),
+    }
+}
+
+// Share of the way to the scheme's pose covered this frame
+fn handover(dt: f32) -> f32 {
+    1.0 - (-HANDOVER_RATE * dt).exp()
+}
+
+// Held roll keys turn the camera around its view axis, otherwise auto-level eases it back.
+// `keys` is -1 to 1, positive rolls right.
+fn roll_camera(camera: &mut Camera, keys: f32, tunables: &ControllerTunables, dt: f32) {
+    if keys != 0.0 {
+        camera.set_roll(camera.roll() + Rad::from(Deg(keys * tunables.roll_speed * dt)));
+    } else if tunables.auto_level && camera.roll().0 != 0.0 {
+        let roll = camera.roll().0 * (-LEVEL_TIME_CONSTANTS * dt / tunables.auto_level_time.max(f32::EPSILON)).exp();
+        camera.set_roll(Rad(if roll.abs() < LEVEL_EPSILON { 0.0 } else { roll }));
+    }
+}
+
+// Whether roll_camera would still change the roll with no keys held
+pub fn is_leveling(camera: &Camera, tunables: &ControllerTunables) -> bool {
+    tunables.auto_level && camera.roll().0 != 0.0
+}
+
+// Forward and right along the ground for the camera's heading
+fn ground_axes(camera: &Camera) -> (Vector3<f32>, Vector3<f32>) {
+    let (sin_yaw, cos_yaw) = camera.yaw().0.sin_cos();
+    (Vector3::new(cos_yaw, 0.0, sin_yaw), Vector3::new(-sin_yaw, 0.0, cos_yaw))
+}
+
+// WASD strafes, Space/Q rise and sink, the mouse looks around
+struct FreeFly {
+    input: Controller,
+}
+
+impl CameraController for FreeFly {
+    fn profile(&self) -> ControllerProfile {
+        ControllerProfile::FreeFly
+    }
+
+    fn input(&self) -> &Controller {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut Controller {
+        &mut self.input
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, tunables: &ControllerTunables, _meter: f32, dt: f32) {
+        roll_camera(camera, self.input.roll_right(), tunables, dt);
+        self.input.update_camera(camera, dt);
+    }
+}
+
+// The mouse swings the camera around a point and scrolling moves closer, WASD and Space/Q move
+// the point with the camera
+struct Orbit {
+    input: Controller,
+    target: Point3<f32>,
+}
+
+impl CameraController for Orbit {
+    fn profile(&self) -> ControllerProfile {
+        ControllerProfile::Orbit
+    }
+
+    fn input(&self) -> &Controller {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut Controller {
+        &mut self.input
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, tunables: &ControllerTunables, _meter: f32, dt: f32) {
+        roll_camera(camera, 0.0, tunables, dt);
+        let axes = self.input.move_axes();
+        let (forward, right) = ground_axes(camera);
+        let pan = (right * axes.x + Vector3::unit_y() * axes.y + forward * axes.z) * self.input.speed() * dt;
+        self.target += pan;
+        let offset = self.input.orbit(camera.position + pan - self.target, dt);
+        camera.position = self.target + offset;
+        camera.look_at(self.target);
+    }
+}
+
+// Looks down at the ground from a height: WASD pans across it, scrolling changes the height and
+// Q/E turn. The mouse doesn't look around.
+struct TopDown {
+    input: Controller,
+    // Where the camera is headed, it eases there
+    height: f32,
+}
+
+impl CameraController for TopDown {
+    fn profile(&self) -> ControllerProfile {
+        ControllerProfile::TopDown
+    }
+
+    fn input(&self) -> &Controller {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut Controller {
+        &mut self.input
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, tunables: &ControllerTunables, meter: f32, dt: f32) {
+        self.input.discard_look();
+        roll_camera(camera, 0.0, tunables, dt);
+        let axes = self.input.move_axes();
+        // Q is the fly down key, there is no down here so it turns the other way from E
+        let turn = self.input.turn_right() + axes.y.min(0.0);
+        let yaw = camera.yaw() + Rad::from(Deg(turn * tunables.top_down_turn_speed * dt));
+
+        let (low, high) = (tunables.top_down_min_height * meter, tunables.top_down_max_height.max(tunables.top_down_min_height) * meter);
+        self.height = (self.height * (self.input.take_scroll() * TOP_DOWN_ZOOM_STEP).exp()).clamp(low, high);
+
+        let (forward, right) = ground_axes(camera);
+        camera.position += (right * axes.x + forward * axes.z) * self.input.speed() * dt;
+        let ease = handover(dt);
+        camera.position.y += (self.height - camera.position.y) * ease;
+        let pitch = -Rad::from(Deg(tunables.top_down_pitch));
+        camera.set_orientation(yaw, camera.pitch() + (pitch - camera.pitch()) * ease);
+    }
+}
+
+// Walks on the ground at eye height: WASD moves along it, the mouse looks around, gravity pulls
+// the camera down and Space jumps
+struct Walk {
+    input: Controller,
+    // Units per second, up is positive
+    vertical_speed: f32,
+}
+
+impl CameraController for Walk {
+    fn profile(&self) -> ControllerProfile {
+        ControllerProfile::Walk
+    }
+
+    fn input(&self) -> &Controller {
+        &self.input
+    }
+
+    fn input_mut(&mut self) -> &mut Controller {
+        &mut self.input
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, tunables: &ControllerTunables, meter: f32, dt: f32) {
+        self.input.take_scroll();
+        roll_camera(camera, 0.0, tunables, dt);
+        self.input.turn_camera(camera, dt);
+        let axes = self.input.move_axes();
+        let (forward, right) = ground_axes(camera);
+        camera.position += (right * axes.x + forward * axes.z) * self.input.speed() * dt;
+
+        let eye = (tunables.walk_ground_height + tunables.walk_eye_height) * meter;
+        let tolerance = GROUND_TOLERANCE * meter;
+        let standing = (camera.position.y - eye).abs() <= tolerance;
+        if standing && axes.y > 0.0 && self.vertical_speed <= 0.0 {
+            self.vertical_speed = tunables.walk_jump_speed * meter;
+        }
+        if camera.position.y < eye - tolerance && self.vertical_speed == 0.0 {
+            // Taken over below the ground, climb out instead of popping up
+            camera.position.y += (eye - camera.position.y) * handover(dt);
+        } else {
+            self.vertical_speed -= tunables.walk_gravity * meter * dt;
+            camera.position.y += self.vertical_speed * dt;
+            if camera.position.y < eye {
+                camera.position.y = eye;
+                self.vertical_speed = 0.0;
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/*
+Purpose: The click-to-move demo, an actor that walks to where the ground was right-clicked
+Responsibilities:
+    - Build the actor's capsule model, a stripe down its front shows which way it faces
+    - Define ActorState, the actor's state machine, kept in its instance's user data: idle or
+      walking to a target
+    - Step an actor along the ground at its speed, turning to face the way it walks and standing
+      on whatever height the ground has under it
+    - ex: a board game piece pushed to the square someone pointed at
+*/
+
+use cgmath::{InnerSpace, Rad, Rotation3, Vector2};
+
+use crate::{instance::Instance, math::Vec3, model::{self, ShadingModel}, render_context::RenderContext, resources, shapes, texture};
+
+// Meters. The capsule's pivot is its middle, this far above the ground.
+const ACTOR_RADIUS: f32 = 0.35;
+const ACTOR_HALF_LENGTH: f32 = 0.55;
+pub const ACTOR_HALF_HEIGHT: f32 = ACTOR_RADIUS + ACTOR_HALF_LENGTH;
+// Meters per second
+pub const DEFAULT_ACTOR_SPEED: f32 = 3.0;
+// Closer than this (meters) to the target counts as there
+const ARRIVAL_RADIUS: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActorState {
+    Idle,
+    // World position on the ground, only x and z are walked towards
+    MovingTo(Vec3),
+}
+
+impl ActorState {
+    pub fn label(self) -> &'static str {
+        match self {
+            ActorState::Idle => "Idle",
+            ActorState::MovingTo(_) => "Walking",
+        }
+    }
+}
+
+pub fn model(context: &RenderContext) -> anyhow::Result<model::Model> {
+    let (shape_vertices, indices) = shapes::create_capsule(ACTOR_RADIUS, ACTOR_HALF_LENGTH, 24, 8);
+    let mesh = resources::mesh_from_shape(&context.device, "click_move_actor", &shape_vertices, &indices);
+
+    // U goes around the capsule, the two texels around a quarter of the way are its +Z side
+    let diffuse = image::RgbaImage::from_fn(8, 1, |x, _| match x {
+        1 | 2 => image::Rgba([240, 200, 60, 255]),
+        _ => image::Rgba([70, 110, 200, 255]),
+    });
+    let diffuse = image::DynamicImage::ImageRgba8(diffuse);
+    let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+    let material = model::Material::new(
+        &context.device,
+        "click_move_actor",
+        texture::Texture::from_image(&context.device, &context.queue, &diffuse, Some("click_move_actor_diffuse"), false)?,
+        texture::Texture::from_image(&context.device, &context.queue, &flat_normal, Some("click_move_actor_normal"), true)?,
+        &context.texture_bind_group_layout,
+        model::MaterialParams { shading_model: ShadingModel::BlinnPhong, ..Default::default() },
+    );
+    Ok(model::Model {
+        meshes: vec![mesh],
+        materials: vec![material],
+        optimize_stats: None,
+        packed: None,
+    })
+}
+
+// Moves the actor `dt` seconds towards its target. `ground` is the ground's height under an x, z
+// position; speed is in meters per second and scaled like everything else by `units_per_meter`.
+// Arriving sets the state back to Idle.
+pub fn step(state: &mut ActorState, instance: &mut Instance, dt: f32, speed: f32, units_per_meter: f32, ground: impl Fn(f32, f32) -> f32) {
+    let ActorState::MovingTo(target) = *state else {
+        return;
+    };
+    let position = instance.initial_position;
+    let to_target = Vector2::new(target.x - position.x, target.z - position.z);
+    let distance = to_target.magnitude();
+    if distance <= ARRIVAL_RADIUS * units_per_meter {
+        *state = ActorState::Idle;
+        return;
+    }
+    let direction = to_target / distance;
+    let travel = (speed * units_per_meter * dt).min(distance);
+    let (x, z) = (position.x + direction.x * travel, position.z + direction.y * travel);
+    instance.initial_position = Vec3::new(x, ground(x, z) + ACTOR_HALF_HEIGHT * units_per_meter, z);
+    // Turned about Y so +Z, the striped side, leads
+    instance.rotation = cgmath::Quaternion::from_angle_y(Rad(direction.x.atan2(direction.y)));
+}
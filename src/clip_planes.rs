@@ -0,0 +1,200 @@
+/*
+Purpose: User clip planes that slice models open to show their insides
+Responsibilities:
+    - Keep up to MAX_CLIP_PLANES planes, each enabled on its own, as a normal and an offset along it
+    - Pack the enabled ones first into the uniform shader.wgsl reads, with their count, so disabled
+      planes cost the fragment shader nothing
+    - Turn a plane to face along the view, and drag planes along their normal with an arrow handle
+    - ex: the cutaway drawing of an engine in a car magazine
+*/
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::{camera::{Camera, Projection}, gpu_layout::{UniformCheck, rust_layout}, transform_gizmo::{GRAB_DISTANCE, HANDLE_LENGTH, ScreenProjection, segment_distance}};
+
+pub const MAX_CLIP_PLANES: usize = 4;
+// Half the edge of the square drawn where a plane is, in world units
+const OUTLINE_SIZE: f32 = 1.5;
+const OFFSET_RANGE: f32 = 20.0;
+const PLANE_COLORS: [egui::Color32; MAX_CLIP_PLANES] = [
+    egui::Color32::from_rgb(230, 90, 200),
+    egui::Color32::from_rgb(60, 200, 220),
+    egui::Color32::from_rgb(240, 200, 60),
+    egui::Color32::from_rgb(150, 110, 240),
+];
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::WHITE;
+
+// Must match ClipPlanes in shader.wgsl, the first `count` planes are the enabled ones
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ClipUniform {
+    // xyz: unit normal, w: minus the offset, so dot(xyz, p) + w is the signed distance
+    planes: [[f32; 4]; MAX_CLIP_PLANES],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+pub const CLIP_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(ClipUniform, [planes, count]),
+    wgsl: &[("shader.wgsl", "ClipPlanes")],
+};
+
+// Everything on the side the normal points to stays, the rest is cut away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    pub enabled: bool,
+    // Doesn't have to be unit length, a zero normal cuts nothing
+    pub normal: Vector3<f32>,
+    // Distance of the plane from the origin along the normal
+    pub offset: f32,
+}
+
+impl ClipPlane {
+    fn unit_normal(&self) -> Option<Vector3<f32>> {
+        (self.normal.magnitude2() > 1e-6).then(|| self.normal.normalize())
+    }
+
+    // The point of the plane closest to the origin, where its handle sits
+    fn anchor(&self, normal: Vector3<f32>) -> Vector3<f32> {
+        normal * self.offset
+    }
+}
+
+struct Drag {
+    plane: usize,
+    start_pointer: egui::Pos2,
+    start_offset: f32,
+    // Screen direction of the normal and points per world unit along it, when the drag started
+    direction: egui::Vec2,
+    points_per_unit: f32,
+}
+
+pub struct ClipPlanes {
+    pub planes: [ClipPlane; MAX_CLIP_PLANES],
+    drag: Option<Drag>,
+}
+
+impl Default for ClipPlanes {
+    fn default() -> Self {
+        let plane = |x, y, z| ClipPlane { enabled: false, normal: Vector3::new(x, y, z), offset: 0.0 };
+        Self {
+            planes: [plane(1.0, 0.0, 0.0), plane(0.0, -1.0, 0.0), plane(0.0, 0.0, 1.0), plane(-1.0, 0.0, -1.0)],
+            drag: None,
+        }
+    }
+}
+
+impl ClipPlanes {
+    fn enabled(&self) -> impl Iterator<Item = (usize, &ClipPlane, Vector3<f32>)> {
+        self.planes.iter().enumerate().filter(|(_, plane)| plane.enabled).filter_map(|(index, plane)| Some((index, plane, plane.unit_normal()?)))
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.enabled().count()
+    }
+
+    pub fn uniform(&self) -> ClipUniform {
+        let mut uniform = ClipUniform { planes: [[0.0; 4]; MAX_CLIP_PLANES], count: 0, _padding: [0; 3] };
+        for (_, plane, normal) in self.enabled() {
+            uniform.planes[uniform.count as usize] = [normal.x, normal.y, normal.z, -plane.offset];
+            uniform.count += 1;
+        }
+        uniform
+    }
+
+    // Faces the plane along the view and keeps it through the same anchor, the near half is cut away
+    pub fn align_to_view(&mut self, index: usize, camera: &Camera) {
+        let plane = &mut self.planes[index];
+        let anchor = plane.unit_normal().map_or(Vector3::zero(), |normal| plane.anchor(normal));
+        let forward = camera.forward();
+        plane.normal = forward;
+        plane.offset = forward.dot(anchor);
+    }
+
+    pub fn draw_settings(&mut self, ui: &mut egui::Ui, camera: &Camera) {
+        for (index, color) in PLANE_COLORS.into_iter().enumerate() {
+            let plane = &mut self.planes[index];
+            let align = ui.horizontal(|ui| {
+                ui.checkbox(&mut plane.enabled, egui::RichText::new(format!("Plane {}", index + 1)).color(color));
+                ui.add_enabled(plane.enabled, egui::Button::new("Align to view")).clicked()
+            }).inner;
+            if align {
+                self.align_to_view(index, camera);
+            }
+            let plane = &mut self.planes[index];
+            if !plane.enabled {
+                continue;
+            }
+            ui.indent(("clip_plane", index), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Normal");
+                    for component in [&mut plane.normal.x, &mut plane.normal.y, &mut plane.normal.z] {
+                        ui.add(egui::DragValue::new(component).speed(0.01).range(-1.0..=1.0));
+                    }
+                });
+                ui.add(egui::Slider::new(&mut plane.offset, -OFFSET_RANGE..=OFFSET_RANGE).text("Offset"));
+                if plane.unit_normal().is_none() {
+                    ui.label("A zero normal cuts nothing");
+                }
+            });
+        }
+    }
+
+    // Outlines every enabled plane with an arrow along its normal, dragging the arrow moves the
+    // plane. Pointer input near the arrows is claimed so the camera doesn't turn meanwhile.
+    pub fn show_handles(&mut self, ctx: &egui::Context, camera: &Camera, projection: &Projection) {
+        let screen = ScreenProjection::new(ctx, camera, projection);
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("clip_plane_handles")));
+        let reach = HANDLE_LENGTH + GRAB_DISTANCE;
+        let handles: Vec<_> = self.enabled().map(|(index, plane, normal)| (index, plane.anchor(normal), normal)).collect();
+        if self.drag.as_ref().is_some_and(|drag| handles.iter().all(|(index, _, _)| *index != drag.plane)) {
+            self.drag = None;
+        }
+        for (index, anchor, normal) in handles {
+            let Some(center) = screen.project(anchor) else {
+                continue;
+            };
+            let Some((direction, points_per_unit)) = screen.axis(anchor, center, normal) else {
+                continue;
+            };
+            let tip = center + direction * HANDLE_LENGTH;
+            let area_rect = egui::Rect::from_center_size(center, egui::vec2(reach, reach) * 2.0);
+            let response = egui::Area::new(egui::Id::new(("clip_plane_handle", index)))
+                .fixed_pos(area_rect.min)
+                .order(egui::Order::Background)
+                .show(ctx, |ui| ui.allocate_exact_size(area_rect.size(), egui::Sense::drag()).1)
+                .inner;
+            let hovered = response.hover_pos().is_some_and(|pointer| segment_distance(pointer, center, tip) <= GRAB_DISTANCE);
+            if response.drag_started()
+                && hovered
+                && let Some(pointer) = response.interact_pointer_pos()
+            {
+                self.drag = Some(Drag { plane: index, start_pointer: pointer, start_offset: self.planes[index].offset, direction, points_per_unit });
+            }
+            let dragging = self.drag.as_ref().is_some_and(|drag| drag.plane == index);
+            if dragging && (response.drag_stopped() || !response.dragged()) {
+                self.drag = None;
+            } else if dragging
+                && let (Some(drag), Some(pointer)) = (&self.drag, response.interact_pointer_pos())
+            {
+                let offset = drag.start_offset + (pointer - drag.start_pointer).dot(drag.direction) / drag.points_per_unit;
+                self.planes[index].offset = offset.clamp(-OFFSET_RANGE, OFFSET_RANGE);
+            }
+
+            let color = if dragging || hovered { ACTIVE_COLOR } else { PLANE_COLORS[index] };
+            let stroke = egui::Stroke::new(2.0, color);
+            // Square around the anchor, spanned by two directions in the plane
+            let helper = if normal.y.abs() < 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+            let u = normal.cross(helper).normalize() * OUTLINE_SIZE;
+            let v = normal.cross(u);
+            let corners: Option<Vec<_>> = [u + v, u - v, -u - v, -u + v].iter().map(|corner| screen.project(anchor + corner)).collect();
+            if let Some(corners) = corners {
+                painter.add(egui::Shape::closed_line(corners, egui::Stroke::new(1.0, color)));
+            }
+            painter.line_segment([center, tip], stroke);
+            let side = egui::vec2(-direction.y, direction.x);
+            let back = tip - direction * 12.0;
+            painter.add(egui::Shape::convex_polygon(vec![tip, back + side * 5.0, back - side * 5.0], color, egui::Stroke::NONE));
+        }
+    }
+}
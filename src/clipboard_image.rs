@@ -0,0 +1,62 @@
+/*
+Purpose: Images pasted from the system clipboard onto a model, without saving a file first
+Responsibilities:
+    - Read the clipboard's image, if it holds one, as RGBA8
+    - Scale images the GPU can't hold down to its largest texture size
+    - Keep each pasted texture with the model showing it and a generated file name
+    - ex: a sticky note slapped on the prototype, peeled off again when the real texture arrives
+*/
+
+use image::{DynamicImage, RgbaImage};
+
+use crate::{model_entry::ModelHandle, texture::Texture};
+
+// Shown in place of the diffuse texture of every material of `model`
+pub struct PastedTexture {
+    // pasted-<n>.png, what it would be saved as
+    pub name: String,
+    pub model: ModelHandle,
+    pub size: (u32, u32),
+    texture: Texture,
+}
+
+impl PastedTexture {
+    pub fn new(name: String, model: ModelHandle, texture: Texture) -> Self {
+        let size = texture.texture.size();
+        Self { name, model, size: (size.width, size.height), texture }
+    }
+
+    // For Material::set_diffuse_override
+    pub fn display(&self) -> (wgpu::TextureView, wgpu::Sampler) {
+        (self.texture.view.clone(), self.texture.sampler.clone())
+    }
+}
+
+pub fn generated_name(index: u32) -> String {
+    format!("pasted-{}.png", index)
+}
+
+// None when the clipboard can't be opened or holds no image (text, files, nothing)
+pub fn read_image(max_dimension: u32) -> Option<DynamicImage> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| log::debug!("No clipboard: {}", e)).ok()?;
+    let data = clipboard.get_image().map_err(|e| log::debug!("No image on the clipboard: {}", e)).ok()?;
+    let rgba = RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())?;
+    Some(fit(DynamicImage::ImageRgba8(rgba), max_dimension))
+}
+
+// Down to `max_dimension` on the longer side, keeping the aspect ratio
+fn fit(image: DynamicImage, max_dimension: u32) -> DynamicImage {
+    if image.width() <= max_dimension && image.height() <= max_dimension {
+        return image;
+    }
+    let scaled = image.resize(max_dimension, max_dimension, image::imageops::FilterType::Triangle);
+    log::info!(
+        "Pasted image is {}x{}, larger than the GPU's {} texture limit, scaled to {}x{}",
+        image.width(),
+        image.height(),
+        max_dimension,
+        scaled.width(),
+        scaled.height()
+    );
+    scaled
+}
@@ -0,0 +1,99 @@
+/*
+Purpose: The engine's one rule for color spaces
+Responsibilities:
+    - Shading happens in linear space. Every color a person authors is sRGB: pickers, constants,
+      vertex colors, light colors, the clear and sky colors, the console and scripts. Each is
+      converted to linear where it is uploaded, with the helpers here.
+    - Textures need nothing, their formats are sRGB and decode to linear when sampled (texture.rs)
+    - The frame is encoded back to sRGB once, by the sRGB surface or by the tonemap pass when the
+      surface isn't one (hdr_tonemap.wgsl)
+    - Edit sRGB colors with egui's picker, which works on linear colors, and show them as swatches
+    - ex: a border crossing, everything is changed into the one currency used inside
+*/
+
+// One channel, the piecewise sRGB curve rather than a 2.2 gamma so 128 and 0.5 line up exactly
+pub fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear_rgb(srgb: [f32; 3]) -> [f32; 3] {
+    srgb.map(srgb_to_linear)
+}
+
+pub fn linear_to_srgb_rgb(linear: [f32; 3]) -> [f32; 3] {
+    linear.map(linear_to_srgb)
+}
+
+// Alpha is coverage rather than a color and is left as it is
+pub fn srgb_to_linear_rgba([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    let [r, g, b] = srgb_to_linear_rgb([r, g, b]);
+    [r, g, b, a]
+}
+
+// What a render pass clears to for an sRGB color
+pub fn clear_color(srgb: [f32; 3]) -> wgpu::Color {
+    let [r, g, b] = srgb_to_linear_rgb(srgb);
+    wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: 1.0 }
+}
+
+// egui's color_edit_button_rgb takes a linear color, this one an sRGB color. Only written back
+// when the picker changed it, so a color that is never touched keeps its exact value.
+pub fn edit_srgb(ui: &mut egui::Ui, srgb: &mut [f32; 3]) -> egui::Response {
+    let [r, g, b] = srgb_to_linear_rgb(*srgb);
+    let mut linear = egui::Rgba::from_rgb(r, g, b);
+    let response = egui::color_picker::color_edit_button_rgba(ui, &mut linear, egui::color_picker::Alpha::Opaque);
+    if response.changed() {
+        *srgb = linear_to_srgb_rgb([linear.r(), linear.g(), linear.b()]);
+    }
+    response
+}
+
+// An sRGB color for labels and swatches, egui's Color32 is sRGB too
+pub fn swatch(srgb: [f32; 3]) -> egui::Color32 {
+    let [r, g, b] = srgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+    egui::Color32::from_rgb(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A few ulps of powf, far below the 1/255 step between 8-bit values
+    const TOLERANCE: f32 = 1e-5;
+
+    #[test]
+    fn every_8_bit_value_survives_the_round_trip() {
+        let mut previous = -1.0;
+        for byte in 0..=255u8 {
+            let srgb = f32::from(byte) / 255.0;
+            let linear = srgb_to_linear(srgb);
+            assert!(linear > previous, "not increasing at {}", byte);
+            previous = linear;
+            let back = linear_to_srgb(linear);
+            assert!((back - srgb).abs() < TOLERANCE, "{}: {} came back as {}", byte, srgb, back);
+            assert_eq!((back * 255.0).round() as u8, byte);
+        }
+        assert_eq!((srgb_to_linear(0.0), srgb_to_linear(1.0)), (0.0, 1.0));
+    }
+
+    #[test]
+    fn matches_the_srgb_curve() {
+        assert!((srgb_to_linear(0.5) - 0.214_041).abs() < TOLERANCE);
+        assert!((linear_to_srgb(0.5) - 0.735_357).abs() < TOLERANCE);
+        // The linear toe and the power segment meet without a jump
+        assert!((srgb_to_linear(0.04045) - srgb_to_linear(0.040_450_1)).abs() < TOLERANCE);
+        assert_eq!(srgb_to_linear_rgba([1.0, 0.5, 0.0, 0.5])[3], 0.5);
+    }
+}
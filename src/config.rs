@@ -0,0 +1,577 @@
+/*
+Purpose: Engine configuration and command-line parsing
+Responsibilities:
+    - Define EngineConfig (what to load, how big the scene is, benchmark mode)
+    - Define RenderSettings (vsync, MSAA, ...) consumed when creating GPU resources
+    - Parse command-line arguments into an EngineConfig
+    - ex: the settings sheet handed to the engine before it starts
+*/
+
+use crate::{edge_outline::OutlineMode, instance::MAX_INSTANCES, instance_cull::CullMode, mesh_optimize::LoadOptions, model::ShadingModel, scene_gen::SceneGenOptions, scripting, sky::SkyMode, stereo::StereoMode, undo::DEFAULT_UNDO_DEPTH, units::SceneUnits, user_settings::DEFAULT_SETTINGS_FILE, uv_fallback::UvFallback};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: app-rusty-engine [OPTIONS]
+
+Options:
+    --model <path>         OBJ model to instance (default: cube.obj from res/)
+    --asset-root <dir>     Look for assets in this folder before the bundled ones,
+                           can be given more than once
+    --scene <path>         Scene file to load
+    --instances <NxM>      Size of the instance grid, e.g. 10x10 (default: 0x0)
+    --random-scene <N>     Scatter N random procedural shapes and a few lights
+    --seed <u64>           Seed for --random-scene, same seed same layout (default: 0)
+    --scene-extent <units> Half-width of the area --random-scene fills (default: 20)
+    --occluder-wall        Stand a wall across the near side of the instance grid, hiding
+                           most of it: the occlusion culling test scene
+    --units <meters|centimeters|factor>
+                           What one scene unit measures, or how many make a meter. Scales
+                           camera speed, near/far planes, grid spacing and gizmos (default: meters)
+    --vsync <on|off>       Wait for vertical sync when presenting (default: on)
+    --msaa <1|4>           Multisample anti-aliasing sample count (default: 1)
+    --hdr <on|off>         Render into a float target and tonemap it (default: on)
+    --taa <on|off>         Temporal anti-aliasing, needs --hdr on and can't be combined
+                           with --msaa 4, can be toggled in the menu (default: off)
+    --depth-prepass <on|off>
+                           Write the models' depth first so each pixel is shaded once,
+                           can be toggled in the menu (default: off)
+    --vertex-pulling <on|off>
+                           Read the models' and shapes' vertices from storage buffers by
+                           index, any vertex layout, falls back to vertex buffers
+                           where the adapter can't, can be toggled in the menu (default: off)
+    --culling <off|cpu|gpu>
+                           Frustum cull the instances before the main pass, GPU falls back
+                           to the CPU where the adapter can't, can be changed in the menu
+                           (default: off)
+    --occlusion-culling <on|off>
+                           Also drop the instances the depth pre-pass hides, tested on the
+                           GPU against a depth pyramid. Needs --culling gpu and
+                           --depth-prepass on, can be toggled in the menu (default: off)
+    --sky <none|procedural>
+                           Background behind the scene: the clear color, or a gradient with a
+                           sun disk, can be changed in the menu (default: none)
+    --outline <off|selection|scene|hull>
+                           Outline drawn over the frame: around the selection or along every
+                           edge in the scene, found in screen space, or the selection's
+                           inverted hull to compare with (default: off)
+    --shading <unlit|lambert|blinn-phong|pbr-lite>
+                           Shading model for every material of --model, e.g. to compare
+                           their cost (default: blinn-phong, per material in the menu)
+    --weld <on|off>        Merge the loaded models' duplicate vertices (default: off)
+    --smoothing-angle <degrees>
+                           Recompute the loaded models' normals, smoothing across edges
+                           flatter than this (default: keep the file's normals)
+    --cache-optimize <on|off>
+                           Reorder the loaded models' triangles for the vertex cache (default: off)
+    --pack-textures <on|off>
+                           Pack each loaded model's material textures into texture arrays,
+                           drawn with one bind group per model (default: off)
+    --uv-fallback <none|planar|box>
+                           Project UVs for meshes whose OBJ has no texture coordinates,
+                           the material menu marks them (default: box)
+    --memory-budget <MiB>  Warn once buffers and textures take more GPU memory than this
+                           (default: guessed from the adapter)
+    --texture-budget <MiB> Evict the least recently drawn model textures beyond this, they
+                           show low-res until they fit again (default: off)
+    --benchmark <seconds>  Run without input for the given time, then print
+                           frame-time statistics as JSON and exit
+    --pause-on-focus-loss <on|off>
+                           Pause the simulation while no window has focus (default: on)
+    --min-size <WxH>       Smallest the main window can be resized to (default: 320x240)
+    --aspect-lock <W:H|off>
+                           Keep the main window at this aspect ratio, e.g. 16:9 (default: off)
+    --custom-titlebar <on|off>
+                           Hide the system title bar and draw one in the UI instead (default: off)
+    --render-mode <continuous|on-demand>
+                           Redraw every frame, or only when something changed (default: continuous)
+    --stereo <off|side-by-side|anaglyph>
+                           Render the main window once per eye, can be changed in the menu
+                           (default: off)
+    --hot-reload <on|off>  Reload textures, shader.wgsl and scripts when their files change
+                           on disk (default: on in debug builds, off in release builds)
+    --scripts <dir>        Folder of .rhai scripts to run (default: assets/scripts in the
+                           source tree)
+    --settings <path>      File UI preferences are saved to (default: rusty-engine.cfg)
+    --undo-depth <N>       Editor edits Ctrl+Z can take back, the oldest are forgotten
+                           past this (default: 100)
+    --diagnostics          Print the GPU adapter, surface and settings report, then exit
+    --check-layouts        Compare the GPU structs with the shaders' and vertex layouts, then
+                           exit, non-zero on a mismatch
+    -h, --help             Print this message";
+
+// When the windows redraw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    // Every frame, as fast as presenting allows
+    Continuous,
+    // Only after input, camera motion, UI activity, running animations or an explicit request,
+    // the event loop sleeps in between
+    OnDemand,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 2] = [RenderMode::Continuous, RenderMode::OnDemand];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RenderMode::Continuous => "Continuous",
+            RenderMode::OnDemand => "On demand",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub vsync: bool,
+    pub msaa_samples: u32,
+    // Scene renders into an Rgba16Float target that is tonemapped into the swapchain.
+    // Off draws straight into the swapchain like before.
+    pub hdr: bool,
+    // Jittered temporal anti-aliasing of the HDR frame. Startup value, the menu toggles it.
+    // Excludes MSAA, both smooth the same edges.
+    pub taa: bool,
+    // Opaque models write depth in a pass of their own, the main pass then shades with an Equal
+    // depth test. Startup value, the menu toggles it.
+    pub depth_prepass: bool,
+    // Models fetch their vertices from storage buffers in the vertex shader, where the adapter
+    // can. Startup value, the menu toggles it.
+    pub vertex_pulling: bool,
+    // Instance culling of the main pass. Startup value, the menu changes it.
+    pub culling: CullMode,
+    // GPU culling also tests against the depth pre-pass's depth pyramid. Startup value, the
+    // menu toggles it.
+    pub occlusion_culling: bool,
+    // What fills the background. Startup value, the menu changes it.
+    pub sky: SkyMode,
+    // Outlines over the frame. Startup value, the menu changes it.
+    pub outline: OutlineMode,
+    // Overrides the shading model of the --model's materials, None keeps what they load with
+    pub shading_model: Option<ShadingModel>,
+    // Clean up pass for every OBJ loaded, startup model and models added later alike
+    pub mesh_load: LoadOptions,
+    // GPU memory to warn beyond in bytes, None guesses it from the adapter (see gpu_memory.rs)
+    pub memory_budget: Option<u64>,
+    // Bytes of textures to keep resident, the least recently used are evicted beyond it (see
+    // texture_residency.rs). None keeps every texture. Startup value, the stats window changes it.
+    pub texture_budget: Option<u64>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            msaa_samples: 1,
+            hdr: true,
+            taa: false,
+            depth_prepass: false,
+            vertex_pulling: false,
+            culling: CullMode::Off,
+            occlusion_culling: false,
+            sky: SkyMode::None,
+            outline: OutlineMode::Off,
+            shading_model: None,
+            mesh_load: LoadOptions::default(),
+            memory_budget: None,
+            texture_budget: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        if self.vsync {
+            wgpu::PresentMode::AutoVsync
+        } else {
+            wgpu::PresentMode::AutoNoVsync
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub model_path: String,
+    // Searched before res/ next to the executable, in the order given
+    pub asset_roots: Vec<PathBuf>,
+    pub scene_path: Option<PathBuf>,
+    pub instances: (u32, u32),
+    // Some(options) when --random-scene was given
+    pub random_scene: Option<SceneGenOptions>,
+    pub seed: u64,
+    // The occlusion culling test scene, a wall in front of the instance grid
+    pub occluder_wall: bool,
+    // The one source camera speed, near/far planes, spacing and gizmo sizes are scaled from
+    pub units: SceneUnits,
+    pub benchmark_seconds: Option<f32>,
+    // Print State::diagnostics_report once the window is up and exit
+    pub diagnostics: bool,
+    pub pause_on_focus_loss: bool,
+    // Smallest inner size of the main window, in physical pixels
+    pub min_inner_size: (u32, u32),
+    // Width:height the main window is snapped back to after every resize
+    pub aspect_ratio_lock: Option<(u32, u32)>,
+    // Where preferences changed in the menu (UI theme, ...) are kept between runs
+    pub settings_path: PathBuf,
+    // Main window without OS decorations, moved and resized through an egui title bar.
+    // Ignored where winit can't move an undecorated window.
+    pub custom_titlebar: bool,
+    // Watch the loaded models' texture files and reload them when they change
+    pub hot_reload: bool,
+    // Every *.rhai in it runs, see scripting.rs
+    pub scripts_dir: PathBuf,
+    // Benchmarks always render continuously
+    pub render_mode: RenderMode,
+    // Most commands the undo stack keeps
+    pub undo_depth: usize,
+    // Startup value, the menu changes it
+    pub stereo: StereoMode,
+    pub render: RenderSettings,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            model_path: "cube.obj".to_string(),
+            asset_roots: Vec::new(),
+            scene_path: None,
+            instances: (0, 0),
+            random_scene: None,
+            seed: 0,
+            occluder_wall: false,
+            units: SceneUnits::default(),
+            benchmark_seconds: None,
+            diagnostics: false,
+            pause_on_focus_loss: true,
+            min_inner_size: (320, 240),
+            aspect_ratio_lock: None,
+            settings_path: PathBuf::from(DEFAULT_SETTINGS_FILE),
+            custom_titlebar: false,
+            hot_reload: cfg!(debug_assertions),
+            scripts_dir: PathBuf::from(scripting::SCRIPT_DIR),
+            render_mode: RenderMode::Continuous,
+            undo_depth: DEFAULT_UNDO_DEPTH,
+            stereo: StereoMode::Off,
+            render: RenderSettings::default(),
+        }
+    }
+}
+
+// What the command line asked for
+pub enum CliCommand {
+    Run(Box<EngineConfig>),
+    Help,
+    CheckLayouts,
+}
+
+impl EngineConfig {
+    // Parse arguments (without the program name). Errors are human readable and meant
+    // to be printed next to USAGE.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<CliCommand, String> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        let mut scene_extent = None;
+
+        while let Some(arg) = args.next() {
+            // Accept both "--flag value" and "--flag=value"
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for {}", name))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(CliCommand::Help),
+                "--check-layouts" => return Ok(CliCommand::CheckLayouts),
+                "--diagnostics" => config.diagnostics = true,
+                "--model" => config.model_path = value("--model")?,
+                "--asset-root" => config.asset_roots.push(PathBuf::from(value("--asset-root")?)),
+                "--scene" => config.scene_path = Some(PathBuf::from(value("--scene")?)),
+                "--settings" => config.settings_path = PathBuf::from(value("--settings")?),
+                "--instances" => {
+                    let raw = value("--instances")?;
+                    config.instances = parse_grid("--instances", &raw)?;
+                    let (columns, rows) = config.instances;
+                    if columns.checked_mul(rows).is_none_or(|count| count > MAX_INSTANCES) {
+                        return Err(format!("--instances {} is more than {} instances", raw, MAX_INSTANCES));
+                    }
+                }
+                "--occluder-wall" => config.occluder_wall = true,
+                "--random-scene" => {
+                    let raw = value("--random-scene")?;
+                    let shape_count = raw
+                        .parse::<u32>()
+                        .map_err(|_| format!("--random-scene expects a number of shapes, got '{}'", raw))?;
+                    // Drawn as instances too, and generated all at once
+                    if shape_count > MAX_INSTANCES {
+                        return Err(format!("--random-scene {} is more than {} shapes", raw, MAX_INSTANCES));
+                    }
+                    config.random_scene = Some(SceneGenOptions {
+                        shape_count,
+                        ..SceneGenOptions::default()
+                    });
+                }
+                "--seed" => {
+                    let raw = value("--seed")?;
+                    config.seed = raw
+                        .parse::<u64>()
+                        .map_err(|_| format!("--seed expects an unsigned integer, got '{}'", raw))?;
+                }
+                "--scene-extent" => {
+                    let raw = value("--scene-extent")?;
+                    let extent = raw
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|e| e.is_finite() && *e > 0.0)
+                        .ok_or_else(|| format!("--scene-extent expects a positive number, got '{}'", raw))?;
+                    scene_extent = Some(extent);
+                }
+                "--units" => config.units = SceneUnits::parse(&value("--units")?)?,
+                "--vsync" => config.render.vsync = parse_switch("--vsync", &value("--vsync")?)?,
+                "--msaa" => {
+                    config.render.msaa_samples = match value("--msaa")?.as_str() {
+                        "1" => 1,
+                        "4" => 4,
+                        other => return Err(format!("--msaa expects 1 or 4, got '{}'", other)),
+                    }
+                }
+                "--hdr" => config.render.hdr = parse_switch("--hdr", &value("--hdr")?)?,
+                "--taa" => config.render.taa = parse_switch("--taa", &value("--taa")?)?,
+                "--depth-prepass" => config.render.depth_prepass = parse_switch("--depth-prepass", &value("--depth-prepass")?)?,
+                "--vertex-pulling" => config.render.vertex_pulling = parse_switch("--vertex-pulling", &value("--vertex-pulling")?)?,
+                "--culling" => {
+                    config.render.culling = match value("--culling")?.as_str() {
+                        "off" => CullMode::Off,
+                        "cpu" => CullMode::Cpu,
+                        "gpu" => CullMode::Gpu,
+                        other => return Err(format!("--culling expects off, cpu or gpu, got '{}'", other)),
+                    }
+                }
+                "--occlusion-culling" => config.render.occlusion_culling = parse_switch("--occlusion-culling", &value("--occlusion-culling")?)?,
+                "--shading" => {
+                    config.render.shading_model = Some(match value("--shading")?.as_str() {
+                        "unlit" => ShadingModel::Unlit,
+                        "lambert" => ShadingModel::Lambert,
+                        "blinn-phong" => ShadingModel::BlinnPhong,
+                        "pbr-lite" => ShadingModel::PbrLite,
+                        other => return Err(format!("--shading expects unlit, lambert, blinn-phong or pbr-lite, got '{}'", other)),
+                    })
+                }
+                "--weld" => config.render.mesh_load.weld = parse_switch("--weld", &value("--weld")?)?,
+                "--smoothing-angle" => {
+                    let raw = value("--smoothing-angle")?;
+                    let angle = raw
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|a| (0.0..=180.0).contains(a))
+                        .ok_or_else(|| format!("--smoothing-angle expects degrees between 0 and 180, got '{}'", raw))?;
+                    config.render.mesh_load.smoothing_angle = Some(angle);
+                }
+                "--cache-optimize" => config.render.mesh_load.cache_optimize = parse_switch("--cache-optimize", &value("--cache-optimize")?)?,
+                "--pack-textures" => config.render.mesh_load.pack_textures = parse_switch("--pack-textures", &value("--pack-textures")?)?,
+                "--uv-fallback" => {
+                    config.render.mesh_load.uv_fallback = match value("--uv-fallback")?.as_str() {
+                        "none" => UvFallback::None,
+                        "planar" => UvFallback::Planar,
+                        "box" => UvFallback::Box,
+                        other => return Err(format!("--uv-fallback expects none, planar or box, got '{}'", other)),
+                    }
+                }
+                "--undo-depth" => {
+                    let raw = value("--undo-depth")?;
+                    config.undo_depth = raw
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|depth| *depth > 0)
+                        .ok_or_else(|| format!("--undo-depth expects a positive number, got '{}'", raw))?;
+                }
+                "--memory-budget" => config.render.memory_budget = Some(parse_mib("--memory-budget", &value("--memory-budget")?)?),
+                "--texture-budget" => config.render.texture_budget = Some(parse_mib("--texture-budget", &value("--texture-budget")?)?),
+                "--benchmark" => {
+                    let raw = value("--benchmark")?;
+                    let seconds = raw
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|s| s.is_finite() && *s > 0.0)
+                        .ok_or_else(|| format!("--benchmark expects a positive number of seconds, got '{}'", raw))?;
+                    config.benchmark_seconds = Some(seconds);
+                }
+                "--pause-on-focus-loss" => config.pause_on_focus_loss = parse_switch("--pause-on-focus-loss", &value("--pause-on-focus-loss")?)?,
+                "--min-size" => {
+                    let raw = value("--min-size")?;
+                    config.min_inner_size = parse_grid("--min-size", &raw)
+                        .ok()
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .ok_or_else(|| format!("--min-size expects WxH with both sides above 0, got '{}'", raw))?;
+                }
+                "--aspect-lock" => {
+                    let raw = value("--aspect-lock")?;
+                    config.aspect_ratio_lock = match raw.as_str() {
+                        "off" => None,
+                        _ => Some(parse_ratio(&raw)?),
+                    };
+                }
+                "--hot-reload" => config.hot_reload = parse_switch("--hot-reload", &value("--hot-reload")?)?,
+                "--scripts" => config.scripts_dir = PathBuf::from(value("--scripts")?),
+                "--custom-titlebar" => config.custom_titlebar = parse_switch("--custom-titlebar", &value("--custom-titlebar")?)?,
+                "--render-mode" => {
+                    config.render_mode = match value("--render-mode")?.as_str() {
+                        "continuous" => RenderMode::Continuous,
+                        "on-demand" => RenderMode::OnDemand,
+                        other => return Err(format!("--render-mode expects continuous or on-demand, got '{}'", other)),
+                    }
+                }
+                "--sky" => {
+                    config.render.sky = match value("--sky")?.as_str() {
+                        "none" => SkyMode::None,
+                        "procedural" => SkyMode::Procedural,
+                        other => return Err(format!("--sky expects none or procedural, got '{}'", other)),
+                    }
+                }
+                "--outline" => {
+                    config.render.outline = match value("--outline")?.as_str() {
+                        "off" => OutlineMode::Off,
+                        "selection" => OutlineMode::SelectionOnly,
+                        "scene" => OutlineMode::FullScene,
+                        "hull" => OutlineMode::Hull,
+                        other => return Err(format!("--outline expects off, selection, scene or hull, got '{}'", other)),
+                    }
+                }
+                "--stereo" => {
+                    config.stereo = match value("--stereo")?.as_str() {
+                        "off" => StereoMode::Off,
+                        "side-by-side" => StereoMode::SideBySide,
+                        "anaglyph" => StereoMode::Anaglyph,
+                        other => return Err(format!("--stereo expects off, side-by-side or anaglyph, got '{}'", other)),
+                    }
+                }
+                other => return Err(format!("unknown argument '{}'", other)),
+            }
+        }
+
+        // Flags can come in any order, so the extent is applied once everything is parsed
+        if let Some(extent) = scene_extent {
+            match config.random_scene.as_mut() {
+                Some(options) => options.extent = extent,
+                None => return Err("--scene-extent requires --random-scene".to_string()),
+            }
+        }
+
+        if config.render.taa && config.render.msaa_samples > 1 {
+            return Err("--taa on and --msaa 4 can't be combined, pick one anti-aliasing".to_string());
+        }
+        if config.render.taa && !config.render.hdr {
+            return Err("--taa on needs --hdr on".to_string());
+        }
+
+        Ok(CliCommand::Run(Box::new(config)))
+    }
+}
+
+// Parses "NxM" (also accepts "N" for a square grid)
+fn parse_grid(flag: &str, text: &str) -> Result<(u32, u32), String> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("{} expects NxM, got '{}'", flag, text))
+    };
+    match text.split_once(['x', 'X']) {
+        Some((columns, rows)) => Ok((parse(columns)?, parse(rows)?)),
+        None => {
+            let n = parse(text)?;
+            Ok((n, n))
+        }
+    }
+}
+
+// Parses "on" or "off"
+fn parse_switch(flag: &str, text: &str) -> Result<bool, String> {
+    match text {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => Err(format!("{} expects on or off, got '{}'", flag, other)),
+    }
+}
+
+// Parses a positive number of MiB, returned in bytes
+fn parse_mib(flag: &str, text: &str) -> Result<u64, String> {
+    let mib = text
+        .parse::<u64>()
+        .ok()
+        .filter(|mib| *mib > 0)
+        .ok_or_else(|| format!("{} expects a positive number of MiB, got '{}'", flag, text))?;
+    mib.checked_mul(1024 * 1024).ok_or_else(|| format!("{} {} MiB is too large", flag, text))
+}
+
+// Parses "W:H" with both sides above zero
+fn parse_ratio(text: &str) -> Result<(u32, u32), String> {
+    let error = || format!("--aspect-lock expects W:H (e.g. 16:9) or off, got '{}'", text);
+    let (width, height) = text.split_once(':').ok_or_else(error)?;
+    let width = width.trim().parse::<u32>().map_err(|_| error())?;
+    let height = height.trim().parse::<u32>().map_err(|_| error())?;
+    if width == 0 || height == 0 {
+        return Err(error());
+    }
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<EngineConfig, String> {
+        match EngineConfig::from_args(args.iter().map(|arg| arg.to_string()))? {
+            CliCommand::Run(config) => Ok(*config),
+            _ => Err("not a run".to_string()),
+        }
+    }
+
+    #[test]
+    fn memory_budget_is_mib() {
+        let config = parse(&["--memory-budget", "512"]).unwrap();
+        assert_eq!(config.render.memory_budget, Some(512 * 1024 * 1024));
+    }
+
+    #[test]
+    fn memory_budget_overflow_is_an_error() {
+        let error = parse(&["--memory-budget", "18000000000000"]).err().unwrap();
+        assert!(error.contains("too large"), "{}", error);
+        assert!(parse(&["--memory-budget", "0"]).is_err());
+    }
+
+    #[test]
+    fn instance_grid_is_capped() {
+        assert_eq!(parse(&["--instances", "10x20"]).unwrap().instances, (10, 20));
+        assert!(parse(&["--instances", "1000x1000"]).is_ok());
+        assert!(parse(&["--instances", "70000x70000"]).is_err());
+        assert!(parse(&["--instances", "1001x1000"]).is_err());
+    }
+
+    #[test]
+    fn random_scene_is_capped() {
+        assert_eq!(parse(&["--random-scene", "250"]).unwrap().random_scene.map(|options| options.shape_count), Some(250));
+        assert!(parse(&["--random-scene", "1000000"]).is_ok());
+        let error = parse(&["--random-scene", "4000000000"]).err().unwrap();
+        assert!(error.contains("more than"), "{}", error);
+        assert!(parse(&["--random-scene", "1000001"]).is_err());
+    }
+
+    #[test]
+    fn texture_budget_overflow_is_an_error() {
+        let config = parse(&["--texture-budget", "64"]).unwrap();
+        assert_eq!(config.render.texture_budget, Some(64 * 1024 * 1024));
+        let error = parse(&["--texture-budget", "18000000000000"]).err().unwrap();
+        assert!(error.contains("--texture-budget") && error.contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn switches_take_on_or_off() {
+        let config = parse(&["--vsync", "off", "--weld=on", "--hot-reload", "on"]).unwrap();
+        assert!(!config.render.vsync && config.render.mesh_load.weld && config.hot_reload);
+        let error = parse(&["--pack-textures", "yes"]).err().unwrap();
+        assert_eq!(error, "--pack-textures expects on or off, got 'yes'");
+    }
+}
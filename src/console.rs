@@ -0,0 +1,448 @@
+/*
+Purpose: Quake-style console, typed commands instead of a button for everything
+Responsibilities:
+    - Keep a registry of named commands that run against the State on the main thread
+    - Show command output and the engine's log lines in one scrollback, colored by severity
+    - Complete command names with Tab, walk the history with Up/Down, and keep the history in a
+      file next to the settings
+    - ex: the service hatch on the back of a vending machine
+*/
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Mutex, OnceLock};
+
+use cgmath::One;
+
+use crate::{camera::Camera, custom_shader, model_entry::{InstanceId, ModelHandle}, rtt::{self, RttId}, state::State, video_export::{self, ExportSpan}};
+
+const MAX_SCROLLBACK: usize = 500;
+const MAX_HISTORY: usize = 100;
+// Log lines waiting for the console to pick them up, older ones are dropped
+const MAX_PENDING_LOG_LINES: usize = 500;
+pub const HISTORY_FILE: &str = "console_history.txt";
+// Fraction of the window the console covers from the top
+const HEIGHT_FRACTION: f32 = 0.4;
+const DEFAULT_SCREENSHOT: &str = "screenshot.png";
+
+// Arguments after the command name. Err is printed together with the command's usage.
+pub type CommandFn = dyn Fn(&[&str], &mut State) -> Result<String, String>;
+
+struct Command {
+    usage: &'static str,
+    help: &'static str,
+    run: Rc<CommandFn>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Input,
+    Output,
+    Error,
+    Log(log::Level),
+}
+
+impl LineKind {
+    fn color(self) -> egui::Color32 {
+        match self {
+            LineKind::Input => egui::Color32::from_rgb(150, 200, 255),
+            LineKind::Output | LineKind::Log(log::Level::Info) => egui::Color32::from_gray(220),
+            LineKind::Error | LineKind::Log(log::Level::Error) => egui::Color32::from_rgb(255, 100, 90),
+            LineKind::Log(log::Level::Warn) => egui::Color32::from_rgb(240, 200, 80),
+            LineKind::Log(log::Level::Debug | log::Level::Trace) => egui::Color32::from_gray(140),
+        }
+    }
+}
+
+struct Line {
+    kind: LineKind,
+    text: String,
+}
+
+pub struct Console {
+    pub open: bool,
+    input: String,
+    scrollback: VecDeque<Line>,
+    commands: BTreeMap<&'static str, Command>,
+    // Oldest first, `history_cursor` walks it backwards from the end
+    history: Vec<String>,
+    history_cursor: Option<usize>,
+    history_path: PathBuf,
+    // Set when the console opens, so typing goes straight into it
+    focus_input: bool,
+}
+
+impl Console {
+    // With the built-in commands, the history is read from `history_path` if it exists
+    pub fn new(history_path: PathBuf) -> Self {
+        let history = std::fs::read_to_string(&history_path)
+            .map(|text| text.lines().filter(|line| !line.trim().is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+        let mut console = Self {
+            open: false,
+            input: String::new(),
+            scrollback: VecDeque::new(),
+            commands: BTreeMap::new(),
+            history,
+            history_cursor: None,
+            history_path,
+            focus_input: false,
+        };
+        console.register_builtins();
+        console
+    }
+
+    // Replaces a command registered under the same name
+    pub fn register(&mut self, name: &'static str, usage: &'static str, help: &'static str, run: impl Fn(&[&str], &mut State) -> Result<String, String> + 'static) {
+        self.commands.insert(name, Command { usage, help, run: Rc::new(run) });
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.focus_input = self.open;
+    }
+
+    fn print(&mut self, kind: LineKind, text: impl Into<String>) {
+        for line in text.into().lines() {
+            if self.scrollback.len() == MAX_SCROLLBACK {
+                self.scrollback.pop_front();
+            }
+            self.scrollback.push_back(Line { kind, text: line.to_string() });
+        }
+    }
+
+    // Runs one line against the state, echoing it and printing what the command returned
+    pub fn execute(state: &mut State, line: &str) {
+        let console = &mut state.console;
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        console.print(LineKind::Input, format!("> {}", line));
+        console.push_history(line);
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let Some(command) = console.commands.get(name) else {
+            console.print(LineKind::Error, format!("Unknown command '{}', try help", name));
+            return;
+        };
+        let (run, usage) = (command.run.clone(), command.usage);
+        let result = run(&args, state);
+        let console = &mut state.console;
+        match result {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => console.print(LineKind::Output, output),
+            Err(error) => {
+                console.print(LineKind::Error, error);
+                console.print(LineKind::Error, format!("usage: {}", usage));
+            }
+        }
+    }
+
+    fn push_history(&mut self, line: &str) {
+        self.history_cursor = None;
+        if self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        let mut text = self.history.join("\n");
+        text.push('\n');
+        if let Err(e) = std::fs::write(&self.history_path, text) {
+            log::warn!("Could not save the console history to {}: {}", self.history_path.display(), e);
+        }
+    }
+
+    // Up goes back in time, Down forward and finally to an empty line
+    fn walk_history(&mut self, back: bool) {
+        let cursor = match (self.history_cursor, back) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) => (index + 1 < self.history.len()).then_some(index + 1),
+        };
+        self.history_cursor = cursor;
+        self.input = cursor.map(|index| self.history[index].clone()).unwrap_or_default();
+    }
+
+    // Completes the command name, as far as the candidates agree, and lists them when they don't
+    fn complete(&mut self) {
+        if self.input.contains(char::is_whitespace) {
+            return;
+        }
+        let candidates: Vec<&str> = self.commands.keys().copied().filter(|name| name.starts_with(self.input.as_str())).collect();
+        match candidates.as_slice() {
+            [] => {}
+            [only] => self.input = format!("{} ", only),
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.len(), |len, name| first.bytes().zip(name.bytes()).take(len).take_while(|(a, b)| a == b).count());
+                self.input = first[..common].to_string();
+                let listed = candidates.join("  ");
+                self.print(LineKind::Output, listed);
+            }
+        }
+    }
+
+    // Moves log lines recorded since the last frame into the scrollback
+    fn collect_log_lines(&mut self) {
+        let lines = std::mem::take(&mut *log_lines().lock().unwrap());
+        for (level, text) in lines {
+            self.print(LineKind::Log(level), text);
+        }
+    }
+
+    // Returns a submitted line, the caller runs it with execute
+    pub fn draw(&mut self, ctx: &egui::Context) -> Option<String> {
+        self.collect_log_lines();
+        if !self.open {
+            return None;
+        }
+        let mut submitted = None;
+        let height = ctx.screen_rect().height() * HEIGHT_FRACTION;
+        egui::TopBottomPanel::top("console")
+            .exact_height(height)
+            .frame(egui::Frame::new().fill(egui::Color32::from_black_alpha(220)).inner_margin(6.0))
+            .show(ctx, |ui| {
+                let input_height = ui.spacing().interact_size.y + 6.0;
+                egui::ScrollArea::vertical()
+                    .max_height(ui.available_height() - input_height)
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in &self.scrollback {
+                            ui.label(egui::RichText::new(&line.text).monospace().color(line.kind.color()));
+                        }
+                    });
+                // Taken before the text field sees them, Tab would move the focus away
+                let (tab, up, down, close) = ui.input_mut(|input| {
+                    (
+                        input.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                        input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                        input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                        input.consume_key(egui::Modifiers::NONE, egui::Key::Backtick) || input.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+                    )
+                });
+                if tab {
+                    self.complete();
+                }
+                if up || down {
+                    self.walk_history(up);
+                }
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.input)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Command, Tab completes, help lists them"),
+                );
+                // The key that opened the console arrives as text too
+                self.input.retain(|c| c != '`');
+                if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                    submitted = Some(std::mem::take(&mut self.input));
+                    self.focus_input = true;
+                }
+                if std::mem::take(&mut self.focus_input) || tab || up || down {
+                    response.request_focus();
+                }
+                if close {
+                    self.open = false;
+                }
+            });
+        submitted
+    }
+
+    fn register_builtins(&mut self) {
+        self.register("help", "help", "List the commands", |_, state| {
+            Ok(state.console.commands.values().map(|command| format!("{:<40} {}", command.usage, command.help)).collect::<Vec<_>>().join("\n"))
+        });
+        self.register("clear", "clear", "Empty the scrollback", |_, state| {
+            state.console.scrollback.clear();
+            Ok(String::new())
+        });
+        self.register("spawn", "spawn <model> [x y z]", "Add an instance of a model, see stats for the numbers", |args, state| {
+            let (model, position) = match args {
+                [model] => (model, [0.0; 3]),
+                [model, x, y, z] => (model, [parse(x)?, parse(y)?, parse(z)?]),
+                _ => return Err("expected a model and optionally a position".to_string()),
+            };
+            let handle = ModelHandle(parse(model)?);
+            let id = state
+                .add_instance_of(handle, position.into(), cgmath::Quaternion::one())
+                .ok_or_else(|| format!("no model {}", handle.0))?;
+            Ok(format!("Spawned instance {} of model {}", id.index, handle.0))
+        });
+        self.register("despawn", "despawn <model> [index]", "Remove an instance of a model, the newest without an index", |args, state| {
+            let (model, index) = match args {
+                [model] => (model, None),
+                [model, index] => (model, Some(parse(index)?)),
+                _ => return Err("expected a model and optionally an instance index".to_string()),
+            };
+            let handle = ModelHandle(parse(model)?);
+            let Some(id) = index.map(|index| InstanceId { model: handle, index }).or_else(|| state.newest_instance(handle)) else {
+                return Err(format!("model {} has no instances to remove", handle.0));
+            };
+            match state.despawn_instance(id) {
+                true => Ok(format!("Removed instance {} of model {}", id.index, handle.0)),
+                false => Err(format!("model {} has no instance {}", handle.0, id.index)),
+            }
+        });
+        self.register("load_model", "load_model <path>", "Load an OBJ relative to res/, without instances, with the last import options for its extension", |args, state| {
+            let [path] = args else {
+                return Err("expected a path".to_string());
+            };
+            let import = state.import_options_for(path);
+            let handle = state.add_model(path, &import).map_err(|e| format!("could not load {}: {}", path, e))?;
+            Ok(format!("Loaded {} as model {}", path, handle.0))
+        });
+        self.register("set_light", "set_light <r> <g> <b> [intensity]", "Color the scene light, sRGB channels 0 to 1", |args, state| {
+            let (color, intensity) = match args {
+                [r, g, b] => ([parse(r)?, parse(g)?, parse(b)?], None),
+                [r, g, b, intensity] => ([parse(r)?, parse(g)?, parse(b)?], Some(parse(intensity)?)),
+                _ => return Err("expected three channels and optionally an intensity".to_string()),
+            };
+            state.set_light(color, intensity);
+            Ok(String::new())
+        });
+        self.register("set_clear_color", "set_clear_color <r> <g> <b>", "Background behind the scene, sRGB channels 0 to 1", |args, state| {
+            let [r, g, b] = args else {
+                return Err("expected three channels".to_string());
+            };
+            state.set_clear_color([parse(r)?, parse(g)?, parse(b)?]);
+            Ok(String::new())
+        });
+        self.register("stats", "stats", "Frame times, models and GPU memory", |_, state| Ok(state.stats_report()));
+        self.register("screenshot", "screenshot [path]", "Save the main window's next frame as a PNG", |args, state| {
+            let path = match args {
+                [] => DEFAULT_SCREENSHOT,
+                [path] => path,
+                _ => return Err("expected at most one path".to_string()),
+            };
+            state.request_screenshot(Path::new(path));
+            Ok(format!("Saving the next frame to {}", path))
+        });
+        self.register("export_video", "export_video <path> <seconds> [fps] [w h]", "Record seconds of the main window's view, to ffmpeg or as PNGs", |args, state| {
+            let (path, seconds, fps, size) = match args {
+                [path, seconds] => (path, seconds, None, None),
+                [path, seconds, fps] => (path, seconds, Some(fps), None),
+                [path, seconds, fps, w, h] => (path, seconds, Some(fps), Some((parse(w)?, parse(h)?))),
+                _ => return Err("expected a path and a length, optionally a frame rate and a size".to_string()),
+            };
+            let fps = fps.map(|fps| parse(fps)).transpose()?.unwrap_or(video_export::DEFAULT_FPS);
+            let span = ExportSpan::Seconds(parse(seconds)?);
+            state
+                .export_video(Path::new(path), fps, span, size.unwrap_or(video_export::DEFAULT_SIZE))
+                .map_err(|e| format!("could not export {}: {}", path, e))?;
+            Ok(format!("Exporting {} s to {}", seconds, path))
+        });
+        self.register("rtt_add", "rtt_add <x> <y> <z> <yaw> <pitch> [w h]", "Add a render-to-texture camera, angles in degrees", |args, state| {
+            let (pose, resolution) = match args {
+                [x, y, z, yaw, pitch] => ([x, y, z, yaw, pitch], rtt::MIRROR_RESOLUTION),
+                [x, y, z, yaw, pitch, w, h] => ([x, y, z, yaw, pitch], (parse(w)?, parse(h)?)),
+                _ => return Err("expected a position, yaw and pitch and optionally a resolution".to_string()),
+            };
+            let [x, y, z, yaw, pitch] = pose;
+            let camera = Camera::new(cgmath::Point3::new(parse(x)?, parse(y)?, parse(z)?), cgmath::Deg::<f32>(parse(yaw)?), cgmath::Deg::<f32>(parse(pitch)?));
+            let id = state.add_rtt_camera(camera, resolution);
+            Ok(format!("Added render-to-texture camera {}", id.0))
+        });
+        self.register("rtt_resolution", "rtt_resolution <rtt> <w> <h>", "Resize a render-to-texture camera's image", |args, state| {
+            let [id, w, h] = args else {
+                return Err("expected a camera, a width and a height".to_string());
+            };
+            let id = RttId(parse(id)?);
+            match state.set_rtt_resolution(id, (parse(w)?, parse(h)?)) {
+                true => Ok(String::new()),
+                false => Err(format!("no render-to-texture camera {}", id.0)),
+            }
+        });
+        self.register("rtt_bind", "rtt_bind <model> <mesh> <rtt>", "Show a camera's image on a mesh's material, mesh by index or name", |args, state| {
+            let [model, mesh, id] = args else {
+                return Err("expected a model, a mesh and a camera".to_string());
+            };
+            let (handle, id) = (ModelHandle(parse(model)?), RttId(parse(id)?));
+            let bound = match mesh.parse::<usize>() {
+                Ok(index) => state.set_material_rtt(handle, index, id),
+                Err(_) => state.set_material_rtt(handle, *mesh, id),
+            };
+            match bound {
+                true => Ok(format!("Model {} mesh {} shows camera {}", handle.0, mesh, id.0)),
+                false => Err(format!("no model {}, mesh {} or camera {}", handle.0, mesh, id.0)),
+            }
+        });
+        self.register("shader_reload", "shader_reload [path]", "Use a WGSL file as the model shader, checked against the engine's bind groups", |args, state| {
+            let path = match args {
+                [] => custom_shader::SHADER_SOURCE,
+                [path] => path,
+                _ => return Err("expected at most one path".to_string()),
+            };
+            state.load_custom_shader(Path::new(path))?;
+            Ok(format!("Drawing models with {}", path))
+        });
+        self.register("shader_reset", "shader_reset", "Go back to the built-in model shader", |_, state| {
+            match state.clear_custom_shader() {
+                true => Ok("Drawing models with the built-in shader".to_string()),
+                false => Err("no custom shader in use".to_string()),
+            }
+        });
+        self.register("quit", "quit", "Close the engine", |_, state| {
+            state.request_quit();
+            Ok(String::new())
+        });
+    }
+}
+
+fn parse<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("'{}' is not a valid number", text))
+}
+
+fn log_lines() -> &'static Mutex<Vec<(log::Level, String)>> {
+    static LINES: OnceLock<Mutex<Vec<(log::Level, String)>>> = OnceLock::new();
+    LINES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+// env_logger still decides what reaches the terminal, through RUST_LOG. The console gets this
+// crate's info and everyone's warnings whatever RUST_LOG says.
+struct ConsoleLogger {
+    terminal: env_logger::Logger,
+}
+
+impl ConsoleLogger {
+    fn captures(metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn || (metadata.level() <= log::Level::Info && metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+}
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.terminal.enabled(metadata) || Self::captures(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.terminal.matches(record) {
+            self.terminal.log(record);
+        }
+        if Self::captures(record.metadata()) {
+            let mut lines = log_lines().lock().unwrap();
+            if lines.len() == MAX_PENDING_LOG_LINES {
+                lines.remove(0);
+            }
+            lines.push((record.level(), format!("[{}] {}", record.level(), record.args())));
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+    }
+}
+
+// In place of env_logger::init
+pub fn install_logger() {
+    let terminal = env_logger::Builder::from_default_env().build();
+    let max_level = terminal.filter().max(log::LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(ConsoleLogger { terminal })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
@@ -0,0 +1,89 @@
+/*
+Purpose: Engine-managed cursor icon
+Responsibilities:
+    - Name the situations that want their own cursor (camera drag, mouse look, gizmo handles, placement)
+    - Keep the contexts pushed by hooks and pick the one with the highest priority
+    - Resolve the icon once per frame through egui, which only touches the window when it changes
+    - ex: the cursor turning into a hand over something that can be grabbed
+*/
+
+use crate::transform_gizmo::GizmoMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorContext {
+    // Turning the camera with the cursor free
+    CameraDrag,
+    // Turning the camera with the cursor grabbed, the cursor is hidden
+    MouseLook,
+    // Waiting for a click in the scene to put something there
+    Placement,
+    // Over a transform gizmo handle, or dragging one
+    GizmoHandle { mode: GizmoMode, dragging: bool },
+}
+
+impl CursorContext {
+    // When several contexts are active the highest wins: hovering a gizmo handle beats
+    // placement, which beats whatever the camera is doing
+    pub fn priority(self) -> u8 {
+        match self {
+            CursorContext::CameraDrag => 0,
+            CursorContext::MouseLook => 1,
+            CursorContext::Placement => 2,
+            CursorContext::GizmoHandle { .. } => 3,
+        }
+    }
+
+    // CursorIcon::None hides the cursor
+    pub fn icon(self) -> egui::CursorIcon {
+        match self {
+            CursorContext::CameraDrag => egui::CursorIcon::Grabbing,
+            CursorContext::MouseLook => egui::CursorIcon::None,
+            CursorContext::Placement => egui::CursorIcon::Crosshair,
+            CursorContext::GizmoHandle { mode: GizmoMode::Translate, .. } => egui::CursorIcon::Move,
+            CursorContext::GizmoHandle { mode: GizmoMode::Rotate, dragging: false } => egui::CursorIcon::Grab,
+            CursorContext::GizmoHandle { mode: GizmoMode::Rotate, dragging: true } => egui::CursorIcon::Grabbing,
+            CursorContext::GizmoHandle { mode: GizmoMode::Scale, .. } => egui::CursorIcon::ResizeNwSe,
+        }
+    }
+}
+
+// Contexts pushed by hooks, they stay until popped. Contexts that follow the input (camera,
+// gizmo) are passed to resolve every frame instead.
+#[derive(Debug, Default)]
+pub struct CursorStack {
+    contexts: Vec<CursorContext>,
+}
+
+impl CursorStack {
+    pub fn push(&mut self, context: CursorContext) {
+        self.contexts.push(context);
+    }
+
+    // Removes the newest context
+    pub fn pop(&mut self) -> Option<CursorContext> {
+        self.contexts.pop()
+    }
+
+    // Highest priority among the pushed and the per-frame contexts, the newest wins a tie
+    pub fn resolve(&self, frame: impl IntoIterator<Item = CursorContext>) -> Option<CursorContext> {
+        self.contexts.iter().copied().chain(frame).max_by_key(|context| context.priority())
+    }
+
+    // Sets this frame's cursor on `ctx` unless egui wants the pointer. Over an egui window the
+    // icon is left to egui (default arrow or its own hints like text or resize), except while
+    // the camera is being dragged, which started in the scene and keeps its cursor.
+    pub fn apply(&self, ctx: &egui::Context, frame: impl IntoIterator<Item = CursorContext>, camera_dragging: bool) {
+        let over_egui = ctx
+            .pointer_hover_pos()
+            .and_then(|pos| ctx.layer_id_at(pos))
+            // The transform gizmo is a background area, it reports its handles as a context instead
+            .is_some_and(|layer| layer.order != egui::Order::Background);
+        let egui_hint = ctx.output(|output| output.cursor_icon) != egui::CursorIcon::Default;
+        if (over_egui || egui_hint) && !camera_dragging {
+            return;
+        }
+        if let Some(context) = self.resolve(frame) {
+            ctx.set_cursor_icon(context.icon());
+        }
+    }
+}
@@ -0,0 +1,436 @@
+/*
+Purpose: Hot-reload the model shader, with whatever engine bindings the edited source declares
+Responsibilities:
+    - Document the bind groups the engine provides at stable indices (material, camera, light,
+      frame uniforms), what each binding holds and which stages can see it
+    - Reflect a WGSL source with naga: the entry points and every @group/@binding it declares
+    - Check the declarations against the engine's groups, listing every binding the engine can't
+      provide instead of letting wgpu fail on an incompatible layout
+    - Build the model pipeline with a layout of exactly the groups the shader reaches
+    - Watch shader.wgsl on disk while hot reload is on
+    - ex: the stage manager reading the rider before the band arrives
+*/
+
+use crate::{model::{self, Vertex}, instance::InstanceRaw, render_context::{self, RenderContext}, texture};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use wgpu::naga;
+
+// Stable group indices, a custom shader declares `@group(FRAME_GROUP) @binding(FRAME_BINDING)`
+// to get the frame uniforms. The first three are the ones render_pipeline always had. Group 3
+// is the last the default limits allow and belongs to each pipeline: shader.wgsl already puts
+// the toon uniform (binding 0) and the reflection probe (1 to 3) there for fs_toon and
+// fs_reflective, so the frame uniforms sit past them.
+pub const MATERIAL_GROUP: u32 = 0;
+pub const CAMERA_GROUP: u32 = 1;
+pub const LIGHT_GROUP: u32 = 2;
+pub const FRAME_GROUP: u32 = 3;
+pub const FRAME_BINDING: u32 = 4;
+
+// What the model pipeline calls, other entry points of the file belong to other pipelines
+const ENTRY_POINTS: [&str; 2] = ["vs_main", "fs_main"];
+
+// The shader.wgsl next to this file, only there when running from the source tree
+pub const SHADER_SOURCE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shader.wgsl");
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Editors often truncate the file then write it, it is only read once it stopped changing this long
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+// Must match a `var<uniform>` of this layout at FRAME_GROUP, FRAME_BINDING:
+// struct Frame { time: f32, delta_time: f32, frame: u32 }
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FrameUniform {
+    // Scene time in seconds, stops while paused
+    pub time: f32,
+    pub delta_time: f32,
+    pub frame: u32,
+    pub _padding: u32,
+}
+
+// What a binding is, as far as matching engine resources goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Uniform,
+    Storage,
+    // Filterable float texture_2d, every texture the engine binds to models is one
+    Texture2d,
+    // Filtering, not comparison
+    Sampler,
+    // Anything else, the engine never provides one
+    Other,
+}
+
+impl BindingKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            BindingKind::Uniform => "uniform buffer",
+            BindingKind::Storage => "storage buffer",
+            BindingKind::Texture2d => "texture_2d<f32>",
+            BindingKind::Sampler => "sampler",
+            BindingKind::Other => "other",
+        }
+    }
+}
+
+pub struct EngineBinding {
+    pub binding: u32,
+    pub name: &'static str,
+    pub kind: BindingKind,
+    pub visibility: wgpu::ShaderStages,
+}
+
+pub struct EngineGroup {
+    pub index: u32,
+    pub name: &'static str,
+    pub bindings: &'static [EngineBinding],
+}
+
+const fn binding(binding: u32, name: &'static str, kind: BindingKind, visibility: wgpu::ShaderStages) -> EngineBinding {
+    EngineBinding { binding, name, kind, visibility }
+}
+
+// Everything a custom model shader can declare, indexed by group. Must match the layouts in
+// RenderContext::new and the bind groups draw_scene_objects sets.
+pub const ENGINE_GROUPS: [EngineGroup; 4] = [
+    EngineGroup {
+        index: MATERIAL_GROUP,
+        name: "material",
+        bindings: &[
+            binding(0, "diffuse texture", BindingKind::Texture2d, wgpu::ShaderStages::FRAGMENT),
+            binding(1, "diffuse sampler", BindingKind::Sampler, wgpu::ShaderStages::FRAGMENT),
+            binding(2, "normal texture", BindingKind::Texture2d, wgpu::ShaderStages::FRAGMENT),
+            binding(3, "normal sampler", BindingKind::Sampler, wgpu::ShaderStages::FRAGMENT),
+            binding(4, "material parameters", BindingKind::Uniform, wgpu::ShaderStages::FRAGMENT),
+        ],
+    },
+    EngineGroup {
+        index: CAMERA_GROUP,
+        name: "camera",
+        bindings: &[binding(0, "camera", BindingKind::Uniform, wgpu::ShaderStages::VERTEX)],
+    },
+    EngineGroup {
+        index: LIGHT_GROUP,
+        name: "light",
+        bindings: &[
+            binding(0, "light", BindingKind::Uniform, wgpu::ShaderStages::VERTEX_FRAGMENT),
+            binding(1, "clip planes", BindingKind::Uniform, wgpu::ShaderStages::FRAGMENT),
+            binding(2, "point lights", BindingKind::Uniform, wgpu::ShaderStages::FRAGMENT),
+        ],
+    },
+    EngineGroup {
+        index: FRAME_GROUP,
+        name: "frame uniforms",
+        bindings: &[binding(FRAME_BINDING, "frame", BindingKind::Uniform, wgpu::ShaderStages::VERTEX_FRAGMENT)],
+    },
+];
+
+// One `@group(g) @binding(b) var ...` of a shader
+#[derive(Debug, Clone)]
+pub struct DeclaredBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub name: String,
+    pub kind: BindingKind,
+    // WGSL-ish type for messages, e.g. texture_depth_2d
+    pub type_name: String,
+    // Stages of vs_main and fs_main that use it, empty when only other entry points do
+    pub stages: wgpu::ShaderStages,
+}
+
+pub struct Reflection {
+    pub bindings: Vec<DeclaredBinding>,
+    pub entry_points: Vec<String>,
+}
+
+// Parses and validates the source with naga. Syntax and type errors come back as naga formats them.
+pub fn reflect(source: &str) -> Result<Reflection, String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| e.emit_to_string(source))?;
+    let mut bindings = Vec::new();
+    for (handle, global) in module.global_variables.iter() {
+        let Some(resource) = &global.binding else {
+            continue;
+        };
+        let (kind, type_name) = describe(&module, global);
+        let mut stages = wgpu::ShaderStages::NONE;
+        for (index, entry_point) in module.entry_points.iter().enumerate() {
+            if ENTRY_POINTS.contains(&entry_point.name.as_str()) && !info.get_entry_point(index)[handle].is_empty() {
+                stages |= match entry_point.stage {
+                    naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                    naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                    _ => wgpu::ShaderStages::COMPUTE,
+                };
+            }
+        }
+        bindings.push(DeclaredBinding {
+            group: resource.group,
+            binding: resource.binding,
+            name: global.name.clone().unwrap_or_else(|| "<unnamed>".to_string()),
+            kind,
+            type_name,
+            stages,
+        });
+    }
+    bindings.sort_by_key(|declared| (declared.group, declared.binding));
+    let entry_points = module.entry_points.iter().map(|entry_point| entry_point.name.clone()).collect();
+    Ok(Reflection { bindings, entry_points })
+}
+
+fn describe(module: &naga::Module, global: &naga::GlobalVariable) -> (BindingKind, String) {
+    match global.space {
+        naga::AddressSpace::Uniform => (BindingKind::Uniform, "var<uniform>".to_string()),
+        naga::AddressSpace::Storage { .. } => (BindingKind::Storage, "var<storage>".to_string()),
+        naga::AddressSpace::Handle => match &module.types[global.ty].inner {
+            naga::TypeInner::Image {
+                dim: naga::ImageDimension::D2,
+                arrayed: false,
+                class: naga::ImageClass::Sampled { kind: naga::ScalarKind::Float, multi: false },
+            } => (BindingKind::Texture2d, "texture_2d<f32>".to_string()),
+            naga::TypeInner::Image { dim, arrayed, class } => {
+                let array = if *arrayed { " array" } else { "" };
+                (BindingKind::Other, format!("{:?}{} texture ({:?})", dim, array, class))
+            }
+            naga::TypeInner::Sampler { comparison: false } => (BindingKind::Sampler, "sampler".to_string()),
+            naga::TypeInner::Sampler { comparison: true } => (BindingKind::Other, "sampler_comparison".to_string()),
+            _ => (BindingKind::Other, "binding array".to_string()),
+        },
+        space => (BindingKind::Other, format!("{:?}", space)),
+    }
+}
+
+// Number of groups the pipeline layout needs, one past the highest used, or every binding the
+// engine can't provide. Only bindings vs_main and fs_main use count. Groups below the highest
+// are filled in even if unused, the draw binds them anyway.
+pub fn check(reflection: &Reflection) -> Result<u32, Vec<String>> {
+    let mut problems = Vec::new();
+    for entry_point in ENTRY_POINTS {
+        if !reflection.entry_points.iter().any(|name| name == entry_point) {
+            problems.push(format!("missing entry point {}, the model pipeline calls vs_main and fs_main", entry_point));
+        }
+    }
+    let used = reflection.bindings.iter().filter(|declared| !declared.stages.is_empty());
+    for declared in used.clone() {
+        let at = format!("@group({}) @binding({}) {}", declared.group, declared.binding, declared.name);
+        let Some(group) = ENGINE_GROUPS.iter().find(|group| group.index == declared.group) else {
+            problems.push(format!("{}: there is no group {}, the engine provides {}", at, declared.group, group_list()));
+            continue;
+        };
+        let Some(provided) = group.bindings.iter().find(|provided| provided.binding == declared.binding) else {
+            let bindings = group.bindings.iter().map(|provided| format!("{} {}", provided.binding, provided.name)).collect::<Vec<_>>();
+            problems.push(format!("{}: the {} group has no binding {}, it has {}", at, group.name, declared.binding, bindings.join(", ")));
+            continue;
+        };
+        if declared.kind != provided.kind {
+            problems.push(format!(
+                "{}: declared as {}, the engine binds the {} there, a {}",
+                at,
+                declared.type_name,
+                provided.name,
+                provided.kind.label()
+            ));
+        } else if !provided.visibility.contains(declared.stages) {
+            problems.push(format!("{}: used in {:?}, the engine only makes the {} visible to {:?}", at, declared.stages, provided.name, provided.visibility));
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(used.map(|declared| declared.group + 1).max().unwrap_or(0).max(LIGHT_GROUP + 1)),
+        false => Err(problems),
+    }
+}
+
+fn group_list() -> String {
+    ENGINE_GROUPS.iter().map(|group| format!("{} {}", group.index, group.name)).collect::<Vec<_>>().join(", ")
+}
+
+// Layout for FRAME_GROUP, the other groups' layouts are RenderContext's existing ones
+pub fn frame_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Frame Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: FRAME_BINDING,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+// A model pipeline built from a reloaded source, standing in for render_pipeline
+pub struct CustomShader {
+    pub pipeline: wgpu::RenderPipeline,
+    pub path: PathBuf,
+    // The layout reaches FRAME_GROUP, the frame bind group has to be set with the pipeline
+    pub uses_frame: bool,
+}
+
+impl CustomShader {
+    // Every problem in one message, one per line, nothing is created unless the source checks out
+    pub fn build(context: &RenderContext, path: &Path, source: &str) -> Result<Self, String> {
+        let reflection = reflect(source)?;
+        let group_count = check(&reflection).map_err(|problems| problems.join("\n"))?;
+        let layouts = [
+            &context.texture_bind_group_layout,
+            &context.camera_bind_group_layout,
+            &context.light_bind_group_layout,
+            &context.frame_bind_group_layout,
+        ];
+        let device = &context.device;
+        // check() already caught what it knows about, this catches whatever wgpu still rejects
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Custom Shader Layout"),
+            bind_group_layouts: &layouts[..group_count as usize],
+            push_constant_ranges: &[],
+        });
+        let pipeline = render_context::create_render_pipeline(
+            device,
+            &layout,
+            context.scene_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            context.settings.msaa_samples,
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Custom Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            },
+        );
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(error.to_string());
+        }
+        Ok(Self { pipeline, path: path.to_path_buf(), uses_frame: group_count > FRAME_GROUP })
+    }
+}
+
+// Polls one WGSL file, like texture_watch.rs does for textures
+pub struct ShaderWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    changed_at: Option<Instant>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    // None if the file isn't there, e.g. a binary run away from its source tree
+    pub fn new(path: &Path) -> Option<Self> {
+        let modified = modified(path)?;
+        Some(Self { path: path.to_path_buf(), modified: Some(modified), changed_at: None, last_poll: Instant::now() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The new source once the file changed and settled. Cheap to call every frame.
+    pub fn poll(&mut self, now: Instant) -> Option<String> {
+        if now.duration_since(self.last_poll) < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = now;
+        let current = modified(&self.path);
+        if current != self.modified {
+            self.modified = current;
+            self.changed_at = Some(now);
+            return None;
+        }
+        if self.changed_at.is_none_or(|at| now.duration_since(at) < SETTLE_TIME) {
+            return None;
+        }
+        self.changed_at = None;
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                log::warn!("Could not read {}: {}, waiting for the next change", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vs_main and fs_main around `declarations`, fs_main returning `color`
+    fn shader(declarations: &str, color: &str) -> String {
+        format!(
+            "{}
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {{
+    return vec4<f32>(position, 1.0);
+}}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {{
+    return {};
+}}
+",
+            declarations, color
+        )
+    }
+
+    fn problems(source: &str) -> Vec<String> {
+        check(&reflect(source).unwrap()).unwrap_err()
+    }
+
+    #[test]
+    fn engine_bindings_check_out() {
+        let source = shader(
+            "@group(0) @binding(0) var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1) var s_diffuse: sampler;
+struct Frame { time: f32, delta_time: f32, frame: u32 }
+@group(3) @binding(4) var<uniform> frame: Frame;",
+            "textureSample(t_diffuse, s_diffuse, vec2<f32>(0.5)) * sin(frame.time)",
+        );
+        let reflection = reflect(&source).unwrap();
+        assert_eq!(reflection.bindings.len(), 3);
+        assert_eq!(reflection.bindings[2].stages, wgpu::ShaderStages::FRAGMENT);
+        // Up to the frame group
+        assert_eq!(check(&reflection), Ok(FRAME_GROUP + 1));
+        assert_eq!(check(&reflect(&shader("", "vec4<f32>(1.0)")).unwrap()), Ok(LIGHT_GROUP + 1));
+        assert!(check(&reflect(include_str!("shader.wgsl")).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn a_group_the_engine_lacks_is_reported() {
+        let missing = problems(&shader("@group(5) @binding(0) var<uniform> tint: vec4<f32>;", "tint"));
+        assert_eq!(missing.len(), 1, "{:?}", missing);
+        assert!(missing[0].contains("there is no group 5, the engine provides 0 material, 1 camera"), "{}", missing[0]);
+
+        // The group is there but not the binding
+        let missing = problems(&shader("@group(3) @binding(0) var<uniform> tint: vec4<f32>;\n@group(1) @binding(7) var<uniform> extra: vec4<f32>;", "tint + extra"));
+        assert_eq!(missing.len(), 2, "{:?}", missing);
+        assert!(missing[0].contains("the camera group has no binding 7"), "{}", missing[0]);
+        assert!(missing[1].contains("the frame uniforms group has no binding 0"), "{}", missing[1]);
+    }
+
+    #[test]
+    fn a_binding_of_the_wrong_type_is_reported() {
+        // A sampler where the diffuse texture goes, and a texture where its sampler goes
+        let source = shader("@group(0) @binding(0) var s: sampler;\n@group(0) @binding(1) var t: texture_2d<f32>;", "textureSample(t, s, vec2<f32>(0.5))");
+        let problems = problems(&source);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("declared as sampler, the engine binds the diffuse texture there"), "{}", problems[0]);
+        assert!(problems[1].contains("a sampler"), "{}", problems[1]);
+    }
+
+    #[test]
+    fn unused_declarations_and_missing_entry_points() {
+        // Declared but not reached from vs_main or fs_main, so not checked
+        assert!(check(&reflect(&shader("@group(5) @binding(0) var<uniform> unused: vec4<f32>;", "vec4<f32>(1.0)")).unwrap()).is_ok());
+        let source = shader("", "vec4<f32>(1.0)").replace("fs_main", "fs_other");
+        assert!(problems(&source)[0].contains("missing entry point fs_main"));
+        assert!(reflect("fn broken( {").is_err());
+    }
+}
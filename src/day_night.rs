@@ -0,0 +1,126 @@
+/*
+Purpose: Day-night cycle demo on top of the scene light
+Responsibilities:
+    - Hold one normalized time of day (0 midnight, 0.25 sunrise, 0.5 noon, 0.75 sunset)
+    - Derive everything else from it: the sun's direction, its color going from warm at the
+      horizon to white overhead, the ambient light, the sky (clear) color and the procedural
+      sky's gradient
+    - Hand over to a dim blue moon while the sun is below the horizon
+    - ex: a time-lapse of a city skyline, one dial turning the whole scene
+*/
+
+use crate::light::LightUniform;
+use cgmath::{InnerSpace, Vector3, VectorSpace};
+
+// The light sits this far out along the sun direction, far enough to light the scene like
+// a directional light would
+const SUN_DISTANCE: f32 = 100.0;
+const SUN_INTENSITY: f32 = 1.2;
+const MOON_INTENSITY: f32 = 0.25;
+const SUNRISE_COLOR: [f32; 3] = [1.0, 0.5, 0.2];
+const NOON_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const MOON_COLOR: [f32; 3] = [0.55, 0.65, 1.0];
+const DAY_AMBIENT: f32 = 0.25;
+const NIGHT_AMBIENT: f32 = 0.03;
+const DAY_SKY: [f32; 3] = [0.35, 0.55, 0.85];
+const NIGHT_SKY: [f32; 3] = [0.01, 0.015, 0.04];
+const TWILIGHT_SKY: [f32; 3] = [0.65, 0.35, 0.25];
+// The procedural sky's gradient, the zenith stays the plain sky color
+const DAY_HORIZON: [f32; 3] = [0.7, 0.8, 0.92];
+const NIGHT_HORIZON: [f32; 3] = [0.03, 0.04, 0.08];
+const SUNSET_HORIZON: [f32; 3] = [1.0, 0.45, 0.2];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayNightCycle {
+    // Takes the scene light over while on
+    pub enabled: bool,
+    pub playing: bool,
+    // 0..1, wraps around at midnight
+    pub time_of_day: f32,
+    // Seconds a full day takes while playing
+    pub day_length: f32,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            playing: true,
+            time_of_day: 0.3,
+            day_length: 60.0,
+        }
+    }
+}
+
+impl DayNightCycle {
+    pub fn advance(&mut self, dt: f32) {
+        if self.enabled && self.playing && self.day_length > 0.0 {
+            self.time_of_day = (self.time_of_day + dt / self.day_length).rem_euclid(1.0);
+        }
+    }
+
+    // Unit vector towards the sun. It rises in +X, peaks at noon and sets in -X, tilted
+    // slightly towards +Z so noon shadows aren't straight down.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = (self.time_of_day - 0.25) * std::f32::consts::TAU;
+        Vector3::new(angle.cos(), angle.sin(), 0.35).normalize()
+    }
+
+    // 0 at night, 1 during the day, blending through twilight
+    fn daylight(&self) -> f32 {
+        smoothstep(-0.1, 0.1, self.sun_direction().y)
+    }
+
+    // Overwrites the light's position, color, intensity and ambient. The sun and the moon
+    // both fade out at the horizon, so swapping one for the other there doesn't pop.
+    pub fn apply(&self, light: &mut LightUniform) {
+        let sun = self.sun_direction();
+        let elevation = sun.y;
+        let daylight = self.daylight();
+
+        let color = Vector3::from(MOON_COLOR).lerp(self.sun_color().into(), daylight);
+        let (direction, intensity) = if elevation >= 0.0 {
+            (sun, SUN_INTENSITY * smoothstep(0.0, 0.15, elevation))
+        } else {
+            (-sun, MOON_INTENSITY * smoothstep(0.0, 0.15, -elevation))
+        };
+
+        light.position = (direction * SUN_DISTANCE).into();
+        light.color = color.into();
+        light.intensity = intensity;
+        light.ambient = NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight;
+    }
+
+    // Warm at the horizon, white overhead
+    fn sun_color(&self) -> [f32; 3] {
+        let elevation = self.sun_direction().y;
+        Vector3::from(SUNRISE_COLOR).lerp(NOON_COLOR.into(), smoothstep(0.0, 0.5, elevation)).into()
+    }
+
+    // The sun disk's color and brightness, gone once it has set
+    pub fn sun_disk(&self) -> ([f32; 3], f32) {
+        (self.sun_color(), self.daylight())
+    }
+
+    // Horizon and zenith of the procedural sky. The horizon glows warmer than the rest of the
+    // sky while the sun is low.
+    pub fn sky_gradient(&self) -> ([f32; 3], [f32; 3]) {
+        let elevation = self.sun_direction().y;
+        let horizon = Vector3::from(NIGHT_HORIZON).lerp(DAY_HORIZON.into(), self.daylight());
+        let glow = (1.0 - elevation.abs() / 0.3).clamp(0.0, 1.0) * 0.8;
+        (horizon.lerp(SUNSET_HORIZON.into(), glow).into(), self.sky_color())
+    }
+
+    // Night to day, with a warm glow while the sun is near the horizon
+    pub fn sky_color(&self) -> [f32; 3] {
+        let elevation = self.sun_direction().y;
+        let sky = Vector3::from(NIGHT_SKY).lerp(DAY_SKY.into(), self.daylight());
+        let glow = (1.0 - elevation.abs() / 0.25).clamp(0.0, 1.0) * 0.5;
+        sky.lerp(TWILIGHT_SKY.into(), glow).into()
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
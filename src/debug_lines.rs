@@ -0,0 +1,147 @@
+/*
+Purpose: Colored line segments drawn into the scene for debugging
+Responsibilities:
+    - Own the line list pipeline, drawn with the scene camera and tested against the scene depth
+      without writing to it
+    - Keep segments in a vertex buffer that is only reallocated when more arrive than fit
+    - ex: chalk lines on a sports field, drawn over the grass and never part of it
+*/
+
+use cgmath::Vector3;
+
+use crate::{color, gpu_layout::{VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, texture};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    // sRGB, LineBuffer::upload makes it linear
+    pub color: [f32; 3],
+}
+
+pub const LINE_VERTEX_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(LineVertex, [position, color]),
+    desc: LineVertex::desc,
+};
+
+impl LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct DebugLinePipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl DebugLinePipeline {
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, color_format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[LineVertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // Hidden behind geometry like everything else, but never hides anything itself
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+        Self { pipeline }
+    }
+}
+
+// Pairs of vertices, one segment each
+pub struct LineBuffer {
+    label: &'static str,
+    buffer: Option<Tracked<wgpu::Buffer>>,
+    vertex_count: u32,
+}
+
+impl LineBuffer {
+    pub fn new(label: &'static str) -> Self {
+        Self { label, buffer: None, vertex_count: 0 }
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[LineVertex]) {
+        let vertices: Vec<LineVertex> = vertices.iter().map(|vertex| LineVertex { color: color::srgb_to_linear_rgb(vertex.color), ..*vertex }).collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let fits = self.buffer.as_ref().is_some_and(|buffer| buffer.size() >= bytes.len() as u64);
+        if !fits && !vertices.is_empty() {
+            self.buffer = Some(gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: Some(self.label),
+                contents: bytes,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }));
+        } else if let Some(buffer) = &self.buffer
+            && !vertices.is_empty()
+        {
+            queue.write_buffer(buffer, 0, bytes);
+        }
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, pipeline: &DebugLinePipeline, camera_bind_group: &wgpu::BindGroup) {
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// Three short segments along the world axes
+pub fn cross(center: Vector3<f32>, size: f32, color: [f32; 3], out: &mut Vec<LineVertex>) {
+    for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+        out.push(LineVertex { position: (center - axis * size).into(), color });
+        out.push(LineVertex { position: (center + axis * size).into(), color });
+    }
+}
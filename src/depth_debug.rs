@@ -0,0 +1,299 @@
+/*
+Purpose: Debug views of a window's depth buffer
+Responsibilities:
+    - Read the depth back, linearize it with the camera's near/far planes and make a grayscale image of it
+    - Draw the linearized depth live into a thumbnail in the corner of the window
+    - ex: checking the near/far planes when something z-fights (Shift+F12 saves a PNG)
+*/
+
+use crate::{camera::Projection, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, render_context::RenderContext, texture};
+
+// Thumbnail width as a share of the window's, it keeps the window's aspect ratio
+const THUMBNAIL_SCALE: f32 = 0.25;
+// Gap to the bottom left corner of the window, in physical pixels
+const THUMBNAIL_MARGIN: f32 = 8.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DepthViewUniform {
+    depth_range: [f32; 4],
+    viewport: [f32; 4],
+}
+
+pub const DEPTH_VIEW_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(DepthViewUniform, [depth_range, viewport]),
+    wgsl: &[("depth_debug.wgsl", "DepthView")],
+};
+
+// Shared between windows, lives in the RenderContext
+pub struct DepthDebugPipelines {
+    bind_group_layout: wgpu::BindGroupLayout,
+    thumbnail: wgpu::RenderPipeline,
+    // Packs the depth's bits into an Rgba8Unorm target for captures that can't copy the depth texture
+    raw: wgpu::RenderPipeline,
+    // Single sampled depth on an adapter that can copy depth textures to buffers
+    copy_directly: bool,
+}
+
+impl DepthDebugPipelines {
+    // `depth_sample_count` is the sample count of the windows' depth textures
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device, surface_format: wgpu::TextureFormat, depth_sample_count: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture::Texture::depth_read_layout_entry(0, depth_sample_count),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Depth Debug Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Debug Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let multisampled = depth_sample_count > 1;
+        let mut source = include_str!("depth_debug.wgsl").to_string();
+        if multisampled {
+            source = source.replace("texture_2d<f32>", "texture_multisampled_2d<f32>");
+        }
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Debug Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let pipeline = |label: &str, entry_point: &str, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        // GL can't copy depth and R32Float isn't renderable everywhere, Rgba8Unorm always is
+        let depth_copies = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES);
+        Self {
+            thumbnail: pipeline("Depth Thumbnail Pipeline", "fs_thumbnail", surface_format),
+            raw: pipeline("Depth Copy Pipeline", "fs_raw", wgpu::TextureFormat::Rgba8Unorm),
+            copy_directly: depth_copies && !multisampled,
+            bind_group_layout,
+        }
+    }
+}
+
+// A window's depth texture as the debug views read it. Recreated with the depth texture when
+// the window resizes.
+pub struct DepthDebugBindings {
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl DepthDebugBindings {
+    pub fn new(device: &wgpu::Device, pipelines: &DepthDebugPipelines, depth_texture: &wgpu::Texture) -> Self {
+        let depth_view = texture::Texture::create_depth_read_view(depth_texture, "Depth Debug View");
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Depth Debug Buffer"),
+            size: std::mem::size_of::<DepthViewUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Depth Debug Bind Group"),
+        });
+        Self { buffer, bind_group }
+    }
+
+    // Linearized depth in the bottom left corner of `target`, over whatever is there
+    pub fn draw_thumbnail(
+        &self,
+        context: &RenderContext,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        target_size: (u32, u32),
+        projection: &Projection,
+    ) {
+        let width = target_size.0 as f32 * THUMBNAIL_SCALE;
+        let height = target_size.1 as f32 * THUMBNAIL_SCALE;
+        let x = THUMBNAIL_MARGIN;
+        let y = (target_size.1 as f32 - height - THUMBNAIL_MARGIN).max(0.0);
+        if width < 1.0 || height < 1.0 {
+            return;
+        }
+        let (znear, zfar) = projection.depth_range();
+        let uniform = DepthViewUniform {
+            depth_range: [znear, zfar, 0.0, 0.0],
+            viewport: [x, y, width, height],
+        };
+        context.queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Thumbnail Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        render_pass.set_pipeline(&context.depth_debug.thumbnail);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Reads the depth back (blocking) and linearizes it with the projection's near/far planes.
+    // Nearest geometry is black, farthest white, and where nothing was drawn is white too.
+    pub fn capture(&self, context: &RenderContext, depth_texture: &wgpu::Texture, projection: &Projection) -> anyhow::Result<image::GrayImage> {
+        let device = &context.device;
+        let (width, height) = (depth_texture.width(), depth_texture.height());
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * 4).div_ceil(align) * align;
+        let readback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Depth Capture Readback Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Depth Capture Encoder") });
+        // Keeps the packed copy target alive until the copy below is recorded
+        let raw_target;
+        let pipelines = &context.depth_debug;
+        let (source, aspect) = if pipelines.copy_directly {
+            (depth_texture, wgpu::TextureAspect::DepthOnly)
+        } else {
+            raw_target = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+                label: Some("Depth Capture Target"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = raw_target.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Copy Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&pipelines.raw);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+            drop(render_pass);
+            (&*raw_target, wgpu::TextureAspect::All)
+        };
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: source,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            size,
+        );
+        context.queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()??;
+
+        let mapped = slice.get_mapped_range();
+        let depths: Vec<f32> = mapped
+            .chunks_exact(bytes_per_row as usize)
+            .flat_map(|row| bytemuck::cast_slice::<u8, f32>(&row[..width as usize * 4]).iter().copied())
+            .collect();
+        drop(mapped);
+        readback_buffer.unmap();
+
+        let (znear, zfar) = projection.depth_range();
+        let linear: Vec<Option<f32>> = depths
+            .iter()
+            .map(|&depth| (depth < 1.0).then(|| linearize_depth(depth, znear, zfar)))
+            .collect();
+        // Stretched over the depth range actually on screen, the whole near..far range is mostly empty
+        let (nearest, farthest) = linear
+            .iter()
+            .flatten()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &d| (lo.min(d), hi.max(d)));
+        let range = (farthest - nearest).max(f32::EPSILON);
+        let pixels = linear
+            .iter()
+            .map(|d| d.map_or(255, |d| ((d - nearest) / range * 255.0).round() as u8))
+            .collect();
+        image::GrayImage::from_raw(width, height, pixels).ok_or_else(|| anyhow::anyhow!("depth capture has the wrong size"))
+    }
+}
+
+// Distance from the camera along its view direction for a [0, 1] depth value. The engine uses
+// standard Z (near at 0). Reversed Z would need near * far / (near + depth * (far - near)).
+fn linearize_depth(depth: f32, znear: f32, zfar: f32) -> f32 {
+    znear * zfar / (zfar - depth * (zfar - znear))
+}
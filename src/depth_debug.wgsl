@@ -0,0 +1,62 @@
+/*
+Purpose: Debug views of the scene's depth buffer
+Responsibilites:
+    - Draw the linearized depth into a thumbnail in a corner of the window
+    - Pack the raw depth into an Rgba8Unorm target for captures that can't copy the depth texture itself
+*/
+
+// Bound as an unfilterable float texture like particles.wgsl does, swapped for
+// texture_multisampled_2d<f32> when MSAA is on and sample 0 is read.
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+
+struct DepthView {
+    // x: near plane, y: far plane
+    depth_range: vec4<f32>,
+    // Where the thumbnail is drawn in the target, in pixels: x, y, width, height
+    viewport: vec4<f32>,
+};
+@group(0) @binding(1)
+var<uniform> view: DepthView;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn load_depth(pixel: vec2<f32>) -> f32 {
+    let last = vec2<i32>(textureDimensions(t_depth)) - 1;
+    return textureLoad(t_depth, clamp(vec2<i32>(pixel), vec2<i32>(0), last), 0).r;
+}
+
+// Distance from the camera along its view direction for a [0, 1] depth value
+fn linear_depth(depth: f32) -> f32 {
+    let near = view.depth_range.x;
+    let far = view.depth_range.y;
+    return near * far / (far - depth * (far - near));
+}
+
+@fragment
+fn fs_thumbnail(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = (in.clip_position.xy - view.viewport.xy) / view.viewport.zw;
+    let depth = load_depth(uv * vec2<f32>(textureDimensions(t_depth)));
+    // Log scale, a linear ramp out to the far plane leaves everything near the camera black
+    let near = view.depth_range.x;
+    let far = view.depth_range.y;
+    let shade = saturate(log(linear_depth(depth) / near) / log(far / near));
+    return vec4<f32>(vec3<f32>(shade), 1.0);
+}
+
+// The float's bits spread over the four channels, read back they are the same f32 again
+@fragment
+fn fs_raw(in: VertexOutput) -> @location(0) vec4<f32> {
+    return unpack4x8unorm(bitcast<u32>(load_depth(in.clip_position.xy)));
+}
@@ -0,0 +1,135 @@
+/*
+Purpose: Optional depth-only pre-pass, so dense scenes shade every pixel once
+Responsibilities:
+    - Own the position-only pipeline that fills the depth buffer with the opaque models
+    - Own the main pass model pipeline that shades with an Equal depth test and no depth writes
+    - Define the stripped vertex layouts (vertex position, instance model matrix) the pre-pass reads
+    - ex: chalking the outlines on the wall before anyone picks up a brush
+*/
+
+use crate::{instance::InstanceRaw, model::{ModelVertex, Vertex}, texture};
+
+// Same strides as ModelVertex and InstanceRaw so the model's buffers bind as they are,
+// the attributes sit at the start of both
+const POSITION_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+const MODEL_MATRIX_ATTRIBUTES: [wgpu::VertexAttribute; 4] =
+    wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4];
+
+pub fn position_layout() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &POSITION_ATTRIBUTES,
+    }
+}
+
+pub fn model_matrix_layout() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &MODEL_MATRIX_ATTRIBUTES,
+    }
+}
+
+fn depth_state(depth_write_enabled: bool, depth_compare: wgpu::CompareFunction) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format: texture::Texture::DEPTH_FORMAT,
+        depth_write_enabled,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct DepthPrepassPipelines {
+    // Vertex stage only, writes depth
+    pub prepass_pipeline: wgpu::RenderPipeline,
+    // render_pipeline with Equal depth and no depth writes, for models the pre-pass drew
+    pub model_pipeline: wgpu::RenderPipeline,
+}
+
+impl DepthPrepassPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let [texture_layout, camera_layout, light_layout] = layouts;
+        let multisample = wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+        // Culling and winding have to match render_pipeline's, or the passes cover different pixels
+        let primitive = wgpu::PrimitiveState {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        };
+
+        let prepass_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Prepass Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("depth_prepass.wgsl").into()),
+        });
+        let prepass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Prepass Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let prepass_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Prepass Pipeline"),
+            layout: Some(&prepass_layout),
+            vertex: wgpu::VertexState {
+                module: &prepass_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[position_layout(), model_matrix_layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive,
+            depth_stencil: Some(depth_state(true, wgpu::CompareFunction::Less)),
+            multisample,
+            multiview: None,
+            cache: None,
+        });
+
+        let model_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Equal Model Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let model_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Equal Model Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, camera_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let model_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Equal Model Pipeline"),
+            layout: Some(&model_layout),
+            vertex: wgpu::VertexState {
+                module: &model_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &model_shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive,
+            depth_stencil: Some(depth_state(false, wgpu::CompareFunction::Equal)),
+            multisample,
+            multiview: None,
+            cache: None,
+        });
+
+        Self { prepass_pipeline, model_pipeline }
+    }
+}
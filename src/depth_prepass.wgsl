@@ -0,0 +1,34 @@
+/*
+Purpose: Depth-only pre-pass for opaque models
+Responsibilites:
+    - Write the depth of every model instance before the main pass shades it
+    - Compute the position exactly like shader.wgsl's vs_main, the main pass tests for Equal depth
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Only the model matrix of InstanceRaw is read
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @invariant @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(position, 1.0);
+    return camera.view_proj * world_position;
+}
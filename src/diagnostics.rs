@@ -0,0 +1,77 @@
+/*
+Purpose: Plain-text report of the GPU environment for bug reports
+Responsibilities:
+    - Describe the adapter (name, backend, driver), the limits and features the engine relies on
+    - Describe a window surface: what it supports and how it is configured
+    - Only read what is already known, never touch frame state, so it is safe at any time
+    - ex: the car's service book, handed over when something goes wrong
+*/
+
+use crate::render_context::RenderContext;
+
+// What a window's surface offered and what was picked from it
+pub struct SurfaceDiagnostics<'a> {
+    pub capabilities: &'a wgpu::SurfaceCapabilities,
+    pub config: &'a wgpu::SurfaceConfiguration,
+}
+
+// `engine` is a list of (setting, value) lines appended at the end
+pub fn report(context: &RenderContext, surface: Option<SurfaceDiagnostics>, engine: &[(&str, String)]) -> String {
+    let mut lines = Vec::new();
+    let info = context.adapter.get_info();
+    lines.push(format!("rusty-engine {}", env!("CARGO_PKG_VERSION")));
+    lines.push(String::new());
+    lines.push("[adapter]".to_string());
+    lines.push(format!("name: {}", info.name));
+    lines.push(format!("backend: {:?}", info.backend));
+    lines.push(format!("device type: {:?}", info.device_type));
+    lines.push(format!("vendor/device id: {:#06x}/{:#06x}", info.vendor, info.device));
+    lines.push(format!("driver: {} {}", info.driver, info.driver_info));
+
+    // The device is created with default limits, the adapter may allow more
+    let limits = context.device.limits();
+    let adapter_limits = context.adapter.limits();
+    lines.push(String::new());
+    lines.push("[limits] (device / adapter)".to_string());
+    lines.push(format!("max texture size 2d: {} / {}", limits.max_texture_dimension_2d, adapter_limits.max_texture_dimension_2d));
+    lines.push(format!("max bind groups: {} / {}", limits.max_bind_groups, adapter_limits.max_bind_groups));
+    lines.push(format!(
+        "uniform buffer offset alignment: {} / {}",
+        limits.min_uniform_buffer_offset_alignment, adapter_limits.min_uniform_buffer_offset_alignment
+    ));
+    lines.push(format!(
+        "max storage buffer binding size: {} / {}",
+        limits.max_storage_buffer_binding_size, adapter_limits.max_storage_buffer_binding_size
+    ));
+    lines.push(format!("max compute invocations per workgroup: {}", limits.max_compute_invocations_per_workgroup));
+    lines.push(format!("adapter features: {:?}", context.adapter.features()));
+    let scene_format_flags = context.adapter.get_texture_format_features(context.scene_format).flags;
+    lines.push(format!("{:?} sample counts: {:?}", context.scene_format, scene_format_flags.supported_sample_counts()));
+
+    lines.push(String::new());
+    lines.push("[surface]".to_string());
+    match surface {
+        Some(surface) => {
+            lines.push(format!("formats: {:?}", surface.capabilities.formats));
+            lines.push(format!("present modes: {:?}", surface.capabilities.present_modes));
+            lines.push(format!("alpha modes: {:?}", surface.capabilities.alpha_modes));
+            lines.push(format!(
+                "configured: {:?} {}x{}, {:?}, {:?}, frame latency {}",
+                surface.config.format,
+                surface.config.width,
+                surface.config.height,
+                surface.config.present_mode,
+                surface.config.alpha_mode,
+                surface.config.desired_maximum_frame_latency
+            ));
+        }
+        None => lines.push("none (no window, rendering offscreen)".to_string()),
+    }
+
+    lines.push(String::new());
+    lines.push("[engine]".to_string());
+    for (setting, value) in engine {
+        lines.push(format!("{}: {}", setting, value));
+    }
+    lines.join("\n")
+}
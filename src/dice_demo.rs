@@ -0,0 +1,40 @@
+/*
+Purpose: A die, the cube builder's per-face atlas mapping on a textured model
+Responsibilities:
+    - Build the die's cube with create_cube_grid_atlas over res/dice.png, a 3 x 2 sheet of the
+      faces one to six
+    - Turn the shape into a model mesh with tangents and wrap it in a Model
+    - ex: the sticker sheet that comes with a blank die, one sticker per side
+*/
+
+use pollster::FutureExt;
+
+use crate::{model::{self, ShadingModel}, render_context::RenderContext, resources, shapes, texture};
+
+const TEXTURE_FILE: &str = "dice.png";
+const SHEET: (u32, u32) = (3, 2);
+// Sheet cells in the cube's face order (front, back, left, right, top, bottom), opposite
+// faces add up to seven
+const FACE_CELLS: [u32; 6] = [0, 5, 2, 3, 1, 4];
+
+pub fn model(context: &RenderContext) -> anyhow::Result<model::Model> {
+    let (shape_vertices, indices) = shapes::create_cube_grid_atlas(SHEET.0, SHEET.1, FACE_CELLS);
+    let mesh = resources::mesh_from_shape(&context.device, "die", &shape_vertices, &indices);
+
+    let diffuse = image::load_from_memory(&resources::load_binary(TEXTURE_FILE).block_on()?)?;
+    let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+    let material = model::Material::new(
+        &context.device,
+        "dice_demo",
+        texture::Texture::from_image(&context.device, &context.queue, &diffuse, Some(TEXTURE_FILE), false)?,
+        texture::Texture::from_image(&context.device, &context.queue, &flat_normal, Some("dice_demo_normal"), true)?,
+        &context.texture_bind_group_layout,
+        model::MaterialParams { shading_model: ShadingModel::BlinnPhong, ..Default::default() },
+    );
+    Ok(model::Model {
+        meshes: vec![mesh],
+        materials: vec![material],
+        optimize_stats: None,
+        packed: None,
+    })
+}
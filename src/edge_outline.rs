@@ -0,0 +1,256 @@
+/*
+Purpose: Screen-space outlines, edges found in the frame's ID, normal and depth buffers
+Responsibilities:
+    - Define OutlineMode and the outline's look (OutlineSettings): width in pixels, color and
+      how sharp a crease or depth step has to be to count as an edge
+    - Own the per-window normal/depth target and edge mask. The IDs come from the picking's ID
+      target, drawn for the whole frame (PickTargets::encode_frame), so they are drawn once
+    - Record the edge pass (the selection's pixels, or every ID, crease and depth discontinuity)
+      and the composite, which grows the edges to the width by distance and blends the color
+      over the tonemapped frame
+    - ex: the inker going over the finished page with a pen of one nib size
+*/
+
+use crate::{color, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, picking::{NORMAL_DEPTH_FORMAT, PickTargets}, render_context::{fullscreen_pipeline, texture_entry}};
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// The composite searches a square this many pixels out, the cost grows with its area
+pub const MAX_OUTLINE_WIDTH: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlineMode {
+    // The selection overlay's tint is all that marks the selection
+    Off,
+    // Around the selected instance, inner edges and occluded parts left out
+    SelectionOnly,
+    // Silhouettes, creases and depth steps of every model, a sketched look
+    FullScene,
+    // The toon style's inverted hull around the selected instance, to compare with
+    Hull,
+}
+
+impl OutlineMode {
+    pub const ALL: [OutlineMode; 4] = [OutlineMode::Off, OutlineMode::SelectionOnly, OutlineMode::FullScene, OutlineMode::Hull];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OutlineMode::Off => "Off",
+            OutlineMode::SelectionOnly => "Selection only",
+            OutlineMode::FullScene => "Full scene",
+            OutlineMode::Hull => "Selection, inverted hull",
+        }
+    }
+
+    // Drawn by the edge and composite passes
+    pub fn screen_space(self) -> bool {
+        matches!(self, OutlineMode::SelectionOnly | OutlineMode::FullScene)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineSettings {
+    pub mode: OutlineMode,
+    // Physical pixels at any distance. Out from the silhouette for the selection, the whole
+    // line across for the full scene.
+    pub width: f32,
+    pub color: [f32; 3],
+    // Full scene only: how far the depth may bend away from a flat surface, relative to the
+    // depth, and 1 - cos of the angle between neighbouring normals
+    pub depth_threshold: f32,
+    pub normal_threshold: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self { mode: OutlineMode::Off, width: 2.0, color: [1.0, 0.55, 0.1], depth_threshold: 0.02, normal_threshold: 0.3 }
+    }
+}
+
+impl OutlineSettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Outline")
+            .selected_text(self.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in OutlineMode::ALL {
+                    ui.selectable_value(&mut self.mode, mode, mode.label());
+                }
+            });
+        ui.add_enabled_ui(self.mode != OutlineMode::Off, |ui| {
+            ui.add(egui::Slider::new(&mut self.width, 1.0..=MAX_OUTLINE_WIDTH).text("Outline width (px)"));
+            ui.horizontal(|ui| {
+                ui.label("Outline color");
+                color::edit_srgb(ui, &mut self.color);
+            });
+        });
+        ui.add_enabled_ui(self.mode == OutlineMode::FullScene, |ui| {
+            ui.add(egui::Slider::new(&mut self.depth_threshold, 0.001..=0.2).logarithmic(true).text("Depth edge threshold"));
+            ui.add(egui::Slider::new(&mut self.normal_threshold, 0.01..=1.0).logarithmic(true).text("Crease threshold"));
+        });
+    }
+}
+
+// Must match Outline in edge_outline.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    // Pick ID of the selected instance, 0 for none
+    selected_id: u32,
+    // 0 selection, 1 full scene
+    mode: u32,
+    width: f32,
+    depth_threshold: f32,
+    normal_threshold: f32,
+    _padding: [f32; 3],
+}
+
+pub const OUTLINE_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(OutlineUniform, [color, selected_id, mode, width, depth_threshold, normal_threshold]),
+    wgsl: &[("edge_outline.wgsl", "Outline")],
+};
+
+// Shared between windows, lives in the RenderContext
+pub struct OutlinePipelines {
+    edge_layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    edge_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl OutlinePipelines {
+    pub fn new(device: &wgpu::Device, surface_format: wgpu::TextureFormat) -> Self {
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<OutlineUniform>() as u64),
+            },
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let edge_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0, wgpu::TextureSampleType::Uint), texture_entry(1, float), uniform_entry(2)],
+            label: Some("Outline Edge Bind Group Layout"),
+        });
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(2), texture_entry(3, float)],
+            label: Some("Outline Composite Bind Group Layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("edge_outline.wgsl").into()),
+        });
+        let edge_pipeline = fullscreen_pipeline(device, "Outline Edge Pipeline", &edge_layout, &shader, "fs_edges", MASK_FORMAT, None);
+        let composite_pipeline = fullscreen_pipeline(
+            device,
+            "Outline Composite Pipeline",
+            &composite_layout,
+            &shader,
+            "fs_composite",
+            surface_format,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
+        Self { edge_layout, composite_layout, edge_pipeline, composite_pipeline }
+    }
+}
+
+// A window's outline targets, made with its PickTargets and again whenever those are
+pub struct OutlineTargets {
+    _normal_depth: Tracked<wgpu::Texture>,
+    normal_depth_view: wgpu::TextureView,
+    _mask: Tracked<wgpu::Texture>,
+    mask_view: wgpu::TextureView,
+    buffer: Tracked<wgpu::Buffer>,
+    edge_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+}
+
+impl OutlineTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &OutlinePipelines, config: &wgpu::SurfaceConfiguration, pick_targets: &PickTargets) -> Self {
+        let target = |label, format| {
+            let texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d { width: config.width, height: config.height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            (texture, view)
+        };
+        let (normal_depth, normal_depth_view) = target("Outline Normal Depth Target", NORMAL_DEPTH_FORMAT);
+        let (mask, mask_view) = target("Outline Edge Mask", MASK_FORMAT);
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Outline Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let edge_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.edge_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(pick_targets.id_view()) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&normal_depth_view) },
+                wgpu::BindGroupEntry { binding: 2, resource: buffer.as_entire_binding() },
+            ],
+            label: Some("Outline Edge Bind Group"),
+        });
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 2, resource: buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(&mask_view) },
+            ],
+            label: Some("Outline Composite Bind Group"),
+        });
+        Self { _normal_depth: normal_depth, normal_depth_view, _mask: mask, mask_view, buffer, edge_bind_group, composite_bind_group }
+    }
+
+    // What PickTargets::encode_frame draws the normals and depth into
+    pub fn normal_depth_view(&self) -> &wgpu::TextureView {
+        &self.normal_depth_view
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, settings: &OutlineSettings, selected_id: Option<u32>) {
+        let [r, g, b] = color::srgb_to_linear_rgb(settings.color);
+        let uniform = OutlineUniform {
+            color: [r, g, b, 1.0],
+            selected_id: selected_id.unwrap_or(0),
+            mode: (settings.mode == OutlineMode::FullScene) as u32,
+            width: settings.width.clamp(1.0, MAX_OUTLINE_WIDTH),
+            depth_threshold: settings.depth_threshold,
+            normal_threshold: settings.normal_threshold,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    // Finds the edges in this frame's IDs, then draws the outline over `target`
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, pipelines: &OutlinePipelines, target: &wgpu::TextureView) {
+        let passes = [
+            ("Outline Edge Pass", &self.mask_view, wgpu::LoadOp::Clear(wgpu::Color::BLACK), &pipelines.edge_pipeline, &self.edge_bind_group),
+            ("Outline Composite Pass", target, wgpu::LoadOp::Load, &pipelines.composite_pipeline, &self.composite_bind_group),
+        ];
+        for (label, view, load, pipeline, bind_group) in passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/*
+Purpose: Screen-space outlines
+Responsibilites:
+    - fs_edges: mark the selected instance's pixels, or in the full scene every pixel where the
+      ID changes, the normals crease or the depth steps off a flat surface
+    - fs_composite: blend the outline color wherever a marked pixel is within the width, by
+      distance so corners stay round and the width is the same at any depth
+*/
+
+// Fullscreen triangle, no vertex buffer needed
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// See edge_outline.rs
+struct Outline {
+    color: vec4<f32>,
+    selected_id: u32,
+    mode: u32,
+    width: f32,
+    depth_threshold: f32,
+    normal_threshold: f32,
+}
+
+const MODE_SELECTION: u32 = 0u;
+
+// Edge pass bindings
+@group(0) @binding(0)
+var t_id: texture_2d<u32>;
+// World normal and view depth, zero where the ID is 0
+@group(0) @binding(1)
+var t_normal_depth: texture_2d<f32>;
+// Both passes
+@group(0) @binding(2)
+var<uniform> outline: Outline;
+// Composite bindings, what fs_edges wrote
+@group(0) @binding(3)
+var t_mask: texture_2d<f32>;
+
+fn clamp_pixel(pixel: vec2<i32>) -> vec2<i32> {
+    return clamp(pixel, vec2<i32>(0), vec2<i32>(textureDimensions(t_id)) - 1);
+}
+
+fn load_id(pixel: vec2<i32>) -> u32 {
+    return textureLoad(t_id, clamp_pixel(pixel), 0).r;
+}
+
+fn load_normal_depth(pixel: vec2<i32>) -> vec4<f32> {
+    return textureLoad(t_normal_depth, clamp_pixel(pixel), 0);
+}
+
+@fragment
+fn fs_edges(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let id = load_id(pixel);
+    if (outline.mode == MODE_SELECTION) {
+        return vec4<f32>(select(0.0, 1.0, id != 0u && id == outline.selected_id), 0.0, 0.0, 1.0);
+    }
+    let right = pixel + vec2<i32>(1, 0);
+    let down = pixel + vec2<i32>(0, 1);
+    let left = pixel - vec2<i32>(1, 0);
+    let up = pixel - vec2<i32>(0, 1);
+    // Silhouettes, and where one instance stands in front of another
+    if (load_id(right) != id || load_id(down) != id) {
+        return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+    }
+    if (id == 0u) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let center = load_normal_depth(pixel);
+    let crease = max(1.0 - dot(center.xyz, load_normal_depth(right).xyz), 1.0 - dot(center.xyz, load_normal_depth(down).xyz));
+    if (crease > outline.normal_threshold) {
+        return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+    }
+    // 1 / depth is linear across a flat surface on screen, what bends away from the line is a
+    // step in depth within the same instance. Relative, so it holds near and far.
+    if (load_id(left) == id && load_id(up) == id) {
+        let inverse = 1.0 / center.w;
+        let across = abs(1.0 / load_normal_depth(left).w + 1.0 / load_normal_depth(right).w - 2.0 * inverse);
+        let along = abs(1.0 / load_normal_depth(up).w + 1.0 / load_normal_depth(down).w - 2.0 * inverse);
+        if (max(across, along) / inverse > outline.depth_threshold) {
+            return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+        }
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let max_pixel = vec2<i32>(textureDimensions(t_mask)) - 1;
+    let selection = outline.mode == MODE_SELECTION;
+    // The selection's outline sits outside it
+    if (selection && textureLoad(t_mask, pixel, 0).r > 0.5) {
+        discard;
+    }
+    // Out from the silhouette between the pixels, or half the line to either side of the edge
+    let reach = select(outline.width * 0.5, outline.width + 0.5, selection);
+    let search = i32(ceil(reach));
+    var nearest = f32(search * search * 2 + 1);
+    for (var y = -search; y <= search; y++) {
+        for (var x = -search; x <= search; x++) {
+            let offset = vec2<i32>(x, y);
+            if (textureLoad(t_mask, clamp(pixel + offset, vec2<i32>(0), max_pixel), 0).r > 0.5) {
+                nearest = min(nearest, f32(x * x + y * y));
+            }
+        }
+    }
+    let coverage = clamp(reach + 0.5 - sqrt(nearest), 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(outline.color.rgb, outline.color.a * coverage);
+}
@@ -0,0 +1,195 @@
+/*
+Purpose: Let systems hear about what happened elsewhere in the engine without knowing about each other
+Responsibilities:
+    - Define EngineEvent, the things worth announcing: assets loading or failing, instances coming
+      and going, the selection, the main window's size, a lost device and changed settings
+    - Queue events as they are pushed from anywhere in State, bounded so nothing can make the
+      queue grow without limit, dropping the oldest and warning about it
+    - Hand every queued event to every listener once per frame, events in the order they were
+      pushed and listeners in the order they subscribed
+    - ex: the station announcements, whoever cares about a train listens for it
+*/
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::model_entry::{InstanceId, ModelHandle};
+
+// Events pushed between two drains beyond this drop the oldest
+pub const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    AssetLoaded { path: String, handle: ModelHandle },
+    AssetFailed { path: String, error: String },
+    InstanceSpawned { id: InstanceId },
+    InstanceDespawned { id: InstanceId },
+    // None once nothing is selected
+    SelectionChanged { id: Option<InstanceId> },
+    // The primary window, in physical pixels
+    WindowResized { width: u32, height: u32 },
+    DeviceLost,
+    // Something remembered in the user settings file changed
+    SettingsChanged,
+}
+
+impl EngineEvent {
+    // What scripts match on, see ScriptHost::event_listener
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineEvent::AssetLoaded { .. } => "asset_loaded",
+            EngineEvent::AssetFailed { .. } => "asset_failed",
+            EngineEvent::InstanceSpawned { .. } => "instance_spawned",
+            EngineEvent::InstanceDespawned { .. } => "instance_despawned",
+            EngineEvent::SelectionChanged { .. } => "selection_changed",
+            EngineEvent::WindowResized { .. } => "window_resized",
+            EngineEvent::DeviceLost => "device_lost",
+            EngineEvent::SettingsChanged => "settings_changed",
+        }
+    }
+}
+
+impl fmt::Display for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineEvent::AssetLoaded { path, handle } => write!(f, "Loaded {} as model {}", path, handle.0),
+            EngineEvent::AssetFailed { path, error } => write!(f, "Could not load {}: {}", path, error),
+            EngineEvent::InstanceSpawned { id } => write!(f, "Spawned instance #{} of model {}", id.index, id.model.0),
+            EngineEvent::InstanceDespawned { id } => write!(f, "Despawned instance #{} of model {}", id.index, id.model.0),
+            EngineEvent::SelectionChanged { id: Some(id) } => write!(f, "Selected instance #{} of model {}", id.index, id.model.0),
+            EngineEvent::SelectionChanged { id: None } => write!(f, "Selection cleared"),
+            EngineEvent::WindowResized { width, height } => write!(f, "Window resized to {}x{}", width, height),
+            EngineEvent::DeviceLost => write!(f, "GPU device lost"),
+            EngineEvent::SettingsChanged => write!(f, "Settings changed"),
+        }
+    }
+}
+
+// What subscribe hands back, unsubscribe takes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerId(u64);
+
+type Listener = Box<dyn FnMut(&EngineEvent)>;
+
+pub struct EventBus {
+    queue: VecDeque<EngineEvent>,
+    capacity: usize,
+    // Called in this order, which is the order they subscribed in
+    listeners: Vec<(ListenerId, Listener)>,
+    next_id: u64,
+    // Pushed out by newer events since the last drain
+    dropped: usize,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self { queue: VecDeque::new(), capacity: capacity.max(1), listeners: Vec::new(), next_id: 0, dropped: 0 }
+    }
+
+    pub fn push(&mut self, event: EngineEvent) {
+        if self.queue.len() == self.capacity {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(event);
+    }
+
+    pub fn subscribe(&mut self, listener: impl FnMut(&EngineEvent) + 'static) -> ListenerId {
+        let id = ListenerId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, Box::new(listener)));
+        id
+    }
+
+    // False if it had already unsubscribed
+    #[allow(dead_code)] // the built-in listeners stay subscribed for the whole run
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        let count = self.listeners.len();
+        self.listeners.retain(|(listener, _)| *listener != id);
+        self.listeners.len() != count
+    }
+
+    // Once per frame. Returns how many events went out.
+    pub fn drain(&mut self) -> usize {
+        if self.dropped > 0 {
+            log::warn!("Dropped the {} oldest engine events, more than {} were queued in one frame", self.dropped, self.capacity);
+            self.dropped = 0;
+        }
+        let count = self.queue.len();
+        for event in self.queue.drain(..) {
+            for (_, listener) in &mut self.listeners {
+                listener(&event);
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn resized(width: u32) -> EngineEvent {
+        EngineEvent::WindowResized { width, height: 1 }
+    }
+
+    // A listener writing (its name, the event) into `heard`
+    fn recorder(heard: &Rc<RefCell<Vec<(&'static str, EngineEvent)>>>, name: &'static str) -> impl FnMut(&EngineEvent) + 'static {
+        let heard = heard.clone();
+        move |event| heard.borrow_mut().push((name, event.clone()))
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest() {
+        let heard = Rc::default();
+        let mut bus = EventBus::new(2);
+        bus.subscribe(recorder(&heard, "a"));
+        for width in 1..=3 {
+            bus.push(resized(width));
+        }
+        assert_eq!(bus.drain(), 2);
+        assert_eq!(*heard.borrow(), [("a", resized(2)), ("a", resized(3))]);
+        assert_eq!(bus.drain(), 0);
+    }
+
+    #[test]
+    fn events_in_push_order_listeners_in_subscribe_order() {
+        let heard = Rc::default();
+        let mut bus = EventBus::default();
+        bus.subscribe(recorder(&heard, "first"));
+        bus.subscribe(recorder(&heard, "second"));
+        bus.push(EngineEvent::SettingsChanged);
+        bus.push(EngineEvent::DeviceLost);
+        bus.drain();
+        assert_eq!(
+            *heard.borrow(),
+            [
+                ("first", EngineEvent::SettingsChanged),
+                ("second", EngineEvent::SettingsChanged),
+                ("first", EngineEvent::DeviceLost),
+                ("second", EngineEvent::DeviceLost),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsubscribe_stops_delivery() {
+        let heard = Rc::default();
+        let mut bus = EventBus::default();
+        let gone = bus.subscribe(recorder(&heard, "gone"));
+        bus.subscribe(recorder(&heard, "kept"));
+        assert!(bus.unsubscribe(gone));
+        assert!(!bus.unsubscribe(gone));
+        bus.push(EngineEvent::DeviceLost);
+        bus.drain();
+        assert_eq!(*heard.borrow(), [("kept", EngineEvent::DeviceLost)]);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+Purpose: Recent errors, kept for an on-screen overlay instead of only the console
+Responsibilities:
+    - Collect warnings and errors from wgpu, shader and pipeline creation, asset loading and
+      the surface, with when they happened
+    - Fold repeats of a message into one entry, a failure every frame stays one line
+    - Keep only the newest MAX_ENTRIES and flag when something new arrived
+    - ex: the check engine light, with the mechanic's printout behind it
+*/
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAX_ENTRIES: usize = 64;
+
+// Ordered, a repeat can only raise an entry's severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorEntry {
+    pub severity: Severity,
+    pub message: String,
+    // Since the log was created, roughly since startup
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    pub count: u32,
+}
+
+pub struct ErrorLog {
+    // Oldest first
+    entries: VecDeque<ErrorEntry>,
+    started: Instant,
+    // Set by a message not seen before, the overlay shows while it is set
+    pub open: bool,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self { entries: VecDeque::new(), started: Instant::now(), open: false }
+    }
+}
+
+impl ErrorLog {
+    pub fn push(&mut self, severity: Severity, message: impl Into<String>) {
+        let message = message.into();
+        let now = self.started.elapsed();
+        if let Some(index) = self.entries.iter().position(|entry| entry.message == message) {
+            // Moved to the back so a repeating error isn't the next one dropped
+            let mut entry = self.entries.remove(index).unwrap();
+            entry.count += 1;
+            entry.last_seen = now;
+            entry.severity = entry.severity.max(severity);
+            self.entries.push_back(entry);
+            return;
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ErrorEntry { severity, message, first_seen: now, last_seen: now, count: 1 });
+        self.open = true;
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &ErrorEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.open = false;
+    }
+
+    // Plain text, one entry per line, for bug reports
+    pub fn report(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let repeats = if entry.count > 1 {
+                    format!(" (x{}, last at {:.1}s)", entry.count, entry.last_seen.as_secs_f32())
+                } else {
+                    String::new()
+                };
+                format!("[{:.1}s] {}: {}{}\n", entry.first_seen.as_secs_f32(), entry.severity.label(), entry.message, repeats)
+            })
+            .collect()
+    }
+}
+
+// wgpu panics on errors nobody captured by default. Routing them into the log keeps the
+// engine running, a broken pipeline then shows up in the overlay instead of taking the app down.
+pub fn capture_device_errors(device: &wgpu::Device, log: Arc<Mutex<ErrorLog>>) {
+    device.on_uncaptured_error(Box::new(move |error| {
+        log::error!("wgpu: {}", error);
+        log.lock().unwrap().push(Severity::Error, format!("wgpu: {}", error));
+    }));
+}
@@ -0,0 +1,537 @@
+/*
+Purpose: Grass scattered over a height field, drawn with one instanced draw call
+Responsibilities:
+    - Define HeightMap (heights on a square grid) and DensityMap (where grass may grow)
+    - Scatter blade clumps over it with a seeded StdRng, skipping slopes that are too steep
+    - Mesh the ground at a few resolutions, picked from its size on screen as a whole
+    - Own the grass pipeline and its crossed-quad clump mesh, and each field's instance buffer
+    - Sway the blades in the vertex shader and cut them out in the fragment shader, no blending
+      means 100k clumps need no sorting
+    - ex: a gardener throwing seed by the handful, none of it takes on the cliffs
+*/
+
+use crate::{gpu_layout::{UniformCheck, VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, math::Ray, shape_lod::{LOD_LEVELS, LodStats, LodView}, shape_renderer::{DynamicShape, ShapePipeline, ShapeStyle}, toon::{ScenePipelineDesc, scene_pipeline}, vertex::Vertex};
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation3, Vector3};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+pub const MAX_GRASS_INSTANCES: usize = 200_000;
+// Samples per side of the demo height field
+const HEIGHT_MAP_RESOLUTION: usize = 96;
+// Height map samples between the ground's vertices at each LOD level, finest first
+const GROUND_LOD_STEPS: [usize; LOD_LEVELS] = [1, 2, 4, 8];
+// Fragments less covered than this are discarded, see grass.wgsl
+const ALPHA_CUTOFF: f32 = 0.5;
+const CLUMP_WIDTH: f32 = 0.5;
+const CLUMP_HEIGHT: f32 = 0.45;
+// Unit direction on the ground the wind blows along
+const WIND_DIRECTION: [f32; 2] = [0.8, 0.6];
+
+// Heights on a square grid of `resolution` samples per side, centered on the origin
+pub struct HeightMap {
+    resolution: usize,
+    extent: f32,
+    heights: Vec<f32>,
+}
+
+impl HeightMap {
+    // Stand-in terrain for the grass demo: a few random waves plus one steep ridge, so the
+    // slope limit has something to skip
+    pub fn rolling_hills(extent: f32, seed: u64) -> Self {
+        let resolution = HEIGHT_MAP_RESOLUTION;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let waves: Vec<(Vector3<f32>, f32)> = (0..4)
+            .map(|_| {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                let frequency = rng.gen_range(0.2..0.6);
+                // x, z: direction scaled by frequency, y: phase
+                (Vector3::new(angle.cos() * frequency, rng.gen_range(0.0..std::f32::consts::TAU), angle.sin() * frequency), rng.gen_range(0.15..0.4))
+            })
+            .collect();
+        let ridge_x = extent * 0.25;
+        let mut heights = Vec::with_capacity(resolution * resolution);
+        for iz in 0..resolution {
+            for ix in 0..resolution {
+                let (x, z) = grid_position(resolution, extent, ix, iz);
+                let hills: f32 = waves.iter().map(|(wave, amplitude)| amplitude * (wave.x * x + wave.z * z + wave.y).sin()).sum();
+                let ridge = 2.0 * (-((x - ridge_x) / 0.8).powi(2)).exp();
+                heights.push(hills + ridge);
+            }
+        }
+        Self { resolution, extent, heights }
+    }
+
+    pub fn extent(&self) -> f32 {
+        self.extent
+    }
+
+    // Bilinear between the grid samples, clamped to the edge outside the map
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let last = (self.resolution - 1) as f32;
+        let gx = ((x / self.extent + 0.5) * last).clamp(0.0, last);
+        let gz = ((z / self.extent + 0.5) * last).clamp(0.0, last);
+        let (x0, z0) = (gx.floor() as usize, gz.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.resolution - 1), (z0 + 1).min(self.resolution - 1));
+        let (tx, tz) = (gx.fract(), gz.fract());
+        let sample = |ix: usize, iz: usize| self.heights[iz * self.resolution + ix];
+        let top = sample(x0, z0) + (sample(x1, z0) - sample(x0, z0)) * tx;
+        let bottom = sample(x0, z1) + (sample(x1, z1) - sample(x0, z1)) * tx;
+        top + (bottom - top) * tz
+    }
+
+    // Central differences one grid step apart
+    pub fn normal_at(&self, x: f32, z: f32) -> Vector3<f32> {
+        let step = self.extent / (self.resolution - 1) as f32;
+        let dx = self.height_at(x + step, z) - self.height_at(x - step, z);
+        let dz = self.height_at(x, z + step) - self.height_at(x, z - step);
+        Vector3::new(-dx, 2.0 * step, -dz).normalize()
+    }
+
+    // Green where grass can grow, grey rock where it is steeper than `max_slope` degrees. A vertex
+    // every `step` samples, the last row and column are always kept so the edges stay put.
+    pub fn mesh(&self, max_slope: f32, step: usize) -> (Vec<Vertex>, Vec<u32>) {
+        let cos_max = max_slope.to_radians().cos();
+        let last = self.resolution - 1;
+        let mut samples: Vec<usize> = (0..self.resolution).step_by(step.max(1)).collect();
+        if samples.last() != Some(&last) {
+            samples.push(last);
+        }
+        let side = samples.len();
+        let mut vertices = Vec::with_capacity(side * side);
+        for &iz in &samples {
+            for &ix in &samples {
+                let (x, z) = grid_position(self.resolution, self.extent, ix, iz);
+                let normal = self.normal_at(x, z);
+                let color = if normal.y < cos_max { [0.35, 0.33, 0.3] } else { [0.22, 0.35, 0.12] };
+                vertices.push(Vertex {
+                    position: [x, self.heights[iz * self.resolution + ix], z],
+                    color,
+                    tex_coords: [ix as f32 / (self.resolution - 1) as f32, iz as f32 / (self.resolution - 1) as f32],
+                    normal: normal.into(),
+                });
+            }
+        }
+        let mut indices = Vec::with_capacity((side - 1) * (side - 1) * 6);
+        for iz in 0..side - 1 {
+            for ix in 0..side - 1 {
+                let i = (iz * side + ix) as u32;
+                let below = i + side as u32;
+                indices.extend_from_slice(&[i, below, i + 1, i + 1, below, below + 1]);
+            }
+        }
+        (vertices, indices)
+    }
+}
+
+fn grid_position(resolution: usize, extent: f32, ix: usize, iz: usize) -> (f32, f32) {
+    let step = extent / (resolution - 1) as f32;
+    (ix as f32 * step - extent * 0.5, iz as f32 * step - extent * 0.5)
+}
+
+// Chance of a clump growing at each point of the height map, 0..1
+pub enum DensityMap {
+    Uniform,
+    // Stretched over the whole height map, read with the nearest texel
+    Image(image::GrayImage),
+}
+
+impl DensityMap {
+    // Any image, its brightness is the density
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        Ok(DensityMap::Image(image::open(path)?.to_luma8()))
+    }
+
+    // Meadow with bare patches, for the demo
+    pub fn patches(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let blobs: Vec<(f32, f32, f32)> = (0..12).map(|_| (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0), rng.gen_range(0.05..0.15))).collect();
+        DensityMap::Image(image::GrayImage::from_fn(128, 128, |x, y| {
+            let (u, v) = (x as f32 / 127.0, y as f32 / 127.0);
+            let bare = blobs
+                .iter()
+                .map(|(bu, bv, radius)| 1.0 - (((u - bu).powi(2) + (v - bv).powi(2)).sqrt() / radius).min(1.0))
+                .fold(0.0_f32, f32::max);
+            image::Luma([((1.0 - bare) * 255.0) as u8])
+        }))
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DensityMap::Uniform => "Uniform",
+            DensityMap::Image(_) => "Density map",
+        }
+    }
+
+    fn sample(&self, u: f32, v: f32) -> f32 {
+        match self {
+            DensityMap::Uniform => 1.0,
+            DensityMap::Image(image) => {
+                let x = (u.clamp(0.0, 1.0) * (image.width() - 1) as f32).round() as u32;
+                let y = (v.clamp(0.0, 1.0) * (image.height() - 1) as f32).round() as u32;
+                image.get_pixel(x, y).0[0] as f32 / 255.0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScatterSettings {
+    // Clumps per square meter where the density map is white
+    pub density: f32,
+    // Side of the square scattered over, centered on the height map and clamped to it
+    pub extent: f32,
+    // Degrees from level, steeper ground stays bare
+    pub max_slope: f32,
+    // Tilt clumps with the ground instead of standing them straight up
+    pub align_to_normal: bool,
+    // How far a clump's size strays from 1
+    pub scale_jitter: f32,
+    pub seed: u64,
+}
+
+impl Default for ScatterSettings {
+    fn default() -> Self {
+        Self {
+            density: 400.0,
+            extent: 16.0,
+            max_slope: 35.0,
+            align_to_normal: false,
+            scale_jitter: 0.3,
+            seed: 1,
+        }
+    }
+}
+
+// Per-instance input of grass.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GrassInstanceRaw {
+    // Position relative to the field's origin, then scale
+    position_scale: [f32; 4],
+    // Quaternion, xyz then w
+    rotation: [f32; 4],
+}
+
+pub const GRASS_INSTANCE_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(GrassInstanceRaw, [position_scale, rotation]),
+    desc: GrassInstanceRaw::desc,
+};
+
+impl GrassInstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![2 => Float32x4, 3 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GrassInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Random candidates over the area, kept where the density map and slope allow. Every
+// candidate draws the same random numbers whether it is kept or not, so a seed always
+// gives the same field.
+pub fn scatter(height_map: &HeightMap, density_map: &DensityMap, settings: &ScatterSettings) -> Vec<GrassInstanceRaw> {
+    let half = settings.extent.min(height_map.extent()) * 0.5;
+    let candidates = ((settings.density * (2.0 * half).powi(2)) as usize).min(MAX_GRASS_INSTANCES);
+    let cos_max = settings.max_slope.to_radians().cos();
+    let jitter = settings.scale_jitter.clamp(0.0, 0.9);
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut instances = Vec::with_capacity(candidates);
+    if half <= 0.0 {
+        return instances;
+    }
+    for _ in 0..candidates {
+        let x = rng.gen_range(-half..half);
+        let z = rng.gen_range(-half..half);
+        let keep = rng.gen_range(0.0..1.0);
+        let yaw = rng.gen_range(0.0..std::f32::consts::TAU);
+        let scale = 1.0 + rng.gen_range(-jitter..=jitter);
+
+        let (u, v) = (x / height_map.extent() + 0.5, z / height_map.extent() + 0.5);
+        if keep >= density_map.sample(u, v) {
+            continue;
+        }
+        let normal = height_map.normal_at(x, z);
+        if normal.y < cos_max {
+            continue;
+        }
+        let mut rotation = Quaternion::from_angle_y(Rad(yaw));
+        if settings.align_to_normal {
+            rotation = Quaternion::from_arc(Vector3::unit_y(), normal, None) * rotation;
+        }
+        instances.push(GrassInstanceRaw {
+            position_scale: [x, height_map.height_at(x, z), z, scale],
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+        });
+    }
+    instances
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GrassVertex {
+    position: [f32; 3],
+    // y is 0 at the root and 1 at the tip, the wind bends by it
+    uv: [f32; 2],
+}
+
+pub const GRASS_VERTEX_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(GrassVertex, [position, uv]),
+    desc: GrassVertex::desc,
+};
+
+impl GrassVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GrassVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Two upright quads crossing at 90 degrees, the blades are cut out of them in grass.wgsl
+fn clump_mesh() -> (Vec<GrassVertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (dx, dz) in [(1.0, 0.0), (0.0, 1.0)] {
+        let base = vertices.len() as u16;
+        let (hx, hz) = (dx * CLUMP_WIDTH * 0.5, dz * CLUMP_WIDTH * 0.5);
+        vertices.extend_from_slice(&[
+            GrassVertex { position: [-hx, 0.0, -hz], uv: [0.0, 0.0] },
+            GrassVertex { position: [hx, 0.0, hz], uv: [1.0, 0.0] },
+            GrassVertex { position: [hx, CLUMP_HEIGHT, hz], uv: [1.0, 1.0] },
+            GrassVertex { position: [-hx, CLUMP_HEIGHT, -hz], uv: [0.0, 1.0] },
+        ]);
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+// Must match the Grass struct in grass.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GrassUniform {
+    origin: [f32; 4],
+    // Direction on xz, strength, seconds of simulation
+    wind: [f32; 4],
+    alpha_cutoff: f32,
+    _padding: [f32; 3],
+}
+
+pub const GRASS_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(GrassUniform, [origin, wind, alpha_cutoff]),
+    wgsl: &[("grass.wgsl", "Grass")],
+};
+
+// Shared between windows, lives in the RenderContext
+pub struct GrassPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
+    index_count: u32,
+}
+
+impl GrassPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: [&wgpu::BindGroupLayout; 2],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let [camera_layout, light_layout] = layouts;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Grass Bind Group Layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grass Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("grass.wgsl").into()),
+        });
+        // Blades are seen from both sides
+        let pipeline = scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Grass Pipeline",
+                bind_group_layouts: &[camera_layout, light_layout, &bind_group_layout],
+                shader: &shader,
+                entry_points: ("vs_main", "fs_main"),
+                vertex_layouts: &[GrassVertex::desc(), GrassInstanceRaw::desc()],
+                cull_mode: None,
+            },
+            color_format,
+            sample_count,
+        );
+        let (vertices, indices) = clump_mesh();
+        let vertex_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Grass Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Grass Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self { pipeline, bind_group_layout, vertex_buffer, index_buffer, index_count: indices.len() as u32 }
+    }
+}
+
+// A patch of ground with grass on it, owned by State for the grass demo
+pub struct GrassField {
+    origin: [f32; 3],
+    height_map: HeightMap,
+    // The height map drawn as a shape, grass needs something to stand on. One piece with a mesh
+    // per LOD level, so there are no level boundaries to crack.
+    ground: DynamicShape,
+    instance_buffer: Option<Tracked<wgpu::Buffer>>,
+    instance_count: u32,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GrassField {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline: &GrassPipeline,
+        shape_pipeline: &ShapePipeline,
+        origin: [f32; 3],
+        height_map: HeightMap,
+    ) -> Self {
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Grass Uniform Buffer"),
+            size: std::mem::size_of::<GrassUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Grass Bind Group"),
+        });
+        Self {
+            origin,
+            height_map,
+            ground: DynamicShape::new(device, shape_pipeline, "Grass Ground", origin, [1.0, 1.0, 1.0]),
+            instance_buffer: None,
+            instance_count: 0,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // Scatter again and upload the result into a buffer sized for it
+    pub fn regenerate(&mut self, device: &wgpu::Device, density_map: &DensityMap, settings: &ScatterSettings) {
+        let levels = GROUND_LOD_STEPS.map(|step| self.height_map.mesh(settings.max_slope, step));
+        self.ground.set_lod_meshes(device, &levels);
+        let instances = scatter(&self.height_map, density_map, settings);
+        self.instance_count = instances.len() as u32;
+        self.instance_buffer = (!instances.is_empty()).then(|| {
+            gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: Some("Grass Instance Buffer"),
+                contents: bytemuck::cast_slice(&instances),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    // World height of the ground under `x`, `z`, None off the field
+    pub fn ground_height(&self, x: f32, z: f32) -> Option<f32> {
+        let [origin_x, origin_y, origin_z] = self.origin;
+        let (x, z) = (x - origin_x, z - origin_z);
+        let half = self.height_map.extent() * 0.5;
+        (x.abs() <= half && z.abs() <= half).then(|| origin_y + self.height_map.height_at(x, z))
+    }
+
+    // Distance along the ray to the ground: marched a grid cell at a time until it is below the
+    // ground, then bisected. A ray coming in under the edge hits the field's side.
+    pub fn ray_hit(&self, ray: &Ray) -> Option<f32> {
+        let [origin_x, origin_y, origin_z] = self.origin;
+        let extent = self.height_map.extent();
+        let step = extent / HEIGHT_MAP_RESOLUTION as f32;
+        let to_field = (Vector3::new(origin_x, origin_y, origin_z) - ray.origin).magnitude();
+        let below = |t: f32| {
+            let point = ray.at(t);
+            self.ground_height(point.x, point.z).is_some_and(|height| point.y <= height)
+        };
+        let mut previous = 0.0;
+        let mut t = step;
+        while t < to_field + extent * 2.0 {
+            if below(t) {
+                let (mut above, mut under) = (previous, t);
+                for _ in 0..16 {
+                    let middle = (above + under) * 0.5;
+                    if below(middle) {
+                        under = middle;
+                    } else {
+                        above = middle;
+                    }
+                }
+                return Some(under);
+            }
+            previous = t;
+            t += step;
+        }
+        None
+    }
+
+    // Picks the ground's level, see DynamicShape::prepare
+    pub fn prepare_ground(&mut self, queue: &wgpu::Queue, view: Option<&LodView>, show_levels: bool) -> LodStats {
+        self.ground.prepare(queue, view, show_levels)
+    }
+
+    // `time` is seconds of simulation, the sway stops while the scene is paused
+    pub fn update(&self, queue: &wgpu::Queue, time: f32, wind_strength: f32) {
+        let [x, y, z] = self.origin;
+        let [wind_x, wind_z] = WIND_DIRECTION;
+        let uniform = GrassUniform {
+            origin: [x, y, z, 0.0],
+            wind: [wind_x, wind_z, wind_strength, time],
+            alpha_cutoff: ALPHA_CUTOFF,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // The ground with the scene's other shapes, then every clump in one draw call
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        pipeline: &GrassPipeline,
+        shape_pipeline: &ShapePipeline,
+        bind_groups: [&wgpu::BindGroup; 2],
+        ground_style: ShapeStyle,
+    ) {
+        let [camera_bind_group, light_bind_group] = bind_groups;
+        self.ground.draw(render_pass, shape_pipeline, camera_bind_group, ground_style);
+        let Some(instance_buffer) = &self.instance_buffer else {
+            return;
+        };
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, light_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, pipeline.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(pipeline.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..pipeline.index_count, 0, 0..self.instance_count);
+    }
+}
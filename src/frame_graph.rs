@@ -0,0 +1,247 @@
+/*
+Purpose: Let a window's offscreen passes share the textures they only need for part of the frame
+Responsibilities:
+    - Collect the transient textures (size, format, usage) and the passes reading and writing
+      them, in the order the frame runs them
+    - Work out each texture's lifetime, first to last pass touching it
+    - Give textures with identical descriptions and lifetimes that don't overlap the same
+      wgpu::Texture, and report the bytes that saves
+    - ex: meeting rooms booked by the hour, two teams share one as long as their slots don't meet
+*/
+
+use crate::gpu_memory::{self, Tracked};
+
+// Everything but the label has to match for two transients to share a texture
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransientDesc {
+    pub label: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl TransientDesc {
+    // Single sampled, one mip, at the window's size like every post-processing target
+    pub fn new(label: &'static str, config: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Self {
+        Self { label, width: config.width.max(1), height: config.height.max(1), format, usage }
+    }
+
+    fn descriptor(&self) -> wgpu::TextureDescriptor<'static> {
+        wgpu::TextureDescriptor {
+            label: Some(self.label),
+            size: wgpu::Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: self.usage,
+            view_formats: &[],
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        gpu_memory::texture_size(&self.descriptor())
+    }
+
+    fn aliases(&self, other: &TransientDesc) -> bool {
+        (self.width, self.height, self.format, self.usage) == (other.width, other.height, other.format, other.usage)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransientId(usize);
+
+struct PassDecl {
+    _name: &'static str,
+    reads: Vec<TransientId>,
+    writes: Vec<TransientId>,
+}
+
+// Declared fresh whenever the window's passes change, see ViewWindow::prepare_transients
+#[derive(Default)]
+pub struct FrameGraph {
+    textures: Vec<TransientDesc>,
+    passes: Vec<PassDecl>,
+}
+
+impl FrameGraph {
+    pub fn texture(&mut self, desc: TransientDesc) -> TransientId {
+        self.textures.push(desc);
+        TransientId(self.textures.len() - 1)
+    }
+
+    // Passes run in the order they are declared
+    pub fn pass(&mut self, name: &'static str, reads: &[TransientId], writes: &[TransientId]) {
+        self.passes.push(PassDecl { _name: name, reads: reads.to_vec(), writes: writes.to_vec() });
+    }
+
+    // First and last pass touching each texture, None for one no pass touches
+    pub fn lifetimes(&self) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None; self.textures.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for id in pass.reads.iter().chain(&pass.writes) {
+                let lifetime: &mut Option<(usize, usize)> = &mut lifetimes[id.0];
+                *lifetime = Some(lifetime.map_or((index, index), |(first, _)| (first, index)));
+            }
+        }
+        lifetimes
+    }
+
+    // Greedy in order of first use: a texture takes the first matching allocation whose last
+    // user ran before it starts, or a new one
+    pub fn compile(&self) -> AliasPlan {
+        let lifetimes = self.lifetimes();
+        let mut order: Vec<usize> = (0..self.textures.len()).filter(|&index| lifetimes[index].is_some()).collect();
+        order.sort_by_key(|&index| lifetimes[index]);
+        // Description and the last pass using it so far
+        let mut slots: Vec<(TransientDesc, usize)> = Vec::new();
+        let mut assignment = vec![None; self.textures.len()];
+        for index in order {
+            let (first, last) = lifetimes[index].expect("filtered above");
+            let desc = &self.textures[index];
+            let slot = match slots.iter().position(|(slot, end)| *end < first && slot.aliases(desc)) {
+                Some(slot) => {
+                    slots[slot].1 = last;
+                    slot
+                }
+                None => {
+                    slots.push((desc.clone(), last));
+                    slots.len() - 1
+                }
+            };
+            assignment[index] = Some(slot);
+        }
+        AliasPlan {
+            unaliased_bytes: self.textures.iter().zip(&assignment).filter(|(_, slot)| slot.is_some()).map(|(desc, _)| desc.bytes()).sum(),
+            slots: slots.into_iter().map(|(desc, _)| desc).collect(),
+            assignment,
+        }
+    }
+}
+
+// Which allocation every transient lives in
+pub struct AliasPlan {
+    slots: Vec<TransientDesc>,
+    // Indexed by TransientId, None for textures no pass touches
+    assignment: Vec<Option<usize>>,
+    unaliased_bytes: u64,
+}
+
+impl AliasPlan {
+    pub fn slot(&self, id: TransientId) -> Option<usize> {
+        self.assignment[id.0]
+    }
+
+    pub fn stats(&self) -> TransientStats {
+        TransientStats {
+            textures: self.assignment.iter().flatten().count(),
+            allocations: self.slots.len(),
+            unaliased_bytes: self.unaliased_bytes,
+            aliased_bytes: self.slots.iter().map(TransientDesc::bytes).sum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransientStats {
+    pub textures: usize,
+    pub allocations: usize,
+    // What the textures would take with one allocation each
+    pub unaliased_bytes: u64,
+    pub aliased_bytes: u64,
+}
+
+// The allocations of a compiled graph and a view of each transient into them
+pub struct Transients {
+    textures: Vec<Tracked<wgpu::Texture>>,
+    plan: AliasPlan,
+}
+
+impl Transients {
+    pub fn new(device: &wgpu::Device, graph: &FrameGraph) -> Self {
+        let plan = graph.compile();
+        let textures = plan.slots.iter().map(|desc| gpu_memory::create_texture(device, &desc.descriptor())).collect();
+        Self { textures, plan }
+    }
+
+    // Panics for a transient no declared pass touches, that is a mistake in the declarations
+    pub fn texture(&self, id: TransientId) -> wgpu::Texture {
+        let slot = self.plan.slot(id).expect("transient texture used by no pass");
+        (*self.textures[slot]).clone()
+    }
+
+    pub fn view(&self, id: TransientId) -> wgpu::TextureView {
+        self.texture(id).create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn stats(&self) -> TransientStats {
+        self.plan.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGBA: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    fn target(graph: &mut FrameGraph, label: &'static str, format: wgpu::TextureFormat) -> TransientId {
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        graph.texture(TransientDesc { label, width: 64, height: 32, format, usage })
+    }
+
+    #[test]
+    fn lifetimes_run_from_first_to_last_pass() {
+        let mut graph = FrameGraph::default();
+        let a = target(&mut graph, "a", RGBA);
+        let b = target(&mut graph, "b", RGBA);
+        target(&mut graph, "unused", RGBA);
+        graph.pass("write a", &[], &[a]);
+        graph.pass("a to b", &[a], &[b]);
+        graph.pass("read b", &[b], &[]);
+        assert_eq!(graph.lifetimes(), [Some((0, 1)), Some((1, 2)), None]);
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_a_texture() {
+        let mut graph = FrameGraph::default();
+        let a = target(&mut graph, "a", RGBA);
+        let b = target(&mut graph, "b", RGBA);
+        let c = target(&mut graph, "c", RGBA);
+        graph.pass("write a", &[], &[a]);
+        graph.pass("a to b", &[a], &[b]);
+        graph.pass("b to c", &[b], &[c]);
+        let plan = graph.compile();
+        assert_eq!(plan.slot(a), plan.slot(c));
+        assert_ne!(plan.slot(a), plan.slot(b));
+        let stats = plan.stats();
+        assert_eq!((stats.textures, stats.allocations), (3, 2));
+        assert_eq!(stats.unaliased_bytes, 3 * 64 * 32 * 4);
+        assert_eq!(stats.aliased_bytes, 2 * 64 * 32 * 4);
+    }
+
+    #[test]
+    fn overlapping_lifetimes_do_not_alias() {
+        let mut graph = FrameGraph::default();
+        let a = target(&mut graph, "a", RGBA);
+        let b = target(&mut graph, "b", RGBA);
+        graph.pass("write a", &[], &[a]);
+        graph.pass("write b", &[], &[b]);
+        graph.pass("read both", &[a, b], &[]);
+        let plan = graph.compile();
+        assert_ne!(plan.slot(a), plan.slot(b));
+        assert_eq!(plan.stats().allocations, 2);
+    }
+
+    #[test]
+    fn different_formats_do_not_alias() {
+        let mut graph = FrameGraph::default();
+        let color = target(&mut graph, "color", RGBA);
+        let hdr = target(&mut graph, "hdr", wgpu::TextureFormat::Rgba16Float);
+        graph.pass("write color", &[], &[color]);
+        graph.pass("write hdr", &[], &[hdr]);
+        let plan = graph.compile();
+        assert_ne!(plan.slot(color), plan.slot(hdr));
+    }
+}
@@ -0,0 +1,220 @@
+/*
+Purpose: Frame rate caps, a lower one while the app is in the background, and refresh pacing
+Responsibilities:
+    - Define FrameCaps (foreground / background FPS, 0 is uncapped, refresh pacing and its
+      target), saved in the settings file
+    - Hold a window's frame back until 1 / cap after its last present, waking the event loop then
+    - With vsync off, hold frames to the refresh rate of the window's monitor (or a set target):
+      sleep through most of the frame, spin the last SPIN_FINISH, and wake earlier by a bias
+      learned from how late frames actually present
+    - Keep the recent present intervals, their spread is the jitter the stats show
+    - Never delay the first frame after a cap is lifted, so refocusing feels instant
+    - ex: the idle speed of an engine at a red light
+*/
+
+use crate::user_settings::UserSettings;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use winit::window::Window;
+
+pub const MAX_FPS_CAP: u32 = 240;
+// The OS timer overshoots, the last part of a paced frame's wait is spun instead of slept
+const SPIN_FINISH: Duration = Duration::from_micros(500);
+// How much earlier than SPIN_FINISH the event loop may be woken, learned per window
+const MAX_SLEEP_BIAS: Duration = Duration::from_millis(3);
+// Share of a late present that is added to the sleep bias, and how fast it shrinks when frames
+// are on time (per frame), so the spinning stays short
+const BIAS_GAIN: f64 = 0.25;
+const BIAS_DECAY: Duration = Duration::from_micros(5);
+// Presents used for the jitter, a second at 120 Hz
+const INTERVAL_HISTORY: usize = 120;
+// A longer gap is the app idling on demand, not a frame interval
+const MAX_TRACKED_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCaps {
+    // Frames per second while one of our windows has focus, 0 for no cap (vsync still applies)
+    pub foreground: u32,
+    // Frames per second while another application has focus
+    pub background: u32,
+    // With vsync off, pace foreground frames to the monitor's refresh rate or `pacing_target`
+    pub refresh_pacing: bool,
+    // Frames per second refresh pacing aims for, 0 follows the monitor
+    pub pacing_target: u32,
+}
+
+impl Default for FrameCaps {
+    fn default() -> Self {
+        Self { foreground: 0, background: 10, refresh_pacing: true, pacing_target: 0 }
+    }
+}
+
+impl FrameCaps {
+    // Anything missing from the file keeps its default
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let default = Self::default();
+        Self {
+            foreground: settings.parse("frame_cap.foreground").unwrap_or(default.foreground).min(MAX_FPS_CAP),
+            background: settings.parse("frame_cap.background").unwrap_or(default.background).min(MAX_FPS_CAP),
+            refresh_pacing: settings.parse("frame_pacing.enabled").unwrap_or(default.refresh_pacing),
+            pacing_target: settings.parse("frame_pacing.target").unwrap_or(default.pacing_target).min(MAX_FPS_CAP),
+        }
+    }
+
+    pub fn write_settings(&self, settings: &mut UserSettings) {
+        settings.set("frame_cap.foreground", self.foreground);
+        settings.set("frame_cap.background", self.background);
+        settings.set("frame_pacing.enabled", self.refresh_pacing);
+        settings.set("frame_pacing.target", self.pacing_target);
+    }
+
+    // None when frames aren't capped
+    pub fn active(&self, focused: bool) -> Option<u32> {
+        let cap = if focused { self.foreground } else { self.background };
+        (cap > 0).then_some(cap)
+    }
+
+    // How a window's frames are held back right now. Refresh pacing needs vsync off, focus and
+    // a rate (the target or the monitor's), and stays under the foreground cap.
+    pub fn pace(&self, focused: bool, vsync: bool, refresh_hz: Option<f64>) -> Option<Pace> {
+        let cap = self.active(focused);
+        let target = (self.pacing_target > 0).then_some(self.pacing_target as f64).or(refresh_hz);
+        match target {
+            Some(target) if self.refresh_pacing && focused && !vsync => {
+                Some(Pace::Refresh(cap.map_or(target, |cap| target.min(cap as f64))))
+            }
+            _ => cap.map(Pace::Cap),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    // Frames per second, woken by the event loop's timer, late by however much it oversleeps
+    Cap(u32),
+    // Frames per second, on time: woken early by the sleep bias and spun to the due time
+    Refresh(f64),
+}
+
+// Present intervals over the last INTERVAL_HISTORY frames
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalStats {
+    pub mean_ms: f32,
+    // The jitter
+    pub stddev_ms: f32,
+    pub samples: usize,
+}
+
+// The refresh rate of the monitor the window is on now, None where the platform doesn't say
+pub fn monitor_refresh_rate(window: &Window) -> Option<f64> {
+    let millihertz = window.current_monitor()?.refresh_rate_millihertz()?;
+    Some(millihertz as f64 / 1000.0)
+}
+
+// One per window, each window keeps its own pace
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    last_present: Option<Instant>,
+    // A frame was held back and the event loop should wake for it then
+    deferred_until: Option<Instant>,
+    // When the last refresh paced frame was due, the next one is due an interval after it
+    last_due: Option<Instant>,
+    // The interval the frame being drawn was paced to, None when it wasn't refresh paced
+    paced_interval: Option<Duration>,
+    sleep_bias: Duration,
+    refresh_hz: Option<f64>,
+    // Milliseconds between presents, oldest first
+    intervals: VecDeque<f32>,
+}
+
+impl FramePacer {
+    pub fn new(refresh_hz: Option<f64>) -> Self {
+        Self { refresh_hz, ..Default::default() }
+    }
+
+    // The window moved, maybe onto another monitor
+    pub fn set_refresh_rate(&mut self, refresh_hz: Option<f64>) {
+        if refresh_hz != self.refresh_hz {
+            log::info!("Monitor refresh rate: {}", refresh_hz.map_or("unknown".to_string(), |hz| format!("{:.2} Hz", hz)));
+            self.refresh_hz = refresh_hz;
+        }
+    }
+
+    pub fn refresh_rate(&self) -> Option<f64> {
+        self.refresh_hz
+    }
+
+    // True when a frame now would come too early for `pace`. The frame is then due at
+    // last present + 1 / cap, or an interval after the last refresh paced frame was due,
+    // see wake_at. A refresh paced frame close to its due time spins until then instead.
+    pub fn defer(&mut self, now: Instant, pace: Option<Pace>) -> bool {
+        let fps = match pace {
+            Some(Pace::Refresh(fps)) => fps,
+            Some(Pace::Cap(cap)) => return self.defer_to_cap(now, Some(cap)),
+            None => return self.defer_to_cap(now, None),
+        };
+        let interval = Duration::from_secs_f64(1.0 / fps);
+        // Counted from when the last frame was due rather than when it presented, so the time
+        // spent drawing doesn't add to every interval. A whole interval behind starts over now.
+        let due = self.last_due.map(|last| last + interval).filter(|due| *due + interval > now).unwrap_or(now);
+        let wake = due.checked_sub(SPIN_FINISH + self.sleep_bias).unwrap_or(due);
+        if wake > now {
+            self.deferred_until = Some(wake);
+            return true;
+        }
+        while Instant::now() < due {
+            std::hint::spin_loop();
+        }
+        self.deferred_until = None;
+        self.last_due = Some(due);
+        self.paced_interval = Some(interval);
+        false
+    }
+
+    fn defer_to_cap(&mut self, now: Instant, cap: Option<u32>) -> bool {
+        self.last_due = None;
+        self.paced_interval = None;
+        let due = cap
+            .zip(self.last_present)
+            .map(|(cap, last)| last + Duration::from_secs_f64(1.0 / cap as f64));
+        self.deferred_until = due.filter(|due| *due > now);
+        self.deferred_until.is_some()
+    }
+
+    // A refresh paced frame that presented late wakes the next one earlier, one on time lets
+    // the bias shrink again
+    pub fn presented(&mut self, now: Instant) {
+        if let Some(interval) = self.last_present.map(|last| now - last).filter(|interval| *interval < MAX_TRACKED_INTERVAL) {
+            if self.intervals.len() == INTERVAL_HISTORY {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(interval.as_secs_f32() * 1000.0);
+            if let Some(target) = self.paced_interval {
+                self.sleep_bias = match interval.checked_sub(target) {
+                    Some(late) => (self.sleep_bias + late.mul_f64(BIAS_GAIN)).min(MAX_SLEEP_BIAS),
+                    None => self.sleep_bias.saturating_sub(BIAS_DECAY),
+                };
+            }
+        }
+        self.last_present = Some(now);
+        self.deferred_until = None;
+    }
+
+    pub fn wake_at(&self) -> Option<Instant> {
+        self.deferred_until
+    }
+
+    pub fn sleep_bias(&self) -> Duration {
+        self.sleep_bias
+    }
+
+    pub fn interval_stats(&self) -> Option<IntervalStats> {
+        let samples = self.intervals.len();
+        if samples < 2 {
+            return None;
+        }
+        let mean = self.intervals.iter().sum::<f32>() / samples as f32;
+        let variance = self.intervals.iter().map(|ms| (ms - mean).powi(2)).sum::<f32>() / samples as f32;
+        Some(IntervalStats { mean_ms: mean, stddev_ms: variance.sqrt(), samples })
+    }
+}
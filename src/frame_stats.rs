@@ -0,0 +1,172 @@
+/*
+Purpose: Frame pacing statistics
+Responsibilities:
+    - Keep the last FRAME_HISTORY frame times in a ring buffer, split into update, render encode and the rest
+    - Summarize the window as p50/p95/p99/max and count hitches (frames over twice the median)
+    - Draw it as a stacked bar graph with 16.6 ms and 33.3 ms reference lines
+    - Count redraws over the last second, which drops towards zero when rendering on demand
+    - ex: a heart monitor, one beat per frame
+*/
+
+pub const FRAME_HISTORY: usize = 240;
+// A frame this many times slower than the median counts as a hitch
+const HITCH_FACTOR: f32 = 2.0;
+const REDRAW_RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(1);
+const REFERENCE_LINES_MS: [(f32, &str); 2] = [(1000.0 / 60.0, "60 fps"), (1000.0 / 30.0, "30 fps")];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameSample {
+    frame_ms: f32,
+    update_ms: f32,
+    encode_ms: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameSummary {
+    pub frames: usize,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+    pub max_ms: f32,
+    pub hitches: usize,
+}
+
+pub struct FrameStats {
+    samples: Vec<FrameSample>,
+    // Where the next sample goes once the ring is full
+    next: usize,
+    // Measured during the frame that is still running
+    pending_update_ms: f32,
+    pending_encode_ms: f32,
+    // Reused for sorting so summarizing doesn't allocate every frame
+    sorted: Vec<f32>,
+    // Primary window redraws within the last REDRAW_RATE_WINDOW
+    redraws: std::collections::VecDeque<std::time::Instant>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            samples: Vec::with_capacity(FRAME_HISTORY),
+            next: 0,
+            pending_update_ms: 0.0,
+            pending_encode_ms: 0.0,
+            sorted: Vec::with_capacity(FRAME_HISTORY),
+            redraws: std::collections::VecDeque::new(),
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn record_update(&mut self, ms: f32) {
+        self.pending_update_ms += ms;
+    }
+
+    // Called once per window rendered, so every view's encode time adds up
+    pub fn record_encode(&mut self, ms: f32) {
+        self.pending_encode_ms += ms;
+    }
+
+    // Close the previous frame, `frame_ms` is the time between the last two updates
+    pub fn end_frame(&mut self, frame_ms: f32) {
+        let sample = FrameSample {
+            frame_ms,
+            update_ms: self.pending_update_ms,
+            encode_ms: self.pending_encode_ms,
+        };
+        self.pending_update_ms = 0.0;
+        self.pending_encode_ms = 0.0;
+        if self.samples.len() < FRAME_HISTORY {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.next] = sample;
+        }
+        self.next = (self.next + 1) % FRAME_HISTORY;
+    }
+
+    pub fn record_redraw(&mut self, now: std::time::Instant) {
+        self.redraws.push_back(now);
+        while self.redraws.front().is_some_and(|&at| now.duration_since(at) > REDRAW_RATE_WINDOW) {
+            self.redraws.pop_front();
+        }
+    }
+
+    // Only refreshed by redraws, so when idle it shows what the last redraw saw
+    pub fn redraws_per_second(&self) -> usize {
+        self.redraws.len()
+    }
+
+    // Oldest first
+    fn chronological(&self) -> impl Iterator<Item = &FrameSample> {
+        let split = if self.samples.len() < FRAME_HISTORY { 0 } else { self.next };
+        self.samples[split..].iter().chain(&self.samples[..split])
+    }
+
+    pub fn summary(&mut self) -> FrameSummary {
+        self.sorted.clear();
+        self.sorted.extend(self.samples.iter().map(|sample| sample.frame_ms));
+        self.sorted.sort_unstable_by(f32::total_cmp);
+        let Some(&max_ms) = self.sorted.last() else {
+            return FrameSummary::default();
+        };
+        // Same nearest-rank percentiles as the benchmark report
+        let percentile = |p: f32| self.sorted[((self.sorted.len() - 1) as f32 * p).round() as usize];
+        let p50_ms = percentile(0.5);
+        FrameSummary {
+            frames: self.sorted.len(),
+            p50_ms,
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms,
+            hitches: self.sorted.iter().filter(|&&ms| ms > p50_ms * HITCH_FACTOR).count(),
+        }
+    }
+
+    // One bar per frame: update at the bottom, render encode on top of it, then the rest of
+    // the frame (present, vsync, the OS). Hitches draw the rest in red.
+    pub fn draw_graph(&self, ui: &mut egui::Ui, summary: &FrameSummary, height: f32) {
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), height), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(160));
+
+        // Keep the 30 fps line on screen, grow when frames are slower
+        let scale_ms = (REFERENCE_LINES_MS[1].0 * 1.25).max(summary.max_ms);
+        let y_for = |ms: f32| rect.bottom() - (ms / scale_ms).min(1.0) * rect.height();
+        let bar_width = rect.width() / FRAME_HISTORY as f32;
+        let hitch_ms = summary.p50_ms * HITCH_FACTOR;
+
+        for (i, sample) in self.chronological().enumerate() {
+            let left = rect.left() + i as f32 * bar_width;
+            let right = left + (bar_width - 1.0).max(1.0);
+            let update_top = sample.update_ms;
+            let encode_top = update_top + sample.encode_ms;
+            let rest_color = if sample.frame_ms > hitch_ms {
+                egui::Color32::from_rgb(220, 70, 60)
+            } else {
+                egui::Color32::from_gray(150)
+            };
+            for (bottom_ms, top_ms, color) in [
+                (0.0, update_top, egui::Color32::from_rgb(90, 150, 230)),
+                (update_top, encode_top, egui::Color32::from_rgb(240, 170, 60)),
+                (encode_top, sample.frame_ms, rest_color),
+            ] {
+                if top_ms > bottom_ms {
+                    let bar = egui::Rect::from_x_y_ranges(left..=right, y_for(top_ms)..=y_for(bottom_ms));
+                    painter.rect_filled(bar, 0.0, color);
+                }
+            }
+        }
+
+        for (ms, label) in REFERENCE_LINES_MS {
+            let y = y_for(ms);
+            painter.hline(rect.x_range(), y, egui::Stroke::new(1.0, egui::Color32::from_white_alpha(120)));
+            painter.text(
+                egui::pos2(rect.left() + 4.0, y - 2.0),
+                egui::Align2::LEFT_BOTTOM,
+                format!("{:.1} ms ({})", ms, label),
+                egui::FontId::proportional(10.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+}
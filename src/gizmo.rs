@@ -0,0 +1,334 @@
+/*
+Purpose: Viewport orientation gizmo
+Responsibilities:
+    - Draw a small axis cube in the top-right corner that turns with the camera
+    - Pick which face of the cube was clicked
+    - Animate the camera to look straight at the clicked side (front/top/right/...)
+    - ex: the compass rose on a map
+*/
+
+use crate::{camera::{Camera, OPENGL_TO_WGPU_MATRIX}, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, math::{Aabb, Ray, ray_aabb_intersect}, vertex::Vertex};
+use cgmath::{Matrix3, Matrix4, Rad, SquareMatrix, Vector3, Vector4};
+
+// Size of the gizmo and its distance from the window edges, in logical pixels
+const GIZMO_SIZE: f32 = 96.0;
+const GIZMO_MARGIN: f32 = 12.0;
+// Leaves room for the menu bar along the top of the primary window
+const GIZMO_TOP_OFFSET: f32 = 36.0;
+// How long the camera takes to swing to a clicked face
+const SNAP_DURATION: f32 = 0.25;
+
+// Face normal, label, and color. The camera snaps to look at a face from outside.
+const FACES: [([f32; 3], &str, [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], "+X", [0.85, 0.25, 0.25]),
+    ([-1.0, 0.0, 0.0], "-X", [0.45, 0.15, 0.15]),
+    ([0.0, 1.0, 0.0], "+Y", [0.25, 0.8, 0.3]),
+    ([0.0, -1.0, 0.0], "-Y", [0.15, 0.4, 0.15]),
+    ([0.0, 0.0, 1.0], "+Z", [0.25, 0.4, 0.9]),
+    ([0.0, 0.0, -1.0], "-Z", [0.15, 0.2, 0.45]),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoUniform {
+    view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+}
+
+pub const GIZMO_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(GizmoUniform, [view_proj, view]),
+    wgsl: &[("gizmo.wgsl", "GizmoUniform")],
+};
+
+// Where the gizmo sits in the window, in physical pixels
+#[derive(Debug, Clone, Copy)]
+pub struct GizmoRect {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+}
+
+impl GizmoRect {
+    // Fixed on-screen size, anchored to the top-right corner whatever the window size.
+    // `top_inset` (logical pixels) is extra room taken along the top, e.g. by a custom title bar.
+    pub fn for_window(width: u32, height: u32, scale_factor: f32, top_inset: f32) -> Self {
+        let size = (GIZMO_SIZE * scale_factor).round().min(width as f32).min(height as f32);
+        Self {
+            x: (width as f32 - size - GIZMO_MARGIN * scale_factor).max(0.0),
+            y: ((GIZMO_TOP_OFFSET + top_inset) * scale_factor).min(height as f32 - size).max(0.0),
+            size,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.size && y >= self.y && y < self.y + self.size
+    }
+
+}
+
+// Only the camera's rotation matters, the cube always sits in front of it
+fn gizmo_view(camera: &Camera) -> Matrix4<f32> {
+    let mut rotation = camera.calc_matrix();
+    rotation.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+    Matrix4::from_translation(Vector3::new(0.0, 0.0, -3.0)) * rotation
+}
+
+fn gizmo_projection() -> Matrix4<f32> {
+    OPENGL_TO_WGPU_MATRIX * cgmath::ortho(-1.0, 1.0, -1.0, 1.0, 0.1, 10.0)
+}
+
+fn rotation_of(camera: &Camera) -> Matrix3<f32> {
+    let m = camera.calc_matrix();
+    Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate())
+}
+
+// The face of the cube under a pixel, if any
+pub fn pick_face(camera: &Camera, rect: GizmoRect, x: f32, y: f32) -> Option<Vector3<f32>> {
+    if !rect.contains(x, y) {
+        return None;
+    }
+    // Cast through the gizmo's own view, it starts outside the cube so it hits it from outside
+    let inverse_view_proj = (gizmo_projection() * gizmo_view(camera)).invert()?;
+    let ray = Ray::from_screen((x - rect.x, y - rect.y), (rect.size, rect.size), inverse_view_proj)?;
+    let cube = Aabb::new(Vector3::new(-0.5, -0.5, -0.5), Vector3::new(0.5, 0.5, 0.5));
+    let hit = ray.at(ray_aabb_intersect(&ray, &cube)?);
+
+    // The face hit is the axis the hit point sits furthest out on
+    let entry_axis = (0..3).max_by(|&a, &b| hit[a].abs().total_cmp(&hit[b].abs()))?;
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    normal[entry_axis] = hit[entry_axis].signum();
+    Some(normal)
+}
+
+// Screen positions (physical pixels) of the labels of the faces turned towards the viewer
+pub fn face_labels(camera: &Camera, rect: GizmoRect) -> Vec<(&'static str, [f32; 2])> {
+    let rotation = rotation_of(camera);
+    let view_proj = gizmo_projection() * gizmo_view(camera);
+    FACES
+        .iter()
+        .filter(|(normal, _, _)| (rotation * Vector3::from(*normal)).z > 0.2)
+        .map(|(normal, label, _)| {
+            // Face center of the unit cube
+            let clip = view_proj * (Vector3::from(*normal) * 0.5).extend(1.0);
+            let x = rect.x + (clip.x / clip.w * 0.5 + 0.5) * rect.size;
+            let y = rect.y + (0.5 - clip.y / clip.w * 0.5) * rect.size;
+            (*label, [x, y])
+        })
+        .collect()
+}
+
+// Camera turning towards an axis-aligned view
+pub struct CameraSnap {
+    from: (Rad<f32>, Rad<f32>),
+    to: (Rad<f32>, Rad<f32>),
+    elapsed: f32,
+}
+
+impl CameraSnap {
+    // Look at the clicked face from outside the cube, i.e. along the opposite of its normal
+    pub fn towards_face(camera: &Camera, normal: Vector3<f32>) -> Self {
+        let forward = -normal;
+        let pitch = Rad(forward.y.clamp(-1.0, 1.0).asin());
+        // Looking straight up/down leaves yaw undefined, keep the current heading
+        let yaw = if forward.x.abs() + forward.z.abs() > 1e-4 {
+            Rad(forward.z.atan2(forward.x))
+        } else {
+            camera.yaw()
+        };
+        Self {
+            from: (camera.yaw(), camera.pitch()),
+            to: (yaw, pitch),
+            elapsed: 0.0,
+        }
+    }
+
+    // Move the camera along, returns false once the snap has finished
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        self.elapsed += dt;
+        let t = (self.elapsed / SNAP_DURATION).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+
+        // Turn the short way around
+        let tau = std::f32::consts::TAU;
+        let yaw_delta = ((self.to.0.0 - self.from.0.0) % tau + tau + std::f32::consts::PI) % tau - std::f32::consts::PI;
+        let yaw = Rad(self.from.0.0 + yaw_delta * t);
+        let pitch = Rad(self.from.1.0 + (self.to.1.0 - self.from.1.0) * t);
+        camera.set_orientation(yaw, pitch);
+        self.elapsed < SNAP_DURATION
+    }
+}
+
+// Cube with its faces wound counter-clockwise from outside, so back faces can be culled
+fn cube_geometry() -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (normal, _, color) in FACES {
+        let n = Vector3::from(normal);
+        let u = if n.y.abs() > 0.5 { Vector3::unit_x() } else { Vector3::unit_y().cross(n) };
+        let v = n.cross(u);
+        let base = vertices.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = n * 0.5 + u * (0.5 * su) + v * (0.5 * sv);
+            vertices.push(Vertex {
+                position: position.into(),
+                color,
+                tex_coords: [(su + 1.0) * 0.5, (sv + 1.0) * 0.5],
+                normal,
+            });
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    (vertices, indices)
+}
+
+// Pipeline and cube mesh, shared between windows
+pub struct GizmoPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
+    num_elements: u32,
+}
+
+impl GizmoPipeline {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Gizmo Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gizmo Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Gizmo Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gizmo.wgsl").into()),
+        });
+        // Drawn onto the resolved frame. A convex cube with back-face culling needs no
+        // depth buffer, so the gizmo never touches (or clears) the scene's depth.
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Gizmo Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (vertices, indices) = cube_geometry();
+        let vertices: Vec<Vertex> = vertices.into_iter().map(Vertex::linear).collect();
+        let vertex_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Gizmo Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Gizmo Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+        }
+    }
+}
+
+// Per-window uniform, the gizmo follows that window's camera
+pub struct ViewGizmo {
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ViewGizmo {
+    pub fn new(device: &wgpu::Device, pipeline: &GizmoPipeline) -> Self {
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Gizmo Buffer"),
+            size: std::mem::size_of::<GizmoUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Gizmo Bind Group"),
+        });
+        Self { uniform_buffer, bind_group }
+    }
+
+    // Draw into the corner of an already rendered frame
+    pub fn draw(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &GizmoPipeline,
+        target: &wgpu::TextureView,
+        camera: &Camera,
+        rect: GizmoRect,
+    ) {
+        let view = gizmo_view(camera);
+        let uniform = GizmoUniform {
+            view_proj: (gizmo_projection() * view).into(),
+            view: view.into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gizmo Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_viewport(rect.x, rect.y, rect.size, rect.size, 0.0, 1.0);
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, pipeline.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(pipeline.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..pipeline.num_elements, 0, 0..1);
+    }
+}
@@ -0,0 +1,40 @@
+/*
+Purpose: Viewport orientation gizmo
+Responsibilites:
+    - Draw the small axis cube in the corner of the viewport
+    - Faces are shaded by how directly they face the viewer
+*/
+
+struct GizmoUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> gizmo: GizmoUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    let view_normal = (gizmo.view * vec4<f32>(model.normal, 0.0)).xyz;
+
+    var out: VertexOutput;
+    out.clip_position = gizmo.view_proj * vec4<f32>(model.position, 1.0);
+    out.color = model.color * (0.55 + 0.45 * max(view_normal.z, 0.0));
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -0,0 +1,21 @@
+/*
+Purpose: Names for GPU objects and passes, so captures (RenderDoc) show what everything is
+Responsibilities:
+    - Build labels that need formatting (per model, per probe, per window) only when they are kept
+    - Keep them in debug builds, and in release builds with the gpu-labels feature
+    - ex: name tags at a conference, handed out only when someone is taking photos
+*/
+
+// Formatted label for a GPU object, hand it over with .as_deref(). None in release builds
+// unless the gpu-labels feature is on, so the formatting isn't paid for.
+macro_rules! debug_label {
+    ($($arg:tt)*) => {
+        if cfg!(any(debug_assertions, feature = "gpu-labels")) {
+            Some(format!($($arg)*))
+        } else {
+            None::<String>
+        }
+    };
+}
+
+pub(crate) use debug_label;
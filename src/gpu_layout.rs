@@ -0,0 +1,266 @@
+/*
+Purpose: Catch Rust structs drifting out of step with the WGSL structs and vertex layouts they feed
+Responsibilities:
+    - Describe a #[repr(C)] struct's size and field offsets (RustLayout, made with rust_layout!)
+      next to the struct, where its private fields can be named
+    - Parse the shaders with naga and compare every UniformCheck with the WGSL structs it
+      names: the size, and the offset of each member against the Rust fields in order
+    - Compare every VertexCheck with its VertexBufferLayout: the stride, and an attribute
+      starting at every field
+    - Run under cargo test, and for --check-layouts, which exits non-zero on a mismatch
+    - ex: the carpenter's try square, held against every joint before the glue dries
+*/
+
+use wgpu::naga;
+
+use crate::{camera, clip_planes, debug_lines, depth_debug, edge_outline, foliage, gizmo, hdr, instance, instance_anim, instance_cull, light, model, motion_blur, particles, point_lights, probes, quad_2d, shape_renderer, skinning, sky, ssao, taa, toon, vertex, vertex_pulling};
+
+// A struct's bytes as the GPU reads them
+pub struct RustLayout {
+    pub name: &'static str,
+    pub size: usize,
+    // Every field but the padding (named _...), in declaration order
+    pub fields: &'static [(&'static str, usize)],
+}
+
+// RustLayout of `$ty`, listing its fields without the padding ones
+macro_rules! rust_layout {
+    ($ty:ty, [$($field:ident),* $(,)?]) => {
+        $crate::gpu_layout::RustLayout {
+            name: stringify!($ty),
+            size: std::mem::size_of::<$ty>(),
+            fields: &[$((stringify!($field), std::mem::offset_of!($ty, $field))),*],
+        }
+    };
+}
+
+pub(crate) use rust_layout;
+
+// A uniform or storage struct and the WGSL structs it is uploaded into, (shader file, struct)
+pub struct UniformCheck {
+    pub rust: RustLayout,
+    pub wgsl: &'static [(&'static str, &'static str)],
+}
+
+// A vertex buffer's element and the layout its pipelines are built with
+pub struct VertexCheck {
+    pub rust: RustLayout,
+    pub desc: fn() -> wgpu::VertexBufferLayout<'static>,
+}
+
+const UNIFORMS: &[UniformCheck] = &[
+    camera::CAMERA_UNIFORM_LAYOUT,
+    light::LIGHT_UNIFORM_LAYOUT,
+    model::MATERIAL_UNIFORM_LAYOUT,
+    clip_planes::CLIP_UNIFORM_LAYOUT,
+    point_lights::POINT_LIGHT_LAYOUT,
+    point_lights::POINT_LIGHTS_UNIFORM_LAYOUT,
+    toon::TOON_UNIFORM_LAYOUT,
+    probes::PROBE_UNIFORM_LAYOUT,
+    vertex_pulling::LAYOUT_UNIFORM_LAYOUT,
+    gizmo::GIZMO_UNIFORM_LAYOUT,
+    hdr::TONEMAP_UNIFORM_LAYOUT,
+    hdr::ADAPT_UNIFORM_LAYOUT,
+    instance_anim::ANIMATED_INSTANCE_LAYOUT,
+    instance_anim::ANIMATION_UNIFORM_LAYOUT,
+    instance_cull::CULL_UNIFORM_LAYOUT,
+    motion_blur::VELOCITY_UNIFORM_LAYOUT,
+    motion_blur::BLUR_UNIFORM_LAYOUT,
+    particles::PARTICLE_VIEW_UNIFORM_LAYOUT,
+    depth_debug::DEPTH_VIEW_UNIFORM_LAYOUT,
+    foliage::GRASS_UNIFORM_LAYOUT,
+    shape_renderer::SCENE_LIGHTS_UNIFORM_LAYOUT,
+    skinning::VERTEX_SKIN_LAYOUT,
+    skinning::SKINNING_UNIFORM_LAYOUT,
+    ssao::SSAO_UNIFORM_LAYOUT,
+    taa::RESOLVE_UNIFORM_LAYOUT,
+    sky::SKY_UNIFORM_LAYOUT,
+    edge_outline::OUTLINE_UNIFORM_LAYOUT,
+];
+
+const VERTICES: &[VertexCheck] = &[
+    model::MODEL_VERTEX_LAYOUT,
+    instance::INSTANCE_RAW_LAYOUT,
+    vertex::VERTEX_LAYOUT,
+    debug_lines::LINE_VERTEX_LAYOUT,
+    foliage::GRASS_VERTEX_LAYOUT,
+    foliage::GRASS_INSTANCE_LAYOUT,
+    particles::PARTICLE_LAYOUT,
+    quad_2d::QUAD_INSTANCE_LAYOUT,
+    shape_renderer::SHAPE_INSTANCE_LAYOUT,
+];
+
+// The shader files UniformChecks name, as the pipelines build them
+fn shader_source(file: &str) -> Option<String> {
+    let source = match file {
+        "shader.wgsl" => include_str!("shader.wgsl"),
+        "pull.wgsl" => return Some(vertex_pulling::shader_source()),
+        "debug_lines.wgsl" => include_str!("debug_lines.wgsl"),
+        "depth_debug.wgsl" => include_str!("depth_debug.wgsl"),
+        "depth_prepass.wgsl" => include_str!("depth_prepass.wgsl"),
+        "edge_outline.wgsl" => include_str!("edge_outline.wgsl"),
+        "gizmo.wgsl" => include_str!("gizmo.wgsl"),
+        "grass.wgsl" => include_str!("grass.wgsl"),
+        "hdr_adapt.wgsl" => include_str!("hdr_adapt.wgsl"),
+        "hdr_tonemap.wgsl" => include_str!("hdr_tonemap.wgsl"),
+        "instance_anim.wgsl" => include_str!("instance_anim.wgsl"),
+        "instance_cull.wgsl" => include_str!("instance_cull.wgsl"),
+        "light.wgsl" => include_str!("light.wgsl"),
+        "motion_blur.wgsl" => include_str!("motion_blur.wgsl"),
+        "motion_velocity.wgsl" => include_str!("motion_velocity.wgsl"),
+        "outline.wgsl" => include_str!("outline.wgsl"),
+        "overlay.wgsl" => include_str!("overlay.wgsl"),
+        "particles.wgsl" => include_str!("particles.wgsl"),
+        "pick.wgsl" => include_str!("pick.wgsl"),
+        "probe.wgsl" => include_str!("probe.wgsl"),
+        "shape.wgsl" => include_str!("shape.wgsl"),
+        "skinning.wgsl" => include_str!("skinning.wgsl"),
+        "sky.wgsl" => include_str!("sky.wgsl"),
+        "ssao.wgsl" => include_str!("ssao.wgsl"),
+        "ssao_normals.wgsl" => include_str!("ssao_normals.wgsl"),
+        "taa.wgsl" => include_str!("taa.wgsl"),
+        _ => return None,
+    };
+    Some(source.to_string())
+}
+
+// Size and (member, offset) of a struct as the module lays it out
+fn wgsl_struct(module: &naga::Module, name: &str) -> Option<(usize, Vec<(String, usize)>)> {
+    module.types.iter().find_map(|(_, ty)| match &ty.inner {
+        naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => Some((
+            *span as usize,
+            members
+                .iter()
+                .map(|member| (member.name.clone().unwrap_or_default(), member.offset as usize))
+                .collect(),
+        )),
+        _ => None,
+    })
+}
+
+fn check_uniform(check: &UniformCheck, module: &naga::Module, file: &str, name: &str, problems: &mut Vec<String>) {
+    let rust = &check.rust;
+    let Some((size, members)) = wgsl_struct(module, name) else {
+        problems.push(format!("{}: {} has no struct {}", rust.name, file, name));
+        return;
+    };
+    // Uniform buffers are padded out to 16 bytes on the Rust side, past the WGSL struct's end
+    if size != rust.size && size.next_multiple_of(16) != rust.size {
+        problems.push(format!("{}: {} bytes, {} in {} is {} bytes", rust.name, rust.size, name, file, size));
+    }
+    if members.len() != rust.fields.len() {
+        problems.push(format!("{}: {} fields, {} in {} has {} members", rust.name, rust.fields.len(), name, file, members.len()));
+    }
+    for ((field, offset), (member, member_offset)) in rust.fields.iter().zip(&members) {
+        if offset != member_offset {
+            problems.push(format!("{}.{} at byte {}, {}.{} in {} at byte {}", rust.name, field, offset, name, member, file, member_offset));
+        }
+    }
+}
+
+fn check_vertex(check: &VertexCheck, problems: &mut Vec<String>) {
+    let rust = &check.rust;
+    let desc = (check.desc)();
+    if desc.array_stride as usize != rust.size {
+        problems.push(format!("{}: {} bytes, its vertex layout steps {}", rust.name, rust.size, desc.array_stride));
+    }
+    for (field, offset) in rust.fields {
+        if !desc.attributes.iter().any(|attribute| attribute.offset as usize == *offset) {
+            problems.push(format!("{}.{} at byte {}, no vertex attribute starts there", rust.name, field, offset));
+        }
+    }
+    for attribute in desc.attributes {
+        if attribute.offset + attribute.format.size() > desc.array_stride {
+            problems.push(format!("{}: attribute @location({}) reads past the end of the vertex", rust.name, attribute.shader_location));
+        }
+    }
+}
+
+// Every mismatch between the Rust structs and the shaders, and how many structs were checked
+pub fn verify() -> (Vec<String>, usize) {
+    let mut problems = Vec::new();
+    let mut modules: Vec<(&str, naga::Module)> = Vec::new();
+    for check in UNIFORMS {
+        for &(file, name) in check.wgsl {
+            if !modules.iter().any(|(parsed, _)| *parsed == file) {
+                let Some(source) = shader_source(file) else {
+                    problems.push(format!("{}: no shader named {}", check.rust.name, file));
+                    continue;
+                };
+                match naga::front::wgsl::parse_str(&source) {
+                    Ok(module) => modules.push((file, module)),
+                    Err(e) => {
+                        problems.push(format!("{} does not parse: {}", file, e.emit_to_string(&source)));
+                        continue;
+                    }
+                }
+            }
+            if let Some((_, module)) = modules.iter().find(|(parsed, _)| *parsed == file) {
+                check_uniform(check, module, file, name, &mut problems);
+            }
+        }
+    }
+    for check in VERTICES {
+        check_vertex(check, &mut problems);
+    }
+    (problems, UNIFORMS.len() + VERTICES.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(layout: &RustLayout) -> Vec<(&'static str, usize)> {
+        layout.fields.to_vec()
+    }
+
+    #[test]
+    fn rust_structs_match_the_shaders() {
+        let (problems, checked) = verify();
+        assert!(problems.is_empty(), "{}", problems.join("\n"));
+        assert_eq!(checked, UNIFORMS.len() + VERTICES.len());
+    }
+
+    #[test]
+    fn camera_uniform_bytes() {
+        assert_eq!(std::mem::size_of::<camera::CameraUniform>(), 80);
+        let rust = &camera::CAMERA_UNIFORM_LAYOUT.rust;
+        assert_eq!(rust.size, 80);
+        assert_eq!(fields(rust), [("view_position", 0), ("view_proj", 16)]);
+    }
+
+    #[test]
+    fn instance_raw_bytes() {
+        assert_eq!(std::mem::size_of::<instance::InstanceRaw>(), 116);
+        let rust = &instance::INSTANCE_RAW_LAYOUT.rust;
+        assert_eq!(rust.size, 116);
+        assert_eq!(fields(rust), [("model", 0), ("normal", 64), ("uv_transform", 100)]);
+    }
+
+    #[test]
+    fn light_uniform_bytes() {
+        assert_eq!(std::mem::size_of::<light::LightUniform>(), 48);
+        assert_eq!(std::mem::offset_of!(light::LightUniform, color), 16);
+        let rust = &light::LIGHT_UNIFORM_LAYOUT.rust;
+        assert_eq!(fields(rust), [("position", 0), ("marker_scale", 12), ("color", 16), ("intensity", 28), ("ambient", 32)]);
+    }
+
+    #[test]
+    fn material_uniform_bytes() {
+        let rust = &model::MATERIAL_UNIFORM_LAYOUT.rust;
+        assert_eq!(rust.size, 48);
+        assert_eq!(
+            fields(rust),
+            [
+                ("color", 0),
+                ("specular_strength", 16),
+                ("shininess", 20),
+                ("roughness", 24),
+                ("metallic", 28),
+                ("shading_model", 32),
+                ("debug_view", 36),
+                ("layer", 40),
+            ]
+        );
+    }
+}
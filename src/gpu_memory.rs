@@ -0,0 +1,348 @@
+/*
+Purpose: Keep count of the GPU memory the engine's buffers and textures take
+Responsibilities:
+    - Create buffers and textures through wrappers that record their size, category and label
+    - Keep running totals per category in atomics, the handle stored next to a resource takes
+      it off again when the resource is dropped
+    - Summarize the live allocations for the stats panel and diagnostics, and guess a budget
+      from the adapter to warn against
+    - Compile down to nothing without the gpu-memory-tracking feature
+    - ex: a bar tab, every round written down and crossed off again when it's paid
+*/
+
+use std::ops::Deref;
+use wgpu::util::DeviceExt;
+
+#[cfg(feature = "gpu-memory-tracking")]
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub const ENABLED: bool = cfg!(feature = "gpu-memory-tracking");
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryCategory {
+    Texture,
+    RenderTarget,
+    Vertex,
+    Index,
+    Uniform,
+    Storage,
+    Readback,
+    Other,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 8] = [
+        MemoryCategory::Texture,
+        MemoryCategory::RenderTarget,
+        MemoryCategory::Vertex,
+        MemoryCategory::Index,
+        MemoryCategory::Uniform,
+        MemoryCategory::Storage,
+        MemoryCategory::Readback,
+        MemoryCategory::Other,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MemoryCategory::Texture => "textures",
+            MemoryCategory::RenderTarget => "render targets",
+            MemoryCategory::Vertex => "vertex buffers",
+            MemoryCategory::Index => "index buffers",
+            MemoryCategory::Uniform => "uniform buffers",
+            MemoryCategory::Storage => "storage buffers",
+            MemoryCategory::Readback => "readback buffers",
+            MemoryCategory::Other => "other",
+        }
+    }
+
+    // Told apart by what the buffer is for, an instance buffer is a vertex buffer here too
+    fn of_buffer(usage: wgpu::BufferUsages) -> Self {
+        if usage.contains(wgpu::BufferUsages::INDEX) {
+            MemoryCategory::Index
+        } else if usage.contains(wgpu::BufferUsages::VERTEX) {
+            MemoryCategory::Vertex
+        } else if usage.contains(wgpu::BufferUsages::UNIFORM) {
+            MemoryCategory::Uniform
+        } else if usage.contains(wgpu::BufferUsages::STORAGE) {
+            MemoryCategory::Storage
+        } else if usage.intersects(wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::MAP_WRITE) {
+            MemoryCategory::Readback
+        } else {
+            MemoryCategory::Other
+        }
+    }
+
+    fn of_texture(usage: wgpu::TextureUsages) -> Self {
+        if usage.contains(wgpu::TextureUsages::RENDER_ATTACHMENT) {
+            MemoryCategory::RenderTarget
+        } else {
+            MemoryCategory::Texture
+        }
+    }
+
+    #[cfg(feature = "gpu-memory-tracking")]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// A GPU resource with the handle that keeps it counted, derefs to the resource
+pub struct Tracked<T> {
+    resource: T,
+    _allocation: GpuAllocation,
+}
+
+impl<T> Tracked<T> {
+    // Keep the count but hold something derived from the resource, like a view of a texture,
+    // which keeps the texture alive just as well
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Tracked<U> {
+        Tracked { resource: f(self.resource), _allocation: self._allocation }
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+// Counted from creation until dropped. Empty without the feature.
+pub struct GpuAllocation {
+    #[cfg(feature = "gpu-memory-tracking")]
+    id: u64,
+    #[cfg(feature = "gpu-memory-tracking")]
+    category: MemoryCategory,
+    #[cfg(feature = "gpu-memory-tracking")]
+    size: u64,
+}
+
+#[cfg(feature = "gpu-memory-tracking")]
+impl Drop for GpuAllocation {
+    fn drop(&mut self) {
+        TRACKER.remove(self);
+    }
+}
+
+pub fn create_buffer(device: &wgpu::Device, desc: &wgpu::BufferDescriptor) -> Tracked<wgpu::Buffer> {
+    let buffer = device.create_buffer(desc);
+    let allocation = track(MemoryCategory::of_buffer(desc.usage), buffer.size(), desc.label);
+    Tracked { resource: buffer, _allocation: allocation }
+}
+
+pub fn create_buffer_init(device: &wgpu::Device, desc: &wgpu::util::BufferInitDescriptor) -> Tracked<wgpu::Buffer> {
+    let buffer = device.create_buffer_init(desc);
+    let allocation = track(MemoryCategory::of_buffer(desc.usage), buffer.size(), desc.label);
+    Tracked { resource: buffer, _allocation: allocation }
+}
+
+pub fn create_texture(device: &wgpu::Device, desc: &wgpu::TextureDescriptor) -> Tracked<wgpu::Texture> {
+    let texture = device.create_texture(desc);
+    let allocation = track(MemoryCategory::of_texture(desc.usage), texture_size(desc), desc.label);
+    Tracked { resource: texture, _allocation: allocation }
+}
+
+// Every mip level and array layer, times the samples. Drivers pad and align on top of this.
+pub fn texture_size(desc: &wgpu::TextureDescriptor) -> u64 {
+    let (block_width, block_height) = desc.format.block_dimensions();
+    let block_bytes = desc
+        .format
+        .block_copy_size(None)
+        .or_else(|| desc.format.block_copy_size(Some(wgpu::TextureAspect::DepthOnly)))
+        .unwrap_or(4) as u64;
+    let layers = match desc.dimension {
+        wgpu::TextureDimension::D3 => 1,
+        _ => desc.size.depth_or_array_layers as u64,
+    };
+    (0..desc.mip_level_count)
+        .map(|level| {
+            let size = desc.size.mip_level_size(level, desc.dimension);
+            let blocks_wide = size.width.div_ceil(block_width) as u64;
+            let blocks_high = size.height.div_ceil(block_height) as u64;
+            let depth = match desc.dimension {
+                wgpu::TextureDimension::D3 => size.depth_or_array_layers as u64,
+                _ => 1,
+            };
+            blocks_wide * blocks_high * depth * block_bytes
+        })
+        .sum::<u64>()
+        * layers
+        * desc.sample_count as u64
+}
+
+#[cfg(feature = "gpu-memory-tracking")]
+fn track(category: MemoryCategory, size: u64, label: Option<&str>) -> GpuAllocation {
+    TRACKER.add(category, size, label.unwrap_or("unlabeled"))
+}
+
+#[cfg(not(feature = "gpu-memory-tracking"))]
+fn track(_category: MemoryCategory, _size: u64, _label: Option<&str>) -> GpuAllocation {
+    GpuAllocation {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CategoryUsage {
+    pub category: MemoryCategory,
+    pub bytes: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemorySnapshot {
+    pub categories: Vec<CategoryUsage>,
+    pub total: u64,
+    pub peak: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LiveAllocation {
+    pub label: String,
+    pub category: MemoryCategory,
+    pub size: u64,
+}
+
+#[cfg(feature = "gpu-memory-tracking")]
+struct CategoryTotal {
+    bytes: AtomicU64,
+    count: AtomicU64,
+}
+
+// The totals are atomics. The labels take a lock, but only while a resource is created or
+// dropped, which costs far more than the lock already.
+#[cfg(feature = "gpu-memory-tracking")]
+struct GpuMemoryTracker {
+    totals: [CategoryTotal; MemoryCategory::ALL.len()],
+    total: AtomicU64,
+    peak: AtomicU64,
+    next_id: AtomicU64,
+    live: Mutex<BTreeMap<u64, LiveAllocation>>,
+}
+
+#[cfg(feature = "gpu-memory-tracking")]
+static TRACKER: GpuMemoryTracker = GpuMemoryTracker {
+    totals: [const { CategoryTotal { bytes: AtomicU64::new(0), count: AtomicU64::new(0) } }; MemoryCategory::ALL.len()],
+    total: AtomicU64::new(0),
+    peak: AtomicU64::new(0),
+    next_id: AtomicU64::new(0),
+    live: Mutex::new(BTreeMap::new()),
+};
+
+#[cfg(feature = "gpu-memory-tracking")]
+impl GpuMemoryTracker {
+    fn add(&self, category: MemoryCategory, size: u64, label: &str) -> GpuAllocation {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let totals = &self.totals[category.index()];
+        totals.bytes.fetch_add(size, Ordering::Relaxed);
+        totals.count.fetch_add(1, Ordering::Relaxed);
+        let total = self.total.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(total, Ordering::Relaxed);
+        self.live.lock().unwrap().insert(id, LiveAllocation { label: label.to_string(), category, size });
+        GpuAllocation { id, category, size }
+    }
+
+    fn remove(&self, allocation: &GpuAllocation) {
+        let totals = &self.totals[allocation.category.index()];
+        totals.bytes.fetch_sub(allocation.size, Ordering::Relaxed);
+        totals.count.fetch_sub(1, Ordering::Relaxed);
+        self.total.fetch_sub(allocation.size, Ordering::Relaxed);
+        self.live.lock().unwrap().remove(&allocation.id);
+    }
+}
+
+// Bytes of everything alive right now
+#[cfg(feature = "gpu-memory-tracking")]
+pub fn total() -> u64 {
+    TRACKER.total.load(Ordering::Relaxed)
+}
+
+#[cfg(not(feature = "gpu-memory-tracking"))]
+pub fn total() -> u64 {
+    0
+}
+
+#[cfg(feature = "gpu-memory-tracking")]
+pub fn snapshot() -> MemorySnapshot {
+    let categories = MemoryCategory::ALL
+        .iter()
+        .map(|&category| {
+            let totals = &TRACKER.totals[category.index()];
+            CategoryUsage {
+                category,
+                bytes: totals.bytes.load(Ordering::Relaxed),
+                count: totals.count.load(Ordering::Relaxed),
+            }
+        })
+        .collect();
+    MemorySnapshot { categories, total: total(), peak: TRACKER.peak.load(Ordering::Relaxed) }
+}
+
+#[cfg(not(feature = "gpu-memory-tracking"))]
+pub fn snapshot() -> MemorySnapshot {
+    let categories = MemoryCategory::ALL.iter().map(|&category| CategoryUsage { category, bytes: 0, count: 0 }).collect();
+    MemorySnapshot { categories, total: 0, peak: 0 }
+}
+
+// The `count` biggest live allocations, biggest first
+#[cfg(feature = "gpu-memory-tracking")]
+pub fn largest(count: usize) -> Vec<LiveAllocation> {
+    let mut live: Vec<_> = TRACKER.live.lock().unwrap().values().cloned().collect();
+    live.sort_by_key(|allocation| std::cmp::Reverse(allocation.size));
+    live.truncate(count);
+    live
+}
+
+#[cfg(not(feature = "gpu-memory-tracking"))]
+pub fn largest(_count: usize) -> Vec<LiveAllocation> {
+    Vec::new()
+}
+
+// wgpu doesn't say how much memory the GPU has. The largest buffer the adapter allows is the
+// closest hint, drivers often cap it at the size of the biggest heap. The device type covers
+// adapters that report no useful cap (software ones say u64::MAX or something tiny).
+pub fn default_budget(adapter: &wgpu::Adapter) -> u64 {
+    let by_type = match adapter.get_info().device_type {
+        wgpu::DeviceType::DiscreteGpu => 4096 * MIB,
+        wgpu::DeviceType::IntegratedGpu | wgpu::DeviceType::VirtualGpu => 2048 * MIB,
+        wgpu::DeviceType::Cpu | wgpu::DeviceType::Other => 1024 * MIB,
+    };
+    let max_buffer = adapter.limits().max_buffer_size;
+    if (512 * MIB..=64 * 1024 * MIB).contains(&max_buffer) { max_buffer } else { by_type }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * MIB {
+        format!("{:.2} GiB", bytes as f64 / (1024 * MIB) as f64)
+    } else if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
+
+// Plain text for diagnostics: totals against the budget, each category, the biggest allocations
+pub fn report(budget: u64) -> String {
+    if !ENABLED {
+        return "gpu memory tracking is compiled out (gpu-memory-tracking feature)\n".to_string();
+    }
+    let snapshot = snapshot();
+    let mut lines = vec![format!(
+        "total: {} of {} budget, peak {}",
+        format_bytes(snapshot.total),
+        format_bytes(budget),
+        format_bytes(snapshot.peak)
+    )];
+    for usage in snapshot.categories.iter().filter(|usage| usage.count > 0) {
+        lines.push(format!("{}: {} in {}", usage.category.label(), format_bytes(usage.bytes), usage.count));
+    }
+    lines.push("largest:".to_string());
+    for allocation in largest(10) {
+        lines.push(format!("  {} ({}): {}", allocation.label, allocation.category.label(), format_bytes(allocation.size)));
+    }
+    lines.into_iter().map(|line| line + "\n").collect()
+}
@@ -0,0 +1,177 @@
+/*
+Purpose: GPU time spent in render passes, measured with timestamp queries
+Responsibilities:
+    - Hand out timestamp writes for the passes it measures, a begin and end timestamp each
+    - Resolve and read the queries back without stalling, the numbers arrive a frame or two late
+    - Keep a smoothed time per pass for the stats panel
+    - ex: a stopwatch handed to the GPU, read out after the lap
+*/
+
+use crate::gpu_memory::{self, Tracked};
+use std::cell::Cell;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+// Weight of the newest reading in the smoothed pass times
+const SMOOTHING: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuPass {
+    DepthPrepass,
+    Main,
+}
+
+impl GpuPass {
+    pub const ALL: [GpuPass; 2] = [GpuPass::DepthPrepass, GpuPass::Main];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GpuPass::DepthPrepass => "Depth pre-pass",
+            GpuPass::Main => "Main pass",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    // Every pass resolves into its own aligned slot, passes that didn't run aren't resolved
+    fn resolve_offset(self) -> wgpu::BufferAddress {
+        self.index() as wgpu::BufferAddress * wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT
+    }
+}
+
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: Tracked<wgpu::Buffer>,
+    readback_buffer: Tracked<wgpu::Buffer>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    // Passes given timestamp writes this frame, one bit per GpuPass
+    written: Cell<u32>,
+    // Passes whose queries are on their way back in readback_buffer
+    in_flight: Option<u32>,
+    // Set from the map_async callback
+    mapped: Arc<AtomicBool>,
+    pass_ms: [Option<f32>; GpuPass::ALL.len()],
+    // Unsmoothed times of the last readback, until taken
+    latest: Vec<(GpuPass, f32)>,
+}
+
+impl GpuTimer {
+    // None when the device was created without TIMESTAMP_QUERY (the adapter can't do it)
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let passes = GpuPass::ALL.len() as u32;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: passes * 2,
+        });
+        let size = passes as wgpu::BufferAddress * wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+        let resolve_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            written: Cell::new(0),
+            in_flight: None,
+            mapped: Arc::new(AtomicBool::new(false)),
+            pass_ms: [None; GpuPass::ALL.len()],
+            latest: Vec::new(),
+        })
+    }
+
+    // Timestamps for `pass`, None while the last readback is still on its way
+    pub fn pass_writes(&self, pass: GpuPass) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+        self.written.set(self.written.get() | 1 << pass.index());
+        let begin = pass.index() as u32 * 2;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(begin),
+            end_of_pass_write_index: Some(begin + 1),
+        })
+    }
+
+    // After the measured passes, before the encoder is finished
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let written = self.written.replace(0);
+        if written == 0 || self.in_flight.is_some() {
+            return;
+        }
+        for pass in GpuPass::ALL.into_iter().filter(|pass| written & 1 << pass.index() != 0) {
+            let begin = pass.index() as u32 * 2;
+            encoder.resolve_query_set(&self.query_set, begin..begin + 2, &self.resolve_buffer, pass.resolve_offset());
+        }
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, self.resolve_buffer.size());
+        self.in_flight = Some(written);
+    }
+
+    // After the encoder was submitted
+    pub fn request_readback(&mut self) {
+        if self.in_flight.is_none() || self.mapped.load(Ordering::Acquire) {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| match result {
+            Ok(()) => mapped.store(true, Ordering::Release),
+            Err(e) => log::warn!("Could not read back GPU timestamps: {}", e),
+        });
+    }
+
+    // Picks up a finished readback, call once per frame before new timestamps are written
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Some(written) = self.in_flight else {
+            return;
+        };
+        if let Err(e) = device.poll(wgpu::PollType::Poll) {
+            log::warn!("Polling for GPU timestamps failed: {}", e);
+        }
+        if !self.mapped.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            for pass in GpuPass::ALL {
+                let slot = &mut self.pass_ms[pass.index()];
+                if written & 1 << pass.index() == 0 {
+                    *slot = None;
+                    continue;
+                }
+                let offset = pass.resolve_offset() as usize;
+                let ticks: &[u64] = bytemuck::cast_slice(&data[offset..offset + 16]);
+                let ms = ticks[1].saturating_sub(ticks[0]) as f32 * self.period / 1_000_000.0;
+                self.latest.push((pass, ms));
+                *slot = Some(slot.map_or(ms, |smoothed| smoothed + (ms - smoothed) * SMOOTHING));
+            }
+        }
+        self.readback_buffer.unmap();
+        self.in_flight = None;
+    }
+
+    // Each readback's raw pass times once, for the profiler's GPU track
+    pub fn take_latest(&mut self) -> Vec<(GpuPass, f32)> {
+        std::mem::take(&mut self.latest)
+    }
+
+    // Smoothed GPU time of the pass, None if it hasn't run lately
+    pub fn pass_ms(&self, pass: GpuPass) -> Option<f32> {
+        self.pass_ms[pass.index()]
+    }
+}
@@ -0,0 +1,114 @@
+/*
+Purpose: Instanced grass clumps, see foliage.rs
+Responsibilites:
+    - Place the crossed-quad clump mesh per instance (position, scale, rotation)
+    - Sway the tips with the wind, the roots stay planted
+    - Cut the blades out of the quads with a cutoff, no blending so nothing needs sorting
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: Scene light, see light.rs
+struct Light {
+    position: vec3<f32>,
+    marker_scale: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    ambient: f32,
+}
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+// Group 2: Grass field, see GrassUniform
+struct Grass {
+    origin: vec4<f32>,
+    // xy: direction on the ground, z: strength, w: seconds
+    wind: vec4<f32>,
+    alpha_cutoff: f32,
+}
+@group(2) @binding(0)
+var<uniform> grass: Grass;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+}
+
+struct InstanceInput {
+    @location(2) position_scale: vec4<f32>,
+    @location(3) rotation: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    // 0..1 per clump, varies the color a little
+    @location(3) variation: f32,
+}
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let root = grass.origin.xyz + instance.position_scale.xyz;
+    let scale = instance.position_scale.w;
+    var world_position = root + rotate(instance.rotation, model.position * scale);
+
+    // Two waves out of step, phased by position so gusts roll across the field
+    let time = grass.wind.w;
+    let phase = dot(root.xz, vec2<f32>(0.35, 0.25));
+    let gust = sin(time * 1.8 + phase) * 0.7 + sin(time * 4.3 + phase * 2.7) * 0.3;
+    let bend = model.uv.y * model.uv.y * scale * grass.wind.z * (0.5 + 0.5 * gust);
+    world_position += vec3<f32>(grass.wind.x, 0.0, grass.wind.y) * bend;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.uv = model.uv;
+    out.world_position = world_position;
+    // Lit as if facing up like the ground, flat quads lit by their own normal flicker as they turn
+    out.world_normal = rotate(instance.rotation, vec3<f32>(0.0, 1.0, 0.0));
+    out.variation = fract(sin(dot(root.xz, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+    return out;
+}
+
+// Coverage of one tapering, leaning blade rooted at `center`
+fn blade(uv: vec2<f32>, center: f32, height: f32, lean: f32) -> f32 {
+    let v = uv.y / height;
+    if (v > 1.0) {
+        return 0.0;
+    }
+    let half_width = 0.09 * (1.0 - v);
+    let x = abs(uv.x - center - lean * v * v);
+    return 1.0 - smoothstep(half_width * 0.6, half_width, x);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = max(
+        max(blade(in.uv, 0.25, 0.75, 0.1), blade(in.uv, 0.5, 1.0, -0.06)),
+        blade(in.uv, 0.75, 0.85, -0.12),
+    );
+    if (coverage < grass.alpha_cutoff) {
+        discard;
+    }
+
+    let root_color = vec3<f32>(0.08, 0.2, 0.04);
+    let tip_color = mix(vec3<f32>(0.35, 0.6, 0.15), vec3<f32>(0.55, 0.6, 0.2), in.variation);
+    let object_color = mix(root_color, tip_color, in.uv.y);
+
+    let light_dir = normalize(light.position - in.world_position);
+    // Wrapped diffuse, blades let some light through
+    let diffuse = max(dot(normalize(in.world_normal), light_dir), 0.0) * 0.7 + 0.3;
+    let lighting = light.color * (light.intensity * diffuse + light.ambient);
+    return vec4<f32>(lighting * object_color, 1.0);
+}
@@ -0,0 +1,741 @@
+/*
+Purpose: Let egui windows be added to the engine without reaching into State
+Responsibilities:
+    - Define GuiWindow, drawn every frame while it is open, and EngineApi, the operations a
+      window may use on the engine (spawning, the light, the camera, stats)
+    - Keep the registered windows and whether each is open, saved in the settings file
+    - Draw the Windows menu that lists and toggles them
+    - Port the built-in settings, frame pacing, light, measurement, scripts, comparison sheet,
+      undo history and video export windows onto the same trait
+    - ex: the wall sockets, plug in whatever appliance you like without rewiring the house
+*/
+
+use cgmath::{Deg, Point3};
+
+use crate::{color, frame_graph::TransientStats, gpu_memory, measure::Measurement, scripting::ScriptInfo, model_entry::{InstanceId, ModelHandle}, point_lights::{PointLight, PointLightId}, render_matrix::MatrixPreset, rust_literal::{self, ToRustLiteral}, state::State, undo::Command, units::SceneUnits, user_settings::UserSettings, video_export::{self, CameraPath, ExportSpan}, view_window::ViewWindow};
+
+pub const SETTINGS_WINDOW: &str = "Settings";
+pub const STATS_WINDOW: &str = "Frame pacing";
+pub const LIGHT_WINDOW: &str = "Light";
+pub const MEASURE_WINDOW: &str = "Measurements";
+pub const SCRIPTS_WINDOW: &str = "Scripts";
+pub const COMPARE_WINDOW: &str = "Comparison sheet";
+pub const HISTORY_WINDOW: &str = "History";
+pub const EXPORT_WINDOW: &str = "Video export";
+
+pub trait GuiWindow {
+    // Listed in the Windows menu, also the key its open state is saved under
+    fn title(&self) -> &str;
+    // Called every frame while open. Clearing `open` (the close button) closes the window.
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi);
+}
+
+// Where the primary window's camera is and where it looks
+#[derive(Debug, Clone, Copy)]
+pub struct CameraInfo {
+    pub position: Point3<f32>,
+    pub yaw: Deg<f32>,
+    pub pitch: Deg<f32>,
+    // Positive rolls right
+    pub roll: Deg<f32>,
+    // Units per second, with the speed multiplier and any held sprint or slow key
+    pub speed: f32,
+    pub speed_multiplier: f32,
+}
+
+// What a window gets to do to the engine for the frame it is drawn in
+pub struct EngineApi<'a> {
+    state: &'a mut State,
+    view: &'a ViewWindow,
+}
+
+impl<'a> EngineApi<'a> {
+    pub fn new(state: &'a mut State, view: &'a ViewWindow) -> Self {
+        Self { state, view }
+    }
+
+    // A static instance of a loaded model, None if there is no such model
+    #[allow(dead_code)] // none of the built-in windows spawn
+    pub fn spawn(&mut self, model: ModelHandle, position: [f32; 3]) -> Option<InstanceId> {
+        self.state.add_instance_of(model, position.into(), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0))
+    }
+
+    // Color and intensity
+    pub fn light(&self) -> ([f32; 3], f32) {
+        self.state.light()
+    }
+
+    pub fn set_light(&mut self, color: [f32; 3], intensity: f32) {
+        self.state.set_light(color, Some(intensity));
+    }
+
+    // The main light's uniform as a Rust struct literal
+    pub fn light_code(&self) -> String {
+        self.state.light_uniform().to_rust_literal()
+    }
+
+    // This window's Camera and Projection as `let` statements
+    pub fn camera_code(&self) -> String {
+        rust_literal::camera_snippet(&self.view.camera, &self.view.projection)
+    }
+
+    // Puts `code` on the clipboard and confirms it with a toast naming `what`
+    pub fn copy_as_code(&mut self, ctx: &egui::Context, what: &str, code: String) {
+        self.state.copy_as_code(ctx, what, code);
+    }
+
+    // The next click in the scene places a point light, see State::begin_light_placement
+    pub fn begin_light_placement(&mut self) {
+        self.state.begin_light_placement();
+    }
+
+    pub fn point_lights(&self) -> Vec<PointLight> {
+        self.state.point_lights().copied().collect()
+    }
+
+    // Everything but the id, false if there is no such light. A change is undoable.
+    pub fn set_point_light(&mut self, light: PointLight) -> bool {
+        let Some(existing) = self.state.point_light_mut(light.id) else {
+            return false;
+        };
+        let before = *existing;
+        *existing = light;
+        if before != light {
+            self.state.record(Command::PointLight { before, after: light });
+        }
+        true
+    }
+
+    pub fn remove_point_light(&mut self, id: PointLightId) -> bool {
+        self.state.remove_point_light(id)
+    }
+
+    pub fn selected_point_light(&self) -> Option<PointLightId> {
+        self.state.selected_point_light()
+    }
+
+    pub fn select_point_light(&mut self, id: Option<PointLightId>) {
+        self.state.select_point_light(id);
+    }
+
+    // Turns measuring on or off, see State::toggle_measuring
+    pub fn toggle_measuring(&mut self) {
+        self.state.toggle_measuring();
+    }
+
+    pub fn is_measuring(&self) -> bool {
+        self.state.measurements.is_active()
+    }
+
+    pub fn measurements(&self) -> Vec<Measurement> {
+        self.state.measurements.list().to_vec()
+    }
+
+    pub fn remove_measurement(&mut self, index: usize) {
+        self.state.measurements.remove(index);
+    }
+
+    pub fn clear_measurements(&mut self) {
+        self.state.measurements.clear();
+    }
+
+    // Whether measuring snaps to instance origins near the cursor
+    pub fn measure_snap(&self) -> bool {
+        self.state.measurements.snap
+    }
+
+    pub fn set_measure_snap(&mut self, snap: bool) {
+        self.state.measurements.snap = snap;
+    }
+
+    pub fn scripts(&self) -> Vec<ScriptInfo> {
+        self.state.scripts()
+    }
+
+    // Enabling a stopped script clears its error, see ScriptHost::set_enabled
+    pub fn set_script_enabled(&mut self, name: &str, enabled: bool) {
+        self.state.set_script_enabled(name, enabled);
+    }
+
+    pub fn reload_scripts(&mut self) {
+        self.state.reload_scripts();
+    }
+
+    // The scripts folder and whether edits in it are picked up
+    pub fn script_dir(&self) -> (String, bool) {
+        let (dir, watching) = self.state.script_dir();
+        (dir.display().to_string(), watching)
+    }
+
+    // Descriptions of the edits undo would take back, newest first, and of those redo would bring
+    // back, next first
+    pub fn history(&self) -> (Vec<String>, Vec<String>) {
+        let undo = &self.state.undo;
+        (undo.done().rev().map(|entry| entry.description.clone()).collect(), undo.undone().map(|entry| entry.description.clone()).collect())
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.state.undo()
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.state.redo()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.state.undo.clear();
+    }
+
+    // Most edits the history keeps
+    pub fn undo_depth(&self) -> usize {
+        self.state.undo.depth()
+    }
+
+    pub fn camera(&self) -> CameraInfo {
+        let camera = &self.view.camera;
+        CameraInfo {
+            position: camera.position,
+            yaw: camera.yaw().into(),
+            pitch: camera.pitch().into(),
+            roll: camera.roll().into(),
+            speed: self.view.controller.input().speed(),
+            speed_multiplier: self.view.controller.input().speed_multiplier,
+        }
+    }
+
+    pub fn units(&self) -> SceneUnits {
+        self.state.units()
+    }
+
+    // The offscreen passes' textures of the primary window, see frame_graph.rs
+    pub fn transient_memory(&self) -> Option<TransientStats> {
+        self.view.transient_stats()
+    }
+
+    pub fn vertex_path(&self) -> &'static str {
+        self.state.vertex_path()
+    }
+
+    // Frame times, models and GPU memory, as the console's stats command prints them
+    pub fn stats(&mut self) -> String {
+        self.state.stats_report()
+    }
+
+    // Rendered from the primary window's camera before its next frame, see State::render_matrix
+    pub fn request_render_matrix(&mut self, preset: MatrixPreset, path: &str) {
+        self.state.request_render_matrix(preset, std::path::Path::new(path));
+    }
+
+    // From the primary window's camera, see State::export_video
+    pub fn export_video(&mut self, path: &str, fps: u32, span: ExportSpan, size: (u32, u32)) -> anyhow::Result<()> {
+        self.state.export_video(std::path::Path::new(path), fps, span, size)
+    }
+
+    // Where this window's camera is and a point a meter ahead of it, a camera path key
+    pub fn camera_key(&self) -> (Point3<f32>, Point3<f32>) {
+        let camera = &self.view.camera;
+        (camera.position, camera.position + camera.forward() * self.state.units().units_per_meter())
+    }
+
+    // In pixels
+    pub fn view_size(&self) -> (u32, u32) {
+        (self.view.config.width, self.view.config.height)
+    }
+}
+
+// The registered windows, in the order they were registered
+#[derive(Default)]
+pub struct GuiWindows {
+    windows: Vec<(Box<dyn GuiWindow>, bool)>,
+}
+
+impl GuiWindows {
+    // Opens it if it was open when the settings were last saved
+    pub fn register(&mut self, window: Box<dyn GuiWindow>, settings: &UserSettings) {
+        let open = settings.parse(&settings_key(window.title())).unwrap_or(false);
+        self.windows.push((window, open));
+    }
+
+    // False if no window has that title
+    pub fn toggle(&mut self, title: &str) -> bool {
+        match self.windows.iter_mut().find(|(window, _)| window.title() == title) {
+            Some((_, open)) => {
+                *open = !*open;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // False if no window has that title
+    pub fn open(&mut self, title: &str) -> bool {
+        match self.windows.iter_mut().find(|(window, _)| window.title() == title) {
+            Some((_, open)) => {
+                *open = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn write_settings(&self, settings: &mut UserSettings) {
+        for (window, open) in &self.windows {
+            settings.set(&settings_key(window.title()), *open);
+        }
+    }
+
+    // Checkboxes for every window, true if one was toggled
+    pub fn draw_menu(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        for (window, open) in &mut self.windows {
+            changed |= ui.checkbox(open, window.title()).changed();
+        }
+        changed
+    }
+
+    // Draws the open windows, true if one of them was closed
+    pub fn show(&mut self, ctx: &egui::Context, engine: &mut EngineApi) -> bool {
+        let mut changed = false;
+        for (window, open) in self.windows.iter_mut().filter(|(_, open)| *open) {
+            window.show(ctx, open, engine);
+            changed |= !*open;
+        }
+        changed
+    }
+}
+
+fn settings_key(title: &str) -> String {
+    format!("window.{}.open", title.to_lowercase().replace(' ', "_"))
+}
+
+// The big menu of engine settings
+pub struct SettingsWindow;
+
+impl GuiWindow for SettingsWindow {
+    fn title(&self) -> &str {
+        SETTINGS_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        engine.state.draw_menu(ctx, open, engine.view);
+    }
+}
+
+pub struct StatsWindow;
+
+impl GuiWindow for StatsWindow {
+    fn title(&self) -> &str {
+        STATS_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        let camera = engine.camera();
+        let units = engine.units();
+        egui::Window::new(STATS_WINDOW)
+            .open(open)
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let position = camera.position;
+                    ui.label(format!(
+                        "Camera: ({:.1}, {:.1}, {:.1}), yaw {:.0}°, pitch {:.0}°, roll {:.0}°",
+                        position.x, position.y, position.z, camera.yaw.0, camera.pitch.0, camera.roll.0
+                    ));
+                    if ui.button("Copy stats").clicked() {
+                        ctx.copy_text(engine.stats());
+                    }
+                    if ui.button("Copy as code").on_hover_text("Camera::new and Projection::new for this view, full precision").clicked() {
+                        let code = engine.camera_code();
+                        engine.copy_as_code(ctx, "camera", code);
+                    }
+                });
+                ui.label(format!(
+                    "Fly speed: {:.1} {}/s (x{:.2}), units: {}",
+                    camera.speed,
+                    units.suffix(),
+                    camera.speed_multiplier,
+                    units
+                ));
+                ui.label(format!("Vertex path: {}", engine.vertex_path()));
+                if let Some(transients) = engine.transient_memory() {
+                    ui.label(format!(
+                        "Transient targets: {} without aliasing, {} with ({} textures in {} allocations)",
+                        gpu_memory::format_bytes(transients.unaliased_bytes),
+                        gpu_memory::format_bytes(transients.aliased_bytes),
+                        transients.textures,
+                        transients.allocations
+                    ));
+                }
+                engine.state.draw_frame_stats(ui, engine.view.gpu_timer(), &engine.view.pacer);
+            });
+    }
+}
+
+// Scene light color and intensity and the placed point lights, made with nothing but EngineApi
+pub struct LightWindow;
+
+impl GuiWindow for LightWindow {
+    fn title(&self) -> &str {
+        LIGHT_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        let (mut color, mut intensity) = engine.light();
+        egui::Window::new(LIGHT_WINDOW).open(open).resizable(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Color");
+                color::edit_srgb(ui, &mut color);
+                if ui.small_button("Copy as code").on_hover_text("The LightUniform as a Rust struct literal").clicked() {
+                    let code = engine.light_code();
+                    engine.copy_as_code(ctx, "light", code);
+                }
+            });
+            ui.add(egui::Slider::new(&mut intensity, 0.0..=8.0).text("Intensity"));
+            ui.separator();
+            draw_point_lights(ui, engine);
+        });
+        if (color, intensity) != engine.light() {
+            engine.set_light(color, intensity);
+        }
+    }
+}
+
+// Measure mode toggle and every measurement with its length, removable one by one or all at once
+pub struct MeasureWindow;
+
+impl GuiWindow for MeasureWindow {
+    fn title(&self) -> &str {
+        MEASURE_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        let units = engine.units();
+        egui::Window::new(MEASURE_WINDOW).open(open).resizable(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let measuring = engine.is_measuring();
+                if ui.selectable_label(measuring, "Measure").on_hover_text("Click the start, then the end. M does the same, right click stops.").clicked() {
+                    engine.toggle_measuring();
+                }
+                let mut snap = engine.measure_snap();
+                if ui.checkbox(&mut snap, "Snap to instance origins").changed() {
+                    engine.set_measure_snap(snap);
+                }
+            });
+            let measurements = engine.measurements();
+            if measurements.is_empty() {
+                ui.label("No measurements yet");
+                return;
+            }
+            let mut remove = None;
+            for (index, measurement) in measurements.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}. {}", index + 1, units.format_distance(measurement.length())));
+                    if ui.small_button("Delete").clicked() {
+                        remove = Some(index);
+                    }
+                });
+            }
+            if let Some(index) = remove {
+                engine.remove_measurement(index);
+            }
+            if ui.button("Clear all").clicked() {
+                engine.clear_measurements();
+            }
+        });
+    }
+}
+
+// Every script with an enable checkbox, whether it runs and how long it takes
+pub struct ScriptsWindow;
+
+impl GuiWindow for ScriptsWindow {
+    fn title(&self) -> &str {
+        SCRIPTS_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        egui::Window::new(SCRIPTS_WINDOW).open(open).resizable(false).show(ctx, |ui| {
+            let (dir, watching) = engine.script_dir();
+            ui.horizontal(|ui| {
+                ui.label(if watching { "Watching" } else { "Loaded from" }).on_hover_text(&dir);
+                ui.monospace(std::path::Path::new(&dir).file_name().map_or(dir.clone(), |name| name.to_string_lossy().into_owned()));
+                if ui.button("Reload").on_hover_text("Read every script again and call on_init").clicked() {
+                    engine.reload_scripts();
+                }
+            });
+            let scripts = engine.scripts();
+            if scripts.is_empty() {
+                ui.label("No .rhai files in the folder");
+                return;
+            }
+            egui::Grid::new("scripts").num_columns(3).striped(true).show(ui, |ui| {
+                ui.strong("Script");
+                ui.strong("Status");
+                ui.strong("Time");
+                ui.end_row();
+                for script in &scripts {
+                    let mut enabled = script.enabled;
+                    if ui.checkbox(&mut enabled, &script.name).changed() {
+                        engine.set_script_enabled(&script.name, enabled);
+                    }
+                    match &script.error {
+                        Some(error) => {
+                            ui.colored_label(ui.visuals().error_fg_color, "error").on_hover_text(error);
+                        }
+                        None if script.idle => {
+                            ui.weak("no on_init, on_update or on_event");
+                        }
+                        None if !script.enabled => {
+                            ui.weak("disabled");
+                        }
+                        None => {
+                            ui.label("running");
+                        }
+                    }
+                    ui.monospace(format!("{:.3} ms", script.time_ms));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+// A preset matrix rendered from the current view into one labeled sheet, every frame saved next to it
+pub struct CompareWindow {
+    preset: MatrixPreset,
+    path: String,
+}
+
+impl Default for CompareWindow {
+    fn default() -> Self {
+        let preset = MatrixPreset::ShadingModels;
+        Self { preset, path: preset.default_path().to_string() }
+    }
+}
+
+impl GuiWindow for CompareWindow {
+    fn title(&self) -> &str {
+        COMPARE_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        egui::Window::new(COMPARE_WINDOW).open(open).resizable(false).show(ctx, |ui| {
+            let previous = self.preset;
+            egui::ComboBox::from_label("Matrix")
+                .selected_text(self.preset.label())
+                .show_ui(ui, |ui| {
+                    for preset in MatrixPreset::ALL {
+                        ui.selectable_value(&mut self.preset, preset, preset.label());
+                    }
+                });
+            // A path still at the old preset's default follows the new one
+            if self.preset != previous && self.path == previous.default_path() {
+                self.path = self.preset.default_path().to_string();
+            }
+            ui.horizontal(|ui| {
+                ui.label("Sheet");
+                ui.text_edit_singleline(&mut self.path);
+            });
+            ui.weak("Rendered like the headless benchmark: no SSAO, TAA or motion blur. Tonemappers and exposure need HDR.");
+            let path = self.path.trim();
+            if ui.add_enabled(!path.is_empty(), egui::Button::new("Render")).clicked() {
+                engine.request_render_matrix(self.preset, path);
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpanKind {
+    Seconds,
+    CameraPath,
+}
+
+// Renders a clip offscreen through State::export_video, for a number of seconds from the current
+// view or along a camera path whose keys are taken from the view
+pub struct ExportWindow {
+    path: String,
+    fps: u32,
+    size: (u32, u32),
+    span: SpanKind,
+    seconds: f32,
+    camera_path: CameraPath,
+    // Seconds between a key and the one added after it
+    key_spacing: f32,
+    // Asked the first time the window is drawn, not every frame
+    ffmpeg: Option<bool>,
+    error: Option<String>,
+}
+
+impl Default for ExportWindow {
+    fn default() -> Self {
+        Self {
+            path: video_export::DEFAULT_PATH.to_string(),
+            fps: video_export::DEFAULT_FPS,
+            size: video_export::DEFAULT_SIZE,
+            span: SpanKind::Seconds,
+            seconds: video_export::DEFAULT_SECONDS,
+            camera_path: CameraPath::default(),
+            key_spacing: 2.0,
+            ffmpeg: None,
+            error: None,
+        }
+    }
+}
+
+impl GuiWindow for ExportWindow {
+    fn title(&self) -> &str {
+        EXPORT_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        let ffmpeg = *self.ffmpeg.get_or_insert_with(video_export::ffmpeg_available);
+        egui::Window::new(EXPORT_WINDOW).open(open).resizable(false).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut self.path);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Size");
+                ui.add(egui::DragValue::new(&mut self.size.0).range(1..=video_export::MAX_SIZE));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut self.size.1).range(1..=video_export::MAX_SIZE));
+                if ui.button("Window").on_hover_text("The main window's size").clicked() {
+                    self.size = engine.view_size();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Frame rate");
+                ui.add(egui::DragValue::new(&mut self.fps).range(video_export::FPS_RANGE).suffix(" fps"));
+            });
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.span, SpanKind::Seconds, "From this view");
+                ui.radio_value(&mut self.span, SpanKind::CameraPath, "Camera path");
+            });
+            match self.span {
+                SpanKind::Seconds => {
+                    ui.horizontal(|ui| {
+                        ui.label("Length");
+                        ui.add(egui::DragValue::new(&mut self.seconds).range(0.1..=600.0).speed(0.1).suffix(" s"));
+                    });
+                }
+                SpanKind::CameraPath => {
+                    ui.label(format!("{} keys, {:.1} s", self.camera_path.key_count(), self.camera_path.duration()));
+                    ui.horizontal(|ui| {
+                        if ui.button("Add key").on_hover_text("Where the camera is and where it looks").clicked() {
+                            let (eye, target) = engine.camera_key();
+                            self.camera_path.push_key(eye, target, self.key_spacing);
+                        }
+                        ui.add(egui::DragValue::new(&mut self.key_spacing).range(0.1..=60.0).speed(0.1).prefix("after ").suffix(" s"));
+                        if ui.button("Clear").clicked() {
+                            self.camera_path = CameraPath::default();
+                        }
+                    });
+                }
+            }
+            if !ffmpeg {
+                ui.weak("ffmpeg isn't installed, frames are saved as a PNG sequence next to the file.");
+            }
+            ui.weak("Rendered like the comparison sheet: no SSAO, TAA or motion blur. The simulation steps one frame at a time.");
+            let path = self.path.trim().to_string();
+            if ui.add_enabled(!path.is_empty(), egui::Button::new("Export")).clicked() {
+                let span = match self.span {
+                    SpanKind::Seconds => ExportSpan::Seconds(self.seconds),
+                    SpanKind::CameraPath => ExportSpan::CameraPath(self.camera_path.clone()),
+                };
+                self.error = engine.export_video(&path, self.fps, span, self.size).err().map(|e| e.to_string());
+            }
+            if let Some(error) = &self.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    }
+}
+
+// The undo stack, newest edit at the top and the undone ones redo would bring back above it
+pub struct HistoryWindow;
+
+impl GuiWindow for HistoryWindow {
+    fn title(&self) -> &str {
+        HISTORY_WINDOW
+    }
+
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool, engine: &mut EngineApi) {
+        let (done, undone) = engine.history();
+        egui::Window::new(HISTORY_WINDOW).open(open).resizable(true).default_width(260.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!done.is_empty(), egui::Button::new("Undo")).on_hover_text("Ctrl+Z").clicked() {
+                    engine.undo();
+                }
+                if ui.add_enabled(!undone.is_empty(), egui::Button::new("Redo")).on_hover_text("Ctrl+Shift+Z").clicked() {
+                    engine.redo();
+                }
+                if ui.add_enabled(!done.is_empty() || !undone.is_empty(), egui::Button::new("Clear")).clicked() {
+                    engine.clear_history();
+                }
+            });
+            ui.weak(format!("{} of the last {} edits", done.len(), engine.undo_depth()));
+            ui.separator();
+            if done.is_empty() && undone.is_empty() {
+                ui.label("Nothing to undo yet");
+                return;
+            }
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                for description in undone.iter().rev() {
+                    ui.weak(description).on_hover_text("Undone, redo brings it back");
+                }
+                for (index, description) in done.iter().enumerate() {
+                    if index == 0 {
+                        ui.strong(description);
+                    } else {
+                        ui.label(description);
+                    }
+                }
+            });
+        });
+    }
+}
+
+// The selected light opens for editing, the others are one line each
+fn draw_point_lights(ui: &mut egui::Ui, engine: &mut EngineApi) {
+    let units = engine.units();
+    ui.horizontal(|ui| {
+        if ui.button("Place light").on_hover_text("Then click in the scene, hold Shift to place several. Shift+L does the same.").clicked() {
+            engine.begin_light_placement();
+        }
+        ui.label(format!("{} point lights", engine.point_lights().len()));
+    });
+    let selected = engine.selected_point_light();
+    for mut light in engine.point_lights() {
+        let is_selected = selected == Some(light.id);
+        let mut remove = false;
+        ui.horizontal(|ui| {
+            if ui.selectable_label(is_selected, format!("Light {}", light.id)).clicked() {
+                engine.select_point_light((!is_selected).then_some(light.id));
+            }
+            color::edit_srgb(ui, &mut light.color);
+            remove = ui.small_button("Remove").clicked();
+        });
+        if is_selected {
+            ui.indent(light.id, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Position ({})", units.suffix()));
+                    for axis in 0..3 {
+                        ui.add(egui::DragValue::new(&mut light.position[axis]).speed(0.05 * units.units_per_meter()));
+                    }
+                });
+                ui.add(egui::Slider::new(&mut light.intensity, 0.0..=8.0).text("Intensity"));
+                ui.add(egui::Slider::new(&mut light.range, 0.1 * units.units_per_meter()..=50.0 * units.units_per_meter()).logarithmic(true).text("Range"));
+                if ui.small_button("Copy as code").on_hover_text("The PointLight as a Rust struct literal, with `id` left to the caller").clicked() {
+                    engine.copy_as_code(ui.ctx(), "point light", light.to_rust_literal());
+                }
+            });
+        }
+        if remove {
+            engine.remove_point_light(light.id);
+        } else {
+            engine.set_point_light(light);
+        }
+    }
+}
@@ -0,0 +1,418 @@
+/*
+Purpose: HDR scene target, exposure and tonemapping
+Responsibilities:
+    - Own the tonemap and auto exposure pipelines (shared by every window)
+    - Own the per-window Rgba16Float scene target and the luminance chain
+    - Record auto exposure (luminance, downsample, adapt) and the final tonemap into the swapchain
+    - ex: the camera's light meter and film stock
+*/
+
+use crate::render_context::{fullscreen_pipeline, texture_entry};
+use crate::gpu_memory::{self, Tracked};
+use crate::gpu_layout::{UniformCheck, rust_layout};
+
+// The scene renders into this when HDR is on, the pipelines are built for it
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const LUMINANCE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+// Must match LUMINANCE_SIZE in hdr_luminance.wgsl, a power of two so every level halves exactly
+const LUMINANCE_SIZE: u32 = 256;
+const LUMINANCE_LEVELS: u32 = LUMINANCE_SIZE.ilog2() + 1;
+// Time constant of eye adaptation, ~95% adapted after one second
+const ADAPTATION_TAU: f32 = 1.0 / 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    Aces,
+    Reinhard,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 2] = [Tonemapper::Aces, Tonemapper::Reinhard];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Tonemapper::Aces => "ACES (approx.)",
+            Tonemapper::Reinhard => "Reinhard",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HdrSettings {
+    pub tonemapper: Tonemapper,
+    // Manual exposure in stops, also applied on top of auto exposure
+    pub exposure_ev: f32,
+    pub auto_exposure: bool,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure_ev: 0.0,
+            auto_exposure: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    auto_exposure: u32,
+    encode_srgb: u32,
+}
+
+pub const TONEMAP_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(TonemapUniform, [exposure, tonemapper, auto_exposure, encode_srgb]),
+    wgsl: &[("hdr_tonemap.wgsl", "TonemapUniform")],
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AdaptUniform {
+    rate: f32,
+    _padding: [f32; 3],
+}
+
+pub const ADAPT_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(AdaptUniform, [rate]),
+    wgsl: &[("hdr_adapt.wgsl", "AdaptUniform")],
+};
+
+// Pipelines shared by every window, only created when HDR is on
+pub struct HdrPipelines {
+    luminance_layout: wgpu::BindGroupLayout,
+    adapt_layout: wgpu::BindGroupLayout,
+    tonemap_layout: wgpu::BindGroupLayout,
+    luminance_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    adapt_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    // The swapchain isn't sRGB, so the tonemap shader encodes by hand
+    encode_srgb: bool,
+}
+
+impl HdrPipelines {
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let float = wgpu::TextureSampleType::Float { filterable: true };
+        let unfiltered = wgpu::TextureSampleType::Float { filterable: false };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let luminance_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, float),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Luminance Bind Group Layout"),
+        });
+        let adapt_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0, unfiltered), texture_entry(1, unfiltered), uniform_entry(2)],
+            label: Some("Adapt Bind Group Layout"),
+        });
+        let tonemap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0, unfiltered), uniform_entry(1), texture_entry(2, unfiltered)],
+            label: Some("Tonemap Bind Group Layout"),
+        });
+
+        let luminance_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Luminance Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("hdr_luminance.wgsl").into()),
+        });
+        let adapt_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Adapt Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("hdr_adapt.wgsl").into()),
+        });
+        let tonemap_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("hdr_tonemap.wgsl").into()),
+        });
+
+        let luminance_pipeline = fullscreen_pipeline(device, "Luminance Pipeline", &luminance_layout, &luminance_shader, "fs_log_luminance", LUMINANCE_FORMAT, None);
+        let downsample_pipeline = fullscreen_pipeline(device, "Luminance Downsample Pipeline", &luminance_layout, &luminance_shader, "fs_downsample", LUMINANCE_FORMAT, None);
+        let adapt_pipeline = fullscreen_pipeline(device, "Adapt Pipeline", &adapt_layout, &adapt_shader, "fs_adapt", LUMINANCE_FORMAT, None);
+        let tonemap_pipeline = fullscreen_pipeline(device, "Tonemap Pipeline", &tonemap_layout, &tonemap_shader, "fs_tonemap", output_format, None);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Luminance Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            luminance_layout,
+            adapt_layout,
+            tonemap_layout,
+            luminance_pipeline,
+            downsample_pipeline,
+            adapt_pipeline,
+            tonemap_pipeline,
+            sampler,
+            encode_srgb: !output_format.is_srgb(),
+        }
+    }
+}
+
+fn create_target(device: &wgpu::Device, width: u32, height: u32, mip_level_count: u32, format: wgpu::TextureFormat, label: &str) -> Tracked<wgpu::Texture> {
+    create_target_with_usage(device, width, height, mip_level_count, format, wgpu::TextureUsages::empty(), label)
+}
+
+fn create_target_with_usage(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+    mip_level_count: u32,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+    label: &str,
+) -> Tracked<wgpu::Texture> {
+    gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: usage | wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+// The HDR scene target and exposure state for one window, recreated whenever it resizes
+pub struct HdrTargets {
+    // Copied out by motion blur, which writes the blurred frame back
+    color: Tracked<wgpu::Texture>,
+    color_view: wgpu::TextureView,
+    // One view per level of the luminance chain, level 0 is LUMINANCE_SIZE square
+    luminance_views: Vec<wgpu::TextureView>,
+    // luminance_bind_groups[i] reads what level i is built from (the HDR frame for level 0)
+    luminance_bind_groups: Vec<wgpu::BindGroup>,
+    // Adapted log luminance is ping-ponged between two 1x1 targets
+    adapted_views: [wgpu::TextureView; 2],
+    adapt_bind_groups: [wgpu::BindGroup; 2],
+    tonemap_bind_groups: [wgpu::BindGroup; 2],
+    adapt_buffer: Tracked<wgpu::Buffer>,
+    tonemap_buffer: Tracked<wgpu::Buffer>,
+    // Which adapted target holds the latest value
+    current: usize,
+    // Snap instead of adapting, the stored value is stale
+    reset_adaptation: bool,
+    last_frame: std::time::Instant,
+}
+
+impl HdrTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &HdrPipelines, width: u32, height: u32) -> Self {
+        let color = create_target_with_usage(device, width, height, 1, HDR_FORMAT, wgpu::TextureUsages::COPY_SRC, "hdr_color");
+        let color_view = color.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let luminance_texture = create_target(device, LUMINANCE_SIZE, LUMINANCE_SIZE, LUMINANCE_LEVELS, LUMINANCE_FORMAT, "hdr_luminance");
+        let luminance_views = (0..LUMINANCE_LEVELS)
+            .map(|level| {
+                luminance_texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let luminance_bind_groups = (0..LUMINANCE_LEVELS as usize)
+            .map(|level| {
+                let source = if level == 0 { &color_view } else { &luminance_views[level - 1] };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pipelines.luminance_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&pipelines.sampler),
+                        },
+                    ],
+                    label: Some("Luminance Bind Group"),
+                })
+            })
+            .collect();
+
+        let adapted_views = [0, 1].map(|_| {
+            create_target(device, 1, 1, 1, LUMINANCE_FORMAT, "hdr_adapted_luminance")
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+
+        let adapt_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Adapt Buffer"),
+            size: std::mem::size_of::<AdaptUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let tonemap_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Tonemap Buffer"),
+            size: std::mem::size_of::<TonemapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // adapt_bind_groups[i] reads adapted target i (and writes the other one)
+        let average_view = luminance_views.last().unwrap();
+        let adapt_bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipelines.adapt_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(average_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&adapted_views[i]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: adapt_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("Adapt Bind Group"),
+            })
+        });
+        // tonemap_bind_groups[i] reads adapted target i
+        let tonemap_bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipelines.tonemap_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&color_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: tonemap_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&adapted_views[i]),
+                    },
+                ],
+                label: Some("Tonemap Bind Group"),
+            })
+        });
+
+        Self {
+            color,
+            color_view,
+            luminance_views,
+            luminance_bind_groups,
+            adapted_views,
+            adapt_bind_groups,
+            tonemap_bind_groups,
+            adapt_buffer,
+            tonemap_buffer,
+            current: 0,
+            reset_adaptation: true,
+            last_frame: std::time::Instant::now(),
+        }
+    }
+
+    // Where the scene pass draws (or resolves into) instead of the swapchain
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+
+    pub fn color_texture(&self) -> &wgpu::Texture {
+        &self.color
+    }
+
+    // Measure the frame if auto exposure is on, then tonemap it into target
+    pub fn encode_tonemap(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        pipelines: &HdrPipelines,
+        settings: &HdrSettings,
+        target: &wgpu::TextureView,
+    ) {
+        let now = std::time::Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        if settings.auto_exposure {
+            // 1. Log luminance of the frame, then halve down to a single texel
+            fullscreen_pass(encoder, "Luminance Pass", &self.luminance_views[0], &pipelines.luminance_pipeline, &self.luminance_bind_groups[0]);
+            for level in 1..self.luminance_views.len() {
+                fullscreen_pass(encoder, "Luminance Downsample Pass", &self.luminance_views[level], &pipelines.downsample_pipeline, &self.luminance_bind_groups[level]);
+            }
+
+            // 2. Ease the adapted value towards the new average
+            let rate = if self.reset_adaptation { 1.0 } else { 1.0 - (-dt / ADAPTATION_TAU).exp() };
+            self.reset_adaptation = false;
+            queue.write_buffer(&self.adapt_buffer, 0, bytemuck::cast_slice(&[AdaptUniform { rate, _padding: [0.0; 3] }]));
+            let next = 1 - self.current;
+            fullscreen_pass(encoder, "Adapt Pass", &self.adapted_views[next], &pipelines.adapt_pipeline, &self.adapt_bind_groups[self.current]);
+            self.current = next;
+        } else {
+            self.reset_adaptation = true;
+        }
+
+        // 3. Expose, tonemap and write to the swapchain
+        let uniform = TonemapUniform {
+            exposure: settings.exposure_ev.exp2(),
+            tonemapper: match settings.tonemapper {
+                Tonemapper::Aces => 0,
+                Tonemapper::Reinhard => 1,
+            },
+            auto_exposure: settings.auto_exposure as u32,
+            encode_srgb: pipelines.encode_srgb as u32,
+        };
+        queue.write_buffer(&self.tonemap_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        fullscreen_pass(encoder, "Tonemap Pass", target, &pipelines.tonemap_pipeline, &self.tonemap_bind_groups[self.current]);
+    }
+}
@@ -0,0 +1,38 @@
+/*
+Purpose: Eye adaptation for auto exposure
+Responsibilites:
+    - Move last frame's adapted log luminance towards this frame's average
+*/
+
+struct AdaptUniform {
+    // 0..1, how far to move this frame (1 snaps straight to the average)
+    rate: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// 1x1 level of the luminance chain
+@group(0) @binding(0)
+var t_average: texture_2d<f32>;
+@group(0) @binding(1)
+var t_previous: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> adapt: AdaptUniform;
+
+@fragment
+fn fs_adapt(in: VertexOutput) -> @location(0) vec4<f32> {
+    let current = textureLoad(t_average, vec2<i32>(0), 0).r;
+    let previous = textureLoad(t_previous, vec2<i32>(0), 0).r;
+    return vec4<f32>(mix(previous, current, adapt.rate), 0.0, 0.0, 1.0);
+}
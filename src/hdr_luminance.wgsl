@@ -0,0 +1,50 @@
+/*
+Purpose: Average scene luminance for auto exposure
+Responsibilites:
+    - fs_log_luminance: reduce the HDR frame to a 256x256 grid of log luminance
+    - fs_downsample: halve the grid, one bilinear tap averages a 2x2 block
+*/
+
+// Must match LUMINANCE_SIZE in hdr.rs
+const LUMINANCE_SIZE: f32 = 256.0;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+fn log_luminance(color: vec3<f32>) -> f32 {
+    return log(max(dot(color, vec3<f32>(0.2126, 0.7152, 0.0722)), 1e-4));
+}
+
+@fragment
+fn fs_log_luminance(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Four taps spread over the part of the frame this texel covers
+    let offset = 0.25 / LUMINANCE_SIZE;
+    var sum = 0.0;
+    sum += log_luminance(textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(-offset, -offset), 0.0).rgb);
+    sum += log_luminance(textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(offset, -offset), 0.0).rgb);
+    sum += log_luminance(textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(-offset, offset), 0.0).rgb);
+    sum += log_luminance(textureSampleLevel(t_source, s_source, in.uv + vec2<f32>(offset, offset), 0.0).rgb);
+    return vec4<f32>(sum * 0.25, 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSampleLevel(t_source, s_source, in.uv, 0.0).r, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,82 @@
+/*
+Purpose: Map the HDR frame into display range
+Responsibilites:
+    - Apply manual exposure (EV) and, optionally, auto exposure from the adapted luminance
+    - Tonemap with the ACES fit or Reinhard
+    - Encode to sRGB by hand when the swapchain format doesn't do it
+*/
+
+// Middle grey that auto exposure aims the average luminance at
+const KEY_VALUE: f32 = 0.18;
+
+struct TonemapUniform {
+    // 2^EV
+    exposure: f32,
+    // 0 = ACES, 1 = Reinhard
+    tonemapper: u32,
+    auto_exposure: u32,
+    encode_srgb: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: TonemapUniform;
+@group(0) @binding(2)
+var t_adapted: texture_2d<f32>;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+    return x / (vec3<f32>(1.0) + x);
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(t_hdr, vec2<i32>(in.clip_position.xy), 0).rgb;
+
+    var exposure = tonemap.exposure;
+    if (tonemap.auto_exposure != 0u) {
+        let average = exp(textureLoad(t_adapted, vec2<i32>(0), 0).r);
+        exposure *= KEY_VALUE / max(average, 1e-4);
+    }
+
+    let exposed = hdr * exposure;
+    var mapped: vec3<f32>;
+    if (tonemap.tonemapper == 0u) {
+        mapped = aces(exposed);
+    } else {
+        mapped = reinhard(exposed);
+    }
+    if (tonemap.encode_srgb != 0u) {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4<f32>(mapped, 1.0);
+}
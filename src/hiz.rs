@@ -0,0 +1,201 @@
+/*
+Purpose: The hierarchical Z pyramid behind occlusion culling
+Responsibilities:
+    - Own a window's pyramid: an R32Uint texture of depth bits with a full mip chain, level 0 the
+      size of the window, each level after half the one before down to 1x1
+    - Build it from the depth pre-pass's depth once that is drawn: copy the depth in, then keep
+      the farthest depth of every 2x2 texels in the next level. A texel of level n is then the
+      farthest depth anywhere under it, so one to four reads bound a rect of any size on screen.
+      Fullscreen passes rather than compute, the GL backend drops storage writes to one level
+      while another is sampled.
+    - Hand the GPU cull its bind group, instance_cull.wgsl's cs_occlusion reads the levels
+    - Build the occluder wall test scene's model, a plain box
+    - ex: a relief map, each zoomed out sheet keeps the deepest valley under every square
+*/
+
+use crate::{
+    gpu_debug::debug_label,
+    gpu_memory::{self, Tracked},
+    instance_cull::InstanceCullPipeline,
+    model::{self, ShadingModel},
+    render_context::{RenderContext, fullscreen_pipeline},
+    resources, shapes, texture,
+};
+
+// Depths' bits, R32Float isn't renderable on downlevel backends
+const HIZ_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// Shared between windows, lives in the RenderContext. None there without GPU culling.
+pub struct HiZPipelines {
+    copy_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+}
+
+impl HiZPipelines {
+    // `depth_sample_count` is the sample count of the windows' depth textures
+    pub fn new(device: &wgpu::Device, depth_sample_count: u32) -> Self {
+        let read_entry = |binding, sample_type, multisampled| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled,
+            },
+            count: None,
+        };
+        let multisampled = depth_sample_count > 1;
+        let copy_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[read_entry(0, wgpu::TextureSampleType::Float { filterable: false }, multisampled)],
+            label: Some("HiZ Copy Bind Group Layout"),
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[read_entry(1, wgpu::TextureSampleType::Uint, false)],
+            label: Some("HiZ Downsample Bind Group Layout"),
+        });
+
+        let mut source = include_str!("hiz.wgsl").to_string();
+        if multisampled {
+            source = source
+                .replace("var t_depth: texture_2d<f32>", "var t_depth: texture_multisampled_2d<f32>")
+                .replace("const DEPTH_SAMPLES: i32 = 1;", &format!("const DEPTH_SAMPLES: i32 = {};", depth_sample_count));
+        }
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("HiZ Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        Self {
+            copy_pipeline: fullscreen_pipeline(device, "HiZ Copy Pipeline", &copy_layout, &shader, "fs_copy_depth", HIZ_FORMAT, None),
+            downsample_pipeline: fullscreen_pipeline(device, "HiZ Downsample Pipeline", &downsample_layout, &shader, "fs_downsample", HIZ_FORMAT, None),
+            copy_layout,
+            downsample_layout,
+        }
+    }
+}
+
+// A window's pyramid, made for its depth texture and again whenever that is
+pub struct HiZTargets {
+    _texture: Tracked<wgpu::Texture>,
+    size: [u32; 2],
+    levels: u32,
+    // One per level, what each pass draws into
+    level_views: Vec<wgpu::TextureView>,
+    copy_bind_group: wgpu::BindGroup,
+    // Level n + 1 from level n
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    // Every level, for the cull
+    cull_bind_group: wgpu::BindGroup,
+}
+
+impl HiZTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &HiZPipelines, cull: &InstanceCullPipeline, depth_texture: &wgpu::Texture) -> Self {
+        let size = [depth_texture.width(), depth_texture.height()];
+        let levels = 32 - size[0].max(size[1]).max(1).leading_zeros();
+        let texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("HiZ Pyramid"),
+            size: wgpu::Extent3d { width: size[0], height: size[1], depth_or_array_layers: 1 },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HIZ_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let level_view = |level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: debug_label!("HiZ Level {}", level).as_deref(),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+        let level_views = (0..levels).map(level_view).collect::<Vec<_>>();
+
+        let depth_view = texture::Texture::create_depth_read_view(depth_texture, "HiZ Depth View");
+        let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.copy_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&depth_view) },
+            ],
+            label: Some("HiZ Copy Bind Group"),
+        });
+        let downsample_bind_groups = level_views
+            .windows(2)
+            .map(|views| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &pipelines.downsample_layout,
+                    entries: &[wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&views[0]) }],
+                    label: Some("HiZ Downsample Bind Group"),
+                })
+            })
+            .collect();
+        let all_levels = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: cull.occlusion_layout(),
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&all_levels) }],
+            label: Some("HiZ Cull Bind Group"),
+        });
+        Self { _texture: texture, size, levels, level_views, copy_bind_group, downsample_bind_groups, cull_bind_group }
+    }
+
+    // Level 0's size, the window's
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.levels
+    }
+
+    pub fn cull_bind_group(&self) -> &wgpu::BindGroup {
+        &self.cull_bind_group
+    }
+
+    // After the depth pre-pass and before the cull that reads it
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, pipelines: &HiZPipelines) {
+        let passes = std::iter::once((&pipelines.copy_pipeline, &self.copy_bind_group))
+            .chain(self.downsample_bind_groups.iter().map(|bind_group| (&pipelines.downsample_pipeline, bind_group)));
+        for ((pipeline, bind_group), view) in passes.zip(&self.level_views) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("HiZ Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+// A unit box, the test scene scales its instance into a wall
+pub fn wall_model(context: &RenderContext) -> anyhow::Result<model::Model> {
+    let (shape_vertices, indices) = shapes::create_cube();
+    let mesh = resources::mesh_from_shape(&context.device, "occluder_wall", &shape_vertices, &indices);
+    let pixel = |rgba| image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)));
+    let material = model::Material::new(
+        &context.device,
+        "occluder_wall",
+        texture::Texture::from_image(&context.device, &context.queue, &pixel([150, 140, 130, 255]), Some("occluder_wall_diffuse"), false)?,
+        texture::Texture::from_image(&context.device, &context.queue, &pixel([128, 128, 255, 255]), Some("occluder_wall_normal"), true)?,
+        &context.texture_bind_group_layout,
+        model::MaterialParams { shading_model: ShadingModel::BlinnPhong, ..Default::default() },
+    );
+    Ok(model::Model {
+        meshes: vec![mesh],
+        materials: vec![material],
+        optimize_stats: None,
+        packed: None,
+    })
+}
@@ -0,0 +1,61 @@
+/*
+Purpose: Build the hierarchical Z pyramid the occlusion cull tests against
+Responsibilites:
+    - fs_copy_depth: level 0 is the depth pre-pass's depth, the farthest of a pixel's samples
+      when it is multisampled
+    - fs_downsample: each level after holds the farthest depth of the 2x2 texels under it, plus
+      the row or column the halving drops off a level with an odd size
+*/
+
+// Fullscreen triangle, no vertex buffer needed
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Standard Z, the farthest depth is the largest. hiz.rs sets the sample count for MSAA.
+// Levels hold a depth's bits, R32Float can't be drawn into everywhere. Depths are never negative
+// so the bits sort like the floats.
+const DEPTH_SAMPLES: i32 = 1;
+
+// Copy pass binding
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+// Downsample pass binding, the level before the one being drawn
+@group(0) @binding(1)
+var t_previous: texture_2d<u32>;
+
+@fragment
+fn fs_copy_depth(in: VertexOutput) -> @location(0) vec4<u32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    var farthest = 0.0;
+    for (var s = 0; s < DEPTH_SAMPLES; s++) {
+        // The sample index when multisampled, the mip level (0) otherwise
+        farthest = max(farthest, textureLoad(t_depth, pixel, s).r);
+    }
+    return vec4<u32>(bitcast<u32>(farthest), 0u, 0u, 1u);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<u32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let previous = vec2<i32>(textureDimensions(t_previous));
+    let first = pixel * 2;
+    // A level's size is rounded down, its last texel also covers what didn't fit
+    let at_edge = pixel == max(previous / 2, vec2<i32>(1)) - 1;
+    let last = select(first + 1, previous - 1, at_edge);
+    var farthest = 0u;
+    for (var y = first.y; y <= last.y; y++) {
+        for (var x = first.x; x <= last.x; x++) {
+            farthest = max(farthest, textureLoad(t_previous, vec2<i32>(x, y), 0).r);
+        }
+    }
+    return vec4<u32>(farthest, 0u, 0u, 1u);
+}
@@ -0,0 +1,117 @@
+/*
+Purpose: Fix up models exported with another tool's conventions while they load
+Responsibilities:
+    - Scale, turn Z-up or X-up models to the engine's Y-up, flip V and reverse the winding,
+      applied by resources::load_model while it builds the vertices
+    - Remember the last options per file extension in the settings file
+    - Draw the options for the import dialog
+    - ex: the travel adapter that makes a foreign plug fit the socket
+*/
+
+use cgmath::{Matrix3, Vector3};
+
+use crate::user_settings::UserSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+    X,
+}
+
+impl UpAxis {
+    pub const ALL: [UpAxis; 3] = [UpAxis::Y, UpAxis::Z, UpAxis::X];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            UpAxis::Y => "Y up",
+            UpAxis::Z => "Z up",
+            UpAxis::X => "X up",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            UpAxis::Y => "y",
+            UpAxis::Z => "z",
+            UpAxis::X => "x",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|axis| axis.key() == key)
+    }
+
+    // A rotation taking this axis to +Y, so the handedness (and the winding) stays the same
+    fn to_y_up(self) -> Matrix3<f32> {
+        match self {
+            UpAxis::Y => Matrix3::from_cols(Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()),
+            // -90 degrees around X: (x, y, z) -> (x, z, -y)
+            UpAxis::Z => Matrix3::from_cols(Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y()),
+            // 90 degrees around Z: (x, y, z) -> (-y, x, z)
+            UpAxis::X => Matrix3::from_cols(Vector3::unit_y(), -Vector3::unit_x(), Vector3::unit_z()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportOptions {
+    // Uniform, 0.01 brings centimeters to meters
+    pub scale: f32,
+    pub up_axis: UpAxis,
+    // For files whose V already runs top to bottom
+    pub flip_v: bool,
+    // For files wound clockwise, whose faces would otherwise be culled from the outside
+    pub flip_winding: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { scale: 1.0, up_axis: UpAxis::Y, flip_v: false, flip_winding: false }
+    }
+}
+
+impl ImportOptions {
+    pub fn transform_position(&self, position: [f32; 3]) -> [f32; 3] {
+        (self.up_axis.to_y_up() * Vector3::from(position) * self.scale).into()
+    }
+
+    // The scale is uniform and positive, so only the axis conversion turns normals
+    pub fn transform_normal(&self, normal: [f32; 3]) -> [f32; 3] {
+        (self.up_axis.to_y_up() * Vector3::from(normal)).into()
+    }
+
+    // Lowercase, without the dot, "" for files without one
+    pub fn extension_of(path: &str) -> String {
+        std::path::Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default()
+    }
+
+    // The last options used for files with this extension, defaults for anything missing
+    pub fn from_settings(settings: &UserSettings, extension: &str) -> Self {
+        let default = Self::default();
+        Self {
+            scale: settings.parse(&format!("import.{}.scale", extension)).filter(|scale: &f32| *scale > 0.0).unwrap_or(default.scale),
+            up_axis: settings.get(&format!("import.{}.up_axis", extension)).and_then(UpAxis::from_key).unwrap_or(default.up_axis),
+            flip_v: settings.parse(&format!("import.{}.flip_v", extension)).unwrap_or(default.flip_v),
+            flip_winding: settings.parse(&format!("import.{}.flip_winding", extension)).unwrap_or(default.flip_winding),
+        }
+    }
+
+    pub fn write_settings(&self, settings: &mut UserSettings, extension: &str) {
+        settings.set(&format!("import.{}.scale", extension), self.scale);
+        settings.set(&format!("import.{}.up_axis", extension), self.up_axis.key());
+        settings.set(&format!("import.{}.flip_v", extension), self.flip_v);
+        settings.set(&format!("import.{}.flip_winding", extension), self.flip_winding);
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::DragValue::new(&mut self.scale).speed(0.01).range(0.0001..=1000.0).prefix("Scale "));
+        egui::ComboBox::from_label("Up axis").selected_text(self.up_axis.label()).show_ui(ui, |ui| {
+            for axis in UpAxis::ALL {
+                ui.selectable_value(&mut self.up_axis, axis, axis.label());
+            }
+        });
+        ui.checkbox(&mut self.flip_v, "Flip V").on_hover_text("For textures that come out upside down");
+        ui.checkbox(&mut self.flip_winding, "Flip winding").on_hover_text("For models that show their insides");
+    }
+}
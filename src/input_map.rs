@@ -0,0 +1,300 @@
+/*
+Purpose: Every keyboard shortcut in one table
+Responsibilities:
+    - Name each action with a description and a category, for the shortcut help overlay
+    - Turn key presses (key plus Ctrl/Shift) into actions, and held keys into camera moves
+    - Find conflicts, two actions on one key in the same context
+    - ex: the legend printed on the back of a remote control
+*/
+
+use std::collections::HashMap;
+use winit::keyboard::{KeyCode, ModifiersState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    Camera,
+    Rendering,
+    Editor,
+    Debug,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [Category::Camera, Category::Rendering, Category::Editor, Category::Debug];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Camera => "Camera",
+            Category::Rendering => "Rendering",
+            Category::Editor => "Editor",
+            Category::Debug => "Debug",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    CloseWindow,
+    ToggleCursorLock,
+    ToggleMenu,
+    ToggleHelp,
+    ToggleConsole,
+    ToggleFrameStats,
+    SaveDepth,
+    OpenInspector,
+    Duplicate,
+    Despawn,
+    Undo,
+    Redo,
+    FrameSelection,
+    FrameModel,
+    GizmoMove,
+    GizmoRotate,
+    GizmoScale,
+    PlaceLight,
+    Measure,
+    PasteTexture,
+    CameraFreeFly,
+    CameraOrbit,
+    CameraTopDown,
+    CameraWalk,
+    ResetRoll,
+    // Held, see InputMap::held
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    TurnRight,
+    RollLeft,
+    RollRight,
+    Sprint,
+    SlowMove,
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::CloseWindow => "Close the window, the main window quits",
+            Action::ToggleCursorLock => "Lock or free the mouse cursor",
+            Action::ToggleMenu => "Show or hide the menu",
+            Action::ToggleHelp => "Show or hide this list",
+            Action::ToggleConsole => "Open or close the command console",
+            Action::ToggleFrameStats => "Frame pacing graph",
+            Action::SaveDepth => "Save the depth buffer as a PNG",
+            Action::OpenInspector => "Open an inspector window",
+            Action::Duplicate => "Duplicate the selected instance",
+            Action::Despawn => "Despawn the selected instance",
+            Action::Undo => "Undo the last edit, see the History window",
+            Action::Redo => "Redo the last undone edit",
+            Action::FrameSelection => "Frame the selection, or everything",
+            Action::FrameModel => "Frame every instance of the selected model",
+            Action::GizmoMove => "Move gizmo",
+            Action::GizmoRotate => "Rotate gizmo",
+            Action::GizmoScale => "Scale gizmo",
+            Action::PlaceLight => "Place a point light with the next click, hold Shift to place several",
+            Action::Measure => "Measure distances, click the start and then the end",
+            Action::PasteTexture => "Paste the clipboard's image onto the selected model",
+            Action::CameraFreeFly => "Free-fly camera controls",
+            Action::CameraOrbit => "Orbit camera controls",
+            Action::CameraTopDown => "Top-down camera controls, WASD pans and Q/E turn",
+            Action::CameraWalk => "Walking camera controls, Space jumps",
+            Action::ResetRoll => "Level the camera's horizon",
+            Action::MoveForward => "Fly forward",
+            Action::MoveBackward => "Fly backward",
+            Action::MoveLeft => "Strafe left",
+            Action::MoveRight => "Strafe right",
+            Action::MoveUp => "Fly up",
+            Action::MoveDown => "Fly down, turn left in the top-down camera",
+            Action::TurnRight => "Turn right in the top-down camera",
+            Action::RollLeft => "Roll left in the free-fly camera",
+            Action::RollRight => "Roll right in the free-fly camera",
+            Action::Sprint => "Fly faster while held",
+            Action::SlowMove => "Fly slower while held",
+        }
+    }
+
+    pub fn category(self) -> Category {
+        match self {
+            Action::CloseWindow | Action::ToggleCursorLock | Action::ToggleMenu | Action::ToggleHelp | Action::OpenInspector => Category::Editor,
+            Action::Duplicate | Action::Despawn | Action::Undo | Action::Redo | Action::GizmoMove | Action::GizmoRotate | Action::GizmoScale => Category::Editor,
+            Action::PlaceLight | Action::Measure | Action::PasteTexture => Category::Editor,
+            Action::ToggleConsole | Action::ToggleFrameStats | Action::SaveDepth => Category::Debug,
+            Action::FrameSelection | Action::FrameModel => Category::Camera,
+            Action::CameraFreeFly | Action::CameraOrbit | Action::CameraTopDown | Action::CameraWalk | Action::ResetRoll => Category::Camera,
+            Action::MoveForward | Action::MoveBackward | Action::MoveLeft | Action::MoveRight | Action::MoveUp | Action::MoveDown => Category::Camera,
+            Action::TurnRight | Action::RollLeft | Action::RollRight => Category::Camera,
+            Action::Sprint | Action::SlowMove => Category::Camera,
+        }
+    }
+
+    // Act while the key is down instead of once per press
+    fn is_held(self) -> bool {
+        matches!(
+            self,
+            Action::MoveForward
+                | Action::MoveBackward
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveUp
+                | Action::MoveDown
+                | Action::TurnRight
+                | Action::RollLeft
+                | Action::RollRight
+                | Action::Sprint
+                | Action::SlowMove
+        )
+    }
+}
+
+// When a binding applies. A contextual binding wins over an Always one on the same key while
+// its context holds, so only bindings in the same context conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum When {
+    Always,
+    // An instance is selected and the cursor is free to drag its handles
+    Selection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub key: KeyCode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub when: When,
+}
+
+impl Binding {
+    fn key(key: KeyCode) -> Self {
+        Self { key, ctrl: false, shift: false, when: When::Always }
+    }
+
+    fn ctrl(key: KeyCode) -> Self {
+        Self { ctrl: true, ..Self::key(key) }
+    }
+
+    fn shift(key: KeyCode) -> Self {
+        Self { shift: true, ..Self::key(key) }
+    }
+
+    fn ctrl_shift(key: KeyCode) -> Self {
+        Self { ctrl: true, ..Self::shift(key) }
+    }
+
+    fn with_selection(key: KeyCode) -> Self {
+        Self { when: When::Selection, ..Self::key(key) }
+    }
+
+    // "Ctrl+D", "Shift+F12", "Home"
+    pub fn label(&self) -> String {
+        let name = format!("{:?}", self.key);
+        let name = name.strip_prefix("Key").or_else(|| name.strip_prefix("Digit")).unwrap_or(&name);
+        let name = match self.key {
+            KeyCode::Slash if self.shift => "?",
+            KeyCode::ShiftLeft => "Left Shift",
+            KeyCode::ShiftRight => "Right Shift",
+            KeyCode::ControlLeft => "Left Ctrl",
+            KeyCode::ControlRight => "Right Ctrl",
+            _ => name,
+        };
+        let mut label = String::new();
+        if self.ctrl {
+            label.push_str("Ctrl+");
+        }
+        if self.shift && self.key != KeyCode::Slash {
+            label.push_str("Shift+");
+        }
+        label + name
+    }
+
+    fn matches(&self, key: KeyCode, modifiers: ModifiersState) -> bool {
+        self.key == key && self.ctrl == modifiers.control_key() && self.shift == modifiers.shift_key()
+    }
+}
+
+pub struct InputMap {
+    bindings: Vec<(Binding, Action)>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use KeyCode::*;
+        let bindings = vec![
+            (Binding::key(Escape), Action::CloseWindow),
+            (Binding::key(KeyL), Action::ToggleCursorLock),
+            (Binding::shift(KeyL), Action::PlaceLight),
+            (Binding::key(KeyM), Action::Measure),
+            (Binding::key(KeyT), Action::ToggleMenu),
+            (Binding::key(F1), Action::ToggleHelp),
+            (Binding::shift(Slash), Action::ToggleHelp),
+            (Binding::key(Backquote), Action::ToggleConsole),
+            (Binding::key(F3), Action::ToggleFrameStats),
+            (Binding::shift(F12), Action::SaveDepth),
+            (Binding::key(KeyI), Action::OpenInspector),
+            (Binding::ctrl(KeyD), Action::Duplicate),
+            (Binding::key(Delete), Action::Despawn),
+            (Binding::ctrl(KeyZ), Action::Undo),
+            (Binding::ctrl_shift(KeyZ), Action::Redo),
+            (Binding::ctrl(KeyV), Action::PasteTexture),
+            (Binding::key(KeyF), Action::FrameSelection),
+            (Binding::key(Home), Action::FrameModel),
+            (Binding::key(Digit1), Action::CameraFreeFly),
+            (Binding::key(Digit2), Action::CameraOrbit),
+            (Binding::key(Digit3), Action::CameraTopDown),
+            (Binding::key(Digit4), Action::CameraWalk),
+            (Binding::key(Slash), Action::ResetRoll),
+            (Binding::with_selection(KeyW), Action::GizmoMove),
+            (Binding::with_selection(KeyE), Action::GizmoRotate),
+            (Binding::with_selection(KeyR), Action::GizmoScale),
+            (Binding::key(KeyW), Action::MoveForward),
+            (Binding::key(ArrowUp), Action::MoveForward),
+            (Binding::key(KeyS), Action::MoveBackward),
+            (Binding::key(ArrowDown), Action::MoveBackward),
+            (Binding::key(KeyA), Action::MoveLeft),
+            (Binding::key(ArrowLeft), Action::MoveLeft),
+            (Binding::key(KeyD), Action::MoveRight),
+            (Binding::key(ArrowRight), Action::MoveRight),
+            (Binding::key(Space), Action::MoveUp),
+            (Binding::key(KeyQ), Action::MoveDown),
+            (Binding::key(KeyE), Action::TurnRight),
+            (Binding::key(Comma), Action::RollLeft),
+            (Binding::key(Period), Action::RollRight),
+            (Binding::key(ShiftLeft), Action::Sprint),
+            (Binding::key(ShiftRight), Action::Sprint),
+            (Binding::key(ControlLeft), Action::SlowMove),
+            (Binding::key(ControlRight), Action::SlowMove),
+        ];
+        Self { bindings }
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self) -> &[(Binding, Action)] {
+        &self.bindings
+    }
+
+    // The one-shot action for a press, contextual bindings first while `selection` holds
+    pub fn pressed(&self, key: KeyCode, modifiers: ModifiersState, selection: bool) -> Option<Action> {
+        let matching = |when: When| {
+            self.bindings
+                .iter()
+                .find(|(binding, action)| !action.is_held() && binding.when == when && binding.matches(key, modifiers))
+                .map(|(_, action)| *action)
+        };
+        selection.then(|| matching(When::Selection)).flatten().or_else(|| matching(When::Always))
+    }
+
+    // The camera move a key drives while it is down. Modifiers are ignored, Shift and Ctrl are keys here.
+    pub fn held(&self, key: KeyCode) -> Option<Action> {
+        self.bindings.iter().find(|(binding, action)| action.is_held() && binding.key == key).map(|(_, action)| *action)
+    }
+
+    // Bindings that share a key, modifiers and context with another action's binding
+    pub fn conflicts(&self) -> Vec<Binding> {
+        let mut actions: HashMap<Binding, Vec<Action>> = HashMap::new();
+        for (binding, action) in &self.bindings {
+            actions.entry(*binding).or_default().push(*action);
+        }
+        actions.into_iter().filter(|(_, actions)| actions.len() > 1).map(|(binding, _)| binding).collect()
+    }
+}
@@ -1,13 +1,174 @@
 
-use crate::model;
+use crate::{gpu_layout::{VertexCheck, rust_layout}, math::{Aabb, Vec3}, model, texture::Atlas};
+use cgmath::{InnerSpace, Matrix, One, Rotation, Rotation3, SquareMatrix, Zero};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::f32::consts::{PI, TAU};
 
 
 
 // Describing each instance
+#[derive(Clone)]
 pub struct Instance {
     pub initial_position: cgmath::Vector3<f32>,
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    // Per axis, applied before the rotation. Keep it away from zero, see clamp_scale.
+    pub scale: cgmath::Vector3<f32>,
+    // Unit axis the instance spins around on top of rotation, and how fast (degrees per second)
+    pub spin_axis: cgmath::Vector3<f32>,
+    pub spin_speed: f32,
+    // Offset (xy) and scale (zw) applied to tex_coords, used to pick a region of a texture atlas
+    pub uv_transform: [f32; 4],
+}
+
+// Most instances a layout generates, bigger grids are refused on the command line and clamped here
+pub const MAX_INSTANCES: u32 = 1_000_000;
+
+// Smallest size an axis can be scaled to, a zero scale has no inverse for the normal matrix
+pub const MIN_INSTANCE_SCALE: f32 = 1e-3;
+
+// Pushes every axis at least MIN_INSTANCE_SCALE away from zero, keeping its sign (negative mirrors)
+pub fn clamp_scale(scale: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let clamp = |s: f32| if s.abs() < MIN_INSTANCE_SCALE { MIN_INSTANCE_SCALE.copysign(s) } else { s };
+    cgmath::Vector3::new(clamp(scale.x), clamp(scale.y), clamp(scale.z))
+}
+
+// How the default scene's instances are laid out, picked in the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Grid,
+    Ring,
+    Line,
+    RandomBox,
+    RandomSphere,
+}
+
+impl Distribution {
+    pub const ALL: [Distribution; 5] = [Distribution::Grid, Distribution::Ring, Distribution::Line, Distribution::RandomBox, Distribution::RandomSphere];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Distribution::Grid => "Grid",
+            Distribution::Ring => "Ring",
+            Distribution::Line => "Line",
+            Distribution::RandomBox => "Random in box",
+            Distribution::RandomSphere => "Random on sphere",
+        }
+    }
+
+    // As many instances as a columns x rows grid, spread so neighbours stay about `spacing` apart
+    pub fn generate(self, columns: u32, rows: u32, spacing: f32, seed: u64) -> Vec<Instance> {
+        let rows = rows.min(MAX_INSTANCES / columns.max(1));
+        let count = columns.min(MAX_INSTANCES) * rows;
+        let n = count as f32;
+        match self {
+            Distribution::Grid => generate_grid(columns.min(MAX_INSTANCES), rows, spacing),
+            Distribution::Ring => generate_ring(count, spacing * n / TAU, true),
+            Distribution::Line => {
+                let half = Vec3::unit_x() * spacing * (n - 1.0).max(0.0) / 2.0;
+                generate_line(count, -half, half)
+            }
+            Distribution::RandomBox => {
+                let half = spacing * n.cbrt() / 2.0;
+                generate_random_in_box(count, Aabb::new(Vec3::new(-half, -half, -half), Vec3::new(half, half, half)), seed)
+            }
+            Distribution::RandomSphere => generate_random_on_sphere(count, spacing * (n / (4.0 * PI)).sqrt(), seed),
+        }
+    }
+}
+
+impl Instance {
+    // Unscaled, showing the whole texture and not spinning until spin_speed is set
+    pub fn placed(position: Vec3, rotation: cgmath::Quaternion<f32>, spin_axis: Vec3) -> Self {
+        Self {
+            initial_position: Vec3::zero(),
+            position,
+            rotation,
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            spin_axis,
+            spin_speed: 0.0,
+            uv_transform: Atlas::FULL_RECT,
+        }
+    }
+
+    // Turned so its +Z faces along `direction`, spinning around that same axis
+    fn facing(position: Vec3, direction: Vec3) -> Self {
+        if direction.magnitude2() < f32::EPSILON {
+            return Self::placed(position, cgmath::Quaternion::one(), Vec3::unit_z());
+        }
+        let direction = direction.normalize();
+        Self::placed(position, cgmath::Quaternion::between_vectors(Vec3::unit_z(), direction), direction)
+    }
+}
+
+// Centered on the origin in the XZ plane, each instance tilted 45 degrees around the direction to its cell
+pub fn generate_grid(columns: u32, rows: u32, spacing: f32) -> Vec<Instance> {
+    (0..rows).flat_map(|z| {
+        (0..columns).map(move |x| {
+            let x = spacing * (x as f32 - columns as f32 / 2.0);
+            let z = spacing * (z as f32 - rows as f32 / 2.0);
+            let position = if columns == 1 && rows == 1 { Vec3::zero() } else { Vec3::new(x, 0.0, z) };
+            if position.is_zero() {
+                Instance::placed(position, cgmath::Quaternion::one(), Vec3::unit_z())
+            } else {
+                let axis = position.normalize();
+                Instance::placed(position, cgmath::Quaternion::from_axis_angle(axis, cgmath::Deg(45.0)), axis)
+            }
+        })
+    }).collect()
+}
+
+// Evenly around a circle in the XZ plane, first instance on +X. A zero radius stacks them on the origin.
+pub fn generate_ring(count: u32, radius: f32, facing_center: bool) -> Vec<Instance> {
+    (0..count).map(|i| {
+        let angle = TAU * i as f32 / count as f32;
+        let position = Vec3::new(angle.cos(), 0.0, angle.sin()) * radius;
+        if facing_center {
+            Instance::facing(position, -position)
+        } else {
+            Instance::placed(position, cgmath::Quaternion::one(), Vec3::unit_y())
+        }
+    }).collect()
+}
+
+// Evenly from start to end inclusive, facing along the line. A single instance sits halfway.
+pub fn generate_line(count: u32, start: Vec3, end: Vec3) -> Vec<Instance> {
+    (0..count).map(|i| {
+        let t = if count == 1 { 0.5 } else { i as f32 / (count - 1) as f32 };
+        Instance::facing(start + (end - start) * t, end - start)
+    }).collect()
+}
+
+// Uniform positions inside the box and uniform random rotations, the same seed gives the same instances
+pub fn generate_random_in_box(count: u32, aabb: Aabb, seed: u64) -> Vec<Instance> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count).map(|_| {
+        // Lerp instead of a gen_range per axis, a flat or inverted box would make that range empty
+        let mut axis = |min: f32, max: f32| min + (max - min) * rng.gen_range(0.0..1.0);
+        let position = Vec3::new(axis(aabb.min.x, aabb.max.x), axis(aabb.min.y, aabb.max.y), axis(aabb.min.z, aabb.max.z));
+        let rotation = random_rotation(&mut rng);
+        Instance::placed(position, rotation, rotation.rotate_vector(Vec3::unit_z()))
+    }).collect()
+}
+
+// Uniform over the sphere's surface around the origin, facing outwards
+pub fn generate_random_on_sphere(count: u32, radius: f32, seed: u64) -> Vec<Instance> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count).map(|_| {
+        // Uniform height and angle gives a uniform surface (Archimedes)
+        let y: f32 = rng.gen_range(-1.0..=1.0);
+        let angle = rng.gen_range(0.0..TAU);
+        let ring = (1.0 - y * y).sqrt();
+        let normal = Vec3::new(ring * angle.cos(), y, ring * angle.sin());
+        Instance::facing(normal * radius.abs(), normal)
+    }).collect()
+}
+
+// Uniform over all orientations (Shoemake)
+fn random_rotation(rng: &mut StdRng) -> cgmath::Quaternion<f32> {
+    let (u1, u2, u3): (f32, f32, f32) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+    let (a, b) = ((1.0 - u1).sqrt(), u1.sqrt());
+    cgmath::Quaternion::new(b * (TAU * u3).cos(), a * (TAU * u2).sin(), a * (TAU * u2).cos(), b * (TAU * u3).sin())
 }
 
 // To avoid writing the math in the shader, we will store Instance data into a matrix
@@ -19,22 +180,41 @@ pub struct Instance {
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
+    uv_transform: [f32; 4],
 }
 
+pub const INSTANCE_RAW_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(InstanceRaw, [model, normal, uv_transform]),
+    desc: <InstanceRaw as model::Vertex>::desc,
+};
+
 // Create method to convert Instance to InstanceRaw
 impl Instance {
-    pub fn to_raw(&self) -> InstanceRaw {
+    // time is in seconds, instance_anim.wgsl does the same math on the GPU
+    pub fn to_raw(&self, time: f32) -> InstanceRaw {
         let combined_position = self.initial_position + self.position;
-        let model = cgmath::Matrix4::from_translation(combined_position) * cgmath::Matrix4::from(self.rotation);
+        let rotation = cgmath::Quaternion::from_axis_angle(self.spin_axis, cgmath::Deg(self.spin_speed * time)) * self.rotation;
+        let scale = clamp_scale(self.scale);
+        let linear = cgmath::Matrix3::from(rotation) * cgmath::Matrix3::from_diagonal(scale);
+        let model = cgmath::Matrix4::from_translation(combined_position) * cgmath::Matrix4::from(linear);
+        // Inverse-transpose, so non-uniform scales bend normals the right way instead of skewing them
+        let normal = linear.invert().map_or(linear, |inverse| inverse.transpose());
 
         InstanceRaw {
             model: model.into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            normal: normal.into(),
+            uv_transform: self.uv_transform,
         }
             
     }
 }
 
+impl InstanceRaw {
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        self.model.into()
+    }
+}
+
 impl model::Vertex for InstanceRaw {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -83,7 +263,117 @@ impl model::Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ]
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1e-4;
+
+    // Position and rotation of each instance, what determinism and bounds are checked on
+    fn placement(instances: &[Instance]) -> Vec<([f32; 3], [f32; 4])> {
+        instances.iter().map(|instance| (instance.position.into(), instance.rotation.into())).collect()
+    }
+
+    fn all_finite(instances: &[Instance]) -> bool {
+        placement(instances).iter().all(|(position, rotation)| position.iter().chain(rotation).all(|v| v.is_finite()))
+    }
+
+    #[test]
+    fn ring_is_evenly_spread_on_its_radius() {
+        let ring = generate_ring(8, 3.0, true);
+        assert_eq!(ring.len(), 8);
+        for instance in &ring {
+            assert!((instance.position.magnitude() - 3.0).abs() < TOLERANCE);
+            assert!(instance.position.y.abs() < TOLERANCE);
+            // +Z turned towards the center
+            let forward = instance.rotation.rotate_vector(Vec3::unit_z());
+            assert!((forward + instance.position / 3.0).magnitude() < TOLERANCE, "{:?}", forward);
+        }
+        assert!((ring[0].position - Vec3::new(3.0, 0.0, 0.0)).magnitude() < TOLERANCE);
+        assert!((ring[2].position - Vec3::new(0.0, 0.0, 3.0)).magnitude() < TOLERANCE);
+        assert_eq!(generate_ring(8, 3.0, false)[3].rotation, cgmath::Quaternion::one());
+    }
+
+    #[test]
+    fn zero_radius_ring_stacks_on_the_origin() {
+        let ring = generate_ring(5, 0.0, true);
+        assert_eq!(ring.len(), 5);
+        assert!(all_finite(&ring));
+        assert!(ring.iter().all(|instance| instance.position.magnitude() == 0.0));
+        assert!(generate_ring(0, 3.0, true).is_empty());
+    }
+
+    #[test]
+    fn line_runs_from_start_to_end() {
+        let (start, end) = (Vec3::new(-2.0, 1.0, 0.0), Vec3::new(2.0, 1.0, 0.0));
+        let line = generate_line(5, start, end);
+        assert_eq!(line.len(), 5);
+        assert!((line[0].position - start).magnitude() < TOLERANCE);
+        assert!((line[4].position - end).magnitude() < TOLERANCE);
+        assert!((line[1].position - Vec3::new(-1.0, 1.0, 0.0)).magnitude() < TOLERANCE);
+        assert!((generate_line(1, start, end)[0].position - Vec3::new(0.0, 1.0, 0.0)).magnitude() < TOLERANCE);
+        assert!(generate_line(0, start, end).is_empty());
+    }
+
+    #[test]
+    fn zero_length_line_has_no_nan_rotation() {
+        let line = generate_line(4, Vec3::unit_y(), Vec3::unit_y());
+        assert_eq!(line.len(), 4);
+        assert!(all_finite(&line));
+        assert!(line.iter().all(|instance| instance.position == Vec3::unit_y()));
+    }
+
+    #[test]
+    fn random_box_stays_inside_and_repeats_per_seed() {
+        let aabb = Aabb::new(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(1.0, 0.5, 6.0));
+        let scattered = generate_random_in_box(200, aabb, 11);
+        assert_eq!(scattered.len(), 200);
+        assert!(all_finite(&scattered));
+        for instance in &scattered {
+            assert!((0..3).all(|axis| instance.position[axis] >= aabb.min[axis] && instance.position[axis] <= aabb.max[axis]));
+            assert!((instance.rotation.magnitude() - 1.0).abs() < TOLERANCE);
+        }
+        assert_eq!(placement(&scattered), placement(&generate_random_in_box(200, aabb, 11)));
+        assert_ne!(placement(&scattered), placement(&generate_random_in_box(200, aabb, 12)));
+        assert!(generate_random_in_box(0, aabb, 11).is_empty());
+
+        // A flat box keeps them on its plane
+        let flat = Aabb::new(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0));
+        assert!(generate_random_in_box(20, flat, 3).iter().all(|instance| instance.position.y == 0.0));
+    }
+
+    #[test]
+    fn random_sphere_stays_on_the_surface_and_repeats_per_seed() {
+        let scattered = generate_random_on_sphere(200, 4.0, 5);
+        assert_eq!(scattered.len(), 200);
+        for instance in &scattered {
+            assert!((instance.position.magnitude() - 4.0).abs() < TOLERANCE);
+            // +Z turned outwards
+            let forward = instance.rotation.rotate_vector(Vec3::unit_z());
+            assert!((forward - instance.position / 4.0).magnitude() < 1e-3, "{:?}", forward);
+        }
+        assert_eq!(placement(&scattered), placement(&generate_random_on_sphere(200, 4.0, 5)));
+        assert_ne!(placement(&scattered), placement(&generate_random_on_sphere(200, 4.0, 6)));
+        assert!(generate_random_on_sphere(0, 4.0, 5).is_empty());
+
+        let collapsed = generate_random_on_sphere(10, 0.0, 5);
+        assert!(all_finite(&collapsed));
+        assert!(collapsed.iter().all(|instance| instance.position.magnitude() == 0.0));
+    }
+
+    #[test]
+    fn oversized_grid_is_clamped() {
+        assert_eq!(Distribution::Grid.generate(3, 4, 1.0, 0).len(), 12);
+        let clamped = Distribution::Line.generate(70_000, 70_000, 1.0, 0).len();
+        assert!(clamped > 0 && clamped <= MAX_INSTANCES as usize, "{}", clamped);
+    }
+}
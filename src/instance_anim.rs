@@ -0,0 +1,251 @@
+/*
+Purpose: Animate the instance grid on the CPU or the GPU
+Responsibilities:
+    - Own the instance vertex buffer the render pipelines read, with room for `capacity` instances
+      so changes are written in place until they outgrow it
+    - CPU path: rebuild every InstanceRaw and upload the whole buffer each frame
+    - GPU path: upload the base transforms once, a compute pass writes InstanceRaw each frame
+    - Keep last frame's InstanceRaw while motion blur needs it
+    - ex: the same choreography, danced by a different troupe
+*/
+
+use crate::{gpu_debug::debug_label, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, instance::{Instance, InstanceRaw, clamp_scale}};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Per-instance input of instance_anim.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct AnimatedInstanceRaw {
+    position: [f32; 4],
+    rotation: [f32; 4],
+    scale: [f32; 4],
+    spin: [f32; 4],
+    uv_transform: [f32; 4],
+}
+
+pub const ANIMATED_INSTANCE_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(AnimatedInstanceRaw, [position, rotation, scale, spin, uv_transform]),
+    wgsl: &[("instance_anim.wgsl", "AnimatedInstance")],
+};
+
+impl From<&Instance> for AnimatedInstanceRaw {
+    fn from(instance: &Instance) -> Self {
+        let position = instance.initial_position + instance.position;
+        let rotation = instance.rotation;
+        let scale = clamp_scale(instance.scale);
+        Self {
+            position: [position.x, position.y, position.z, 1.0],
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: [scale.x, scale.y, scale.z, 0.0],
+            spin: [instance.spin_axis.x, instance.spin_axis.y, instance.spin_axis.z, instance.spin_speed],
+            uv_transform: instance.uv_transform,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct AnimationUniform {
+    time: f32,
+    count: u32,
+    _padding: [u32; 2],
+}
+
+pub const ANIMATION_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(AnimationUniform, [time, count]),
+    wgsl: &[("instance_anim.wgsl", "AnimationUniform")],
+};
+
+// Shared by every State, lives in the RenderContext
+pub struct InstanceAnimationPipeline {
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+}
+
+impl InstanceAnimationPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("Instance Animation Bind Group Layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Instance Animation Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Animation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("instance_anim.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Instance Animation Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Self { pipeline, layout }
+    }
+}
+
+// GPU side of one model's instances, rebuilt by its ModelEntry whenever the instances change
+pub struct AnimatedInstances {
+    count: u32,
+    capacity: u32,
+    // Read as a vertex buffer by the render pipelines, written by whichever path is active
+    instance_buffer: Tracked<wgpu::Buffer>,
+    // Base transforms the compute pass reads
+    animation_buffer: Tracked<wgpu::Buffer>,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // The instance buffer as it was before this frame posed it, for the motion blur velocity
+    previous_buffer: Option<Tracked<wgpu::Buffer>>,
+}
+
+impl AnimatedInstances {
+    // Empty, fill it with write. `label` names the buffers after their model in GPU captures.
+    pub fn new(device: &wgpu::Device, pipeline: &InstanceAnimationPipeline, capacity: u32, label: &str) -> Self {
+        // Storage bindings can't be empty
+        let slots = capacity.max(1) as usize;
+        let instance_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Buffer", label).as_deref(),
+            size: (slots * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let animation_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Animation Buffer", label).as_deref(),
+            size: (slots * std::mem::size_of::<AnimatedInstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Animation Uniform Buffer", label).as_deref(),
+            size: std::mem::size_of::<AnimationUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: animation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+            label: debug_label!("{} Instance Animation Bind Group", label).as_deref(),
+        });
+
+        Self {
+            count: 0,
+            capacity: slots as u32,
+            instance_buffer,
+            animation_buffer,
+            uniform_buffer,
+            bind_group,
+            previous_buffer: None,
+        }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    // Replaces the base transforms, `instances` has to fit the capacity. Posing them is up to
+    // animate_cpu or animate_gpu.
+    pub fn write(&mut self, queue: &wgpu::Queue, instances: &[Instance]) {
+        debug_assert!(instances.len() <= self.capacity as usize);
+        self.count = instances.len() as u32;
+        if instances.is_empty() {
+            return;
+        }
+        let animated = instances.iter().map(AnimatedInstanceRaw::from).collect::<Vec<_>>();
+        queue.write_buffer(&self.animation_buffer, 0, bytemuck::cast_slice(&animated));
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    // Copy the instances as last posed aside, submitted before this frame poses them again
+    pub fn retain_previous(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let size = self.instance_buffer.size();
+        let previous = self.previous_buffer.get_or_insert_with(|| {
+            gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("Previous Instance Buffer"),
+                size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        encoder.copy_buffer_to_buffer(&self.instance_buffer, 0, previous, 0, size);
+    }
+
+    // None until retain_previous has run since these buffers were created
+    pub fn previous_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.previous_buffer.as_deref()
+    }
+
+    pub fn release_previous(&mut self) {
+        self.previous_buffer = None;
+    }
+
+    // CPU path: every matrix is rebuilt and the whole buffer uploaded.
+    // `instances` must be the ones the buffers were built from.
+    pub fn animate_cpu(&self, queue: &wgpu::Queue, instances: &[Instance], time: f32) {
+        if instances.is_empty() {
+            return;
+        }
+        let data = instances.iter().map(|instance| instance.to_raw(time)).collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&data));
+    }
+
+    // GPU path: only the time is uploaded, the compute pass writes the buffer
+    pub fn animate_gpu(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, pipeline: &InstanceAnimationPipeline, time: f32) {
+        if self.count == 0 {
+            return;
+        }
+        let uniform = AnimationUniform {
+            time,
+            count: self.len(),
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Instance Animation Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.len().div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,89 @@
+/*
+Purpose: Spin instances on the GPU
+Responsibilites:
+    - Turn each instance's base transform, scale and spin (axis, degrees per second) into the
+      InstanceRaw layout for the current time
+    - Write straight into the instance vertex buffer, the render pipelines don't change
+*/
+
+struct AnimatedInstance {
+    position: vec4<f32>,
+    // Base rotation quaternion (x, y, z, w)
+    rotation: vec4<f32>,
+    // Per axis scale (xyz), never zero on the CPU side
+    scale: vec4<f32>,
+    // Spin axis (xyz) and speed in degrees per second (w)
+    spin: vec4<f32>,
+    uv_transform: vec4<f32>,
+};
+
+struct AnimationUniform {
+    time: f32,
+    count: u32,
+};
+
+// Floats per InstanceRaw: mat4 model + mat3 normal + vec4 uv_transform
+const RAW_STRIDE: u32 = 29u;
+
+@group(0) @binding(0)
+var<uniform> animation: AnimationUniform;
+@group(0) @binding(1)
+var<storage, read> instances: array<AnimatedInstance>;
+@group(0) @binding(2)
+var<storage, read_write> raw: array<f32>;
+
+fn quat_mul(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(a.w * b.xyz + b.w * a.xyz + cross(a.xyz, b.xyz), a.w * b.w - dot(a.xyz, b.xyz));
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= animation.count) {
+        return;
+    }
+    let instance = instances[i];
+
+    let half_angle = radians(instance.spin.w * animation.time) * 0.5;
+    let spin = vec4<f32>(instance.spin.xyz * sin(half_angle), cos(half_angle));
+    let q = quat_mul(spin, instance.rotation);
+
+    // Same expansion as cgmath's Matrix3::from(Quaternion), column by column
+    let x2 = q.x + q.x;
+    let y2 = q.y + q.y;
+    let z2 = q.z + q.z;
+    let xx2 = x2 * q.x;
+    let xy2 = x2 * q.y;
+    let xz2 = x2 * q.z;
+    let yy2 = y2 * q.y;
+    let yz2 = y2 * q.z;
+    let zz2 = z2 * q.z;
+    let sx2 = x2 * q.w;
+    let sy2 = y2 * q.w;
+    let sz2 = z2 * q.w;
+    let c0 = vec3<f32>(1.0 - yy2 - zz2, xy2 + sz2, xz2 - sy2);
+    let c1 = vec3<f32>(xy2 - sz2, 1.0 - xx2 - zz2, yz2 + sx2);
+    let c2 = vec3<f32>(xz2 + sy2, yz2 - sx2, 1.0 - xx2 - yy2);
+    let s = instance.scale.xyz;
+    // model = translation * rotation * scale
+    let m0 = c0 * s.x;
+    let m1 = c1 * s.y;
+    let m2 = c2 * s.z;
+    // Inverse-transpose of rotation * scale is rotation * (1 / scale)
+    let n0 = c0 / s.x;
+    let n1 = c1 / s.y;
+    let n2 = c2 / s.z;
+
+    let base = i * RAW_STRIDE;
+    raw[base + 0u] = m0.x; raw[base + 1u] = m0.y; raw[base + 2u] = m0.z; raw[base + 3u] = 0.0;
+    raw[base + 4u] = m1.x; raw[base + 5u] = m1.y; raw[base + 6u] = m1.z; raw[base + 7u] = 0.0;
+    raw[base + 8u] = m2.x; raw[base + 9u] = m2.y; raw[base + 10u] = m2.z; raw[base + 11u] = 0.0;
+    raw[base + 12u] = instance.position.x; raw[base + 13u] = instance.position.y; raw[base + 14u] = instance.position.z; raw[base + 15u] = 1.0;
+    raw[base + 16u] = n0.x; raw[base + 17u] = n0.y; raw[base + 18u] = n0.z;
+    raw[base + 19u] = n1.x; raw[base + 20u] = n1.y; raw[base + 21u] = n1.z;
+    raw[base + 22u] = n2.x; raw[base + 23u] = n2.y; raw[base + 24u] = n2.z;
+    raw[base + 25u] = instance.uv_transform.x;
+    raw[base + 26u] = instance.uv_transform.y;
+    raw[base + 27u] = instance.uv_transform.z;
+    raw[base + 28u] = instance.uv_transform.w;
+}
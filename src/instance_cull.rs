@@ -0,0 +1,527 @@
+/*
+Purpose: Frustum and occlusion cull the instances of each model before the main pass draws them
+Responsibilities:
+    - CPU path: test every instance's bounding sphere, upload the ones in view packed together
+      and draw 0..visible
+    - GPU path: a compute pass does the same test into a second buffer and counts the survivors
+      atomically, the count lands in indirect draw args so the CPU never waits for it
+    - Occlusion on the GPU path: what is in the frustum is also tested against the hierarchical
+      Z pyramid of the depth pre-pass (hiz.rs), and dropped when everything under its rect on
+      screen is nearer. Those are counted apart from the frustum's rejects.
+    - Read the GPU's counts back a frame late for the stats panel
+    - Only the GPU path needs compute and indirect draws, adapters without them fall back to the CPU
+    - ex: a bouncer checking the list at the door, by hand or with a scanner
+*/
+
+use std::ops::Range;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use cgmath::{InnerSpace, Matrix4};
+
+use crate::{
+    gpu_debug::debug_label,
+    gpu_layout::{UniformCheck, rust_layout},
+    gpu_memory::{self, Tracked},
+    hiz::HiZTargets,
+    instance::InstanceRaw,
+    math::{Aabb, Frustum, Sphere},
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+// One DrawIndexedIndirectArgs per mesh, the instance count sits after the index count
+const ARGS_SIZE: wgpu::BufferAddress = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+const INSTANCE_COUNT_OFFSET: wgpu::BufferAddress = 4;
+// The visible and the occluded count, one u32 each
+const COUNTS_SIZE: wgpu::BufferAddress = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    Off,
+    Cpu,
+    Gpu,
+}
+
+impl CullMode {
+    pub const ALL: [CullMode; 3] = [CullMode::Off, CullMode::Cpu, CullMode::Gpu];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CullMode::Off => "Off",
+            CullMode::Cpu => "CPU",
+            CullMode::Gpu => "GPU (compute + indirect)",
+        }
+    }
+}
+
+// What one cull did with the instances, for the stats and the benchmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CullCounts {
+    pub total: u32,
+    pub outside_frustum: u32,
+    // In the frustum and hidden by the depth pre-pass, GPU path only
+    pub occluded: u32,
+    pub drawn: u32,
+}
+
+// What the GPU path needs beyond what every adapter has
+pub fn gpu_culling_supported(adapter: &wgpu::Adapter) -> bool {
+    adapter
+        .get_downlevel_capabilities()
+        .flags
+        .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+}
+
+// Sphere around the model's visible meshes, in model space
+pub fn bounding_sphere(bounds: &Aabb) -> Sphere {
+    Sphere { center: bounds.center(), radius: bounds.size().magnitude() * 0.5 }
+}
+
+// `sphere` moved by an instance, grown by its largest scale. Same math as instance_cull.wgsl.
+pub fn instance_sphere(model: Matrix4<f32>, sphere: &Sphere) -> Sphere {
+    let scale = model.x.truncate().magnitude2().max(model.y.truncate().magnitude2()).max(model.z.truncate().magnitude2()).sqrt();
+    Sphere {
+        center: (model * sphere.center.extend(1.0)).truncate(),
+        radius: sphere.radius * scale,
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    // The camera the pyramid's depth was drawn with
+    view_proj: [[f32; 4]; 4],
+    count: u32,
+    hiz_levels: u32,
+    hiz_size: [f32; 2],
+}
+
+pub const CULL_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(CullUniform, [planes, sphere, view_proj, count, hiz_levels, hiz_size]),
+    wgsl: &[("instance_cull.wgsl", "CullUniform")],
+};
+
+impl CullUniform {
+    fn new(frustum: &Frustum, view_proj: Matrix4<f32>, sphere: &Sphere, count: u32, hiz: Option<&HiZTargets>) -> Self {
+        let planes = frustum.planes.map(|plane| [plane.normal.x, plane.normal.y, plane.normal.z, plane.distance]);
+        let center = sphere.center;
+        Self {
+            planes,
+            sphere: [center.x, center.y, center.z, sphere.radius],
+            view_proj: view_proj.into(),
+            count,
+            hiz_levels: hiz.map_or(0, HiZTargets::levels),
+            hiz_size: hiz.map_or([0.0; 2], |hiz| hiz.size().map(|side| side as f32)),
+        }
+    }
+}
+
+// Shared by every State, lives in the RenderContext. None there when the adapter can't run it.
+pub struct InstanceCullPipeline {
+    pipeline: wgpu::ComputePipeline,
+    // Frustum and then the pyramid, group 1 is the pyramid's
+    occlusion_pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    occlusion_layout: wgpu::BindGroupLayout,
+}
+
+impl InstanceCullPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("Instance Cull Bind Group Layout"),
+        });
+        let occlusion_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Uint,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("Instance Occlusion Cull Bind Group Layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("instance_cull.wgsl").into()),
+        });
+        let pipeline = |label: &str, bind_group_layouts: &[&wgpu::BindGroupLayout], entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        Self {
+            pipeline: pipeline("Instance Cull Pipeline", &[&layout], "cs_main"),
+            occlusion_pipeline: pipeline("Instance Occlusion Cull Pipeline", &[&layout, &occlusion_layout], "cs_occlusion"),
+            layout,
+            occlusion_layout,
+        }
+    }
+
+    // What HiZTargets binds its pyramid with
+    pub fn occlusion_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.occlusion_layout
+    }
+}
+
+// How the main pass draws the culled buffer
+pub enum CulledDraw<'a> {
+    Instances(Range<u32>),
+    // One DrawIndexedIndirectArgs per mesh of the model, see indirect_offset
+    Indirect(&'a wgpu::Buffer),
+}
+
+// Where mesh `index`'s draw args start in the indirect buffer
+pub fn indirect_offset(index: usize) -> wgpu::BufferAddress {
+    index as wgpu::BufferAddress * ARGS_SIZE
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    // The count was copied into the readback buffer by this frame's encoder
+    Copied,
+    Mapping,
+}
+
+// The compute path's buffers, bound to the instance buffer they were made for
+struct GpuCull {
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    count_buffer: Tracked<wgpu::Buffer>,
+    indirect_buffer: Tracked<wgpu::Buffer>,
+    readback_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    readback: Readback,
+    // Set from the map_async callback
+    mapped: Arc<AtomicBool>,
+    visible: Option<u32>,
+    occluded: Option<u32>,
+}
+
+// One model's instances in view, rebuilt by its ModelEntry whenever its instance buffer is replaced
+pub struct CulledInstances {
+    // Same capacity as the instance buffer, the visible ones packed from the start
+    buffer: Tracked<wgpu::Buffer>,
+    mode: CullMode,
+    // Instances the CPU path uploaded last
+    cpu_visible: u32,
+    // None when the adapter can't run the compute path
+    gpu: Option<GpuCull>,
+}
+
+impl CulledInstances {
+    // `source` is the posed instance buffer with room for `capacity` instances, `index_counts`
+    // the model's meshes in order
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline: Option<&InstanceCullPipeline>,
+        source: &wgpu::Buffer,
+        capacity: u32,
+        index_counts: &[u32],
+        label: &str,
+    ) -> Self {
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Culled Instance Buffer", label).as_deref(),
+            size: (capacity.max(1) as usize * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let gpu = pipeline.map(|pipeline| GpuCull::new(device, pipeline, source, &buffer, index_counts, label));
+        Self { buffer, mode: CullMode::Off, cpu_visible: 0, gpu }
+    }
+
+    // Test `instances` (posed like the instance buffer) and upload the visible ones
+    pub fn cull_cpu(&mut self, queue: &wgpu::Queue, instances: impl Iterator<Item = InstanceRaw>, sphere: &Sphere, frustum: &Frustum) {
+        let visible = instances
+            .filter(|raw| frustum.contains_sphere(&instance_sphere(raw.model_matrix(), sphere)))
+            .collect::<Vec<_>>();
+        if !visible.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&visible));
+        }
+        self.cpu_visible = visible.len() as u32;
+        self.mode = CullMode::Cpu;
+    }
+
+    // Record the compute pass for `count` instances, tested against `hiz` too when given: it
+    // must have been built from depth drawn with `view_proj`. False (and nothing recorded)
+    // without the compute path, the caller culls on the CPU instead.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_gpu(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        pipeline: &InstanceCullPipeline,
+        sphere: &Sphere,
+        frustum: &Frustum,
+        view_proj: Matrix4<f32>,
+        hiz: Option<&HiZTargets>,
+        count: u32,
+    ) -> bool {
+        let Some(gpu) = self.gpu.as_mut() else {
+            return false;
+        };
+        queue.write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&CullUniform::new(frustum, view_proj, sphere, count, hiz)));
+        encoder.clear_buffer(&gpu.count_buffer, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Instance Cull Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &gpu.bind_group, &[]);
+            match hiz {
+                Some(hiz) => {
+                    compute_pass.set_pipeline(&pipeline.occlusion_pipeline);
+                    compute_pass.set_bind_group(1, hiz.cull_bind_group(), &[]);
+                }
+                None => compute_pass.set_pipeline(&pipeline.pipeline),
+            }
+            compute_pass.dispatch_workgroups(count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        // A buffer can't be copied into itself, so the count is kept apart and fanned out
+        let meshes = gpu.indirect_buffer.size() / ARGS_SIZE;
+        for mesh in 0..meshes {
+            encoder.copy_buffer_to_buffer(&gpu.count_buffer, 0, &gpu.indirect_buffer, mesh * ARGS_SIZE + INSTANCE_COUNT_OFFSET, 4);
+        }
+        if gpu.readback == Readback::Idle {
+            encoder.copy_buffer_to_buffer(&gpu.count_buffer, 0, &gpu.readback_buffer, 0, COUNTS_SIZE);
+            gpu.readback = Readback::Copied;
+        }
+        self.mode = CullMode::Gpu;
+        true
+    }
+
+    // The buffer to bind at slot 1 and how to draw it, as the last cull left them
+    pub fn draw(&self) -> (&wgpu::Buffer, CulledDraw<'_>) {
+        let draw = match (&self.gpu, self.mode) {
+            (Some(gpu), CullMode::Gpu) => CulledDraw::Indirect(&gpu.indirect_buffer),
+            _ => CulledDraw::Instances(0..self.cpu_visible),
+        };
+        (&self.buffer, draw)
+    }
+
+    // Instances the last cull let through. The GPU's count arrives a frame or two late, None
+    // until it has.
+    pub fn visible(&self) -> Option<u32> {
+        match self.mode {
+            CullMode::Gpu => self.gpu.as_ref().and_then(|gpu| gpu.visible),
+            _ => Some(self.cpu_visible),
+        }
+    }
+
+    // Instances in the frustum the last cull found hidden, late like visible. The CPU path
+    // doesn't test occlusion.
+    pub fn occluded(&self) -> Option<u32> {
+        match self.mode {
+            CullMode::Gpu => self.gpu.as_ref().and_then(|gpu| gpu.occluded),
+            _ => Some(0),
+        }
+    }
+
+    // After the encoder with the cull was submitted
+    pub fn request_readback(&mut self) {
+        let Some(gpu) = self.gpu.as_mut().filter(|gpu| gpu.readback == Readback::Copied) else {
+            return;
+        };
+        let mapped = gpu.mapped.clone();
+        gpu.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| match result {
+            Ok(()) => mapped.store(true, Ordering::Release),
+            Err(e) => log::warn!("Could not read back the culled instance counts: {}", e),
+        });
+        gpu.readback = Readback::Mapping;
+    }
+
+    // Picks up a finished readback, the device is polled by the caller
+    pub fn poll_readback(&mut self) {
+        let Some(gpu) = self.gpu.as_mut() else {
+            return;
+        };
+        if gpu.readback != Readback::Mapping || !gpu.mapped.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        {
+            let data = gpu.readback_buffer.slice(..).get_mapped_range();
+            let counts = bytemuck::cast_slice::<u8, u32>(&data);
+            gpu.visible = Some(counts[0]);
+            gpu.occluded = Some(counts[1]);
+        }
+        gpu.readback_buffer.unmap();
+        gpu.readback = Readback::Idle;
+    }
+}
+
+impl GpuCull {
+    fn new(
+        device: &wgpu::Device,
+        pipeline: &InstanceCullPipeline,
+        source: &wgpu::Buffer,
+        culled: &wgpu::Buffer,
+        index_counts: &[u32],
+        label: &str,
+    ) -> Self {
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Cull Uniform Buffer", label).as_deref(),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let count_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Cull Count Buffer", label).as_deref(),
+            size: COUNTS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        // Everything but the instance counts stays as written here
+        let args = index_counts
+            .iter()
+            .flat_map(|&index_count| {
+                wgpu::util::DrawIndexedIndirectArgs { index_count, instance_count: 0, first_index: 0, base_vertex: 0, first_instance: 0 }
+                    .as_bytes()
+                    .to_vec()
+            })
+            .collect::<Vec<u8>>();
+        let indirect_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Indirect Draw Buffer", label).as_deref(),
+            contents: if args.is_empty() { &[0; ARGS_SIZE as usize] } else { &args },
+            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+        });
+        let readback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Instance Cull Readback Buffer", label).as_deref(),
+            size: COUNTS_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: source.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: culled.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: count_buffer.as_entire_binding(),
+                },
+            ],
+            label: debug_label!("{} Instance Cull Bind Group", label).as_deref(),
+        });
+        Self {
+            uniform_buffer,
+            count_buffer,
+            indirect_buffer,
+            readback_buffer,
+            bind_group,
+            readback: Readback::Idle,
+            mapped: Arc::new(AtomicBool::new(false)),
+            visible: None,
+            occluded: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+    use pollster::FutureExt;
+    use wgpu::util::DeviceExt;
+
+    use crate::{camera::{Camera, Projection}, instance::Instance};
+
+    fn device() -> (wgpu::Device, wgpu::Queue) {
+        let adapter = wgpu::Instance::default().request_adapter(&wgpu::RequestAdapterOptions::default()).block_on().expect("no usable GPU adapter");
+        assert!(gpu_culling_supported(&adapter));
+        adapter.request_device(&wgpu::DeviceDescriptor::default()).block_on().unwrap()
+    }
+
+    // A jittered grid, turned and stretched differently each, so plenty sit across the planes
+    fn instances() -> Vec<InstanceRaw> {
+        (0..900)
+            .map(|i| {
+                let (row, column) = ((i / 30) as f32, (i % 30) as f32);
+                let position = Vector3::new(column * 2.1 - 30.0 + (i % 7) as f32 * 0.13, (i % 5) as f32 - 2.0, row * 2.1 - 40.0);
+                let rotation = Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 0.5).normalize(), Deg(i as f32 * 37.0));
+                let mut instance = Instance::placed(position, rotation, Vector3::unit_y());
+                instance.scale = Vector3::new(1.0 + (i % 3) as f32, 0.5, 1.0 + (i % 4) as f32 * 0.5);
+                instance.to_raw(0.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gpu_and_cpu_cull_keep_the_same_instances() {
+        let (device, queue) = device();
+        let pipeline = InstanceCullPipeline::new(&device);
+        let raws = instances();
+        let source = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test Instance Buffer"),
+            contents: bytemuck::cast_slice(&raws),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+        let mut culled = CulledInstances::new(&device, Some(&pipeline), &source, raws.len() as u32, &[36, 6], "Test");
+        // Off its origin, so moving the center is tested too
+        let sphere = bounding_sphere(&Aabb::new(Vector3::new(-0.5, 0.0, -0.5), Vector3::new(0.5, 2.0, 0.5)));
+
+        let projection = Projection::new(160, 90, Deg(45.0), 0.1, 30.0);
+        let cameras = [
+            Camera::new((0.0, 5.0, 10.0), Deg(-90.0), Deg(-20.0)),
+            Camera::new((-25.0, 1.0, -10.0), Deg(-30.0), Deg(5.0)).with_roll(Deg(40.0)),
+            Camera::new((3.0, 25.0, -15.0), Deg(-90.0), Deg(-89.0)),
+        ];
+        for camera in cameras {
+            let view_proj = projection.calc_matrix() * camera.calc_matrix();
+            let frustum = Frustum::from_view_proj(view_proj).unwrap();
+
+            culled.cull_cpu(&queue, raws.iter().copied(), &sphere, &frustum);
+            let cpu = culled.visible().unwrap();
+            assert!(cpu > 0 && cpu < raws.len() as u32, "{cpu} visible from {:?}", camera);
+            assert!(matches!(culled.draw().1, CulledDraw::Instances(range) if range == (0..cpu)));
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            assert!(culled.encode_gpu(&mut encoder, &queue, &pipeline, &sphere, &frustum, view_proj, None, raws.len() as u32));
+            queue.submit(Some(encoder.finish()));
+            culled.request_readback();
+            device.poll(wgpu::PollType::Wait).unwrap();
+            culled.poll_readback();
+            assert_eq!(culled.visible(), Some(cpu), "from {:?}", camera);
+            assert_eq!(culled.occluded(), Some(0));
+            assert!(matches!(culled.draw().1, CulledDraw::Indirect(_)));
+        }
+    }
+}
@@ -0,0 +1,149 @@
+/*
+Purpose: Frustum and occlusion cull one model's instances on the GPU
+Responsibilites:
+    - Move the model's bounding sphere by each instance's model matrix and test it against the
+      six frustum planes
+    - cs_occlusion: then project the box around the sphere, pick the pyramid level where its
+      rect is at most two texels across and drop it when its nearest depth is behind the
+      farthest depth of those texels
+    - Copy the instances that pass into the culled buffer, packed from the start, and count them
+      with an atomic so the indirect draw can use 0..count. The occluded ones are counted too.
+*/
+
+struct CullUniform {
+    // Normals point inwards, w is the plane's distance
+    planes: array<vec4<f32>, 6>,
+    // Model space center (xyz) and radius (w) around the visible meshes
+    sphere: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    count: u32,
+    // Mip levels of t_hiz and level 0's size in pixels
+    hiz_levels: u32,
+    hiz_size: vec2<f32>,
+};
+
+struct Counts {
+    visible: atomic<u32>,
+    occluded: atomic<u32>,
+};
+
+// Floats per InstanceRaw: mat4 model + mat3 normal + vec4 uv_transform, as in instance_anim.wgsl
+const RAW_STRIDE: u32 = 29u;
+
+@group(0) @binding(0)
+var<uniform> cull: CullUniform;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> culled: array<f32>;
+@group(0) @binding(3)
+var<storage, read_write> counts: Counts;
+// cs_occlusion only, the farthest depth under each texel (hiz.wgsl)
+@group(1) @binding(0)
+var t_hiz: texture_2d<u32>;
+
+// Instance i's bounding sphere in world space, xyz center and w radius
+fn world_sphere(i: u32) -> vec4<f32> {
+    let base = i * RAW_STRIDE;
+    let c0 = vec3<f32>(instances[base + 0u], instances[base + 1u], instances[base + 2u]);
+    let c1 = vec3<f32>(instances[base + 4u], instances[base + 5u], instances[base + 6u]);
+    let c2 = vec3<f32>(instances[base + 8u], instances[base + 9u], instances[base + 10u]);
+    let translation = vec3<f32>(instances[base + 12u], instances[base + 13u], instances[base + 14u]);
+    let center = c0 * cull.sphere.x + c1 * cull.sphere.y + c2 * cull.sphere.z + translation;
+    // The longest axis bounds a non-uniformly scaled sphere
+    let radius = cull.sphere.w * sqrt(max(dot(c0, c0), max(dot(c1, c1), dot(c2, c2))));
+    return vec4<f32>(center, radius);
+}
+
+fn in_frustum(sphere: vec4<f32>) -> bool {
+    for (var p = 0u; p < 6u; p++) {
+        let plane = cull.planes[p];
+        if (dot(plane.xyz, sphere.xyz) + plane.w < -sphere.w) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn emit(i: u32) {
+    let base = i * RAW_STRIDE;
+    let slot = atomicAdd(&counts.visible, 1u) * RAW_STRIDE;
+    for (var f = 0u; f < RAW_STRIDE; f++) {
+        culled[slot + f] = instances[base + f];
+    }
+}
+
+// True when everything under the sphere's rect on screen is nearer than the sphere gets
+fn occluded(sphere: vec4<f32>) -> bool {
+    var rect_min = vec2<f32>(1.0);
+    var rect_max = vec2<f32>(-1.0);
+    var nearest = 1.0;
+    for (var c = 0u; c < 8u; c++) {
+        let corner = sphere.xyz + sphere.w * vec3<f32>(
+            select(-1.0, 1.0, (c & 1u) != 0u),
+            select(-1.0, 1.0, (c & 2u) != 0u),
+            select(-1.0, 1.0, (c & 4u) != 0u),
+        );
+        let clip = cull.view_proj * vec4<f32>(corner, 1.0);
+        // Reaching past the near plane, the rect has no bounds and the camera may be inside
+        if (clip.w <= 0.0 || clip.z < 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        rect_min = min(rect_min, ndc.xy);
+        rect_max = max(rect_max, ndc.xy);
+        nearest = min(nearest, ndc.z);
+    }
+    // In pixels with y down, a pixel wider for the rounding and TAA's jitter
+    let uv_min = vec2<f32>(rect_min.x, -rect_max.y) * 0.5 + 0.5;
+    let uv_max = vec2<f32>(rect_max.x, -rect_min.y) * 0.5 + 0.5;
+    let pixel_min = clamp(uv_min * cull.hiz_size - 1.0, vec2<f32>(0.0), cull.hiz_size - 1.0);
+    let pixel_max = clamp(uv_max * cull.hiz_size + 1.0, vec2<f32>(0.0), cull.hiz_size - 1.0);
+    // A texel of level n covers 2^n pixels, at this level the rect spans one or two each way
+    let extent = max(pixel_max.x - pixel_min.x, pixel_max.y - pixel_min.y);
+    var level = i32(min(u32(ceil(log2(max(extent, 1.0)))), cull.hiz_levels - 1u));
+    // A level finer still when the rect happens to sit within 2x2 of its texels, fewer of the
+    // pixels around it are in the test
+    if (level > 0) {
+        let finer = vec2<u32>(u32(level - 1));
+        if (all((vec2<i32>(pixel_max) >> finer) - (vec2<i32>(pixel_min) >> finer) <= vec2<i32>(1))) {
+            level -= 1;
+        }
+    }
+    let last = vec2<i32>(textureDimensions(t_hiz, level)) - 1;
+    // The last texel of a level also covers what its rounded down size left off
+    let texel_min = min(vec2<i32>(pixel_min) >> vec2<u32>(u32(level)), last);
+    let texel_max = min(vec2<i32>(pixel_max) >> vec2<u32>(u32(level)), last);
+    // hiz.wgsl keeps the depths' bits, which sort like the depths themselves
+    let farthest = bitcast<f32>(max(
+        max(textureLoad(t_hiz, texel_min, level).r, textureLoad(t_hiz, vec2<i32>(texel_max.x, texel_min.y), level).r),
+        max(textureLoad(t_hiz, vec2<i32>(texel_min.x, texel_max.y), level).r, textureLoad(t_hiz, texel_max, level).r),
+    ));
+    return nearest > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= cull.count || !in_frustum(world_sphere(i))) {
+        return;
+    }
+    emit(i);
+}
+
+@compute @workgroup_size(64)
+fn cs_occlusion(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= cull.count) {
+        return;
+    }
+    let sphere = world_sphere(i);
+    if (!in_frustum(sphere)) {
+        return;
+    }
+    if (occluded(sphere)) {
+        atomicAdd(&counts.occluded, 1u);
+        return;
+    }
+    emit(i);
+}
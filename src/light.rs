@@ -1,12 +1,30 @@
+use crate::{color, gpu_layout::{UniformCheck, rust_layout}};
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 // Represents a colored point in space
 pub struct LightUniform {
     pub position: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding: u32,
+    // Size of the light's debug marker in world units, before intensity scales it. Also fills
+    // the padding uniforms need after a vec3 (16 byte / 4 float spacing).
+    pub marker_scale: f32,
+    // sRGB as picked, linear() is what gets uploaded
     pub color: [f32; 3],
-    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
-    pub _padding2: u32,
+    // Multiplies color when lighting, and grows the marker
+    pub intensity: f32,
+    // Light reaching every surface regardless of direction, as a fraction of color
+    pub ambient: f32,
+    pub _padding: [f32; 3],
+}
+
+impl LightUniform {
+    // With the color shaders light in, see color.rs
+    pub fn linear(self) -> Self {
+        Self { color: color::srgb_to_linear_rgb(self.color), ..self }
+    }
 }
 
+pub const LIGHT_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(LightUniform, [position, marker_scale, color, intensity, ambient]),
+    wgsl: &[("shader.wgsl", "Light"), ("grass.wgsl", "Light"), ("light.wgsl", "Light"), ("shape.wgsl", "Light")],
+};
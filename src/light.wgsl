@@ -1,3 +1,10 @@
+/*
+Purpose: Debug marker drawn where the scene light is
+Responsibilites:
+    - Draw the model's geometry at the light's position, sized by the marker scale and intensity
+    - Tint it with the light's color times its intensity, so brighter lights glow brighter under HDR
+*/
+
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
@@ -7,7 +14,10 @@ var<uniform> camera: Camera;
 
 struct Light {
     position: vec3<f32>,
+    marker_scale: f32,
     color: vec3<f32>,
+    intensity: f32,
+    ambient: f32,
 }
 @group(1) @binding(0)
 var<uniform> light: Light;
@@ -25,10 +35,11 @@ struct VertexOutput {
 fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
-    let scale = 0.25;
+    // Square root so a light twice as bright doesn't get a marker twice as wide
+    let scale = light.marker_scale * sqrt(max(light.intensity, 0.0));
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
-    out.color = light.color;
+    out.color = light.color * light.intensity;
     return out;
 }
 
@@ -37,4 +48,4 @@ fn vs_main(
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+}
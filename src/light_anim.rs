@@ -0,0 +1,69 @@
+/*
+Purpose: Keyframed light animation
+Responsibilities:
+    - Define Track, keyframes of one value sampled with the same interpolation as joint animation
+    - Define LightAnimation, optional tracks for a light's position, color and intensity
+    - Build the default orbit the scene light has always had
+    - ex: the lighting cues of a stage play, written down once and replayed every night
+*/
+
+use crate::{light::LightUniform, skeleton::sample_keys};
+use cgmath::{Deg, Quaternion, Rotation, Rotation3, Vector3, VectorSpace};
+
+// Keyframes of one value, times ascending. A looping track starts over after its last key,
+// so for a seamless loop the last key repeats the first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    pub keys: Vec<(f32, T)>,
+    pub looping: bool,
+}
+
+impl<T: Copy> Track<T> {
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    // None without keys
+    pub fn sample(&self, time: f32, interpolate: impl Fn(T, T, f32) -> T) -> Option<T> {
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 { time.rem_euclid(duration) } else { time };
+        sample_keys(&self.keys, time, interpolate)
+    }
+}
+
+// Any of the tracks can be missing, that part of the light is then left as it is
+#[derive(Debug, Clone, Default)]
+pub struct LightAnimation {
+    pub position: Option<Track<Vector3<f32>>>,
+    pub color: Option<Track<Vector3<f32>>>,
+    pub intensity: Option<Track<f32>>,
+}
+
+impl LightAnimation {
+    // Circles `start` around the world Y axis, one key every 10 degrees keeps the radius within 0.4%
+    pub fn orbit(start: Vector3<f32>, degrees_per_second: f32) -> Self {
+        let keys = (0..=36)
+            .map(|step| {
+                let degrees = step as f32 * 10.0;
+                (degrees / degrees_per_second, Quaternion::from_angle_y(Deg(degrees)).rotate_vector(start))
+            })
+            .collect();
+        Self {
+            position: Some(Track { keys, looping: true }),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&self, time: f32, light: &mut LightUniform) {
+        let lerp = |a: Vector3<f32>, b: Vector3<f32>, t: f32| a.lerp(b, t);
+        if let Some(position) = self.position.as_ref().and_then(|track| track.sample(time, lerp)) {
+            light.position = position.into();
+        }
+        if let Some(color) = self.color.as_ref().and_then(|track| track.sample(time, lerp)) {
+            light.color = color.into();
+        }
+        if let Some(intensity) = self.intensity.as_ref().and_then(|track| track.sample(time, |a, b, t| a + (b - a) * t)) {
+            light.intensity = intensity;
+        }
+    }
+}
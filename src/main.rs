@@ -7,26 +7,183 @@ Responsibilities:
     - Stay as small as possible (ex: traffic controller)
 */
 
+mod animation_path;
 mod app;
+mod asset_source;
+mod benchmark;
 mod camera;
+mod camera_controller;
+mod click_move;
+mod clip_planes;
+mod clipboard_image;
+mod color;
+mod config;
+mod console;
+mod cursor;
+mod custom_shader;
+mod day_night;
+mod debug_lines;
+mod depth_debug;
+mod depth_prepass;
+mod diagnostics;
+mod dice_demo;
+mod edge_outline;
+mod engine_events;
+mod error_log;
+mod foliage;
+mod frame_graph;
+mod frame_pacer;
+mod frame_stats;
+mod gizmo;
+mod gpu_debug;
+mod gpu_layout;
+mod gpu_memory;
+mod gpu_timer;
+mod gui_window;
+mod hdr;
+mod hiz;
+mod import_options;
+mod input_map;
 mod instance;
+mod instance_anim;
+mod instance_cull;
 mod light;
+mod light_anim;
+mod material_array;
+mod material_set;
+mod math;
+mod measure;
+mod mesh_library;
+mod mesh_optimize;
 mod model;
+mod model_entry;
+mod motion_blur;
+mod overlay;
+mod particles;
+mod picking;
+mod point_lights;
+mod probes;
+mod profiler;
+mod quad_2d;
+mod render_context;
 mod resources;
+mod render_matrix;
+mod road;
+mod rtt;
+mod rust_literal;
+mod scene_gen;
+mod scripting;
+mod sdf;
 mod state;
 mod texture;
+mod texture_residency;
+mod texture_stream;
+mod texture_watch;
+mod title_bar;
+mod toon;
+mod transform_gizmo;
 mod vertex;
+mod vertex_pulling;
+mod ui_theme;
+mod units;
 mod uniforms;
+mod user_settings;
+mod uv_fallback;
+mod shape_lod;
+mod shape_renderer;
 mod shapes;
+mod skeleton;
+mod skinning;
+mod sky;
+mod ssao;
+mod stereo;
+mod taa;
+mod toast;
+mod undo;
+mod video_export;
+mod view_window;
 
 use app::App;
+use config::{CliCommand, EngineConfig};
+use pollster::FutureExt;
 use winit::event_loop::EventLoop;
 
 fn main() {
     {
-        env_logger::init();
+        console::install_logger();
     }
-    let event_loop = EventLoop::new().unwrap();
-    let mut app = App::new();
+    let config = match EngineConfig::from_args(std::env::args().skip(1)) {
+        Ok(CliCommand::Run(config)) => *config,
+        Ok(CliCommand::Help) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Ok(CliCommand::CheckLayouts) => {
+            let (problems, checked) = gpu_layout::verify();
+            for problem in &problems {
+                eprintln!("layout mismatch: {}", problem);
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{} GPU structs match their shaders and vertex layouts", checked);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, config::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    asset_source::install(asset_source::AssetSource::new(&config.asset_roots));
+
+    let event_loop = match EventLoop::new() {
+        Ok(event_loop) => event_loop,
+        // No display at all, the adapter can still be described without a surface
+        Err(e) if config.diagnostics => {
+            log::warn!("Unable to create an event loop ({}), reporting without a window", e);
+            return print_headless_diagnostics(&config);
+        }
+        // No display at all, a benchmark can still run offscreen
+        Err(e) => match config.benchmark_seconds {
+            Some(seconds) => {
+                log::warn!("Unable to create an event loop ({}), running the benchmark offscreen", e);
+                return run_headless_benchmark(&config, seconds);
+            }
+            None => {
+                eprintln!("error: unable to create an event loop: {}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+    let mut app = App::new(config.clone());
     event_loop.run_app(&mut app).unwrap();
+
+    if app.headless_fallback && config.diagnostics {
+        print_headless_diagnostics(&config);
+    } else if app.headless_fallback && let Some(seconds) = config.benchmark_seconds {
+        run_headless_benchmark(&config, seconds);
+    } else if app.failed {
+        std::process::exit(1);
+    }
+}
+
+fn print_headless_diagnostics(config: &EngineConfig) {
+    match state::State::new_headless(config).block_on() {
+        Ok(state) => println!("{}", state.diagnostics_report(None)),
+        Err(e) => {
+            eprintln!("error: no usable GPU adapter: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_headless_benchmark(config: &EngineConfig, seconds: f32) {
+    match benchmark::run_headless(config, seconds) {
+        Ok(report) => println!("{}", report),
+        Err(e) => {
+            eprintln!("error: headless benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
\ No newline at end of file
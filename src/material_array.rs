@@ -0,0 +1,317 @@
+/*
+Purpose: Draw a model's materials from texture arrays instead of a bind group each
+Responsibilities:
+    - Copy every packable material's diffuse and normal image into one layer of a pair of
+      D2Array textures, smaller images are scaled up to the biggest one
+    - Keep the packed materials' uniforms in one buffer, a dynamic offset picks the material
+    - Draw a model's packed meshes with that single bind group, the rest with their own
+    - Count material bind group switches per frame, for the Frame pacing window
+    - ex: a spice rack instead of a cupboard of loose jars, one door to open for every spice
+*/
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::ops::Range;
+
+use image::imageops::FilterType;
+
+use crate::{gpu_debug::debug_label, gpu_memory::{self, Tracked}, instance::InstanceRaw, model::{self, DrawModel, Material, Model, PackedSlot, Vertex}, render_context::create_render_pipeline, texture};
+
+// set_bind_group calls for group 0 of a model pipeline since the last take_material_binds.
+// Changing only the dynamic offset of the bound group doesn't count, backends keep it bound.
+static MATERIAL_BINDS: AtomicU32 = AtomicU32::new(0);
+
+pub fn count_material_bind() {
+    MATERIAL_BINDS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn take_material_binds() -> u32 {
+    MATERIAL_BINDS.swap(0, Ordering::Relaxed)
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct MaterialArrayPipeline {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    // render_pipeline with shader.wgsl sampling the arrays at the material's layer
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl MaterialArrayPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        [camera_layout, light_layout]: [&wgpu::BindGroupLayout; 2],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let array_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        // Same bindings as texture_bind_group_layout, the uniform is picked by offset
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                array_entry(0),
+                sampler_entry(1),
+                array_entry(2),
+                sampler_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(Material::UNIFORM_SIZE),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Material Array Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Material Array Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, camera_layout, light_layout],
+            push_constant_ranges: &[],
+        });
+        let source = include_str!("shader.wgsl")
+            .replace("texture_2d<f32>", "texture_2d_array<f32>")
+            .replace("in.tex_coords)", "in.tex_coords, material.layer)");
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Material Array Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        };
+        let pipeline = create_render_pipeline(
+            device,
+            &layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            sample_count,
+            shader,
+        );
+        Self { bind_group_layout, pipeline }
+    }
+}
+
+// Owned by the Model, the materials point back into it through their PackedSlot
+pub struct PackedMaterials {
+    _diffuse: Tracked<wgpu::Texture>,
+    _normal: Tracked<wgpu::Texture>,
+    _uniforms: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    pub layers: u32,
+    pub layer_size: (u32, u32),
+}
+
+// `images` holds each material's diffuse and normal image, None for materials that can't be
+// packed (streamed textures only have a placeholder yet). Those keep their own bind group, as
+// do any past the adapter's layer limit. None when nothing could be packed.
+pub fn pack(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    label: &str,
+    materials: &[Material],
+    images: &[Option<(image::DynamicImage, image::DynamicImage)>],
+) -> Option<PackedMaterials> {
+    let max_layers = device.limits().max_texture_array_layers as usize;
+    let packable: Vec<_> = images
+        .iter()
+        .enumerate()
+        .filter_map(|(material, images)| Some((material, images.as_ref()?)))
+        .take(max_layers)
+        .collect();
+    if packable.is_empty() {
+        return None;
+    }
+    let width = packable.iter().map(|(_, (diffuse, normal))| diffuse.width().max(normal.width())).max()?;
+    let height = packable.iter().map(|(_, (diffuse, normal))| diffuse.height().max(normal.height())).max()?;
+    let layers = packable.len() as u32;
+
+    // GL guesses a texture's view dimension from its layer count, one layer would pass for a D2
+    let allocated_layers = layers.max(2);
+    let create_array = |name: &str, format| {
+        gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: debug_label!("{} {} Array", label, name).as_deref(),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: allocated_layers },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    };
+    // Same formats as texture::Texture::create_blank
+    let diffuse = create_array("Diffuse", wgpu::TextureFormat::Rgba8UnormSrgb);
+    let normal = create_array("Normal", wgpu::TextureFormat::Rgba8Unorm);
+    for (layer, (_, (diffuse_image, normal_image))) in packable.iter().enumerate() {
+        write_layer(queue, &diffuse, layer as u32, diffuse_image);
+        write_layer(queue, &normal, layer as u32, normal_image);
+    }
+
+    let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+    let stride = Material::UNIFORM_SIZE.div_ceil(alignment) * alignment;
+    let uniforms = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+        label: debug_label!("{} Material Uniforms", label).as_deref(),
+        size: stride * layers as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let array_view = |texture: &wgpu::Texture| {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    };
+    // Sampled like every other material texture
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: debug_label!("{} Array Sampler", label).as_deref(),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&array_view(&diffuse)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&array_view(&normal)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniforms,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(Material::UNIFORM_SIZE),
+                }),
+            },
+        ],
+        label: debug_label!("{} Material Array", label).as_deref(),
+    });
+
+    for (layer, (material, _)) in packable.iter().enumerate() {
+        let slot = PackedSlot {
+            buffer: (*uniforms).clone(),
+            offset: (stride * layer as wgpu::BufferAddress) as wgpu::DynamicOffset,
+            layer: layer as u32,
+        };
+        materials[*material].pack(queue, slot);
+    }
+    log::info!("Packed {} of {} materials of {} into {}x{} texture arrays", layers, materials.len(), label, width, height);
+
+    Some(PackedMaterials {
+        _diffuse: diffuse,
+        _normal: normal,
+        _uniforms: uniforms,
+        bind_group,
+        layers,
+        layer_size: (width, height),
+    })
+}
+
+fn write_layer(queue: &wgpu::Queue, texture: &wgpu::Texture, layer: u32, img: &image::DynamicImage) {
+    let (width, height) = (texture.width(), texture.height());
+    let mut rgba = img.to_rgba8();
+    if rgba.dimensions() != (width, height) {
+        rgba = image::imageops::resize(&rgba, width, height, FilterType::Triangle);
+    }
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+            aspect: wgpu::TextureAspect::All,
+        },
+        &rgba,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * width),
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    );
+}
+
+pub trait DrawPacked<'a> {
+    // draw_model_instanced for models with PackedMaterials: the packed meshes with `pipeline`'s
+    // single bind group, the others with `fallback` and their own. Leaves `fallback` set.
+    fn draw_model_packed(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        pipeline: &'a MaterialArrayPipeline,
+        fallback: &'a wgpu::RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'b> DrawPacked<'b> for wgpu::RenderPass<'_> {
+    fn draw_model_packed(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        pipeline: &'b MaterialArrayPipeline,
+        fallback: &'b wgpu::RenderPipeline,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        let Some(packed) = &model.packed else {
+            self.draw_model_instanced(model, instances, camera_bind_group, light_bind_group);
+            return;
+        };
+        let mut packed_meshes = Vec::new();
+        let mut loose = Vec::new();
+        for mesh in model.meshes.iter().filter(|mesh| mesh.is_visible()) {
+            match model.materials[mesh.material].packed_offset() {
+                Some(offset) => packed_meshes.push((mesh, offset)),
+                None => loose.push(mesh),
+            }
+        }
+
+        if !packed_meshes.is_empty() {
+            self.set_pipeline(&pipeline.pipeline);
+            self.set_bind_group(1, camera_bind_group, &[]);
+            self.set_bind_group(2, light_bind_group, &[]);
+            count_material_bind();
+            for (mesh, offset) in packed_meshes {
+                self.set_bind_group(0, &packed.bind_group, &[offset]);
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+            self.set_pipeline(fallback);
+        }
+        for mesh in loose {
+            self.draw_mesh_instanced(mesh, &model.materials[mesh.material], instances.clone(), camera_bind_group, light_bind_group);
+        }
+    }
+}
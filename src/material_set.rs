@@ -0,0 +1,237 @@
+/*
+Purpose: Build a material from a texture pack's files, found by their names
+Responsibilities:
+    - Know the suffixes packs use for each map (brick_albedo.png, brick_normal.png, ...) and pick
+      one file per map, the same one every time when a pack has several
+    - Fill the maps a pack doesn't have with neutral defaults: white albedo, flat normal, mid
+      roughness, no metal, no occlusion
+    - Fold what the material can't bind into what it can: roughness and metallic maps become
+      their average, occlusion darkens the albedo
+    - Keep every decoded set by its prefix so spawning it again doesn't read the files again
+    - ex: the tailor reading the labels sewn into each piece of a pattern
+*/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{model::{self, MaterialParams, ShadingModel}, texture};
+
+// Checked in this order, the first extension present wins
+const EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "ktx2"];
+const DEFAULT_ROUGHNESS: f32 = 0.5;
+const DEFAULT_METALLIC: f32 = 0.0;
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapKind {
+    Albedo,
+    Normal,
+    Roughness,
+    Metallic,
+    Ao,
+}
+
+impl MapKind {
+    pub const ALL: [MapKind; 5] = [MapKind::Albedo, MapKind::Normal, MapKind::Roughness, MapKind::Metallic, MapKind::Ao];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MapKind::Albedo => "Albedo",
+            MapKind::Normal => "Normal",
+            MapKind::Roughness => "Roughness",
+            MapKind::Metallic => "Metallic",
+            MapKind::Ao => "Ambient occlusion",
+        }
+    }
+
+    // Lowercase, preferred first
+    fn suffixes(self) -> &'static [&'static str] {
+        match self {
+            MapKind::Albedo => &["albedo", "basecolor"],
+            MapKind::Normal => &["normal"],
+            MapKind::Roughness => &["roughness"],
+            MapKind::Metallic => &["metallic"],
+            MapKind::Ao => &["ao"],
+        }
+    }
+
+    // What a missing map is replaced with, for the material editor
+    fn default_label(self) -> &'static str {
+        match self {
+            MapKind::Albedo => "white",
+            MapKind::Normal => "flat",
+            MapKind::Roughness => "0.5",
+            MapKind::Metallic => "0",
+            MapKind::Ao => "none",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// Where one map of a set came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapSource {
+    // The asset path
+    File(String),
+    Defaulted,
+    // Found, but only as a format there is no decoder for (KTX2)
+    Unsupported(String),
+}
+
+// Per MapKind, for the material editor
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialSetReport {
+    pub prefix: String,
+    pub maps: [MapSource; 5],
+}
+
+impl MaterialSetReport {
+    pub fn source(&self, kind: MapKind) -> &MapSource {
+        &self.maps[kind.index()]
+    }
+
+    // "brick_albedo.png", "white (default)" or "brick_ao.ktx2 (unsupported, none)"
+    pub fn describe(&self, kind: MapKind) -> String {
+        match self.source(kind) {
+            MapSource::File(path) => file_name(path).to_string(),
+            MapSource::Defaulted => format!("{} (default)", kind.default_label()),
+            MapSource::Unsupported(path) => format!("{} (unsupported, {})", file_name(path), kind.default_label()),
+        }
+    }
+}
+
+// "textures/brick" or any of its maps, e.g. "textures/brick_albedo.png", split into the folder
+// and the name the maps start with
+pub fn split_prefix(prefix: &str) -> (String, String) {
+    let path = std::path::Path::new(prefix.trim());
+    let dir = path.parent().map(|dir| dir.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut stem = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if let Some((name, extension)) = stem.rsplit_once('.')
+        && EXTENSIONS.contains(&extension.to_lowercase().as_str())
+    {
+        stem = name.to_string();
+    }
+    if let Some((name, suffix)) = stem.rsplit_once('_')
+        && MapKind::ALL.iter().any(|kind| kind.suffixes().contains(&suffix.to_lowercase().as_str()))
+    {
+        stem = name.to_string();
+    }
+    (dir, stem)
+}
+
+// The folder and name joined back, what sets are cached and reported by
+pub fn normalize_prefix(prefix: &str) -> String {
+    let (dir, stem) = split_prefix(prefix);
+    std::path::Path::new(&dir).join(stem).to_string_lossy().into_owned()
+}
+
+// The file for each map among `files` (names in one folder), matched case-insensitively. With
+// several candidates the earlier suffix wins, then the earlier extension, then the name, and the
+// others are logged as ignored.
+pub fn pick_maps(stem: &str, files: &[String]) -> [Option<String>; 5] {
+    let start = format!("{}_", stem.to_lowercase());
+    let mut candidates: [Vec<(usize, usize, &String)>; 5] = Default::default();
+    for file in files {
+        let lower = file.to_lowercase();
+        let Some((rest, extension)) = lower.strip_prefix(&start).and_then(|rest| rest.rsplit_once('.')) else {
+            continue;
+        };
+        let Some(extension_rank) = EXTENSIONS.iter().position(|known| *known == extension) else {
+            continue;
+        };
+        for kind in MapKind::ALL {
+            if let Some(suffix_rank) = kind.suffixes().iter().position(|suffix| *suffix == rest) {
+                candidates[kind.index()].push((suffix_rank, extension_rank, file));
+            }
+        }
+    }
+    MapKind::ALL.map(|kind| {
+        let found = &mut candidates[kind.index()];
+        found.sort();
+        let (_, _, chosen) = found.first()?;
+        if found.len() > 1 {
+            let ignored: Vec<&str> = found[1..].iter().map(|(_, _, file)| file.as_str()).collect();
+            log::info!("{}: using {} for the {} map, ignoring {}", stem, chosen, kind.label().to_lowercase(), ignored.join(", "));
+        }
+        Some((*chosen).clone())
+    })
+}
+
+// Decoded and ready to become a Material
+pub struct MaterialSet {
+    pub report: MaterialSetReport,
+    albedo: image::DynamicImage,
+    normal: image::DynamicImage,
+    params: MaterialParams,
+}
+
+impl MaterialSet {
+    // `images` by MapKind, None where the set has no usable file. Occlusion is multiplied into the
+    // albedo, which takes its size when it was defaulted.
+    pub fn new(report: MaterialSetReport, mut images: [Option<image::DynamicImage>; 5]) -> Self {
+        let mut load = |kind: MapKind| images[kind.index()].take();
+        let ao = load(MapKind::Ao);
+        let mut albedo = load(MapKind::Albedo).map(|image| image.to_rgba8()).unwrap_or_else(|| {
+            let (width, height) = ao.as_ref().map_or((1, 1), |ao| (ao.width(), ao.height()));
+            image::RgbaImage::from_pixel(width, height, image::Rgba([255; 4]))
+        });
+        if let Some(ao) = ao {
+            let ao = image::imageops::resize(&ao.to_luma8(), albedo.width(), albedo.height(), image::imageops::FilterType::Triangle);
+            for (pixel, occlusion) in albedo.pixels_mut().zip(ao.pixels()) {
+                for channel in &mut pixel.0[..3] {
+                    *channel = (*channel as u16 * occlusion.0[0] as u16 / 255) as u8;
+                }
+            }
+        }
+        let normal = load(MapKind::Normal).unwrap_or_else(|| image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(FLAT_NORMAL))));
+        let params = MaterialParams {
+            shading_model: ShadingModel::PbrLite,
+            roughness: load(MapKind::Roughness).map_or(DEFAULT_ROUGHNESS, |image| average(&image)),
+            metallic: load(MapKind::Metallic).map_or(DEFAULT_METALLIC, |image| average(&image)),
+            ..Default::default()
+        };
+        Self { report, albedo: image::DynamicImage::ImageRgba8(albedo), normal, params }
+    }
+
+    // A new material every call, materials belong to one model
+    pub fn material(&self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> anyhow::Result<model::Material> {
+        let name = &self.report.prefix;
+        let albedo = texture::Texture::from_image(device, queue, &self.albedo, Some(&format!("{} albedo", name)), false)?;
+        let normal = texture::Texture::from_image(device, queue, &self.normal, Some(&format!("{} normal", name)), true)?;
+        let mut material = model::Material::new(device, name, albedo, normal, layout, self.params);
+        material.material_set = Some(self.report.clone());
+        Ok(material)
+    }
+}
+
+// Decoded sets by normalized prefix, so a set asked for by one of its maps is the same set
+#[derive(Default)]
+pub struct MaterialSetCache {
+    sets: HashMap<String, Arc<MaterialSet>>,
+}
+
+impl MaterialSetCache {
+    pub fn get_or_load(&mut self, prefix: &str, load: impl FnOnce(&str) -> anyhow::Result<MaterialSet>) -> anyhow::Result<Arc<MaterialSet>> {
+        let key = normalize_prefix(prefix);
+        if let Some(set) = self.sets.get(&key) {
+            return Ok(set.clone());
+        }
+        let set = Arc::new(load(&key)?);
+        self.sets.insert(key, set.clone());
+        Ok(set)
+    }
+}
+
+// The map's mean brightness, 0..1
+fn average(image: &image::DynamicImage) -> f32 {
+    let luma = image.to_luma8();
+    let sum: u64 = luma.pixels().map(|pixel| u64::from(pixel.0[0])).sum();
+    sum as f32 / (luma.pixels().len().max(1) as f32 * 255.0)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
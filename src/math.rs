@@ -0,0 +1,447 @@
+/*
+Purpose: Geometry helpers shared by picking, culling and gizmos
+Responsibilities:
+    - Plain data primitives: Ray, Plane, Aabb, Sphere, Frustum
+    - Ray casts against boxes, planes, spheres and triangles, returning the distance along the ray
+    - Build a picking ray from a cursor position and a frustum from a view-projection matrix
+    - ex: the ruler and protractor in the drawer, one of each, everyone borrows them
+Built on cgmath like the rest of the engine rather than glam, so callers convert nothing.
+*/
+
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+
+pub type Vec3 = Vector3<f32>;
+
+// Below this a length, determinant or w is treated as zero
+const EPSILON: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    // Unit length when built through new or from_screen
+    pub direction: Vec3,
+}
+
+impl Ray {
+    // None when the direction has no length
+    pub fn new(origin: Vec3, direction: Vec3) -> Option<Self> {
+        let length = direction.magnitude();
+        (length > EPSILON).then(|| Self { origin, direction: direction / length })
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // Ray from the near plane through the pixel under `cursor` (physical pixels, y down).
+    // None for an empty viewport or a matrix that can't be inverted.
+    pub fn from_screen(cursor: (f32, f32), viewport_size: (f32, f32), inv_view_proj: Matrix4<f32>) -> Option<Self> {
+        let (width, height) = viewport_size;
+        if width <= 0.0 || height <= 0.0 {
+            return None;
+        }
+        let ndc_x = cursor.0 / width * 2.0 - 1.0;
+        let ndc_y = 1.0 - cursor.1 / height * 2.0;
+        // wgpu clip space depth goes from 0 (near) to 1 (far)
+        let unproject = |depth: f32| {
+            let point = inv_view_proj * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+            (point.w.abs() > EPSILON).then(|| point.truncate() / point.w)
+        };
+        let near = unproject(0.0)?;
+        let far = unproject(1.0)?;
+        Self::new(near, far - near)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+// Axis aligned box, min <= max on every axis. A side may be zero (a flat box).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    // None for no points
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| Self {
+            min: Vector3::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y), aabb.min.z.min(point.z)),
+            max: Vector3::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y), aabb.max.z.max(point.z)),
+        }))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vector3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        std::array::from_fn(|i| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        })
+    }
+
+    // Outward normal of the face nearest to `point`, for a point on or near the surface
+    pub fn face_normal(&self, point: Vec3) -> Vec3 {
+        let mut nearest = (f32::INFINITY, Vec3::unit_y());
+        for axis in 0..3 {
+            let mut normal = Vec3::new(0.0, 0.0, 0.0);
+            for (distance, sign) in [((point[axis] - self.min[axis]).abs(), -1.0), ((self.max[axis] - point[axis]).abs(), 1.0)] {
+                if distance < nearest.0 {
+                    normal[axis] = sign;
+                    nearest = (distance, normal);
+                }
+            }
+        }
+        nearest.1
+    }
+
+    // Smallest box holding this one after `transform`. Each output axis sums, per input axis,
+    // whichever of min or max pushes it furthest (Arvo's method).
+    pub fn transformed(&self, transform: Matrix4<f32>) -> Aabb {
+        let translation = transform.w.truncate();
+        let mut min = translation;
+        let mut max = translation;
+        for column in 0..3 {
+            for row in 0..3 {
+                let a = transform[column][row] * self.min[column];
+                let b = transform[column][row] * self.max[column];
+                min[row] += a.min(b);
+                max[row] += a.max(b);
+            }
+        }
+        Aabb { min, max }
+    }
+}
+
+// Points p with normal . p + distance = 0, normal is unit length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    // Counter-clockwise a, b, c seen from the side the normal points to. None when the points
+    // are on one line.
+    #[allow(dead_code)] // for collision code, the engine builds its planes from matrices
+    pub fn from_points(a: Vec3, b: Vec3, c: Vec3) -> Option<Self> {
+        let normal = (b - a).cross(c - a);
+        let length = normal.magnitude();
+        if length <= EPSILON {
+            return None;
+        }
+        let normal = normal / length;
+        Some(Self { normal, distance: -normal.dot(a) })
+    }
+
+    // From the (a, b, c, d) of ax + by + cz + d = 0, normalized. None if (a, b, c) is zero.
+    pub fn from_coefficients(coefficients: Vector4<f32>) -> Option<Self> {
+        let length = coefficients.truncate().magnitude();
+        (length > EPSILON).then(|| Self {
+            normal: coefficients.truncate() / length,
+            distance: coefficients.w / length,
+        })
+    }
+
+    // Positive on the side the normal points to
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+// The six planes of a camera's view volume, normals pointing inwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Planes read straight off the matrix rows (Gribb/Hartmann) with wgpu's 0..1 depth range.
+    // None for a degenerate matrix.
+    pub fn from_view_proj(view_proj: Matrix4<f32>) -> Option<Self> {
+        let row = |i: usize| Vector4::new(view_proj.x[i], view_proj.y[i], view_proj.z[i], view_proj.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Some(Self {
+            planes: [
+                Plane::from_coefficients(w + x)?, // left
+                Plane::from_coefficients(w - x)?, // right
+                Plane::from_coefficients(w + y)?, // bottom
+                Plane::from_coefficients(w - y)?, // top
+                Plane::from_coefficients(z)?,     // near
+                Plane::from_coefficients(w - z)?, // far
+            ],
+        })
+    }
+
+    pub fn contains_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    // Conservative: a box near a frustum corner can pass without being visible
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal
+            let corner = Vec3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+// Distance along the ray to where it enters the box, 0 when it starts inside. Slab test, axes
+// the ray runs parallel to only need the origin between the slabs.
+pub fn ray_aabb_intersect(ray: &Ray, aabb: &Aabb) -> Option<f32> {
+    let mut t_near = 0.0f32;
+    let mut t_far = f32::INFINITY;
+    for axis in 0..3 {
+        let origin = ray.origin[axis];
+        let direction = ray.direction[axis];
+        if direction.abs() < EPSILON {
+            if origin < aabb.min[axis] || origin > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (aabb.min[axis] - origin) / direction;
+        let t1 = (aabb.max[axis] - origin) / direction;
+        t_near = t_near.max(t0.min(t1));
+        t_far = t_far.min(t0.max(t1));
+        if t_near > t_far {
+            return None;
+        }
+    }
+    Some(t_near)
+}
+
+// Distance along the ray to where it enters the sphere, 0 when it starts inside
+#[allow(dead_code)] // for collision code, picking casts against boxes
+pub fn ray_sphere_intersect(ray: &Ray, sphere: &Sphere) -> Option<f32> {
+    let to_origin = ray.origin - sphere.center;
+    let c = to_origin.magnitude2() - sphere.radius * sphere.radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let a = ray.direction.magnitude2();
+    if a < EPSILON {
+        return None;
+    }
+    let half_b = to_origin.dot(ray.direction);
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // The origin is outside, so both hits are on the same side of it
+    let t = (-half_b - discriminant.sqrt()) / a;
+    (t >= 0.0).then_some(t)
+}
+
+// Distance along the ray to where it crosses the plane, from either side. None for rays
+// parallel to the plane and crossings behind the origin.
+pub fn ray_plane_intersect(ray: &Ray, plane: &Plane) -> Option<f32> {
+    let facing = plane.normal.dot(ray.direction);
+    if facing.abs() < EPSILON {
+        return None;
+    }
+    let t = -plane.signed_distance(ray.origin) / facing;
+    (t >= 0.0).then_some(t)
+}
+
+// Möller–Trumbore, hits either side of the triangle. None for degenerate triangles, rays in
+// the triangle's plane, and hits behind the origin.
+#[allow(dead_code)] // for collision code, picking casts against boxes
+pub fn ray_triangle_intersect(ray: &Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse;
+    (t >= 0.0).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Point3, SquareMatrix};
+
+    const TOLERANCE: f32 = 1e-4;
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray::new(origin.into(), direction.into()).unwrap()
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    fn camera_view_proj() -> Matrix4<f32> {
+        let view = Matrix4::look_at_rh(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0), Vec3::unit_y());
+        crate::camera::OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(60.0), 1.0, 0.1, 100.0) * view
+    }
+
+    #[test]
+    fn ray_direction_is_normalized_and_zero_length_rejected() {
+        assert_eq!(ray([0.0; 3], [0.0, 0.0, -4.0]).direction, Vec3::new(0.0, 0.0, -1.0));
+        assert!(Ray::new(Vec3::unit_x(), Vec3::new(0.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn screen_ray_through_the_center_looks_at_the_target() {
+        let ray = Ray::from_screen((50.0, 50.0), (100.0, 100.0), camera_view_proj().invert().unwrap()).unwrap();
+        assert!((ray.direction - Vec3::new(0.0, 0.0, -1.0)).magnitude() < TOLERANCE);
+        assert!((ray.origin.z - 9.9).abs() < TOLERANCE, "starts on the near plane, {:?}", ray.origin);
+        assert!(Ray::from_screen((0.0, 0.0), (0.0, 100.0), Matrix4::identity()).is_none());
+    }
+
+    #[test]
+    fn ray_aabb_hits_and_misses() {
+        let hit = ray_aabb_intersect(&ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]), &unit_box()).unwrap();
+        assert!((hit - 4.0).abs() < TOLERANCE);
+        assert_eq!(ray_aabb_intersect(&ray([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]), &unit_box()), Some(0.0));
+        assert_eq!(ray_aabb_intersect(&ray([0.0, 3.0, 5.0], [0.0, 0.0, -1.0]), &unit_box()), None);
+        // Behind the origin
+        assert_eq!(ray_aabb_intersect(&ray([0.0, 0.0, 5.0], [0.0, 0.0, 1.0]), &unit_box()), None);
+    }
+
+    #[test]
+    fn ray_aabb_hits_a_flat_box() {
+        let flat = Aabb::new(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0));
+        let hit = ray_aabb_intersect(&ray([0.5, 2.0, 0.5], [0.0, -1.0, 0.0]), &flat).unwrap();
+        assert!((hit - 2.0).abs() < TOLERANCE);
+        // Running parallel beside it
+        assert_eq!(ray_aabb_intersect(&ray([0.0, 1.0, 5.0], [0.0, 0.0, -1.0]), &flat), None);
+    }
+
+    #[test]
+    fn ray_plane_hits_and_misses() {
+        let ground = Plane { normal: Vec3::unit_y(), distance: 0.0 };
+        let hit = ray_plane_intersect(&ray([0.0, 3.0, 0.0], [0.0, -1.0, 0.0]), &ground).unwrap();
+        assert!((hit - 3.0).abs() < TOLERANCE);
+        // From below
+        assert!(ray_plane_intersect(&ray([0.0, -2.0, 0.0], [0.0, 1.0, 0.0]), &ground).is_some());
+        assert_eq!(ray_plane_intersect(&ray([0.0, 3.0, 0.0], [0.0, 1.0, 0.0]), &ground), None);
+        assert_eq!(ray_plane_intersect(&ray([0.0, 3.0, 0.0], [1.0, 0.0, 0.0]), &ground), None);
+    }
+
+    #[test]
+    fn ray_sphere_hits_and_misses() {
+        let sphere = Sphere { center: Vec3::new(0.0, 0.0, 0.0), radius: 1.0 };
+        let hit = ray_sphere_intersect(&ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]), &sphere).unwrap();
+        assert!((hit - 4.0).abs() < TOLERANCE);
+        assert_eq!(ray_sphere_intersect(&ray([0.0, 0.5, 0.0], [1.0, 0.0, 0.0]), &sphere), Some(0.0));
+        assert_eq!(ray_sphere_intersect(&ray([0.0, 2.0, 5.0], [0.0, 0.0, -1.0]), &sphere), None);
+        // Behind the origin
+        assert_eq!(ray_sphere_intersect(&ray([0.0, 0.0, 5.0], [0.0, 0.0, 1.0]), &sphere), None);
+        // A zero-length direction only hits from inside
+        let stuck = |origin: [f32; 3]| Ray { origin: origin.into(), direction: Vec3::new(0.0, 0.0, 0.0) };
+        assert_eq!(ray_sphere_intersect(&stuck([0.0, 0.0, 5.0]), &sphere), None);
+        assert_eq!(ray_sphere_intersect(&stuck([0.0, 0.0, 0.5]), &sphere), Some(0.0));
+    }
+
+    #[test]
+    fn ray_triangle_hits_and_misses() {
+        let (a, b, c) = (Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let hit = ray_triangle_intersect(&ray([0.0, 0.0, 3.0], [0.0, 0.0, -1.0]), a, b, c).unwrap();
+        assert!((hit - 3.0).abs() < TOLERANCE);
+        // From the back side
+        assert!(ray_triangle_intersect(&ray([0.0, 0.0, -3.0], [0.0, 0.0, 1.0]), a, b, c).is_some());
+        assert_eq!(ray_triangle_intersect(&ray([2.0, 0.0, 3.0], [0.0, 0.0, -1.0]), a, b, c), None);
+        // Behind the origin
+        assert_eq!(ray_triangle_intersect(&ray([0.0, 0.0, 3.0], [0.0, 0.0, 1.0]), a, b, c), None);
+        // In the triangle's plane
+        assert_eq!(ray_triangle_intersect(&ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]), a, b, c), None);
+        // Degenerate, all three corners on one line
+        assert_eq!(ray_triangle_intersect(&ray([0.0, 0.0, 3.0], [0.0, 0.0, -1.0]), a, b, Vec3::new(0.0, -1.0, 0.0)), None);
+        assert_eq!(ray_triangle_intersect(&ray([0.0, 0.0, 3.0], [0.0, 0.0, -1.0]), a, a, a), None);
+    }
+
+    #[test]
+    fn plane_from_points_faces_the_counter_clockwise_side() {
+        let plane = Plane::from_points(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 2.0, 1.0), Vec3::new(1.0, 2.0, 0.0)).unwrap();
+        assert!((plane.normal - Vec3::unit_y()).magnitude() < TOLERANCE, "{:?}", plane);
+        assert!((plane.signed_distance(Vec3::new(3.0, 5.0, -1.0)) - 3.0).abs() < TOLERANCE);
+        // On one line, or all the same point
+        assert!(Plane::from_points(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), Vec3::new(2.0, 2.0, 2.0)).is_none());
+        assert!(Plane::from_points(Vec3::unit_x(), Vec3::unit_x(), Vec3::unit_x()).is_none());
+    }
+
+    #[test]
+    fn plane_from_zero_coefficients_is_rejected() {
+        assert!(Plane::from_coefficients(Vector4::new(0.0, 0.0, 0.0, 1.0)).is_none());
+        let plane = Plane::from_coefficients(Vector4::new(0.0, 2.0, 0.0, -4.0)).unwrap();
+        assert!((plane.signed_distance(Vec3::new(0.0, 5.0, 0.0)) - 3.0).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn aabb_from_points_and_transformed() {
+        assert!(Aabb::from_points(std::iter::empty()).is_none());
+        let aabb = Aabb::from_points([Vec3::new(1.0, -2.0, 0.0), Vec3::new(-1.0, 2.0, 3.0)]).unwrap();
+        assert_eq!(aabb, Aabb::new(Vec3::new(-1.0, -2.0, 0.0), Vec3::new(1.0, 2.0, 3.0)));
+
+        let turned = unit_box().transformed(Matrix4::from_translation(Vec3::new(5.0, 0.0, 0.0)) * Matrix4::from_angle_y(Deg(45.0)));
+        let half = 2.0f32.sqrt();
+        assert!((turned.min - Vec3::new(5.0 - half, -1.0, -half)).magnitude() < TOLERANCE, "{:?}", turned);
+        assert!((turned.max - Vec3::new(5.0 + half, 1.0, half)).magnitude() < TOLERANCE, "{:?}", turned);
+    }
+
+    #[test]
+    fn aabb_face_normal_points_out_of_the_nearest_face() {
+        assert_eq!(unit_box().face_normal(Vec3::new(0.2, 0.99, 0.1)), Vec3::unit_y());
+        assert_eq!(unit_box().face_normal(Vec3::new(-1.0, 0.0, 0.5)), -Vec3::unit_x());
+    }
+
+    #[test]
+    fn frustum_keeps_what_the_camera_sees() {
+        let frustum = Frustum::from_view_proj(camera_view_proj()).unwrap();
+        assert!(frustum.contains_sphere(&Sphere { center: Vec3::new(0.0, 0.0, 0.0), radius: 1.0 }));
+        assert!(!frustum.contains_sphere(&Sphere { center: Vec3::new(0.0, 0.0, 20.0), radius: 1.0 }));
+        // Touching a side plane counts
+        assert!(frustum.contains_sphere(&Sphere { center: Vec3::new(7.0, 0.0, 0.0), radius: 2.0 }));
+        assert!(frustum.intersects_aabb(&unit_box()));
+        assert!(!frustum.intersects_aabb(&Aabb::new(Vec3::new(50.0, -1.0, -1.0), Vec3::new(52.0, 1.0, 1.0))));
+        assert!(Frustum::from_view_proj(Matrix4::from_scale(0.0)).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+/*
+Purpose: Measure distances in the scene with two clicks
+Responsibilities:
+    - Keep the committed measurements and the start of the one being made
+    - Snap the cursor's hit point to an instance origin close to it on screen
+    - Build the lines of every measurement and the rubber band from the start to the cursor
+    - Label each line with its length at its screen-space middle
+    - ex: a tape measure hooked on one wall and pulled across to the other
+*/
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{debug_lines::{self, LineVertex}, transform_gizmo::ScreenProjection, units::SceneUnits};
+
+// In points around the cursor
+const SNAP_RADIUS: f32 = 12.0;
+// In meters, multiplied by the scene units
+const END_MARKER_SIZE: f32 = 0.08;
+const LINE_COLOR: [f32; 3] = [1.0, 0.85, 0.2];
+const RUBBER_BAND_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub start: Vector3<f32>,
+    pub end: Vector3<f32>,
+}
+
+impl Measurement {
+    // In scene units
+    pub fn length(&self) -> f32 {
+        (self.end - self.start).magnitude()
+    }
+}
+
+#[derive(Debug)]
+pub struct Measurements {
+    list: Vec<Measurement>,
+    // Clicks in the main window measure while on
+    active: bool,
+    // First click of the measurement being made
+    start: Option<Vector3<f32>>,
+    // Where the next click would land, kept up to date every frame by the main window
+    pub hover: Option<Vector3<f32>>,
+    pub snap: bool,
+}
+
+impl Default for Measurements {
+    fn default() -> Self {
+        Self { list: Vec::new(), active: false, start: None, hover: None, snap: true }
+    }
+}
+
+impl Measurements {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn begin(&mut self) {
+        self.active = true;
+    }
+
+    // Drops a started measurement, the committed ones stay
+    pub fn cancel(&mut self) {
+        self.active = false;
+        self.start = None;
+        self.hover = None;
+    }
+
+    // The first click starts a measurement, the second commits it and the next one starts over.
+    // Returns the committed measurement.
+    pub fn click(&mut self, point: Vector3<f32>) -> Option<Measurement> {
+        match self.start.take() {
+            Some(start) => {
+                let measurement = Measurement { start, end: point };
+                self.list.push(measurement);
+                Some(measurement)
+            }
+            None => {
+                self.start = Some(point);
+                None
+            }
+        }
+    }
+
+    pub fn list(&self) -> &[Measurement] {
+        &self.list
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.list.len() {
+            self.list.remove(index);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    // Every measurement, the rubber band and a cross at each end. `scale` is the gizmo scale.
+    pub fn lines(&self, scale: f32) -> Vec<LineVertex> {
+        let mut lines = Vec::new();
+        for measurement in &self.list {
+            lines.push(LineVertex { position: measurement.start.into(), color: LINE_COLOR });
+            lines.push(LineVertex { position: measurement.end.into(), color: LINE_COLOR });
+            debug_lines::cross(measurement.start, END_MARKER_SIZE * scale, LINE_COLOR, &mut lines);
+            debug_lines::cross(measurement.end, END_MARKER_SIZE * scale, LINE_COLOR, &mut lines);
+        }
+        if let Some(start) = self.start {
+            debug_lines::cross(start, END_MARKER_SIZE * scale, RUBBER_BAND_COLOR, &mut lines);
+            if let Some(hover) = self.hover {
+                lines.push(LineVertex { position: start.into(), color: RUBBER_BAND_COLOR });
+                lines.push(LineVertex { position: hover.into(), color: RUBBER_BAND_COLOR });
+            }
+        }
+        if let Some(hover) = self.hover.filter(|_| self.active) {
+            debug_lines::cross(hover, END_MARKER_SIZE * scale, RUBBER_BAND_COLOR, &mut lines);
+        }
+        lines
+    }
+
+    // The length of every line next to its middle, the rubber band's included
+    pub fn draw_labels(&self, ctx: &egui::Context, screen: &ScreenProjection, units: SceneUnits) {
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("measurement_labels")));
+        let rubber_band = self.start.zip(self.hover).map(|(start, end)| Measurement { start, end });
+        for measurement in self.list.iter().chain(rubber_band.as_ref()) {
+            let Some(middle) = screen.project((measurement.start + measurement.end) * 0.5) else {
+                continue;
+            };
+            let text = units.format_distance(measurement.length());
+            let galley = painter.layout_no_wrap(text, egui::FontId::proportional(13.0), egui::Color32::WHITE);
+            let rect = egui::Rect::from_center_size(middle, galley.size()).expand(3.0);
+            painter.rect_filled(rect, 3.0, egui::Color32::from_black_alpha(160));
+            painter.galley(rect.min + egui::vec2(3.0, 3.0), galley, egui::Color32::WHITE);
+        }
+    }
+}
+
+// The point of `candidates` closest to `cursor` on screen, if any is within SNAP_RADIUS
+pub fn snap_point(screen: &ScreenProjection, cursor: egui::Pos2, candidates: impl Iterator<Item = Vector3<f32>>) -> Option<Vector3<f32>> {
+    candidates
+        .filter_map(|point| Some((screen.project(point)?.distance(cursor), point)))
+        .filter(|(distance, _)| *distance <= SNAP_RADIUS)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, point)| point)
+}
@@ -0,0 +1,197 @@
+/*
+Purpose: Upload each procedural shape once and share it
+Responsibilities:
+    - Define GpuMesh (vertex + index buffer of one piece of geometry), drawn from its vertex
+      buffer or with the shader pulling its vertices (vertex_pulling.rs)
+    - Build the shapes.rs primitives on first use, keyed by ShapeKey, and hand out Arc<GpuMesh>
+    - Report how many meshes are resident and how much buffer memory they take
+    - ex: the tool library, one of each tool, borrowed by whoever needs it
+*/
+
+use crate::{gpu_debug::debug_label, gpu_memory::{self, Tracked}, shapes, vertex::Vertex, vertex_pulling::{self, PulledMesh, VertexLayout}};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub struct GpuMesh {
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
+    num_elements: u32,
+    label: String,
+    // The pulling path's bind group, made on the first pulled draw. Replaced geometry that fits
+    // is written into the same buffers and keeps it.
+    pulled: OnceLock<PulledMesh>,
+}
+
+impl GpuMesh {
+    // For geometry that isn't one of the library shapes (SDF meshes, ...)
+    pub fn from_geometry(device: &wgpu::Device, label: &str, vertices: &[Vertex], indices: &[u32]) -> Self {
+        Self::upload(device, label, &linear_colors(vertices), indices)
+    }
+
+    // `vertices` already have linear colors
+    fn upload(device: &wgpu::Device, label: &str, vertices: &[Vertex], indices: &[u32]) -> Self {
+        Self {
+            vertex_buffer: gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: debug_label!("{} Vertex Buffer", label).as_deref(),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            }),
+            index_buffer: gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: debug_label!("{} Index Buffer", label).as_deref(),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            }),
+            num_elements: indices.len() as u32,
+            label: label.to_string(),
+            pulled: OnceLock::new(),
+        }
+    }
+
+    // Swap in new geometry, the buffers are only reallocated when it doesn't fit
+    pub fn replace(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, label: &str, vertices: &[Vertex], indices: &[u32]) {
+        let vertices = linear_colors(vertices);
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(indices);
+        if vertex_bytes.len() as u64 > self.vertex_buffer.size() || index_bytes.len() as u64 > self.index_buffer.size() {
+            *self = Self::upload(device, label, &vertices, indices);
+            return;
+        }
+        if !indices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, vertex_bytes);
+            queue.write_buffer(&self.index_buffer, 0, index_bytes);
+        }
+        self.num_elements = indices.len() as u32;
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.vertex_buffer.size() + self.index_buffer.size()
+    }
+
+    pub fn triangles(&self) -> u32 {
+        self.num_elements / 3
+    }
+
+    // Binds the mesh and draws the `instances` range of `instance_buffer`
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, instance_buffer: &wgpu::Buffer, instances: Range<u32>) {
+        if self.num_elements == 0 || instances.is_empty() {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_elements, 0, instances);
+    }
+
+    // Small enough to bind as a storage buffer
+    pub fn can_pull(&self, device: &wgpu::Device) -> bool {
+        vertex_pulling::can_pull(device, &self.vertex_buffer)
+    }
+
+    // Like draw, with the pulled shape pipeline set and `bind_group_layout` its group 3
+    pub fn draw_pulled(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        if self.num_elements == 0 || instances.is_empty() {
+            return;
+        }
+        let pulled = self.pulled.get_or_init(|| PulledMesh::new(device, bind_group_layout, &self.label, &self.vertex_buffer, VertexLayout::SHAPE));
+        render_pass.set_bind_group(3, &pulled.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.num_elements, 0, instances);
+    }
+}
+
+// The shapes' vertex colors as shape.wgsl lights them, see color.rs
+fn linear_colors(vertices: &[Vertex]) -> Vec<Vertex> {
+    vertices.iter().copied().map(Vertex::linear).collect()
+}
+
+// One shapes.rs builder and its parameters. Shapes are unit sized, instances scale them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShapeKey {
+    Plane,
+    Pyramid,
+    Cube,
+    // Radius 0.5
+    Sphere { sectors: u32, stacks: u32 },
+}
+
+impl ShapeKey {
+    pub fn build(self) -> (Vec<Vertex>, Vec<u32>) {
+        match self {
+            ShapeKey::Plane => shapes::create_plane(),
+            ShapeKey::Pyramid => shapes::create_pyramid(),
+            ShapeKey::Cube => shapes::create_cube(),
+            ShapeKey::Sphere { sectors, stacks } => shapes::create_sphere(0.5, sectors, stacks),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MeshLibraryStats {
+    pub meshes: usize,
+    pub bytes: u64,
+}
+
+// Shared between windows through the ShapePipeline. Meshes stay resident once built.
+#[derive(Default)]
+pub struct MeshLibrary {
+    meshes: Mutex<HashMap<ShapeKey, Arc<GpuMesh>>>,
+}
+
+impl MeshLibrary {
+    // Every caller asking for the same key gets the same buffers
+    pub fn get(&self, device: &wgpu::Device, key: ShapeKey) -> Arc<GpuMesh> {
+        let mut meshes = self.meshes.lock().unwrap();
+        meshes
+            .entry(key)
+            .or_insert_with(|| {
+                let (vertices, indices) = key.build();
+                Arc::new(GpuMesh::from_geometry(device, &format!("{:?}", key), &vertices, &indices))
+            })
+            .clone()
+    }
+
+    pub fn stats(&self) -> MeshLibraryStats {
+        let meshes = self.meshes.lock().unwrap();
+        MeshLibraryStats {
+            meshes: meshes.len(),
+            bytes: meshes.values().map(|mesh| mesh.bytes()).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::FutureExt;
+
+    fn device() -> wgpu::Device {
+        let adapter = wgpu::Instance::default().request_adapter(&wgpu::RequestAdapterOptions::default()).block_on().expect("no usable GPU adapter");
+        adapter.request_device(&wgpu::DeviceDescriptor::default()).block_on().unwrap().0
+    }
+
+    #[test]
+    fn users_of_one_shape_share_its_buffers() {
+        let device = device();
+        let library = MeshLibrary::default();
+        let first = library.get(&device, ShapeKey::Cube);
+        let second = library.get(&device, ShapeKey::Cube);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.triangles(), 12);
+
+        let sphere = library.get(&device, ShapeKey::Sphere { sectors: 8, stacks: 4 });
+        assert!(!Arc::ptr_eq(&first, &sphere));
+        assert!(!Arc::ptr_eq(&sphere, &library.get(&device, ShapeKey::Sphere { sectors: 16, stacks: 4 })));
+        let stats = library.stats();
+        assert_eq!(stats.meshes, 3);
+        assert!(stats.bytes >= first.bytes() + sphere.bytes());
+    }
+}
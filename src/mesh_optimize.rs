@@ -0,0 +1,378 @@
+/*
+Purpose: Optional clean up pass for meshes imported from OBJ files
+Responsibilities:
+    - Weld vertices whose position, UV and normal agree within small epsilons into shared indices
+    - Recompute normals with a smoothing angle, faces meeting at a sharper angle keep split normals
+    - Reorder triangles for the post-transform vertex cache (Tipsify) and vertices by first use
+    - Count vertices, indices and cache misses before and after for the log and the stats panel
+    - ex: resources::load_model runs it on every mesh when LoadOptions asks for any of it
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{import_options::ImportOptions, model::ModelVertex, uv_fallback::UvFallback};
+
+// Post-transform cache size the triangle order is tuned for, ACMR is measured with it too
+const CACHE_SIZE: usize = 16;
+
+// Largest per-component difference for two vertices to be welded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeldEpsilons {
+    pub position: f32,
+    pub uv: f32,
+    pub normal: f32,
+}
+
+impl Default for WeldEpsilons {
+    fn default() -> Self {
+        Self {
+            position: 1e-5,
+            uv: 1e-5,
+            normal: 1e-3,
+        }
+    }
+}
+
+// Everything is off by default, some assets rely on their split normals or index order
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoadOptions {
+    pub weld: bool,
+    pub cache_optimize: bool,
+    // In degrees. Recomputing normals leaves one vertex per face corner, so this welds too.
+    pub smoothing_angle: Option<f32>,
+    pub epsilons: WeldEpsilons,
+    // Not a clean up, but decided at load time too: material textures as arrays, see material_array.rs
+    pub pack_textures: bool,
+    // Per file rather than per run, scale and axis conversion applied to the vertices as they are built
+    pub import: ImportOptions,
+    // Only for meshes without texture coordinates, so on by default: files with UVs keep theirs
+    pub uv_fallback: UvFallback,
+}
+
+impl LoadOptions {
+    pub fn any(&self) -> bool {
+        self.weld || self.cache_optimize || self.smoothing_angle.is_some()
+    }
+}
+
+// Summed over a model's meshes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OptimizeStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub indices_before: usize,
+    pub indices_after: usize,
+    cache_misses_before: usize,
+    cache_misses_after: usize,
+}
+
+impl OptimizeStats {
+    pub fn add(&mut self, other: &OptimizeStats) {
+        self.vertices_before += other.vertices_before;
+        self.vertices_after += other.vertices_after;
+        self.indices_before += other.indices_before;
+        self.indices_after += other.indices_after;
+        self.cache_misses_before += other.cache_misses_before;
+        self.cache_misses_after += other.cache_misses_after;
+    }
+
+    // Average cache miss ratio: vertex shader runs per triangle, 0.5 is the best a grid can do
+    pub fn acmr_before(&self) -> f32 {
+        acmr(self.cache_misses_before, self.indices_before)
+    }
+
+    pub fn acmr_after(&self) -> f32 {
+        acmr(self.cache_misses_after, self.indices_after)
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} -> {} vertices, {} -> {} indices, ACMR {:.2} -> {:.2}",
+            self.vertices_before,
+            self.vertices_after,
+            self.indices_before,
+            self.indices_after,
+            self.acmr_before(),
+            self.acmr_after()
+        )
+    }
+}
+
+fn acmr(misses: usize, indices: usize) -> f32 {
+    if indices < 3 { 0.0 } else { misses as f32 / (indices / 3) as f32 }
+}
+
+// Tangents aren't touched, resources::load_model computes them from the result
+pub fn optimize(vertices: Vec<ModelVertex>, indices: Vec<u32>, options: &LoadOptions) -> (Vec<ModelVertex>, Vec<u32>, OptimizeStats) {
+    let mut stats = OptimizeStats {
+        vertices_before: vertices.len(),
+        indices_before: indices.len(),
+        cache_misses_before: cache_misses(&indices),
+        ..OptimizeStats::default()
+    };
+    let (mut vertices, mut indices) = (vertices, indices);
+
+    if let Some(angle) = options.smoothing_angle {
+        (vertices, indices) = smooth_normals(&vertices, &indices, angle, options.epsilons.position);
+    }
+    if options.weld || options.smoothing_angle.is_some() {
+        (vertices, indices) = weld(&vertices, &indices, &options.epsilons);
+        // Welding can collapse a sliver triangle's corners into one vertex
+        indices = indices
+            .chunks_exact(3)
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[0] != t[2])
+            .flatten()
+            .copied()
+            .collect();
+    }
+    if options.cache_optimize {
+        indices = tipsify(&indices, vertices.len(), CACHE_SIZE);
+        (vertices, indices) = reorder_vertices(&vertices, &indices);
+    }
+
+    stats.vertices_after = vertices.len();
+    stats.indices_after = indices.len();
+    stats.cache_misses_after = cache_misses(&indices);
+    (vertices, indices, stats)
+}
+
+// Hashes positions into cells one epsilon wide, anything within epsilon is in a neighbouring cell
+struct PositionGrid {
+    cell: f32,
+    cells: HashMap<[i64; 3], Vec<u32>>,
+}
+
+impl PositionGrid {
+    fn new(epsilon: f32) -> Self {
+        Self {
+            cell: epsilon.max(f32::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn key(&self, position: [f32; 3]) -> [i64; 3] {
+        position.map(|c| (c / self.cell).floor() as i64)
+    }
+
+    fn find(&self, position: [f32; 3], mut matches: impl FnMut(u32) -> bool) -> Option<u32> {
+        let [x, y, z] = self.key(position);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let found = self
+                        .cells
+                        .get(&[x + dx, y + dy, z + dz])
+                        .and_then(|ids| ids.iter().copied().find(|&id| matches(id)));
+                    if found.is_some() {
+                        return found;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn insert(&mut self, position: [f32; 3], id: u32) {
+        self.cells.entry(self.key(position)).or_default().push(id);
+    }
+}
+
+fn within<const N: usize>(a: [f32; N], b: [f32; N], epsilon: f32) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() <= epsilon)
+}
+
+fn weld(vertices: &[ModelVertex], indices: &[u32], epsilons: &WeldEpsilons) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut grid = PositionGrid::new(epsilons.position);
+    let mut welded: Vec<ModelVertex> = Vec::new();
+    let mut remap = Vec::with_capacity(vertices.len());
+    for vertex in vertices {
+        let same = |id: u32| {
+            let other = &welded[id as usize];
+            within(other.position, vertex.position, epsilons.position)
+                && within(other.tex_coords, vertex.tex_coords, epsilons.uv)
+                && within(other.normal, vertex.normal, epsilons.normal)
+        };
+        let id = match grid.find(vertex.position, same) {
+            Some(id) => id,
+            None => {
+                let id = welded.len() as u32;
+                welded.push(*vertex);
+                grid.insert(vertex.position, id);
+                id
+            }
+        };
+        remap.push(id);
+    }
+    let indices = indices.iter().map(|&i| remap[i as usize]).collect();
+    (welded, indices)
+}
+
+// Every face corner gets the area weighted normal of the faces around its position that are
+// within the smoothing angle of its own face. Returns one vertex per corner.
+fn smooth_normals(vertices: &[ModelVertex], indices: &[u32], angle_degrees: f32, position_epsilon: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let cos_limit = angle_degrees.to_radians().cos();
+    let position = |i: u32| Vector3::from(vertices[i as usize].position);
+    // Length is twice the face's area
+    let face_normals: Vec<Vector3<f32>> = indices
+        .chunks_exact(3)
+        .map(|t| (position(t[1]) - position(t[0])).cross(position(t[2]) - position(t[0])))
+        .collect();
+    let unit = |n: Vector3<f32>| if n.magnitude2() > 0.0 { n.normalize() } else { n };
+
+    // Corners at the same position (within epsilon) share a group, each group lists its faces
+    let mut grid = PositionGrid::new(position_epsilon);
+    let mut group_positions: Vec<[f32; 3]> = Vec::new();
+    let mut group_faces: Vec<Vec<usize>> = Vec::new();
+    let mut corner_groups = Vec::with_capacity(indices.len());
+    for (corner, &i) in indices.iter().enumerate().take(face_normals.len() * 3) {
+        let p = vertices[i as usize].position;
+        let group = match grid.find(p, |g| within(group_positions[g as usize], p, position_epsilon)) {
+            Some(group) => group as usize,
+            None => {
+                grid.insert(p, group_positions.len() as u32);
+                group_positions.push(p);
+                group_faces.push(Vec::new());
+                group_positions.len() - 1
+            }
+        };
+        group_faces[group].push(corner / 3);
+        corner_groups.push(group);
+    }
+
+    let corners = corner_groups
+        .iter()
+        .enumerate()
+        .map(|(corner, &group)| {
+            let face = unit(face_normals[corner / 3]);
+            let sum = group_faces[group]
+                .iter()
+                .map(|&other| face_normals[other])
+                .filter(|&n| unit(n).dot(face) >= cos_limit)
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+            let mut vertex = vertices[indices[corner] as usize];
+            // Degenerate faces keep the normal they were loaded with
+            if sum.magnitude2() > 0.0 {
+                vertex.normal = sum.normalize().into();
+            }
+            vertex
+        })
+        .collect::<Vec<_>>();
+    let indices = (0..corners.len() as u32).collect();
+    (corners, indices)
+}
+
+// Tipsify (Sander, Nehab, Barczak 2007): fan out around a vertex, then continue from the
+// candidate most likely to still be in the cache, or a recently used one at a dead end
+fn tipsify(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let indices = &indices[..triangle_count * 3];
+
+    // Triangles around each vertex, packed one vertex after the other
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for &i in indices {
+        offsets[i as usize + 1] += 1;
+    }
+    for v in 0..vertex_count {
+        offsets[v + 1] += offsets[v];
+    }
+    let mut fill = offsets.clone();
+    let mut adjacency = vec![0usize; indices.len()];
+    for (corner, &i) in indices.iter().enumerate() {
+        adjacency[fill[i as usize]] = corner / 3;
+        fill[i as usize] += 1;
+    }
+
+    // Triangles not emitted yet around each vertex
+    let mut live: Vec<usize> = (0..vertex_count).map(|v| offsets[v + 1] - offsets[v]).collect();
+    let mut cache_time = vec![0usize; vertex_count];
+    let mut emitted = vec![false; triangle_count];
+    let mut dead_end = Vec::new();
+    let mut output = Vec::with_capacity(indices.len());
+    let mut time = cache_size + 1;
+    let mut cursor = 0;
+
+    let mut fanning = (vertex_count > 0).then_some(0usize);
+    while let Some(f) = fanning {
+        let mut candidates = Vec::new();
+        for &t in &adjacency[offsets[f]..offsets[f + 1]] {
+            if emitted[t] {
+                continue;
+            }
+            emitted[t] = true;
+            for &v in &indices[t * 3..t * 3 + 3] {
+                let v = v as usize;
+                output.push(v as u32);
+                dead_end.push(v);
+                candidates.push(v);
+                live[v] -= 1;
+                if time - cache_time[v] > cache_size {
+                    cache_time[v] = time;
+                    time += 1;
+                }
+            }
+        }
+
+        // Prefer the candidate that is oldest in the cache but still there after its fan
+        let mut best: Option<(usize, usize)> = None;
+        for &v in &candidates {
+            if live[v] == 0 {
+                continue;
+            }
+            let age = time - cache_time[v];
+            let priority = if age + 2 * live[v] <= cache_size { age } else { 0 };
+            if best.is_none_or(|(best_priority, _)| priority > best_priority) {
+                best = Some((priority, v));
+            }
+        }
+        fanning = best.map(|(_, v)| v).or_else(|| {
+            while let Some(v) = dead_end.pop() {
+                if live[v] > 0 {
+                    return Some(v);
+                }
+            }
+            while cursor < vertex_count {
+                cursor += 1;
+                if live[cursor - 1] > 0 {
+                    return Some(cursor - 1);
+                }
+            }
+            None
+        });
+    }
+    output
+}
+
+// Vertices in the order the indices first use them, unused ones are dropped
+fn reorder_vertices(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut ordered = Vec::with_capacity(vertices.len());
+    let indices = indices
+        .iter()
+        .map(|&i| {
+            if remap[i as usize] == u32::MAX {
+                remap[i as usize] = ordered.len() as u32;
+                ordered.push(vertices[i as usize]);
+            }
+            remap[i as usize]
+        })
+        .collect();
+    (ordered, indices)
+}
+
+// FIFO cache simulation, what most hardware's post-transform cache is modelled as
+fn cache_misses(indices: &[u32]) -> usize {
+    let mut cache = VecDeque::with_capacity(CACHE_SIZE);
+    let mut misses = 0;
+    for &i in indices {
+        if !cache.contains(&i) {
+            misses += 1;
+            if cache.len() == CACHE_SIZE {
+                cache.pop_front();
+            }
+            cache.push_back(i);
+        }
+    }
+    misses
+}
@@ -1,6 +1,9 @@
 use std::ops::Range;
+use std::sync::{OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::{texture};
+
+use crate::{color, gpu_layout::{UniformCheck, VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, instance_cull, material_array::{self, PackedMaterials}, material_set::MaterialSetReport, math::Aabb, mesh_optimize::OptimizeStats, texture, uv_fallback::UvFallback, vertex_pulling::{PulledMesh, VertexLayout}};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -16,6 +19,11 @@ pub struct ModelVertex {
     pub bitangent: [f32; 3],
 }
 
+pub const MODEL_VERTEX_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(ModelVertex, [position, tex_coords, normal, tangent, bitangent]),
+    desc: <ModelVertex as Vertex>::desc,
+};
+
 impl Vertex for ModelVertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
@@ -53,65 +61,483 @@ impl Vertex for ModelVertex {
     }
 }
 
+// Which texture of a material, used to swap in streamed textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
+}
+
+impl TextureSlot {
+    pub const ALL: [TextureSlot; 2] = [TextureSlot::Diffuse, TextureSlot::Normal];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// How a material is lit, a branch in shader.wgsl's shade()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    // Texture times color, no lighting
+    Unlit,
+    // Ambient and diffuse
+    Lambert,
+    // Lambert plus a specular highlight, what every material used before there was a choice
+    #[default]
+    BlinnPhong,
+    // GGX specular from roughness/metallic, approximated from the MTL
+    PbrLite,
+}
+
+impl ShadingModel {
+    pub const ALL: [ShadingModel; 4] = [ShadingModel::Unlit, ShadingModel::Lambert, ShadingModel::BlinnPhong, ShadingModel::PbrLite];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShadingModel::Unlit => "Unlit",
+            ShadingModel::Lambert => "Lambert",
+            ShadingModel::BlinnPhong => "Blinn-Phong",
+            ShadingModel::PbrLite => "PBR-lite",
+        }
+    }
+}
+
+// Shading knobs of a material, the defaults light it exactly as before materials had any
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialParams {
+    pub shading_model: ShadingModel,
+    // Multiplies the diffuse texture in every mode. sRGB, see color.rs.
+    pub color: [f32; 4],
+    // Blinn-Phong highlight
+    pub specular_strength: f32,
+    pub shininess: f32,
+    // PBR-lite
+    pub roughness: f32,
+    pub metallic: f32,
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self {
+            shading_model: ShadingModel::default(),
+            color: [1.0; 4],
+            specular_strength: 1.0,
+            shininess: 32.0,
+            roughness: 0.5,
+            metallic: 0.0,
+        }
+    }
+}
+
+impl MaterialParams {
+    // Roughness from the specular exponent (Ns) the usual Blinn-Phong to GGX way, metallic from
+    // how bright the specular color (Ks) is past a plain dielectric's 0.5. The other modes keep
+    // their defaults, so loaded models look like they always did.
+    pub fn from_mtl(shininess: f32, specular: [f32; 3]) -> Self {
+        let brightest = specular.into_iter().fold(0.0, f32::max);
+        Self {
+            roughness: (2.0 / (shininess.max(0.0) + 2.0)).sqrt(),
+            metallic: ((brightest - 0.5) * 2.0).clamp(0.0, 1.0),
+            ..Self::default()
+        }
+    }
+
+    fn to_uniform(self, debug_view: bool, layer: u32) -> MaterialUniform {
+        MaterialUniform {
+            color: color::srgb_to_linear_rgba(self.color),
+            specular_strength: self.specular_strength,
+            shininess: self.shininess,
+            roughness: self.roughness,
+            metallic: self.metallic,
+            shading_model: self.shading_model as u32,
+            debug_view: debug_view as u32,
+            layer,
+            _padding: 0,
+        }
+    }
+}
+
+// Must match the Material struct in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    color: [f32; 4],
+    specular_strength: f32,
+    shininess: f32,
+    roughness: f32,
+    metallic: f32,
+    shading_model: u32,
+    // Non-zero paints the shading model instead of lighting, see State::set_shading_model_view
+    debug_view: u32,
+    // Layer of the model's texture arrays, only read by the packed pipeline (material_array.rs)
+    layer: u32,
+    _padding: u32,
+}
+
+pub const MATERIAL_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(MaterialUniform, [color, specular_strength, shininess, roughness, metallic, shading_model, debug_view, layer]),
+    wgsl: &[("shader.wgsl", "Material")],
+};
+
+// Where a packed material's uniform and textures live, see material_array.rs
+#[derive(Clone)]
+pub struct PackedSlot {
+    pub buffer: wgpu::Buffer,
+    pub offset: wgpu::DynamicOffset,
+    pub layer: u32,
+}
+
+// Textures and the bind group built from them, replaced together
+struct MaterialBindings {
+    diffuse_texture: texture::Texture,
+    normal_texture: texture::Texture,
+    // Sampled instead of the diffuse texture while set, a render-to-texture camera's output
+    diffuse_override: Option<(wgpu::TextureView, wgpu::Sampler)>,
+    bind_group: wgpu::BindGroup,
+    // Per TextureSlot, the low-res stand-in is bound while set, see evict_texture
+    evicted: [bool; 2],
+}
+
 pub struct Material {
     pub _name: String,
-    pub _diffuse_texture: texture::Texture,
-    pub _normal_texture: texture::Texture,
-    pub bind_group: wgpu::BindGroup,
+    // Asset files the textures were loaded from, watched by texture_watch when hot reload is on
+    pub texture_files: Vec<(TextureSlot, String)>,
+    // How the UVs of a mesh using it were made up, None when they all came from the file
+    pub uv_fallback: Option<UvFallback>,
+    // Which maps of a texture pack it was built from, see material_set.rs
+    pub material_set: Option<MaterialSetReport>,
+    layout: wgpu::BindGroupLayout,
+    // Behind a lock so texture_stream can swap a finished texture in for its placeholder
+    bindings: RwLock<MaterialBindings>,
+    // Locked like the bindings, models (and their materials) are shared through an Arc
+    params: RwLock<MaterialParams>,
+    debug_view: AtomicBool,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    // Set when the model's textures were packed into arrays, cleared once a texture changes
+    packed: RwLock<Option<PackedSlot>>,
+    // Set by every bind_group call, texture_residency takes it once a frame to see what was drawn
+    drawn: AtomicBool,
+    // Per TextureSlot, a downscaled copy of the loaded image to bind while the texture is evicted
+    low_res: RwLock<[Option<image::RgbaImage>; 2]>,
 }
 
 impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        _diffuse_texture: texture::Texture,
-        _normal_texture: texture::Texture,
+        diffuse_texture: texture::Texture,
+        normal_texture: texture::Texture,
         layout: &wgpu::BindGroupLayout,
+        params: MaterialParams,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let uniform_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&[params.to_uniform(false, 0)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let diffuse = (&diffuse_texture.view, &diffuse_texture.sampler);
+        let bind_group = Self::create_bind_group(device, name, diffuse, &normal_texture, &uniform_buffer, layout);
+
+        Self {
+            _name: String::from(name),
+            texture_files: Vec::new(),
+            uv_fallback: None,
+            material_set: None,
+            layout: layout.clone(),
+            bindings: RwLock::new(MaterialBindings {
+                diffuse_texture,
+                normal_texture,
+                diffuse_override: None,
+                bind_group,
+                evicted: [false; 2],
+            }),
+            params: RwLock::new(params),
+            debug_view: AtomicBool::new(false),
+            uniform_buffer,
+            packed: RwLock::new(None),
+            drawn: AtomicBool::new(false),
+            low_res: RwLock::new([None, None]),
+        }
+    }
+
+    pub const UNIFORM_SIZE: wgpu::BufferAddress = std::mem::size_of::<MaterialUniform>() as wgpu::BufferAddress;
+
+    pub fn params(&self) -> MaterialParams {
+        *self.params.read().unwrap()
+    }
+
+    // Frames recorded after this draw with the new params
+    pub fn set_params(&self, queue: &wgpu::Queue, params: MaterialParams) {
+        *self.params.write().unwrap() = params;
+        self.write_uniform(queue);
+    }
+
+    // Paint the shading model instead of lighting
+    pub fn set_debug_view(&self, queue: &wgpu::Queue, debug_view: bool) {
+        self.debug_view.store(debug_view, Ordering::Relaxed);
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let packed = self.packed.read().unwrap();
+        let layer = packed.as_ref().map_or(0, |slot| slot.layer);
+        let uniform = self.params().to_uniform(self.debug_view.load(Ordering::Relaxed), layer);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        if let Some(slot) = packed.as_ref() {
+            queue.write_buffer(&slot.buffer, slot.offset as wgpu::BufferAddress, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    // From now on drawn from the model's texture arrays, see material_array::pack
+    pub fn pack(&self, queue: &wgpu::Queue, slot: PackedSlot) {
+        *self.packed.write().unwrap() = Some(slot);
+        self.write_uniform(queue);
+    }
+
+    // Dynamic offset of the material's uniform, None when it draws with its own bind group
+    pub fn packed_offset(&self) -> Option<wgpu::DynamicOffset> {
+        self.packed.read().unwrap().as_ref().map(|slot| slot.offset)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        (diffuse_view, diffuse_sampler): (&wgpu::TextureView, &wgpu::Sampler),
+        normal_texture: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&_diffuse_texture.view),
+                    resource: wgpu::BindingResource::TextureView(diffuse_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&_diffuse_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(diffuse_sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&_normal_texture.view),
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&_normal_texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform_buffer.as_entire_binding(),
                 },
             ],
             label: Some(name),
-        });
+        })
+    }
 
-        Self {
-            _name: String::from(name),
-            _diffuse_texture,
-            _normal_texture,
-            bind_group,
+    pub fn bind_group(&self) -> wgpu::BindGroup {
+        self.drawn.store(true, Ordering::Relaxed);
+        self.bindings.read().unwrap().bind_group.clone()
+    }
+
+    // Whether anything fetched the bind group to draw since the last call
+    pub fn take_drawn(&self) -> bool {
+        self.drawn.swap(false, Ordering::Relaxed)
+    }
+
+    // Bound in the texture's place once it is evicted, see texture_stream::placeholder_image
+    pub fn keep_low_res(&self, slot: TextureSlot, low_res: image::RgbaImage) {
+        self.low_res.write().unwrap()[slot.index()] = Some(low_res);
+    }
+
+    pub fn is_evicted(&self, slot: TextureSlot) -> bool {
+        self.bindings.read().unwrap().evicted[slot.index()]
+    }
+
+    // Of the texture bound in the slot, the low-res one while evicted. Every material texture is RGBA8.
+    pub fn texture_bytes(&self, slot: TextureSlot) -> u64 {
+        let bindings = self.bindings.read().unwrap();
+        let texture = match slot {
+            TextureSlot::Diffuse => &bindings.diffuse_texture.texture,
+            TextureSlot::Normal => &bindings.normal_texture.texture,
+        };
+        texture.width() as u64 * texture.height() as u64 * 4
+    }
+
+    // Binds the low-res copy and lets the full texture and its memory go. Without a copy a single
+    // texel stands in: mid gray, or a normal map's straight up. Replacing the texture again
+    // (streaming, a reload) ends the eviction.
+    pub fn evict_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, slot: TextureSlot) -> anyhow::Result<()> {
+        let img = match self.low_res.read().unwrap()[slot.index()].clone() {
+            Some(rgba) => image::DynamicImage::ImageRgba8(rgba),
+            None => {
+                let texel = if slot == TextureSlot::Normal { [128, 128, 255, 255] } else { [128, 128, 128, 255] };
+                image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(texel)))
+            }
+        };
+        let texture = texture::Texture::from_image(device, queue, &img, Some(&format!("{} (evicted)", self._name)), slot == TextureSlot::Normal)?;
+        self.replace_texture(device, slot, texture);
+        self.bindings.write().unwrap().evicted[slot.index()] = true;
+        Ok(())
+    }
+
+    // Swaps one texture and rebuilds the bind group, frames recorded after this use the new texture
+    pub fn replace_texture(&self, device: &wgpu::Device, slot: TextureSlot, texture: texture::Texture) {
+        // The array layer still holds the old texture, the material's own bind group has the new one
+        *self.packed.write().unwrap() = None;
+        let mut bindings = self.bindings.write().unwrap();
+        match slot {
+            TextureSlot::Diffuse => bindings.diffuse_texture = texture,
+            TextureSlot::Normal => bindings.normal_texture = texture,
+        }
+        bindings.evicted[slot.index()] = false;
+        self.rebuild_bind_group(device, &mut bindings);
+    }
+
+    // Sample this view instead of the diffuse texture, None goes back to the texture. The loaded
+    // texture is kept, and reloads of it apply once the override is cleared.
+    pub fn set_diffuse_override(&self, device: &wgpu::Device, view: Option<(wgpu::TextureView, wgpu::Sampler)>) {
+        *self.packed.write().unwrap() = None;
+        let mut bindings = self.bindings.write().unwrap();
+        bindings.diffuse_override = view;
+        self.rebuild_bind_group(device, &mut bindings);
+    }
+
+    fn rebuild_bind_group(&self, device: &wgpu::Device, bindings: &mut MaterialBindings) {
+        let diffuse = match &bindings.diffuse_override {
+            Some((view, sampler)) => (view, sampler),
+            None => (&bindings.diffuse_texture.view, &bindings.diffuse_texture.sampler),
+        };
+        bindings.bind_group = Self::create_bind_group(device, &self._name, diffuse, &bindings.normal_texture, &self.uniform_buffer, &self.layout);
+    }
+
+    // New content for a texture. Same size is written into the existing texture, anything else
+    // gets a new texture and bind group.
+    pub fn reload_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, slot: TextureSlot, img: &image::DynamicImage) -> anyhow::Result<()> {
+        *self.packed.write().unwrap() = None;
+        {
+            let bindings = self.bindings.read().unwrap();
+            let current = match slot {
+                TextureSlot::Diffuse => &bindings.diffuse_texture.texture,
+                TextureSlot::Normal => &bindings.normal_texture.texture,
+            };
+            if (current.width(), current.height()) == (img.width(), img.height()) {
+                let rgba = img.to_rgba8();
+                queue.write_texture(
+                    current.as_image_copy(),
+                    &rgba,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * img.width()),
+                        rows_per_image: Some(img.height()),
+                    },
+                    current.size(),
+                );
+                return Ok(());
+            }
         }
+        let texture = texture::Texture::from_image(device, queue, img, Some(&self._name), slot == TextureSlot::Normal)?;
+        self.replace_texture(device, slot, texture);
+        Ok(())
     }
 }
 
 pub struct Mesh {
-    pub _name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    // The OBJ group name, unique within its model (see resources::unique_mesh_name)
+    pub name: String,
+    pub vertex_buffer: Tracked<wgpu::Buffer>,
+    pub index_buffer: Tracked<wgpu::Buffer>,
     pub num_elements: u32,
     pub material: usize,
+    // In model space, for ray picking
+    pub bounds: Aabb,
+    // Atomic because models are shared between scene entries through an Arc
+    visible: AtomicBool,
+    // Where the attributes sit in the vertex buffer, for the vertex pulling path
+    pub layout: VertexLayout,
+    // That path's bind group, made on the first pulled draw
+    pub pulled: OnceLock<PulledMesh>,
 }
 
+impl Mesh {
+    pub fn new(name: String, vertex_buffer: Tracked<wgpu::Buffer>, index_buffer: Tracked<wgpu::Buffer>, num_elements: u32, material: usize, bounds: Aabb) -> Self {
+        Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_elements,
+            material,
+            bounds,
+            visible: AtomicBool::new(true),
+            layout: VertexLayout::MODEL,
+            pulled: OnceLock::new(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::Relaxed)
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        self.visible.store(visible, Ordering::Relaxed);
+    }
+}
+
+// A mesh of a model, by name or by position in Model::meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshRef<'a> {
+    Name(&'a str),
+    Index(usize),
+}
+
+impl<'a> From<&'a str> for MeshRef<'a> {
+    fn from(name: &'a str) -> Self {
+        MeshRef::Name(name)
+    }
+}
+
+impl From<usize> for MeshRef<'_> {
+    fn from(index: usize) -> Self {
+        MeshRef::Index(index)
+    }
+}
 
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    // Before/after counts when the model was loaded with a mesh clean up, see mesh_optimize.rs
+    pub optimize_stats: Option<OptimizeStats>,
+    // The materials' textures as arrays when loaded with LoadOptions::pack_textures
+    pub packed: Option<PackedMaterials>,
+}
+
+impl Model {
+    // Model space box around the visible meshes, None when every mesh is hidden
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.meshes
+            .iter()
+            .filter(|mesh| mesh.is_visible())
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+    }
+
+    pub fn mesh_index_by_name(&self, name: &str) -> Option<usize> {
+        self.meshes.iter().position(|mesh| mesh.name == name)
+    }
+
+    pub fn mesh(&self, mesh: MeshRef) -> Option<&Mesh> {
+        match mesh {
+            MeshRef::Name(name) => self.meshes.get(self.mesh_index_by_name(name)?),
+            MeshRef::Index(index) => self.meshes.get(index),
+        }
+    }
+
+    // Shows or hides every mesh whose name contains `pattern`, returns how many matched
+    pub fn set_visible_matching(&self, pattern: &str, visible: bool) -> usize {
+        let matching = self.meshes.iter().filter(|mesh| mesh.name.contains(pattern));
+        matching.map(|mesh| mesh.set_visible(visible)).count()
+    }
 }
 
 pub trait DrawModel<'a> {
@@ -134,12 +560,26 @@ pub trait DrawModel<'a> {
         light_bind_group: &'a wgpu::BindGroup,
     );
 
+    // Instance counts come from `indirect`, see instance_cull::indirect_offset
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        indirect: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
-impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
-where
-    'b: 'a,
-{
+impl<'b> DrawModel<'b> for wgpu::RenderPass<'_> {
     fn _draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, camera_bind_group: &'b wgpu::BindGroup, light_bind_group: &'b wgpu::BindGroup) {
         self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group, light_bind_group);
     }
@@ -154,7 +594,8 @@ where
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(0, &material.bind_group(), &[]);
+        material_array::count_material_bind();
         self.set_bind_group(1, camera_bind_group, &[]);
         self.set_bind_group(2, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
@@ -171,11 +612,42 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        for mesh in &model.meshes {
+        for mesh in model.meshes.iter().filter(|mesh| mesh.is_visible()) {
             let material = &model.materials[mesh.material];
             self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group, light_bind_group);
         }
     }
+
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group(), &[]);
+        material_array::count_material_bind();
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect, indirect_offset);
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        indirect: &'b wgpu::Buffer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for (index, mesh) in model.meshes.iter().enumerate().filter(|(_, mesh)| mesh.is_visible()) {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_indirect(mesh, material, indirect, instance_cull::indirect_offset(index), camera_bind_group, light_bind_group);
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
@@ -208,10 +680,7 @@ pub trait DrawLight<'a> {
     );
 }
 
-impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
-where 
-    'b: 'a,
-{
+impl<'b> DrawLight<'b> for wgpu::RenderPass<'_> {
     fn _draw_light_mesh(
             &mut self,
             mesh: &'b Mesh,
@@ -256,3 +725,17 @@ where
     }
 }
 
+// Geometry only, for passes that bind their own groups (depth/normal prepasses)
+pub trait DrawGeometry<'a> {
+    fn draw_model_geometry_instanced(&mut self, model: &'a Model, instances: Range<u32>);
+}
+
+impl<'b> DrawGeometry<'b> for wgpu::RenderPass<'_> {
+    fn draw_model_geometry_instanced(&mut self, model: &'b Model, instances: Range<u32>) {
+        for mesh in model.meshes.iter().filter(|mesh| mesh.is_visible()) {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
@@ -0,0 +1,545 @@
+/*
+Purpose: One model in the scene together with every instance of it
+Responsibilities:
+    - Own the model (shared through an Arc), its instances, and their GPU buffers
+    - Give every instance an id that stays its own until it is despawned, wherever the
+      instance moves in the buffers
+    - Hold whatever gameplay code attached to an instance, dropped with the instance
+    - Track when the instances changed so buffers are only rebuilt and uploaded when needed
+    - Grow the buffers in powers of two, and shrink them again once most instances are gone
+    - Stream the big textures of models added at runtime
+    - Cull the instances against the main camera's frustum on the CPU or the GPU, and what the
+      depth pre-pass hides on the GPU
+    - ex: one shelf in the warehouse, a single product and how many of it are in stock
+*/
+
+use cgmath::Matrix4;
+
+use crate::{
+    hiz::HiZTargets,
+    instance::Instance,
+    instance_anim::{AnimatedInstances, InstanceAnimationPipeline},
+    instance_cull::{self, CullMode, CulledInstances, InstanceCullPipeline},
+    math::Frustum,
+    model,
+    texture_stream::TextureStreamer,
+};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Fewer live instances than 1 / SPARSE_FRACTION of the capacity for this many uploads in a row
+// shrinks the buffers, a burst of spawning and despawning doesn't reallocate every frame
+const SPARSE_FRACTION: u32 = 4;
+const COMPACT_AFTER_UPLOADS: u32 = 120;
+
+// Stays valid while the model is loaded, handles are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ModelHandle(pub u32);
+
+// Stays valid until the instance is despawned or its model's instances are replaced. Indices
+// are handed out in spawn order and never reused by the model, despawning one instance leaves
+// the others' ids alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceId {
+    pub model: ModelHandle,
+    pub index: usize,
+}
+
+// Every entry starts on this layer, see ModelEntry::layers
+pub const DEFAULT_LAYER: u32 = 1;
+// A layer mask that draws everything, the windows' own cameras use it
+pub const ALL_LAYERS: u32 = u32::MAX;
+
+// What an upload does to the instance buffers, see ModelEntry::plan_upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferPlan {
+    // Nothing changed since the last upload
+    Keep,
+    // Every instance rewritten into the buffers it has
+    Write,
+    // New buffers with this many slots, then every instance written
+    Reallocate(u32),
+}
+
+pub struct ModelEntry {
+    pub handle: ModelHandle,
+    pub name: String,
+    pub model: Arc<model::Model>,
+    // In buffer order, a despawn moves the last one into the gap
+    instances: Vec<Instance>,
+    // The InstanceId index of each of `instances`
+    ids: Vec<usize>,
+    // Where each live index sits in `instances`
+    slots: BTreeMap<usize, usize>,
+    next_index: usize,
+    // By instance index, only for the instances that have any
+    user_data: BTreeMap<usize, Box<dyn Any + Send>>,
+    // None until the first upload, rewritten when the instances change and replaced when they
+    // outgrow it or it is compacted
+    buffers: Option<AnimatedInstances>,
+    // The instances in view, made for the current buffers on the first cull
+    culled: Option<CulledInstances>,
+    dirty: bool,
+    // Uploads in a row with the buffers mostly empty, see COMPACT_AFTER_UPLOADS
+    sparse_uploads: u32,
+    compact_requested: bool,
+    // Animation time the buffer was last posed for, spinning instances are reposed when it moves
+    posed_time: Option<f32>,
+    spins: bool,
+    // Big textures of models loaded at runtime and evicted textures coming back. The startup
+    // model's first load streams through the RenderContext.
+    streamer: Option<TextureStreamer>,
+    // Mirrors the nearest baked reflection probe, or the sky without one
+    pub reflective: bool,
+    // Bits render-to-texture cameras match against their layer mask, DEFAULT_LAYER unless changed
+    pub layers: u32,
+    // Its textures stay resident however far over the texture budget, see texture_residency
+    pub pin_textures: bool,
+}
+
+impl ModelEntry {
+    pub fn new(handle: ModelHandle, name: String, model: Arc<model::Model>, streamer: Option<TextureStreamer>) -> Self {
+        Self {
+            handle,
+            name,
+            model,
+            instances: Vec::new(),
+            ids: Vec::new(),
+            slots: BTreeMap::new(),
+            next_index: 0,
+            user_data: BTreeMap::new(),
+            buffers: None,
+            culled: None,
+            dirty: true,
+            sparse_uploads: 0,
+            compact_requested: false,
+            posed_time: None,
+            spins: false,
+            streamer,
+            reflective: false,
+            layers: DEFAULT_LAYER,
+            pin_textures: false,
+        }
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    // Instances the buffers have room for, 0 before the first upload
+    pub fn instance_capacity(&self) -> u32 {
+        self.buffers.as_ref().map_or(0, AnimatedInstances::capacity)
+    }
+
+    // Shrinks the buffers to fit on the next upload, even if they aren't sparse for long yet
+    pub fn request_compaction(&mut self) {
+        self.compact_requested = true;
+    }
+
+    // Average instance position, None without instances
+    pub fn center(&self) -> Option<cgmath::Vector3<f32>> {
+        let count = self.instances.len();
+        (count > 0).then(|| self.instances.iter().map(|instance| instance.position).sum::<cgmath::Vector3<f32>>() / count as f32)
+    }
+
+    // The old instances' ids and user data go with them, the new ones get fresh indices
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let first = self.next_index;
+        self.next_index += instances.len();
+        self.ids = (first..self.next_index).collect();
+        self.slots = self.ids.iter().enumerate().map(|(slot, &index)| (index, slot)).collect();
+        self.instances = instances;
+        self.user_data.clear();
+        self.dirty = true;
+    }
+
+    // The new instance's index
+    pub fn push_instance(&mut self, instance: Instance) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.place(index, instance);
+        index
+    }
+
+    // Brings a despawned instance back under its old index (undo). False if that index is live or
+    // was never handed out.
+    pub fn insert_instance(&mut self, index: usize, instance: Instance) -> bool {
+        if index >= self.next_index || self.slots.contains_key(&index) {
+            return false;
+        }
+        self.place(index, instance);
+        true
+    }
+
+    // Appends to the buffers under an index the caller checked
+    fn place(&mut self, index: usize, instance: Instance) {
+        self.slots.insert(index, self.instances.len());
+        self.ids.push(index);
+        self.instances.push(instance);
+        self.dirty = true;
+    }
+
+    // Drops its user data. The last instance in the buffers moves into the gap, keeping its index.
+    pub fn remove_instance(&mut self, index: usize) -> Option<Instance> {
+        let slot = self.slots.remove(&index)?;
+        let instance = self.instances.swap_remove(slot);
+        self.ids.swap_remove(slot);
+        if let Some(&moved) = self.ids.get(slot) {
+            self.slots.insert(moved, slot);
+        }
+        self.user_data.remove(&index);
+        self.dirty = true;
+        Some(instance)
+    }
+
+    // The most recently spawned instance still there, None without any
+    pub fn newest_index(&self) -> Option<usize> {
+        self.slots.last_key_value().map(|(&index, _)| index)
+    }
+
+    pub fn instance(&self, index: usize) -> Option<&Instance> {
+        self.instances.get(*self.slots.get(&index)?)
+    }
+
+    // Marks the entry dirty, the change is uploaded on the next update
+    pub fn instance_mut(&mut self, index: usize) -> Option<&mut Instance> {
+        let instance = self.instances.get_mut(*self.slots.get(&index)?)?;
+        self.dirty = true;
+        Some(instance)
+    }
+
+    // (index, instance) in buffer order
+    pub fn instances(&self) -> impl Iterator<Item = (usize, &Instance)> {
+        self.ids.iter().copied().zip(&self.instances)
+    }
+
+    // Where the instance sits in the instance buffer, for drawing or picking just that one
+    pub fn slot(&self, index: usize) -> Option<usize> {
+        self.slots.get(&index).copied()
+    }
+
+    // The index of the instance in buffer slot `slot`
+    pub fn index_at(&self, slot: usize) -> Option<usize> {
+        self.ids.get(slot).copied()
+    }
+
+    // Replaces what was there. False if there is no such instance.
+    pub fn set_user_data(&mut self, index: usize, data: Box<dyn Any + Send>) -> bool {
+        if !self.slots.contains_key(&index) {
+            return false;
+        }
+        self.user_data.insert(index, data);
+        true
+    }
+
+    // Whatever type it is, the instance is left without
+    pub fn take_user_data(&mut self, index: usize) -> Option<Box<dyn Any + Send>> {
+        self.user_data.remove(&index)
+    }
+
+    // None without data or with data of another type
+    pub fn user_data<T: Any>(&self, index: usize) -> Option<&T> {
+        self.user_data.get(&index)?.downcast_ref()
+    }
+
+    pub fn user_data_mut<T: Any>(&mut self, index: usize) -> Option<&mut T> {
+        self.user_data.get_mut(&index)?.downcast_mut()
+    }
+
+    // The instances whose data is a T, by index, lowest first
+    pub fn iter_user_data<T: Any>(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.user_data.iter().filter_map(|(&index, data)| Some((index, data.downcast_ref()?)))
+    }
+
+    // Needs an upload or keeps moving, either way the next frames look different
+    pub fn is_animated(&self) -> bool {
+        self.dirty || (self.spins && !self.instances.is_empty())
+    }
+
+    // Made on first use
+    pub fn streamer_mut(&mut self) -> &mut TextureStreamer {
+        self.streamer.get_or_insert_with(TextureStreamer::default)
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streamer.as_ref().is_some_and(|streamer| streamer.stats().active_streams > 0)
+    }
+
+    // The vertex buffer to bind at slot 1, None while there is nothing to draw
+    pub fn instance_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffers
+            .as_ref()
+            .filter(|buffers| buffers.len() > 0)
+            .map(AnimatedInstances::buffer)
+    }
+
+    // Rewrite the buffers if the instances changed and pose them for `time` if they need it.
+    // With an encoder the compute pass poses them, otherwise the CPU does. Runs before the frame
+    // is encoded, so reallocating here never leaves a draw on a released buffer.
+    // Returns whether anything was uploaded.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &InstanceAnimationPipeline,
+        encoder: Option<&mut wgpu::CommandEncoder>,
+        time: f32,
+    ) -> bool {
+        let capacity = self.instance_capacity();
+        let plan = self.plan_upload(self.buffers.as_ref().map(AnimatedInstances::capacity));
+        if plan != BufferPlan::Keep {
+            if let BufferPlan::Reallocate(slots) = plan {
+                if slots < capacity {
+                    log::info!("Compacted {}'s instance buffers from {} to {} slots for {} instances", self.name, capacity, slots, self.instances.len());
+                }
+                // The old buffers are released when they are replaced, the culled ones with them
+                self.culled = None;
+                self.buffers = Some(AnimatedInstances::new(device, pipeline, slots, &self.name));
+            }
+            if let Some(buffers) = self.buffers.as_mut() {
+                buffers.write(queue, &self.instances);
+            }
+            self.spins = self.instances.iter().any(|instance| instance.spin_speed != 0.0);
+            self.posed_time = None;
+        }
+        let needs_pose = match self.posed_time {
+            None => true,
+            Some(posed) => self.spins && posed != time,
+        };
+        let Some(buffers) = self.buffers.as_ref().filter(|_| needs_pose) else {
+            return false;
+        };
+        match encoder {
+            Some(encoder) => buffers.animate_gpu(encoder, queue, pipeline, time),
+            None => buffers.animate_cpu(queue, &self.instances, time),
+        }
+        self.posed_time = Some(time);
+        true
+    }
+
+    // What this upload does to buffers of `capacity` slots (None before the first), and counts the
+    // uploads in a row they were sparse. The instances keep their indices through growing and
+    // compacting, only despawns move them between slots.
+    fn plan_upload(&mut self, capacity: Option<u32>) -> BufferPlan {
+        let live = self.instances.len() as u32;
+        self.sparse_uploads = if live * SPARSE_FRACTION < capacity.unwrap_or(0) { self.sparse_uploads + 1 } else { 0 };
+        let compact = self.compact_requested || self.sparse_uploads >= COMPACT_AFTER_UPLOADS;
+        if !self.dirty && !compact {
+            return BufferPlan::Keep;
+        }
+        self.dirty = false;
+        self.sparse_uploads = 0;
+        self.compact_requested = false;
+        match capacity {
+            Some(capacity) if !compact && live <= capacity => BufferPlan::Write,
+            _ => BufferPlan::Reallocate(live.max(1).next_power_of_two()),
+        }
+    }
+
+    // Keep the instances as they were posed last frame, see AnimatedInstances::retain_previous
+    pub fn retain_previous_instances(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(buffers) = self.buffers.as_mut() {
+            buffers.retain_previous(device, encoder);
+        }
+    }
+
+    pub fn release_previous_instances(&mut self) {
+        if let Some(buffers) = self.buffers.as_mut() {
+            buffers.release_previous();
+        }
+    }
+
+    // Last frame's instance_buffer, the current one stands in right after the buffers were replaced
+    pub fn previous_instance_buffer(&self) -> Option<&wgpu::Buffer> {
+        let buffers = self.buffers.as_ref().filter(|buffers| buffers.len() > 0)?;
+        buffers.previous_buffer().or(Some(buffers.buffer()))
+    }
+
+    // Cull the posed instances against `frustum` with `mode` (not Off), and on the GPU against
+    // `hiz` too when given, see CulledInstances::encode_gpu. Without `pipeline` the GPU mode falls
+    // back to the CPU. Before the main pass is encoded, see culled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cull(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: Option<&InstanceCullPipeline>,
+        mode: CullMode,
+        frustum: &Frustum,
+        view_proj: Matrix4<f32>,
+        hiz: Option<&HiZTargets>,
+        time: f32,
+    ) {
+        let (Some(buffers), Some(bounds)) = (self.buffers.as_ref().filter(|buffers| buffers.len() > 0), self.model.bounds()) else {
+            return;
+        };
+        let sphere = instance_cull::bounding_sphere(&bounds);
+        let culled = self.culled.get_or_insert_with(|| {
+            let index_counts = self.model.meshes.iter().map(|mesh| mesh.num_elements).collect::<Vec<_>>();
+            CulledInstances::new(device, pipeline, buffers.buffer(), buffers.capacity(), &index_counts, &self.name)
+        });
+        let on_gpu = match (mode, pipeline) {
+            (CullMode::Gpu, Some(pipeline)) => culled.encode_gpu(encoder, queue, pipeline, &sphere, frustum, view_proj, hiz, buffers.len()),
+            _ => false,
+        };
+        if !on_gpu {
+            culled.cull_cpu(queue, self.instances.iter().map(|instance| instance.to_raw(time)), &sphere, frustum);
+        }
+    }
+
+    // Left by the last cull, None before the first one and after the buffers were replaced
+    pub fn culled(&self) -> Option<&CulledInstances> {
+        self.culled.as_ref()
+    }
+
+    pub fn culled_mut(&mut self) -> Option<&mut CulledInstances> {
+        self.culled.as_mut()
+    }
+
+    // Upload a few more strips of this model's streaming textures and bind the finished ones
+    pub fn pump_textures(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let Some(streamer) = self.streamer.as_mut() else {
+            return;
+        };
+        for (target, texture) in streamer.pump(device, queue) {
+            self.model.materials[target.material].replace_texture(device, target.slot, texture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance::generate_line;
+
+    fn empty_entry() -> ModelEntry {
+        let model = model::Model { meshes: Vec::new(), materials: Vec::new(), optimize_stats: None, packed: None };
+        ModelEntry::new(ModelHandle(1), "test".to_string(), Arc::new(model), None)
+    }
+
+    fn x(entry: &ModelEntry, index: usize) -> Option<f32> {
+        entry.instance(index).map(|instance| instance.position.x)
+    }
+
+    fn filled(count: u32) -> ModelEntry {
+        let mut entry = empty_entry();
+        entry.set_instances(generate_line(count, cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(count as f32 - 1.0, 0.0, 0.0)));
+        entry
+    }
+
+    #[test]
+    fn despawning_any_instance_keeps_the_other_indices() {
+        let mut entry = filled(4);
+        assert_eq!(entry.remove_instance(1).map(|instance| instance.position.x), Some(1.0));
+        assert_eq!((x(&entry, 0), x(&entry, 1), x(&entry, 2), x(&entry, 3)), (Some(0.0), None, Some(2.0), Some(3.0)));
+        assert!(entry.remove_instance(1).is_none());
+        // The last one moved into the gap
+        assert_eq!((entry.slot(3), entry.index_at(1)), (Some(1), Some(3)));
+
+        // Indices aren't reused
+        assert_eq!(entry.push_instance(entry.instance(0).unwrap().clone()), 4);
+        assert_eq!(entry.newest_index(), Some(4));
+        assert_eq!(entry.instances().map(|(index, _)| index).collect::<Vec<_>>(), [0, 3, 2, 4]);
+
+        // Undo puts it back under its own
+        let restored = Instance::placed(cgmath::Vector3::new(1.0, 0.0, 0.0), cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0), cgmath::Vector3::unit_y());
+        assert!(entry.insert_instance(1, restored.clone()));
+        assert!(!entry.insert_instance(1, restored.clone()));
+        assert!(!entry.insert_instance(9, restored));
+        assert_eq!((x(&entry, 1), entry.instance_count()), (Some(1.0), 5));
+    }
+
+    #[test]
+    fn only_handed_out_indices_can_be_restored() {
+        let mut entry = filled(2);
+        let instance = entry.instance(0).unwrap().clone();
+        // The next index push_instance would give hasn't been handed out yet
+        assert!(!entry.insert_instance(2, instance.clone()));
+        assert_eq!(entry.push_instance(instance.clone()), 2);
+        assert!(entry.remove_instance(2).is_some());
+        assert!(entry.insert_instance(2, instance));
+        assert_eq!(entry.newest_index(), Some(2));
+    }
+
+    #[test]
+    fn edits_in_one_frame_upload_once() {
+        let mut entry = filled(3);
+        assert_eq!(entry.plan_upload(None), BufferPlan::Reallocate(4));
+        assert_eq!(entry.plan_upload(Some(4)), BufferPlan::Keep);
+
+        entry.instance_mut(0).unwrap().position.y = 1.0;
+        entry.instance_mut(2).unwrap().position.y = 2.0;
+        entry.push_instance(entry.instance(1).unwrap().clone());
+        assert_eq!(entry.plan_upload(Some(4)), BufferPlan::Write);
+        assert_eq!(entry.plan_upload(Some(4)), BufferPlan::Keep);
+
+        entry.push_instance(entry.instance(1).unwrap().clone());
+        assert_eq!(entry.plan_upload(Some(4)), BufferPlan::Reallocate(8));
+    }
+
+    #[test]
+    fn sparse_buffers_compact_after_a_while() {
+        let mut entry = filled(40);
+        assert_eq!(entry.plan_upload(None), BufferPlan::Reallocate(64));
+        while entry.instance_count() > 3 {
+            entry.remove_instance(entry.newest_index().unwrap());
+        }
+        assert_eq!(entry.plan_upload(Some(64)), BufferPlan::Write);
+        for _ in 1..COMPACT_AFTER_UPLOADS {
+            assert_eq!(entry.plan_upload(Some(64)), BufferPlan::Keep);
+        }
+        assert_eq!(entry.plan_upload(Some(64)), BufferPlan::Reallocate(4));
+        assert_eq!((x(&entry, 0), x(&entry, 2), x(&entry, 3)), (Some(0.0), Some(2.0), None));
+        assert_eq!(entry.plan_upload(Some(4)), BufferPlan::Keep);
+    }
+
+    #[test]
+    fn user_data_downcasts_to_its_own_type_only() {
+        let mut entry = filled(3);
+        assert!(entry.set_user_data(0, Box::new(7u32)));
+        assert!(entry.set_user_data(2, Box::new("goblin")));
+        assert!(!entry.set_user_data(3, Box::new(1u32)));
+        assert_eq!(entry.user_data::<u32>(0), Some(&7));
+        assert_eq!(entry.user_data::<String>(0), None);
+        assert_eq!(entry.user_data::<u32>(1), None);
+        *entry.user_data_mut::<u32>(0).unwrap() += 1;
+        assert_eq!(entry.iter_user_data::<u32>().collect::<Vec<_>>(), [(0, &8)]);
+        assert_eq!(entry.iter_user_data::<&str>().collect::<Vec<_>>(), [(2, &"goblin")]);
+    }
+
+    #[test]
+    fn user_data_survives_compaction_and_goes_with_its_instance() {
+        let mut entry = filled(8);
+        entry.set_user_data(1, Box::new(1u32));
+        entry.set_user_data(7, Box::new(7u32));
+        assert_eq!(entry.plan_upload(None), BufferPlan::Reallocate(8));
+        entry.remove_instance(1);
+        entry.request_compaction();
+        assert_eq!(entry.plan_upload(Some(8)), BufferPlan::Reallocate(8));
+        // 7 moved into 1's slot and took its data along
+        assert_eq!((entry.user_data::<u32>(1), entry.user_data::<u32>(7)), (None, Some(&7)));
+
+        // Coming back under the same index starts without the old data
+        assert!(entry.insert_instance(1, entry.instance(7).unwrap().clone()));
+        assert_eq!(entry.user_data::<u32>(1), None);
+        let copy = entry.push_instance(entry.instance(7).unwrap().clone());
+        assert_eq!(copy, 8);
+        assert_eq!(entry.user_data::<u32>(copy), None);
+
+        // The grid being rebuilt replaces every instance
+        entry.set_instances(generate_line(8, cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(entry.iter_user_data::<u32>().count(), 0);
+    }
+
+    #[test]
+    fn compaction_on_request() {
+        let mut entry = filled(5);
+        assert_eq!(entry.plan_upload(None), BufferPlan::Reallocate(8));
+        entry.request_compaction();
+        assert_eq!(entry.plan_upload(Some(8)), BufferPlan::Reallocate(8));
+        let mut empty = empty_entry();
+        assert_eq!(empty.plan_upload(None), BufferPlan::Reallocate(1));
+    }
+}
@@ -0,0 +1,430 @@
+/*
+Purpose: Per-pixel motion blur of the HDR frame
+Responsibilities:
+    - Own the velocity prepass and blur pipelines (shared by every window, HDR only)
+    - Declare the per-window velocity target and frame copy in the window's frame graph, and
+      keep last frame's camera, dropped when neither the blur nor TAA (taa.rs) needs them
+    - Skip the blur for a frame in which the camera jumped (a bookmark, framing a model)
+    - Record the velocity prepass and the blur, which runs before tonemapping
+    - ex: the streak a long exposure leaves behind a passing car
+*/
+
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+
+use crate::{
+    camera::{Camera, Projection},
+    frame_graph::{FrameGraph, TransientDesc, TransientId, Transients},
+    gpu_layout::{UniformCheck, rust_layout},
+    gpu_memory::{self, Tracked},
+    hdr::HDR_FORMAT,
+    instance::InstanceRaw,
+    model::ModelVertex,
+    render_context::{fullscreen_pipeline, texture_entry},
+    texture,
+};
+
+pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const MAX_SAMPLES: u32 = 32;
+// A camera that moves or turns more than this in one frame was teleported, not flown
+const TELEPORT_DISTANCE: f32 = 5.0;
+const TELEPORT_ANGLE: Deg<f32> = Deg(45.0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionBlurSettings {
+    // When off no velocity target exists and none of the passes are recorded
+    pub enabled: bool,
+    // 1 smears over the whole distance moved since the last frame
+    pub intensity: f32,
+    // Longest smear, in pixels
+    pub max_radius: f32,
+    pub samples: u32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 1.0,
+            max_radius: 32.0,
+            samples: 8,
+        }
+    }
+}
+
+impl MotionBlurSettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Motion blur");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.add(egui::Slider::new(&mut self.intensity, 0.0..=2.0).text("Blur intensity"));
+            ui.add(egui::Slider::new(&mut self.max_radius, 1.0..=128.0).text("Max blur radius (px)"));
+            ui.add(egui::Slider::new(&mut self.samples, 2..=MAX_SAMPLES).text("Blur samples"));
+        });
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VelocityUniform {
+    view_proj: [[f32; 4]; 4],
+    previous_view_proj: [[f32; 4]; 4],
+}
+
+pub const VELOCITY_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(VelocityUniform, [view_proj, previous_view_proj]),
+    wgsl: &[("motion_velocity.wgsl", "VelocityUniform")],
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlurUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    previous_view_proj: [[f32; 4]; 4],
+    intensity: f32,
+    max_radius: f32,
+    samples: u32,
+    _padding: u32,
+}
+
+pub const BLUR_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(BlurUniform, [inverse_view_proj, previous_view_proj, intensity, max_radius, samples]),
+    wgsl: &[("motion_blur.wgsl", "BlurUniform")],
+};
+
+// Only the position of ModelVertex and the model matrices of InstanceRaw, which together with
+// last frame's matrices stay within the 16 vertex attributes every adapter has
+const POSITION_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4];
+const PREVIOUS_INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![9 => Float32x4, 10 => Float32x4, 11 => Float32x4, 12 => Float32x4];
+
+fn instance_layout(attributes: &[wgpu::VertexAttribute]) -> wgpu::VertexBufferLayout<'_> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes,
+    }
+}
+
+// Shared by every window, only created when HDR is on
+pub struct MotionBlurPipelines {
+    velocity_layout: wgpu::BindGroupLayout,
+    velocity_pipeline: wgpu::RenderPipeline,
+    blur_layout: wgpu::BindGroupLayout,
+    blur_pipeline: wgpu::RenderPipeline,
+}
+
+impl MotionBlurPipelines {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let velocity_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(0, wgpu::ShaderStages::VERTEX)],
+            label: Some("Velocity Bind Group Layout"),
+        });
+        let unfiltered = wgpu::TextureSampleType::Float { filterable: false };
+        let blur_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, unfiltered),
+                texture_entry(1, unfiltered),
+                uniform_entry(2, wgpu::ShaderStages::FRAGMENT),
+            ],
+            label: Some("Motion Blur Bind Group Layout"),
+        });
+
+        let velocity_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Velocity Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("motion_velocity.wgsl").into()),
+        });
+        let velocity_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Velocity Pipeline Layout"),
+            bind_group_layouts: &[&velocity_layout],
+            push_constant_ranges: &[],
+        });
+        let velocity_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Velocity Pipeline"),
+            layout: Some(&velocity_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &velocity_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &POSITION_ATTRIBUTES,
+                    },
+                    instance_layout(&INSTANCE_ATTRIBUTES),
+                    instance_layout(&PREVIOUS_INSTANCE_ATTRIBUTES),
+                ],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &velocity_shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: VELOCITY_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let blur_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Motion Blur Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("motion_blur.wgsl").into()),
+        });
+        let blur_pipeline = fullscreen_pipeline(device, "Motion Blur Pipeline", &blur_layout, &blur_shader, "fs_blur", HDR_FORMAT, None);
+
+        Self { velocity_layout, velocity_pipeline, blur_layout, blur_pipeline }
+    }
+}
+
+// Where the camera was for the last frame drawn
+#[derive(Debug, Clone, Copy)]
+struct CameraState {
+    view_proj: Matrix4<f32>,
+    position: Point3<f32>,
+    forward: Vector3<f32>,
+}
+
+impl CameraState {
+    fn new(camera: &Camera, projection: &Projection) -> Self {
+        Self {
+            view_proj: projection.calc_matrix() * camera.calc_matrix(),
+            position: camera.position,
+            forward: camera.forward(),
+        }
+    }
+
+    fn jumped_from(&self, previous: &CameraState) -> bool {
+        let turned = Deg::from(self.forward.angle(previous.forward));
+        (self.position - previous.position).magnitude() > TELEPORT_DISTANCE || turned > TELEPORT_ANGLE
+    }
+}
+
+// This frame's and last frame's camera, unjittered, for passes that look up last frame's pixels
+#[derive(Debug, Clone, Copy)]
+pub struct Reprojection {
+    pub view_proj: Matrix4<f32>,
+    pub previous_view_proj: Matrix4<f32>,
+}
+
+// The window's frame graph entries, only alive from the velocity prepass to the blur
+pub struct MotionBlurTransients {
+    velocity: TransientId,
+    depth: TransientId,
+    // None when only TAA reads the velocity
+    scene_copy: Option<TransientId>,
+}
+
+impl MotionBlurTransients {
+    // The velocity prepass, after the main pass
+    pub fn declare(graph: &mut FrameGraph, config: &wgpu::SurfaceConfiguration) -> Self {
+        let velocity = graph.texture(TransientDesc::new(
+            "motion_velocity",
+            config,
+            VELOCITY_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        let depth = graph.texture(TransientDesc::new("motion_velocity_depth", config, texture::Texture::DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT));
+        graph.pass("velocity prepass", &[], &[velocity, depth]);
+        Self { velocity, depth, scene_copy: None }
+    }
+
+    // The blur itself, the last pass before tonemapping
+    pub fn declare_blur(&mut self, graph: &mut FrameGraph, config: &wgpu::SurfaceConfiguration) {
+        let scene_copy = graph.texture(TransientDesc::new(
+            "motion_blur_scene_copy",
+            config,
+            HDR_FORMAT,
+            wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        graph.pass("motion blur", &[self.velocity], &[scene_copy]);
+        self.scene_copy = Some(scene_copy);
+    }
+
+    pub fn velocity(&self) -> TransientId {
+        self.velocity
+    }
+}
+
+// The blur reads the frame from here and writes it back over the HDR target
+struct BlurTarget {
+    scene_copy: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+// Velocity target and a copy of the frame for one window, recreated with its frame graph
+pub struct MotionBlurTargets {
+    velocity_view: wgpu::TextureView,
+    // The prepass's own, the scene depth may be multisampled
+    depth_view: wgpu::TextureView,
+    // None while only TAA uses the velocity
+    blur: Option<BlurTarget>,
+    velocity_buffer: Tracked<wgpu::Buffer>,
+    blur_buffer: Tracked<wgpu::Buffer>,
+    velocity_bind_group: wgpu::BindGroup,
+    // None until the first frame, and again after a resize
+    previous: Option<CameraState>,
+}
+
+impl MotionBlurTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &MotionBlurPipelines, transients: &Transients, ids: &MotionBlurTransients) -> Self {
+        let velocity_view = transients.view(ids.velocity);
+        let depth_view = transients.view(ids.depth);
+
+        let velocity_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Velocity Buffer"),
+            size: std::mem::size_of::<VelocityUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let blur_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Motion Blur Buffer"),
+            size: std::mem::size_of::<BlurUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let velocity_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.velocity_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: velocity_buffer.as_entire_binding(),
+            }],
+            label: Some("Velocity Bind Group"),
+        });
+        let blur = ids.scene_copy.map(|id| {
+            let scene_copy = transients.texture(id);
+            let scene_copy_view = scene_copy.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipelines.blur_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&scene_copy_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&velocity_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: blur_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("Motion Blur Bind Group"),
+            });
+            BlurTarget { scene_copy, bind_group }
+        });
+
+        Self {
+            velocity_view,
+            depth_view,
+            blur,
+            velocity_buffer,
+            blur_buffer,
+            velocity_bind_group,
+            previous: None,
+        }
+    }
+
+    // Upload this frame's and last frame's camera. None when there is nothing to look back at:
+    // the first frame, or one after the camera jumped.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &MotionBlurSettings, camera: &Camera, projection: &Projection) -> Option<Reprojection> {
+        let current = CameraState::new(camera, projection);
+        let previous = self.previous.replace(current);
+        let previous = previous.filter(|previous| !current.jumped_from(previous))?;
+        let velocity = VelocityUniform {
+            view_proj: current.view_proj.into(),
+            previous_view_proj: previous.view_proj.into(),
+        };
+        queue.write_buffer(&self.velocity_buffer, 0, bytemuck::bytes_of(&velocity));
+        let blur = BlurUniform {
+            inverse_view_proj: current.view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+            previous_view_proj: previous.view_proj.into(),
+            intensity: settings.intensity,
+            max_radius: settings.max_radius,
+            samples: settings.samples.clamp(2, MAX_SAMPLES),
+            _padding: 0,
+        };
+        queue.write_buffer(&self.blur_buffer, 0, bytemuck::bytes_of(&blur));
+        Some(Reprojection { view_proj: current.view_proj, previous_view_proj: previous.view_proj })
+    }
+
+    // Velocity of the instanced scene, draw it with a last-frame instance buffer in slot 2
+    pub fn begin_velocity_pass<'a>(&self, encoder: &'a mut wgpu::CommandEncoder, pipelines: &MotionBlurPipelines) -> wgpu::RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Velocity Prepass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.velocity_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipelines.velocity_pipeline);
+        render_pass.set_bind_group(0, &self.velocity_bind_group, &[]);
+        render_pass
+    }
+
+    // Blur the HDR frame in place, after the velocity prepass. Does nothing without the blur's
+    // frame copy.
+    pub fn encode_blur(&self, encoder: &mut wgpu::CommandEncoder, pipelines: &MotionBlurPipelines, scene: &wgpu::Texture, scene_view: &wgpu::TextureView) {
+        let Some(blur) = &self.blur else {
+            return;
+        };
+        encoder.copy_texture_to_texture(scene.as_image_copy(), blur.scene_copy.as_image_copy(), scene.size());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Motion Blur Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: scene_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipelines.blur_pipeline);
+        render_pass.set_bind_group(0, &blur.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
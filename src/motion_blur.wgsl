@@ -0,0 +1,81 @@
+/*
+Purpose: Smear the HDR frame along its per-pixel velocity
+Responsibilites:
+    - Read the velocity prepass, or reproject the far plane for pixels it didn't cover (the sky),
+      which only the camera moves
+    - Average the frame at evenly spaced points along the velocity, centered on the pixel
+    - Scale the velocity by the intensity and clamp it to the max radius in pixels
+*/
+
+struct BlurUniform {
+    inverse_view_proj: mat4x4<f32>,
+    previous_view_proj: mat4x4<f32>,
+    intensity: f32,
+    // Longest smear in pixels
+    max_radius: f32,
+    samples: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// A copy of the frame, the pass writes back over the original
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+// xy: velocity, z: 1 where the prepass drew something
+@group(0) @binding(1)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> blur: BlurUniform;
+
+// Screen movement since last frame of whatever is infinitely far away behind this pixel
+fn camera_velocity(pixel: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    let uv = (pixel + 0.5) / size;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let world = blur.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let previous = blur.previous_view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    if (previous.w <= 0.0) {
+        return vec2<f32>(0.0);
+    }
+    return (ndc - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let center = textureLoad(t_scene, pixel, 0);
+
+    let covered = textureLoad(t_velocity, pixel, 0);
+    var velocity = covered.xy;
+    if (covered.z == 0.0) {
+        velocity = camera_velocity(in.clip_position.xy - 0.5, size);
+    }
+    var offset = velocity * size * blur.intensity;
+    let length_px = length(offset);
+    if (length_px < 0.5 || blur.samples < 2u) {
+        return center;
+    }
+    if (length_px > blur.max_radius) {
+        offset *= blur.max_radius / length_px;
+    }
+
+    let last = vec2<f32>(size - 1.0);
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < blur.samples; i++) {
+        let t = f32(i) / f32(blur.samples - 1u) - 0.5;
+        let p = clamp(in.clip_position.xy + offset * t, vec2<f32>(0.0), last);
+        sum += textureLoad(t_scene, vec2<i32>(p), 0).rgb;
+    }
+    return vec4<f32>(sum / f32(blur.samples), center.a);
+}
@@ -0,0 +1,65 @@
+/*
+Purpose: Motion blur velocity prepass
+Responsibilites:
+    - Project each vertex with this frame's camera and instance matrix and with last frame's
+    - Write how far it moved across the screen (in UV units) into the velocity target, and
+      mark the pixel as covered
+*/
+
+struct VelocityUniform {
+    view_proj: mat4x4<f32>,
+    previous_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> velocity: VelocityUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    // The same instance as it was posed last frame
+    @location(9) previous_matrix_0: vec4<f32>,
+    @location(10) previous_matrix_1: vec4<f32>,
+    @location(11) previous_matrix_2: vec4<f32>,
+    @location(12) previous_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) current: vec4<f32>,
+    @location(1) previous: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let previous_matrix = mat4x4<f32>(
+        instance.previous_matrix_0,
+        instance.previous_matrix_1,
+        instance.previous_matrix_2,
+        instance.previous_matrix_3,
+    );
+    var out: VertexOutput;
+    out.current = velocity.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    out.previous = velocity.previous_view_proj * previous_matrix * vec4<f32>(position, 1.0);
+    out.clip_position = out.current;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Behind last frame's camera there is no sensible answer, leave it sharp
+    if (in.previous.w <= 0.0) {
+        return vec4<f32>(0.0, 0.0, 1.0, 0.0);
+    }
+    let current = in.current.xy / in.current.w;
+    let previous = in.previous.xy / in.previous.w;
+    // NDC y points up, UV y down
+    return vec4<f32>((current - previous) * vec2<f32>(0.5, -0.5), 1.0, 0.0);
+}
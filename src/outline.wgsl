@@ -0,0 +1,96 @@
+/*
+Purpose: Silhouette outlines for the toon render style
+Responsibilites:
+    - Redraw geometry as an inverted hull: vertices pushed out along their normals
+    - Keep the outline the same width in pixels at any distance
+    - Fill it with one flat color
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: Toon settings, see toon.rs
+struct Toon {
+    outline_color: vec4<f32>,
+    viewport: vec2<f32>,
+    outline_width: f32,
+    bands: u32,
+}
+@group(1) @binding(0)
+var<uniform> toon: Toon;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+// Loaded models (ModelVertex)
+struct ModelVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+// shapes.rs geometry (Vertex)
+struct ShapeVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+}
+
+// Push the projected vertex `outline_width` pixels out along its projected normal.
+// Offsetting in clip space and multiplying by w undoes the perspective divide, so the
+// width doesn't shrink with distance.
+fn hull_position(position: vec3<f32>, normal: vec3<f32>, instance: InstanceInput, depth_push: f32) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var world_position = (model_matrix * vec4<f32>(position, 1.0)).xyz;
+    let world_normal = normalize(normal_matrix * normal);
+    // Away from the camera, a fraction of the distance so it holds up near and far
+    let to_vertex = world_position - camera.view_pos.xyz;
+    world_position += to_vertex * depth_push;
+
+    var clip = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let clip_normal = (camera.view_proj * vec4<f32>(world_normal, 0.0)).xy;
+    if (dot(clip_normal, clip_normal) > 1e-12) {
+        let pixels_to_ndc = 2.0 / max(toon.viewport, vec2<f32>(1.0));
+        clip = vec4<f32>(clip.xy + normalize(clip_normal) * toon.outline_width * pixels_to_ndc * clip.w, clip.zw);
+    }
+    return clip;
+}
+
+// Drawn with front faces culled, the object itself hides the rest of the hull
+@vertex
+fn vs_model(model: ModelVertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return hull_position(model.position, model.normal, instance, 0.0);
+}
+
+// Shapes don't agree on a winding order and are drawn double sided, so the hull can't rely
+// on culling. It is pushed slightly behind the shape instead, only its rim stays visible.
+@vertex
+fn vs_shape(shape: ShapeVertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return hull_position(shape.position, shape.normal, instance, 0.02);
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return toon.outline_color;
+}
@@ -0,0 +1,340 @@
+/*
+Purpose: Draw translucent highlights right on top of the surfaces they mark, without z-fighting
+Responsibilities:
+    - Build the overlay pipeline: model geometry in one flat color per OverlayKind, blended over
+      the frame, depth tested but not written and pulled towards the camera by a depth bias
+    - Work the bias out from the depth test's direction and the near/far range, or take it from
+      the debug sliders, and rebuild the pipeline when it changes
+    - Keep the placement footprint, a quad on the ground under the cursor the size of the model
+      about to be placed
+    - Build the regression scene's ground, one large plane to look across at a grazing angle
+    - ex: a highlighter pen, the ink sits on the page without covering the words
+*/
+
+use std::ops::Range;
+
+use cgmath::{One, Quaternion, Vector3};
+
+use crate::{color, gpu_memory::{self, Tracked}, instance::{Instance, InstanceRaw}, math::Aabb, model::{self, ShadingModel, Vertex}, render_context::RenderContext, resources, shapes, texture};
+
+// Depth units of the constant bias at the default depth range, see OverlayBias::depth_bias
+const BASE_CONSTANT: f32 = 4.0;
+const AUTO_SLOPE_SCALE: f32 = 1.5;
+// far / near of the default depth range (units.rs), where BASE_CONSTANT is enough
+const REFERENCE_RANGE_RATIO: f32 = 1000.0;
+// create_plane is 10 units across
+const FOOTPRINT_PLANE_SIZE: f32 = 10.0;
+// The footprint of a flat model still shows
+const MIN_FOOTPRINT_SIZE: f32 = 0.2;
+// Meters across, far enough that its far edge sits deep in the crowded end of the depth range
+const GROUND_SIZE: f32 = 200.0;
+const GROUND_COLOR: [u8; 4] = [128, 128, 120, 255];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayKind {
+    Selection,
+    Placement,
+    Measure,
+}
+
+impl OverlayKind {
+    pub const ALL: [OverlayKind; 3] = [OverlayKind::Selection, OverlayKind::Placement, OverlayKind::Measure];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OverlayKind::Selection => "Selection",
+            OverlayKind::Placement => "Placement preview",
+            OverlayKind::Measure => "Measured surface",
+        }
+    }
+
+    // sRGB with straight alpha, blended over the frame
+    pub fn color(self) -> [f32; 4] {
+        match self {
+            OverlayKind::Selection => [1.0, 0.55, 0.1, 0.35],
+            OverlayKind::Placement => [0.2, 0.75, 1.0, 0.4],
+            OverlayKind::Measure => [1.0, 0.85, 0.2, 0.3],
+        }
+    }
+}
+
+// How far the overlay is pulled towards the camera, the debug sliders in the menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlayBias {
+    // Worked out from the depth range, constant and slope_scale only count while this is off
+    pub auto: bool,
+    // In steps of the depth format, towards the camera whichever way the depth test goes
+    pub constant: i32,
+    // Times the surface's depth slope, what keeps grazing angles from fighting
+    pub slope_scale: f32,
+    // In meters, times the scene units. The most the bias may lift the overlay off the surface
+    // in the middle of the depth range, it clamps the slope term at silhouettes and grazing angles.
+    pub max_lift: f32,
+}
+
+impl Default for OverlayBias {
+    fn default() -> Self {
+        Self { auto: true, constant: BASE_CONSTANT as i32, slope_scale: AUTO_SLOPE_SCALE, max_lift: 0.05 }
+    }
+}
+
+impl OverlayBias {
+    // The constant and slope scale in use, positive means towards the camera
+    pub fn amounts(&self, (near, far): (f32, f32)) -> (i32, f32) {
+        if !self.auto {
+            return (self.constant, self.slope_scale);
+        }
+        // Depth values crowd against the far end as far / near grows, the rounding of the two
+        // surfaces' depths spans more steps with them
+        let ratio = (far / near.max(f32::EPSILON)).max(2.0);
+        let constant = (BASE_CONSTANT * ratio.log2() / REFERENCE_RANGE_RATIO.log2()).ceil().max(1.0);
+        (constant as i32, AUTO_SLOPE_SCALE)
+    }
+
+    // For a depth test of `compare` (Less here, Greater with reversed Z) and a projection's
+    // depth range. Standard Z maps a distance d to about 1 - near / d, so a lift of L at d moves
+    // the depth by L * near * far / ((far - near) * d^2). In the middle of the range, where
+    // d^2 = near * far, that is L / (far - near), which is what the clamp allows.
+    pub fn depth_bias(&self, compare: wgpu::CompareFunction, depth_range: (f32, f32), units_per_meter: f32) -> wgpu::DepthBiasState {
+        let toward_camera = match compare {
+            wgpu::CompareFunction::Greater | wgpu::CompareFunction::GreaterEqual => 1,
+            _ => -1,
+        };
+        let (constant, slope_scale) = self.amounts(depth_range);
+        let (near, far) = depth_range;
+        wgpu::DepthBiasState {
+            constant: constant * toward_camera,
+            slope_scale: slope_scale * toward_camera as f32,
+            clamp: toward_camera as f32 * self.max_lift * units_per_meter / (far - near).max(f32::EPSILON),
+        }
+    }
+}
+
+// Owned by State, the pipeline is rebuilt when the bias changes
+pub struct OverlayRenderer {
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    bias: wgpu::DepthBiasState,
+    // Without DEPTH_BIAS_CLAMP (GLES) the clamp stays 0, which is no clamp
+    clamp_supported: bool,
+    // By OverlayKind
+    colors: Vec<(Tracked<wgpu::Buffer>, wgpu::BindGroup)>,
+    footprint_mesh: model::Mesh,
+    footprint_instance: Tracked<wgpu::Buffer>,
+    show_footprint: bool,
+}
+
+impl OverlayRenderer {
+    // The depth test of every scene pipeline, see create_render_pipeline
+    pub const SCENE_DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::Less;
+
+    pub fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        camera_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        bias: wgpu::DepthBiasState,
+    ) -> Self {
+        let clamp_supported = adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::DEPTH_BIAS_CLAMP);
+        let bias = wgpu::DepthBiasState { clamp: if clamp_supported { bias.clamp } else { 0.0 }, ..bias };
+        let color_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Overlay Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &color_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("overlay.wgsl").into()),
+        });
+        let colors = OverlayKind::ALL
+            .iter()
+            .map(|kind| {
+                let buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                    label: Some("Overlay Color Buffer"),
+                    contents: bytemuck::cast_slice(&color::srgb_to_linear_rgba(kind.color())),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &color_layout,
+                    entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+                    label: Some("Overlay Bind Group"),
+                });
+                (buffer, bind_group)
+            })
+            .collect();
+        let (vertices, indices) = shapes::create_plane();
+        let footprint_mesh = resources::mesh_from_shape(device, "Placement Footprint", &vertices, &indices);
+        let footprint_instance = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Placement Footprint Instance Buffer"),
+            size: std::mem::size_of::<InstanceRaw>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pipeline = Self::create_pipeline(device, &layout, &shader, color_format, sample_count, bias);
+        Self { layout, shader, color_format, sample_count, pipeline, bias, clamp_supported, colors, footprint_mesh, footprint_instance, show_footprint: false }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        bias: wgpu::DepthBiasState,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            // Both sides, the footprint quad faces down. The far side of a closed mesh fails the
+            // depth test against its near side.
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            // Equal depths pass, the bias does the rest. Nothing is written, so an overlay never
+            // hides what is drawn after it.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: match Self::SCENE_DEPTH_COMPARE {
+                    wgpu::CompareFunction::Greater => wgpu::CompareFunction::GreaterEqual,
+                    _ => wgpu::CompareFunction::LessEqual,
+                },
+                stencil: wgpu::StencilState::default(),
+                bias,
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, mask: !0, alpha_to_coverage_enabled: false },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Rebuilds the pipeline if the bias differs from the one it was built with
+    pub fn set_bias(&mut self, device: &wgpu::Device, bias: wgpu::DepthBiasState) {
+        let bias = wgpu::DepthBiasState { clamp: if self.clamp_supported { bias.clamp } else { 0.0 }, ..bias };
+        if bias == self.bias {
+            return;
+        }
+        self.pipeline = Self::create_pipeline(device, &self.layout, &self.shader, self.color_format, self.sample_count, bias);
+        self.bias = bias;
+    }
+
+    // The bias the pipeline was built with
+    pub fn bias(&self) -> wgpu::DepthBiasState {
+        self.bias
+    }
+
+    // Where the placed model would stand, its bounds' footprint on the ground. None hides it.
+    pub fn set_footprint(&mut self, queue: &wgpu::Queue, footprint: Option<(Vector3<f32>, &Aabb)>) {
+        self.show_footprint = footprint.is_some();
+        let Some((position, bounds)) = footprint else {
+            return;
+        };
+        let size = bounds.size();
+        let mut instance = Instance::placed(position, Quaternion::one(), Vector3::unit_y());
+        instance.scale = Vector3::new(size.x.max(MIN_FOOTPRINT_SIZE), 1.0, size.z.max(MIN_FOOTPRINT_SIZE)) / FOOTPRINT_PLANE_SIZE;
+        queue.write_buffer(&self.footprint_instance, 0, bytemuck::bytes_of(&instance.to_raw(0.0)));
+    }
+
+    // Every mesh of `model` for `instances` of `instance_buffer`, after the model itself was drawn
+    pub fn draw_model(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        kind: OverlayKind,
+        model: &model::Model,
+        instance_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.colors[kind as usize].1, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        for mesh in model.meshes.iter().filter(|mesh| mesh.is_visible()) {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+
+    // The placement footprint, if set_footprint placed one
+    pub fn draw_footprint(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        if !self.show_footprint {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.colors[OverlayKind::Placement as usize].1, &[]);
+        render_pass.set_vertex_buffer(0, self.footprint_mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.footprint_instance.slice(..));
+        render_pass.set_index_buffer(self.footprint_mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.footprint_mesh.num_elements, 0, 0..1);
+    }
+}
+
+// The regression scene's ground at y = 0. Selected and seen from just above it, an overlay that
+// fights the surface shows as stripes towards the horizon.
+pub fn ground_model(context: &RenderContext, units_per_meter: f32) -> anyhow::Result<model::Model> {
+    let (mut shape_vertices, mut indices) = shapes::create_plane();
+    let scale = GROUND_SIZE / FOOTPRINT_PLANE_SIZE * units_per_meter;
+    // create_plane faces down, the ground is seen from above
+    for vertex in &mut shape_vertices {
+        vertex.position = vertex.position.map(|axis| axis * scale);
+        vertex.normal = vertex.normal.map(|axis| -axis);
+    }
+    for triangle in indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+    let mesh = resources::mesh_from_shape(&context.device, "overlay ground", &shape_vertices, &indices);
+
+    let diffuse = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(GROUND_COLOR)));
+    let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+    let material = model::Material::new(
+        &context.device,
+        "overlay_ground",
+        texture::Texture::from_image(&context.device, &context.queue, &diffuse, Some("overlay_ground"), false)?,
+        texture::Texture::from_image(&context.device, &context.queue, &flat_normal, Some("overlay_ground_normal"), true)?,
+        &context.texture_bind_group_layout,
+        model::MaterialParams { shading_model: ShadingModel::BlinnPhong, ..Default::default() },
+    );
+    Ok(model::Model {
+        meshes: vec![mesh],
+        materials: vec![material],
+        optimize_stats: None,
+        packed: None,
+    })
+}
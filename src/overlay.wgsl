@@ -0,0 +1,45 @@
+/*
+Purpose: Coplanar overlays, a translucent flat color drawn right on top of existing surfaces
+Responsibilites:
+    - Transform model geometry exactly like shader.wgsl does, nothing else of the vertex is read
+    - Fill it with the overlay's color, blended over what is already there
+    - The pipeline's depth bias keeps it in front of the surface it covers, see overlay.rs
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: The overlay's color, see OverlayKind in overlay.rs
+struct Overlay {
+    color: vec4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> overlay: Overlay;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * (model_matrix * vec4<f32>(position, 1.0));
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return overlay.color;
+}
@@ -0,0 +1,336 @@
+/*
+Purpose: A small CPU particle emitter drawn as soft, additive billboards
+Responsibilities:
+    - Spawn, move and age particles, upload the live ones as instances
+    - Own the particle pipeline and the per-window binding of the opaque pass's depth
+    - Fade particles where they meet geometry instead of cutting them off (soft particles)
+    - ex: steam rising off a cup that doesn't show a hard line where it meets the table
+*/
+
+use cgmath::{InnerSpace, Vector3};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{camera::{Camera, Projection}, color, gpu_layout::{UniformCheck, VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, texture};
+
+const MAX_PARTICLES: usize = 2048;
+
+// Per-instance input of particles.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleRaw {
+    center_size: [f32; 4],
+    color: [f32; 4],
+    fade_distance: f32,
+}
+
+pub const PARTICLE_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(ParticleRaw, [center_size, color, fade_distance]),
+    desc: ParticleRaw::desc,
+};
+
+impl ParticleRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+            0 => Float32x4, 1 => Float32x4, 2 => Float32,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleViewUniform {
+    right: [f32; 4],
+    up: [f32; 4],
+    depth_range: [f32; 4],
+}
+
+pub const PARTICLE_VIEW_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(ParticleViewUniform, [right, up, depth_range]),
+    wgsl: &[("particles.wgsl", "ParticleView")],
+};
+
+// Shared between windows, lives in the RenderContext
+pub struct ParticlePipeline {
+    pipeline: wgpu::RenderPipeline,
+    view_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl ParticlePipeline {
+    // `depth_sample_count` is the sample count of the depth texture the particles read,
+    // they always draw into the single sampled (resolved) scene target
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_sample_count: u32,
+    ) -> Self {
+        let multisampled = depth_sample_count > 1;
+        let view_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture::Texture::depth_read_layout_entry(0, depth_sample_count),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Particle View Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &view_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut source = include_str!("particles.wgsl").to_string();
+        if multisampled {
+            source = source.replace("texture_2d<f32>", "texture_multisampled_2d<f32>");
+        }
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        // Additive and no depth attachment: the shader does its own depth test against the
+        // scene depth, so the depth texture is never bound as an attachment while it is sampled
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[ParticleRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            view_bind_group_layout,
+        }
+    }
+}
+
+// A window's depth texture and camera basis as the particle shader sees them.
+// Recreated with the depth texture when the window resizes.
+pub struct ParticleViewBindings {
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ParticleViewBindings {
+    pub fn new(device: &wgpu::Device, pipeline: &ParticlePipeline, depth_texture: &wgpu::Texture) -> Self {
+        let depth_view = texture::Texture::create_depth_read_view(depth_texture, "Particle Scene Depth View");
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Particle View Buffer"),
+            size: std::mem::size_of::<ParticleViewUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.view_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Particle View Bind Group"),
+        });
+        Self { buffer, bind_group }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, projection: &Projection) {
+        // The view matrix's rows are the camera's axes in world space
+        let view = camera.calc_matrix();
+        let (znear, zfar) = projection.depth_range();
+        let uniform = ParticleViewUniform {
+            right: [view.x.x, view.y.x, view.z.x, 0.0],
+            up: [view.x.y, view.y.y, view.z.y, 0.0],
+            depth_range: [znear, zfar, 0.0, 0.0],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmitterSettings {
+    pub position: [f32; 3],
+    // Particles per second
+    pub rate: f32,
+    pub lifetime: f32,
+    pub speed: f32,
+    pub size: f32,
+    pub color: [f32; 3],
+    // How far (in world units) in front of geometry a particle starts fading, 0 gives a hard edge
+    pub fade_distance: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            position: [0.0, -0.5, 0.0],
+            rate: 120.0,
+            lifetime: 3.0,
+            speed: 1.2,
+            size: 0.35,
+            color: [1.0, 0.55, 0.2],
+            fade_distance: 0.5,
+        }
+    }
+}
+
+struct Particle {
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    age: f32,
+}
+
+// Owned by State, simulated with the rest of the scene
+pub struct ParticleEmitter {
+    pub settings: EmitterSettings,
+    particles: Vec<Particle>,
+    // Fractional particles carried over to the next frame
+    spawn_debt: f32,
+    rng: StdRng,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    uploaded: u32,
+}
+
+impl ParticleEmitter {
+    pub fn new(device: &wgpu::Device, settings: EmitterSettings) -> Self {
+        Self {
+            settings,
+            particles: Vec::with_capacity(MAX_PARTICLES),
+            spawn_debt: 0.0,
+            rng: StdRng::seed_from_u64(0),
+            instance_buffer: gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("Particle Instance Buffer"),
+                size: (MAX_PARTICLES * std::mem::size_of::<ParticleRaw>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            uploaded: 0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let settings = self.settings;
+
+        // 1. Age and move, drop the dead
+        for particle in &mut self.particles {
+            particle.age += dt;
+            particle.position += particle.velocity * dt;
+        }
+        self.particles.retain(|particle| particle.age < settings.lifetime);
+
+        // 2. Spawn, upwards in a loose cone
+        self.spawn_debt += settings.rate * dt;
+        while self.spawn_debt >= 1.0 && self.particles.len() < MAX_PARTICLES {
+            self.spawn_debt -= 1.0;
+            let direction = Vector3::new(self.rng.gen_range(-0.35..=0.35), 1.0, self.rng.gen_range(-0.35..=0.35)).normalize();
+            self.particles.push(Particle {
+                position: Vector3::from(settings.position),
+                velocity: direction * settings.speed * self.rng.gen_range(0.6..=1.0),
+                age: 0.0,
+            });
+        }
+        self.spawn_debt = self.spawn_debt.min(1.0);
+    }
+
+    // Upload the live particles, growing and fading as they age
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        let settings = self.settings;
+        let [r, g, b] = color::srgb_to_linear_rgb(settings.color);
+        let data = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let life = particle.age / settings.lifetime;
+                let [x, y, z] = particle.position.into();
+                ParticleRaw {
+                    center_size: [x, y, z, settings.size * (0.5 + life)],
+                    color: [r, g, b, 1.0 - life],
+                    fade_distance: settings.fade_distance,
+                }
+            })
+            .collect::<Vec<_>>();
+        if !data.is_empty() {
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&data));
+        }
+        self.uploaded = data.len() as u32;
+    }
+
+    // Draw over the finished opaque scene. `target` must be single sampled, with MSAA it is the resolved scene.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &ParticlePipeline,
+        target: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        view_bindings: &ParticleViewBindings,
+    ) {
+        if self.uploaded == 0 {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Particle Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &view_bindings.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..self.uploaded);
+    }
+}
@@ -0,0 +1,79 @@
+/*
+Purpose: Additive billboard particles that fade out where they meet geometry
+Responsibilites:
+    - Expand each particle into a camera facing quad
+    - Read the opaque pass's depth and fade the particle as it gets close to the surface behind it
+*/
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Depth of the opaque pass, bound as an unfilterable float texture so textureLoad works everywhere.
+// Swapped for texture_multisampled_2d<f32> when MSAA is on, the load below reads sample 0 then.
+@group(1) @binding(0)
+var t_scene_depth: texture_2d<f32>;
+
+struct ParticleView {
+    right: vec4<f32>,
+    up: vec4<f32>,
+    // x: near plane, y: far plane
+    depth_range: vec4<f32>,
+};
+@group(1) @binding(1)
+var<uniform> view: ParticleView;
+
+struct InstanceInput {
+    // xyz: center, w: half size
+    @location(0) center_size: vec4<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) fade_distance: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) fade_distance: f32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    // Triangle strip: (-1, -1), (1, -1), (-1, 1), (1, 1)
+    let corner = vec2<f32>(f32(vertex_index & 1u) * 2.0 - 1.0, f32(vertex_index >> 1u) * 2.0 - 1.0);
+    let offset = (view.right.xyz * corner.x + view.up.xyz * corner.y) * instance.center_size.w;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(instance.center_size.xyz + offset, 1.0);
+    out.corner = corner;
+    out.color = instance.color;
+    out.fade_distance = instance.fade_distance;
+    return out;
+}
+
+// Distance from the camera along its view direction for a [0, 1] depth value
+fn linear_depth(depth: f32) -> f32 {
+    let near = view.depth_range.x;
+    let far = view.depth_range.y;
+    return near * far / (far - depth * (far - near));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Round, soft edged blob
+    let falloff = saturate(1.0 - length(in.corner));
+
+    let scene_depth = linear_depth(textureLoad(t_scene_depth, vec2<i32>(in.clip_position.xy), 0).r);
+    let particle_depth = linear_depth(in.clip_position.z);
+    // Hidden behind geometry fades to nothing, the fade distance just makes the cut gradual
+    var soft = select(0.0, 1.0, particle_depth < scene_depth);
+    if in.fade_distance > 0.0 {
+        soft = saturate((scene_depth - particle_depth) / in.fade_distance);
+    }
+
+    let alpha = in.color.a * falloff * falloff * soft;
+    return vec4<f32>(in.color.rgb * alpha, alpha);
+}
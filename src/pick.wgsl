@@ -0,0 +1,114 @@
+/*
+Purpose: ID buffer for pixel-perfect picking
+Responsibilites:
+    - Draw model instances into an R32Uint target, each with its own pick ID
+    - Compute the position exactly like shader.wgsl's vs_main so the scene's depth can be reused
+    - vs_frame/fs_frame: the whole frame's IDs plus world normals and view depth, for the
+      screen-space outline
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: ID of the model entry's first instance, picking.rs writes one per entry
+struct PickEntry {
+    first_id: u32,
+};
+@group(1) @binding(0)
+var<uniform> entry: PickEntry;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @invariant @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.id = entry.first_id + instance_index;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
+
+struct FrameVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct FrameInstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct FrameOutput {
+    @invariant @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+    @location(1) world_normal: vec3<f32>,
+    // clip w, the distance along the view direction
+    @location(2) view_depth: f32,
+};
+
+struct FrameTargets {
+    @location(0) id: u32,
+    @location(1) normal_depth: vec4<f32>,
+};
+
+@vertex
+fn vs_frame(model: FrameVertexInput, instance: FrameInstanceInput, @builtin(instance_index) instance_index: u32) -> FrameOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    var out: FrameOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.id = entry.first_id + instance_index;
+    out.world_normal = normal_matrix * model.normal;
+    out.view_depth = out.clip_position.w;
+    return out;
+}
+
+@fragment
+fn fs_frame(in: FrameOutput) -> FrameTargets {
+    var out: FrameTargets;
+    out.id = in.id;
+    out.normal_depth = vec4<f32>(normalize(in.world_normal), in.view_depth);
+    return out;
+}
@@ -0,0 +1,399 @@
+/*
+Purpose: Pixel-perfect selection through an ID buffer
+Responsibilities:
+    - Own the pipelines that draw model instances as pick IDs into an R32Uint target
+    - Own each window's ID target, per-entry ID uniforms and the one-pixel readback buffer
+    - Render just the pixel under the cursor (scissored) and read its ID back, 0 is nothing
+    - Render the whole frame's IDs with normals and depth for the screen-space outline
+      (edge_outline.rs), a click then reads the pixel from that frame instead of drawing again
+    - ex: a paint-by-numbers sheet, the number under your finger says what you touched
+*/
+
+use crate::{depth_prepass, gpu_memory::{self, Tracked}, instance::InstanceRaw, model::{self, DrawGeometry, Model, Vertex}, model_entry::{InstanceId, ModelHandle}, render_context::RenderContext, texture};
+
+pub const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+// World normal (xyz) and view depth (w) next to the frame's IDs, zero where nothing was drawn
+pub const NORMAL_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// ID of the first instance drawn, 0 is left for "nothing under the cursor"
+pub const FIRST_PICK_ID: u32 = 1;
+// Every model entry's ID uniform sits in its own slot, dynamic offsets have to be aligned like this
+const ENTRY_STRIDE: wgpu::BufferAddress = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickResult {
+    pub instance: InstanceId,
+}
+
+// One model entry in the ID pass, its instances get first_id, first_id + 1, ...
+pub struct PickDraw<'a> {
+    pub handle: ModelHandle,
+    pub first_id: u32,
+    pub model: &'a Model,
+    pub instance_buffer: &'a wgpu::Buffer,
+    pub instance_count: u32,
+}
+
+impl PickDraw<'_> {
+    pub fn range(&self) -> PickRange {
+        PickRange { handle: self.handle, first_id: self.first_id, instance_count: self.instance_count }
+    }
+
+    // Pick ID of the instance in buffer slot `slot`
+    pub fn id(&self, slot: usize) -> Option<u32> {
+        (slot < self.instance_count as usize).then_some(self.first_id + slot as u32)
+    }
+}
+
+// The IDs a PickDraw handed out, kept with a drawn frame after the draws are gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickRange {
+    pub handle: ModelHandle,
+    pub first_id: u32,
+    pub instance_count: u32,
+}
+
+// Which instance of which draw `id` belongs to, its index being the slot in the draw's instance
+// buffer. ModelEntry::index_at turns that into the instance's id.
+pub fn resolve_pick(ranges: &[PickRange], id: u32) -> Option<PickResult> {
+    ranges.iter().find_map(|range| {
+        let index = id.checked_sub(range.first_id)?;
+        (index < range.instance_count).then_some(PickResult {
+            instance: InstanceId { model: range.handle, index: index as usize },
+        })
+    })
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct PickPipelines {
+    entry_layout: wgpu::BindGroupLayout,
+    // Tested against the scene depth of the last frame: only the nearest surface passes, and
+    // anything else in the scene (shapes, grass) hides what is behind it
+    scene_depth_pipeline: wgpu::RenderPipeline,
+    // Own depth buffer, for MSAA windows whose multisampled depth can't go with the ID target
+    own_depth_pipeline: wgpu::RenderPipeline,
+    // The whole frame's IDs plus normals and depth, tested like the two above
+    frame_scene_depth_pipeline: wgpu::RenderPipeline,
+    frame_own_depth_pipeline: wgpu::RenderPipeline,
+}
+
+impl PickPipelines {
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout) -> Self {
+        let entry_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(4),
+                },
+                count: None,
+            }],
+            label: Some("Pick Entry Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &entry_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Pick Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("pick.wgsl").into()),
+        });
+        let target = |format| Some(wgpu::ColorTargetState { format, blend: None, write_mask: wgpu::ColorWrites::ALL });
+        let id_targets = [target(PICK_FORMAT)];
+        let frame_targets = [target(PICK_FORMAT), target(NORMAL_DEPTH_FORMAT)];
+        let id_buffers = [depth_prepass::position_layout(), depth_prepass::model_matrix_layout()];
+        // The frame pass needs the normals as well
+        let frame_buffers = [model::ModelVertex::desc(), InstanceRaw::desc()];
+        let pipeline = |label: &str, frame: bool, depth_write_enabled: bool, depth_compare: wgpu::CompareFunction| {
+            let (entry_points, buffers, targets) = match frame {
+                true => (("vs_frame", "fs_frame"), &frame_buffers, &frame_targets[..]),
+                false => (("vs_main", "fs_main"), &id_buffers, &id_targets[..]),
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(entry_points.0),
+                    buffers,
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_points.1),
+                    targets,
+                    compilation_options: Default::default(),
+                }),
+                // Culled like render_pipeline, so the pixels that win are the ones on screen
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let scene_depth_pipeline = pipeline("Pick Pipeline (scene depth)", false, false, wgpu::CompareFunction::LessEqual);
+        let own_depth_pipeline = pipeline("Pick Pipeline (own depth)", false, true, wgpu::CompareFunction::Less);
+        let frame_scene_depth_pipeline = pipeline("Pick Frame Pipeline (scene depth)", true, false, wgpu::CompareFunction::LessEqual);
+        let frame_own_depth_pipeline = pipeline("Pick Frame Pipeline (own depth)", true, true, wgpu::CompareFunction::Less);
+
+        Self { entry_layout, scene_depth_pipeline, own_depth_pipeline, frame_scene_depth_pipeline, frame_own_depth_pipeline }
+    }
+}
+
+// What a window needs to pick, created on the first click and again after a resize
+pub struct PickTargets {
+    size: (u32, u32),
+    id_texture: Tracked<wgpu::Texture>,
+    id_view: wgpu::TextureView,
+    // Only for windows that can't share their scene depth, see PickPipelines
+    own_depth: Option<texture::Texture>,
+    entry_buffer: Tracked<wgpu::Buffer>,
+    entry_bind_group: wgpu::BindGroup,
+    entry_capacity: usize,
+    readback_buffer: Tracked<wgpu::Buffer>,
+    // Set while the ID target holds the last frame's IDs, see encode_frame
+    frame: Option<Vec<PickRange>>,
+}
+
+impl PickTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &PickPipelines, config: &wgpu::SurfaceConfiguration, own_depth: bool) -> Self {
+        let id_texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("Pick ID Target"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PICK_FORMAT,
+            // Bound by the outline's edge pass
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let id_view = id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let own_depth = own_depth.then(|| texture::Texture::create_depth_texture(device, config, 1, "pick_depth_texture"));
+        let readback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let (entry_buffer, entry_bind_group) = Self::entry_slots(device, pipelines, 1);
+        Self {
+            size: (config.width, config.height),
+            id_texture,
+            id_view,
+            own_depth,
+            entry_buffer,
+            entry_bind_group,
+            entry_capacity: 1,
+            readback_buffer,
+            frame: None,
+        }
+    }
+
+    pub fn id_view(&self) -> &wgpu::TextureView {
+        &self.id_view
+    }
+
+    fn entry_slots(device: &wgpu::Device, pipelines: &PickPipelines, capacity: usize) -> (Tracked<wgpu::Buffer>, wgpu::BindGroup) {
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Pick Entry Buffer"),
+            size: capacity as wgpu::BufferAddress * ENTRY_STRIDE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.entry_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(4),
+                }),
+            }],
+            label: Some("Pick Entry Bind Group"),
+        });
+        (buffer, bind_group)
+    }
+
+    fn write_entries(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pipelines: &PickPipelines, draws: &[PickDraw]) {
+        if draws.len() > self.entry_capacity {
+            self.entry_capacity = draws.len().next_power_of_two();
+            (self.entry_buffer, self.entry_bind_group) = Self::entry_slots(device, pipelines, self.entry_capacity);
+        }
+        let mut slots = vec![0u8; draws.len() * ENTRY_STRIDE as usize];
+        for (slot, draw) in slots.chunks_exact_mut(ENTRY_STRIDE as usize).zip(draws) {
+            slot[..4].copy_from_slice(bytemuck::bytes_of(&draw.first_id));
+        }
+        queue.write_buffer(&self.entry_buffer, 0, &slots);
+    }
+
+    // Starts the ID pass over the scene depth, or the own depth when the scene's can't be used.
+    // Both are stored, the scene depth is only tested against and has to survive the pass.
+    fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        label: &str,
+        scene_depth: Option<&'a wgpu::TextureView>,
+        normal_depth: Option<&'a wgpu::TextureView>,
+    ) -> Option<wgpu::RenderPass<'a>> {
+        let (depth_view, depth_load) = match (scene_depth, &self.own_depth) {
+            (Some(view), _) => (view, wgpu::LoadOp::Load),
+            (None, Some(own)) => (&own.view, wgpu::LoadOp::Clear(1.0)),
+            (None, None) => return None,
+        };
+        // Clears to ID 0, nothing
+        let attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT), store: wgpu::StoreOp::Store },
+            })
+        };
+        let color_attachments = [Some(&self.id_view), normal_depth].map(|view| view.and_then(attachment));
+        let count = if normal_depth.is_some() { 2 } else { 1 };
+        Some(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &color_attachments[..count],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        }))
+    }
+
+    fn draw_entries(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup, draws: &[PickDraw]) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (slot, draw) in draws.iter().enumerate() {
+            render_pass.set_bind_group(1, &self.entry_bind_group, &[(slot as wgpu::BufferAddress * ENTRY_STRIDE) as u32]);
+            render_pass.set_vertex_buffer(1, draw.instance_buffer.slice(..));
+            render_pass.draw_model_geometry_instanced(draw.model, 0..draw.instance_count);
+        }
+    }
+
+    // Draws every pixel's ID into the ID target, and the normals and depth into `normal_depth`,
+    // after the scene pass so `scene_depth` is this frame's. Clicks read from it until
+    // forget_frame, instead of drawing their pixel again.
+    pub fn encode_frame(
+        &mut self,
+        context: &RenderContext,
+        encoder: &mut wgpu::CommandEncoder,
+        scene_depth: Option<&wgpu::TextureView>,
+        normal_depth: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        draws: &[PickDraw],
+    ) {
+        let pipelines = &context.picking;
+        self.write_entries(&context.device, &context.queue, pipelines, draws);
+        let pipeline = match scene_depth {
+            Some(_) => &pipelines.frame_scene_depth_pipeline,
+            None => &pipelines.frame_own_depth_pipeline,
+        };
+        let Some(mut render_pass) = self.begin_pass(encoder, "Pick Frame Pass", scene_depth, Some(normal_depth)) else {
+            self.frame = None;
+            return;
+        };
+        render_pass.set_pipeline(pipeline);
+        self.draw_entries(&mut render_pass, camera_bind_group, draws);
+        drop(render_pass);
+        self.frame = Some(draws.iter().map(PickDraw::range).collect());
+    }
+
+    // The ID target no longer follows the frames
+    pub fn forget_frame(&mut self) {
+        self.frame = None;
+    }
+
+    // The instance at `pixel`, blocking until the GPU is done. Read from the last frame's IDs
+    // when encode_frame drew them, otherwise `draws` are drawn into just that pixel first.
+    // `scene_depth` is the window's depth from the last frame, when it is single sampled. None
+    // for nothing there, and if the readback failed.
+    pub fn pick(
+        &mut self,
+        context: &RenderContext,
+        scene_depth: Option<&wgpu::TextureView>,
+        camera_bind_group: &wgpu::BindGroup,
+        draws: &[PickDraw],
+        pixel: (u32, u32),
+    ) -> Option<PickResult> {
+        let (device, queue, pipelines) = (&context.device, &context.queue, &context.picking);
+        let (x, y) = (pixel.0.min(self.size.0 - 1), pixel.1.min(self.size.1 - 1));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Pick Encoder") });
+        let ranges = match self.frame.clone() {
+            Some(ranges) => ranges,
+            None => {
+                self.write_entries(device, queue, pipelines, draws);
+                let pipeline = match scene_depth {
+                    Some(_) => &pipelines.scene_depth_pipeline,
+                    None => &pipelines.own_depth_pipeline,
+                };
+                let mut render_pass = self.begin_pass(&mut encoder, "Pick Pass", scene_depth, None)?;
+                render_pass.set_scissor_rect(x, y, 1, 1);
+                render_pass.set_pipeline(pipeline);
+                self.draw_entries(&mut render_pass, camera_bind_group, draws);
+                drop(render_pass);
+                draws.iter().map(PickDraw::range).collect()
+            }
+        };
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = self.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        if let Err(e) = device.poll(wgpu::PollType::Wait) {
+            log::warn!("Waiting for the pick readback failed: {}", e);
+        }
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log::warn!("Could not read back the pick ID: {}", e);
+                return None;
+            }
+            Err(_) => return None,
+        }
+        let id = bytemuck::pod_read_unaligned::<u32>(&slice.get_mapped_range()[..4]);
+        self.readback_buffer.unmap();
+        resolve_pick(&ranges, id)
+    }
+}
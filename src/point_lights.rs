@@ -0,0 +1,160 @@
+/*
+Purpose: Point lights placed by clicking in the scene, on top of the main light
+Responsibilities:
+    - Keep up to MAX_POINT_LIGHTS lights and pack them into the uniform array shader.wgsl loops over
+    - Work out where a placement click puts a light: just off the surface the cursor ray hits,
+      or a fixed distance along the ray when it hits nothing
+    - Build the crosses marking each light in the scene, the selected one larger
+    - ex: clip-on work lamps, pointed at whatever the ceiling light leaves in the dark
+*/
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{color, debug_lines::{self, LineVertex}, gpu_layout::{UniformCheck, rust_layout}, math::Ray};
+
+pub const MAX_POINT_LIGHTS: usize = 16;
+// In meters, multiplied by the scene units
+const SURFACE_OFFSET: f32 = 0.3;
+const EMPTY_SPACE_DISTANCE: f32 = 5.0;
+const DEFAULT_RANGE: f32 = 6.0;
+const MARKER_SIZE: f32 = 0.15;
+const SELECTED_MARKER_SCALE: f32 = 2.0;
+
+// Must match PointLight in shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointLightRaw {
+    position: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+pub const POINT_LIGHT_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(PointLightRaw, [position, range, color, intensity]),
+    wgsl: &[("shader.wgsl", "PointLight")],
+};
+
+// Must match PointLights in shader.wgsl, the first `count` lights are lit
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightsUniform {
+    lights: [PointLightRaw; MAX_POINT_LIGHTS],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+pub const POINT_LIGHTS_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(PointLightsUniform, [lights, count]),
+    wgsl: &[("shader.wgsl", "PointLights")],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PointLightId(u32);
+
+impl PointLightId {
+    // What scripts hold on to, see scripting.rs
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for PointLightId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub id: PointLightId,
+    pub position: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Fades to nothing at this distance
+    pub range: f32,
+}
+
+#[derive(Default)]
+pub struct PointLights {
+    lights: Vec<PointLight>,
+    next_id: u32,
+    pub selected: Option<PointLightId>,
+}
+
+impl PointLights {
+    // A white light of the default range, selected. None when all MAX_POINT_LIGHTS are placed.
+    pub fn add(&mut self, position: Vector3<f32>, units_per_meter: f32) -> Option<PointLightId> {
+        if self.lights.len() >= MAX_POINT_LIGHTS {
+            return None;
+        }
+        let id = PointLightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push(PointLight { id, position, color: [1.0; 3], intensity: 1.0, range: DEFAULT_RANGE * units_per_meter });
+        self.selected = Some(id);
+        Some(id)
+    }
+
+    pub fn remove(&mut self, id: PointLightId) -> bool {
+        let count = self.lights.len();
+        self.lights.retain(|light| light.id != id);
+        if self.selected == Some(id) {
+            self.selected = None;
+        }
+        self.lights.len() != count
+    }
+
+    pub fn get_mut(&mut self, id: PointLightId) -> Option<&mut PointLight> {
+        self.lights.iter_mut().find(|light| light.id == id)
+    }
+
+    // By the number PointLightId::raw gave, scripts only hold on to that
+    pub fn get_mut_raw(&mut self, raw: i64) -> Option<&mut PointLight> {
+        self.lights.iter_mut().find(|light| i64::from(light.id.0) == raw)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PointLight> {
+        self.lights.iter()
+    }
+
+    pub fn uniform(&self) -> PointLightsUniform {
+        let mut uniform = PointLightsUniform {
+            lights: [PointLightRaw { position: [0.0; 3], range: 1.0, color: [0.0; 3], intensity: 0.0 }; MAX_POINT_LIGHTS],
+            count: self.lights.len() as u32,
+            _padding: [0; 3],
+        };
+        for (raw, light) in uniform.lights.iter_mut().zip(&self.lights) {
+            *raw = PointLightRaw { position: light.position.into(), range: light.range.max(1e-3), color: color::srgb_to_linear_rgb(light.color), intensity: light.intensity };
+        }
+        uniform
+    }
+
+    // A cross in each light's color, `scale` is the gizmo scale times the units per meter
+    pub fn marker_lines(&self, scale: f32) -> Vec<LineVertex> {
+        let mut lines = Vec::new();
+        for light in &self.lights {
+            let size = match self.selected == Some(light.id) {
+                true => MARKER_SIZE * SELECTED_MARKER_SCALE,
+                false => MARKER_SIZE,
+            };
+            debug_lines::cross(light.position, size * scale, light.color, &mut lines);
+        }
+        lines
+    }
+}
+
+// Where a light goes for a placement ray. `hit` is the distance to the surface it hits and that
+// surface's normal: the light floats just off the surface, on the side the ray came from.
+// Without a hit it hangs in the air a fixed distance away.
+pub fn placement_point(ray: &Ray, hit: Option<(f32, Vector3<f32>)>, units_per_meter: f32) -> Vector3<f32> {
+    match hit {
+        Some((t, normal)) => {
+            let normal = match normal.dot(ray.direction) > 0.0 {
+                true => -normal,
+                false => normal,
+            };
+            ray.at(t) + normal * SURFACE_OFFSET * units_per_meter
+        }
+        None => ray.at(EMPTY_SPACE_DISTANCE * units_per_meter),
+    }
+}
@@ -0,0 +1,62 @@
+/*
+Purpose: Reflection probe gizmos
+Responsibilites:
+    - Draw a small mirror ball at a probe's position
+    - Show the probe's cubemap on it, or the sky until it is baked
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: The probe, see probes.rs
+struct Probe {
+    position: vec3<f32>,
+    radius: f32,
+    sky_color: vec3<f32>,
+    baked: u32,
+}
+@group(1) @binding(1)
+var t_probe: texture_cube<f32>;
+@group(1) @binding(2)
+var s_probe: sampler;
+@group(1) @binding(3)
+var<uniform> probe: Probe;
+
+// shapes.rs geometry (Vertex), a unit sphere
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @invariant @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    // The camera is only visible to the vertex stage
+    @location(2) view_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    let world_position = probe.position + model.position * probe.radius;
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.world_position = world_position;
+    out.world_normal = model.normal;
+    out.view_position = camera.view_pos.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let view_dir = normalize(in.world_position - in.view_position);
+    let reflected = reflect(view_dir, normalize(in.world_normal));
+    // Faces are baked with z mirrored, see FACES in probes.rs
+    let sampled = textureSample(t_probe, s_probe, vec3<f32>(reflected.x, reflected.y, -reflected.z)).rgb;
+    return vec4<f32>(select(probe.sky_color, sampled, probe.baked != 0u), 1.0);
+}
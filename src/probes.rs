@@ -0,0 +1,444 @@
+/*
+Purpose: Reflection probes, cubemaps of the scene baked from points inside it
+Responsibilities:
+    - Own each probe's cubemap, depth target and the camera it renders its six faces with
+    - Bake on request only, one face per frame so a bake never becomes one long hitch
+    - Own the reflective model pipeline and the sphere gizmos probes are shown with
+    - Provide the sky fallback bound when no baked probe exists
+    - ex: a security mirror hung in a corner, showing the room from where it hangs
+*/
+
+use crate::{gpu_debug::debug_label, camera::{CameraUniform, OPENGL_TO_WGPU_MATRIX}, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, instance::InstanceRaw, model::{self, Vertex as _}, shapes, texture, toon::{ScenePipelineDesc, scene_pipeline}, vertex::Vertex};
+use cgmath::{Deg, Matrix4, Point3, Vector3, perspective};
+
+// In meters, multiplied by the scene's units per meter
+const PROBE_GIZMO_RADIUS: f32 = 0.2;
+const PROBE_NEAR: f32 = 0.05;
+const PROBE_FAR: f32 = 100.0;
+
+// Looking direction and up vector of every face, in cube layer order (+X, -X, +Y, -Y, +Z, -Z).
+// Cubemaps are left-handed, so the faces are rendered right-handed with z mirrored: +Z's layer
+// is rendered looking down -Z, and the shaders flip z before sampling (probe_direction).
+const FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+];
+
+// Stays valid until the probe is removed, ids are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeId(pub u32);
+
+// Must match the Probe struct in shader.wgsl and probe.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeUniform {
+    position: [f32; 3],
+    radius: f32,
+    // Shown instead of the cubemap until something was baked into it
+    sky_color: [f32; 3],
+    baked: u32,
+}
+
+pub const PROBE_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(ProbeUniform, [position, radius, sky_color, baked]),
+    wgsl: &[("shader.wgsl", "Probe"), ("probe.wgsl", "Probe")],
+};
+
+// Shared between windows, lives in the RenderContext
+pub struct ProbePipelines {
+    // Bindings 1-3, binding 0 of the reflective pipeline's group 3 is the toon uniform's in shader.wgsl
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // shader.wgsl's fs_reflective, the realistic pipeline plus a probe in group 3
+    pub reflective_model_pipeline: wgpu::RenderPipeline,
+    gizmo_pipeline: wgpu::RenderPipeline,
+    sphere_vertices: Tracked<wgpu::Buffer>,
+    sphere_indices: Tracked<wgpu::Buffer>,
+    sphere_index_count: u32,
+    // Bound when no baked probe is around, shows the sky color only
+    fallback_buffer: Tracked<wgpu::Buffer>,
+    pub fallback_bind_group: wgpu::BindGroup,
+}
+
+impl ProbePipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let [texture_layout, camera_layout, light_layout] = layouts;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Probe Bind Group Layout"),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Probe Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let model_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Reflective Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let reflective_model_pipeline = scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Reflective Model Pipeline",
+                bind_group_layouts: &[texture_layout, camera_layout, light_layout, &bind_group_layout],
+                shader: &model_shader,
+                entry_points: ("vs_main", "fs_reflective"),
+                vertex_layouts: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                cull_mode: Some(wgpu::Face::Back),
+            },
+            color_format,
+            sample_count,
+        );
+        let gizmo_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Probe Gizmo Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("probe.wgsl").into()),
+        });
+        let gizmo_pipeline = scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Probe Gizmo Pipeline",
+                bind_group_layouts: &[camera_layout, &bind_group_layout],
+                shader: &gizmo_shader,
+                entry_points: ("vs_main", "fs_main"),
+                vertex_layouts: &[Vertex::desc()],
+                cull_mode: None,
+            },
+            color_format,
+            sample_count,
+        );
+        let (vertices, indices) = shapes::create_sphere(1.0, 24, 16);
+        let sphere_vertices = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Probe Gizmo Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let sphere_indices = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Probe Gizmo Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        // Any format will do, the fallback's cubemap is never sampled
+        let fallback_texture = create_cubemap(device, Some("Probe Fallback Cubemap"), 1, wgpu::TextureFormat::Rgba8Unorm);
+        let fallback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Probe Fallback Buffer"),
+            size: std::mem::size_of::<ProbeUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let fallback_bind_group = create_bind_group(device, &bind_group_layout, &sampler, &fallback_texture, &fallback_buffer, Some("Probe Fallback Bind Group"));
+
+        Self {
+            bind_group_layout,
+            sampler,
+            reflective_model_pipeline,
+            gizmo_pipeline,
+            sphere_vertices,
+            sphere_indices,
+            sphere_index_count: indices.len() as u32,
+            fallback_buffer,
+            fallback_bind_group,
+        }
+    }
+
+    // The sky can change (day-night cycle), written every frame with the clear color
+    pub fn write_fallback(&self, queue: &wgpu::Queue, sky_color: [f32; 3]) {
+        let uniform = ProbeUniform { position: [0.0; 3], radius: 0.0, sky_color, baked: 0 };
+        queue.write_buffer(&self.fallback_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // A small mirror ball at every probe, showing what it captured
+    pub fn draw_gizmos<'a>(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup, probes: impl Iterator<Item = &'a ReflectionProbe>) {
+        render_pass.set_pipeline(&self.gizmo_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.sphere_vertices.slice(..));
+        render_pass.set_index_buffer(self.sphere_indices.slice(..), wgpu::IndexFormat::Uint32);
+        for probe in probes {
+            render_pass.set_bind_group(1, &probe.bind_group, &[]);
+            render_pass.draw_indexed(0..self.sphere_index_count, 0, 0..1);
+        }
+    }
+}
+
+fn create_cubemap(device: &wgpu::Device, label: Option<&str>, resolution: u32, format: wgpu::TextureFormat) -> Tracked<wgpu::Texture> {
+    gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+        label,
+        size: wgpu::Extent3d { width: resolution, height: resolution, depth_or_array_layers: 6 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    cubemap: &wgpu::Texture,
+    buffer: &wgpu::Buffer,
+    label: Option<&str>,
+) -> wgpu::BindGroup {
+    let view = cubemap.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&view) },
+            wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(sampler) },
+            wgpu::BindGroupEntry { binding: 3, resource: buffer.as_entire_binding() },
+        ],
+        label,
+    })
+}
+
+// What a face render draws into, see ReflectionProbe::begin_face
+pub struct ProbeFaceTarget {
+    pub face: usize,
+    color_view: wgpu::TextureView,
+    // Multisampled target resolved into color_view when the scene pipelines use MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+    pub camera_bind_group: wgpu::BindGroup,
+}
+
+impl ProbeFaceTarget {
+    pub fn begin_pass<'e>(&self, encoder: &'e mut wgpu::CommandEncoder, clear: wgpu::Color) -> wgpu::RenderPass<'e> {
+        let (view, resolve_target) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&self.color_view)),
+            None => (&self.color_view, None),
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: debug_label!("Probe Face {} Pass", self.face).as_deref(),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(clear), store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: wgpu::StoreOp::Discard }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+}
+
+pub struct ReflectionProbe {
+    pub id: ProbeId,
+    pub position: Point3<f32>,
+    pub resolution: u32,
+    cubemap: Tracked<wgpu::Texture>,
+    msaa_texture: Option<Tracked<wgpu::Texture>>,
+    depth_texture: Tracked<wgpu::Texture>,
+    camera_buffer: Tracked<wgpu::Buffer>,
+    camera_bind_group: wgpu::BindGroup,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // Face the next bake step renders, None when no bake is in progress
+    next_face: Option<usize>,
+    // Every face was rendered at least once
+    baked: bool,
+    world_scale: f32,
+}
+
+// Built with the same formats and sample count as the scene pipelines it renders with
+pub struct ProbeDesc<'a> {
+    pub pipelines: &'a ProbePipelines,
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+    pub color_format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    // Scene units per meter, see units.rs
+    pub world_scale: f32,
+}
+
+impl ReflectionProbe {
+    pub fn new(device: &wgpu::Device, desc: &ProbeDesc, id: ProbeId, position: Point3<f32>, resolution: u32) -> Self {
+        let size = wgpu::Extent3d { width: resolution, height: resolution, depth_or_array_layers: 1 };
+        let cubemap = create_cubemap(device, debug_label!("Probe {} Cubemap", id.0).as_deref(), resolution, desc.color_format);
+        let msaa_texture = (desc.sample_count > 1).then(|| {
+            gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+                label: debug_label!("Probe {} MSAA Target", id.0).as_deref(),
+                size,
+                mip_level_count: 1,
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.color_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+        });
+        let depth_texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: debug_label!("Probe {} Depth", id.0).as_deref(),
+            size,
+            mip_level_count: 1,
+            sample_count: desc.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let camera_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("Probe {} Camera Buffer", id.0).as_deref(),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: desc.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: camera_buffer.as_entire_binding() }],
+            label: debug_label!("Probe {} Camera Bind Group", id.0).as_deref(),
+        });
+        let buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("Probe {} Buffer", id.0).as_deref(),
+            contents: bytemuck::cast_slice(&[ProbeUniform {
+                position: position.into(),
+                radius: PROBE_GIZMO_RADIUS * desc.world_scale,
+                sky_color: [0.0; 3],
+                baked: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = create_bind_group(device, &desc.pipelines.bind_group_layout, &desc.pipelines.sampler, &cubemap, &buffer, debug_label!("Probe {} Bind Group", id.0).as_deref());
+        Self {
+            id,
+            position,
+            resolution,
+            cubemap,
+            msaa_texture,
+            depth_texture,
+            camera_buffer,
+            camera_bind_group,
+            buffer,
+            bind_group,
+            next_face: None,
+            baked: false,
+            world_scale: desc.world_scale,
+        }
+    }
+
+    // Starts over from the first face, also when a bake is already running
+    pub fn request_bake(&mut self) {
+        self.next_face = Some(0);
+    }
+
+    pub fn is_baking(&self) -> bool {
+        self.next_face.is_some()
+    }
+
+    pub fn is_baked(&self) -> bool {
+        self.baked
+    }
+
+    // Faces rendered by the running bake
+    pub fn bake_progress(&self) -> Option<usize> {
+        self.next_face
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // Points the probe camera at the next face. The caller renders the scene into the target,
+    // then calls finish_face. Each face goes in its own submission so the camera buffer can be
+    // rewritten for the next one.
+    pub fn begin_face(&self, queue: &wgpu::Queue) -> Option<ProbeFaceTarget> {
+        let face = self.next_face?;
+        let (direction, up) = FACES[face];
+        let view = Matrix4::look_to_rh(self.position, Vector3::from(direction), Vector3::from(up));
+        let projection = OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, PROBE_NEAR * self.world_scale, PROBE_FAR * self.world_scale);
+        let camera = CameraUniform::from_view_proj(self.position, projection * view);
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+
+        let color_view = self.cubemap.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: face as u32,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        Some(ProbeFaceTarget {
+            face,
+            color_view,
+            msaa_view: self.msaa_texture.as_ref().map(|texture| texture.create_view(&Default::default())),
+            depth_view: self.depth_texture.create_view(&Default::default()),
+            camera_bind_group: self.camera_bind_group.clone(),
+        })
+    }
+
+    pub fn finish_face(&mut self, queue: &wgpu::Queue) {
+        let Some(face) = self.next_face else {
+            return;
+        };
+        if face + 1 < FACES.len() {
+            self.next_face = Some(face + 1);
+            return;
+        }
+        self.next_face = None;
+        if !self.baked {
+            self.baked = true;
+            let uniform = ProbeUniform { position: self.position.into(), radius: PROBE_GIZMO_RADIUS * self.world_scale, sky_color: [0.0; 3], baked: 1 };
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    // New units resize the gizmo and rebake a baked probe with the new near and far planes
+    pub fn set_world_scale(&mut self, queue: &wgpu::Queue, world_scale: f32) {
+        self.world_scale = world_scale;
+        if self.baked {
+            let uniform = ProbeUniform { position: self.position.into(), radius: PROBE_GIZMO_RADIUS * world_scale, sky_color: [0.0; 3], baked: 1 };
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+            self.request_bake();
+        }
+    }
+
+    // Until the first bake finishes the gizmo shows the sky like the fallback
+    pub fn write_sky(&self, queue: &wgpu::Queue, sky_color: [f32; 3]) {
+        if !self.baked {
+            let uniform = ProbeUniform { position: self.position.into(), radius: PROBE_GIZMO_RADIUS * self.world_scale, sky_color, baked: 0 };
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+/*
+Purpose: Nested CPU timing scopes per frame, with GPU pass times on their own track
+Responsibilities:
+    - Time named scopes on a thread-local stack, so every scope knows its parent
+    - Keep the last MAX_FRAMES frames of scopes in a rolling buffer, always recording so a capture
+      can be dumped after something went wrong
+    - Show one frame as an indented tree or as flame bars
+    - Write frames out in the Chrome trace_event format, for about://tracing or Perfetto
+    - ex: a flight recorder, running the whole time and read out after the incident
+*/
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Instant;
+
+// About ten seconds at 60 fps
+pub const MAX_FRAMES: usize = 600;
+// Scopes past this in one frame are not recorded, a runaway loop can't grow the buffer
+const MAX_SCOPES_PER_FRAME: usize = 256;
+const FLAME_ROW_HEIGHT: f32 = 18.0;
+// Track ids in the trace, the GPU gets its own row under the CPU
+const CPU_TID: u32 = 1;
+const GPU_TID: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub name: &'static str,
+    // Nesting level, 0 for scopes opened with nothing else open
+    pub depth: usize,
+    pub start: Instant,
+    pub end: Instant,
+}
+
+// A GPU pass's duration, read back a frame or two after it ran
+#[derive(Debug, Clone)]
+pub struct GpuSpan {
+    pub name: &'static str,
+    pub ms: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameCapture {
+    pub start: Instant,
+    pub end: Instant,
+    // In the order they were opened, parents before their children
+    pub scopes: Vec<Scope>,
+    pub gpu: Vec<GpuSpan>,
+}
+
+#[derive(Default)]
+struct Recorder {
+    scopes: Vec<Scope>,
+    // Indices into scopes of the ones still open, innermost last
+    stack: Vec<usize>,
+}
+
+thread_local! {
+    static RECORDER: RefCell<Recorder> = RefCell::new(Recorder::default());
+}
+
+// Closes its scope when dropped. Not Send, it has to close on the thread that opened it.
+pub struct ScopeGuard {
+    index: Option<usize>,
+    _thread: PhantomData<*const ()>,
+}
+
+// Times everything until the returned guard is dropped
+pub fn scope(name: &'static str) -> ScopeGuard {
+    let index = RECORDER.with_borrow_mut(|recorder| {
+        if recorder.scopes.len() >= MAX_SCOPES_PER_FRAME {
+            return None;
+        }
+        let now = Instant::now();
+        let index = recorder.scopes.len();
+        recorder.scopes.push(Scope { name, depth: recorder.stack.len(), start: now, end: now });
+        recorder.stack.push(index);
+        Some(index)
+    });
+    ScopeGuard { index, _thread: PhantomData }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        let Some(index) = self.index else {
+            return;
+        };
+        RECORDER.with_borrow_mut(|recorder| {
+            recorder.scopes[index].end = Instant::now();
+            if let Some(position) = recorder.stack.iter().rposition(|&open| open == index) {
+                recorder.stack.truncate(position);
+            }
+        });
+    }
+}
+
+pub struct Profiler {
+    pub show: bool,
+    pub flame: bool,
+    // Holds the shown frame still while the buffer keeps rolling
+    pub frozen: Option<FrameCapture>,
+    pub dump_frames: usize,
+    frames: VecDeque<FrameCapture>,
+    frame_start: Instant,
+    pending_gpu: Vec<GpuSpan>,
+    // Trace timestamps count from here
+    epoch: Instant,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            show: false,
+            flame: true,
+            frozen: None,
+            dump_frames: 120,
+            frames: VecDeque::with_capacity(MAX_FRAMES),
+            frame_start: now,
+            pending_gpu: Vec::new(),
+            epoch: now,
+        }
+    }
+}
+
+impl Profiler {
+    // GPU times arrive late, they land in the frame that read them back
+    pub fn record_gpu(&mut self, name: &'static str, ms: f32) {
+        self.pending_gpu.push(GpuSpan { name, ms });
+    }
+
+    // Closes the frame with everything recorded on this thread since the last call. Scopes still
+    // open would lose their parents, so then the frame keeps going until they are closed.
+    pub fn end_frame(&mut self) {
+        let Some(scopes) = RECORDER.with_borrow_mut(|recorder| recorder.stack.is_empty().then(|| std::mem::take(&mut recorder.scopes))) else {
+            return;
+        };
+        let now = Instant::now();
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(FrameCapture {
+            start: self.frame_start,
+            end: now,
+            scopes,
+            gpu: std::mem::take(&mut self.pending_gpu),
+        });
+        self.frame_start = now;
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn latest(&self) -> Option<&FrameCapture> {
+        self.frames.back()
+    }
+
+    // The newest `last_n_frames` as trace_event JSON, B/E pairs in microseconds
+    pub fn chrome_trace(&self, last_n_frames: usize) -> String {
+        let micros = |at: Instant| at.duration_since(self.epoch).as_secs_f64() * 1_000_000.0;
+        let mut events = vec![
+            format!(r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"CPU"}}}}"#, CPU_TID),
+            format!(r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"GPU passes"}}}}"#, GPU_TID),
+        ];
+        let mut event = |name: &str, phase: char, ts: f64, tid: u32| {
+            events.push(format!(r#"{{"name":"{}","ph":"{}","ts":{:.3},"pid":1,"tid":{}}}"#, escape(name), phase, ts, tid));
+        };
+        let skip = self.frames.len().saturating_sub(last_n_frames);
+        for frame in self.frames.iter().skip(skip) {
+            event("frame", 'B', micros(frame.start), CPU_TID);
+            // Children close before their parent and before the next sibling opens
+            let mut open: Vec<&Scope> = Vec::new();
+            for scope in &frame.scopes {
+                while open.last().is_some_and(|last| last.depth >= scope.depth) {
+                    let closed = open.pop().unwrap();
+                    event(closed.name, 'E', micros(closed.end), CPU_TID);
+                }
+                event(scope.name, 'B', micros(scope.start), CPU_TID);
+                open.push(scope);
+            }
+            while let Some(closed) = open.pop() {
+                event(closed.name, 'E', micros(closed.end), CPU_TID);
+            }
+            event("frame", 'E', micros(frame.end), CPU_TID);
+
+            // The timestamps aren't on the CPU clock, only the durations are real, laid end to end
+            let mut at = micros(frame.start);
+            for span in &frame.gpu {
+                event(span.name, 'B', at, GPU_TID);
+                at += span.ms as f64 * 1000.0;
+                event(span.name, 'E', at, GPU_TID);
+            }
+        }
+        format!("{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n", events.join(",\n"))
+    }
+
+    pub fn dump_chrome_trace(&self, path: impl AsRef<Path>, last_n_frames: usize) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace(last_n_frames))
+    }
+
+    pub fn draw(&self, ui: &mut egui::Ui, frame: &FrameCapture) {
+        let frame_ms = ms_between(frame.start, frame.end);
+        ui.label(format!("{} scopes, {:.2} ms frame", frame.scopes.len(), frame_ms));
+        if self.flame {
+            draw_flame(ui, frame, frame_ms);
+        } else {
+            let mut tree = String::new();
+            for scope in &frame.scopes {
+                let _ = writeln!(tree, "{:indent$}{} {:.3} ms", "", scope.name, ms_between(scope.start, scope.end), indent = scope.depth * 2);
+            }
+            ui.label(egui::RichText::new(tree).monospace());
+        }
+        for span in &frame.gpu {
+            ui.label(format!("GPU {}: {:.3} ms", span.name, span.ms));
+        }
+    }
+}
+
+fn ms_between(start: Instant, end: Instant) -> f32 {
+    end.saturating_duration_since(start).as_secs_f32() * 1000.0
+}
+
+// One row per depth, bars placed by time within the frame, hover for the name and duration
+fn draw_flame(ui: &mut egui::Ui, frame: &FrameCapture, frame_ms: f32) {
+    let rows = frame.scopes.iter().map(|scope| scope.depth + 1).max().unwrap_or(1);
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), rows as f32 * FLAME_ROW_HEIGHT), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(160));
+    let x_for = |at: Instant| rect.left() + ms_between(frame.start, at) / frame_ms.max(f32::EPSILON) * rect.width();
+    let hover = response.hover_pos();
+    for scope in &frame.scopes {
+        let top = rect.top() + scope.depth as f32 * FLAME_ROW_HEIGHT;
+        let left = x_for(scope.start);
+        let bar = egui::Rect::from_min_max(egui::pos2(left, top), egui::pos2(x_for(scope.end).max(left + 1.0), top + FLAME_ROW_HEIGHT - 1.0));
+        painter.rect_filled(bar, 1.0, scope_color(scope.name));
+        if bar.width() > 40.0 {
+            painter.with_clip_rect(bar).text(
+                bar.left_center() + egui::vec2(3.0, 0.0),
+                egui::Align2::LEFT_CENTER,
+                scope.name,
+                egui::FontId::proportional(11.0),
+                egui::Color32::BLACK,
+            );
+        }
+        if hover.is_some_and(|pos| bar.contains(pos)) {
+            response.clone().on_hover_text(format!("{}: {:.3} ms", scope.name, ms_between(scope.start, scope.end)));
+        }
+    }
+}
+
+// Pastel and stable per name, so a scope keeps its color from frame to frame
+fn scope_color(name: &str) -> egui::Color32 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    let hash = hasher.finish();
+    let channel = |shift: u32| 140 + ((hash >> shift) & 0x5f) as u8;
+    egui::Color32::from_rgb(channel(0), channel(8), channel(16))
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
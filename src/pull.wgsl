@@ -0,0 +1,44 @@
+/*
+Purpose: Vertex pulling, appended to shader.wgsl or shape.wgsl by vertex_pulling.rs
+Responsibilites:
+    - Read a mesh's vertices out of a storage buffer by vertex_index, where its layout says
+    - Fall back to a default for the attributes the mesh doesn't have
+    - pull_model.wgsl and pull_shape.wgsl follow with the vs_main that uses them
+*/
+
+// Group 3: The mesh's vertices as plain floats and where each attribute starts. Bindings 0-3 are
+// the toon and probe uniforms shader.wgsl declares, the pulling pipelines use neither.
+// Must match LayoutUniform in vertex_pulling.rs
+struct MeshLayout {
+    // Floats from one vertex to the next
+    stride: u32,
+    // Within a vertex in floats, MISSING when the mesh doesn't have the attribute
+    position: u32,
+    tex_coords: u32,
+    normal: u32,
+    tangent: u32,
+    bitangent: u32,
+    color: u32,
+}
+@group(3) @binding(4)
+var<storage, read> vertices: array<f32>;
+@group(3) @binding(5)
+var<uniform> mesh_layout: MeshLayout;
+
+const MISSING: u32 = 0xffffffffu;
+
+fn fetch2(base: u32, offset: u32, fallback: vec2<f32>) -> vec2<f32> {
+    if offset == MISSING {
+        return fallback;
+    }
+    let i = base + offset;
+    return vec2<f32>(vertices[i], vertices[i + 1u]);
+}
+
+fn fetch3(base: u32, offset: u32, fallback: vec3<f32>) -> vec3<f32> {
+    if offset == MISSING {
+        return fallback;
+    }
+    let i = base + offset;
+    return vec3<f32>(vertices[i], vertices[i + 1u], vertices[i + 2u]);
+}
@@ -0,0 +1,28 @@
+/*
+Purpose: The model pipeline's vs_main when pulling, after pull.wgsl
+Responsibilites:
+    - Fill in what a model mesh doesn't have: no UVs, an up normal, tangents around the normal
+    - Hand the attributes to shader.wgsl's transform, the same as the vertex buffer path
+*/
+
+// Any direction across the normal, for meshes without tangents
+fn any_tangent(normal: vec3<f32>) -> vec3<f32> {
+    let axis = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.x) > 0.9);
+    return normalize(cross(axis, normal));
+}
+
+// Replaces shader.wgsl's vs_main, which vertex_pulling.rs renames out of the way
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let base = vertex_index * mesh_layout.stride;
+    var model: VertexInput;
+    model.position = fetch3(base, mesh_layout.position, vec3<f32>(0.0));
+    model.tex_coords = fetch2(base, mesh_layout.tex_coords, vec2<f32>(0.0));
+    model.normal = fetch3(base, mesh_layout.normal, vec3<f32>(0.0, 1.0, 0.0));
+    model.tangent = fetch3(base, mesh_layout.tangent, any_tangent(model.normal));
+    model.bitangent = fetch3(base, mesh_layout.bitangent, cross(model.normal, model.tangent));
+    return transform(model, instance);
+}
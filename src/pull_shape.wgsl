@@ -0,0 +1,21 @@
+/*
+Purpose: The shape pipeline's vs_main when pulling, after pull.wgsl
+Responsibilites:
+    - Fill in what a shape mesh doesn't have: white, no UVs, an up normal
+    - Hand the attributes to shape.wgsl's transform, the same as the vertex buffer path
+*/
+
+// Replaces shape.wgsl's vs_main, which vertex_pulling.rs renames out of the way
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let base = vertex_index * mesh_layout.stride;
+    var model: VertexInput;
+    model.position = fetch3(base, mesh_layout.position, vec3<f32>(0.0));
+    model.color = fetch3(base, mesh_layout.color, vec3<f32>(1.0));
+    model.tex_coords = fetch2(base, mesh_layout.tex_coords, vec2<f32>(0.0));
+    model.normal = fetch3(base, mesh_layout.normal, vec3<f32>(0.0, 1.0, 0.0));
+    return transform(model, instance);
+}
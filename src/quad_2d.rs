@@ -0,0 +1,437 @@
+/*
+Purpose: Textured quads in pixel coordinates, drawn over the 3D scene
+Responsibilities:
+    - Own the 2D pipeline: alpha blended, no depth, drawn after tonemapping and before egui
+    - Load textures through the asset source once per path and hand out ids for them
+    - Collect the frame's quads, sort them by layer and upload them as one instance buffer,
+      consecutive quads with the same texture and filter become one draw call
+    - ex: the stickers on a shop window, the street behind it is the 3D scene
+*/
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pollster::FutureExt;
+
+use crate::{camera::Camera2D, color, gpu_debug::debug_label, gpu_layout::{VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, resources, texture};
+
+// Quads the instance buffer holds at first, it doubles when a frame has more
+const INITIAL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QuadTexture(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuadFilter {
+    #[default]
+    Linear,
+    // No blur between texels, for pixel art at whole zoom levels
+    Nearest,
+}
+
+// Must match QuadInput in quad_2d.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct QuadInstance {
+    position: [f32; 2],
+    size: [f32; 2],
+    uv_rect: [f32; 4],
+    tint: [f32; 4],
+    rotation: f32,
+}
+
+pub const QUAD_INSTANCE_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(QuadInstance, [position, size, uv_rect, tint, rotation]),
+    desc: QuadInstance::desc,
+};
+
+impl QuadInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x4, 3 => Float32x4, 4 => Float32];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<QuadInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// One queued quad, see State::draw_quad_2d. The setters chain on what draw_quad_2d returns.
+pub struct Quad2D {
+    texture: QuadTexture,
+    instance: QuadInstance,
+    layer: i32,
+    filter: QuadFilter,
+}
+
+impl Quad2D {
+    // Higher layers draw over lower ones, quads on the same layer in the order they were queued
+    pub fn layer(&mut self, layer: i32) -> &mut Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn filter(&mut self, filter: QuadFilter) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    // Part of the texture to show, offset and size in UVs, e.g. texture::Atlas::uv_rect
+    pub fn uv_rect(&mut self, uv_rect: [f32; 4]) -> &mut Self {
+        self.instance.uv_rect = uv_rect;
+        self
+    }
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct Quad2DPipeline {
+    pipeline: wgpu::RenderPipeline,
+    camera_layout: wgpu::BindGroupLayout,
+    texture_layout: wgpu::BindGroupLayout,
+    linear_sampler: wgpu::Sampler,
+    nearest_sampler: wgpu::Sampler,
+}
+
+impl Quad2DPipeline {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Camera2D Bind Group Layout"),
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Quad 2D Texture Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Quad 2D Pipeline Layout"),
+            bind_group_layouts: &[&camera_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Quad 2D Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("quad_2d.wgsl").into()),
+        });
+        // Straight into the swapchain like the gizmo, quads are ordered by layer instead of depth
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Quad 2D Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[QuadInstance::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            // Negative sizes mirror a quad, so nothing is culled
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let sampler = |label, filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some(label),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                ..Default::default()
+            })
+        };
+        Self {
+            pipeline,
+            camera_layout,
+            texture_layout,
+            linear_sampler: sampler("Quad 2D Linear Sampler", wgpu::FilterMode::Linear),
+            nearest_sampler: sampler("Quad 2D Nearest Sampler", wgpu::FilterMode::Nearest),
+        }
+    }
+}
+
+// Per-window uniform, every window draws the quads with its own Camera2D
+pub struct ViewQuads {
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ViewQuads {
+    pub fn new(device: &wgpu::Device, pipeline: &Quad2DPipeline) -> Self {
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Camera2D Buffer"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Camera2D Bind Group"),
+        });
+        Self { uniform_buffer, bind_group }
+    }
+}
+
+struct LoadedTexture {
+    _texture: texture::Texture,
+    linear: wgpu::BindGroup,
+    nearest: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+// Consecutive quads drawn with one call
+struct Batch {
+    texture: QuadTexture,
+    filter: QuadFilter,
+    instances: Range<u32>,
+}
+
+#[derive(Default)]
+pub struct QuadBatcher {
+    textures: Vec<LoadedTexture>,
+    by_path: HashMap<String, QuadTexture>,
+    quads: Vec<Quad2D>,
+    // Set when quads were queued or cleared since the last flush
+    dirty: bool,
+    instance_buffer: Option<Tracked<wgpu::Buffer>>,
+    batches: Vec<Batch>,
+}
+
+impl QuadBatcher {
+    // Relative to res/ like model textures. Each path is loaded once, later calls return the same id.
+    pub fn load_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pipeline: &Quad2DPipeline, path: &str) -> anyhow::Result<QuadTexture> {
+        if let Some(&id) = self.by_path.get(path) {
+            return Ok(id);
+        }
+        let data = resources::load_binary(path).block_on()?;
+        let img = image::load_from_memory(&data)?;
+        let texture = texture::Texture::from_image(device, queue, &img, Some(path), false)?;
+        let bind_group = |sampler: &wgpu::Sampler, filter: &str| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipeline.texture_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+                label: debug_label!("{} Quad 2D {} Bind Group", path, filter).as_deref(),
+            })
+        };
+        let linear = bind_group(&pipeline.linear_sampler, "Linear");
+        let nearest = bind_group(&pipeline.nearest_sampler, "Nearest");
+        let id = QuadTexture(self.textures.len());
+        self.textures.push(LoadedTexture { size: (img.width(), img.height()), _texture: texture, linear, nearest });
+        self.by_path.insert(path.to_string(), id);
+        Ok(id)
+    }
+
+    // In pixels, draw a quad this size to show the texture 1:1
+    pub fn texture_size(&self, texture: QuadTexture) -> (u32, u32) {
+        self.textures[texture.0].size
+    }
+
+    // Quads only last one frame, State::update starts every frame empty
+    pub fn clear(&mut self) {
+        if !self.quads.is_empty() {
+            self.quads.clear();
+            self.dirty = true;
+        }
+    }
+
+    // `tint` is sRGB with straight alpha
+    pub fn push(&mut self, texture: QuadTexture, position: [f32; 2], size: [f32; 2], rotation: f32, tint: [f32; 4]) -> &mut Quad2D {
+        self.dirty = true;
+        let tint = color::srgb_to_linear_rgba(tint);
+        self.quads.push(Quad2D {
+            texture,
+            instance: QuadInstance { position, size, uv_rect: [0.0, 0.0, 1.0, 1.0], tint, rotation },
+            layer: 0,
+            filter: QuadFilter::default(),
+        });
+        self.quads.last_mut().unwrap()
+    }
+
+    // Quads and draw calls of the last flush
+    pub fn stats(&self) -> (usize, usize) {
+        (self.quads.len(), self.batches.len())
+    }
+
+    // Upload the queued quads, at most once per frame however many windows draw them
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        // Stable, so quads on one layer keep their order
+        self.quads.sort_by_key(|quad| quad.layer);
+        self.batches.clear();
+        for (index, quad) in self.quads.iter().enumerate() {
+            let index = index as u32;
+            match self.batches.last_mut() {
+                Some(batch) if batch.texture == quad.texture && batch.filter == quad.filter => batch.instances.end = index + 1,
+                _ => self.batches.push(Batch { texture: quad.texture, filter: quad.filter, instances: index..index + 1 }),
+            }
+        }
+        if self.quads.is_empty() {
+            return;
+        }
+
+        let instances: Vec<_> = self.quads.iter().map(|quad| quad.instance).collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&instances);
+        let fits = self.instance_buffer.as_ref().is_some_and(|buffer| buffer.size() >= bytes.len() as u64);
+        if !fits {
+            let capacity = instances.len().next_power_of_two().max(INITIAL_CAPACITY);
+            self.instance_buffer = Some(gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("Quad 2D Instance Buffer"),
+                size: (capacity * std::mem::size_of::<QuadInstance>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.instance_buffer {
+            queue.write_buffer(buffer, 0, bytes);
+        }
+    }
+
+    // Over an already rendered frame, with the window's Camera2D
+    pub fn draw(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &Quad2DPipeline,
+        view: &ViewQuads,
+        camera: &Camera2D,
+        target: &wgpu::TextureView,
+    ) {
+        let Some(buffer) = &self.instance_buffer else {
+            return;
+        };
+        if self.batches.is_empty() {
+            return;
+        }
+        let view_proj: [[f32; 4]; 4] = camera.calc_matrix().into();
+        queue.write_buffer(&view.uniform_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Quad 2D Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, &view.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        for batch in &self.batches {
+            let texture = &self.textures[batch.texture.0];
+            let bind_group = match batch.filter {
+                QuadFilter::Linear => &texture.linear,
+                QuadFilter::Nearest => &texture.nearest,
+            };
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..6, batch.instances.clone());
+        }
+    }
+}
+
+pub const DEMO_TEXTURE: &str = "cube-diffuse.jpg";
+pub const MAX_DEMO_QUADS: u32 = 20_000;
+const DEMO_QUAD_SIZE: f32 = 24.0;
+
+// What the demo queues through State::draw_quad_2d
+pub struct DemoQuad {
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub rotation: f32,
+    pub tint: [f32; 4],
+    pub layer: i32,
+    pub uv_rect: [f32; 4],
+}
+
+// Menu demo: sprites drifting around the window on three layers, each showing a quarter of the
+// texture, the whole texture at 1:1 in the top-left corner to check the filter, and a marker
+// under the cursor
+pub struct QuadDemo {
+    pub enabled: bool,
+    pub count: u32,
+    pub filter: QuadFilter,
+}
+
+impl Default for QuadDemo {
+    fn default() -> Self {
+        Self { enabled: false, count: 2_000, filter: QuadFilter::Nearest }
+    }
+}
+
+impl QuadDemo {
+    pub fn quads(&self, window: cgmath::Vector2<f32>, time: f32) -> impl Iterator<Item = DemoQuad> {
+        let room = window - cgmath::Vector2::new(DEMO_QUAD_SIZE, DEMO_QUAD_SIZE);
+        (0..self.count).map(move |i| {
+            // Golden ratio steps spread the quads evenly without a random generator
+            let a = (i as f32 * 0.618_034).fract();
+            let b = (i as f32 * 0.414_214).fract();
+            let x = (a + time * (0.02 + 0.05 * b)).fract() * room.x;
+            // Triangle wave, the quads bounce between the top and bottom edge
+            let y = (1.0 - ((b + time * (0.03 + 0.04 * a)).fract() * 2.0 - 1.0).abs()) * room.y;
+            DemoQuad {
+                position: [x, y],
+                size: [DEMO_QUAD_SIZE; 2],
+                rotation: time * (b - 0.5) * 4.0,
+                tint: [0.5 + 0.5 * a, 0.5 + 0.5 * b, 1.0 - 0.5 * a, 0.85],
+                layer: (i % 3) as i32,
+                uv_rect: [0.5 * (i % 2) as f32, 0.5 * (i / 2 % 2) as f32, 0.5, 0.5],
+            }
+        })
+    }
+}
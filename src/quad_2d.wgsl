@@ -0,0 +1,66 @@
+/*
+Purpose: 2D pass
+Responsibilites:
+    - Draw textured, tinted and rotated quads positioned in pixels, one instance per quad
+*/
+
+// Group 0: Camera2D, pixels with y down
+struct Camera2D {
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera2D;
+
+// Group 1: The batch's texture, with a linear or nearest sampler
+@group(1) @binding(0)
+var t_quad: texture_2d<f32>;
+@group(1) @binding(1)
+var s_quad: sampler;
+
+// Must match QuadInstance in quad_2d.rs
+struct QuadInput {
+    // Top-left corner before rotation, and size, in pixels
+    @location(0) position: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    // xy: offset, zw: scale into the texture
+    @location(2) uv_rect: vec4<f32>,
+    @location(3) tint: vec4<f32>,
+    // Radians around the quad's center, clockwise on screen
+    @location(4) rotation: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) tint: vec4<f32>,
+};
+
+// Two triangles, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, quad: QuadInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[index];
+    let local = (corner - 0.5) * quad.size;
+    let c = cos(quad.rotation);
+    let s = sin(quad.rotation);
+    let rotated = vec2<f32>(local.x * c - local.y * s, local.x * s + local.y * c);
+    let center = quad.position + quad.size * 0.5;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(center + rotated, 0.0, 1.0);
+    out.tex_coords = quad.uv_rect.xy + corner * quad.uv_rect.zw;
+    out.tint = quad.tint;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_quad, s_quad, in.tex_coords) * in.tint;
+}
@@ -0,0 +1,560 @@
+/*
+Purpose: GPU resources shared by every window
+Responsibilities:
+    - Own the wgpu instance, adapter, device and queue
+    - Own the bind group layouts and render pipelines
+    - Own loaded assets so every window draws the same data
+    - ex: the power plant every window plugs into
+*/
+
+use crate::{config::RenderSettings, custom_shader, debug_lines::DebugLinePipeline, depth_debug::DepthDebugPipelines, depth_prepass::DepthPrepassPipelines, edge_outline::OutlinePipelines, error_log::{self, ErrorLog}, foliage::GrassPipeline, gizmo::GizmoPipeline, gpu_memory::{self, Tracked}, hdr::{self, HdrPipelines}, hiz::HiZPipelines, instance::InstanceRaw, instance_anim::InstanceAnimationPipeline, instance_cull::{self, InstanceCullPipeline}, material_array::MaterialArrayPipeline, model::{self, Vertex}, motion_blur::MotionBlurPipelines, particles::ParticlePipeline, picking::PickPipelines, probes::ProbePipelines, quad_2d::Quad2DPipeline, resources, shape_renderer::ShapePipeline, skinning::SkinningPipeline, ssao, stereo::AnaglyphPipeline, taa::TaaPipeline, texture, texture_stream::TextureStreamer, toon::ToonPipelines, vertex_pulling::{self, VertexPullingPipeline}};
+use std::sync::{Arc, Mutex};
+
+pub struct RenderContext {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub surface_format: wgpu::TextureFormat,
+    // What the scene pipelines draw into: HDR_FORMAT, or surface_format when HDR is off
+    pub scene_format: wgpu::TextureFormat,
+    // Settings the pipelines were built with (MSAA may be lowered if the adapter can't do it)
+    pub settings: RenderSettings,
+    pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    pub light_bind_group_layout: wgpu::BindGroupLayout,
+    // Material textures, needed to load more models after startup
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    // Frame uniforms, only custom shaders read them, see custom_shader::ENGINE_GROUPS
+    pub frame_bind_group_layout: wgpu::BindGroupLayout,
+    pub render_pipeline: wgpu::RenderPipeline,
+    // render_pipeline for models whose materials were packed into texture arrays
+    pub material_array: MaterialArrayPipeline,
+    // RenderStyle::Toon versions of render_pipeline, plus the outline pass
+    pub toon: ToonPipelines,
+    pub light_render_pipeline: wgpu::RenderPipeline,
+    // Depth-only pass and the Equal-depth version of render_pipeline that goes with it
+    pub depth_prepass: DepthPrepassPipelines,
+    pub picking: PickPipelines,
+    // Screen-space outlines over the tonemapped frame, see edge_outline.rs
+    pub outline: OutlinePipelines,
+    // The --model model, shared with the scene's first model entry
+    pub obj_model: Arc<model::Model>,
+    // Uploads obj_model's big textures over several frames, pumped by State::update
+    pub texture_streamer: Mutex<TextureStreamer>,
+    // Demo sprite sheet, instances pick regions of it through their UV transform
+    pub atlas: texture::Atlas,
+    pub atlas_material: model::Material,
+    pub ssao: ssao::SsaoPipelines,
+    // Combines the eye targets of a window in anaglyph stereo
+    pub anaglyph: AnaglyphPipeline,
+    pub shape_pipeline: ShapePipeline,
+    pub particle_pipeline: ParticlePipeline,
+    pub grass_pipeline: GrassPipeline,
+    // Reflective model pipeline, probe gizmos and the sky fallback
+    pub probe_pipelines: ProbePipelines,
+    pub gizmo_pipeline: GizmoPipeline,
+    pub debug_lines: DebugLinePipeline,
+    // Textured quads in pixels, drawn after tonemapping
+    pub quad_2d: Quad2DPipeline,
+    // Depth thumbnail and capture, see depth_debug.rs
+    pub depth_debug: DepthDebugPipelines,
+    pub instance_animation: InstanceAnimationPipeline,
+    // Present when the adapter has compute shaders and indirect draws
+    pub instance_cull: Option<InstanceCullPipeline>,
+    // The occlusion cull's depth pyramid, present with instance_cull
+    pub hiz: Option<HiZPipelines>,
+    pub skinning: SkinningPipeline,
+    // Present when the adapter reads storage buffers in the vertex stage
+    pub vertex_pulling: Option<VertexPullingPipeline>,
+    // Present when HDR is on
+    pub hdr: Option<HdrPipelines>,
+    // Present when HDR is on, the blur works on the HDR frame
+    pub motion_blur: Option<MotionBlurPipelines>,
+    // Present when HDR is on and MSAA off, TAA resolves the HDR frame instead of multisampling it
+    pub taa: Option<TaaPipeline>,
+    // Errors shown in the error overlay, wgpu's are routed here from the moment the device exists
+    pub error_log: Arc<Mutex<ErrorLog>>,
+    // Bytes of buffers and textures past which the error overlay warns
+    pub memory_budget: u64,
+}
+
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    sample_count: u32,
+    shader: wgpu::ShaderModuleDescriptor,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),  // vertex shader function
+                buffers: vertex_layouts,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"), // fragment shader function
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState {
+                        alpha: wgpu::BlendComponent::REPLACE,
+                        color: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+    })
+}
+
+// Layout entry for a plain 2D texture read by a fragment shader
+pub fn texture_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type,
+        },
+        count: None,
+    }
+}
+
+// Pipeline for a fullscreen triangle pass (vs_fullscreen, no vertex buffers, no depth)
+pub fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_fullscreen"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_point),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+// Procedural sprites for the atlas demo: checkerboards in a few sizes and colors
+fn demo_sprites() -> Vec<(String, image::DynamicImage)> {
+    let colors = [
+        ("red", [220, 60, 60]),
+        ("green", [60, 200, 90]),
+        ("blue", [60, 110, 230]),
+        ("yellow", [240, 210, 60]),
+        ("purple", [160, 80, 210]),
+        ("orange", [240, 140, 40]),
+    ];
+    colors
+        .iter()
+        .enumerate()
+        .map(|(i, (name, [r, g, b]))| {
+            let size = if i % 2 == 0 { 64 } else { 32 };
+            let cell = size / 4;
+            let img = image::RgbaImage::from_fn(size, size, |x, y| {
+                if (x / cell + y / cell) % 2 == 0 {
+                    image::Rgba([*r, *g, *b, 255])
+                } else {
+                    image::Rgba([r / 3, g / 3, b / 3, 255])
+                }
+            });
+            (name.to_string(), image::DynamicImage::ImageRgba8(img))
+        })
+        .collect()
+}
+
+impl RenderContext {
+    // The first window's surface is used to pick an adapter that can present to it.
+    // Every other window is expected to support the same surface format.
+    // Without a surface (headless benchmark) the context renders to offscreen targets.
+    pub async fn new(
+        instance: wgpu::Instance,
+        surface: Option<&wgpu::Surface<'static>>,
+        mut settings: RenderSettings,
+        model_path: &str,
+    ) -> anyhow::Result<Self> {
+        // 1. Choose an adapter (represents a physical GPU)
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter: false,
+            })
+            .await?;
+
+        // 2. Request device and queue (logical GPU + command queue)
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Device"),
+                    // Timestamps are only for the stats panel, not every adapter has them
+                    required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                    required_limits: wgpu::Limits::default(),
+                    memory_hints: wgpu::MemoryHints::default(),
+                    trace: wgpu::Trace::Off, // trace path
+                },
+            )
+            .await?;
+        let error_log = Arc::new(Mutex::new(ErrorLog::default()));
+        error_log::capture_device_errors(&device, error_log.clone());
+        let memory_budget = settings.memory_budget.unwrap_or_else(|| gpu_memory::default_budget(&adapter));
+
+        // 3. Get the surface's format, an sRGB one when there is one so the hardware encodes the
+        // linear colors the shaders write (see color.rs). Otherwise only the HDR tonemap does.
+        let surface_format = match surface {
+            Some(surface) => {
+                let formats = surface.get_capabilities(&adapter).formats;
+                let format = formats.iter().copied().find(wgpu::TextureFormat::is_srgb).unwrap_or(formats[0]);
+                if !format.is_srgb() && !settings.hdr {
+                    log::warn!("The surface has no sRGB format and HDR is off, colors will come out dark");
+                }
+                format
+            }
+            None => wgpu::TextureFormat::Rgba8UnormSrgb,
+        };
+
+        let scene_format = if settings.hdr { hdr::HDR_FORMAT } else { surface_format };
+
+        // Not every adapter can multisample every format, fall back to no MSAA
+        let format_features = adapter.get_texture_format_features(scene_format);
+        if !format_features.flags.sample_count_supported(settings.msaa_samples) {
+            log::warn!("{}x MSAA is not supported for {:?}, disabling it", settings.msaa_samples, scene_format);
+            settings.msaa_samples = 1;
+        }
+
+        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // Shading model and its parameters, see model::MaterialParams
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        });
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Camera Bind Group Layout"),
+        });
+        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // User clip planes, only shader.wgsl reads them
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Placed point lights, see point_lights.rs
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Light Bind Group Layout"),
+        });
+
+        let frame_bind_group_layout = custom_shader::frame_bind_group_layout(&device);
+
+        // Before the model is loaded, packing its materials needs the array layout
+        let material_array = MaterialArrayPipeline::new(&device, [&camera_bind_group_layout, &light_bind_group_layout], scene_format, settings.msaa_samples);
+
+        let mut texture_streamer = TextureStreamer::default();
+        let obj_model = Arc::new(
+            resources::load_model(
+                model_path,
+                &device,
+                &queue,
+                &texture_bind_group_layout,
+                &material_array.bind_group_layout,
+                &mut texture_streamer,
+                &settings.mesh_load,
+            )
+            .await?,
+        );
+
+        // Atlas demo material, a flat normal map keeps the lighting the same as the model's
+        let (atlas, atlas_texture) = texture::Atlas::new(&device, &queue, &demo_sprites(), 256, 256, "demo_atlas")?;
+        let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+        let flat_normal_texture = texture::Texture::from_image(&device, &queue, &flat_normal, Some("flat_normal"), true)?;
+        let atlas_material = model::Material::new(
+            &device,
+            "demo_atlas",
+            atlas_texture,
+            flat_normal_texture,
+            &texture_bind_group_layout,
+            model::MaterialParams::default(),
+        );
+
+        // 4. Define pipeline layout
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // 5. Create render pipelines
+        let render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+            };
+            create_render_pipeline(
+                &device,
+                &render_pipeline_layout,
+                scene_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                settings.msaa_samples,
+                shader,
+            )
+        };
+
+        let light_render_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+            };
+            create_render_pipeline(
+                &device,
+                &layout,
+                scene_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[model::ModelVertex::desc()],
+                settings.msaa_samples,
+                shader,
+            )
+        };
+
+        let depth_prepass = DepthPrepassPipelines::new(
+            &device,
+            [&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout],
+            scene_format,
+            settings.msaa_samples,
+        );
+        let picking = PickPipelines::new(&device, &camera_bind_group_layout);
+        let ssao = ssao::SsaoPipelines::new(&device, &queue, &camera_bind_group_layout, scene_format);
+        let anaglyph = AnaglyphPipeline::new(&device, scene_format);
+        let toon = ToonPipelines::new(
+            &device,
+            [&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout],
+            scene_format,
+            settings.msaa_samples,
+        );
+        // Particles read the (possibly multisampled) depth but draw into the resolved scene
+        let particle_pipeline = ParticlePipeline::new(&device, &camera_bind_group_layout, scene_format, settings.msaa_samples);
+        let probe_pipelines = ProbePipelines::new(
+            &device,
+            [&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout],
+            scene_format,
+            settings.msaa_samples,
+        );
+        let debug_lines = DebugLinePipeline::new(&device, &camera_bind_group_layout, scene_format, settings.msaa_samples);
+        let grass_pipeline = GrassPipeline::new(&device, [&camera_bind_group_layout, &light_bind_group_layout], scene_format, settings.msaa_samples);
+        // The gizmo draws after tonemapping, straight into the swapchain
+        let gizmo_pipeline = GizmoPipeline::new(&device, surface_format);
+        let quad_2d = Quad2DPipeline::new(&device, surface_format);
+        let depth_debug = DepthDebugPipelines::new(&adapter, &device, surface_format, settings.msaa_samples);
+        let outline = OutlinePipelines::new(&device, surface_format);
+        let instance_animation = InstanceAnimationPipeline::new(&device);
+        let instance_cull = instance_cull::gpu_culling_supported(&adapter).then(|| InstanceCullPipeline::new(&device));
+        let hiz = instance_cull.is_some().then(|| HiZPipelines::new(&device, settings.msaa_samples));
+        let skinning = SkinningPipeline::new(&device);
+        let vertex_pulling = vertex_pulling::supported(&adapter).then(|| {
+            VertexPullingPipeline::new(&device, [&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout], scene_format, settings.msaa_samples)
+        });
+        if vertex_pulling.is_none() {
+            log::info!("The adapter can't read storage buffers in vertex shaders, models and shapes draw from vertex buffers");
+        }
+        let shape_pipeline = ShapePipeline::new(
+            &device,
+            &camera_bind_group_layout,
+            &toon.bind_group_layout,
+            vertex_pulling.as_ref().map(|pulling| &pulling.bind_group_layout),
+            scene_format,
+            settings.msaa_samples,
+        );
+        let hdr = settings.hdr.then(|| HdrPipelines::new(&device, surface_format));
+        let motion_blur = settings.hdr.then(|| MotionBlurPipelines::new(&device));
+        let taa = (settings.hdr && settings.msaa_samples == 1).then(|| TaaPipeline::new(&device));
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            surface_format,
+            scene_format,
+            settings,
+            camera_bind_group_layout,
+            light_bind_group_layout,
+            texture_bind_group_layout,
+            frame_bind_group_layout,
+            render_pipeline,
+            material_array,
+            toon,
+            light_render_pipeline,
+            depth_prepass,
+            picking,
+            outline,
+            obj_model,
+            texture_streamer: Mutex::new(texture_streamer),
+            atlas,
+            atlas_material,
+            ssao,
+            anaglyph,
+            shape_pipeline,
+            particle_pipeline,
+            grass_pipeline,
+            probe_pipelines,
+            gizmo_pipeline,
+            debug_lines,
+            quad_2d,
+            depth_debug,
+            instance_animation,
+            instance_cull,
+            hiz,
+            skinning,
+            vertex_pulling,
+            hdr,
+            motion_blur,
+            taa,
+            error_log,
+            memory_budget,
+        })
+    }
+
+    // Counted by gpu_memory.rs like every other buffer the engine makes
+    pub fn create_buffer_init(&self, desc: &wgpu::util::BufferInitDescriptor) -> Tracked<wgpu::Buffer> {
+        gpu_memory::create_buffer_init(&self.device, desc)
+    }
+}
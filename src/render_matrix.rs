@@ -0,0 +1,180 @@
+/*
+Purpose: Compare settings side by side, the same view rendered once per variant on one sheet
+Responsibilities:
+    - Define RenderVariant, a label and what it changes before its frame is rendered
+    - Offer the preset matrices: every shading model, every tonemapper, three exposures
+    - Lay the frames out in a grid with each label under its frame, rasterized with egui's fonts
+    - Name the single frames written next to the sheet
+    - ex: the paint store's swatch card, one square per color and its name printed under it
+*/
+
+use std::path::{Path, PathBuf};
+
+use egui::epaint::{AlphaFromCoverage, text::Fonts};
+
+use crate::{hdr::Tonemapper, model::ShadingModel, state::State};
+
+// Frames wider than this are scaled down on the sheet, the single frames keep their size
+const TILE_WIDTH: u32 = 480;
+const MAX_COLUMNS: usize = 4;
+// Around and between the tiles
+const MARGIN: u32 = 12;
+const LABEL_HEIGHT: u32 = 26;
+const LABEL_SIZE: f32 = 16.0;
+const BACKGROUND: [u8; 3] = [24, 24, 28];
+// Where a failed variant's frame would be
+const EMPTY_TILE: [u8; 3] = [60, 20, 20];
+// Stops around the current exposure
+const EXPOSURE_STEPS: [f32; 3] = [-1.0, 0.0, 1.0];
+
+type Apply = Box<dyn Fn(&mut State)>;
+
+// Changes settings on State before its frame, State::render_matrix puts them back after it
+pub struct RenderVariant {
+    pub label: String,
+    apply: Apply,
+}
+
+impl RenderVariant {
+    pub fn new(label: impl Into<String>, apply: impl Fn(&mut State) + 'static) -> Self {
+        Self { label: label.into(), apply: Box::new(apply) }
+    }
+
+    pub fn apply(&self, state: &mut State) {
+        (self.apply)(state);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixPreset {
+    ShadingModels,
+    Tonemappers,
+    Exposures,
+}
+
+impl MatrixPreset {
+    pub const ALL: [MatrixPreset; 3] = [MatrixPreset::ShadingModels, MatrixPreset::Tonemappers, MatrixPreset::Exposures];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MatrixPreset::ShadingModels => "Shading models",
+            MatrixPreset::Tonemappers => "Tonemappers",
+            MatrixPreset::Exposures => "Exposure -1/0/+1 EV",
+        }
+    }
+
+    // Where the sheet goes unless another path is given
+    pub fn default_path(self) -> &'static str {
+        match self {
+            MatrixPreset::ShadingModels => "compare-shading.png",
+            MatrixPreset::Tonemappers => "compare-tonemappers.png",
+            MatrixPreset::Exposures => "compare-exposure.png",
+        }
+    }
+
+    pub fn variants(self) -> Vec<RenderVariant> {
+        match self {
+            MatrixPreset::ShadingModels => ShadingModel::ALL
+                .into_iter()
+                .map(|model| RenderVariant::new(model.label(), move |state: &mut State| state.set_every_material_shading(model)))
+                .collect(),
+            MatrixPreset::Tonemappers => Tonemapper::ALL
+                .into_iter()
+                .map(|tonemapper| RenderVariant::new(tonemapper.label(), move |state: &mut State| state.hdr_settings.tonemapper = tonemapper))
+                .collect(),
+            MatrixPreset::Exposures => EXPOSURE_STEPS
+                .into_iter()
+                .map(|stops| RenderVariant::new(format!("{:+} EV", stops), move |state: &mut State| state.hdr_settings.exposure_ev += stops))
+                .collect(),
+        }
+    }
+}
+
+// "out/sheet.png" and "Blinn-Phong" become "out/sheet-2-blinn-phong.png", counting from 1. A
+// sign in front of a number is spelled out, so "-1 EV" and "+1 EV" don't share a name.
+pub fn frame_path(sheet: &Path, index: usize, label: &str) -> PathBuf {
+    let stem = sheet.file_stem().map_or("matrix".into(), |stem| stem.to_string_lossy());
+    let mut slug = String::new();
+    let mut chars = label.chars().peekable();
+    while let Some(c) = chars.next() {
+        let signed = chars.peek().is_some_and(char::is_ascii_digit) && !slug.ends_with(|c: char| c.is_ascii_alphanumeric());
+        match c {
+            '+' if signed => slug.push_str("plus"),
+            '-' if signed => slug.push_str("minus"),
+            c if c.is_ascii_alphanumeric() => slug.push(c.to_ascii_lowercase()),
+            _ if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+            _ => {}
+        }
+    }
+    sheet.with_file_name(format!("{}-{}-{}.png", stem, index + 1, slug.trim_end_matches('-')))
+}
+
+// Every frame scaled to one tile size, left to right and top to bottom with its label under it.
+// None leaves an empty tile, for a variant that failed. `frames` must hold at least one image.
+pub fn contact_sheet(frames: &[(String, Option<image::RgbaImage>)]) -> anyhow::Result<image::RgbaImage> {
+    let Some(first) = frames.iter().find_map(|(_, frame)| frame.as_ref()) else {
+        anyhow::bail!("no frame was rendered");
+    };
+    let tile_width = first.width().min(TILE_WIDTH);
+    let tile_height = (first.height() as u64 * tile_width as u64 / first.width().max(1) as u64).max(1) as u32;
+    // As few rows as MAX_COLUMNS allows, filled evenly
+    let rows = frames.len().div_ceil(MAX_COLUMNS);
+    let columns = frames.len().div_ceil(rows) as u32;
+    let rows = rows as u32;
+    let cell = (tile_width + MARGIN, tile_height + LABEL_HEIGHT + MARGIN);
+    let [r, g, b] = BACKGROUND;
+    let mut sheet = image::RgbaImage::from_pixel(MARGIN + columns * cell.0, MARGIN + rows * cell.1, image::Rgba([r, g, b, 255]));
+
+    let fonts = Fonts::new(1.0, 2048, AlphaFromCoverage::default(), egui::FontDefinitions::default());
+    for (index, (label, frame)) in frames.iter().enumerate() {
+        let (x, y) = (MARGIN + index as u32 % columns * cell.0, MARGIN + index as u32 / columns * cell.1);
+        match frame {
+            Some(frame) => {
+                let tile = image::imageops::resize(frame, tile_width, tile_height, image::imageops::FilterType::Triangle);
+                image::imageops::replace(&mut sheet, &tile, x as i64, y as i64);
+            }
+            None => {
+                let [r, g, b] = EMPTY_TILE;
+                let tile = image::RgbaImage::from_pixel(tile_width, tile_height, image::Rgba([r, g, b, 255]));
+                image::imageops::replace(&mut sheet, &tile, x as i64, y as i64);
+            }
+        }
+        let text = if frame.is_some() { label.clone() } else { format!("{} (failed)", label) };
+        draw_label(&mut sheet, &fonts, &text, (x, y + tile_height), tile_width);
+    }
+    Ok(sheet)
+}
+
+// White text centered in the `width` wide label strip starting at `corner`, clipped to it
+fn draw_label(sheet: &mut image::RgbaImage, fonts: &Fonts, text: &str, corner: (u32, u32), width: u32) {
+    let galley = fonts.layout_no_wrap(text.to_string(), egui::FontId::proportional(LABEL_SIZE), egui::Color32::WHITE);
+    // Laying the text out rasterizes its glyphs into the atlas
+    let atlas = fonts.image();
+    let left = corner.0 as f32 + ((width as f32 - galley.size().x) * 0.5).max(0.0);
+    let top = corner.1 as f32 + (LABEL_HEIGHT as f32 - galley.size().y) * 0.5;
+    let right = (corner.0 + width).min(sheet.width());
+    let bottom = (corner.1 + LABEL_HEIGHT).min(sheet.height());
+    for row in &galley.rows {
+        for glyph in &row.glyphs {
+            let uv = glyph.uv_rect;
+            if uv.is_nothing() {
+                continue;
+            }
+            let origin = row.pos + glyph.pos.to_vec2() + uv.offset;
+            let (origin_x, origin_y) = ((left + origin.x).round() as i64, (top + origin.y).round() as i64);
+            for v in uv.min[1]..uv.max[1] {
+                for u in uv.min[0]..uv.max[0] {
+                    let (x, y) = (origin_x + (u - uv.min[0]) as i64, origin_y + (v - uv.min[1]) as i64);
+                    if x < corner.0 as i64 || y < corner.1 as i64 || x >= right as i64 || y >= bottom as i64 {
+                        continue;
+                    }
+                    let coverage = atlas[(u as usize, v as usize)].a() as u16;
+                    let pixel = sheet.get_pixel_mut(x as u32, y as u32);
+                    for channel in &mut pixel.0[..3] {
+                        *channel = ((*channel as u16 * (255 - coverage) + 255 * coverage) / 255) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,39 +1,81 @@
+use std::collections::{HashMap, HashSet};
 use std::io::{BufReader, Cursor};
+use std::time::Instant;
 
-use wgpu::util::DeviceExt;
 
-use crate::{model, texture};
+use crate::{asset_source, gpu_debug::debug_label, gpu_memory, import_options::ImportOptions, material_array, material_set::{self, MapSource, MaterialSet, MaterialSetReport}, math::Aabb, mesh_optimize::{self, LoadOptions, OptimizeStats}, model, texture, texture_stream::{self, StreamTarget, TextureStreamer}, uv_fallback::{self, UvFallback}};
+use cgmath::Zero;
+use rayon::prelude::*;
 
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let txt = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read_to_string(path)?
-    };
-
-    Ok(txt)
+    let data = load_binary(file_name).await?;
+    Ok(String::from_utf8(data)?)
 }
 
+// Resolved through the installed asset source, see asset_source.rs for the search order
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let data = {
-        let path = std::path::Path::new(env!("OUT_DIR"))
-            .join("res")
-            .join(file_name);
-        std::fs::read(path)?
-    };
+    read_binary(file_name)
+}
 
+// load_binary without the future, for the rayon workers
+fn read_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+    let (data, origin) = asset_source::current().read(file_name)?;
+    log::info!("Loaded {} from {}", file_name, origin);
     Ok(data)
 }
 
-pub async fn load_texture(
+pub fn decode_image(file_name: &str) -> anyhow::Result<image::DynamicImage> {
+    let data = read_binary(file_name)?;
+    Ok(image::load_from_memory(&data)?)
+}
+
+// Big images are handed to the streamer, the returned texture is then its placeholder and no
+// image comes back with it
+fn upload_texture(
     file_name: &str,
+    img: image::DynamicImage,
     is_normal_map: bool,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    streamer: &mut TextureStreamer,
+    target: StreamTarget,
+) -> anyhow::Result<(texture::Texture, Option<image::DynamicImage>)> {
+    if TextureStreamer::should_stream(&img) {
+        let (_, placeholder) = streamer.stream_image(device, queue, &img, file_name, is_normal_map, target)?;
+        return Ok((placeholder, None));
+    }
+    let texture = texture::Texture::from_image(device, queue, &img, Some(file_name), is_normal_map)?;
+    Ok((texture, Some(img)))
+}
+
+fn elapsed_ms(since: Instant) -> f32 {
+    since.elapsed().as_secs_f32() * 1000.0
+}
+
+// Decoding textures and building vertices run on the rayon pool, RAYON_NUM_THREADS=1 makes it
+// all sequential. Everything touching the GPU stays on this thread, in file order.
+// Each library an OBJ names, once, in the order they're first named
+fn material_libraries(obj_text: &str) -> Vec<&str> {
+    let mut libraries = Vec::new();
+    for library in obj_text.lines().filter_map(|line| line.trim().strip_prefix("mtllib ")).filter_map(|rest| rest.split_whitespace().next()) {
+        if !libraries.contains(&library) {
+            libraries.push(library);
+        }
+    }
+    libraries
+}
+
+// What a material's decode depends on, its diffuse and normal texture files
+type TextureKey = (String, String);
+
+fn texture_key(material: &tobj::Material) -> TextureKey {
+    (material.diffuse_texture.clone(), material.normal_texture.clone())
+}
+
+// tobj merges a library again for every mtllib line naming it, so its materials can come more
+// than once. Each takes the decode made for its own textures, a repeat decodes them again.
+fn take_decoded<T>(decoded: &mut HashMap<TextureKey, T>, material: &tobj::Material, decode: impl FnOnce(&tobj::Material) -> T) -> T {
+    decoded.remove(&texture_key(material)).unwrap_or_else(|| decode(material))
 }
 
 pub async fn load_model(
@@ -41,150 +83,451 @@ pub async fn load_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    // material_array's, used when options.pack_textures is set
+    array_layout: &wgpu::BindGroupLayout,
+    streamer: &mut TextureStreamer,
+    options: &LoadOptions,
 ) -> anyhow::Result<model::Model> {
+    let read_start = Instant::now();
     let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
-
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
-        &tobj::LoadOptions {
-            triangulate: true,
-            single_index: true,
-            ..Default::default()
-        },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
-        },
-    )
-    .await?;
 
+    // Materials and textures are referenced relative to the OBJ file
+    let base_dir = std::path::Path::new(file_name)
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+    let relative_to_obj = |p: &str| base_dir.join(p).to_string_lossy().into_owned();
+
+    // The material libraries are read up front, so their textures can decode while tobj is
+    // still going through the geometry. tobj asks for them again by name during the parse.
+    let mut libraries = HashMap::new();
+    let mut mtl_materials = Vec::new();
+    for library in material_libraries(&obj_text) {
+        let mat_text = load_string(&relative_to_obj(library)).await?;
+        let parsed = tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)));
+        if let Ok((materials, _)) = &parsed {
+            mtl_materials.extend(materials.iter().cloned());
+        }
+        libraries.insert(library.to_string(), parsed);
+    }
+    let read_ms = elapsed_ms(read_start);
+
+    let parse_geometry = || {
+        let start = Instant::now();
+        let parsed = tobj::load_obj_buf(
+            &mut BufReader::new(Cursor::new(obj_text.as_bytes())),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |p| libraries.get(p.to_string_lossy().as_ref()).cloned().unwrap_or(Err(tobj::LoadError::OpenFileFailed)),
+        );
+        (parsed, elapsed_ms(start))
+    };
+    let decode_material = |m: &tobj::Material| {
+        let (diffuse_file, normal_file) = (relative_to_obj(&m.diffuse_texture), relative_to_obj(&m.normal_texture));
+        // With the low-res copies evicted textures fall back to, see texture_residency
+        let with_low_res = |img: image::DynamicImage| {
+            let low_res = texture_stream::placeholder_image(&img).to_rgba8();
+            (img, low_res)
+        };
+        let diffuse = decode_image(&diffuse_file).map(with_low_res);
+        let normal = decode_image(&normal_file).map(with_low_res);
+        (diffuse_file, diffuse, normal_file, normal)
+    };
+    let decode_textures = || {
+        let start = Instant::now();
+        let decoded = mtl_materials
+            .par_iter()
+            .map(|m| (texture_key(m), decode_material(m)))
+            .collect::<HashMap<_, _>>();
+        (decoded, elapsed_ms(start))
+    };
+    let ((parsed, parse_ms), (mut decoded, decode_ms)) = rayon::join(parse_geometry, decode_textures);
+    let (models, obj_materials) = parsed?;
+    let obj_materials = obj_materials?;
+
+    let upload_start = Instant::now();
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, false, device, queue).await?;
-        let normal_texture = load_texture(&m.normal_texture, true, device, queue).await?;
+    let mut images = Vec::new();
+    for m in obj_materials {
+        let (diffuse_file, diffuse, normal_file, normal) = take_decoded(&mut decoded, &m, decode_material);
+        let material = materials.len();
+        let diffuse_target = StreamTarget { material, slot: model::TextureSlot::Diffuse };
+        let normal_target = StreamTarget { material, slot: model::TextureSlot::Normal };
+        let ((diffuse, diffuse_low_res), (normal, normal_low_res)) = (diffuse?, normal?);
+        let (diffuse_texture, diffuse_image) = upload_texture(&diffuse_file, diffuse, false, device, queue, streamer, diffuse_target)?;
+        let (normal_texture, normal_image) = upload_texture(&normal_file, normal, true, device, queue, streamer, normal_target)?;
+        if options.pack_textures {
+            images.push(diffuse_image.zip(normal_image));
+        }
 
-        materials.push(model::Material::new(
+        let mut material = model::Material::new(
             device,
             &m.name,
             diffuse_texture,
             normal_texture,
             layout,
-        ))
+            model::MaterialParams::from_mtl(m.shininess, m.specular),
+        );
+        material.texture_files = vec![(model::TextureSlot::Diffuse, diffuse_file), (model::TextureSlot::Normal, normal_file)];
+        material.keep_low_res(model::TextureSlot::Diffuse, diffuse_low_res);
+        material.keep_low_res(model::TextureSlot::Normal, normal_low_res);
+        materials.push(material);
     }
+    let upload_ms = elapsed_ms(upload_start);
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-                let mut  vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                    tangent: [0.0; 3],
-                    bitangent: [0.0; 3],
-                })
-                .collect::<Vec<_>>();
-
-            let indices = &m.mesh.indices;
-            let mut triangles_included = vec![0; vertices.len()];
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0: cgmath::Vector3<_> = v0.position.into();
-                let pos1: cgmath::Vector3<_> = v1.position.into();
-                let pos2: cgmath::Vector3<_> = v2.position.into();
-
-                let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-                let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-                let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                // We flip the bitangent to enable right-handed normal
-                // maps with wgpu texture coordinate system
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-                vertices[c[1] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-                vertices[c[2] as usize].tangent =
-                    (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-                vertices[c[0] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-                vertices[c[1] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-                vertices[c[2] as usize].bitangent =
-                    (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-                // Used to average the tangents/bitangents
-                triangles_included[c[0] as usize] += 1;
-                triangles_included[c[1] as usize] += 1;
-                triangles_included[c[2] as usize] += 1;
+    let build_start = Instant::now();
+    let built = models
+        .into_par_iter()
+        .map(|m| (m.name, m.mesh.material_id, build_mesh(m.mesh, options, &options.import)))
+        .collect::<Vec<_>>();
+    let build_ms = elapsed_ms(build_start);
+    for (name, material_id, built) in &built {
+        if !built.missing_uvs {
+            continue;
+        }
+        match options.uv_fallback {
+            UvFallback::None => log::warn!("{} in {} has no texture coordinates", name, file_name),
+            fallback => {
+                log::info!("{} in {} has no texture coordinates, generated them with {} projection", name, file_name, fallback.label().to_lowercase());
+                if let Some(material) = material_id.and_then(|id| materials.get_mut(id)) {
+                    material.uv_fallback = Some(fallback);
+                }
             }
+        }
+    }
 
-            // Average the tangents/bitangents
-            for (i, n) in triangles_included.into_iter().enumerate() {
-                let denom = 1.0 / n as f32;
-                let v = &mut vertices[i];
-                v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-                v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    let buffers_start = Instant::now();
+    let mut used_names = HashSet::new();
+    let mut optimize_stats = options.any().then(OptimizeStats::default);
+    let meshes = built
+        .into_iter()
+        .map(|(name, material_id, built)| {
+            let name = unique_mesh_name(&name, &mut used_names);
+            if let (Some(total), Some(stats)) = (optimize_stats.as_mut(), &built.stats) {
+                total.add(stats);
             }
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+            let vertex_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: debug_label!("{:?} {} Vertex Buffer", file_name, name).as_deref(),
+                contents: bytemuck::cast_slice(&built.vertices),
+                // Storage for the vertex pulling path
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
             });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+            let index_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: debug_label!("{:?} {} Index Buffer", file_name, name).as_deref(),
+                contents: bytemuck::cast_slice(&built.indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
+            model::Mesh::new(name, vertex_buffer, index_buffer, built.indices.len() as u32, material_id.unwrap_or(0), built.bounds)
+        })
+        .collect::<Vec<_>>();
+    let buffers_ms = elapsed_ms(buffers_start);
 
-            model::Mesh {
-                _name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+    log::info!(
+        "Loaded {} on {} threads: read {:.1} ms, parse {:.1} ms alongside decode {:.1} ms, textures {:.1} ms, meshes {:.1} ms, buffers {:.1} ms",
+        file_name,
+        rayon::current_num_threads(),
+        read_ms,
+        parse_ms,
+        decode_ms,
+        upload_ms,
+        build_ms,
+        buffers_ms,
+    );
+    if let Some(stats) = &optimize_stats {
+        log::info!("Optimized {}: {}", file_name, stats.summary());
+    }
+    let packed = options
+        .pack_textures
+        .then(|| material_array::pack(device, queue, array_layout, file_name, &materials, &images))
+        .flatten();
+    Ok(model::Model { meshes, materials, optimize_stats, packed })
+}
+
+// The maps of a texture pack found by naming convention, see material_set.rs. `prefix` is
+// relative to res/ like a model, e.g. "textures/brick" or one of its maps. Fails when none of
+// the maps is there or a file doesn't decode.
+pub fn load_material_set(prefix: &str) -> anyhow::Result<MaterialSet> {
+    let (dir, stem) = material_set::split_prefix(prefix);
+    let picked = material_set::pick_maps(&stem, &asset_source::current().list(&dir));
+    if picked.iter().all(Option::is_none) {
+        anyhow::bail!("no {}_albedo, _normal, _roughness, _metallic or _ao files in {}", stem, if dir.is_empty() { "the asset folders" } else { &dir });
+    }
+    let maps = picked.map(|file| match file {
+        None => MapSource::Defaulted,
+        Some(file) => {
+            let path = std::path::Path::new(&dir).join(file).to_string_lossy().into_owned();
+            if path.to_lowercase().ends_with(".ktx2") {
+                log::warn!("{}: no KTX2 decoder, using the default instead", path);
+                MapSource::Unsupported(path)
+            } else {
+                MapSource::File(path)
             }
+        }
+    });
+    let mut images: [Option<image::DynamicImage>; 5] = Default::default();
+    for (image, source) in images.iter_mut().zip(&maps) {
+        if let MapSource::File(path) = source {
+            *image = Some(decode_image(path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?);
+        }
+    }
+    Ok(MaterialSet::new(MaterialSetReport { prefix: material_set::normalize_prefix(prefix), maps }, images))
+}
+
+// A shapes.rs builder's output as one model mesh, with tangents for normal mapping
+pub fn mesh_from_shape(device: &wgpu::Device, name: &str, shape_vertices: &[crate::vertex::Vertex], indices: &[u32]) -> model::Mesh {
+    let mut vertices: Vec<model::ModelVertex> = shape_vertices
+        .iter()
+        .map(|vertex| model::ModelVertex {
+            position: vertex.position,
+            tex_coords: vertex.tex_coords,
+            normal: vertex.normal,
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect();
+    compute_tangents(&mut vertices, indices);
+    let vertex_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+        label: debug_label!("{} Vertex Buffer", name).as_deref(),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+    });
+    let index_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+        label: debug_label!("{} Index Buffer", name).as_deref(),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    let bounds = Aabb::from_points(vertices.iter().map(|vertex| vertex.position.into())).expect("shapes have vertices");
+    model::Mesh::new(name.to_string(), vertex_buffer, index_buffer, indices.len() as u32, 0, bounds)
+}
+
+struct BuiltMesh {
+    vertices: Vec<model::ModelVertex>,
+    indices: Vec<u32>,
+    stats: Option<OptimizeStats>,
+    bounds: Aabb,
+    // The file had no `vt` for it, see uv_fallback.rs
+    missing_uvs: bool,
+}
+
+// The CPU side of a mesh, run on the rayon pool: vertices in engine conventions, optional
+// optimization, tangents
+fn build_mesh(mesh: tobj::Mesh, options: &LoadOptions, import: &ImportOptions) -> BuiltMesh {
+    let missing_uvs = mesh.texcoords.is_empty();
+    let mut vertices = (0..mesh.positions.len() / 3)
+        .map(|i| model::ModelVertex {
+            position: import.transform_position([
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ]),
+            // OBJ's V runs bottom to top, wgpu's top to bottom, unless the file was exported flipped
+            tex_coords: match missing_uvs {
+                true => [0.0; 2],
+                false => [mesh.texcoords[i * 2], if import.flip_v { mesh.texcoords[i * 2 + 1] } else { 1.0 - mesh.texcoords[i * 2 + 1] }],
+            },
+            normal: import.transform_normal([
+                mesh.normals[i * 3],
+                mesh.normals[i * 3 + 1],
+                mesh.normals[i * 3 + 2],
+            ]),
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
         })
         .collect::<Vec<_>>();
+    let mut indices = mesh.indices;
+    if import.flip_winding {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    // Before the optimizer, welding keeps the vertices split along projection seams apart
+    if missing_uvs {
+        uv_fallback::generate(options.uv_fallback, &mut vertices, &mut indices);
+    }
+    let mut stats = None;
+    if options.any() {
+        let optimized;
+        (vertices, indices, optimized) = mesh_optimize::optimize(vertices, indices, options);
+        stats = Some(optimized);
+    }
 
-    Ok(model::Model { meshes, materials })
+    compute_tangents(&mut vertices, &indices);
+
+    let bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into())).unwrap_or(Aabb::new(cgmath::Vector3::zero(), cgmath::Vector3::zero()));
+    BuiltMesh { vertices, indices, stats, bounds, missing_uvs }
 }
 
+// Per-vertex tangents and bitangents from the triangles' UVs, averaged where triangles share a
+// vertex. For meshes built in code as well as loaded ones.
+pub fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
 
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        vertices[c[0] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+
+        // Used to average the tangents/bitangents
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    // Average the tangents/bitangents
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let denom = 1.0 / n as f32;
+        let v = &mut vertices[i];
+        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
+    }
+}
+
+// OBJ group names aren't unique, a repeated one gets the first free _1, _2, ... suffix so
+// every mesh can still be found by name. Unnamed groups are called "mesh".
+fn unique_mesh_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base = if name.is_empty() { "mesh" } else { name };
+    let mut candidate = base.to_string();
+    let mut suffix = 1;
+    while used.contains(&candidate) {
+        candidate = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+    used.insert(candidate.clone());
+    candidate
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import_options::UpAxis;
+
+    const RED: &str = "newmtl red\nmap_Kd red.png\nmap_Bump red_normal.png\n";
+    const BLUE: &str = "newmtl blue\nmap_Kd blue.png\nmap_Bump blue_normal.png\n";
+
+    #[test]
+    fn duplicated_mtllib_keeps_textures_on_their_materials() {
+        let obj = "mtllib red.mtl\nmtllib blue.mtl\nmtllib red.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl blue\nf 1 2 3\n";
+        assert_eq!(material_libraries(obj), ["red.mtl", "blue.mtl"]);
+
+        let library = |name: &str| tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(if name == "red.mtl" { RED } else { BLUE })));
+        let mut decoded = HashMap::new();
+        for name in material_libraries(obj) {
+            for m in library(name).unwrap().0 {
+                decoded.insert(texture_key(&m), format!("decoded {}", m.diffuse_texture));
+            }
+        }
+
+        let (_, obj_materials) = tobj::load_obj_buf(&mut BufReader::new(Cursor::new(obj)), &tobj::LoadOptions::default(), |p| library(&p.to_string_lossy())).unwrap();
+        let obj_materials = obj_materials.unwrap();
+        assert_eq!(obj_materials.len(), 3, "tobj lists a repeated library again");
+
+        let taken: Vec<_> = obj_materials
+            .iter()
+            .map(|m| (m.diffuse_texture.clone(), take_decoded(&mut decoded, m, |m| format!("again {}", m.diffuse_texture))))
+            .collect();
+        assert_eq!(
+            taken,
+            [
+                ("red.png".to_string(), "decoded red.png".to_string()),
+                ("blue.png".to_string(), "decoded blue.png".to_string()),
+                ("red.png".to_string(), "again red.png".to_string()),
+            ]
+        );
+    }
+
+    // res/z-up.obj as load_model builds it
+    fn build_z_up(import: ImportOptions) -> BuiltMesh {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/z-up.obj");
+        let (mut models, _) = tobj::load_obj(path, &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() }).unwrap();
+        build_mesh(models.remove(0).mesh, &LoadOptions::default(), &import)
+    }
+
+    // Its geometric normal against the one its vertices carry, positive when wound outward
+    fn facing(built: &BuiltMesh, triangle: &[u32]) -> f32 {
+        let [p0, p1, p2] = [0, 1, 2].map(|corner| cgmath::Vector3::from(built.vertices[triangle[corner] as usize].position));
+        let normal = cgmath::Vector3::from(built.vertices[triangle[0] as usize].normal);
+        cgmath::InnerSpace::dot((p1 - p0).cross(p2 - p0), normal)
+    }
+
+    #[test]
+    fn z_up_centimeters_import_as_y_up_meters() {
+        let import = ImportOptions { scale: 0.01, up_axis: UpAxis::Z, flip_v: true, flip_winding: false };
+        let built = build_z_up(import);
+        let positions: Vec<_> = built.vertices.iter().map(|vertex| vertex.position).collect();
+        // (x, y, z) -> (x, z, -y), in meters
+        for expected in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]] {
+            assert!(positions.iter().any(|position| position.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-5)), "{:?} not in {:?}", expected, positions);
+        }
+        // The floor faces up and the wall faces the viewer
+        let normals: Vec<_> = built.indices.chunks_exact(3).map(|triangle| built.vertices[triangle[0] as usize].normal).collect();
+        assert_eq!(normals, [[0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+        assert!(built.indices.chunks_exact(3).all(|triangle| facing(&built, triangle) > 0.0));
+        // V as written, the wall's top corner sits a quarter down the texture
+        assert!(built.vertices.iter().any(|vertex| vertex.tex_coords == [0.0, 0.25]));
+        assert!(built.bounds.max.y <= 1.0 + 1e-5 && built.bounds.min.z >= -1.0 - 1e-5);
+    }
+
+    #[test]
+    fn defaults_keep_the_file_and_flip_winding_reverses_it() {
+        let plain = build_z_up(ImportOptions::default());
+        assert!(plain.vertices.iter().any(|vertex| vertex.position == [0.0, 100.0, 0.0]));
+        assert!(plain.vertices.iter().any(|vertex| vertex.tex_coords == [0.0, 0.75]));
+
+        let flipped = build_z_up(ImportOptions { flip_winding: true, ..ImportOptions::default() });
+        for (before, after) in plain.indices.chunks_exact(3).zip(flipped.indices.chunks_exact(3)) {
+            assert_eq!([before[0], before[2], before[1]], after);
+            assert!(facing(&flipped, after) < 0.0);
+        }
+    }
+}
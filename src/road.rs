@@ -0,0 +1,173 @@
+/*
+Purpose: The road tool, click a path onto the ground and get a road mesh along it
+Responsibilities:
+    - Keep the control points and the ribbon's settings, the mesh is shapes::create_ribbon's
+    - Rebuild the mesh whenever a point or a setting changed, so a dragged point reshapes the
+      road while it moves
+    - Draw a handle on every control point and the lines between them, and report which handle
+      is dragged to where. State finds the ground under the pointer, the tool doesn't know what
+      the ground is.
+    - ex: a garden hose laid out on the lawn, tug any point and the rest follows
+*/
+
+use crate::{camera::{Camera, Projection}, math::Vec3, render_context::RenderContext, shape_renderer::DynamicShape, shapes, transform_gizmo::ScreenProjection};
+
+// Meters
+pub const DEFAULT_WIDTH: f32 = 4.0;
+// The road floats this far (meters) over what it was drawn on, so the two don't z-fight
+const LIFT: f32 = 0.02;
+pub const DEFAULT_SEGMENTS_PER_SPAN: u32 = 12;
+// V per meter, one texture repeat every four meters
+pub const DEFAULT_UV_TILING: f32 = 0.25;
+const ROAD_TINT: [f32; 3] = [0.75, 0.75, 0.78];
+// Screen points
+const HANDLE_RADIUS: f32 = 6.0;
+const HANDLE_COLOR: egui::Color32 = egui::Color32::from_rgb(240, 200, 60);
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::WHITE;
+const LINE_COLOR: egui::Color32 = egui::Color32::from_rgba_premultiplied(120, 100, 30, 160);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RibbonSettings {
+    // Meters
+    pub width: f32,
+    pub segments_per_span: u32,
+    pub uv_tiling: f32,
+    // Joins the last point back to the first
+    pub closed: bool,
+}
+
+impl Default for RibbonSettings {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            segments_per_span: DEFAULT_SEGMENTS_PER_SPAN,
+            uv_tiling: DEFAULT_UV_TILING,
+            closed: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RoadTool {
+    pub enabled: bool,
+    pub points: Vec<Vec3>,
+    pub settings: RibbonSettings,
+    // Clicks in the main window add points while on
+    drawing: bool,
+    // Handle being dragged
+    drag: Option<usize>,
+    // What the mesh was built from, with the units per meter
+    built: Option<(Vec<Vec3>, RibbonSettings, f32)>,
+    shape: Option<DynamicShape>,
+    triangles: usize,
+}
+
+impl RoadTool {
+    pub fn is_drawing(&self) -> bool {
+        self.drawing
+    }
+
+    pub fn begin_drawing(&mut self) {
+        self.drawing = true;
+    }
+
+    // The points stay
+    pub fn stop_drawing(&mut self) {
+        self.drawing = false;
+    }
+
+    pub fn add_point(&mut self, point: Vec3) {
+        self.points.push(point);
+    }
+
+    pub fn move_point(&mut self, index: usize, point: Vec3) {
+        if let Some(current) = self.points.get_mut(index) {
+            *current = point;
+        }
+    }
+
+    pub fn triangles(&self) -> usize {
+        self.triangles
+    }
+
+    pub fn shape(&self) -> Option<&DynamicShape> {
+        self.shape.as_ref().filter(|_| self.enabled)
+    }
+
+    // What create_ribbon gets, a closed road repeats its first point at the end
+    fn control_points(&self, units_per_meter: f32) -> Vec<Vec3> {
+        let lift = Vec3::unit_y() * LIFT * units_per_meter;
+        let closing = self.points.first().filter(|_| self.settings.closed && self.points.len() > 2);
+        self.points.iter().chain(closing).map(|point| point + lift).collect()
+    }
+
+    // Rebuilds the mesh when the points or settings changed since it was last built
+    pub fn update(&mut self, context: &RenderContext, units_per_meter: f32) {
+        if !self.enabled {
+            self.drag = None;
+            return;
+        }
+        let key = (self.points.clone(), self.settings, units_per_meter);
+        if self.built.as_ref() == Some(&key) {
+            return;
+        }
+        let settings = self.settings;
+        let (vertices, indices) = shapes::create_ribbon(
+            &self.control_points(units_per_meter),
+            settings.width * units_per_meter,
+            settings.segments_per_span,
+            settings.uv_tiling / units_per_meter,
+        );
+        let shape = self
+            .shape
+            .get_or_insert_with(|| DynamicShape::new(&context.device, &context.shape_pipeline, "Road", [0.0, 0.0, 0.0], ROAD_TINT));
+        shape.set_mesh(&context.device, &context.queue, &vertices, &indices);
+        self.triangles = indices.len() / 3;
+        self.built = Some(key);
+    }
+
+    // Handles over the control points and the lines between them. Returns the dragged handle and
+    // where the pointer is, in egui points.
+    pub fn show_handles(&mut self, ctx: &egui::Context, camera: &Camera, projection: &Projection) -> Option<(usize, egui::Pos2)> {
+        if !self.enabled {
+            return None;
+        }
+        let screen = ScreenProjection::new(ctx, camera, projection);
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("road_handles")));
+        let centers: Vec<_> = self.points.iter().map(|point| screen.project(*point)).collect();
+        let closing = centers.first().filter(|_| self.settings.closed && centers.len() > 2);
+        for pair in centers.iter().chain(closing).collect::<Vec<_>>().windows(2) {
+            if let (Some(a), Some(b)) = (pair[0], pair[1]) {
+                painter.line_segment([*a, *b], egui::Stroke::new(1.0, LINE_COLOR));
+            }
+        }
+        if self.drag.is_some_and(|index| index >= self.points.len()) {
+            self.drag = None;
+        }
+
+        let mut dragged = None;
+        for (index, center) in centers.into_iter().enumerate() {
+            let Some(center) = center else {
+                continue;
+            };
+            let rect = egui::Rect::from_center_size(center, egui::Vec2::splat(HANDLE_RADIUS * 3.0));
+            let response = egui::Area::new(egui::Id::new(("road_handle", index)))
+                .fixed_pos(rect.min)
+                .order(egui::Order::Background)
+                .show(ctx, |ui| ui.allocate_exact_size(rect.size(), egui::Sense::drag()).1)
+                .inner;
+            if response.drag_started() {
+                self.drag = Some(index);
+            }
+            let dragging = self.drag == Some(index);
+            if dragging && (response.drag_stopped() || !response.dragged()) {
+                self.drag = None;
+            } else if dragging && let Some(pointer) = response.interact_pointer_pos() {
+                dragged = Some((index, pointer));
+            }
+            let color = if dragging || response.hovered() { ACTIVE_COLOR } else { HANDLE_COLOR };
+            painter.circle(center, HANDLE_RADIUS, color, egui::Stroke::new(1.0, egui::Color32::BLACK));
+        }
+        dragged
+    }
+}
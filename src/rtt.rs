@@ -0,0 +1,336 @@
+/*
+Purpose: Render-to-texture cameras, the scene seen from another camera shown on a material
+Responsibilities:
+    - Own each camera's render target, depth (and MSAA) target and the copy materials sample
+    - Record a camera's pass and copy the result to where materials read it, so a camera that
+      sees a surface showing its own output reads the previous level instead of itself
+    - Remember which materials show a camera, a new resolution rebinds them
+    - Build the mirror demo: a quad showing a camera mirrored about its plane
+    - ex: the security monitor at the front desk showing the hallway camera
+*/
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation, Vector3};
+
+use crate::{
+    camera::{Camera, CameraUniform, Projection},
+    gpu_debug::debug_label,
+    gpu_memory::{self, Tracked},
+    math::Aabb,
+    model::{self, ModelVertex, ShadingModel},
+    model_entry::{ALL_LAYERS, ModelHandle},
+    render_context::RenderContext,
+    texture,
+};
+
+// How many times per frame the cameras render, each level sees the one before on its monitors
+pub const MAX_RECURSION_DEPTH: u32 = 4;
+// Entries on this layer are left out of the mirror camera, it would only see the mirror's back
+pub const MIRROR_LAYER: u32 = 1 << 1;
+// Resolutions the menu allows
+pub const MIN_RESOLUTION: u32 = 16;
+pub const MAX_RESOLUTION: u32 = 4096;
+
+const FOVY: Rad<f32> = Rad(std::f32::consts::FRAC_PI_4);
+
+// Stays valid until the camera is removed, ids are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttId(pub u32);
+
+// Built with the same formats and sample count as the scene pipelines it renders with
+pub struct RttDesc<'a> {
+    pub camera_bind_group_layout: &'a wgpu::BindGroupLayout,
+    pub color_format: wgpu::TextureFormat,
+    pub sample_count: u32,
+}
+
+// Recreated whenever the resolution changes
+struct RttTargets {
+    // Rendered (or resolved) into, then copied to `display`
+    render: Tracked<wgpu::Texture>,
+    render_view: wgpu::TextureView,
+    msaa_view: Option<Tracked<wgpu::TextureView>>,
+    depth_view: Tracked<wgpu::TextureView>,
+    // What the materials sample
+    display: Tracked<wgpu::Texture>,
+    display_view: wgpu::TextureView,
+}
+
+impl RttTargets {
+    fn new(device: &wgpu::Device, desc: &RttDesc, id: RttId, (width, height): (u32, u32)) -> Self {
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        let create = |label: Option<&str>, format, sample_count, usage| {
+            gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let render = create(
+            debug_label!("RTT {} Target", id.0).as_deref(),
+            desc.color_format,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
+        let msaa_view = (desc.sample_count > 1).then(|| {
+            create(debug_label!("RTT {} MSAA Target", id.0).as_deref(), desc.color_format, desc.sample_count, wgpu::TextureUsages::RENDER_ATTACHMENT)
+                .map(|texture| texture.create_view(&Default::default()))
+        });
+        let depth_view = create(
+            debug_label!("RTT {} Depth", id.0).as_deref(),
+            texture::Texture::DEPTH_FORMAT,
+            desc.sample_count,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+        .map(|texture| texture.create_view(&Default::default()));
+        // Cleared through a render pass at the start of every frame, see RttCamera::clear_display
+        let display = create(
+            debug_label!("RTT {} Display", id.0).as_deref(),
+            desc.color_format,
+            1,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        Self {
+            render_view: render.create_view(&Default::default()),
+            render,
+            msaa_view,
+            depth_view,
+            display_view: display.create_view(&Default::default()),
+            display,
+        }
+    }
+}
+
+pub struct RttCamera {
+    pub id: RttId,
+    pub camera: Camera,
+    // Its aspect follows the resolution, the field of view is free to change
+    pub projection: Projection,
+    // Model entries sharing no bit with this are left out, see ModelEntry::layers
+    pub layer_mask: u32,
+    resolution: (u32, u32),
+    // Near and far plane from the scene units
+    depth_range: (f32, f32),
+    targets: RttTargets,
+    sampler: wgpu::Sampler,
+    camera_buffer: Tracked<wgpu::Buffer>,
+    camera_bind_group: wgpu::BindGroup,
+    // Materials (model, material index) showing this camera
+    materials: Vec<(ModelHandle, usize)>,
+}
+
+impl RttCamera {
+    pub fn new(device: &wgpu::Device, desc: &RttDesc, id: RttId, camera: Camera, resolution: (u32, u32), depth_range: (f32, f32)) -> Self {
+        let resolution = clamp_resolution(resolution);
+        let targets = RttTargets::new(device, desc, id, resolution);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: debug_label!("RTT {} Sampler", id.0).as_deref(),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let camera_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("RTT {} Camera Buffer", id.0).as_deref(),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: desc.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: camera_buffer.as_entire_binding() }],
+            label: debug_label!("RTT {} Camera Bind Group", id.0).as_deref(),
+        });
+        let (znear, zfar) = depth_range;
+        Self {
+            id,
+            camera,
+            projection: Projection::new(resolution.0, resolution.1, FOVY, znear, zfar),
+            layer_mask: ALL_LAYERS,
+            resolution,
+            depth_range,
+            targets,
+            sampler,
+            camera_buffer,
+            camera_bind_group,
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    // New targets at the new size. The caller rebinds the materials, see materials().
+    pub fn set_resolution(&mut self, device: &wgpu::Device, desc: &RttDesc, resolution: (u32, u32)) {
+        self.resolution = clamp_resolution(resolution);
+        self.targets = RttTargets::new(device, desc, self.id, self.resolution);
+        self.projection.resize(self.resolution.0, self.resolution.1);
+    }
+
+    // Bind to a material's diffuse, see Material::set_diffuse_override
+    pub fn display(&self) -> (wgpu::TextureView, wgpu::Sampler) {
+        (self.targets.display_view.clone(), self.sampler.clone())
+    }
+
+    pub fn materials(&self) -> &[(ModelHandle, usize)] {
+        &self.materials
+    }
+
+    pub fn add_material(&mut self, handle: ModelHandle, material: usize) {
+        if !self.materials.contains(&(handle, material)) {
+            self.materials.push((handle, material));
+        }
+    }
+
+    // A material now showing something else
+    pub fn remove_material(&mut self, handle: ModelHandle, material: usize) {
+        self.materials.retain(|bound| *bound != (handle, material));
+    }
+
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        &self.camera_bind_group
+    }
+
+    pub fn set_depth_range(&mut self, depth_range: (f32, f32)) {
+        self.depth_range = depth_range;
+        self.projection.set_depth_range(depth_range);
+    }
+
+    // Point the camera uniform at where the camera is now, before its passes are recorded
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&self.camera, &self.projection);
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Monitors seen before the first level is rendered show this, which ends the recursion
+    pub fn clear_display(&self, encoder: &mut wgpu::CommandEncoder, clear: wgpu::Color) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: debug_label!("RTT {} Clear", self.id.0).as_deref(),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.targets.display_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(clear), store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+
+    // Draw the scene into it with camera_bind_group, then call finish_pass
+    pub fn begin_pass<'e>(&self, encoder: &'e mut wgpu::CommandEncoder, clear: wgpu::Color) -> wgpu::RenderPass<'e> {
+        let (view, resolve_target) = match &self.targets.msaa_view {
+            Some(msaa_view) => (&**msaa_view, Some(&self.targets.render_view)),
+            None => (&self.targets.render_view, None),
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: debug_label!("RTT {} Pass", self.id.0).as_deref(),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(clear), store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.targets.depth_view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(1.0), store: wgpu::StoreOp::Discard }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    // Materials sample the new image from the next pass on
+    pub fn finish_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_texture(self.targets.render.as_image_copy(), self.targets.display.as_image_copy(), self.targets.render.size());
+    }
+}
+
+fn clamp_resolution((width, height): (u32, u32)) -> (u32, u32) {
+    (width.clamp(MIN_RESOLUTION, MAX_RESOLUTION), height.clamp(MIN_RESOLUTION, MAX_RESOLUTION))
+}
+
+// Mirror demo quad, in model space it faces +Z
+const MIRROR_WIDTH: f32 = 4.0;
+const MIRROR_HEIGHT: f32 = 3.0;
+pub const MIRROR_RESOLUTION: (u32, u32) = (512, 384);
+
+// A quad showing an RTT camera that is kept mirrored about the quad's plane
+pub struct MirrorDemo {
+    pub handle: ModelHandle,
+    pub rtt: RttId,
+}
+
+impl MirrorDemo {
+    // The model shows a plain white material until the caller binds the camera to it
+    pub fn model(context: &RenderContext) -> anyhow::Result<model::Model> {
+        let (half_width, half_height) = (MIRROR_WIDTH / 2.0, MIRROR_HEIGHT / 2.0);
+        // u runs right to left, a reflection is the mirrored camera's image flipped sideways
+        let corners = [
+            ([-half_width, -half_height], [1.0, 1.0]),
+            ([half_width, -half_height], [0.0, 1.0]),
+            ([half_width, half_height], [0.0, 0.0]),
+            ([-half_width, half_height], [1.0, 0.0]),
+        ];
+        let vertices = corners.map(|([x, y], tex_coords)| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0],
+            bitangent: [0.0, 1.0, 0.0],
+        });
+        let indices: [u32; 6] = [0, 1, 2, 0, 2, 3];
+        let vertex_buffer = gpu_memory::create_buffer_init(&context.device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Mirror Demo Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+        });
+        let index_buffer = gpu_memory::create_buffer_init(&context.device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Mirror Demo Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let bounds = Aabb::new(Vector3::new(-half_width, -half_height, 0.0), Vector3::new(half_width, half_height, 0.0));
+
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])));
+        let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+        let material = model::Material::new(
+            &context.device,
+            "mirror_demo",
+            texture::Texture::from_image(&context.device, &context.queue, &white, Some("mirror_demo_diffuse"), false)?,
+            texture::Texture::from_image(&context.device, &context.queue, &flat_normal, Some("mirror_demo_normal"), true)?,
+            &context.texture_bind_group_layout,
+            // What the camera sees is already lit
+            model::MaterialParams { shading_model: ShadingModel::Unlit, ..Default::default() },
+        );
+        Ok(model::Model {
+            meshes: vec![model::Mesh::new("mirror".to_string(), vertex_buffer, index_buffer, indices.len() as u32, 0, bounds)],
+            materials: vec![material],
+            optimize_stats: None,
+            packed: None,
+        })
+    }
+
+    // Put the camera where `viewer` appears to be behind the mirror, looking at the mirror's
+    // center with a field of view that just covers it
+    pub fn place_camera(viewer: &Camera, position: Vector3<f32>, rotation: Quaternion<f32>, camera: &mut RttCamera) {
+        let center = Point3::from_vec(position);
+        let normal = rotation.rotate_vector(Vector3::unit_z());
+        let offset = viewer.position - center;
+        let mirrored = viewer.position - normal * (2.0 * offset.dot(normal));
+        camera.camera.position = mirrored;
+        camera.camera.look_at(center);
+        let distance = (center - mirrored).magnitude().max(0.01);
+        let fovy = Rad(2.0 * (MIRROR_HEIGHT * 0.5 / distance).atan());
+        let (znear, zfar) = camera.depth_range;
+        let (width, height) = camera.resolution();
+        // Just short of the glass, so what is behind the mirror mostly stays out of it
+        camera.projection = Projection::new(width, height, fovy, (distance * 0.9).clamp(znear, zfar * 0.5), zfar);
+    }
+}
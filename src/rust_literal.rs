@@ -0,0 +1,175 @@
+/*
+Purpose: Turn engine values into Rust source that rebuilds them
+Responsibilities:
+    - Define ToRustLiteral, the Rust expression of a value with every f32 at full precision
+    - Implement it for the cgmath types the engine uses and for instances, lights and cameras
+    - ex: the stenographer writing down exactly what was said, not the gist of it
+*/
+
+use cgmath::{Point3, Quaternion, Rad, Vector3};
+
+use crate::{camera::{Camera, Projection}, instance::Instance, light::LightUniform, point_lights::PointLight};
+
+pub trait ToRustLiteral {
+    // An expression that compiles back to the same value, cgmath paths spelled out
+    fn to_rust_literal(&self) -> String;
+}
+
+// Debug prints the shortest digits that parse back to the same f32, always with a `.` or an
+// exponent so the literal isn't read as an integer
+impl ToRustLiteral for f32 {
+    fn to_rust_literal(&self) -> String {
+        match *self {
+            value if value.is_nan() => "f32::NAN".to_string(),
+            f32::INFINITY => "f32::INFINITY".to_string(),
+            f32::NEG_INFINITY => "f32::NEG_INFINITY".to_string(),
+            value => format!("{:?}", value),
+        }
+    }
+}
+
+impl<const N: usize> ToRustLiteral for [f32; N] {
+    fn to_rust_literal(&self) -> String {
+        format!("[{}]", join(self))
+    }
+}
+
+impl ToRustLiteral for Vector3<f32> {
+    fn to_rust_literal(&self) -> String {
+        format!("cgmath::Vector3::new({})", join(&[self.x, self.y, self.z]))
+    }
+}
+
+impl ToRustLiteral for Point3<f32> {
+    fn to_rust_literal(&self) -> String {
+        format!("cgmath::Point3::new({})", join(&[self.x, self.y, self.z]))
+    }
+}
+
+// Scalar first, like Quaternion::new
+impl ToRustLiteral for Quaternion<f32> {
+    fn to_rust_literal(&self) -> String {
+        format!("cgmath::Quaternion::new({})", join(&[self.s, self.v.x, self.v.y, self.v.z]))
+    }
+}
+
+impl ToRustLiteral for Rad<f32> {
+    fn to_rust_literal(&self) -> String {
+        format!("cgmath::Rad({})", self.0.to_rust_literal())
+    }
+}
+
+impl ToRustLiteral for Instance {
+    fn to_rust_literal(&self) -> String {
+        format!(
+            "Instance {{\n    initial_position: {},\n    position: {},\n    rotation: {},\n    scale: {},\n    spin_axis: {},\n    spin_speed: {},\n    uv_transform: {},\n}}",
+            self.initial_position.to_rust_literal(),
+            self.position.to_rust_literal(),
+            self.rotation.to_rust_literal(),
+            self.scale.to_rust_literal(),
+            self.spin_axis.to_rust_literal(),
+            self.spin_speed.to_rust_literal(),
+            self.uv_transform.to_rust_literal(),
+        )
+    }
+}
+
+impl ToRustLiteral for LightUniform {
+    fn to_rust_literal(&self) -> String {
+        format!(
+            "LightUniform {{\n    position: {},\n    marker_scale: {},\n    color: {},\n    intensity: {},\n    ambient: {},\n    _padding: [0.0; 3],\n}}",
+            self.position.to_rust_literal(),
+            self.marker_scale.to_rust_literal(),
+            self.color.to_rust_literal(),
+            self.intensity.to_rust_literal(),
+            self.ambient.to_rust_literal(),
+        )
+    }
+}
+
+// Ids only come from PointLights::add, the literal takes an `id` in scope
+impl ToRustLiteral for PointLight {
+    fn to_rust_literal(&self) -> String {
+        format!(
+            "PointLight {{\n    id,\n    position: {},\n    color: {},\n    intensity: {},\n    range: {},\n}}",
+            self.position.to_rust_literal(),
+            self.color.to_rust_literal(),
+            self.intensity.to_rust_literal(),
+            self.range.to_rust_literal(),
+        )
+    }
+}
+
+// Yaw, pitch and roll in radians, what Camera keeps, so nothing is lost converting to degrees.
+// A level camera leaves the roll out.
+impl ToRustLiteral for Camera {
+    fn to_rust_literal(&self) -> String {
+        let camera = format!("Camera::new({}, {}, {})", self.position.to_rust_literal(), self.yaw().to_rust_literal(), self.pitch().to_rust_literal());
+        if self.roll().0 == 0.0 {
+            camera
+        } else {
+            format!("{}.with_roll({})", camera, self.roll().to_rust_literal())
+        }
+    }
+}
+
+// The aspect comes from the size the projection is made for, `width` and `height` in scope
+impl ToRustLiteral for Projection {
+    fn to_rust_literal(&self) -> String {
+        let (znear, zfar) = self.depth_range();
+        format!("Projection::new(width, height, {}, {}, {})", self.fovy().to_rust_literal(), znear.to_rust_literal(), zfar.to_rust_literal())
+    }
+}
+
+// Both as statements, ready to paste into a function body
+pub fn camera_snippet(camera: &Camera, projection: &Projection) -> String {
+    format!("let camera = {};\nlet projection = {};", camera.to_rust_literal(), projection.to_rust_literal())
+}
+
+fn join(values: &[f32]) -> String {
+    values.iter().map(ToRustLiteral::to_rust_literal).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What rustc would make of the literal
+    fn parse(literal: &str) -> f32 {
+        match literal {
+            "f32::NAN" => f32::NAN,
+            "f32::INFINITY" => f32::INFINITY,
+            "f32::NEG_INFINITY" => f32::NEG_INFINITY,
+            // An integer literal where an f32 goes doesn't compile
+            _ if !literal.contains(['.', 'e']) => panic!("{} isn't a float literal", literal),
+            _ => literal.parse().unwrap_or_else(|e| panic!("{}: {}", literal, e)),
+        }
+    }
+
+    const VALUES: [f32; 14] = [0.0, -0.0, 1.0, -2.5, 0.1, 1.0 / 3.0, 16_777_217.0, 1e-7, 3.4e38, f32::MAX, f32::MIN_POSITIVE, f32::EPSILON, 1e-45, -f32::MIN_POSITIVE / 3.0];
+
+    #[test]
+    fn floats_parse_back_to_the_same_bits() {
+        for value in VALUES {
+            let literal = value.to_rust_literal();
+            assert_eq!(parse(&literal).to_bits(), value.to_bits(), "{}", literal);
+        }
+        // Subnormals among them
+        assert!(VALUES.iter().filter(|value| value.is_subnormal()).count() >= 2);
+        assert!(parse(&f32::NAN.to_rust_literal()).is_nan());
+        assert_eq!(parse(&f32::INFINITY.to_rust_literal()), f32::INFINITY);
+        assert_eq!(parse(&f32::NEG_INFINITY.to_rust_literal()), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn arrays_parse_back_element_by_element() {
+        let values = [0.1, f32::NAN, -0.0, f32::INFINITY, 1e-45, f32::NEG_INFINITY];
+        let literal = values.to_rust_literal();
+        let parsed: Vec<f32> = literal.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).unwrap().split(", ").map(parse).collect();
+        assert_eq!(parsed.len(), values.len(), "{}", literal);
+        for (parsed, value) in parsed.iter().zip(values) {
+            assert!(parsed.to_bits() == value.to_bits() || (parsed.is_nan() && value.is_nan()), "{} in {}", value, literal);
+        }
+        assert_eq!([0.0f32; 0].to_rust_literal(), "[]");
+    }
+}
@@ -0,0 +1,140 @@
+/*
+Purpose: Deterministic random scene generation
+Responsibilities:
+    - Turn a seed into a full scene description (shapes, tints, scales, rotation speeds, lights)
+    - Depend on nothing but the seed and options (no system time, no HashMap iteration)
+    - ex: the same seed always plants the same garden
+*/
+
+use cgmath::InnerSpace;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+// Which shapes.rs builder a generated shape uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShapeKind {
+    Plane,
+    Pyramid,
+    Cube,
+    Sphere,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 4] = [ShapeKind::Plane, ShapeKind::Pyramid, ShapeKind::Cube, ShapeKind::Sphere];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShapeKind::Plane => "Plane",
+            ShapeKind::Pyramid => "Pyramid",
+            ShapeKind::Cube => "Cube",
+            ShapeKind::Sphere => "Sphere",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedShape {
+    pub kind: ShapeKind,
+    pub position: [f32; 3],
+    pub scale: f32,
+    pub tint: [f32; 3],
+    // Unit axis the shape spins around and how fast (degrees per second)
+    pub rotation_axis: [f32; 3],
+    pub rotation_speed: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedLight {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneDescription {
+    pub seed: u64,
+    pub shapes: Vec<GeneratedShape>,
+    pub lights: Vec<GeneratedLight>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneGenOptions {
+    pub shape_count: u32,
+    // Shapes are placed within [-extent, extent] on X and Z
+    pub extent: f32,
+    pub light_count: u32,
+}
+
+impl Default for SceneGenOptions {
+    fn default() -> Self {
+        Self {
+            shape_count: 100,
+            extent: 20.0,
+            light_count: 3,
+        }
+    }
+}
+
+// Everything is drawn from one StdRng in a fixed order, so the output only depends on
+// the seed and options. Don't add HashMap/HashSet iteration or time-based values here.
+pub fn generate(seed: u64, options: &SceneGenOptions) -> SceneDescription {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let extent = options.extent.max(0.0);
+
+    let shapes = (0..options.shape_count)
+        .map(|_| {
+            let kind = ShapeKind::ALL[rng.gen_range(0..ShapeKind::ALL.len())];
+            // Planes are big, keep them small so they don't swallow the scene
+            let scale = match kind {
+                ShapeKind::Plane => rng.gen_range(0.05..0.2),
+                _ => rng.gen_range(0.5..2.0),
+            };
+            let position = [
+                rng.gen_range(-extent..=extent),
+                rng.gen_range(0.0..=extent * 0.25),
+                rng.gen_range(-extent..=extent),
+            ];
+            let tint = [rng.gen_range(0.2..1.0), rng.gen_range(0.2..1.0), rng.gen_range(0.2..1.0)];
+            let axis = cgmath::Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0f32));
+            let rotation_axis = if axis.magnitude2() > 1e-6 {
+                axis.normalize().into()
+            } else {
+                [0.0, 1.0, 0.0]
+            };
+            let rotation_speed = rng.gen_range(-90.0..90.0);
+
+            GeneratedShape {
+                kind,
+                position,
+                scale,
+                tint,
+                rotation_axis,
+                rotation_speed,
+            }
+        })
+        .collect();
+
+    let lights = (0..options.light_count)
+        .map(|_| GeneratedLight {
+            position: [
+                rng.gen_range(-extent..=extent),
+                rng.gen_range(2.0..=2.0 + extent * 0.5),
+                rng.gen_range(-extent..=extent),
+            ],
+            color: [rng.gen_range(0.4..1.0), rng.gen_range(0.4..1.0), rng.gen_range(0.4..1.0)],
+        })
+        .collect();
+
+    SceneDescription { seed, shapes, lights }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_scene() {
+        let options = SceneGenOptions::default();
+        assert_eq!(generate(7, &options), generate(7, &options));
+        assert_ne!(generate(7, &options), generate(8, &options));
+        assert_eq!(generate(7, &options).shapes.len(), 100);
+    }
+}
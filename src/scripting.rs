@@ -0,0 +1,534 @@
+/*
+Purpose: Run small rhai scripts against the scene without recompiling
+Responsibilities:
+    - Load every *.rhai of the scripts folder, and while hot reload is on pick up the ones that
+      are added, edited or removed
+    - Call a script's on_init(engine) once after each load and on_update(engine, dt) every
+      simulation step, with a `this` map that keeps the script's own values between calls
+    - Hand engine events to a script's on_event(engine, event) before its on_update, the event a
+      map with a `kind` and that kind's fields
+    - Give scripts the same kind of operations EngineApi gives windows: spawning and moving
+      instances, the main and point lights, held keys and the time
+    - Stop a script at its first error, reported with the file and line, until it is reloaded or
+      re-enabled, and time how long each one takes
+    - ex: the stagehands, each following their own cue sheet while the play runs
+*/
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use cgmath::{Deg, Euler, Quaternion, Vector3};
+use rhai::{AST, Array, CallFnOptions, Dynamic, EvalAltResult, FLOAT, INT, Map, Scope};
+
+use crate::{engine_events::{EVENT_CAPACITY, EngineEvent}, instance::{Instance, clamp_scale}, model_entry::{InstanceId, ModelEntry, ModelHandle}, point_lights::PointLights};
+
+// The assets/scripts folder next to this crate, only there when running from the source tree
+pub const SCRIPT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/scripts");
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Editors often truncate the file then write it, it is only read once it stopped changing this long
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+// Per call, a script stuck in a loop is stopped with an error instead of freezing the engine
+const MAX_OPERATIONS: u64 = 500_000;
+// Weight of the newest call in the execution time shown in the Scripts window
+const TIME_SMOOTHING: f32 = 0.1;
+
+// What a script can touch while it runs. State moves these in before the scripts run and takes
+// them back after.
+#[derive(Default)]
+pub struct ScriptWorld {
+    pub models: Vec<ModelEntry>,
+    pub grid_model: ModelHandle,
+    pub point_lights: PointLights,
+    pub units_per_meter: f32,
+    pub light_color: [f32; 3],
+    pub light_intensity: f32,
+    // Seconds of simulation so far
+    pub time: f32,
+    // winit KeyCode names, e.g. "KeyW" or "Space"
+    pub held_keys: Vec<String>,
+    // What the scripts spawned and despawned this run, for the engine events
+    pub spawned: Vec<InstanceId>,
+    pub despawned: Vec<InstanceId>,
+}
+
+impl ScriptWorld {
+    fn model(&self, handle: INT) -> Option<&ModelEntry> {
+        self.models.iter().find(|entry| i64::from(entry.handle.0) == handle)
+    }
+
+    fn model_mut(&mut self, handle: INT) -> Option<&mut ModelEntry> {
+        self.models.iter_mut().find(|entry| i64::from(entry.handle.0) == handle)
+    }
+
+    fn instance_mut(&mut self, handle: INT, index: INT) -> Option<&mut Instance> {
+        self.model_mut(handle)?.instance_mut(usize::try_from(index).ok()?)
+    }
+
+    fn despawn(&mut self, handle: INT, index: usize) -> bool {
+        let Some(entry) = self.model_mut(handle) else {
+            return false;
+        };
+        let model = entry.handle;
+        if entry.remove_instance(index).is_none() {
+            return false;
+        }
+        self.despawned.push(InstanceId { model, index });
+        true
+    }
+}
+
+// The `engine` argument of on_init and on_update, called `Engine` in script errors
+#[derive(Clone)]
+struct ScriptApi(Rc<RefCell<ScriptWorld>>);
+
+impl ScriptApi {
+    fn register(engine: &mut rhai::Engine) {
+        engine.register_type_with_name::<ScriptApi>("Engine");
+
+        // Models are passed around as their handle, -1 when there is no such model
+        engine.register_fn("grid_model", |api: &mut ScriptApi| i64::from(api.0.borrow().grid_model.0));
+        engine.register_fn("model", |api: &mut ScriptApi, name: &str| {
+            let world = api.0.borrow();
+            let matches = |entry: &&ModelEntry| entry.name == name || Path::new(&entry.name).file_stem().is_some_and(|stem| stem == name);
+            world.models.iter().find(matches).map_or(-1, |entry| i64::from(entry.handle.0))
+        });
+        engine.register_fn("instance_count", |api: &mut ScriptApi, model: INT| {
+            api.0.borrow().model(model).map_or(0, |entry| INT::from(entry.instance_count()))
+        });
+
+        // Instances are an index into their model that stays theirs until they are despawned
+        engine.register_fn("has_instance", |api: &mut ScriptApi, model: INT, index: INT| {
+            api.0.borrow().model(model).is_some_and(|entry| usize::try_from(index).is_ok_and(|index| entry.instance(index).is_some()))
+        });
+        engine.register_fn("spawn_instance", |api: &mut ScriptApi, model: INT, x: FLOAT, y: FLOAT, z: FLOAT| {
+            let mut world = api.0.borrow_mut();
+            let instance = Instance::placed(vec3(x, y, z), Quaternion::new(1.0, 0.0, 0.0, 0.0), Vector3::unit_y());
+            let Some(entry) = world.model_mut(model) else {
+                return -1;
+            };
+            let id = InstanceId { model: entry.handle, index: entry.push_instance(instance) };
+            world.spawned.push(id);
+            id.index as INT
+        });
+        // Without an index, the newest one
+        engine.register_fn("despawn_instance", |api: &mut ScriptApi, model: INT| {
+            let mut world = api.0.borrow_mut();
+            let newest = world.model(model).and_then(ModelEntry::newest_index);
+            newest.is_some_and(|index| world.despawn(model, index))
+        });
+        engine.register_fn("despawn_instance", |api: &mut ScriptApi, model: INT, index: INT| {
+            usize::try_from(index).is_ok_and(|index| api.0.borrow_mut().despawn(model, index))
+        });
+        engine.register_fn("position", |api: &mut ScriptApi, model: INT, index: INT| {
+            let world = api.0.borrow();
+            let instance = world.model(model).and_then(|entry| entry.instance(usize::try_from(index).ok()?));
+            instance.map_or(Dynamic::UNIT, |instance| vec3_array(instance.initial_position + instance.position).into())
+        });
+        engine.register_fn("set_position", |api: &mut ScriptApi, model: INT, index: INT, x: FLOAT, y: FLOAT, z: FLOAT| {
+            api.0.borrow_mut().instance_mut(model, index).map(|instance| instance.initial_position = vec3(x, y, z) - instance.position).is_some()
+        });
+        engine.register_fn("set_rotation", |api: &mut ScriptApi, model: INT, index: INT, x: FLOAT, y: FLOAT, z: FLOAT| {
+            let rotation = Quaternion::from(Euler::new(Deg(x as f32), Deg(y as f32), Deg(z as f32)));
+            api.0.borrow_mut().instance_mut(model, index).map(|instance| instance.rotation = rotation).is_some()
+        });
+        engine.register_fn("set_scale", |api: &mut ScriptApi, model: INT, index: INT, scale: FLOAT| {
+            let scale = clamp_scale(Vector3::new(scale as f32, scale as f32, scale as f32));
+            api.0.borrow_mut().instance_mut(model, index).map(|instance| instance.scale = scale).is_some()
+        });
+
+        engine.register_fn("light_intensity", |api: &mut ScriptApi| FLOAT::from(api.0.borrow().light_intensity));
+        engine.register_fn("set_light_intensity", |api: &mut ScriptApi, intensity: FLOAT| {
+            api.0.borrow_mut().light_intensity = (intensity as f32).max(0.0);
+        });
+        engine.register_fn("light_color", |api: &mut ScriptApi| -> Array {
+            api.0.borrow().light_color.iter().map(|&channel| Dynamic::from_float(FLOAT::from(channel))).collect()
+        });
+        engine.register_fn("set_light_color", |api: &mut ScriptApi, r: FLOAT, g: FLOAT, b: FLOAT| {
+            api.0.borrow_mut().light_color = [r as f32, g as f32, b as f32];
+        });
+
+        // Point lights are passed around as their id, -1 once MAX_POINT_LIGHTS are placed
+        engine.register_fn("add_point_light", |api: &mut ScriptApi, x: FLOAT, y: FLOAT, z: FLOAT| {
+            let mut world = api.0.borrow_mut();
+            let units_per_meter = world.units_per_meter;
+            // Placing selects the light, which is the menu's business and not the script's
+            let selected = world.point_lights.selected;
+            let id = world.point_lights.add(vec3(x, y, z), units_per_meter);
+            world.point_lights.selected = selected;
+            id.map_or(-1, |id| INT::from(id.raw()))
+        });
+        engine.register_fn("move_point_light", |api: &mut ScriptApi, id: INT, x: FLOAT, y: FLOAT, z: FLOAT| {
+            api.0.borrow_mut().point_lights.get_mut_raw(id).map(|light| light.position = vec3(x, y, z)).is_some()
+        });
+        engine.register_fn("set_point_light_color", |api: &mut ScriptApi, id: INT, r: FLOAT, g: FLOAT, b: FLOAT| {
+            api.0.borrow_mut().point_lights.get_mut_raw(id).map(|light| light.color = [r as f32, g as f32, b as f32]).is_some()
+        });
+        engine.register_fn("set_point_light_intensity", |api: &mut ScriptApi, id: INT, intensity: FLOAT| {
+            api.0.borrow_mut().point_lights.get_mut_raw(id).map(|light| light.intensity = (intensity as f32).max(0.0)).is_some()
+        });
+        engine.register_fn("remove_point_light", |api: &mut ScriptApi, id: INT| {
+            let mut world = api.0.borrow_mut();
+            let light = world.point_lights.get_mut_raw(id).map(|light| light.id);
+            light.is_some_and(|id| world.point_lights.remove(id))
+        });
+
+        engine.register_fn("key_down", |api: &mut ScriptApi, key: &str| api.0.borrow().held_keys.iter().any(|held| held == key));
+        engine.register_fn("time", |api: &mut ScriptApi| FLOAT::from(api.0.borrow().time));
+    }
+}
+
+fn vec3(x: FLOAT, y: FLOAT, z: FLOAT) -> Vector3<f32> {
+    Vector3::new(x as f32, y as f32, z as f32)
+}
+
+fn vec3_array(v: Vector3<f32>) -> Array {
+    [v.x, v.y, v.z].iter().map(|&axis| Dynamic::from_float(FLOAT::from(axis))).collect()
+}
+
+// What the Scripts window shows of one script
+#[derive(Debug, Clone)]
+pub struct ScriptInfo {
+    pub name: String,
+    pub enabled: bool,
+    // The error that stopped it
+    pub error: Option<String>,
+    // Has none of on_init, on_update and on_event, or didn't compile
+    pub idle: bool,
+    // Smoothed over the last calls
+    pub time_ms: f32,
+}
+
+struct Script {
+    name: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    // Last time the modification time moved, cleared once the new source was read
+    changed_at: Option<Instant>,
+    // None until the source compiles
+    ast: Option<AST>,
+    has_init: bool,
+    has_update: bool,
+    has_event: bool,
+    // on_init runs before the next on_update
+    needs_init: bool,
+    // `this` inside the entry points, kept across reloads so a script can find what it spawned
+    this: Dynamic,
+    enabled: bool,
+    error: Option<String>,
+    time_ms: f32,
+}
+
+pub struct ScriptHost {
+    engine: rhai::Engine,
+    dir: PathBuf,
+    // Sorted by name, the order they run in
+    scripts: Vec<Script>,
+    watch: bool,
+    last_poll: Instant,
+    // Compile and runtime errors not reported yet
+    errors: Vec<String>,
+    // Filled by event_listener, handed to on_event on the next update
+    events: Rc<RefCell<VecDeque<Map>>>,
+}
+
+impl ScriptHost {
+    // A missing folder is no scripts, not an error. Without `watch` the folder is only read here
+    // and by reload.
+    pub fn new(dir: &Path, watch: bool) -> Self {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| log::info!("script: {}", text));
+        engine.on_debug(|text, source, position| log::debug!("script {}{}: {}", source.unwrap_or_default(), position, text));
+        ScriptApi::register(&mut engine);
+        let mut host = Self { engine, dir: dir.to_path_buf(), scripts: Vec::new(), watch, last_poll: Instant::now(), errors: Vec::new(), events: Rc::default() };
+        host.reload();
+        if !host.scripts.is_empty() {
+            log::info!("Loaded {} scripts from {}", host.scripts.len(), host.dir.display());
+        }
+        host
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watch
+    }
+
+    // Reads every script again, as if they had all just been saved
+    pub fn reload(&mut self) {
+        let paths = script_paths(&self.dir);
+        self.scripts.retain(|script| paths.contains(&script.path));
+        for path in paths {
+            let modified = modified(&path);
+            match self.scripts.iter().position(|script| script.path == path) {
+                Some(index) => self.scripts[index].modified = modified,
+                None => self.scripts.push(Script {
+                    name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+                    path,
+                    modified,
+                    changed_at: None,
+                    ast: None,
+                    has_init: false,
+                    has_update: false,
+                    has_event: false,
+                    needs_init: false,
+                    this: Map::new().into(),
+                    enabled: true,
+                    error: None,
+                    time_ms: 0.0,
+                }),
+            }
+        }
+        self.scripts.sort_by(|a, b| a.name.cmp(&b.name));
+        for index in 0..self.scripts.len() {
+            self.compile(index);
+        }
+    }
+
+    // Cheap to call every frame, the folder is only looked at every POLL_INTERVAL
+    pub fn poll(&mut self, now: Instant) {
+        if !self.watch || now.duration_since(self.last_poll) < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = now;
+        let paths = script_paths(&self.dir);
+        let before = self.scripts.len();
+        self.scripts.retain(|script| paths.contains(&script.path));
+        if self.scripts.len() != before {
+            log::info!("{} scripts removed from {}", before - self.scripts.len(), self.dir.display());
+        }
+        let mut changed = Vec::new();
+        for path in paths {
+            let current = modified(&path);
+            let Some(script) = self.scripts.iter_mut().find(|script| script.path == path) else {
+                // New files settle like edited ones
+                self.scripts.push(Script {
+                    name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+                    path,
+                    modified: current,
+                    changed_at: Some(now),
+                    ast: None,
+                    has_init: false,
+                    has_update: false,
+                    has_event: false,
+                    needs_init: false,
+                    this: Map::new().into(),
+                    enabled: true,
+                    error: None,
+                    time_ms: 0.0,
+                });
+                continue;
+            };
+            if current != script.modified {
+                script.modified = current;
+                script.changed_at = Some(now);
+                continue;
+            }
+            if script.changed_at.is_some_and(|at| now.duration_since(at) >= SETTLE_TIME) {
+                script.changed_at = None;
+                changed.push(script.path.clone());
+            }
+        }
+        self.scripts.sort_by(|a, b| a.name.cmp(&b.name));
+        for path in changed {
+            if let Some(index) = self.scripts.iter().position(|script| script.path == path) {
+                log::info!("Reloading {}", path.display());
+                self.compile(index);
+            }
+        }
+    }
+
+    // A script that doesn't compile keeps nothing of its previous version, like a fresh start
+    fn compile(&mut self, index: usize) {
+        let script = &mut self.scripts[index];
+        script.error = None;
+        script.ast = None;
+        script.has_init = false;
+        script.has_update = false;
+        script.has_event = false;
+        let source = match std::fs::read_to_string(&script.path) {
+            Ok(source) => source,
+            Err(e) => {
+                let message = format!("{}: could not read it: {}", script.name, e);
+                script.error = Some(message.clone());
+                self.errors.push(message);
+                return;
+            }
+        };
+        match self.engine.compile(&source) {
+            Ok(ast) => {
+                let entry_point = |name: &str, params: usize| ast.iter_functions().any(|function| function.name == name && function.params.len() == params);
+                script.has_init = entry_point("on_init", 1);
+                script.has_update = entry_point("on_update", 2);
+                script.has_event = entry_point("on_event", 2);
+                script.needs_init = script.has_init;
+                script.ast = Some(ast);
+            }
+            Err(e) => {
+                let message = located(&script.name, e.1, &e.0);
+                script.error = Some(message.clone());
+                self.errors.push(message);
+            }
+        }
+    }
+
+    // Subscribed to the engine's EventBus. Events wait for the next update, the oldest going once
+    // EVENT_CAPACITY are waiting, as they do in the bus.
+    pub fn event_listener(&self) -> impl FnMut(&EngineEvent) + 'static {
+        let events = self.events.clone();
+        move |event| {
+            let mut events = events.borrow_mut();
+            if events.len() == EVENT_CAPACITY {
+                events.pop_front();
+            }
+            events.push_back(event_map(event));
+        }
+    }
+
+    // Nothing is going to run them, so they'd only arrive late
+    pub fn discard_events(&mut self) {
+        self.events.borrow_mut().clear();
+    }
+
+    // Runs on_init where it is due, then on_event for every event since the last update, then
+    // every on_update. The world comes back with whatever the scripts changed.
+    pub fn update(&mut self, world: ScriptWorld, dt: f32) -> ScriptWorld {
+        let api = ScriptApi(Rc::new(RefCell::new(world)));
+        let events: Vec<Map> = self.events.borrow_mut().drain(..).collect();
+        for script in &mut self.scripts {
+            let Some(ast) = script.ast.as_ref().filter(|_| script.enabled && script.error.is_none()) else {
+                continue;
+            };
+            let start = Instant::now();
+            let mut result = Ok(());
+            if script.needs_init {
+                script.needs_init = false;
+                result = call(&self.engine, ast, &mut script.this, "on_init", (api.clone(),));
+            }
+            if script.has_event {
+                for event in &events {
+                    if result.is_err() {
+                        break;
+                    }
+                    result = call(&self.engine, ast, &mut script.this, "on_event", (api.clone(), event.clone()));
+                }
+            }
+            if result.is_ok() && script.has_update {
+                result = call(&self.engine, ast, &mut script.this, "on_update", (api.clone(), FLOAT::from(dt)));
+            }
+            let ms = start.elapsed().as_secs_f32() * 1000.0;
+            script.time_ms += (ms - script.time_ms) * TIME_SMOOTHING;
+            if let Err(e) = result {
+                let message = describe_error(&script.name, e);
+                script.error = Some(message.clone());
+                self.errors.push(message);
+            }
+        }
+        // A script may have kept its `engine` in `this`, what it points to is gone after this
+        std::mem::take(&mut *api.0.borrow_mut())
+    }
+
+    // Errors since the last call, meant for the error overlay
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn scripts(&self) -> Vec<ScriptInfo> {
+        self.scripts
+            .iter()
+            .map(|script| ScriptInfo {
+                name: script.name.clone(),
+                enabled: script.enabled,
+                error: script.error.clone(),
+                idle: script.ast.is_none() || !(script.has_init || script.has_update || script.has_event),
+                time_ms: script.time_ms,
+            })
+            .collect()
+    }
+
+    // Enabling clears an error from a run, on_init isn't called again until the file reloads
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if let Some(script) = self.scripts.iter_mut().find(|script| script.name == name) {
+            script.enabled = enabled;
+            if enabled && script.ast.is_some() {
+                script.error = None;
+            }
+        }
+    }
+
+    // Loaded and not stopped, for the diagnostics report
+    pub fn running(&self) -> usize {
+        self.scripts.iter().filter(|script| script.ast.is_some() && script.enabled && script.error.is_none()).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+}
+
+// #{kind: "instance_spawned", model: 1, index: 4}, a cleared selection has no model or index
+fn event_map(event: &EngineEvent) -> Map {
+    let mut map = Map::new();
+    map.insert("kind".into(), event.kind().into());
+    let mut instance = |id: crate::model_entry::InstanceId| {
+        map.insert("model".into(), (id.model.0 as INT).into());
+        map.insert("index".into(), (id.index as INT).into());
+    };
+    match event {
+        EngineEvent::InstanceSpawned { id } | EngineEvent::InstanceDespawned { id } | EngineEvent::SelectionChanged { id: Some(id) } => instance(*id),
+        EngineEvent::AssetLoaded { path, handle } => {
+            map.insert("path".into(), path.clone().into());
+            map.insert("model".into(), (handle.0 as INT).into());
+        }
+        EngineEvent::AssetFailed { path, error } => {
+            map.insert("path".into(), path.clone().into());
+            map.insert("error".into(), error.clone().into());
+        }
+        EngineEvent::WindowResized { width, height } => {
+            map.insert("width".into(), (*width as INT).into());
+            map.insert("height".into(), (*height as INT).into());
+        }
+        EngineEvent::SelectionChanged { id: None } | EngineEvent::DeviceLost | EngineEvent::SettingsChanged => {}
+    }
+    map
+}
+
+fn call(engine: &rhai::Engine, ast: &AST, this: &mut Dynamic, name: &str, args: impl rhai::FuncArgs) -> Result<(), Box<EvalAltResult>> {
+    // eval_ast off, top-level statements ran when nothing was listening and don't run again
+    let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+    engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, args).map(|_| ())
+}
+
+// "orbit.rhai:12: <what went wrong>", with the line inside the script rather than the call site
+fn describe_error(script: &str, mut error: Box<EvalAltResult>) -> String {
+    while let EvalAltResult::ErrorInFunctionCall(.., inner, _) = *error {
+        error = inner;
+    }
+    let position = error.take_position();
+    located(script, position, &error)
+}
+
+// Errors like running out of operations have no line
+fn located(script: &str, position: rhai::Position, error: &dyn std::fmt::Display) -> String {
+    match position.line() {
+        Some(line) => format!("{}:{}: {}", script, line, error),
+        None => format!("{}: {}", script, error),
+    }
+}
+
+fn script_paths(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).filter(|path| path.extension().is_some_and(|extension| extension == "rhai")).collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,79 @@
+/*
+Purpose: Signed distance functions for procedural meshes
+Responsibilities:
+    - Primitive distances (sphere, box, torus) and a smooth union to blend them
+    - The demo shapes the menu turns into a mesh with shapes::mesh_from_sdf
+    - ex: describing a sculpture by how far you are from it instead of where its surface is
+*/
+
+use cgmath::{InnerSpace, Vector2};
+
+use crate::math::{Aabb, Vec3};
+
+pub fn sphere(p: Vec3, radius: f32) -> f32 {
+    p.magnitude() - radius
+}
+
+pub fn cuboid(p: Vec3, half_extents: Vec3) -> f32 {
+    let q = Vec3::new(p.x.abs() - half_extents.x, p.y.abs() - half_extents.y, p.z.abs() - half_extents.z);
+    let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+    let inside = q.x.max(q.y).max(q.z).min(0.0);
+    outside + inside
+}
+
+// Ring lying in the XZ plane
+pub fn torus(p: Vec3, major_radius: f32, minor_radius: f32) -> f32 {
+    let q = Vector2::new(Vector2::new(p.x, p.z).magnitude() - major_radius, p.y);
+    q.magnitude() - minor_radius
+}
+
+// Polynomial smooth minimum. `k` is how close (in distance) two surfaces get before they
+// start melting together, 0 is a plain union.
+pub fn smooth_union(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+// The shapes offered by the SDF demo in the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdfShape {
+    Sphere,
+    Box,
+    Torus,
+    BlendedSpheres,
+}
+
+impl SdfShape {
+    pub const ALL: [SdfShape; 4] = [SdfShape::Sphere, SdfShape::Box, SdfShape::Torus, SdfShape::BlendedSpheres];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SdfShape::Sphere => "Sphere",
+            SdfShape::Box => "Box",
+            SdfShape::Torus => "Torus",
+            SdfShape::BlendedSpheres => "Two spheres, smooth union",
+        }
+    }
+
+    // Only the blended spheres use `blend`
+    pub fn distance(self, p: Vec3, blend: f32) -> f32 {
+        match self {
+            SdfShape::Sphere => sphere(p, 1.0),
+            SdfShape::Box => cuboid(p, Vec3::new(0.8, 0.6, 0.7)),
+            SdfShape::Torus => torus(p, 0.9, 0.35),
+            SdfShape::BlendedSpheres => smooth_union(
+                sphere(p - Vec3::new(-0.6, 0.0, 0.0), 0.6),
+                sphere(p - Vec3::new(0.6, 0.0, 0.0), 0.6),
+                blend,
+            ),
+        }
+    }
+
+    // Every shape fits in here with a cell to spare at the usual resolutions
+    pub fn bounds(self) -> Aabb {
+        Aabb::new(Vec3::new(-1.5, -1.5, -1.5), Vec3::new(1.5, 1.5, 1.5))
+    }
+}
@@ -31,6 +31,22 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+// Must match MaterialUniform in model.rs
+struct Material {
+    color: vec4<f32>,
+    specular_strength: f32,
+    shininess: f32,
+    roughness: f32,
+    metallic: f32,
+    // 0 unlit, 1 Lambert, 2 Blinn-Phong, 3 PBR-lite, see ShadingModel
+    shading_model: u32,
+    debug_view: u32,
+    // Texture array layer, only used by the packed variant of this shader (material_array.rs)
+    layer: u32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+
 // Group 1: Camera
 struct CameraUniform {
     view_pos: vec4<f32>,
@@ -42,11 +58,37 @@ var<uniform> camera: CameraUniform;
 // Group 2: Lighting
 struct Light {
     position: vec3<f32>,
+    marker_scale: f32,
     color: vec3<f32>,
+    intensity: f32,
+    ambient: f32,
 }
 @group(2) @binding(0)
 var<uniform> light: Light;
 
+// Group 2: User clip planes, the enabled ones first (clip_planes.rs)
+struct ClipPlanes {
+    // xyz: unit normal, w: minus the offset along it
+    planes: array<vec4<f32>, 4>,
+    count: u32,
+}
+@group(2) @binding(1)
+var<uniform> clip: ClipPlanes;
+
+// Group 2: Placed point lights, on top of `light` (point_lights.rs)
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+struct PointLights {
+    lights: array<PointLight, 16>,
+    count: u32,
+}
+@group(2) @binding(2)
+var<uniform> point_lights: PointLights;
+
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
@@ -56,6 +98,9 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+
+    // xy: offset, zw: scale into a texture atlas
+    @location(12) uv_transform: vec4<f32>,
 };
 
 
@@ -70,11 +115,16 @@ struct VertexInput {
 
 // store the output of the vertex shader
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // Invariant so the depth pre-pass (depth_prepass.wgsl) lands on exactly the same depth
+    @invariant @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    // World space, for reflections. The camera is only visible to the vertex stage.
+    @location(4) world_position: vec3<f32>,
+    @location(5) world_normal: vec3<f32>,
+    @location(6) view_position: vec3<f32>,
 };
 
 @vertex
@@ -82,6 +132,11 @@ fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
+    return transform(model, instance);
+}
+
+// vs_main's work, also called by pull_model.wgsl with the attributes read from a storage buffer
+fn transform(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
@@ -106,22 +161,41 @@ fn vs_main(
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
-    out.tex_coords = model.tex_coords;
+    out.tex_coords = model.tex_coords * instance.uv_transform.zw + instance.uv_transform.xy;
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
+    out.world_normal = world_normal;
+    out.view_position = camera.view_pos.xyz;
     return out;
 }
 
 // Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    clip_fragment(in.world_position);
+    return shade(in);
+}
+
+// Throws away fragments behind any enabled clip plane, with none enabled the loop never runs
+fn clip_fragment(world_position: vec3<f32>) {
+    for (var i = 0u; i < clip.count; i++) {
+        if dot(clip.planes[i].xyz, world_position) + clip.planes[i].w < 0.0 {
+            discard;
+        }
+    }
+}
+
+// Textured and normal mapped in the material's shading model, shared by fs_main and fs_reflective
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.color;
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
 
-    // think of this like the light emission strenght
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    let light_color = light.color * light.intensity;
+
+    // think of this like the light emission strenght, see LightUniform::ambient
+    let ambient_color = light.color * light.ambient;
 
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
     let light_dir = normalize(in.tangent_light_position - in.tangent_position);
@@ -129,12 +203,161 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let half_dir = normalize(view_dir + light_dir);
 
     let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = light_color * diffuse_strength;
+
+    if material.debug_view != 0u {
+        // Flat color per shading model, a little diffuse keeps the shapes readable
+        return vec4<f32>(shading_model_color(material.shading_model) * (0.4 + 0.6 * diffuse_strength), object_color.a);
+    }
+
+    var result: vec3<f32>;
+    switch material.shading_model {
+        case 0u: {
+            result = object_color.xyz;
+        }
+        case 1u: {
+            result = (ambient_color + diffuse_color) * object_color.xyz;
+        }
+        case 3u: {
+            result = pbr_lite(object_color.xyz, normalize(tangent_normal), light_dir, view_dir, half_dir, light_color, ambient_color);
+        }
+        default: {
+            let specular_strength = material.specular_strength * pow(max(dot(tangent_normal, half_dir), 0.0), material.shininess);
+            let specular_color = specular_strength * light_color;
+            result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+        }
+    }
+    if material.shading_model != 0u {
+        result += point_light_color(in.world_position, in.world_normal, in.view_position) * object_color.xyz;
+    }
+
+    return vec4<f32>(result, object_color.a);
+}
+
+// Diffuse and a Blinn-Phong highlight from every placed light, in world space with the vertex
+// normal. Each light fades out smoothly towards its range.
+fn point_light_color(world_position: vec3<f32>, world_normal: vec3<f32>, view_position: vec3<f32>) -> vec3<f32> {
+    let normal = normalize(world_normal);
+    let view_dir = normalize(view_position - world_position);
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < point_lights.count; i++) {
+        let point = point_lights.lights[i];
+        let to_light = point.position - world_position;
+        let distance = length(to_light);
+        let light_dir = to_light / max(distance, 1e-4);
+        let falloff = saturate(1.0 - distance / point.range);
+        let diffuse = max(dot(normal, light_dir), 0.0);
+        let half_dir = normalize(view_dir + light_dir);
+        let specular = material.specular_strength * pow(max(dot(normal, half_dir), 0.0), material.shininess);
+        total += point.color * point.intensity * falloff * falloff * (diffuse + specular);
+    }
+    return total;
+}
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+const PI: f32 = 3.14159265;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+// GGX specular with Schlick's Fresnel and Smith geometry, Lambert diffuse. Scaled by PI so it
+// is about as bright as the other modes under the same light.
+fn pbr_lite(albedo: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>, view_dir: vec3<f32>, half_dir: vec3<f32>, light_color: vec3<f32>, ambient_color: vec3<f32>) -> vec3<f32> {
+    let roughness = clamp(material.roughness, 0.04, 1.0);
+    let metallic = clamp(material.metallic, 0.0, 1.0);
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+    let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    let alpha = roughness * roughness;
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (PI * d * d);
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = (n_dot_v / (n_dot_v * (1.0 - k) + k)) * (n_dot_l / (n_dot_l * (1.0 - k) + k));
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+
+    let specular = distribution * geometry * fresnel / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo;
+    return ambient_color * albedo + (diffuse + PI * specular) * light_color * n_dot_l;
+}
+
+// Debug view colors: grey unlit, blue Lambert, green Blinn-Phong, orange PBR-lite
+fn shading_model_color(shading_model: u32) -> vec3<f32> {
+    switch shading_model {
+        case 0u: {
+            return vec3<f32>(0.6, 0.6, 0.6);
+        }
+        case 1u: {
+            return vec3<f32>(0.2, 0.4, 1.0);
+        }
+        case 3u: {
+            return vec3<f32>(1.0, 0.5, 0.1);
+        }
+        default: {
+            return vec3<f32>(0.2, 0.9, 0.3);
+        }
+    }
+}
+
+// Group 3: Toon settings, only bound by the RenderStyle::Toon pipeline (toon.rs)
+struct Toon {
+    outline_color: vec4<f32>,
+    viewport: vec2<f32>,
+    outline_width: f32,
+    bands: u32,
+}
+@group(3) @binding(0)
+var<uniform> toon: Toon;
+
+// fs_main with the diffuse term snapped to `bands` flat steps and a hard-edged highlight
+@fragment
+fn fs_toon(in: VertexOutput) -> @location(0) vec4<f32> {
+    clip_fragment(in.world_position);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
+
+    let light_color = light.color * light.intensity;
+    let ambient_color = light.color * light.ambient;
+
+    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
+    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
+    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let bands = f32(max(toon.bands, 1u));
+    let diffuse_strength = ceil(max(dot(tangent_normal, light_dir), 0.0) * bands) / bands;
+    let specular_strength = step(0.5, pow(max(dot(tangent_normal, half_dir), 0.0), 32.0));
+
+    let result = (ambient_color + light_color * (diffuse_strength + specular_strength)) * object_color.xyz;
 
     return vec4<f32>(result, object_color.a);
 }
+
+// Group 3: Reflection probe, only bound by the reflective pipeline (probes.rs). Binding 0 is
+// left to the toon uniform above, the two never share a pipeline.
+struct Probe {
+    position: vec3<f32>,
+    radius: f32,
+    sky_color: vec3<f32>,
+    baked: u32,
+}
+@group(3) @binding(1)
+var t_probe: texture_cube<f32>;
+@group(3) @binding(2)
+var s_probe: sampler;
+@group(3) @binding(3)
+var<uniform> probe: Probe;
+
+// fs_main with the probe's cubemap mirrored in, more at grazing angles (Schlick's Fresnel)
+@fragment
+fn fs_reflective(in: VertexOutput) -> @location(0) vec4<f32> {
+    clip_fragment(in.world_position);
+    let lit = shade(in);
+    let normal = normalize(in.world_normal);
+    let view_dir = normalize(in.world_position - in.view_position);
+    let reflected = reflect(view_dir, normal);
+    // Probe faces are baked with z mirrored, see FACES in probes.rs
+    let sampled = textureSample(t_probe, s_probe, vec3<f32>(reflected.x, reflected.y, -reflected.z)).rgb;
+    let environment = select(probe.sky_color, sampled, probe.baked != 0u);
+    let fresnel = 0.3 + 0.7 * pow(1.0 - max(dot(normal, -view_dir), 0.0), 5.0);
+    return vec4<f32>(mix(lit.rgb, environment, fresnel), lit.a);
+}
@@ -0,0 +1,141 @@
+/*
+Purpose: Procedural shape shader
+Responsibilites:
+    - Draw shapes.rs geometry (per-vertex color) tinted per instance
+    - Light them with a small array of point lights
+    - Faces are drawn double sided, the normal is flipped for back faces
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: Scene lights, laid out like LightUniform (light.rs)
+struct Light {
+    position: vec3<f32>,
+    marker_scale: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    ambient: f32,
+}
+struct SceneLights {
+    lights: array<Light, 4>,
+    count: u32,
+}
+@group(1) @binding(0)
+var<uniform> scene_lights: SceneLights;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+
+    @location(12) tint: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    // The camera group is only visible to the vertex stage
+    @location(3) view_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return transform(model, instance);
+}
+
+// vs_main's work, also called by pull_shape.wgsl with the attributes read from a storage buffer
+fn transform(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.color = model.color * instance.tint.rgb;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_position = world_position.xyz;
+    out.view_position = camera.view_pos.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let normal = normalize(select(-in.world_normal, in.world_normal, front_facing));
+    let view_dir = normalize(in.view_position - in.world_position);
+
+    var lighting = vec3<f32>(0.1);
+    for (var i = 0u; i < min(scene_lights.count, 4u); i++) {
+        let light = scene_lights.lights[i];
+        let light_dir = normalize(light.position - in.world_position);
+        let half_dir = normalize(view_dir + light_dir);
+
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
+        lighting += light.color * (diffuse_strength + specular_strength);
+    }
+
+    return vec4<f32>(lighting * in.color, 1.0);
+}
+
+// Group 2: Toon settings, only bound by the toon shape pipeline (toon.rs)
+struct Toon {
+    outline_color: vec4<f32>,
+    viewport: vec2<f32>,
+    outline_width: f32,
+    bands: u32,
+}
+@group(2) @binding(0)
+var<uniform> toon: Toon;
+
+// fs_main with every light's diffuse term snapped to `bands` flat steps and hard highlights
+@fragment
+fn fs_toon(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let normal = normalize(select(-in.world_normal, in.world_normal, front_facing));
+    let view_dir = normalize(in.view_position - in.world_position);
+    let bands = f32(max(toon.bands, 1u));
+
+    var lighting = vec3<f32>(0.1);
+    for (var i = 0u; i < min(scene_lights.count, 4u); i++) {
+        let light = scene_lights.lights[i];
+        let light_dir = normalize(light.position - in.world_position);
+        let half_dir = normalize(view_dir + light_dir);
+
+        let diffuse_strength = ceil(max(dot(normal, light_dir), 0.0) * bands) / bands;
+        let specular_strength = step(0.5, pow(max(dot(normal, half_dir), 0.0), 32.0));
+        lighting += light.color * (diffuse_strength + specular_strength);
+    }
+
+    return vec4<f32>(lighting * in.color, 1.0);
+}
@@ -0,0 +1,104 @@
+/*
+Purpose: Pick how finely a procedural shape is tessellated from how big it shows on screen
+Responsibilities:
+    - Define the LOD levels and the projected size each level is kept down to
+    - Estimate an object's diameter in pixels from its bounding sphere and the camera
+    - Only change level once the size is clearly past a threshold, so a shape sitting right on
+      one doesn't pop back and forth
+    - Count the triangles each level drew against drawing everything at the finest level
+    - ex: a painter who only puts in the leaves on the trees in the foreground
+*/
+
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
+
+use crate::camera::{Camera, Projection};
+
+// Finest first
+pub const LOD_LEVELS: usize = 4;
+// Smallest diameter on screen, in pixels, each level but the coarsest is drawn down to
+const MIN_PIXELS: [f32; LOD_LEVELS - 1] = [240.0, 90.0, 30.0];
+// How far past a threshold the size has to go before the level changes, as a fraction of it
+const HYSTERESIS: f32 = 0.15;
+// Debug view, green is the finest and red the coarsest
+pub const LOD_TINTS: [[f32; 3]; LOD_LEVELS] = [[0.2, 0.9, 0.2], [0.9, 0.9, 0.2], [1.0, 0.55, 0.1], [0.95, 0.15, 0.15]];
+// Debug view, shapes that only have the one mesh
+pub const NO_LOD_TINT: [f32; 3] = [0.5, 0.5, 0.5];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSettings {
+    pub enabled: bool,
+    // Draw every shape in its level's LOD_TINTS color instead of its own
+    pub tint: bool,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self { enabled: true, tint: false }
+    }
+}
+
+// What the levels are picked for, the main window's camera
+#[derive(Debug, Clone, Copy)]
+pub struct LodView {
+    eye: Vector3<f32>,
+    // Pixels a unit long object covers one unit in front of the camera
+    pixels_per_unit: f32,
+}
+
+impl LodView {
+    pub fn new(camera: &Camera, projection: &Projection, viewport_height: u32) -> Self {
+        let (_, tan_half_fovy) = projection.half_fov_tangents();
+        Self { eye: camera.position.to_vec(), pixels_per_unit: viewport_height as f32 / (2.0 * tan_half_fovy) }
+    }
+
+    // Diameter on screen in pixels. From inside the sphere it covers everything.
+    pub fn coverage(&self, center: Vector3<f32>, radius: f32) -> f32 {
+        let distance = (center - self.eye).magnitude();
+        if distance <= radius {
+            return f32::INFINITY;
+        }
+        2.0 * radius * self.pixels_per_unit / distance
+    }
+}
+
+// The level for a shape covering `coverage` pixels that was drawn at `current` last frame
+pub fn select(coverage: f32, current: usize) -> usize {
+    let mut level = current.min(LOD_LEVELS - 1);
+    while level > 0 && coverage > MIN_PIXELS[level - 1] * (1.0 + HYSTERESIS) {
+        level -= 1;
+    }
+    while level < LOD_LEVELS - 1 && coverage < MIN_PIXELS[level] * (1.0 - HYSTERESIS) {
+        level += 1;
+    }
+    level
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LodStats {
+    // Shapes drawn at each level
+    pub shapes: [u32; LOD_LEVELS],
+    pub triangles: u64,
+    // What the same shapes would have taken at the finest level
+    pub full_triangles: u64,
+}
+
+impl LodStats {
+    // `count` shapes at `level` of `triangles` each, whose finest level has `full_triangles`
+    pub fn add(&mut self, level: usize, count: u32, triangles: u32, full_triangles: u32) {
+        self.shapes[level] += count;
+        self.triangles += u64::from(count) * u64::from(triangles);
+        self.full_triangles += u64::from(count) * u64::from(full_triangles);
+    }
+
+    pub fn merge(&mut self, other: &LodStats) {
+        for (shapes, other) in self.shapes.iter_mut().zip(other.shapes) {
+            *shapes += other;
+        }
+        self.triangles += other.triangles;
+        self.full_triangles += other.full_triangles;
+    }
+
+    pub fn saved(&self) -> u64 {
+        self.full_triangles - self.triangles
+    }
+}
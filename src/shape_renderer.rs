@@ -0,0 +1,551 @@
+/*
+Purpose: Draw procedural shapes from shapes.rs
+Responsibilities:
+    - Own the shape pipeline and the mesh library every shape's geometry comes from
+    - Turn a generated SceneDescription into one instance buffer per shared mesh, and scene lights
+    - Spin each shape at its own rotation speed every frame
+    - Pick each sphere's tessellation from its size on screen and draw the instances of each
+      level with that level's mesh
+    - Draw a single shape whose mesh is replaced at runtime (the SDF demo, the grass ground)
+    - Draw either style, the toon one adds banded shading and an outline pass. The realistic one
+      can pull its vertices in the vertex shader instead (vertex_pulling.rs).
+    - ex: the stage crew that sets out the props
+*/
+
+use crate::{color, gpu_debug::debug_label, gpu_layout::{UniformCheck, VertexCheck, rust_layout}, gpu_memory::{self, Tracked}, instance_cull, light::LightUniform, math::{Aabb, Sphere}, mesh_library::{GpuMesh, MeshLibrary, ShapeKey}, scene_gen::{GeneratedLight, SceneDescription, ShapeKind}, shape_lod::{self, LOD_LEVELS, LOD_TINTS, LodStats, LodView, NO_LOD_TINT}, texture, toon::{self, ScenePipelineDesc}, vertex::Vertex, vertex_pulling};
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
+use std::ops::Range;
+use std::sync::Arc;
+
+// Must match the lights array length in shape.wgsl
+pub const MAX_SCENE_LIGHTS: usize = 4;
+
+// Per-instance data for shapes, like InstanceRaw but with a tint instead of an atlas rect
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShapeInstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    tint: [f32; 4],
+}
+
+pub const SHAPE_INSTANCE_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(ShapeInstanceRaw, [model, normal, tint]),
+    desc: ShapeInstanceRaw::desc,
+};
+
+impl ShapeInstanceRaw {
+    // `tint` is sRGB, uploaded linear (color.rs)
+    fn new(position: [f32; 3], rotation: Quaternion<f32>, scale: f32, tint: [f32; 3]) -> Self {
+        let model = Matrix4::from_translation(position.into()) * Matrix4::from(rotation) * Matrix4::from_scale(scale);
+        let [r, g, b] = color::srgb_to_linear_rgb(tint);
+        Self {
+            model: model.into(),
+            normal: cgmath::Matrix3::from(rotation).into(),
+            tint: [r, g, b, 1.0],
+        }
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+            5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4,
+            9 => Float32x3, 10 => Float32x3, 11 => Float32x3,
+            12 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ShapeInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SceneLightsUniform {
+    lights: [LightUniform; MAX_SCENE_LIGHTS],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+pub const SCENE_LIGHTS_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(SceneLightsUniform, [lights, count]),
+    wgsl: &[("shape.wgsl", "SceneLights")],
+};
+
+// Sectors and stacks of each sphere level, finest first
+const SPHERE_TESSELLATION: [(u32, u32); LOD_LEVELS] = [(48, 32), (24, 16), (12, 8), (8, 6)];
+// Of ShapeKey::Sphere, before the shape's scale
+const SPHERE_RADIUS: f32 = 0.5;
+
+// The library meshes a generated shape is drawn with, one per LOD level. The flat-sided shapes
+// are a handful of triangles and only have the one.
+pub fn mesh_keys(kind: ShapeKind) -> Vec<ShapeKey> {
+    match kind {
+        ShapeKind::Plane => vec![ShapeKey::Plane],
+        ShapeKind::Pyramid => vec![ShapeKey::Pyramid],
+        ShapeKind::Cube => vec![ShapeKey::Cube],
+        ShapeKind::Sphere => SPHERE_TESSELLATION.iter().map(|&(sectors, stacks)| ShapeKey::Sphere { sectors, stacks }).collect(),
+    }
+}
+
+// What the LOD debug view draws a shape in instead of its own color
+fn lod_tint(tint: [f32; 3], level: Option<usize>, show_levels: bool) -> [f32; 3] {
+    match (show_levels, level) {
+        (false, _) => tint,
+        (true, Some(level)) => LOD_TINTS[level],
+        (true, None) => NO_LOD_TINT,
+    }
+}
+
+// How a pass draws the shapes
+#[derive(Clone, Copy)]
+pub enum ShapeStyle<'a> {
+    Realistic,
+    // Realistic with the vertices read from storage buffers, where the adapter can
+    Pulled(&'a wgpu::Device),
+    // The toon bind group, banded and outlined
+    Toon(&'a wgpu::BindGroup),
+}
+
+// ShapeStyle::Pulled's pipeline: shape.wgsl on pull.wgsl, with the mesh's vertices in group 3 and
+// nothing in group 2, which shape.wgsl gives to the toon uniform
+struct PulledShapePipeline {
+    pipeline: wgpu::RenderPipeline,
+    // VertexPullingPipeline's
+    bind_group_layout: wgpu::BindGroupLayout,
+    empty_bind_group: wgpu::BindGroup,
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct ShapePipeline {
+    pipeline: wgpu::RenderPipeline,
+    // Only where the adapter can pull vertices
+    pulled: Option<PulledShapePipeline>,
+    // RenderStyle::Toon: shape.wgsl's fs_toon and the outline hull, both with the toon group last
+    toon_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    lights_bind_group_layout: wgpu::BindGroupLayout,
+    // Shapes are built the first time a scene uses them
+    pub meshes: MeshLibrary,
+}
+
+impl ShapePipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        toon_bind_group_layout: &wgpu::BindGroupLayout,
+        // VertexPullingPipeline's group 3, when the adapter has one
+        pulled_bind_group_layout: Option<&wgpu::BindGroupLayout>,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let lights_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Scene Lights Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shape Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &lights_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shape Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shape.wgsl").into()),
+        });
+
+        // Like create_render_pipeline, but without culling: the shapes.rs builders
+        // don't agree on a winding order, so the shader lights whichever side is visible
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shape Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::desc(), ShapeInstanceRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        let toon_pipeline = toon::scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Toon Shape Pipeline",
+                bind_group_layouts: &[camera_bind_group_layout, &lights_bind_group_layout, toon_bind_group_layout],
+                shader: &shader,
+                entry_points: ("vs_main", "fs_toon"),
+                vertex_layouts: &[Vertex::desc(), ShapeInstanceRaw::desc()],
+                cull_mode: None,
+            },
+            color_format,
+            sample_count,
+        );
+        let outline_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shape Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+        });
+        let outline_pipeline = toon::scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Shape Outline Pipeline",
+                bind_group_layouts: &[camera_bind_group_layout, toon_bind_group_layout],
+                shader: &outline_shader,
+                entry_points: ("vs_shape", "fs_outline"),
+                vertex_layouts: &[Vertex::desc(), ShapeInstanceRaw::desc()],
+                cull_mode: None,
+            },
+            color_format,
+            sample_count,
+        );
+
+        let pulled = pulled_bind_group_layout.map(|bind_group_layout| {
+            let empty_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { entries: &[], label: Some("Empty Bind Group Layout") });
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Pulled Shape Shader"),
+                source: wgpu::ShaderSource::Wgsl(vertex_pulling::shape_shader_source().into()),
+            });
+            let pipeline = toon::scene_pipeline(
+                device,
+                ScenePipelineDesc {
+                    label: "Pulled Shape Pipeline",
+                    bind_group_layouts: &[camera_bind_group_layout, &lights_bind_group_layout, &empty_layout, bind_group_layout],
+                    shader: &shader,
+                    entry_points: ("vs_main", "fs_main"),
+                    vertex_layouts: &[ShapeInstanceRaw::desc()],
+                    cull_mode: None,
+                },
+                color_format,
+                sample_count,
+            );
+            PulledShapePipeline {
+                pipeline,
+                bind_group_layout: bind_group_layout.clone(),
+                empty_bind_group: device.create_bind_group(&wgpu::BindGroupDescriptor { layout: &empty_layout, entries: &[], label: Some("Empty Bind Group") }),
+            }
+        });
+
+        Self {
+            pipeline,
+            pulled,
+            toon_pipeline,
+            outline_pipeline,
+            lights_bind_group_layout,
+            meshes: MeshLibrary::default(),
+        }
+    }
+
+    fn create_lights_bind_group(&self, device: &wgpu::Device, scene_lights: &[GeneratedLight]) -> wgpu::BindGroup {
+        if scene_lights.len() > MAX_SCENE_LIGHTS {
+            log::warn!("Only the first {} of {} scene lights are used", MAX_SCENE_LIGHTS, scene_lights.len());
+        }
+        let mut lights = SceneLightsUniform {
+            lights: [LightUniform { position: [0.0; 3], marker_scale: 0.0, color: [0.0; 3], intensity: 1.0, ambient: 0.0, _padding: [0.0; 3] }; MAX_SCENE_LIGHTS],
+            count: scene_lights.len().min(MAX_SCENE_LIGHTS) as u32,
+            _padding: [0; 3],
+        };
+        for (uniform, light) in lights.lights.iter_mut().zip(scene_lights) {
+            uniform.position = light.position;
+            uniform.color = color::srgb_to_linear_rgb(light.color);
+        }
+        let lights_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Scene Lights Buffer"),
+            contents: bytemuck::cast_slice(&[lights]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.lights_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: lights_buffer.as_entire_binding(),
+            }],
+            label: Some("Scene Lights Bind Group"),
+        })
+    }
+
+    // Draws `meshes` with their instance buffers in `style`. Pulled falls back to the vertex
+    // buffers without a pulled pipeline, or with a mesh too big to bind as a storage buffer.
+    fn draw<'a>(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        camera_bind_group: &wgpu::BindGroup,
+        lights_bind_group: &wgpu::BindGroup,
+        style: ShapeStyle,
+        meshes: impl Iterator<Item = (&'a GpuMesh, &'a wgpu::Buffer, Range<u32>)> + Clone,
+    ) {
+        if let ShapeStyle::Pulled(device) = style
+            && let Some(pulled) = &self.pulled
+            && meshes.clone().all(|(mesh, _, _)| mesh.can_pull(device))
+        {
+            render_pass.set_pipeline(&pulled.pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, lights_bind_group, &[]);
+            render_pass.set_bind_group(2, &pulled.empty_bind_group, &[]);
+            for (mesh, instance_buffer, instances) in meshes {
+                mesh.draw_pulled(render_pass, device, &pulled.bind_group_layout, instance_buffer, instances);
+            }
+            return;
+        }
+        let ShapeStyle::Toon(toon) = style else {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, lights_bind_group, &[]);
+            for (mesh, instance_buffer, instances) in meshes {
+                mesh.draw(render_pass, instance_buffer, instances);
+            }
+            return;
+        };
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, toon, &[]);
+        for (mesh, instance_buffer, instances) in meshes.clone() {
+            mesh.draw(render_pass, instance_buffer, instances);
+        }
+        render_pass.set_pipeline(&self.toon_pipeline);
+        render_pass.set_bind_group(1, lights_bind_group, &[]);
+        render_pass.set_bind_group(2, toon, &[]);
+        for (mesh, instance_buffer, instances) in meshes {
+            mesh.draw(render_pass, instance_buffer, instances);
+        }
+    }
+}
+
+// Every shape of one kind. Its instances are sorted by LOD level, each level's buffers are bound
+// once for all of its instances.
+struct ShapeGroup {
+    // One per LOD level, finest first
+    meshes: Vec<Arc<GpuMesh>>,
+    // Indices into the description's shapes
+    shapes: Vec<usize>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    // The instances drawn with each of `meshes`, set by prepare
+    ranges: Vec<Range<u32>>,
+}
+
+// A generated scene uploaded to the GPU, owned by State
+pub struct ShapeScene {
+    pub description: SceneDescription,
+    // Only kinds the scene uses have a group
+    groups: Vec<ShapeGroup>,
+    lights_bind_group: wgpu::BindGroup,
+    elapsed: f32,
+    // Per shape of the description, the level it was drawn at last frame
+    levels: Vec<usize>,
+    lod_stats: LodStats,
+}
+
+impl ShapeScene {
+    pub fn new(device: &wgpu::Device, pipeline: &ShapePipeline, description: SceneDescription) -> Self {
+        let groups = ShapeKind::ALL
+            .iter()
+            .filter_map(|&kind| {
+                let shapes = description
+                    .shapes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, shape)| shape.kind == kind)
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                if shapes.is_empty() {
+                    return None;
+                }
+                let instance_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+                    label: debug_label!("{:?} Shape Instance Buffer", kind).as_deref(),
+                    size: (shapes.len() * std::mem::size_of::<ShapeInstanceRaw>()) as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                // Every level is built up front, switching never waits on an upload
+                Some(ShapeGroup {
+                    meshes: mesh_keys(kind).into_iter().map(|key| pipeline.meshes.get(device, key)).collect(),
+                    shapes,
+                    instance_buffer,
+                    ranges: Vec::new(),
+                })
+            })
+            .collect();
+
+        let lights_bind_group = pipeline.create_lights_bind_group(device, &description.lights);
+
+        Self {
+            levels: vec![0; description.shapes.len()],
+            description,
+            groups,
+            lights_bind_group,
+            elapsed: 0.0,
+            lod_stats: LodStats::default(),
+        }
+    }
+
+    // Advance every shape's spin, prepare uploads it
+    pub fn update(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
+    // Picks each shape's level for `view`, the finest everywhere without one, and uploads the
+    // spun transforms sorted by level. `show_levels` tints every shape by its level.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, view: Option<&LodView>, show_levels: bool) {
+        let mut stats = LodStats::default();
+        for group in &mut self.groups {
+            let has_levels = group.meshes.len() > 1;
+            let mut by_level = vec![Vec::new(); group.meshes.len()];
+            for &i in &group.shapes {
+                let shape = &self.description.shapes[i];
+                let level = match view.filter(|_| has_levels) {
+                    Some(view) => shape_lod::select(view.coverage(shape.position.into(), SPHERE_RADIUS * shape.scale), self.levels[i]),
+                    None => 0,
+                };
+                self.levels[i] = level;
+                let rotation = Quaternion::from_axis_angle(Vector3::from(shape.rotation_axis), Deg(shape.rotation_speed * self.elapsed));
+                let tint = lod_tint(shape.tint, has_levels.then_some(level), show_levels);
+                by_level[level].push(ShapeInstanceRaw::new(shape.position, rotation, shape.scale, tint));
+            }
+            group.ranges.clear();
+            let mut start = 0;
+            for (level, instances) in by_level.iter().enumerate() {
+                let count = instances.len() as u32;
+                group.ranges.push(start..start + count);
+                start += count;
+                if has_levels {
+                    stats.add(level, count, group.meshes[level].triangles(), group.meshes[0].triangles());
+                }
+            }
+            queue.write_buffer(&group.instance_buffer, 0, bytemuck::cast_slice(&by_level.concat()));
+        }
+        self.lod_stats = stats;
+    }
+
+    // Of the shapes with levels, as of the last prepare
+    pub fn lod_stats(&self) -> LodStats {
+        self.lod_stats
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, pipeline: &ShapePipeline, camera_bind_group: &wgpu::BindGroup, style: ShapeStyle) {
+        let meshes = self
+            .groups
+            .iter()
+            .flat_map(|group| group.meshes.iter().zip(&group.ranges).map(|(mesh, range)| (&**mesh, &*group.instance_buffer, range.clone())));
+        pipeline.draw(render_pass, camera_bind_group, &self.lights_bind_group, style, meshes);
+    }
+}
+
+// One shape whose mesh is replaced at runtime, owned by State for the SDF demo and by the grass
+// field for its ground
+pub struct DynamicShape {
+    label: String,
+    // Owned rather than shared, its geometry changes at runtime. One per LOD level, finest first,
+    // only set_lod_meshes gives it more than one.
+    meshes: Vec<GpuMesh>,
+    level: usize,
+    // World space, around the finest level. Only known with levels.
+    bounds: Option<Sphere>,
+    position: [f32; 3],
+    tint: [f32; 3],
+    instance_buffer: Tracked<wgpu::Buffer>,
+    lights_bind_group: wgpu::BindGroup,
+}
+
+impl DynamicShape {
+    pub fn new(device: &wgpu::Device, pipeline: &ShapePipeline, label: &str, position: [f32; 3], tint: [f32; 3]) -> Self {
+        let instance = ShapeInstanceRaw::new(position, Quaternion::new(1.0, 0.0, 0.0, 0.0), 1.0, tint);
+        let lights = [GeneratedLight {
+            position: [position[0] + 3.0, position[1] + 4.0, position[2] + 3.0],
+            color: [1.0, 1.0, 1.0],
+        }];
+        Self {
+            label: label.to_string(),
+            meshes: vec![GpuMesh::from_geometry(device, label, &[], &[])],
+            level: 0,
+            bounds: None,
+            position,
+            tint,
+            instance_buffer: gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: debug_label!("{} Instance Buffer", label).as_deref(),
+                contents: bytemuck::cast_slice(&[instance]),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }),
+            lights_bind_group: pipeline.create_lights_bind_group(device, &lights),
+        }
+    }
+
+    // A single level, replacing the first in place
+    pub fn set_mesh(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[Vertex], indices: &[u32]) {
+        self.meshes.truncate(1);
+        self.meshes[0].replace(device, queue, &self.label, vertices, indices);
+        self.level = 0;
+        self.bounds = None;
+    }
+
+    // Every level at once, finest first, uploaded into buffers of their own
+    pub fn set_lod_meshes(&mut self, device: &wgpu::Device, levels: &[(Vec<Vertex>, Vec<u32>)]) {
+        self.meshes = levels
+            .iter()
+            .enumerate()
+            .map(|(level, (vertices, indices))| GpuMesh::from_geometry(device, &format!("{} LOD {}", self.label, level), vertices, indices))
+            .collect();
+        self.level = self.level.min(self.meshes.len().saturating_sub(1));
+        let offset = Vector3::from(self.position);
+        self.bounds = levels
+            .first()
+            .and_then(|(vertices, _)| Aabb::from_points(vertices.iter().map(|vertex| Vector3::from(vertex.position) + offset)))
+            .map(|bounds| instance_cull::bounding_sphere(&bounds));
+    }
+
+    // Like ShapeScene::prepare for the one shape, stats are empty without levels
+    pub fn prepare(&mut self, queue: &wgpu::Queue, view: Option<&LodView>, show_levels: bool) -> LodStats {
+        let has_levels = self.meshes.len() > 1;
+        self.level = match (view, self.bounds) {
+            (Some(view), Some(bounds)) if has_levels => shape_lod::select(view.coverage(bounds.center, bounds.radius), self.level).min(self.meshes.len() - 1),
+            _ => 0,
+        };
+        let tint = lod_tint(self.tint, has_levels.then_some(self.level), show_levels);
+        let instance = ShapeInstanceRaw::new(self.position, Quaternion::new(1.0, 0.0, 0.0, 0.0), 1.0, tint);
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::bytes_of(&instance));
+        let mut stats = LodStats::default();
+        if has_levels {
+            stats.add(self.level, 1, self.meshes[self.level].triangles(), self.meshes[0].triangles());
+        }
+        stats
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, pipeline: &ShapePipeline, camera_bind_group: &wgpu::BindGroup, style: ShapeStyle) {
+        let meshes = std::iter::once((&self.meshes[self.level], &*self.instance_buffer, 0..1));
+        pipeline.draw(render_pass, camera_bind_group, &self.lights_bind_group, style, meshes);
+    }
+}
@@ -1,224 +1,763 @@
-// /*
-// Purpose: Stores reusable geometry definitions
-// Responsibilities:
-//     - Constant arrays for simple shapes (TRIANGLE_VERTICES, SQUARE_VERTICES)
-//     - Functions like create_circle(radius, segments, color) for procedural geometry
-//     - ex: lego bricks
-// */
-
-// use crate::vertex::Vertex;
-
-// pub fn create_plane() -> (Vec<Vertex>, Vec<u16>) {
-//     let mut plane_vertices = vec![
-//         // Bottom Left
-//         Vertex { position: [-5.0, 0.0, -5.0], normal: [0.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [0.3, 0.3, 0.3] },
-//         // Bottom Right
-//         Vertex { position: [5.0, 0.0, -5.0], normal: [0.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [0.3, 0.3, 0.3] },
-//         // Top Right
-//         Vertex { position: [5.0, 0.0, 5.0], normal: [0.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [0.3, 0.3, 0.3] },
-//         // Top Left
-//         Vertex { position: [-5.0, 0.0, 5.0], normal: [0.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [0.3, 0.3, 0.3] },
-//     ];
-
-//     let plane_indices = vec![
-//         0, 1, 2, // first triangle
-//         0, 2, 3, // second triangle
-//     ];
-
-//     Vertex::compute_normals(&mut plane_vertices, &plane_indices);
-
-//     (plane_vertices, plane_indices)
-// }
-
-
-// pub fn create_pyramid() -> (Vec<Vertex>, Vec<u16>) {
-//     let mut vertices = vec![
-//         // Base (y = 0, facing downward - normal = (0, -1, 0))
-//         Vertex { position: [-0.5, 0.0, -0.5], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
-//         Vertex { position: [ 0.5, 0.0, -0.5], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
-//         Vertex { position: [ 0.5, 0.0,  0.5], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
-//         Vertex { position: [-0.5, 0.0,  0.5], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
-
-//         // Front face (apex + front base edge) -> normal points forward
-//         Vertex { position: [0.0, 1.0, 0.0], normal: [0.0, 0.5, -0.866], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] }, // apex
-//         Vertex { position: [-0.5, 0.0, -0.5], normal: [0.0, 0.5, -0.866], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
-//         Vertex { position: [ 0.5, 0.0, -0.5], normal: [0.0, 0.5, -0.866], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
-
-//         // Right face
-//         Vertex { position: [0.0, 1.0, 0.0], normal: [0.866, 0.5, 0.0], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
-//         Vertex { position: [0.5, 0.0, -0.5], normal: [0.866, 0.5, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
-//         Vertex { position: [0.5, 0.0,  0.5], normal: [0.866, 0.5, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
-
-//         // Back face
-//         Vertex { position: [0.0, 1.0, 0.0], normal: [0.0, 0.5, 0.866], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
-//         Vertex { position: [0.5, 0.0, 0.5], normal: [0.0, 0.5, 0.866], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
-//         Vertex { position: [-0.5, 0.0, 0.5], normal: [0.0, 0.5, 0.866], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
-
-//         // Left face
-//         Vertex { position: [0.0, 1.0, 0.0], normal: [-0.866, 0.5, 0.0], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
-//         Vertex { position: [-0.5, 0.0, 0.5], normal: [-0.866, 0.5, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
-//         Vertex { position: [-0.5, 0.0, -0.5], normal: [-0.866, 0.5, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
-//     ];
-
-//     let indices: Vec<u16> = vec![
-//         // Base
-//         0, 1, 2,
-//         0, 2, 3,
-
-//         // Sides
-//         4, 5, 6,   // front
-//         7, 8, 9,   // right
-//         10, 11, 12, // back
-//         13, 14, 15, // left
-//     ];
-
-//     Vertex::compute_normals(&mut vertices, &indices);
-
-//     (vertices, indices)
-// }
-
-// pub fn create_cube() -> (Vec<Vertex>, Vec<u16>) {
-//     let mut vertices = vec![
-//         // Front face (+Z)
-//         Vertex { position: [-0.5, -0.5,  0.5], color: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, 1.0] },
-//         Vertex { position: [ 0.5, -0.5,  0.5], color: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, 1.0] },
-//         Vertex { position: [ 0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, 1.0] },
-//         Vertex { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, 1.0] },
-
-//         // Back face (-Z)
-//         Vertex { position: [-0.5, -0.5, -0.5], color: [1.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, -1.0] },
-//         Vertex { position: [ 0.5, -0.5, -0.5], color: [0.0, 1.0, 1.0], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, -1.0] },
-//         Vertex { position: [ 0.5,  0.5, -0.5], color: [0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, -1.0] },
-//         Vertex { position: [-0.5,  0.5, -0.5], color: [1.0, 0.5, 0.0], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, -1.0] },
-
-//         // Left face (-X)
-//         Vertex { position: [-0.5, -0.5, -0.5], color: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], normal: [-1.0, 0.0, 0.0] },
-//         Vertex { position: [-0.5, -0.5,  0.5], color: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], normal: [-1.0, 0.0, 0.0] },
-//         Vertex { position: [-0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], normal: [-1.0, 0.0, 0.0] },
-//         Vertex { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [-1.0, 0.0, 0.0] },
-
-//         // Right face (+X)
-//         Vertex { position: [ 0.5, -0.5, -0.5], color: [1.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [1.0, 0.0, 0.0] },
-//         Vertex { position: [ 0.5, -0.5,  0.5], color: [0.0, 1.0, 1.0], tex_coords: [0.0, 0.0], normal: [1.0, 0.0, 0.0] },
-//         Vertex { position: [ 0.5,  0.5,  0.5], color: [0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], normal: [1.0, 0.0, 0.0] },
-//         Vertex { position: [ 0.5,  0.5, -0.5], color: [1.0, 0.5, 0.0], tex_coords: [1.0, 1.0], normal: [1.0, 0.0, 0.0] },
-
-//         // Top face (+Y)
-//         Vertex { position: [-0.5,  0.5, -0.5], color: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], normal: [0.0, 1.0, 0.0] },
-//         Vertex { position: [-0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, 1.0, 0.0] },
-//         Vertex { position: [ 0.5,  0.5,  0.5], color: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], normal: [0.0, 1.0, 0.0] },
-//         Vertex { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, 1.0, 0.0] },
-
-//         // Bottom face (-Y)
-//         Vertex { position: [-0.5, -0.5, -0.5], color: [0.0, 1.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, -1.0, 0.0] },
-//         Vertex { position: [-0.5, -0.5,  0.5], color: [1.0, 0.0, 1.0], tex_coords: [0.0, 0.0], normal: [0.0, -1.0, 0.0] },
-//         Vertex { position: [ 0.5, -0.5,  0.5], color: [1.0, 0.5, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, -1.0, 0.0] },
-//         Vertex { position: [ 0.5, -0.5, -0.5], color: [0.5, 0.5, 0.5], tex_coords: [1.0, 1.0], normal: [0.0, -1.0, 0.0] },
-//     ];
-
-//     let indices = vec![
-//         0, 1, 2, 0, 2, 3,    // front
-//         4, 5, 6, 4, 6, 7,    // back
-//         8, 9, 10, 8, 10, 11, // left
-//         12, 13, 14, 12, 14, 15, // right
-//         16, 17, 18, 16, 18, 19, // top
-//         20, 21, 22, 20, 22, 23, // bottom
-//     ];
-
-//     Vertex::compute_normals(&mut vertices, &indices);
-
-//     (vertices, indices)
-// }
-
-// pub fn create_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<Vertex>, Vec<u16>) {
+/*
+Purpose: Stores reusable geometry definitions
+Responsibilities:
+    - Constant arrays for simple shapes (TRIANGLE_VERTICES, SQUARE_VERTICES)
+    - Functions like create_circle(radius, segments, color) for procedural geometry
+    - Turn a signed distance function into a mesh with marching cubes (mesh_from_sdf)
+    - Extrude a flat ribbon, a road, along a spline through control points (create_ribbon)
+    - ex: lego bricks
+*/
+
+use std::sync::OnceLock;
+
+use cgmath::InnerSpace;
+
+use crate::{math::{Aabb, Vec3}, vertex::{Vertex, DEFAULT_SMOOTHING_ANGLE}};
+
+
+pub fn create_plane() -> (Vec<Vertex>, Vec<u32>) {
+    let mut plane_vertices = vec![
+        // Bottom Left
+        Vertex { position: [-5.0, 0.0, -5.0], normal: [0.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [0.3, 0.3, 0.3] },
+        // Bottom Right
+        Vertex { position: [5.0, 0.0, -5.0], normal: [0.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [0.3, 0.3, 0.3] },
+        // Top Right
+        Vertex { position: [5.0, 0.0, 5.0], normal: [0.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [0.3, 0.3, 0.3] },
+        // Top Left
+        Vertex { position: [-5.0, 0.0, 5.0], normal: [0.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [0.3, 0.3, 0.3] },
+    ];
+
+    let mut plane_indices = vec![
+        0, 1, 2, // first triangle
+        0, 2, 3, // second triangle
+    ];
+
+    Vertex::compute_normals_with_angle(&mut plane_vertices, &mut plane_indices, DEFAULT_SMOOTHING_ANGLE);
+
+    (plane_vertices, plane_indices)
+}
+
+
+pub fn create_pyramid() -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = vec![
+        // Base (y = 0, facing downward - normal = (0, -1, 0))
+        Vertex { position: [-0.5, 0.0, -0.5], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
+        Vertex { position: [ 0.5, 0.0, -0.5], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
+        Vertex { position: [ 0.5, 0.0,  0.5], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
+        Vertex { position: [-0.5, 0.0,  0.5], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
+
+        // Front face (apex + front base edge) -> normal points forward
+        Vertex { position: [0.0, 1.0, 0.0], normal: [0.0, 0.5, -0.866], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] }, // apex
+        Vertex { position: [-0.5, 0.0, -0.5], normal: [0.0, 0.5, -0.866], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
+        Vertex { position: [ 0.5, 0.0, -0.5], normal: [0.0, 0.5, -0.866], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
+
+        // Right face
+        Vertex { position: [0.0, 1.0, 0.0], normal: [0.866, 0.5, 0.0], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
+        Vertex { position: [0.5, 0.0, -0.5], normal: [0.866, 0.5, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
+        Vertex { position: [0.5, 0.0,  0.5], normal: [0.866, 0.5, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
+
+        // Back face
+        Vertex { position: [0.0, 1.0, 0.0], normal: [0.0, 0.5, 0.866], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
+        Vertex { position: [0.5, 0.0, 0.5], normal: [0.0, 0.5, 0.866], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
+        Vertex { position: [-0.5, 0.0, 0.5], normal: [0.0, 0.5, 0.866], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
+
+        // Left face
+        Vertex { position: [0.0, 1.0, 0.0], normal: [-0.866, 0.5, 0.0], tex_coords: [0.5, 1.0], color: [1.0, 1.0, 1.0] },
+        Vertex { position: [-0.5, 0.0, 0.5], normal: [-0.866, 0.5, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
+        Vertex { position: [-0.5, 0.0, -0.5], normal: [-0.866, 0.5, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
+    ];
+
+    let mut indices: Vec<u32> = vec![
+        // Base
+        0, 1, 2,
+        0, 2, 3,
+
+        // Sides
+        4, 5, 6,   // front
+        7, 8, 9,   // right
+        10, 11, 12, // back
+        13, 14, 15, // left
+    ];
+
+    Vertex::compute_normals_with_angle(&mut vertices, &mut indices, DEFAULT_SMOOTHING_ANGLE);
+
+    (vertices, indices)
+}
+
+pub fn create_cube() -> (Vec<Vertex>, Vec<u32>) {
+    create_cube_atlas([UvRect::FULL; 6])
+}
+
+// A rectangle of texture coordinates, min is the corner the face's (0, 0) lands on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    pub const FULL: UvRect = UvRect { min: [0.0, 0.0], max: [1.0, 1.0] };
+
+    // Where `uv` of the unit square lands in the rectangle
+    pub fn map(&self, uv: [f32; 2]) -> [f32; 2] {
+        [self.min[0] + uv[0] * (self.max[0] - self.min[0]), self.min[1] + uv[1] * (self.max[1] - self.min[1])]
+    }
+}
+
+// create_cube with each face showing its own part of one texture, e.g. the six sides of a
+// crate or a die. Same 24 vertices, normals and winding. Faces go front (+Z), back (-Z),
+// left (-X), right (+X), top (+Y), bottom (-Y).
+pub fn create_cube_atlas(face_uvs: [UvRect; 6]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = vec![
+        // Front face (+Z)
+        Vertex { position: [-0.5, -0.5,  0.5], color: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, 1.0] },
+        Vertex { position: [ 0.5, -0.5,  0.5], color: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, 1.0] },
+        Vertex { position: [ 0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, 1.0] },
+        Vertex { position: [-0.5,  0.5,  0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, 1.0] },
+
+        // Back face (-Z)
+        Vertex { position: [-0.5, -0.5, -0.5], color: [1.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, 0.0, -1.0] },
+        Vertex { position: [ 0.5, -0.5, -0.5], color: [0.0, 1.0, 1.0], tex_coords: [0.0, 0.0], normal: [0.0, 0.0, -1.0] },
+        Vertex { position: [ 0.5,  0.5, -0.5], color: [0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], normal: [0.0, 0.0, -1.0] },
+        Vertex { position: [-0.5,  0.5, -0.5], color: [1.0, 0.5, 0.0], tex_coords: [1.0, 1.0], normal: [0.0, 0.0, -1.0] },
+
+        // Left face (-X)
+        Vertex { position: [-0.5, -0.5, -0.5], color: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], normal: [-1.0, 0.0, 0.0] },
+        Vertex { position: [-0.5, -0.5,  0.5], color: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], normal: [-1.0, 0.0, 0.0] },
+        Vertex { position: [-0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], normal: [-1.0, 0.0, 0.0] },
+        Vertex { position: [-0.5,  0.5, -0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [-1.0, 0.0, 0.0] },
+
+        // Right face (+X)
+        Vertex { position: [ 0.5, -0.5, -0.5], color: [1.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [1.0, 0.0, 0.0] },
+        Vertex { position: [ 0.5, -0.5,  0.5], color: [0.0, 1.0, 1.0], tex_coords: [0.0, 0.0], normal: [1.0, 0.0, 0.0] },
+        Vertex { position: [ 0.5,  0.5,  0.5], color: [0.5, 0.5, 0.5], tex_coords: [0.0, 1.0], normal: [1.0, 0.0, 0.0] },
+        Vertex { position: [ 0.5,  0.5, -0.5], color: [1.0, 0.5, 0.0], tex_coords: [1.0, 1.0], normal: [1.0, 0.0, 0.0] },
+
+        // Top face (+Y)
+        Vertex { position: [-0.5,  0.5, -0.5], color: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], normal: [0.0, 1.0, 0.0] },
+        Vertex { position: [-0.5,  0.5,  0.5], color: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, 1.0, 0.0] },
+        Vertex { position: [ 0.5,  0.5,  0.5], color: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], normal: [0.0, 1.0, 0.0] },
+        Vertex { position: [ 0.5,  0.5, -0.5], color: [1.0, 1.0, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, 1.0, 0.0] },
+
+        // Bottom face (-Y)
+        Vertex { position: [-0.5, -0.5, -0.5], color: [0.0, 1.0, 1.0], tex_coords: [1.0, 0.0], normal: [0.0, -1.0, 0.0] },
+        Vertex { position: [-0.5, -0.5,  0.5], color: [1.0, 0.0, 1.0], tex_coords: [0.0, 0.0], normal: [0.0, -1.0, 0.0] },
+        Vertex { position: [ 0.5, -0.5,  0.5], color: [1.0, 0.5, 0.0], tex_coords: [0.0, 1.0], normal: [0.0, -1.0, 0.0] },
+        Vertex { position: [ 0.5, -0.5, -0.5], color: [0.5, 0.5, 0.5], tex_coords: [1.0, 1.0], normal: [0.0, -1.0, 0.0] },
+    ];
+    for (index, vertex) in vertices.iter_mut().enumerate() {
+        vertex.tex_coords = face_uvs[index / 4].map(vertex.tex_coords);
+    }
+
+    let mut indices = vec![
+        0, 1, 2, 0, 2, 3,    // front
+        4, 5, 6, 4, 6, 7,    // back
+        8, 9, 10, 8, 10, 11, // left
+        12, 13, 14, 12, 14, 15, // right
+        16, 17, 18, 16, 18, 19, // top
+        20, 21, 22, 20, 22, 23, // bottom
+    ];
+
+    Vertex::compute_normals_with_angle(&mut vertices, &mut indices, DEFAULT_SMOOTHING_ANGLE);
+
+    (vertices, indices)
+}
+
+// create_cube_atlas over a sprite sheet of `cols` x `rows` equal cells, numbered left to right
+// then top to bottom. `face_indices` picks each face's cell in CUBE_FACES order, cells past
+// the end of the sheet wrap around.
+pub fn create_cube_grid_atlas(cols: u32, rows: u32, face_indices: [u32; 6]) -> (Vec<Vertex>, Vec<u32>) {
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let face_uvs = face_indices.map(|cell| {
+        let cell = cell % (cols * rows);
+        let (col, row) = ((cell % cols) as f32, (cell / cols) as f32);
+        let (width, height) = (1.0 / cols as f32, 1.0 / rows as f32);
+        UvRect { min: [col * width, row * height], max: [(col + 1.0) * width, (row + 1.0) * height] }
+    });
+    create_cube_atlas(face_uvs)
+}
+
+pub fn create_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    // vertices
+    for i in 0..=stacks {
+        let stack_angle = std::f32::consts::PI / 2.0 - i as f32 * std::f32::consts::PI / stacks as f32; // from pi/2 to -pi/2
+        let xy = radius * stack_angle.cos();
+        let z = radius *stack_angle.sin();
+
+        for j in 0..=sectors {
+            let sector_angle = j as f32 * 2.0 * std::f32::consts::PI / sectors as f32; // 0 to 2pi
+
+            let x = xy * sector_angle.cos();
+            let y = xy * sector_angle.sin();
+
+            let nx = x / radius;
+            let ny = y / radius;
+            let nz = z / radius;
+
+            let u = j as f32 / sectors as f32;
+            let v = i as f32 / stacks as f32;
+
+            vertices.push(Vertex {
+                position: [x, y, z],
+                color: [0.5, 0.5, 0.5], // default white
+                tex_coords: [u, v],
+                normal: [nx, ny, nz],
+            });
+        }
+    }
+
+    // indices
+    for i in 0..stacks {
+        let k1 = i * (sectors + 1);
+        let k2 = k1 + sectors + 1;
+
+        for j in 0..sectors {
+            if i != 0 {
+                indices.push(k1 + j);
+                indices.push(k2 + j);
+                indices.push(k1 + j + 1);
+            }
+
+            if i != (stacks - 1) {
+                indices.push(k1 + j + 1);
+                indices.push(k2 + j);
+                indices.push(k2 + j + 1);
+            }
+        }
+    }
+
+    // A sphere has no hard edges, smooth across everything
+    Vertex::compute_normals(&mut vertices, &mut indices);
+
+    (vertices, indices)
+}
+
+// Standing on the Y axis: a cylinder `half_length` up and down from the origin, capped by two
+// hemispheres. `stacks` rings per cap. U runs around from +X towards +Z, V from top to bottom.
+pub fn create_capsule(radius: f32, half_length: f32, sectors: u32, stacks: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let half_height = half_length + radius;
+    // Top cap down to its equator, then the bottom cap from its equator, the cylinder is the band between
+    let rings: Vec<(f32, f32)> = (0..=stacks)
+        .map(|i| (std::f32::consts::FRAC_PI_2 * (1.0 - i as f32 / stacks as f32), half_length))
+        .chain((0..=stacks).map(|i| (-std::f32::consts::FRAC_PI_2 * i as f32 / stacks as f32, -half_length)))
+        .collect();
+    let mut vertices = Vec::new();
+    for &(latitude, center_y) in &rings {
+        let (ring_y, ring_radius) = latitude.sin_cos();
+        for j in 0..=sectors {
+            let u = j as f32 / sectors as f32;
+            let (sin, cos) = (u * std::f32::consts::TAU).sin_cos();
+            let normal = [ring_radius * cos, ring_y, ring_radius * sin];
+            let y = center_y + radius * ring_y;
+            vertices.push(Vertex {
+                position: [radius * normal[0], y, radius * normal[2]],
+                color: [0.5, 0.5, 0.5],
+                tex_coords: [u, 0.5 - y / (2.0 * half_height)],
+                normal,
+            });
+        }
+    }
+
+    // Counter-clockwise from outside, the rings at the poles have collapsed to a point
+    let mut indices = Vec::new();
+    let last_band = rings.len() as u32 - 2;
+    for i in 0..=last_band {
+        let k1 = i * (sectors + 1);
+        let k2 = k1 + sectors + 1;
+        for j in 0..sectors {
+            if i != 0 {
+                indices.extend([k1 + j, k1 + j + 1, k2 + j]);
+            }
+            if i != last_band {
+                indices.extend([k1 + j + 1, k2 + j + 1, k2 + j]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+
+// Squared distance under which two ribbon points are the same point
+const RIBBON_EPSILON: f32 = 1e-8;
+
+// A point on the centripetal Catmull-Rom span from p1 to p2, `t` from 0 to 1. Knots are spaced by
+// the square root of the chord lengths, so unevenly spaced points don't overshoot or loop.
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let knot = |a: Vec3, b: Vec3| (b - a).magnitude().sqrt().max(1e-4);
+    let t1 = knot(p0, p1);
+    let t2 = t1 + knot(p1, p2);
+    let t3 = t2 + knot(p2, p3);
+    let t = t1 + (t2 - t1) * t;
+    let blend = |a: Vec3, b: Vec3, start: f32, end: f32| a * ((end - t) / (end - start)) + b * ((t - start) / (end - start));
+    let a1 = blend(p0, p1, 0.0, t1);
+    let a2 = blend(p1, p2, t1, t2);
+    let a3 = blend(p2, p3, t2, t3);
+    blend(blend(a1, a2, 0.0, t2), blend(a2, a3, t1, t3), t1, t2)
+}
+
+// A flat strip `width` wide along a Catmull-Rom spline through `control_points`, a road on the
+// ground. The strip's up is carried along the curve by parallel transport (double reflection)
+// rather than crossed with a fixed up, so it never flips where the path turns vertical. When the
+// first and last points are the same the path is a closed loop: the twist transport builds up
+// around it is spread along the loop so its ends meet. U runs across from left to right, V along
+// the arc length times `uv_tiling`. Empty with fewer than two distinct points.
+pub fn create_ribbon(control_points: &[Vec3], width: f32, segments_per_span: u32, uv_tiling: f32) -> (Vec<Vertex>, Vec<u32>) {
+    // 1. Drop repeated points, they would make spans of no length
+    let mut points: Vec<Vec3> = Vec::with_capacity(control_points.len());
+    for &point in control_points {
+        if points.last().is_none_or(|last| (point - last).magnitude2() > RIBBON_EPSILON) {
+            points.push(point);
+        }
+    }
+    let closed = points.len() > 3 && (points[0] - points[points.len() - 1]).magnitude2() <= RIBBON_EPSILON;
+    if closed {
+        points.pop();
+    }
+    if points.len() < 2 {
+        return (Vec::new(), Vec::new());
+    }
+
+    // 2. Sample the spline. A loop wraps around for its neighbours, an open path's ends mirror the
+    // point next to them. The last sample repeats the first on a loop, V needs both ends.
+    let count = points.len();
+    let control = |i: isize| match i {
+        _ if closed => points[i.rem_euclid(count as isize) as usize],
+        -1 => points[0] * 2.0 - points[1],
+        i if i as usize == count => points[count - 1] * 2.0 - points[count - 2],
+        i => points[i as usize],
+    };
+    let spans = if closed { count } else { count - 1 };
+    let segments = segments_per_span.max(1);
+    let mut samples = Vec::with_capacity(spans * segments as usize + 1);
+    for span in 0..spans as isize {
+        let [p0, p1, p2, p3] = [control(span - 1), control(span), control(span + 1), control(span + 2)];
+        samples.extend((0..segments).map(|s| catmull_rom(p0, p1, p2, p3, s as f32 / segments as f32)));
+    }
+    samples.push(if closed { points[0] } else { points[count - 1] });
+
+    // 3. Tangents from the neighbouring samples, wrapping around a loop
+    let last = samples.len() - 1;
+    let neighbours = |i: usize| match i {
+        _ if closed => (samples[(i + last - 1) % last], samples[(i + 1) % last]),
+        0 => (samples[0], samples[1]),
+        i if i == last => (samples[last - 1], samples[last]),
+        i => (samples[i - 1], samples[i + 1]),
+    };
+    let mut tangents: Vec<Vec3> = Vec::with_capacity(samples.len());
+    for i in 0..samples.len() {
+        let (before, after) = neighbours(i);
+        let tangent = after - before;
+        let previous = tangents.last().copied().unwrap_or(Vec3::unit_z());
+        tangents.push(if tangent.magnitude2() > RIBBON_EPSILON { tangent.normalize() } else { previous });
+    }
+
+    // 4. Transport the up along the samples, starting from world up (or X for a path that starts
+    // straight up). Each step reflects the frame in the plane between the two samples, then in the
+    // plane between the reflected and the next tangent, the rotation minimizing frame of Wang et al.
+    let start = if tangents[0].y.abs() < 0.99 { Vec3::unit_y() } else { Vec3::unit_x() };
+    let mut ups = vec![(start - tangents[0] * start.dot(tangents[0])).normalize()];
+    let reflect = |v: Vec3, normal: Vec3| {
+        let length2 = normal.magnitude2();
+        if length2 > RIBBON_EPSILON { v - normal * (2.0 * normal.dot(v) / length2) } else { v }
+    };
+    for i in 0..last {
+        let step = samples[i + 1] - samples[i];
+        let (up, tangent) = (reflect(ups[i], step), reflect(tangents[i], step));
+        let up = reflect(up, tangents[i + 1] - tangent);
+        // Back onto the plane across the tangent, rounding drifts over many samples
+        let next = tangents[i + 1];
+        let up = up - next * up.dot(next);
+        ups.push(if up.magnitude2() > RIBBON_EPSILON { up.normalize() } else { ups[i] });
+    }
+
+    // Along the samples, for V and for spreading a loop's twist
+    let mut distances = Vec::with_capacity(samples.len());
+    let mut distance = 0.0;
+    for i in 0..samples.len() {
+        if i > 0 {
+            distance += (samples[i] - samples[i - 1]).magnitude();
+        }
+        distances.push(distance);
+    }
+    if closed && distance > 0.0 {
+        // How far the up came round from where it started, turned back a little at every sample
+        let axis = tangents[0];
+        let twist = axis.dot(ups[last].cross(ups[0])).atan2(ups[last].dot(ups[0]));
+        for i in 1..last {
+            let (sin, cos) = (twist * distances[i] / distance).sin_cos();
+            let up = ups[i];
+            ups[i] = up * cos + tangents[i].cross(up) * sin;
+        }
+        ups[last] = ups[0];
+        tangents[last] = tangents[0];
+    }
+
+    // 5. Two vertices per sample, counter-clockwise seen from the ribbon's up
+    let half_width = width * 0.5;
+    let mut vertices = Vec::with_capacity(samples.len() * 2);
+    for i in 0..samples.len() {
+        let right = tangents[i].cross(ups[i]) * half_width;
+        let v = distances[i] * uv_tiling;
+        for (position, u) in [(samples[i] - right, 0.0), (samples[i] + right, 1.0)] {
+            vertices.push(Vertex {
+                position: position.into(),
+                normal: ups[i].into(),
+                tex_coords: [u, v],
+                color: [0.5, 0.5, 0.5],
+            });
+        }
+    }
+    let mut indices = Vec::with_capacity(last * 6);
+    for i in 0..last as u32 {
+        let (left, right, next_left, next_right) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+        indices.extend([left, right, next_left, right, next_right, next_left]);
+    }
+    (vertices, indices)
+}
+
+
+// Corner i of a marching cubes cell sits at (i & 1, (i >> 1) & 1, (i >> 2) & 1).
+// Each edge goes from its lower corner along one axis.
+const CELL_EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7), // along x
+    (0, 2), (1, 3), (4, 6), (5, 7), // along y
+    (0, 4), (1, 5), (2, 6), (3, 7), // along z
+];
+
+// The corners of each cell face, in order around the face
+const CELL_FACES: [[usize; 4]; 6] = [
+    [0, 2, 6, 4], [1, 3, 7, 5], // x = 0, x = 1
+    [0, 1, 5, 4], [2, 3, 7, 6], // y = 0, y = 1
+    [0, 1, 3, 2], [4, 5, 7, 6], // z = 0, z = 1
+];
+
+fn cell_edge(a: usize, b: usize) -> usize {
+    CELL_EDGES
+        .iter()
+        .position(|&edge| edge == (a.min(b), a.max(b)))
+        .expect("corners share an edge")
+}
+
+// Triangles (as cell edges) for every combination of inside corners, built once instead of
+// typed out: the surface crosses each face in one or two segments, the segments join into
+// loops around the cell and each loop is fanned into triangles. A face with two diagonal inside
+// corners always keeps them apart, so neighbouring cells agree on the shared face and the mesh
+// has no cracks. Winding is fixed later from the SDF gradient.
+fn marching_cubes_cases() -> &'static [Vec<[usize; 3]>] {
+    static CASES: OnceLock<Vec<Vec<[usize; 3]>>> = OnceLock::new();
+    CASES.get_or_init(|| {
+        (0..256usize)
+            .map(|case| {
+                let inside = |corner: usize| case >> corner & 1 == 1;
+
+                // 1. Link the crossed edges of each face
+                let mut links = [[usize::MAX; 2]; 12];
+                let mut link = |a: usize, b: usize| {
+                    for (from, to) in [(a, b), (b, a)] {
+                        let slot = if links[from][0] == usize::MAX { 0 } else { 1 };
+                        links[from][slot] = to;
+                    }
+                };
+                for face in CELL_FACES {
+                    let crossed = (0..4)
+                        .filter(|&k| inside(face[k]) != inside(face[(k + 1) % 4]))
+                        .map(|k| cell_edge(face[k], face[(k + 1) % 4]))
+                        .collect::<Vec<_>>();
+                    match crossed.len() {
+                        2 => link(crossed[0], crossed[1]),
+                        // Ambiguous face, cut off each inside corner on its own
+                        4 => {
+                            for k in (0..4).filter(|&k| inside(face[k])) {
+                                let previous = face[(k + 3) % 4];
+                                let next = face[(k + 1) % 4];
+                                link(cell_edge(previous, face[k]), cell_edge(face[k], next));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                // 2. Walk the loops and fan them into triangles
+                let mut triangles = Vec::new();
+                let mut visited = [false; 12];
+                for start in 0..12 {
+                    if visited[start] || links[start][0] == usize::MAX {
+                        continue;
+                    }
+                    let mut polygon = vec![start];
+                    visited[start] = true;
+                    let (mut previous, mut current) = (start, links[start][0]);
+                    while current != start {
+                        polygon.push(current);
+                        visited[current] = true;
+                        let next = if links[current][0] == previous { links[current][1] } else { links[current][0] };
+                        (previous, current) = (current, next);
+                    }
+                    for i in 1..polygon.len() - 1 {
+                        triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                }
+                triangles
+            })
+            .collect()
+    })
+}
+
+// Mesh the surface where `sdf` is zero inside `bounds`, sampled on a grid of `resolution`
+// cells per axis. Negative distances are inside. Normals come from the SDF gradient and UVs are
+// a planar projection onto the box's XZ plane.
+pub fn mesh_from_sdf(sdf: impl Fn(Vec3) -> f32, bounds: Aabb, resolution: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let cells = resolution.max(1) as usize;
+    let points = cells + 1;
+    let size = bounds.size();
+    let step = size / cells as f32;
+    let point_index = |x: usize, y: usize, z: usize| (z * points + y) * points + x;
+    let point_position = |index: usize| {
+        let (x, y, z) = (index % points, index / points % points, index / (points * points));
+        bounds.min + Vec3::new(x as f32 * step.x, y as f32 * step.y, z as f32 * step.z)
+    };
+
+    // 1. Sample the SDF at every grid point
+    let samples = (0..points * points * points).map(|index| sdf(point_position(index))).collect::<Vec<_>>();
+
+    // Central differences, small next to a cell so thin features keep their normals
+    let h = step.x.min(step.y).min(step.z) * 0.1;
+    let gradient = |p: Vec3| {
+        let dx = sdf(p + Vec3::new(h, 0.0, 0.0)) - sdf(p - Vec3::new(h, 0.0, 0.0));
+        let dy = sdf(p + Vec3::new(0.0, h, 0.0)) - sdf(p - Vec3::new(0.0, h, 0.0));
+        let dz = sdf(p + Vec3::new(0.0, 0.0, h)) - sdf(p - Vec3::new(0.0, 0.0, h));
+        let g = Vec3::new(dx, dy, dz);
+        if g.magnitude2() > f32::EPSILON * f32::EPSILON { g.normalize() } else { Vec3::unit_y() }
+    };
+
+    // 2. March the cells. Vertices on a grid edge are shared by the cells around it, keyed by
+    // the edge's lower grid point and axis. A crossing exactly on a grid point is keyed by the
+    // point (slot 3) so the edges meeting there share one vertex.
+    let cases = marching_cubes_cases();
+    let mut edge_vertices = vec![u32::MAX; samples.len() * 4];
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for z in 0..cells {
+        for y in 0..cells {
+            for x in 0..cells {
+                let corners: [usize; 8] = std::array::from_fn(|c| point_index(x + (c & 1), y + (c >> 1 & 1), z + (c >> 2 & 1)));
+                let case = (0..8).filter(|&c| samples[corners[c]] < 0.0).fold(0, |case, c| case | 1 << c);
+                for triangle in &cases[case] {
+                    let ids = triangle.map(|edge| {
+                        let (a, b) = CELL_EDGES[edge];
+                        let (da, db) = (samples[corners[a]], samples[corners[b]]);
+                        let t = da / (da - db);
+                        let key = match t {
+                            t if t <= 0.0 => corners[a] * 4 + 3,
+                            t if t >= 1.0 => corners[b] * 4 + 3,
+                            _ => corners[a] * 4 + (a ^ b).trailing_zeros() as usize,
+                        };
+                        if edge_vertices[key] == u32::MAX {
+                            let (pa, pb) = (point_position(corners[a]), point_position(corners[b]));
+                            let position = pa + (pb - pa) * t.clamp(0.0, 1.0);
+                            let uv = [(position.x - bounds.min.x) / size.x, (position.z - bounds.min.z) / size.z];
+                            edge_vertices[key] = vertices.len() as u32;
+                            vertices.push(Vertex {
+                                position: position.into(),
+                                color: [0.5, 0.5, 0.5],
+                                tex_coords: uv,
+                                normal: gradient(position).into(),
+                            });
+                        }
+                        edge_vertices[key]
+                    });
+
+                    // 3. Drop degenerate triangles, the ones whose corners collapsed onto a point or a line
+                    let [p0, p1, p2] = ids.map(|id| Vec3::from(vertices[id as usize].position));
+                    let face_normal = (p1 - p0).cross(p2 - p0);
+                    let longest2 = (p1 - p0).magnitude2().max((p2 - p1).magnitude2()).max((p0 - p2).magnitude2());
+                    if ids[0] == ids[1] || ids[1] == ids[2] || ids[0] == ids[2] || face_normal.magnitude2() <= (f32::EPSILON * longest2).powi(2) {
+                        continue;
+                    }
+                    // Counter-clockwise seen from outside
+                    let outward = ids.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &id| sum + Vec3::from(vertices[id as usize].normal));
+                    if face_normal.dot(outward) < 0.0 {
+                        indices.extend_from_slice(&[ids[0], ids[2], ids[1]]);
+                    } else {
+                        indices.extend_from_slice(&ids);
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+
+// pub fn create_circle(radius: f32, segments: usize, color: [f32; 3], tex_coords: [f32; 2]) -> (Vec<Vertex>, Vec<u32>) {
+//     // Imagine a pizza: one vertex at the center, then a ring of vertices around the edge
+//     // Each slice (center + two edge points) is one triangle
+//     // Put enough slices together -> looks like a circle
 //     let mut vertices = Vec::new();
 //     let mut indices = Vec::new();
 
-//     // vertices
-//     for i in 0..=stacks {
-//         let stack_angle = std::f32::consts::PI / 2.0 - i as f32 * std::f32::consts::PI / stacks as f32; // from pi/2 to -pi/2
-//         let xy = radius * stack_angle.cos();
-//         let z = radius *stack_angle.sin();
-
-//         for j in 0..=sectors {
-//             let sector_angle = j as f32 * 2.0 * std::f32::consts::PI / sectors as f32; // 0 to 2pi
-
-//             let x = xy * sector_angle.cos();
-//             let y = xy * sector_angle.sin();
-
-//             let nx = x / radius;
-//             let ny = y / radius;
-//             let nz = z / radius;
-
-//             let u = j as f32 / sectors as f32;
-//             let v = i as f32 / stacks as f32;
-
-//             vertices.push(Vertex {
-//                 position: [x, y, z],
-//                 color: [0.5, 0.5, 0.5], // default white
-//                 tex_coords: [u, v],
-//                 normal: [nx, ny, nz],
-//             });
-//         }
-//     }
-
-//     // indices
-//     for i in 0..stacks {
-//         let k1 = i * (sectors + 1);
-//         let k2 = k1 + sectors + 1;
-
-//         for j in 0..sectors {
-//             if i != 0 {
-//                 indices.push((k1 + j) as u16);
-//                 indices.push((k2 + j) as u16);
-//                 indices.push((k1 + j + 1) as u16);
-//             }
-
-//             if i != (stacks - 1) {
-//                 indices.push((k1 + j + 1) as u16);
-//                 indices.push((k2 + j) as u16);
-//                 indices.push((k2 + j + 1) as u16);
-//             }
+//     // Center vertex
+//     vertices.push(Vertex {
+//         position: [0.0, 0.0, 0.0],
+//         color,
+//         tex_coords,
+//     });
+
+//     // Create edge vertices around the circle
+//     for i in 0..=segments {
+//         let theta = (i as f32 / segments as f32) * std::f32::consts::TAU; // TAU = 2pi
+//         let x = radius * theta.cos();
+//         let y = radius * theta.sin();
+
+//         vertices.push(Vertex {
+//             position: [x,y, 0.0],
+//             color,
+//             tex_coords,
+//         });
+
+//         // Add indices to form triangles (skip first edge)
+//         if i > 0 {
+//             indices.push(0); // center
+//             indices.push(i as u32);
+//             indices.push((i as u32) + 1);
 //         }
 //     }
 
 //     (vertices, indices)
 // }
 
-
-// // pub fn create_circle(radius: f32, segments: usize, color: [f32; 3], tex_coords: [f32; 2]) -> (Vec<Vertex>, Vec<u16>) {
-// //     // Imagine a pizza: one vertex at the center, then a ring of vertices around the edge
-// //     // Each slice (center + two edge points) is one triangle
-// //     // Put enough slices together -> looks like a circle
-// //     let mut vertices = Vec::new();
-// //     let mut indices = Vec::new();
-
-// //     // Center vertex
-// //     vertices.push(Vertex {
-// //         position: [0.0, 0.0, 0.0],
-// //         color,
-// //         tex_coords,
-// //     });
-
-// //     // Create edge vertices around the circle
-// //     for i in 0..=segments {
-// //         let theta = (i as f32 / segments as f32) * std::f32::consts::TAU; // TAU = 2pi
-// //         let x = radius * theta.cos();
-// //         let y = radius * theta.sin();
-
-// //         vertices.push(Vertex {
-// //             position: [x,y, 0.0],
-// //             color,
-// //             tex_coords,
-// //         });
-
-// //         // Add indices to form triangles (skip first edge)
-// //         if i > 0 {
-// //             indices.push(0); // center
-// //             indices.push(i as u16);
-// //             indices.push((i as u16) + 1);
-// //         }
-// //     }
-
-// //     (vertices, indices)
-// // }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_cube_face_stays_in_its_atlas_cell() {
+        let face_uvs: [UvRect; 6] = std::array::from_fn(|face| {
+            let (col, row) = ((face % 3) as f32, (face / 3) as f32);
+            UvRect { min: [col / 3.0, row / 2.0], max: [(col + 1.0) / 3.0, (row + 1.0) / 2.0] }
+        });
+        let (vertices, indices) = create_cube_atlas(face_uvs);
+        assert_eq!((vertices.len(), indices.len()), (24, 36));
+
+        // Four vertices a face, in face order
+        for (face, (rect, face_vertices)) in face_uvs.iter().zip(vertices.chunks_exact(4)).enumerate() {
+            for vertex in face_vertices {
+                let [u, v] = vertex.tex_coords;
+                assert!((rect.min[0]..=rect.max[0]).contains(&u) && (rect.min[1]..=rect.max[1]).contains(&v), "face {}: {:?} outside {:?}", face, vertex.tex_coords, rect);
+            }
+            // The whole cell, not a sliver of it
+            for corner in [rect.min, [rect.max[0], rect.min[1]], rect.max, [rect.min[0], rect.max[1]]] {
+                assert!(face_vertices.iter().any(|vertex| vertex.tex_coords == corner), "face {} misses {:?}", face, corner);
+            }
+        }
+    }
+
+    fn position(vertex: &Vertex) -> Vec3 {
+        vertex.position.into()
+    }
+
+    fn normal(vertex: &Vertex) -> Vec3 {
+        vertex.normal.into()
+    }
+
+    // Each triangle winds counter-clockwise seen from its vertices' normal
+    fn assert_faces_its_normals(vertices: &[Vertex], indices: &[u32]) {
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| &vertices[triangle[corner] as usize]);
+            let facing = (position(b) - position(a)).cross(position(c) - position(a));
+            for vertex in [a, b, c] {
+                assert!(facing.dot(normal(vertex)) > 0.0, "{:?} faces away from {:?}", triangle, vertex.normal);
+            }
+        }
+    }
+
+    #[test]
+    fn ribbon_without_two_distinct_points_is_empty() {
+        let point = Vec3::new(1.0, 0.0, 2.0);
+        assert_eq!(create_ribbon(&[], 1.0, 8, 1.0), (Vec::new(), Vec::new()));
+        assert_eq!(create_ribbon(&[point], 1.0, 8, 1.0), (Vec::new(), Vec::new()));
+        assert_eq!(create_ribbon(&[point, point, point], 1.0, 8, 1.0), (Vec::new(), Vec::new()));
+        // A repeated point is dropped rather than making a span of no length
+        let (vertices, indices) = create_ribbon(&[point, point, Vec3::new(4.0, 0.0, 2.0)], 1.0, 8, 1.0);
+        assert_eq!((vertices.len(), indices.len()), (18, 48));
+    }
+
+    #[test]
+    fn flat_ribbon_follows_its_points_on_the_ground() {
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 0.0, -1.0), Vec3::new(5.0, 0.0, -6.0), Vec3::new(12.0, 0.0, -7.0)];
+        let (vertices, indices) = create_ribbon(&points, 2.0, 6, 0.5);
+        assert_eq!((vertices.len(), indices.len()), ((3 * 6 + 1) * 2, 3 * 6 * 6));
+        assert_faces_its_normals(&vertices, &indices);
+
+        let rings = vertices.chunks_exact(2).collect::<Vec<_>>();
+        // Through every control point, where its span starts
+        for (point, ring) in points.iter().zip(rings.iter().step_by(6)) {
+            let center = (position(&ring[0]) + position(&ring[1])) * 0.5;
+            assert!((center - point).magnitude() < 1e-5, "{:?} != {:?}", center, point);
+        }
+        let mut arc_length = 0.0;
+        for (i, ring) in rings.iter().enumerate() {
+            let [left, right] = [&ring[0], &ring[1]];
+            assert_eq!((left.tex_coords[0], right.tex_coords[0]), (0.0, 1.0));
+            assert_eq!(left.tex_coords[1], right.tex_coords[1]);
+            assert!(((position(right) - position(left)).magnitude() - 2.0).abs() < 1e-5);
+            for vertex in ring.iter() {
+                assert!((normal(vertex) - Vec3::unit_y()).magnitude() < 1e-5, "{:?}", vertex.normal);
+            }
+            // Left is left, walking along the path with up being up
+            if i > 0 {
+                let center = |ring: &[Vertex]| (position(&ring[0]) + position(&ring[1])) * 0.5;
+                let forward = center(ring) - center(rings[i - 1]);
+                assert!((position(right) - position(left)).cross(forward).dot(Vec3::unit_y()) > 0.0);
+                arc_length += forward.magnitude();
+                // V is the distance along the path, tiled
+                assert!((left.tex_coords[1] - arc_length * 0.5).abs() < 1e-4, "{} at {}", left.tex_coords[1], arc_length);
+            }
+        }
+        assert_eq!(rings[0][0].tex_coords[1], 0.0);
+    }
+
+    #[test]
+    fn ribbon_turning_vertical_does_not_flip() {
+        // Along the ground, then up a wall and back over it
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(5.0, 0.0, 0.0), Vec3::new(6.0, 5.0, 0.0), Vec3::new(6.0, 10.0, 1.0), Vec3::new(2.0, 12.0, 3.0)];
+        let (vertices, indices) = create_ribbon(&points, 1.0, 10, 1.0);
+        assert_faces_its_normals(&vertices, &indices);
+        let rings = vertices.chunks_exact(2).collect::<Vec<_>>();
+        for pair in rings.windows(2) {
+            let (before, after) = (normal(&pair[0][0]), normal(&pair[1][0]));
+            assert!((after.magnitude() - 1.0).abs() < 1e-4);
+            assert!(before.dot(after) > 20.0f32.to_radians().cos(), "{:?} to {:?}", before, after);
+            // Across the strip is square to its up
+            assert!((position(&pair[1][1]) - position(&pair[1][0])).dot(after).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn closed_ribbon_meets_itself_at_the_seam() {
+        let flat = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(6.0, 0.0, 0.0), Vec3::new(6.0, 0.0, -6.0), Vec3::new(0.0, 0.0, -6.0), Vec3::new(0.0, 0.0, 0.0)];
+        // Up and over itself, transport alone would come back twisted
+        let twisted = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(6.0, 2.0, 0.0), Vec3::new(6.0, 8.0, -6.0), Vec3::new(-2.0, 4.0, -4.0), Vec3::new(0.0, 0.0, 0.0)];
+        for points in [flat, twisted] {
+            let (vertices, indices) = create_ribbon(&points, 1.5, 8, 1.0);
+            // Four spans, the first point is not repeated as a span of its own
+            assert_eq!(vertices.len(), (4 * 8 + 1) * 2);
+            assert_faces_its_normals(&vertices, &indices);
+            let (first, last) = (&vertices[..2], &vertices[vertices.len() - 2..]);
+            for (a, b) in first.iter().zip(last) {
+                assert!((position(a) - position(b)).magnitude() < 1e-5, "{:?} != {:?}", a.position, b.position);
+                assert!((normal(a) - normal(b)).magnitude() < 1e-5, "{:?} != {:?}", a.normal, b.normal);
+                assert_eq!(a.tex_coords[0], b.tex_coords[0]);
+            }
+            // Only V differs, by the loop's length
+            assert!(last[0].tex_coords[1] > 20.0, "{:?}", last[0].tex_coords);
+        }
+        // Flat, the up never leaves +Y
+        let (vertices, _) = create_ribbon(&flat, 1.5, 8, 1.0);
+        assert!(vertices.iter().all(|vertex| (normal(vertex) - Vec3::unit_y()).magnitude() < 1e-5));
+    }
+}
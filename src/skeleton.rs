@@ -0,0 +1,367 @@
+/*
+Purpose: Skeletons and keyframed joint animation, independent of the file format they come from
+Responsibilities:
+    - Define Skeleton (joint hierarchy, rest pose, inverse bind matrices) and AnimationClip
+      (translation/rotation/scale keyframes per joint)
+    - Sample a clip at a time into a local pose, crossfading from the previous clip
+    - Turn a pose into the joint matrices a skinning shader multiplies vertices by
+    - ex: the puppeteer's notes, which string to pull how far and when
+*/
+
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector3, VectorSpace};
+
+// Local transform of one joint relative to its parent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for JointTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl JointTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // t = 0 is self, t = 1 is other
+    pub fn blend(&self, other: &JointTransform, t: f32) -> JointTransform {
+        JointTransform {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: nlerp(self.rotation, other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    // Index of the parent joint, always lower than this joint's own index
+    pub parent: Option<usize>,
+    pub inverse_bind: Matrix4<f32>,
+    // Pose used for anything a clip doesn't animate
+    pub rest: JointTransform,
+}
+
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Joints must be ordered parents first so one pass computes every global transform
+    pub fn new(joints: Vec<Joint>) -> anyhow::Result<Self> {
+        for (index, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent
+                && parent >= index
+            {
+                anyhow::bail!("joint {} ({}) comes before its parent {}", index, joint.name, parent);
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    pub fn rest_pose(&self) -> Vec<JointTransform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    // Model space joint transform times inverse bind matrix, one per joint
+    pub fn joint_matrices(&self, pose: &[JointTransform]) -> Vec<Matrix4<f32>> {
+        let mut globals: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose) {
+            let local = local.matrix();
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+            globals.push(global);
+        }
+        globals
+            .iter()
+            .zip(&self.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // the skinning demo only bends its joints, a file loader would key the rest
+pub enum Keyframes {
+    Translation(Vec<(f32, Vector3<f32>)>),
+    Rotation(Vec<(f32, Quaternion<f32>)>),
+    Scale(Vec<(f32, Vector3<f32>)>),
+}
+
+// One animated property of one joint, keyframe times ascending
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub joint: usize,
+    pub keyframes: Keyframes,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    // Time of the last keyframe in any channel
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(name: String, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| match &channel.keyframes {
+                Keyframes::Translation(keys) | Keyframes::Scale(keys) => keys.last().map(|(t, _)| *t),
+                Keyframes::Rotation(keys) => keys.last().map(|(t, _)| *t),
+            })
+            .fold(0.0, f32::max);
+        Self { name, channels, duration }
+    }
+
+    // Linear interpolation between keyframes, held at the first and last one outside their range
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Vec<JointTransform> {
+        let mut pose = skeleton.rest_pose();
+        for channel in &self.channels {
+            let Some(joint) = pose.get_mut(channel.joint) else {
+                continue;
+            };
+            match &channel.keyframes {
+                Keyframes::Translation(keys) => {
+                    if let Some(value) = sample_keys(keys, time, |a, b, t| a.lerp(b, t)) {
+                        joint.translation = value;
+                    }
+                }
+                Keyframes::Rotation(keys) => {
+                    if let Some(value) = sample_keys(keys, time, nlerp) {
+                        joint.rotation = value;
+                    }
+                }
+                Keyframes::Scale(keys) => {
+                    if let Some(value) = sample_keys(keys, time, |a, b, t| a.lerp(b, t)) {
+                        joint.scale = value;
+                    }
+                }
+            }
+        }
+        pose
+    }
+}
+
+// Also drives light_anim.rs tracks
+pub fn sample_keys<T: Copy>(keys: &[(f32, T)], time: f32, interpolate: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let (first, last) = (keys.first()?, keys.last()?);
+    if time <= first.0 {
+        return Some(first.1);
+    }
+    if time >= last.0 {
+        return Some(last.1);
+    }
+    // First key after `time`, there is one before it since time > first
+    let next = keys.partition_point(|(t, _)| *t <= time);
+    let (t0, a) = keys[next - 1];
+    let (t1, b) = keys[next];
+    let span = t1 - t0;
+    let t = if span > 0.0 { (time - t0) / span } else { 0.0 };
+    Some(interpolate(a, b, t))
+}
+
+// Normalized lerp along the shorter arc, close enough to slerp between nearby keyframes
+fn nlerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - t) + b * t).normalize()
+}
+
+struct Playback {
+    clip: usize,
+    time: f32,
+    looping: bool,
+}
+
+impl Playback {
+    fn advance(&mut self, dt: f32, clips: &[AnimationClip]) {
+        let duration = clips[self.clip].duration;
+        self.time += dt;
+        if self.looping && duration > 0.0 {
+            self.time %= duration;
+        } else {
+            self.time = self.time.min(duration);
+        }
+    }
+}
+
+// Plays the clips of one skeleton, fading from the previous clip when a new one starts
+pub struct AnimationPlayer {
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+    // Seconds a newly started clip takes to fully replace the previous one
+    pub crossfade: f32,
+    current: Option<Playback>,
+    previous: Option<Playback>,
+    // 0 right after a clip started, 1 once the previous clip is gone
+    fade: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new(skeleton: Skeleton, clips: Vec<AnimationClip>) -> Self {
+        Self { skeleton, clips, crossfade: 0.2, current: None, previous: None, fade: 1.0 }
+    }
+
+    // False when the skeleton has no clip by that name
+    pub fn play(&mut self, clip_name: &str, looping: bool) -> bool {
+        let Some(clip) = self.clips.iter().position(|clip| clip.name == clip_name) else {
+            return false;
+        };
+        self.previous = self.current.take().filter(|_| self.crossfade > 0.0);
+        self.fade = if self.previous.is_some() { 0.0 } else { 1.0 };
+        self.current = Some(Playback { clip, time: 0.0, looping });
+        true
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for playback in [self.current.as_mut(), self.previous.as_mut()].into_iter().flatten() {
+            playback.advance(dt, &self.clips);
+        }
+        if self.previous.is_some() {
+            self.fade = (self.fade + dt / self.crossfade).min(1.0);
+            if self.fade >= 1.0 {
+                self.previous = None;
+            }
+        }
+    }
+
+    // Rest pose while nothing plays
+    pub fn pose(&self) -> Vec<JointTransform> {
+        let sample = |playback: &Playback| self.clips[playback.clip].sample(&self.skeleton, playback.time);
+        let Some(current) = self.current.as_ref().map(sample) else {
+            return self.skeleton.rest_pose();
+        };
+        match self.previous.as_ref().map(sample) {
+            Some(previous) => previous.iter().zip(&current).map(|(from, to)| from.blend(to, self.fade)).collect(),
+            None => current,
+        }
+    }
+
+    pub fn joint_matrices(&self) -> Vec<Matrix4<f32>> {
+        self.skeleton.joint_matrices(&self.pose())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Rotation3};
+
+    const TOLERANCE: f32 = 1e-4;
+
+    fn joint(name: &str, parent: Option<usize>) -> Joint {
+        Joint { name: name.to_string(), parent, inverse_bind: Matrix4::from_scale(1.0), rest: JointTransform::default() }
+    }
+
+    // A root and a child one unit above it
+    fn two_joints() -> Skeleton {
+        let mut child = joint("child", Some(0));
+        child.rest.translation = Vector3::new(0.0, 1.0, 0.0);
+        Skeleton::new(vec![joint("root", None), child]).unwrap()
+    }
+
+    // Moves the root along X from 0 at t = 0 to `to` at t = 1
+    fn slide(name: &str, to: f32) -> AnimationClip {
+        let keys = vec![(0.0, Vector3::new(0.0, 0.0, 0.0)), (1.0, Vector3::new(to, 0.0, 0.0))];
+        AnimationClip::new(name.to_string(), vec![Channel { joint: 0, keyframes: Keyframes::Translation(keys) }])
+    }
+
+    fn root_x(player: &AnimationPlayer) -> f32 {
+        player.pose()[0].translation.x
+    }
+
+    #[test]
+    fn parents_must_come_first() {
+        assert!(Skeleton::new(vec![joint("child", Some(1)), joint("root", None)]).is_err());
+        assert!(Skeleton::new(vec![joint("self", Some(0))]).is_err());
+    }
+
+    #[test]
+    fn keys_interpolate_and_hold_at_the_ends() {
+        let keys = [(1.0, 10.0), (2.0, 20.0), (4.0, 0.0)];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        assert_eq!(sample_keys(&keys, 0.0, lerp), Some(10.0));
+        assert_eq!(sample_keys(&keys, 1.5, lerp), Some(15.0));
+        assert_eq!(sample_keys(&keys, 3.0, lerp), Some(10.0));
+        assert_eq!(sample_keys(&keys, 9.0, lerp), Some(0.0));
+        assert_eq!(sample_keys::<f32>(&[], 1.0, lerp), None);
+    }
+
+    #[test]
+    fn clip_sampling_keeps_the_rest_pose_of_unanimated_joints() {
+        let skeleton = two_joints();
+        let turn = Quaternion::from_angle_y(Deg(90.0));
+        let clip = AnimationClip::new(
+            "turn".to_string(),
+            vec![
+                Channel { joint: 0, keyframes: Keyframes::Rotation(vec![(0.0, Quaternion::new(1.0, 0.0, 0.0, 0.0)), (2.0, turn)]) },
+                Channel { joint: 0, keyframes: Keyframes::Scale(vec![(0.0, Vector3::new(2.0, 2.0, 2.0))]) },
+            ],
+        );
+        assert_eq!(clip.duration, 2.0);
+        let pose = clip.sample(&skeleton, 2.0);
+        assert!((pose[0].rotation - turn).magnitude() < TOLERANCE);
+        assert_eq!(pose[0].scale, Vector3::new(2.0, 2.0, 2.0));
+        assert_eq!(pose[1], skeleton.rest_pose()[1]);
+        let halfway = clip.sample(&skeleton, 1.0)[0].rotation;
+        assert!((halfway - Quaternion::from_angle_y(Deg(45.0))).magnitude() < TOLERANCE, "{:?}", halfway);
+    }
+
+    #[test]
+    fn joint_matrices_chain_through_the_parent() {
+        let skeleton = two_joints();
+        let mut pose = skeleton.rest_pose();
+        pose[0].translation = Vector3::new(3.0, 0.0, 0.0);
+        let matrices = skeleton.joint_matrices(&pose);
+        assert_eq!(matrices[1].w.truncate(), Vector3::new(3.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn looping_wraps_and_one_shots_hold() {
+        let mut player = AnimationPlayer::new(two_joints(), vec![slide("slide", 4.0)]);
+        assert!(!player.play("missing", true));
+        assert!(player.play("slide", true));
+        player.update(1.25);
+        assert!((root_x(&player) - 1.0).abs() < TOLERANCE);
+
+        player.crossfade = 0.0;
+        player.play("slide", false);
+        player.update(3.0);
+        assert!((root_x(&player) - 4.0).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn a_new_clip_fades_in_over_the_crossfade() {
+        let mut player = AnimationPlayer::new(two_joints(), vec![slide("right", 10.0), slide("left", -10.0)]);
+        player.crossfade = 0.5;
+        player.play("right", false);
+        player.update(1.0);
+        assert!((root_x(&player) - 10.0).abs() < TOLERANCE);
+
+        player.play("left", false);
+        assert!((root_x(&player) - 10.0).abs() < TOLERANCE, "starts from the previous clip");
+        player.update(0.25);
+        // Halfway between right held at 10 and left at -2.5
+        assert!((root_x(&player) - 3.75).abs() < TOLERANCE, "{}", root_x(&player));
+        player.update(0.25);
+        assert!((root_x(&player) + 5.0).abs() < TOLERANCE, "{}", root_x(&player));
+    }
+}
@@ -0,0 +1,509 @@
+/*
+Purpose: Deform skinned meshes with morph targets before they are drawn
+Responsibilities:
+    - Add each vertex's morph target deltas by the target weights, then move it by the weighted
+      sum of up to four joint matrices from skeleton.rs
+    - GPU path: a compute pass writes the deformed vertices into the vertex buffer the render
+      pipelines draw. CPU path: the same math in Rust and one upload, to check it against.
+    - Build the skinning demo, a column that bends and bulges, until a loader brings skins
+    - ex: the clay over the armature, pressed into shape again every frame
+*/
+
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{Deg, InnerSpace, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+
+use crate::{
+    gpu_debug::debug_label,
+    gpu_layout::{UniformCheck, rust_layout},
+    gpu_memory::{self, Tracked},
+    math::Aabb,
+    model::{self, ModelVertex},
+    model_entry::ModelHandle,
+    render_context::RenderContext,
+    skeleton::{AnimationClip, AnimationPlayer, Channel, Joint, JointTransform, Keyframes, Skeleton},
+    texture,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+// The weights travel in one vec4
+pub const MAX_MORPH_TARGETS: usize = 4;
+
+// Joints a vertex follows and how much, the weights sum to one. Unused slots have weight 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VertexSkin {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+pub const VERTEX_SKIN_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(VertexSkin, [joints, weights]),
+    wgsl: &[("skinning.wgsl", "VertexSkin")],
+};
+
+// Offsets from the rest vertices, one per vertex
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub name: String,
+    pub position_deltas: Vec<[f32; 3]>,
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+// Everything a mesh needs to be deformed, the vertices in their bind pose
+pub struct SkinData {
+    pub rest: Vec<ModelVertex>,
+    pub skins: Vec<VertexSkin>,
+    pub targets: Vec<MorphTarget>,
+    pub joint_count: usize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkinningUniform {
+    vertex_count: u32,
+    target_count: u32,
+    _padding: [u32; 2],
+    weights: [f32; MAX_MORPH_TARGETS],
+}
+
+pub const SKINNING_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(SkinningUniform, [vertex_count, target_count, weights]),
+    wgsl: &[("skinning.wgsl", "SkinningUniform")],
+};
+
+// Shared by every State, lives in the RenderContext
+pub struct SkinningPipeline {
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+}
+
+impl SkinningPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read_only = wgpu::BufferBindingType::Storage { read_only: true };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, read_only),
+                buffer_entry(2, read_only),
+                buffer_entry(3, read_only),
+                buffer_entry(4, read_only),
+                buffer_entry(5, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("Skinning Bind Group Layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinning Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skinning Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("skinning.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Skinning Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Self { pipeline, layout }
+    }
+}
+
+// One deformable mesh. The deformed vertex buffer is handed out by new for the model::Mesh that draws it.
+pub struct SkinnedMesh {
+    data: SkinData,
+    // Also drawn by the mesh, which owns (and counts) it
+    deformed_buffer: wgpu::Buffer,
+    joint_buffer: Tracked<wgpu::Buffer>,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    // Read by the compute pass only, never change
+    _rest_buffer: Tracked<wgpu::Buffer>,
+    _skin_buffer: Tracked<wgpu::Buffer>,
+    _morph_buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl SkinnedMesh {
+    // The returned vertex buffer starts out in the bind pose
+    pub fn new(device: &wgpu::Device, pipeline: &SkinningPipeline, data: SkinData, label: &str) -> anyhow::Result<(Self, Tracked<wgpu::Buffer>)> {
+        let vertex_count = data.rest.len();
+        if vertex_count == 0 {
+            anyhow::bail!("{} has no vertices", label);
+        }
+        if data.skins.len() != vertex_count {
+            anyhow::bail!("{} has {} vertices but {} skin weights", label, vertex_count, data.skins.len());
+        }
+        if data.targets.len() > MAX_MORPH_TARGETS {
+            anyhow::bail!("{} has {} morph targets, at most {} are supported", label, data.targets.len(), MAX_MORPH_TARGETS);
+        }
+        if let Some(target) = data.targets.iter().find(|target| target.position_deltas.len() != vertex_count || target.normal_deltas.len() != vertex_count) {
+            anyhow::bail!("morph target {} of {} doesn't cover its {} vertices", target.name, label, vertex_count);
+        }
+        if let Some(joint) = data.skins.iter().flat_map(|skin| skin.joints).find(|joint| *joint as usize >= data.joint_count) {
+            anyhow::bail!("{} uses joint {} of {}", label, joint, data.joint_count);
+        }
+
+        let deformed = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Deformed Vertex Buffer", label).as_deref(),
+            contents: bytemuck::cast_slice(&data.rest),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let rest_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Rest Vertex Buffer", label).as_deref(),
+            contents: bytemuck::cast_slice(&data.rest),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let skin_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Skin Buffer", label).as_deref(),
+            contents: bytemuck::cast_slice(&data.skins),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Storage bindings can't be empty
+        let mut deltas = data
+            .targets
+            .iter()
+            .flat_map(|target| target.position_deltas.iter().zip(&target.normal_deltas))
+            .flat_map(|(position, normal)| [[position[0], position[1], position[2], 0.0], [normal[0], normal[1], normal[2], 0.0]])
+            .collect::<Vec<_>>();
+        if deltas.is_empty() {
+            deltas.push([0.0; 4]);
+        }
+        let morph_buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Morph Target Buffer", label).as_deref(),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let joint_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Joint Buffer", label).as_deref(),
+            size: (data.joint_count.max(1) * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: debug_label!("{} Skinning Uniform Buffer", label).as_deref(),
+            size: std::mem::size_of::<SkinningUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let entries = [&uniform_buffer, &rest_buffer, &skin_buffer, &morph_buffer, &joint_buffer, &deformed];
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.layout,
+            entries: &entries
+                .iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>(),
+            label: debug_label!("{} Skinning Bind Group", label).as_deref(),
+        });
+
+        let mesh = Self {
+            data,
+            deformed_buffer: (*deformed).clone(),
+            joint_buffer,
+            uniform_buffer,
+            _rest_buffer: rest_buffer,
+            _skin_buffer: skin_buffer,
+            _morph_buffer: morph_buffer,
+            bind_group,
+        };
+        Ok((mesh, deformed))
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.data.rest.len()
+    }
+
+    // CPU path: every vertex is deformed here and the whole buffer uploaded.
+    // Missing weights count as 0, extra ones are ignored.
+    pub fn deform_cpu(&self, queue: &wgpu::Queue, joints: &[Matrix4<f32>], weights: &[f32]) {
+        let data = &self.data;
+        let vertices = (0..data.rest.len()).map(|index| self.deform_vertex(index, joints, weights)).collect::<Vec<_>>();
+        if !vertices.is_empty() {
+            queue.write_buffer(&self.deformed_buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+    }
+
+    // GPU path: only the joints and weights are uploaded, the compute pass writes the buffer
+    pub fn deform_gpu(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, pipeline: &SkinningPipeline, joints: &[Matrix4<f32>], weights: &[f32]) {
+        let vertex_count = self.vertex_count() as u32;
+        if vertex_count == 0 {
+            return;
+        }
+        let matrices = (0..self.data.joint_count)
+            .map(|joint| joints.get(joint).copied().unwrap_or_else(Matrix4::identity).into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        if !matrices.is_empty() {
+            queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&matrices));
+        }
+        let uniform = SkinningUniform {
+            vertex_count,
+            target_count: self.data.targets.len() as u32,
+            _padding: [0; 2],
+            weights: target_weights(weights),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Skinning Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pipeline.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Same steps as skinning.wgsl
+    fn deform_vertex(&self, index: usize, joints: &[Matrix4<f32>], weights: &[f32]) -> ModelVertex {
+        let rest = &self.data.rest[index];
+        let weights = target_weights(weights);
+        let mut position = Vector3::from(rest.position);
+        let mut normal = Vector3::from(rest.normal);
+        for (target, weight) in self.data.targets.iter().zip(weights) {
+            position += Vector3::from(target.position_deltas[index]) * weight;
+            normal += Vector3::from(target.normal_deltas[index]) * weight;
+        }
+
+        let skin = &self.data.skins[index];
+        let joint = |slot: usize| joints.get(skin.joints[slot] as usize).copied().unwrap_or_else(Matrix4::identity);
+        let m = (0..4).fold(Matrix4::from_scale(0.0), |m, slot| m + joint(slot) * skin.weights[slot]);
+        let direction = |v: Vector3<f32>| (m * v.extend(0.0)).truncate();
+        ModelVertex {
+            position: (m * position.extend(1.0)).truncate().into(),
+            tex_coords: rest.tex_coords,
+            normal: direction(normal).normalize().into(),
+            tangent: direction(rest.tangent.into()).into(),
+            bitangent: direction(rest.bitangent.into()).into(),
+        }
+    }
+}
+
+fn target_weights(weights: &[f32]) -> [f32; MAX_MORPH_TARGETS] {
+    let mut padded = [0.0; MAX_MORPH_TARGETS];
+    for (slot, weight) in padded.iter_mut().zip(weights) {
+        *slot = *weight;
+    }
+    padded
+}
+
+// Smoothed milliseconds each path took, the GPU one only counts uploading and encoding
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SkinningStats {
+    pub cpu_path_ms: Option<f32>,
+    pub gpu_path_ms: Option<f32>,
+}
+
+// Demo column, along +Y from its base
+const DEMO_HEIGHT: f32 = 3.0;
+const DEMO_RADIUS: f32 = 0.35;
+const DEMO_SEGMENTS: u32 = 24;
+const DEMO_RINGS: u32 = 48;
+const DEMO_JOINTS: usize = 3;
+const DEMO_BULGE: f32 = 0.25;
+
+// A column bent by a three joint chain while a morph target swells its middle
+pub struct SkinningDemo {
+    pub handle: ModelHandle,
+    mesh: SkinnedMesh,
+    player: AnimationPlayer,
+    time: f32,
+    // Compute pass when set, otherwise the CPU path
+    gpu: bool,
+    // Whether the vertex buffer shows the current time, cleared when it moves or the path changes
+    posed: bool,
+    pub stats: SkinningStats,
+}
+
+impl SkinningDemo {
+    // The model draws the deformed buffer, add it to the scene under `handle`
+    pub fn new(context: &RenderContext, handle: ModelHandle) -> anyhow::Result<(Self, model::Model)> {
+        let (data, indices) = demo_column();
+        let vertex_count = data.rest.len();
+        let bounds = Aabb::from_points(data.rest.iter().map(|vertex| Vector3::from(vertex.position)))
+            .map(|aabb| {
+                // Room for the bend and the bulge, picking uses these model space bounds
+                let margin = Vector3::new(DEMO_HEIGHT, DEMO_BULGE, DEMO_HEIGHT);
+                Aabb::new(aabb.min - margin, aabb.max + margin)
+            })
+            .ok_or_else(|| anyhow::anyhow!("the skinning demo column has no vertices"))?;
+        let (mesh, vertex_buffer) = SkinnedMesh::new(&context.device, &context.skinning, data, "Skinning Demo")?;
+        let index_buffer = gpu_memory::create_buffer_init(&context.device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Skinning Demo Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])));
+        let flat_normal = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255])));
+        let material = model::Material::new(
+            &context.device,
+            "skinning_demo",
+            texture::Texture::from_image(&context.device, &context.queue, &white, Some("skinning_demo_diffuse"), false)?,
+            texture::Texture::from_image(&context.device, &context.queue, &flat_normal, Some("skinning_demo_normal"), true)?,
+            &context.texture_bind_group_layout,
+            model::MaterialParams {
+                color: [0.3, 0.7, 0.9, 1.0],
+                ..Default::default()
+            },
+        );
+        let model = model::Model {
+            meshes: vec![model::Mesh::new("column".to_string(), vertex_buffer, index_buffer, indices.len() as u32, 0, bounds)],
+            materials: vec![material],
+            optimize_stats: None,
+            packed: None,
+        };
+
+        let mut player = AnimationPlayer::new(demo_skeleton()?, vec![demo_clip()]);
+        player.play("bend", true);
+        log::info!("Skinning demo: {} vertices, {} joints", vertex_count, DEMO_JOINTS);
+        let demo = Self { handle, mesh, player, time: 0.0, gpu: false, posed: false, stats: SkinningStats::default() };
+        Ok((demo, model))
+    }
+
+    pub fn gpu(&self) -> bool {
+        self.gpu
+    }
+
+    pub fn set_gpu(&mut self, gpu: bool) {
+        self.gpu = gpu;
+        self.posed = false;
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.mesh.vertex_count()
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.player.update(dt);
+        self.time += dt;
+        self.posed = false;
+    }
+
+    // Deforms the column for the current time on the active path, unless it already shows it
+    pub fn deform(&mut self, context: &RenderContext) {
+        if self.posed {
+            return;
+        }
+        let start = std::time::Instant::now();
+        let joints = self.player.joint_matrices();
+        let weights = [0.5 - 0.5 * (self.time * 2.0).cos()];
+        if self.gpu {
+            let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Skinning Encoder"),
+            });
+            encoder.push_debug_group("skinning");
+            self.mesh.deform_gpu(&mut encoder, &context.queue, &context.skinning, &joints, &weights);
+            encoder.pop_debug_group();
+            context.queue.submit(std::iter::once(encoder.finish()));
+        } else {
+            self.mesh.deform_cpu(&context.queue, &joints, &weights);
+        }
+        self.posed = true;
+
+        let ms = start.elapsed().as_secs_f32() * 1000.0;
+        let slot = if self.gpu { &mut self.stats.gpu_path_ms } else { &mut self.stats.cpu_path_ms };
+        // Smooth it so the panel is readable
+        *slot = Some(slot.map_or(ms, |previous| previous + (ms - previous) * 0.1));
+    }
+}
+
+// Rings of vertices up the column, each following the two joints nearest its height
+fn demo_column() -> (SkinData, Vec<u32>) {
+    let segment = DEMO_HEIGHT / DEMO_JOINTS as f32;
+    let mut rest = Vec::new();
+    let mut skins = Vec::new();
+    let mut bulge = Vec::new();
+    for ring in 0..=DEMO_RINGS {
+        let v = ring as f32 / DEMO_RINGS as f32;
+        let y = v * DEMO_HEIGHT;
+        // Blend from the middle of one bone to the middle of the next
+        let s = (y / segment - 0.5).clamp(0.0, (DEMO_JOINTS - 1) as f32);
+        let lower = (s.floor() as usize).min(DEMO_JOINTS - 1);
+        let upper = (lower + 1).min(DEMO_JOINTS - 1);
+        let blend = s - lower as f32;
+        let swell = DEMO_BULGE * (PI * v).sin().powi(2);
+        for step in 0..=DEMO_SEGMENTS {
+            let u = step as f32 / DEMO_SEGMENTS as f32;
+            let (sin, cos) = (TAU * u).sin_cos();
+            let normal = [cos, 0.0, sin];
+            rest.push(ModelVertex {
+                position: [DEMO_RADIUS * cos, y, DEMO_RADIUS * sin],
+                tex_coords: [u, 1.0 - v],
+                normal,
+                tangent: [-sin, 0.0, cos],
+                bitangent: [0.0, 1.0, 0.0],
+            });
+            skins.push(VertexSkin {
+                joints: [lower as u32, upper as u32, 0, 0],
+                weights: [1.0 - blend, blend, 0.0, 0.0],
+            });
+            bulge.push([normal[0] * swell, 0.0, normal[2] * swell]);
+        }
+    }
+
+    let columns = DEMO_SEGMENTS + 1;
+    let mut indices = Vec::new();
+    for ring in 0..DEMO_RINGS {
+        for step in 0..DEMO_SEGMENTS {
+            let a = ring * columns + step;
+            let (b, c) = (a + 1, a + columns);
+            indices.extend_from_slice(&[a, c, b, b, c, c + 1]);
+        }
+    }
+
+    let normal_deltas = vec![[0.0; 3]; bulge.len()];
+    let targets = vec![MorphTarget { name: "bulge".to_string(), position_deltas: bulge, normal_deltas }];
+    (SkinData { rest, skins, targets, joint_count: DEMO_JOINTS }, indices)
+}
+
+// A chain up the column, one joint at the base of each bone
+fn demo_skeleton() -> anyhow::Result<Skeleton> {
+    let segment = DEMO_HEIGHT / DEMO_JOINTS as f32;
+    let joints = (0..DEMO_JOINTS)
+        .map(|index| Joint {
+            name: format!("bone{}", index),
+            parent: index.checked_sub(1),
+            inverse_bind: Matrix4::from_translation(Vector3::new(0.0, -segment * index as f32, 0.0)),
+            rest: JointTransform {
+                translation: Vector3::new(0.0, if index == 0 { 0.0 } else { segment }, 0.0),
+                ..Default::default()
+            },
+        })
+        .collect();
+    Skeleton::new(joints)
+}
+
+// The upper joints sway around Z, a quarter period apart
+fn demo_clip() -> AnimationClip {
+    const DURATION: f32 = 4.0;
+    let sway = |joint: usize, degrees: f32, phase: f32| Channel {
+        joint,
+        keyframes: Keyframes::Rotation(
+            (0..=16)
+                .map(|key| {
+                    let t = key as f32 / 16.0 * DURATION;
+                    let angle = degrees * (TAU * (t / DURATION + phase)).sin();
+                    (t, Quaternion::from_angle_z(Deg(angle)))
+                })
+                .collect(),
+        ),
+    };
+    AnimationClip::new("bend".to_string(), vec![sway(1, 30.0, 0.0), sway(2, 40.0, 0.25)])
+}
+
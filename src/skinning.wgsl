@@ -0,0 +1,79 @@
+/*
+Purpose: Deform a skinned mesh on the GPU
+Responsibilites:
+    - Add each vertex's morph target deltas, scaled by the target weights, to its rest position and normal
+    - Move the result by the weighted sum of its (up to four) joint matrices
+    - Write the ModelVertex layout straight into the vertex buffer the render pipelines draw
+*/
+
+struct VertexSkin {
+    joints: vec4<u32>,
+    // Sum to one
+    weights: vec4<f32>,
+};
+
+struct SkinningUniform {
+    vertex_count: u32,
+    target_count: u32,
+    // One per morph target, up to four
+    weights: vec4<f32>,
+};
+
+// Floats per ModelVertex: position 3, tex_coords 2, normal 3, tangent 3, bitangent 3
+const VERTEX_STRIDE: u32 = 14u;
+
+@group(0) @binding(0)
+var<uniform> skinning: SkinningUniform;
+@group(0) @binding(1)
+var<storage, read> rest: array<f32>;
+@group(0) @binding(2)
+var<storage, read> skins: array<VertexSkin>;
+// Position (xyz) then normal (xyz) delta per target and vertex, target major
+@group(0) @binding(3)
+var<storage, read> morph_deltas: array<vec4<f32>>;
+@group(0) @binding(4)
+var<storage, read> joints: array<mat4x4<f32>>;
+@group(0) @binding(5)
+var<storage, read_write> deformed: array<f32>;
+
+fn read_vec3(base: u32) -> vec3<f32> {
+    return vec3<f32>(rest[base], rest[base + 1u], rest[base + 2u]);
+}
+
+fn write_vec3(base: u32, value: vec3<f32>) {
+    deformed[base] = value.x;
+    deformed[base + 1u] = value.y;
+    deformed[base + 2u] = value.z;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= skinning.vertex_count) {
+        return;
+    }
+    let base = i * VERTEX_STRIDE;
+    var position = read_vec3(base);
+    var normal = read_vec3(base + 5u);
+    let tangent = read_vec3(base + 8u);
+    let bitangent = read_vec3(base + 11u);
+
+    for (var t = 0u; t < skinning.target_count; t++) {
+        let delta = (t * skinning.vertex_count + i) * 2u;
+        position += morph_deltas[delta].xyz * skinning.weights[t];
+        normal += morph_deltas[delta + 1u].xyz * skinning.weights[t];
+    }
+
+    let skin = skins[i];
+    let m = joints[skin.joints.x] * skin.weights.x
+        + joints[skin.joints.y] * skin.weights.y
+        + joints[skin.joints.z] * skin.weights.z
+        + joints[skin.joints.w] * skin.weights.w;
+
+    write_vec3(base, (m * vec4<f32>(position, 1.0)).xyz);
+    deformed[base + 3u] = rest[base + 3u];
+    deformed[base + 4u] = rest[base + 4u];
+    write_vec3(base + 5u, normalize((m * vec4<f32>(normal, 0.0)).xyz));
+    write_vec3(base + 8u, (m * vec4<f32>(tangent, 0.0)).xyz);
+    write_vec3(base + 11u, (m * vec4<f32>(bitangent, 0.0)).xyz);
+}
@@ -0,0 +1,248 @@
+/*
+Purpose: Procedural sky, a light stand-in for a skybox behind the scene
+Responsibilities:
+    - Define SkyMode, None keeps the flat clear color
+    - Hold the sky's look (SkySettings): horizon, zenith and ground colors, the sun disk's size
+      and brightness and its halo
+    - Build the sky pipeline: a fullscreen triangle on the far plane, depth tested but not
+      written, so it only fills the pixels the scene left empty
+    - Upload where the sun is and the colors of the moment (SkyColors), the day-night cycle or
+      the scene light decide those, see State::sky_colors
+    - ex: the painted backdrop of a theatre stage, hung behind everything else
+*/
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{color, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, texture};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkyMode {
+    // The clear color, or the day-night cycle's
+    None,
+    Procedural,
+}
+
+impl SkyMode {
+    pub const ALL: [SkyMode; 2] = [SkyMode::None, SkyMode::Procedural];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SkyMode::None => "None (clear color)",
+            SkyMode::Procedural => "Procedural",
+        }
+    }
+}
+
+// The menu's sky settings, startup mode from RenderSettings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+    pub mode: SkyMode,
+    // Ignored while the day-night cycle colors the sky
+    pub horizon: [f32; 3],
+    pub zenith: [f32; 3],
+    // Below the horizon, off keeps the horizon color all the way down
+    pub show_ground: bool,
+    pub ground: [f32; 3],
+    // Angular radius of the disk in degrees, the real sun is about 0.27
+    pub sun_size: f32,
+    // Times the sun light's color, in HDR the disk outshines what it lights
+    pub sun_brightness: f32,
+    // Strength and angular width in degrees of the glow around the disk
+    pub halo: f32,
+    pub halo_size: f32,
+    // The clear color follows the horizon, what shows where the sky isn't drawn: probe bakes and
+    // the reflections of models without a baked probe
+    pub clear_from_horizon: bool,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            mode: SkyMode::None,
+            horizon: [0.75, 0.85, 0.95],
+            zenith: [0.25, 0.45, 0.8],
+            show_ground: true,
+            ground: [0.3, 0.28, 0.25],
+            sun_size: 1.5,
+            sun_brightness: 4.0,
+            halo: 0.4,
+            halo_size: 8.0,
+            clear_from_horizon: true,
+        }
+    }
+}
+
+impl SkySettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui, day_night: bool) {
+        egui::ComboBox::from_label("Sky")
+            .selected_text(self.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in SkyMode::ALL {
+                    ui.selectable_value(&mut self.mode, mode, mode.label());
+                }
+            });
+        ui.add_enabled_ui(self.mode == SkyMode::Procedural, |ui| {
+            ui.add_enabled_ui(!day_night, |ui| {
+                ui.horizontal(|ui| {
+                    color::edit_srgb(ui, &mut self.horizon);
+                    ui.label("Horizon");
+                    color::edit_srgb(ui, &mut self.zenith);
+                    ui.label("Zenith");
+                });
+            })
+            .response
+            .on_disabled_hover_text("The day-night cycle colors the sky");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.show_ground, "Ground");
+                ui.add_enabled_ui(self.show_ground, |ui| color::edit_srgb(ui, &mut self.ground));
+            });
+            ui.add(egui::Slider::new(&mut self.sun_size, 0.1..=10.0).logarithmic(true).text("Sun size (°)"));
+            ui.add(egui::Slider::new(&mut self.sun_brightness, 0.0..=20.0).text("Sun brightness"));
+            ui.add(egui::Slider::new(&mut self.halo, 0.0..=2.0).text("Halo"));
+            ui.add(egui::Slider::new(&mut self.halo_size, 1.0..=45.0).text("Halo size (°)"));
+            ui.checkbox(&mut self.clear_from_horizon, "Clear color from horizon")
+                .on_hover_text("What probe bakes and unbaked reflections see instead of the sky");
+        });
+    }
+}
+
+// What the sky shows this frame, colors in sRGB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyColors {
+    pub horizon: [f32; 3],
+    pub zenith: [f32; 3],
+    // Towards the sun
+    pub sun_direction: Vector3<f32>,
+    // The sun light's, zero intensity hides the disk
+    pub sun_color: [f32; 3],
+    pub sun_intensity: f32,
+}
+
+// Must match Sky in sky.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    horizon: [f32; 3],
+    sun_radius: f32,
+    zenith: [f32; 3],
+    halo: f32,
+    ground: [f32; 3],
+    show_ground: u32,
+    sun_direction: [f32; 3],
+    halo_width: f32,
+    sun_color: [f32; 3],
+    _padding: f32,
+}
+
+pub const SKY_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(SkyUniform, [horizon, sun_radius, zenith, halo, ground, show_ground, sun_direction, halo_width, sun_color]),
+    wgsl: &[("sky.wgsl", "Sky")],
+};
+
+impl SkyUniform {
+    fn new(settings: &SkySettings, colors: &SkyColors) -> Self {
+        let direction = colors.sun_direction;
+        let direction = if direction.magnitude2() > 0.0 { direction.normalize() } else { Vector3::unit_y() };
+        Self {
+            horizon: color::srgb_to_linear_rgb(colors.horizon),
+            sun_radius: settings.sun_size.to_radians(),
+            zenith: color::srgb_to_linear_rgb(colors.zenith),
+            halo: settings.halo,
+            ground: color::srgb_to_linear_rgb(settings.ground),
+            show_ground: settings.show_ground as u32,
+            sun_direction: direction.into(),
+            halo_width: settings.halo_size.to_radians(),
+            sun_color: color::srgb_to_linear_rgb(colors.sun_color).map(|channel| channel * colors.sun_intensity * settings.sun_brightness),
+            _padding: 0.0,
+        }
+    }
+}
+
+pub struct SkyRenderer {
+    pipeline: wgpu::RenderPipeline,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl SkyRenderer {
+    pub fn new(device: &wgpu::Device, camera_layout: &wgpu::BindGroupLayout, color_format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<SkyUniform>() as u64),
+                },
+                count: None,
+            }],
+            label: Some("Sky Bind Group Layout"),
+        });
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+            label: Some("Sky Bind Group"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sky.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // On the far plane, it passes where the depth buffer still holds its clear value of 1
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState { count: sample_count, mask: !0, alpha_to_coverage_enabled: false },
+            multiview: None,
+            cache: None,
+        });
+        Self { pipeline, buffer, bind_group }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, settings: &SkySettings, colors: &SkyColors) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&SkyUniform::new(settings, colors)));
+    }
+
+    // After the opaque scene, so the depth test skips every pixel it covered
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
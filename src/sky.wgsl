@@ -0,0 +1,76 @@
+/*
+Purpose: Procedural sky, drawn behind the scene instead of the flat clear color
+Responsibilites:
+    - Cover the screen with one triangle at the far plane, only where nothing else wrote depth
+    - Turn each pixel back into a view direction through the camera
+    - Shade it: horizon to zenith gradient, ground below the horizon, a sun disk with a halo
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: Must match SkyUniform in sky.rs
+struct Sky {
+    horizon: vec3<f32>,
+    // Angles in radians
+    sun_radius: f32,
+    zenith: vec3<f32>,
+    halo: f32,
+    ground: vec3<f32>,
+    show_ground: u32,
+    // Unit vector towards the sun
+    sun_direction: vec3<f32>,
+    halo_width: f32,
+    sun_color: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> sky: Sky;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Not normalized, it interpolates across the screen exactly like this
+    @location(0) direction: vec3<f32>,
+};
+
+// The world direction drawn at `ndc`. A direction has no translation, so only the rows of
+// view_proj giving clip x, y and w count: solve them for (ndc.x, ndc.y, 1).
+fn view_direction(ndc: vec2<f32>) -> vec3<f32> {
+    let m = camera.view_proj;
+    let r0 = vec3<f32>(m[0].x, m[1].x, m[2].x);
+    let r1 = vec3<f32>(m[0].y, m[1].y, m[2].y);
+    let r2 = vec3<f32>(m[0].w, m[1].w, m[2].w);
+    let det = dot(r0, cross(r1, r2));
+    return (ndc.x * cross(r1, r2) + ndc.y * cross(r2, r0) + cross(r0, r1)) / det;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let ndc = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    var out: VertexOutput;
+    // On the far plane, the depth test keeps it behind everything drawn
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.direction = view_direction(ndc);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+    let up = direction.y;
+    var color = mix(sky.horizon, sky.zenith, sqrt(max(up, 0.0)));
+    if sky.show_ground != 0u {
+        color = mix(color, sky.ground, smoothstep(0.0, 0.03, -up));
+    }
+    let angle = acos(clamp(dot(direction, sky.sun_direction), -1.0, 1.0));
+    let disk = 1.0 - smoothstep(sky.sun_radius * 0.85, sky.sun_radius, angle);
+    let halo = sky.halo * exp(-angle / max(sky.halo_width, 1e-4));
+    // The horizon cuts the sun off as it sets
+    let above = smoothstep(-0.01, 0.01, up);
+    color += sky.sun_color * (disk + halo) * above;
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,530 @@
+/*
+Purpose: Screen-space ambient occlusion
+Responsibilities:
+    - Own the SSAO pipelines, sample kernel and rotation noise (shared by every window)
+    - Declare the per-window offscreen targets (normals + depth, raw and blurred AO) and the
+      passes using them, the window's frame graph allocates them
+    - Record the prepass, occlusion, blur and composite passes
+    - ex: dust settling into the corners of the scene
+*/
+
+use crate::{camera::{Camera, Projection}, frame_graph::{FrameGraph, TransientDesc, TransientId, Transients}, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, instance::InstanceRaw, model::{self, Vertex}, render_context::{create_render_pipeline, fullscreen_pipeline, texture_entry}, texture};
+use cgmath::InnerSpace;
+use rand::{Rng, SeedableRng};
+
+// Must match the kernel array length in ssao.wgsl and ssao_normals.wgsl
+pub const MAX_KERNEL_SIZE: usize = 64;
+const NOISE_SIZE: u32 = 4;
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    // When off none of the SSAO passes are recorded
+    pub enabled: bool,
+    pub kernel_size: u32,
+    // World-space radius of the sampled hemisphere
+    pub radius: f32,
+    // Depth offset that keeps flat surfaces from occluding themselves
+    pub bias: f32,
+    // Exponent applied to the result, higher is darker
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kernel_size: 32,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    projection: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    kernel: [[f32; 4]; MAX_KERNEL_SIZE],
+    kernel_size: u32,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+}
+
+pub const SSAO_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(SsaoUniform, [projection, view, kernel, kernel_size, radius, bias, intensity]),
+    wgsl: &[("ssao.wgsl", "SsaoUniform"), ("ssao_normals.wgsl", "SsaoUniform")],
+};
+
+// Pipelines and constant data shared by every window
+pub struct SsaoPipelines {
+    uniform_layout: wgpu::BindGroupLayout,
+    ssao_layout: wgpu::BindGroupLayout,
+    ao_layout: wgpu::BindGroupLayout,
+    normals_pipeline: wgpu::RenderPipeline,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    kernel: [[f32; 4]; MAX_KERNEL_SIZE],
+    noise_view: wgpu::TextureView,
+}
+
+impl SsaoPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        // 1. Bind group layouts
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let unfilterable = wgpu::TextureSampleType::Float { filterable: false };
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[uniform_entry],
+            label: Some("SSAO Uniform Bind Group Layout"),
+        });
+        let ssao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry,
+                texture_entry(1, unfilterable),
+                texture_entry(2, unfilterable),
+            ],
+            label: Some("SSAO Bind Group Layout"),
+        });
+        let ao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0, unfilterable)],
+            label: Some("SSAO Blur Bind Group Layout"),
+        });
+
+        // 2. Pipelines
+        let normals_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("SSAO Normals Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &uniform_layout],
+                push_constant_ranges: &[],
+            });
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("SSAO Normals Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("ssao_normals.wgsl").into()),
+            };
+            create_render_pipeline(
+                device,
+                &layout,
+                NORMAL_FORMAT,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                1,
+                shader,
+            )
+        };
+        let ssao_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ssao.wgsl").into()),
+        });
+        let blur_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Blur Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ssao_blur.wgsl").into()),
+        });
+        let ssao_pipeline = fullscreen_pipeline(device, "SSAO Pipeline", &ssao_layout, &ssao_shader, "fs_ssao", AO_FORMAT, None);
+        let blur_pipeline = fullscreen_pipeline(device, "SSAO Blur Pipeline", &ao_layout, &blur_shader, "fs_blur", AO_FORMAT, None);
+        // Multiply blend (dst * src) darkens the already lit frame, alpha is left alone
+        let multiply = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::Src,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let composite_pipeline = fullscreen_pipeline(device, "SSAO Composite Pipeline", &ao_layout, &blur_shader, "fs_composite", color_format, Some(multiply));
+
+        // 3. Hemisphere kernel (z up), samples bunched towards the center. Seeded so runs look the same.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x55a0);
+        let mut kernel = [[0.0; 4]; MAX_KERNEL_SIZE];
+        for (i, sample) in kernel.iter_mut().enumerate() {
+            let direction = cgmath::Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.05..1.0f32)).normalize();
+            let t = i as f32 / MAX_KERNEL_SIZE as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            let v = direction * rng.gen_range(0.0..1.0f32) * scale;
+            *sample = [v.x, v.y, v.z, 0.0];
+        }
+
+        // 4. Rotation noise, tiled across the screen
+        let noise = (0..NOISE_SIZE * NOISE_SIZE)
+            .flat_map(|_| [rng.gen_range(-1.0..1.0f32), rng.gen_range(-1.0..1.0f32), 0.0, 0.0])
+            .collect::<Vec<_>>();
+        let noise_size = wgpu::Extent3d {
+            width: NOISE_SIZE,
+            height: NOISE_SIZE,
+            depth_or_array_layers: 1,
+        };
+        let noise_texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("SSAO Noise"),
+            size: noise_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            noise_texture.as_image_copy(),
+            bytemuck::cast_slice(&noise),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * NOISE_SIZE),
+                rows_per_image: Some(NOISE_SIZE),
+            },
+            noise_size,
+        );
+        let noise_view = noise_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            uniform_layout,
+            ssao_layout,
+            ao_layout,
+            normals_pipeline,
+            ssao_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            kernel,
+            noise_view,
+        }
+    }
+}
+
+// The window's frame graph entries, all of them done with before the main pass but the blurred
+// AO, which the composite reads after it
+pub struct SsaoTransients {
+    normals: TransientId,
+    depth: TransientId,
+    raw: TransientId,
+    blurred: TransientId,
+}
+
+impl SsaoTransients {
+    // The passes before the main pass
+    pub fn declare(graph: &mut FrameGraph, config: &wgpu::SurfaceConfiguration) -> Self {
+        let target = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let normals = graph.texture(TransientDesc::new("ssao_normals", config, NORMAL_FORMAT, target));
+        let depth = graph.texture(TransientDesc::new("ssao_depth", config, texture::Texture::DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT));
+        let raw = graph.texture(TransientDesc::new("ssao_raw", config, AO_FORMAT, target));
+        let blurred = graph.texture(TransientDesc::new("ssao_blurred", config, AO_FORMAT, target));
+        graph.pass("ssao prepass", &[], &[normals, depth]);
+        graph.pass("ssao", &[normals], &[raw]);
+        graph.pass("ssao blur", &[raw], &[blurred]);
+        Self { normals, depth, raw, blurred }
+    }
+
+    // The composite over the lit scene, after the main pass
+    pub fn declare_composite(&self, graph: &mut FrameGraph) {
+        graph.pass("ssao composite", &[self.blurred], &[]);
+    }
+}
+
+// Offscreen targets for one window, recreated with the window's frame graph.
+// The depth texture only serves the prepass, the SSAO pass reads depth from the normal target.
+pub struct SsaoTargets {
+    normal_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    ao_view: wgpu::TextureView,
+    blur_view: wgpu::TextureView,
+    uniform_buffer: Tracked<wgpu::Buffer>,
+    uniform_bind_group: wgpu::BindGroup,
+    ssao_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+}
+
+impl SsaoTargets {
+    pub fn new(device: &wgpu::Device, pipelines: &SsaoPipelines, transients: &Transients, ids: &SsaoTransients) -> Self {
+        let normal_view = transients.view(ids.normals);
+        let depth_view = transients.view(ids.depth);
+        let ao_view = transients.view(ids.raw);
+        let blur_view = transients.view(ids.blurred);
+
+        let uniform_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("SSAO Buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("SSAO Uniform Bind Group"),
+        });
+        let ssao_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipelines.ssao_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&normal_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&pipelines.noise_view),
+                },
+            ],
+            label: Some("SSAO Bind Group"),
+        });
+        let ao_bind_group = |view: &wgpu::TextureView, label: &str| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &pipelines.ao_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                }],
+                label: Some(label),
+            })
+        };
+        let blur_bind_group = ao_bind_group(&ao_view, "SSAO Blur Bind Group");
+        let composite_bind_group = ao_bind_group(&blur_view, "SSAO Composite Bind Group");
+
+        Self {
+            normal_view,
+            depth_view,
+            ao_view,
+            blur_view,
+            uniform_buffer,
+            uniform_bind_group,
+            ssao_bind_group,
+            blur_bind_group,
+            composite_bind_group,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, pipelines: &SsaoPipelines, settings: &SsaoSettings, camera: &Camera, projection: &Projection) {
+        let projection_matrix = projection.calc_matrix();
+        let uniform = SsaoUniform {
+            projection: projection_matrix.into(),
+            view: camera.calc_matrix().into(),
+            kernel: pipelines.kernel,
+            kernel_size: settings.kernel_size.clamp(1, MAX_KERNEL_SIZE as u32),
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Start the normals + depth prepass. The caller draws the scene geometry into it.
+    pub fn begin_prepass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        pipelines: &SsaoPipelines,
+        camera_bind_group: &wgpu::BindGroup,
+    ) -> wgpu::RenderPass<'a> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO Prepass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.normal_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // w = 0 marks "nothing drawn", visible geometry always has negative view z
+                    load: wgpu::LoadOp::Clear(wgpu::Color { r: 0.0, g: 0.0, b: 1.0, a: 0.0 }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipelines.normals_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        render_pass
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Compute occlusion from the prepass output, then blur it
+    pub fn encode_occlusion(&self, encoder: &mut wgpu::CommandEncoder, pipelines: &SsaoPipelines) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::WHITE);
+        Self::fullscreen_pass(encoder, "SSAO Pass", &self.ao_view, clear, &pipelines.ssao_pipeline, &self.ssao_bind_group);
+        Self::fullscreen_pass(encoder, "SSAO Blur Pass", &self.blur_view, clear, &pipelines.blur_pipeline, &self.blur_bind_group);
+    }
+
+    // Darken the finished (resolved) scene with the blurred occlusion
+    pub fn encode_composite(&self, encoder: &mut wgpu::CommandEncoder, pipelines: &SsaoPipelines, target: &wgpu::TextureView) {
+        Self::fullscreen_pass(encoder, "SSAO Composite Pass", target, wgpu::LoadOp::Load, &pipelines.composite_pipeline, &self.composite_bind_group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::CameraUniform, config::EngineConfig, frame_graph::FrameGraph, instance::Instance, model::ModelVertex, state::State, view_window::ViewWindow};
+    use cgmath::{Deg, Point3, Quaternion, Vector3, Vector4};
+    use pollster::FutureExt;
+    use wgpu::util::DeviceExt;
+
+    const SIZE: u32 = 96;
+    // What the composite multiplies the occlusion into, read back afterwards
+    const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    fn quad(corners: [[f32; 3]; 4], normal: [f32; 3]) -> Vec<ModelVertex> {
+        corners.map(|position| ModelVertex { position, tex_coords: [0.0; 2], normal, tangent: [0.0; 3], bitangent: [0.0; 3] }).to_vec()
+    }
+
+    // Where `point` lands in the SIZE x SIZE target
+    fn pixel(view_proj: cgmath::Matrix4<f32>, point: Point3<f32>) -> (u32, u32) {
+        let clip: Vector4<f32> = view_proj * point.to_homogeneous();
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        (((x * 0.5 + 0.5) * SIZE as f32) as u32, ((0.5 - y * 0.5) * SIZE as f32) as u32)
+    }
+
+    #[test]
+    fn the_crease_is_darker_than_the_open_floor() {
+        let state = State::new_headless(&EngineConfig::default()).block_on().expect("no usable GPU adapter");
+        let context = &state.context;
+        let (device, queue) = (&context.device, &context.queue);
+        let pipelines = SsaoPipelines::new(device, queue, &context.camera_bind_group_layout, TARGET_FORMAT);
+
+        // Floor on y = 0 running into a wall on x = 0, both facing the camera
+        let mut vertices = quad([[0.0, 0.0, -3.0], [0.0, 0.0, 3.0], [6.0, 0.0, 3.0], [6.0, 0.0, -3.0]], [0.0, 1.0, 0.0]);
+        vertices.extend(quad([[0.0, 0.0, -3.0], [0.0, 3.0, -3.0], [0.0, 3.0, 3.0], [0.0, 0.0, 3.0]], [1.0, 0.0, 0.0]));
+        let indices: [u32; 12] = [0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7];
+        let buffer = |label, contents: &[u8], usage| device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: Some(label), contents, usage });
+        let vertex_buffer = buffer("Corner Vertices", bytemuck::cast_slice(&vertices), wgpu::BufferUsages::VERTEX);
+        let index_buffer = buffer("Corner Indices", bytemuck::cast_slice(&indices), wgpu::BufferUsages::INDEX);
+        let instance = Instance::placed(Vector3::new(0.0, 0.0, 0.0), Quaternion::new(1.0, 0.0, 0.0, 0.0), Vector3::unit_y()).to_raw(0.0);
+        let instance_buffer = buffer("Corner Instance", bytemuck::bytes_of(&instance), wgpu::BufferUsages::VERTEX);
+
+        let mut camera = Camera::new((4.0, 2.5, 0.0), Deg(0.0), Deg(0.0));
+        camera.look_at(Point3::new(1.5, 0.0, 0.0));
+        let projection = Projection::new(SIZE, SIZE, Deg(60.0), 0.1, 50.0);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, &projection);
+        let camera_buffer = buffer("Corner Camera", bytemuck::bytes_of(&camera_uniform), wgpu::BufferUsages::UNIFORM);
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: camera_buffer.as_entire_binding() }],
+            label: Some("Corner Camera Bind Group"),
+        });
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: TARGET_FORMAT,
+            width: SIZE,
+            height: SIZE,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let mut graph = FrameGraph::default();
+        let ids = SsaoTransients::declare(&mut graph, &config);
+        ids.declare_composite(&mut graph);
+        let transients = Transients::new(device, &graph);
+        let targets = SsaoTargets::new(device, &pipelines, &transients, &ids);
+        // Wider and stronger than the default, so the crease stands well clear of any noise
+        let settings = SsaoSettings { radius: 1.0, intensity: 2.0, ..SsaoSettings::default() };
+        targets.update(queue, &pipelines, &settings, &camera, &projection);
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Corner Target"),
+            size: wgpu::Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Corner Encoder") });
+        {
+            let mut render_pass = targets.begin_prepass(&mut encoder, &pipelines, &camera_bind_group);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..indices.len() as u32, 0, 0..1);
+        }
+        targets.encode_occlusion(&mut encoder, &pipelines);
+        {
+            // White, so what is left after the multiply is the occlusion itself
+            let _clear = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Corner Clear"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::WHITE), store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+        }
+        targets.encode_composite(&mut encoder, &pipelines, &target_view);
+        queue.submit(std::iter::once(encoder.finish()));
+        let image = ViewWindow::capture_frame(context, &target).unwrap();
+
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        let occlusion = |point: Point3<f32>| {
+            let (x, y) = pixel(view_proj, point);
+            image.get_pixel(x, y)[0]
+        };
+        let crease = occlusion(Point3::new(0.05, 0.05, 0.0));
+        let open_floor = occlusion(Point3::new(3.0, 0.0, 0.0));
+        assert!(crease + 40 < open_floor, "crease {} vs open floor {}", crease, open_floor);
+        assert!(open_floor > 200, "open floor {}", open_floor);
+    }
+}
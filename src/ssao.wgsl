@@ -0,0 +1,96 @@
+/*
+Purpose: Screen-space ambient occlusion
+Responsibilites:
+    - fs_ssao: sample a hemisphere kernel around each pixel and count occluded samples
+    - Blurring and compositing live in ssao_blur.wgsl
+    - ex: the shadows that collect in creases and corners
+*/
+
+struct SsaoUniform {
+    projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    kernel: array<vec4<f32>, 64>,
+    kernel_size: u32,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// SSAO pass bindings
+@group(0) @binding(0)
+var<uniform> ssao: SsaoUniform;
+// xyz: view-space normal, w: view-space depth (0 where nothing was drawn)
+@group(0) @binding(1)
+var t_normal_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var t_noise: texture_2d<f32>;
+
+// Rebuild the view-space position of a pixel from its depth and the projection
+fn view_position(pixel: vec2<i32>, size: vec2<f32>, view_z: f32) -> vec3<f32> {
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    return vec3<f32>(
+        ndc.x * -view_z / ssao.projection[0][0],
+        ndc.y * -view_z / ssao.projection[1][1],
+        view_z,
+    );
+}
+
+@fragment
+fn fs_ssao(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let size = vec2<f32>(textureDimensions(t_normal_depth));
+    let normal_depth = textureLoad(t_normal_depth, pixel, 0);
+
+    // Nothing was drawn here, leave it unoccluded
+    if normal_depth.w >= 0.0 {
+        return vec4<f32>(1.0);
+    }
+
+    let position = view_position(pixel, size, normal_depth.w);
+    let normal = normalize(normal_depth.xyz);
+
+    // Rotate the kernel per pixel with the tiled noise texture, Gram-Schmidt into a TBN basis
+    let noise_size = vec2<i32>(textureDimensions(t_noise));
+    let random = textureLoad(t_noise, pixel % noise_size, 0).xyz;
+    let tangent = normalize(random - normal * dot(random, normal));
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+    var occlusion = 0.0;
+    for (var i = 0u; i < ssao.kernel_size; i++) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+
+        // Project the sample to find which pixel's depth to compare against
+        let clip = ssao.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            continue;
+        }
+        let scene_z = textureLoad(t_normal_depth, vec2<i32>(uv * size), 0).w;
+        if scene_z >= 0.0 {
+            continue;
+        }
+
+        // Only count occluders within the radius, so distant geometry doesn't darken edges
+        let range_check = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - scene_z));
+        occlusion += select(0.0, 1.0, scene_z >= sample_position.z + ssao.bias) * range_check;
+    }
+
+    let ao = 1.0 - occlusion / f32(max(ssao.kernel_size, 1u));
+    return vec4<f32>(pow(ao, ssao.intensity), 0.0, 0.0, 1.0);
+}
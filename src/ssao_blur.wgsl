@@ -0,0 +1,43 @@
+/*
+Purpose: SSAO blur and composite
+Responsibilites:
+    - fs_blur: 4x4 box blur that hides the rotation noise pattern
+    - fs_composite: multiply the blurred occlusion into the lit frame
+*/
+
+// Fullscreen triangle, no vertex buffer needed
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// Blur and composite bindings
+@group(0) @binding(0)
+var t_ao: texture_2d<f32>;
+
+@fragment
+fn fs_blur(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let max_pixel = vec2<i32>(textureDimensions(t_ao)) - 1;
+    var sum = 0.0;
+    for (var x = -2; x < 2; x++) {
+        for (var y = -2; y < 2; y++) {
+            sum += textureLoad(t_ao, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), max_pixel), 0).r;
+        }
+    }
+    return vec4<f32>(sum / 16.0, 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ao = textureLoad(t_ao, vec2<i32>(in.clip_position.xy), 0).r;
+    // Blended as dst * src, so this darkens whatever the scene pass drew
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
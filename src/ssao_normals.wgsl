@@ -0,0 +1,80 @@
+/*
+Purpose: SSAO geometry prepass
+Responsibilites:
+    - Write view-space normals (xyz) and view-space depth (w) of the instanced scene
+      into an offscreen target for the SSAO pass
+*/
+
+// Group 0: Camera
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Group 1: SSAO parameters (only the view matrix is used here)
+struct SsaoUniform {
+    projection: mat4x4<f32>,
+    view: mat4x4<f32>,
+    kernel: array<vec4<f32>, 64>,
+    kernel_size: u32,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+};
+@group(1) @binding(0)
+var<uniform> ssao: SsaoUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) view_normal: vec3<f32>,
+    @location(1) view_z: f32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_normal = normalize(normal_matrix * model.normal);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.view_normal = (ssao.view * vec4<f32>(world_normal, 0.0)).xyz;
+    out.view_z = (ssao.view * world_position).z;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(normalize(in.view_normal), in.view_z);
+}
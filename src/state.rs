@@ -1,730 +1,5308 @@
 /*
-Purpose: Manages global GPU + winfow state
+Purpose: Manages the shared scene state
 Responsibilities:
-    - Initilize WGPU (device, queue, surface, pipeline)
-    - Store the RenderPipeline, SurfaceConfiguration, and window size
-    - Own a collection of Renderable objects
-    - Handle resizing, updating transforms, and rendering per frame
+    - Hold a handle to the shared RenderContext (device, queue, pipelines)
+    - Own the scene data every window looks at (light, models and their instances)
+    - Handle updating transforms and rendering a frame into any ViewWindow
     - ex: engine room
 */
 
-use crate::{camera::{Camera, CameraUniform, Controller, Projection}, instance::{Instance, InstanceRaw}, light, model::{self, DrawLight, DrawModel, Vertex}, resources, texture};
+use crate::{animation_path::{self, AnimationPaths, PathEntity}, camera::{self, Camera}, camera_controller::{ControllerProfile, ControllerTunables}, click_move::{self, ActorState}, clip_planes::ClipPlanes, clipboard_image::{self, PastedTexture}, color, config::{EngineConfig, RenderMode}, console::{self, Console}, cursor::{CursorContext, CursorStack}, custom_shader::{self, CustomShader, FrameUniform, ShaderWatcher}, day_night::DayNightCycle, dice_demo, debug_lines::LineBuffer, engine_events::{EngineEvent, EventBus, ListenerId}, diagnostics, edge_outline::{OutlineMode, OutlineSettings}, error_log::Severity, gui_window::{self, CompareWindow, EngineApi, ExportWindow, GuiWindows, HistoryWindow, LightWindow, MeasureWindow, ScriptsWindow, SettingsWindow, StatsWindow}, foliage::{DensityMap, GrassField, HeightMap, ScatterSettings}, frame_pacer::{FrameCaps, FramePacer, MAX_FPS_CAP, Pace}, frame_stats::FrameStats, gpu_memory::{self, Tracked}, gpu_timer::{GpuPass, GpuTimer}, import_options::ImportOptions, input_map::{Category, InputMap, When}, particles::{EmitterSettings, ParticleEmitter}, picking::{FIRST_PICK_ID, PickDraw, PickResult}, point_lights::{self, MAX_POINT_LIGHTS, PointLight, PointLightId, PointLights}, probes::{ProbeDesc, ProbeId, ReflectionProbe}, profiler::{self, Profiler}, quad_2d::{self, Quad2D, QuadBatcher, QuadDemo, QuadTexture}, instance::{Distribution, Instance, MAX_INSTANCES, clamp_scale}, instance_cull::{self, CullCounts, CullMode, CulledDraw, CulledInstances}, light, light_anim::LightAnimation, material_array::{self, DrawPacked}, material_set::{self, MapKind, MapSource, MaterialSetCache}, math::{self, Aabb, Frustum, Plane}, measure::{self, Measurements}, mesh_optimize::LoadOptions, model::{self, DrawGeometry, DrawLight, DrawModel, MaterialParams, MeshRef, ShadingModel}, model_entry::{ALL_LAYERS, DEFAULT_LAYER, InstanceId, ModelEntry, ModelHandle}, overlay::{self, OverlayBias, OverlayKind, OverlayRenderer}, render_context::RenderContext, render_matrix::{self, MatrixPreset, RenderVariant}, resources, road::RoadTool, rtt::{self, MirrorDemo, RttCamera, RttDesc, RttId}, rust_literal::ToRustLiteral, scene_gen::{self, ShapeKind}, scripting::{ScriptHost, ScriptInfo, ScriptWorld}, shape_lod::{LOD_TINTS, LodSettings, LodStats, LodView}, sdf::SdfShape, skinning::SkinningDemo, shape_renderer::{self, DynamicShape, ShapeScene, ShapeStyle}, shapes, sky::{SkyColors, SkyMode, SkyRenderer, SkySettings}, hdr::{HdrSettings, HdrTargets, Tonemapper}, hiz::{self, HiZTargets}, motion_blur::MotionBlurSettings, ssao::{self, SsaoSettings}, stereo::{self, Eye, StereoMode, StereoSettings}, taa::TaaSettings, toast::Toast, texture::{Atlas, Texture}, texture_residency::TextureResidency, texture_stream::TextureStreamer, texture_watch::TextureWatcher, title_bar, toon::{RenderStyle, ToonSettings}, transform_gizmo::{self, GizmoMode, GizmoTransform, TransformGizmo}, ui_theme::{self, EngineTheme}, undo::{Command, InstanceTransform, UndoStack}, units::SceneUnits, user_settings::UserSettings, vertex_pulling::{self, DrawPulled}, video_export::{ExportSpan, VideoExport}, view_window::{ViewKind, ViewWindow}};
+use pollster::FutureExt;
+use rand::{Rng, seq::SliceRandom};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
 use std::sync::Arc;
-use wgpu::{util::DeviceExt};
-use winit::{event::{MouseButton, MouseScrollDelta, WindowEvent}, event_loop::ActiveEventLoop, keyboard::KeyCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use winit::keyboard::KeyCode;
 use winit::window::Window;
 use cgmath::prelude::*;
 use egui::Context;
-use egui_wgpu::wgpu::{CommandEncoder, Device, Queue, StoreOp, TextureView};
-use egui_wgpu::{wgpu, Renderer, ScreenDescriptor};
-use egui_winit::State as EguiState;
 
+// Degrees per second every grid instance spins around its own axis
+const INSTANCE_SPIN_SPEED: f32 = 30.0;
 
-// We'll create a struct to manage our GPU state
+// Everything the instance grid is built from, the grid is rebuilt when this changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct InstanceLayout {
+    distribution: Distribution,
+    seed: u64,
+    columns: u32,
+    rows: u32,
+    offset: [f32; 3],
+    atlas_demo: bool,
+    atlas_regions: usize,
+    scale_jitter: f32,
+}
+
+// CPU time spent posing the instance grid each frame, per path (None until that path has run)
+#[derive(Debug, Clone, Copy, Default)]
+struct InstanceAnimationStats {
+    cpu_path_ms: Option<f32>,
+    gpu_path_ms: Option<f32>,
+}
+
+// Edge length of the light marker at gizmo scale 1 and intensity 1, the cube model is 2 units wide
+const LIGHT_MARKER_SIZE: f32 = 0.25;
+// Ambient of the scene light as a fraction of its color at intensity 1, unless the day-night cycle sets it
+const LIGHT_AMBIENT: f32 = 0.1;
+// Degrees per second of the default light track around the Y axis
+const LIGHT_ORBIT_SPEED: f32 = 60.0;
+// The scene light's id among the entities animation paths are drawn for
+const LIGHT_PATH_ID: u64 = 0;
+const CLEAR_COLOR: [f32; 3] = [0.1, 0.2, 0.3];
+
+// Longest step the simulation takes in one update, so the first frame after rendering on
+// demand sat idle doesn't jump
+const MAX_SIMULATION_STEP: f32 = 0.1;
+// Engine events listed in the Frame pacing window
+const EVENT_LOG_LEN: usize = 8;
+
+// Where the profiler window dumps Chrome traces, relative to the working directory
+const PROFILER_TRACE_PATH: &str = "profile_trace.json";
+
+// Ctrl+D puts the copy this far from the original so both stay visible
+const DUPLICATE_OFFSET: cgmath::Vector3<f32> = cgmath::Vector3::new(0.5, 0.0, 0.5);
+
+// Where the SDF demo mesh floats, above the instance grid
+const SDF_DEMO_POSITION: [f32; 3] = [0.0, 4.0, 0.0];
+// Base of the skinning demo column, left of the SDF demo
+const SKINNING_DEMO_POSITION: [f32; 3] = [-4.0, 3.0, 0.0];
+// Right of the SDF demo, mirroring the skinning demo
+const DICE_DEMO_POSITION: [f32; 3] = [4.0, 3.0, 0.0];
+// Meters above the overlay test ground and from its center, the view looks across the center
+// and over the far half at about one degree
+const OVERLAY_GRAZING_VIEW: [f32; 2] = [1.5, 40.0];
+// Facing the default camera from behind the instance grid
+const MIRROR_DEMO_POSITION: [f32; 3] = [0.0, 2.0, -8.0];
+// Grass demo ground, below the instance grid
+const GRASS_FIELD_ORIGIN: [f32; 3] = [0.0, -4.0, 0.0];
+const GRASS_FIELD_EXTENT: f32 = 20.0;
+// Where the click-to-move actor starts on the ground, in front of the instance grid
+const CLICK_MOVE_START: [f32; 2] = [0.0, 6.0];
+// The camera's offset when it follows the actor, behind and above it
+const CLICK_MOVE_FOLLOW_OFFSET: [f32; 3] = [0.0, 4.0, 8.0];
+// Meters. The occluder wall rises this far above the instance grid and stands this far in front
+// of it, this thick.
+const OCCLUDER_WALL_RISE: f32 = 2.0;
+const OCCLUDER_WALL_GAP: f32 = 1.0;
+const OCCLUDER_WALL_THICKNESS: f32 = 0.5;
+// Meters above the wall's top and in front of it the view over it looks from. Level with the
+// top the wall hides the grid, what the cull still draws is what its bounds reach over.
+const OCCLUDER_VIEW: [f32; 2] = [0.0, 8.0];
+const PROBE_RESOLUTIONS: [u32; 4] = [64, 128, 256, 512];
+
+// What the SDF demo mesh is built from, it is remeshed when this changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SdfDemoSettings {
+    shape: SdfShape,
+    blend: f32,
+    resolution: u32,
+}
+
+// Spring stiffness the menu's Follow button starts with
+const DEFAULT_FOLLOW_STIFFNESS: f32 = 4.0;
+
+// Instance the main window's camera follows, see set_camera_follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CameraFollowTarget {
+    instance: InstanceId,
+    offset: cgmath::Vector3<f32>,
+    stiffness: f32,
+    // Handed to the main window's camera yet
+    started: bool,
+}
+
+// The render settings before a RenderVariant changed them, see render_matrix
+struct RenderSnapshot {
+    hdr_settings: HdrSettings,
+    ssao_settings: SsaoSettings,
+    render_style: RenderStyle,
+    toon_settings: ToonSettings,
+    clear_color: [f32; 3],
+    materials: Vec<(ModelHandle, usize, MaterialParams)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SdfDemoStats {
+    vertices: usize,
+    triangles: usize,
+    build_ms: f32,
+}
+
+// We'll create a struct to manage the scene shared between windows
 pub struct State {
-    surface: wgpu::Surface<'static>, // The surface (connection between window & GPU)
-    pub device: wgpu::Device, // Logical device (our handle to the GPU)
-    pub queue: wgpu::Queue, // Command queue to submit work to the GPU
-    config: wgpu::SurfaceConfiguration, pub(crate) // How the surface is configured (size, format, etc.)
-    size: winit::dpi::PhysicalSize<u32>,
-    is_surface_configured: bool,
-    pub window: Arc<Window>,
-    render_pipeline: wgpu::RenderPipeline,
-    camera: Camera,
-    projection: Projection,
-    pub controller: Controller,
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
-    depth_texture: texture::Texture,
-    obj_model: model::Model,
+    pub context: Arc<RenderContext>,
     light_uniform: light::LightUniform,
     light_bind_group: wgpu::BindGroup,
-    light_buffer: wgpu::Buffer,
-    light_render_pipeline: wgpu::RenderPipeline,
+    light_buffer: Tracked<wgpu::Buffer>,
+    // Slice models open, shares the light bind group
+    clip_planes: ClipPlanes,
+    clip_buffer: Tracked<wgpu::Buffer>,
+    // Placed by clicking in the scene, also in the light bind group
+    point_lights: PointLights,
+    point_light_buffer: Tracked<wgpu::Buffer>,
+    point_light_markers: LineBuffer,
+    // Distances measured by clicking in the scene, for the session like the lights
+    pub measurements: Measurements,
+    measurement_lines: LineBuffer,
+    // custom_shader::FRAME_GROUP, only bound while a custom shader declares it
+    frame_uniform: FrameUniform,
+    frame_buffer: Tracked<wgpu::Buffer>,
+    frame_bind_group: wgpu::BindGroup,
     last_frame: std::time::Instant,
-    pub mouse_pressed: bool,
-    scale_factor: f32,
-    pub show_menu: bool,
+    // Whether a window drew in this event loop iteration yet, see update_for_view
+    updated_this_iteration: bool,
+    // Every shortcut, the app dispatches key presses through it
+    pub input_map: InputMap,
+    // Shortcut help overlay, generated from input_map
+    pub show_help: bool,
+    help_filter: String,
+    // Toggled with the backtick key, its commands run in draw_console
+    pub console: Console,
+    // Windows drawn over the main window, listed in the Windows menu
+    gui_windows: GuiWindows,
+    // Behind the scene unless the day-night cycle colors the sky
+    clear_color: [f32; 3],
+    // Saved by the next render of the main window, see request_screenshot
+    screenshot_request: Option<std::path::PathBuf>,
+    // Rendered before the next frame of the main window, see request_render_matrix
+    matrix_request: Option<(MatrixPreset, std::path::PathBuf)>,
+    // Renders one frame per main window frame until it is done or cancelled, see export_video
+    video_export: Option<VideoExport>,
+    // Where the camera was before an export's camera path took it over, put back afterwards
+    camera_before_export: Option<Camera>,
+    // Set by the quit command, App exits once the frame is out
+    quit_requested: bool,
+    pub render_mode: RenderMode,
+    // Set by request_redraw, App redraws every window once and clears it
+    redraw_requested: bool,
+    // Whether the last check found the GPU memory over budget, the overlay warns when it goes over
+    over_memory_budget: bool,
+    // Evicts model textures beyond its budget and brings them back, see texture_residency.rs
+    texture_residency: TextureResidency,
+    // Plays light_animation, turn off to let on-demand rendering go idle
+    orbit_light: bool,
+    // Keyframes for the scene light, an orbit around the origin unless replaced
+    light_animation: LightAnimation,
+    light_animation_time: f32,
+    // Where keyframed entities (the scene light) are headed, drawn as debug lines
+    animation_paths: AnimationPaths,
+    // Drives the scene light and the clear color instead of light_animation while enabled
+    day_night: DayNightCycle,
+    // Samples are collected even while the frame pacing window is closed
+    frame_stats: FrameStats,
+    // Always recording the last few seconds of scopes, shown when its window is open
+    profiler: Profiler,
     num_of_instances: u32,
+    num_of_instance_rows: u32,
+    // Shape of the default scene's instances, the random ones are the same for the same seed
+    instance_distribution: Distribution,
+    instance_seed: u64,
     instance_position_x: f32,
     instance_position_y: f32,
     instance_position_z: f32,
-    egui_state: EguiState,
-    egui_renderer: Renderer,
-    egui_frame_started: bool,
-}
-
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),  // vertex shader function
-                buffers: vertex_layouts,
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"), // fragment shader function
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: color_format,
-                    blend: Some(wgpu::BlendState {
-                        alpha: wgpu::BlendComponent::REPLACE,
-                        color: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-                format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-    })
+    // Draw instances with the demo atlas, each showing a random region of it
+    atlas_demo: bool,
+    atlas_assignment: Vec<[f32; 4]>,
+    // How far each grid instance's scale strays from 1 on every axis, and the random
+    // direction (-1..1 per axis) each one strays in, kept so the slider grows the same jitter
+    scale_jitter: f32,
+    scale_jitter_directions: Vec<cgmath::Vector3<f32>>,
+    // Every model in the scene with its own instances, the instance grid's model is the first entry
+    models: Vec<ModelEntry>,
+    grid_model: ModelHandle,
+    next_model_handle: u32,
+    // Menu state for loading models and moving the last spawned instance
+    model_path_input: String,
+    // Texture pack prefix and shape for the models panel's "Spawn textured"
+    material_set_input: String,
+    material_set_shape: ShapeKind,
+    // Decoded texture packs by prefix, and the model each pack and shape became
+    material_sets: MaterialSetCache,
+    material_set_models: HashMap<(String, ShapeKind), ModelHandle>,
+    model_load_error: Option<String>,
+    // Path and options of a load waiting in the import dialog
+    import_dialog: Option<(String, ImportOptions)>,
+    // Substring the Show/Hide buttons match mesh names against
+    mesh_filter_input: String,
+    selected_instance: Option<InstanceId>,
+    // Euler degrees shown for the selection and the rotation they were made from. They are shown
+    // again while the rotation hasn't changed, so reading them back never nudges the quaternion.
+    selection_euler: Option<(InstanceId, cgmath::Quaternion<f32>, [f32; 3])>,
+    // Ctrl+Z and Ctrl+Shift+Z, see record
+    pub undo: UndoStack,
+    // Clipboard images shown on models, at most one per model. Counted for their file names.
+    pasted_textures: Vec<PastedTexture>,
+    pasted_count: u32,
+    // Handles drawn around the selected instance, W/E/R pick the mode
+    pub transform_gizmo: TransformGizmo,
+    // Model whose next instance goes where the scene is clicked, see begin_placement
+    placing: Option<ModelHandle>,
+    // The next click places a point light instead, see begin_light_placement
+    placing_light: bool,
+    // Model whose Frame button was clicked, framed in the window the menu is drawn in
+    frame_request: Option<ModelHandle>,
+    // Control points clicked onto the ground and the road mesh through them, see road.rs
+    road: RoadTool,
+    // Tinted selection, placement footprint and measured instance, see overlay.rs
+    overlay: OverlayRenderer,
+    overlay_bias: OverlayBias,
+    // Drawn behind the scene instead of the clear color, see sky.rs
+    sky: SkyRenderer,
+    sky_settings: SkySettings,
+    // Around the selection or along every edge, see edge_outline.rs
+    outline: OutlineSettings,
+    // The instance under the cursor while measuring
+    measure_hover_instance: Option<InstanceId>,
+    camera_follow: Option<CameraFollowTarget>,
+    // Clicks select through the ID buffer, off falls back to the cheaper bounding box ray test
+    pub precise_picking: bool,
+    // Cursor contexts pushed by hooks, resolved with the camera and gizmo ones every frame
+    cursor_stack: CursorStack,
+    instance_layout: Option<InstanceLayout>,
+    // Pose instances with the compute pass instead of uploading matrices every frame
+    instance_animation_gpu: bool,
+    animation_stats: InstanceAnimationStats,
+    // Frustum culling of the instances in the main window's main pass, see instance_cull.rs
+    instance_culling: CullMode,
+    // What the main window's instances were last culled against, None while culling is off
+    cull_frustum: Option<Frustum>,
+    // Set while the main window's scene passes are encoded, the main pass draws the culled buffers
+    draw_culled: bool,
+    // GPU culling also drops what the depth pre-pass hides, see occlusion_culling_active
+    occlusion_culling: bool,
+    // The last cull's results are drawn again instead of culling, to fly around and see them
+    freeze_culling: bool,
+    // Tessellation of the procedural shapes by their size on screen, see shape_lod.rs
+    pub shape_lod: LodSettings,
+    // The main window's camera as of its last frame, what the levels are picked for
+    lod_view: Option<LodView>,
+    lod_stats: LodStats,
+    // Seconds of unpaused simulation, drives the instance spin
+    animation_time: f32,
+    pub ssao_settings: SsaoSettings,
+    pub motion_blur: MotionBlurSettings,
+    // Jitters every window's camera while on, see taa.rs
+    pub taa: TaaSettings,
+    // The main window's stereo 3D, see stereo.rs
+    pub stereo: StereoSettings,
+    // Models neither eye sees this frame, skipped by the main pass. Empty without stereo.
+    stereo_culled: Vec<ModelHandle>,
+    pub show_gizmo: bool,
+    // Size of in-scene debug gizmos like the light marker, so they don't dwarf small scenes
+    pub gizmo_scale: f32,
+    // What a scene unit measures, every window's camera speed and depth range follow it
+    units: SceneUnits,
+    pub hdr_settings: HdrSettings,
+    // Both styles' pipelines exist from startup, switching only changes what draw_scene binds
+    pub render_style: RenderStyle,
+    pub toon_settings: ToonSettings,
+    // Materials paint their shading model instead of lighting, see set_shading_model_view
+    show_shading_models: bool,
+    // Models write depth in a pass of their own first, see encode_depth_prepass
+    depth_prepass: bool,
+    // Models fetch their vertices in the vertex shader where the adapter can, see vertex_pulling.rs
+    vertex_pulling: bool,
+    // Models loaded with --pack-textures draw from their texture arrays, off draws them like the others
+    draw_packed_materials: bool,
+    // Material bind group switches of the last frame, see material_array::count_material_bind
+    material_binds: u32,
+    // Set by App while no window has focus, the simulation stops advancing
+    pub paused: bool,
+    pub pause_on_focus_loss: bool,
+    // Set by App while no window has focus, frames drop to the background cap
+    pub in_background: bool,
+    frame_caps: FrameCaps,
+    // Pushed to every window's camera controller at the start of its frame, saved in the settings file
+    invert_mouse_y: bool,
+    // Pushed to every window like invert_mouse_y, the window swaps its scheme when it changes
+    controller_profile: ControllerProfile,
+    controller_tunables: ControllerTunables,
+    // Procedural shapes from --random-scene
+    shape_scene: Option<ShapeScene>,
+    // Main window size picked from the menu, requested by App after the frame
+    window_size_request: Option<winit::dpi::PhysicalSize<u32>>,
+    // Soft particle demo rising out of the middle of the instance grid
+    show_particles: bool,
+    // This frame's 2D quads, queued with draw_quad_2d
+    quads_2d: QuadBatcher,
+    quad_demo: QuadDemo,
+    particles: ParticleEmitter,
+    // Linearized depth drawn live into a corner of the main window
+    show_depth_thumbnail: bool,
+    show_sdf_demo: bool,
+    sdf_demo_settings: SdfDemoSettings,
+    // The mesh and the settings it was last built with
+    sdf_demo: Option<(SdfDemoSettings, DynamicShape)>,
+    sdf_demo_stats: Option<SdfDemoStats>,
+    show_skinning_demo: bool,
+    // Built when first shown, its model is a scene entry like any other
+    skinning_demo: Option<SkinningDemo>,
+    show_grass: bool,
+    grass_settings: ScatterSettings,
+    grass_density: DensityMap,
+    grass_density_input: String,
+    grass_wind_strength: f32,
+    // Built the first time the demo is shown, rescattered by the Regenerate button
+    grass_field: Option<GrassField>,
+    grass_scatter_ms: f32,
+    // Baked on request, one face per update, see bake_next_probe_face
+    reflection_probes: Vec<ReflectionProbe>,
+    next_probe_id: u32,
+    // Menu choice for the next probe added
+    probe_resolution: u32,
+    // Rendered before the main pass of the main window, see render_rtt_cameras
+    rtt_cameras: Vec<RttCamera>,
+    next_rtt_id: u32,
+    // Levels of monitors showing monitors, every camera renders this many times a frame
+    rtt_recursion_depth: u32,
+    show_mirror_demo: bool,
+    // Built when first shown, a model entry and an RTT camera
+    mirror_demo: Option<MirrorDemo>,
+    show_dice_demo: bool,
+    // Built when first shown, see dice_demo.rs
+    dice_demo: Option<ModelHandle>,
+    show_click_move_demo: bool,
+    // The actor, its state in its user data, see click_move.rs
+    click_move_actor: Option<InstanceId>,
+    // Meters per second
+    click_move_speed: f32,
+    // The overlay regression scene's ground, built when first shown
+    show_overlay_ground: bool,
+    overlay_ground: Option<ModelHandle>,
+    // The menu's grazing angle button, handled in the window the menu is drawn in
+    grazing_view_request: bool,
+    // The occlusion culling test scene's wall, built when first shown
+    show_occluder_wall: bool,
+    occluder_wall: Option<ModelHandle>,
+    // Like grazing_view_request, for the view over the wall
+    occluder_view_request: bool,
+    // Styling of every window's egui layer, saved to the settings file when it changes
+    theme: EngineTheme,
+    user_settings: UserSettings,
+    // Present with --hot-reload on
+    texture_watcher: Option<TextureWatcher>,
+    // Present with --hot-reload on when the source tree's shader.wgsl is there
+    shader_watcher: Option<ShaderWatcher>,
+    // The last reloaded model shader that checked out, in place of render_pipeline
+    custom_shader: Option<CustomShader>,
+    // The scripts folder's *.rhai, run every simulation step
+    scripts: ScriptHost,
+    // In any window, what scripts' key_down sees
+    held_keys: HashSet<KeyCode>,
+    // Confirms copies, drawn over the primary window
+    toast: Toast,
+    // Pushed to from anywhere in here, dispatched at the end of every update
+    events: EventBus,
+    // The newest events first, for the Frame pacing window
+    event_log: Rc<RefCell<VecDeque<String>>>,
+    // The selection the last SelectionChanged announced
+    announced_selection: Option<InstanceId>,
+    // Set by the device's lost callback, which may run on another thread
+    device_lost: Arc<AtomicBool>,
 }
 
-
-
 impl State {
-    // Async setup because GPU initialization may take time
-    pub async fn new(window: Window) -> Self {
-        // Get window size
-        let size = window.inner_size();
+    // Async setup because GPU initialization may take time.
+    // Returns the state together with the primary window's view.
+    pub async fn new(window: Window, config: &EngineConfig) -> anyhow::Result<(Self, ViewWindow)> {
         let window = Arc::new(window);
 
         // 1. Create GPU instance (entry point to wgpu)
         let instance = wgpu::Instance::default();
 
         // 2. Choose an surface (binds GPU rendering to our window)
-        let surface = instance.create_surface(window.clone()).unwrap();
-
-        // 3. Choose an adapter (represents a physical GPU)
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .expect("Failed to find an appropriate adapter");
-
-        // 4. Request device and queue (logical GPU + command queue)
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::default(),
-                    memory_hints: wgpu::MemoryHints::default(),
-                    trace: wgpu::Trace::Off, // trace path
-                },
-            )
-            .await
-            .unwrap();
+        let surface = instance.create_surface(window.clone())?;
 
-        // 5. Get the surface's preferred format (like RGBA8Unorm)
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps.formats[0];
+        // 3. Create the device, queue, pipelines and assets shared by every window
+        let context = Arc::new(RenderContext::new(instance, Some(&surface), config.render, &config.model_path).await?);
 
-        // 6. Configure the surface with width, height, format, and presentation mode
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
-        surface.configure(&device, &config);
+        let scale = config.units.units_per_meter();
+        let camera = Camera::new((0.0, 5.0 * scale, 10.0 * scale), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let view = ViewWindow::new(&context, window, surface, ViewKind::Primary, camera);
 
-        let egui_context = Context::default();
+        Ok((Self::from_context(context, config), view))
+    }
 
-        let egui_state = egui_winit::State::new(
-            egui_context,
-            egui::viewport::ViewportId::ROOT,
-            &window,
-            Some(window.scale_factor() as f32),
-            None,
-            Some(2 * 1024), // default dimension is 2048
-        );
-        let egui_renderer = Renderer::new(
-            &device,
-            config.format,
-            None,
-            1,
-            true,
-        );
+    // Same scene without any window, used when no surface can be created
+    pub async fn new_headless(config: &EngineConfig) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::default();
+        let context = Arc::new(RenderContext::new(instance, None, config.render, &config.model_path).await?);
+        Ok(Self::from_context(context, config))
+    }
 
-        // let egui_renderer = EguiRenderer::new(&device, config.format, None, 1, &window);
+    fn from_context(context: Arc<RenderContext>, config: &EngineConfig) -> Self {
+        if let Some(scene_path) = &config.scene_path {
+            log::warn!("Scene files are not supported yet, ignoring {}", scene_path.display());
+        }
 
-        // Grabbing the bytes from the image file and load them into an image
-        // which is then converted into a Vec of RGBA bytes
-        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        // Creating buffer to store light
+        let light_uniform = light::LightUniform {
+            position: [2.0, 2.0, 2.0],
+            marker_scale: LIGHT_MARKER_SIZE,
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            ambient: LIGHT_AMBIENT,
+            _padding: [0.0; 3],
+        };
+        let light_animation = LightAnimation::orbit(light_uniform.position.into(), LIGHT_ORBIT_SPEED);
+        let light_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Buffer"),
+                contents: bytemuck::cast_slice(&[light_uniform.linear()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        let clip_planes = ClipPlanes::default();
+        let clip_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Clip Plane Buffer"),
+            contents: bytemuck::bytes_of(&clip_planes.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let point_lights = PointLights::default();
+        let point_light_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Point Light Buffer"),
+            contents: bytemuck::bytes_of(&point_lights.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.light_bind_group_layout,
             entries: &[
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
+                    resource: light_buffer.as_entire_binding(),
                 },
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+                    resource: clip_buffer.as_entire_binding(),
                 },
-                wgpu::BindGroupLayoutEntry {
+                wgpu::BindGroupEntry {
                     binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
+                    resource: point_light_buffer.as_entire_binding(),
                 },
             ],
-            label: Some("texture_bind_group_layout"),
+            label: Some("Light Bind Group"),
         });
-        // 9. Setup Camera uniform buffer and bind group
-        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection = Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let controller = Controller::new(4.0, 1.0);
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera, &projection);
-
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
+        let frame_uniform = FrameUniform::default();
+        let frame_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frame Buffer"),
+            contents: bytemuck::bytes_of(&frame_uniform),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("Camera Bind Group Layout"),
-        });
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
+        let frame_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.frame_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
+                binding: custom_shader::FRAME_BINDING,
+                resource: frame_buffer.as_entire_binding(),
             }],
-            label: Some("Camera Bind Group"),
+            label: Some("Frame Bind Group"),
         });
 
-        // 10. Setting up instances
-        let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-
-        let obj_model = resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout).await.unwrap();
-
-        // Creating buffer to store light
-        let light_uniform = light::LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
-        let light_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Light VB"),
-                contents: bytemuck::cast_slice(&[light_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
-        );
-        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: None,
-        });
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
+        let shape_scene = config.random_scene.map(|options| {
+            let description = scene_gen::generate(config.seed, &options);
+            log::info!(
+                "Generated scene from seed {}: {} shapes, {} lights",
+                config.seed,
+                description.shapes.len(),
+                description.lights.len()
+            );
+            ShapeScene::new(&context.device, &context.shape_pipeline, description)
         });
 
-        // 10. Define pipeline layout
-        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Pipeline Layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout, &light_bind_group_layout],
-            push_constant_ranges: &[],
+        let particles = ParticleEmitter::new(&context.device, EmitterSettings::default());
+        let grid_model = ModelHandle(0);
+        let grid_entry = ModelEntry::new(grid_model, config.model_path.clone(), context.obj_model.clone(), None);
+        let user_settings = UserSettings::load(&config.settings_path);
+        let mut gui_windows = GuiWindows::default();
+        gui_windows.register(Box::new(SettingsWindow), &user_settings);
+        gui_windows.register(Box::new(StatsWindow), &user_settings);
+        gui_windows.register(Box::new(LightWindow), &user_settings);
+        gui_windows.register(Box::new(MeasureWindow), &user_settings);
+        gui_windows.register(Box::new(ScriptsWindow), &user_settings);
+        gui_windows.register(Box::new(CompareWindow::default()), &user_settings);
+        gui_windows.register(Box::new(HistoryWindow), &user_settings);
+        gui_windows.register(Box::new(ExportWindow::default()), &user_settings);
+        let theme = EngineTheme::from_settings(&user_settings);
+        let texture_watcher = config.hot_reload.then(|| {
+            let mut watcher = TextureWatcher::default();
+            watcher.watch_model(grid_model, &context.obj_model);
+            log::info!("Watching {} texture files for changes", watcher.watched_files());
+            watcher
         });
+        let shader_watcher = config.hot_reload.then(|| ShaderWatcher::new(std::path::Path::new(custom_shader::SHADER_SOURCE))).flatten();
+        if let Some(watcher) = &shader_watcher {
+            log::info!("Watching {} for changes", watcher.path().display());
+        }
 
-        // 11. Create render pipeline
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
-
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
-        };
-
-        let scale_factor = 1.0;
-
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            is_surface_configured: false,
-            window: window,
-            render_pipeline,
-            camera,
-            projection,
-            camera_bind_group,
-            camera_buffer,
-            camera_uniform,
-            controller,
-            depth_texture,
-            obj_model,
+        // Rebuilt for the main window's depth range once it renders, see render
+        let overlay_bias = OverlayBias::default();
+        let overlay = OverlayRenderer::new(
+            &context.device,
+            &context.adapter,
+            &context.camera_bind_group_layout,
+            context.scene_format,
+            context.settings.msaa_samples,
+            overlay_bias.depth_bias(OverlayRenderer::SCENE_DEPTH_COMPARE, config.units.depth_range(), config.units.units_per_meter()),
+        );
+        let sky = SkyRenderer::new(&context.device, &context.camera_bind_group_layout, context.scene_format, context.settings.msaa_samples);
+
+        let mut state = Self {
+            context,
             light_uniform,
             light_buffer,
+            clip_planes,
+            clip_buffer,
+            point_lights,
+            point_light_buffer,
+            point_light_markers: LineBuffer::new("Point Light Marker Buffer"),
+            measurements: Measurements::default(),
+            measurement_lines: LineBuffer::new("Measurement Line Buffer"),
+            frame_uniform,
+            frame_buffer,
+            frame_bind_group,
             light_bind_group,
-            light_render_pipeline,
             last_frame: std::time::Instant::now(),
-            mouse_pressed: false,
-            scale_factor,
-            show_menu: false,
-            num_of_instances: 0,
+            updated_this_iteration: false,
+            input_map: InputMap::default(),
+            show_help: false,
+            help_filter: String::new(),
+            console: Console::new(config.settings_path.with_file_name(console::HISTORY_FILE)),
+            clear_color: CLEAR_COLOR,
+            screenshot_request: None,
+            matrix_request: None,
+            video_export: None,
+            camera_before_export: None,
+            quit_requested: false,
+            render_mode: config.render_mode,
+            redraw_requested: false,
+            over_memory_budget: false,
+            texture_residency: TextureResidency::new(config.render.texture_budget),
+            orbit_light: true,
+            light_animation,
+            light_animation_time: 0.0,
+            animation_paths: AnimationPaths::default(),
+            day_night: DayNightCycle::default(),
+            frame_stats: FrameStats::default(),
+            profiler: Profiler::default(),
+            num_of_instances: config.instances.0,
+            num_of_instance_rows: config.instances.1,
+            instance_distribution: Distribution::Grid,
+            instance_seed: 0,
             instance_position_x: 0.0,
             instance_position_y: 0.0,
             instance_position_z: 0.0,
-            egui_state,
-            egui_renderer,
-            egui_frame_started: false,
+            atlas_demo: false,
+            atlas_assignment: Vec::new(),
+            scale_jitter: 0.0,
+            scale_jitter_directions: Vec::new(),
+            models: vec![grid_entry],
+            grid_model,
+            next_model_handle: grid_model.0 + 1,
+            model_path_input: String::new(),
+            material_set_input: String::new(),
+            material_set_shape: ShapeKind::Cube,
+            material_sets: MaterialSetCache::default(),
+            material_set_models: HashMap::new(),
+            import_dialog: None,
+            model_load_error: None,
+            mesh_filter_input: String::new(),
+            selected_instance: None,
+            selection_euler: None,
+            pasted_textures: Vec::new(),
+            pasted_count: 0,
+            precise_picking: true,
+            frame_request: None,
+            road: RoadTool::default(),
+            overlay,
+            overlay_bias,
+            sky,
+            sky_settings: SkySettings { mode: config.render.sky, ..SkySettings::default() },
+            outline: OutlineSettings { mode: config.render.outline, ..OutlineSettings::default() },
+            measure_hover_instance: None,
+            camera_follow: None,
+            undo: UndoStack::new(config.undo_depth),
+            transform_gizmo: TransformGizmo::default(),
+            placing: None,
+            placing_light: false,
+            cursor_stack: CursorStack::default(),
+            instance_layout: None,
+            instance_animation_gpu: false,
+            animation_stats: InstanceAnimationStats::default(),
+            instance_culling: config.render.culling,
+            cull_frustum: None,
+            draw_culled: false,
+            occlusion_culling: config.render.occlusion_culling,
+            freeze_culling: false,
+            shape_lod: LodSettings::default(),
+            lod_view: None,
+            lod_stats: LodStats::default(),
+            animation_time: 0.0,
+            ssao_settings: SsaoSettings::default(),
+            motion_blur: MotionBlurSettings::default(),
+            taa: TaaSettings { enabled: config.render.taa, ..TaaSettings::default() },
+            stereo: StereoSettings { mode: config.stereo, ..StereoSettings::default() },
+            stereo_culled: Vec::new(),
+            show_gizmo: true,
+            gizmo_scale: 1.0,
+            units: config.units,
+            hdr_settings: HdrSettings::default(),
+            render_style: RenderStyle::Realistic,
+            depth_prepass: config.render.depth_prepass,
+            vertex_pulling: config.render.vertex_pulling,
+            draw_packed_materials: true,
+            material_binds: 0,
+            toon_settings: ToonSettings::default(),
+            show_shading_models: false,
+            paused: false,
+            pause_on_focus_loss: config.pause_on_focus_loss,
+            in_background: false,
+            frame_caps: FrameCaps::from_settings(&user_settings),
+            invert_mouse_y: user_settings.parse("mouse.invert_y").unwrap_or(false),
+            controller_profile: ControllerProfile::from_settings(&user_settings),
+            controller_tunables: ControllerTunables::from_settings(&user_settings),
+            shape_scene,
+            window_size_request: None,
+            show_particles: false,
+            quads_2d: QuadBatcher::default(),
+            quad_demo: QuadDemo::default(),
+            show_depth_thumbnail: false,
+            particles,
+            show_sdf_demo: false,
+            sdf_demo_settings: SdfDemoSettings {
+                shape: SdfShape::BlendedSpheres,
+                blend: 0.3,
+                resolution: 48,
+            },
+            sdf_demo: None,
+            sdf_demo_stats: None,
+            show_skinning_demo: false,
+            skinning_demo: None,
+            show_grass: false,
+            grass_settings: ScatterSettings::default(),
+            grass_density: DensityMap::Uniform,
+            grass_density_input: String::new(),
+            grass_wind_strength: 0.25,
+            grass_field: None,
+            grass_scatter_ms: 0.0,
+            reflection_probes: Vec::new(),
+            rtt_cameras: Vec::new(),
+            next_rtt_id: 0,
+            rtt_recursion_depth: 1,
+            show_mirror_demo: false,
+            show_dice_demo: false,
+            dice_demo: None,
+            show_click_move_demo: false,
+            click_move_actor: None,
+            click_move_speed: click_move::DEFAULT_ACTOR_SPEED,
+            show_overlay_ground: false,
+            overlay_ground: None,
+            grazing_view_request: false,
+            show_occluder_wall: config.occluder_wall,
+            occluder_wall: None,
+            occluder_view_request: false,
+            mirror_demo: None,
+            next_probe_id: 0,
+            probe_resolution: 128,
+            theme,
+            user_settings,
+            gui_windows,
+            texture_watcher,
+            shader_watcher,
+            custom_shader: None,
+            scripts: ScriptHost::new(&config.scripts_dir, config.hot_reload),
+            held_keys: HashSet::new(),
+            toast: Toast::default(),
+            events: EventBus::default(),
+            event_log: Rc::default(),
+            announced_selection: None,
+            device_lost: Arc::new(AtomicBool::new(false)),
+        };
+        // Internal listeners first, then scripts, which is the order they hear every event in
+        let event_log = state.event_log.clone();
+        state.events.subscribe(move |event| {
+            let mut log = event_log.borrow_mut();
+            log.push_front(event.to_string());
+            log.truncate(EVENT_LOG_LEN);
+        });
+        let scripts = state.scripts.event_listener();
+        state.events.subscribe(scripts);
+        let device_lost = state.device_lost.clone();
+        state.context.device.set_device_lost_callback(move |reason, message| {
+            log::error!("GPU device lost ({:?}): {}", reason, message);
+            device_lost.store(true, Ordering::Relaxed);
+        });
+        if let Some(shading_model) = config.render.shading_model {
+            for index in 0..state.context.obj_model.meshes.len() {
+                state.set_material_shading(grid_model, index, shading_model);
+            }
         }
+        state
+    }
+
+    // Open another window that shares this state's device and pipelines, fails when the window
+    // can't get a surface
+    pub fn create_view(&self, window: Window, kind: ViewKind, camera: Camera) -> anyhow::Result<ViewWindow> {
+        let window = Arc::new(window);
+        let surface = self.context.instance.create_surface(window.clone())?;
+        Ok(ViewWindow::new(&self.context, window, surface, kind, camera))
     }
 
-    // Called when window resizes
-    pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            self.projection.resize(width, height);
-            self.config.width = width;
-            self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
-            self.is_surface_configured = true;
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+    // Before drawing a window. Only the first window drawn in an event loop iteration steps the
+    // scene, the others draw the same step.
+    pub fn update_for_view(&mut self) {
+        if !std::mem::replace(&mut self.updated_this_iteration, true) {
+            self.update();
         }
     }
 
-    // This is where we'll handle keyboard events
-    pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        if !self.controller.handle_key(code, is_pressed) {
-            match (code, is_pressed) {
-                (KeyCode::Escape, true) => event_loop.exit(),
-                _ => {}
+    // The event loop is going idle, the next window drawn steps the scene again
+    pub fn end_iteration(&mut self) {
+        self.updated_this_iteration = false;
+    }
+
+    pub fn update(&mut self) {
+        let now = std::time::Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.frame_stats.end_frame(dt * 1000.0);
+        self.profiler.end_frame();
+        let _update = profiler::scope("update");
+        self.material_binds = material_array::take_material_binds();
+        self.quads_2d.clear();
+
+        // An export steps exactly one frame for every frame it wrote, paused or not
+        let step = match self.video_export.as_mut() {
+            Some(export) => export.take_step(),
+            None => (!self.paused).then(|| dt.min(MAX_SIMULATION_STEP)),
+        };
+        if let Some(step) = step {
+            let _simulation = profiler::scope("simulation");
+            self.advance_simulation(step);
+            self.animation_time += step;
+        }
+        // Color, intensity, time of day and marker size can change from the menu even while paused
+        if self.day_night.enabled {
+            self.day_night.apply(&mut self.light_uniform);
+        } else {
+            self.light_uniform.ambient = LIGHT_AMBIENT * self.light_uniform.intensity;
+        }
+        self.light_uniform.marker_scale = LIGHT_MARKER_SIZE * self.gizmo_scale * self.units.gizmo_scale();
+        self.context.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform.linear()]));
+        self.context.queue.write_buffer(&self.clip_buffer, 0, bytemuck::bytes_of(&self.clip_planes.uniform()));
+        self.context.queue.write_buffer(&self.point_light_buffer, 0, bytemuck::bytes_of(&self.point_lights.uniform()));
+        let markers = self.point_lights.marker_lines(self.gizmo_scale * self.units.gizmo_scale());
+        self.point_light_markers.upload(&self.context.device, &self.context.queue, &markers);
+        self.frame_uniform = FrameUniform {
+            time: self.animation_time,
+            delta_time: self.video_export.as_ref().map_or(dt, VideoExport::step),
+            frame: self.frame_uniform.frame.wrapping_add(1),
+            _padding: 0,
+        };
+        self.context.queue.write_buffer(&self.frame_buffer, 0, bytemuck::bytes_of(&self.frame_uniform));
+        if let Some(source) = self.shader_watcher.as_mut().and_then(|watcher| watcher.poll(now)) {
+            let path = self.shader_watcher.as_ref().map(|watcher| watcher.path().to_path_buf()).unwrap_or_default();
+            // Failures are already in the error overlay
+            let _ = self.apply_custom_shader(&path, &source);
+        }
+        self.scripts.poll(now);
+        self.report_script_errors();
+        {
+            let _instances = profiler::scope("instances");
+            self.animate_instances();
+        }
+        self.update_animation_paths();
+        if self.show_sdf_demo {
+            self.update_sdf_demo();
+        }
+        self.update_skinning_demo();
+        self.update_mirror_demo();
+        self.update_dice_demo();
+        self.update_click_move_demo();
+        self.update_road();
+        self.update_overlay_ground();
+        self.update_occluder_wall();
+        if self.show_grass {
+            if self.grass_field.is_none() {
+                self.regenerate_grass();
+            }
+            if let Some(field) = &self.grass_field {
+                field.update(&self.context.queue, self.animation_time, self.grass_wind_strength);
             }
         }
-    }
+        self.prepare_shape_lods();
 
-    pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
-        match button {
-            MouseButton::Left => self.mouse_pressed = pressed,
-            _ => {}
+        // Upload a few more strips of any streaming textures and bind the ones that finished
+        let textures = profiler::scope("textures");
+        let context = &self.context;
+        let finished = context.texture_streamer.lock().unwrap().pump(&context.device, &context.queue);
+        for (target, texture) in finished {
+            context.obj_model.materials[target.material].replace_texture(&context.device, target.slot, texture);
+        }
+        for entry in &mut self.models {
+            entry.pump_textures(&context.device, &context.queue);
+        }
+        self.texture_residency.update(&context.device, &context.queue, &mut self.models);
+        self.reload_changed_textures();
+        self.check_memory_budget();
+        drop(textures);
+
+        if self.sky_settings.mode == SkyMode::Procedural {
+            self.sky.write(&self.context.queue, &self.sky_settings, &self.sky_colors());
+        }
+        let sky = self.clear_color();
+        let sky = [sky.r as f32, sky.g as f32, sky.b as f32];
+        self.context.probe_pipelines.write_fallback(&self.context.queue, sky);
+        for probe in &self.reflection_probes {
+            probe.write_sky(&self.context.queue, sky);
         }
+        {
+            let _probes = profiler::scope("probe bake");
+            self.bake_next_probe_face();
+        }
+        self.dispatch_events();
+        self.frame_stats.record_update(now.elapsed().as_secs_f32() * 1000.0);
     }
 
-    pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.controller.handle_scroll(delta);
+    // Queued until the end of the next update, see EventBus
+    pub fn push_event(&mut self, event: EngineEvent) {
+        self.events.push(event);
     }
 
-    pub fn window(&self) -> &Window {
-        self.window.as_ref()
+    // For gameplay and hook code. Listeners hear every event after the ones subscribed before them.
+    #[allow(dead_code)] // the built-in listeners subscribe on the bus while State is built
+    pub fn subscribe(&mut self, listener: impl FnMut(&EngineEvent) + 'static) -> ListenerId {
+        self.events.subscribe(listener)
     }
 
-    pub fn update(&mut self) {
-        let now = std::time::Instant::now();
-        let dt = now.duration_since(self.last_frame).as_secs_f32();
-        self.last_frame = now;
+    // False if it had already unsubscribed
+    #[allow(dead_code)] // for gameplay and hook code
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.events.unsubscribe(id)
+    }
 
-        self.controller.update_camera(&mut self.camera, dt);
+    // The selection is assigned in many places, so it is announced here once it settled for the frame
+    fn dispatch_events(&mut self) {
+        if self.device_lost.swap(false, Ordering::Relaxed) {
+            self.events.push(EngineEvent::DeviceLost);
+        }
+        if self.selected_instance != self.announced_selection {
+            self.announced_selection = self.selected_instance;
+            self.events.push(EngineEvent::SelectionChanged { id: self.selected_instance });
+        }
+        if self.events.drain() > 0 {
+            self.request_redraw();
+        }
+    }
 
+    // The light follows its track only while the day-night cycle is off, the path shows then
+    fn update_animation_paths(&mut self) {
+        let light = self.light_animation.position.as_ref().filter(|_| !self.day_night.enabled).map(|track| PathEntity {
+            id: LIGHT_PATH_ID,
+            track,
+            time: self.light_animation_time,
+        });
+        let entities: Vec<_> = light.into_iter().collect();
+        self.animation_paths.update(&self.context.device, &self.context.queue, &entities);
+    }
 
-        self.camera_uniform.update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    // Warns once each time the total goes over the budget, not every frame it stays there
+    fn check_memory_budget(&mut self) {
+        let total = gpu_memory::total();
+        let over = total > self.context.memory_budget;
+        if over && !self.over_memory_budget {
+            let message = format!(
+                "GPU memory over budget: {} of {}, see the frame pacing window",
+                gpu_memory::format_bytes(total),
+                gpu_memory::format_bytes(self.context.memory_budget)
+            );
+            log::warn!("{}", message);
+            self.report_error(Severity::Warning, message);
+        }
+        self.over_memory_budget = over;
+    }
 
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position = (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(60.0 * dt)) * old_position).into();
-        self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
+    // Replaces the model shader when the source checks out against custom_shader::ENGINE_GROUPS,
+    // otherwise reports every problem and keeps drawing with the shader it had
+    pub fn load_custom_shader(&mut self, path: &std::path::Path) -> Result<(), String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        self.apply_custom_shader(path, &source)
     }
 
-    pub fn redraw_instances(&mut self, num_of_instances: u32, instance_position_x: f32, instance_position_y: f32, instance_position_z: f32, device: &wgpu::Device) -> (std::vec::Vec<Instance>, wgpu::Buffer) {
-        let num_instances = num_of_instances;
-        const SPACE_BETWEEN: f32 = 3.0;
+    fn apply_custom_shader(&mut self, path: &std::path::Path, source: &str) -> Result<(), String> {
+        match CustomShader::build(&self.context, path, source) {
+            Ok(shader) => {
+                log::info!("Reloaded {}{}", path.display(), if shader.uses_frame { " with frame uniforms" } else { "" });
+                self.custom_shader = Some(shader);
+                self.request_redraw();
+                Ok(())
+            }
+            Err(problems) => {
+                let message = format!("Could not reload {}, keeping the previous shader:\n{}", path.display(), problems);
+                self.report_error(Severity::Error, message.clone());
+                Err(message)
+            }
+        }
+    }
 
-        let instances = (0..self.num_of_instances).flat_map(|z| {
-            (0..self.num_of_instances).map(move |x| {
-                let x = SPACE_BETWEEN * (x as f32 - num_instances as f32 / 2.0);
-                let z = SPACE_BETWEEN * (z as f32 - num_instances as f32 / 2.0);
-                let mut position = cgmath::Vector3 { x, y: 0.0, z };
+    // Back to the built-in render_pipeline, true if a custom shader was in use
+    pub fn clear_custom_shader(&mut self) -> bool {
+        self.request_redraw();
+        self.custom_shader.take().is_some()
+    }
 
-                let rotation = if num_instances == 1 {
-                    position = cgmath::Vector3 { x: 0.0, y: 0.0, z: 0.0 };
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else if position.is_zero() {
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else {
-                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+    // Textures edited on disk since the last poll go straight into the materials showing them
+    fn reload_changed_textures(&mut self) {
+        let Some(watcher) = self.texture_watcher.as_mut() else {
+            return;
+        };
+        let reloads = watcher.poll(std::time::Instant::now());
+        for reload in &reloads {
+            let mut refreshed = Vec::new();
+            for user in &reload.users {
+                let Some(material) = self.model(user.model).and_then(|entry| entry.model.materials.get(user.material)) else {
+                    continue;
                 };
-
-                Instance {
-                    initial_position: cgmath::Vector3 { x: instance_position_x, y: instance_position_y, z: instance_position_z },
-                    position,
-                    rotation,
+                match material.reload_texture(&self.context.device, &self.context.queue, user.slot, &reload.image) {
+                    Ok(()) => refreshed.push(format!("{} ({:?})", material._name, user.slot)),
+                    Err(e) => {
+                        let message = format!("Could not reload {} into {}: {}", reload.path.display(), material._name, e);
+                        log::warn!("{}", message);
+                        self.context.error_log.lock().unwrap().push(Severity::Warning, message);
+                    }
                 }
-            })
-        }).collect::<Vec<_>>();
+            }
+            log::info!("Reloaded {}, refreshed materials: {}", reload.path.display(), refreshed.join(", "));
+        }
+        if !reloads.is_empty() {
+            self.request_redraw();
+        }
+    }
+
+    fn advance_simulation(&mut self, dt: f32) {
+        if self.day_night.enabled {
+            self.day_night.advance(dt);
+        } else if self.orbit_light {
+            self.light_animation_time += dt;
+            self.light_animation.apply(self.light_animation_time, &mut self.light_uniform);
+        }
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        if let Some(shape_scene) = self.shape_scene.as_mut() {
+            shape_scene.update(dt);
+        }
+        if self.show_particles {
+            self.particles.update(dt);
+            self.particles.upload(&self.context.queue);
+        }
+        if let Some(demo) = self.skinning_demo.as_mut() {
+            demo.advance(dt);
+        }
+        self.step_click_move_actors(dt);
+        self.run_scripts(dt);
+    }
 
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
+    // The scripts get the models and lights for the step. What they spawned or removed is
+    // squared with the selection and the undo history afterwards.
+    fn run_scripts(&mut self, dt: f32) {
+        if self.scripts.running() == 0 {
+            self.scripts.discard_events();
+            return;
+        }
+        let world = ScriptWorld {
+            models: std::mem::take(&mut self.models),
+            grid_model: self.grid_model,
+            point_lights: std::mem::take(&mut self.point_lights),
+            units_per_meter: self.units.units_per_meter(),
+            light_color: self.light_uniform.color,
+            light_intensity: self.light_uniform.intensity,
+            time: self.animation_time,
+            held_keys: self.held_keys.iter().map(|code| format!("{:?}", code)).collect(),
+            ..ScriptWorld::default()
+        };
+        let world = self.scripts.update(world, dt);
+        self.models = world.models;
+        self.point_lights = world.point_lights;
+        self.light_uniform.color = world.light_color;
+        self.light_uniform.intensity = world.light_intensity;
+        for &id in &world.spawned {
+            self.events.push(EngineEvent::InstanceSpawned { id });
+        }
+        for &id in &world.despawned {
+            self.events.push(EngineEvent::InstanceDespawned { id });
+        }
+        let mut changed: Vec<ModelHandle> = world.spawned.iter().chain(&world.despawned).map(|id| id.model).collect();
+        changed.sort_by_key(|handle| handle.0);
+        changed.dedup();
+        if !changed.is_empty() {
+            for handle in changed {
+                self.forget_undo(handle, "a script spawned or despawned");
             }
-        );
+            if let Some(id) = self.selected_instance
+                && self.model(id.model).is_none_or(|entry| entry.instance(id.index).is_none())
+            {
+                self.selected_instance = None;
+            }
+        }
+        self.report_script_errors();
+        self.request_redraw();
+    }
 
-        (instances, instance_buffer)
+    fn report_script_errors(&mut self) {
+        for message in self.scripts.take_errors() {
+            log::warn!("{}", message);
+            self.report_error(Severity::Error, message);
+        }
+    }
 
+    // Pressed or released in any window
+    pub fn set_key_held(&mut self, code: KeyCode, held: bool) {
+        if held {
+            self.held_keys.insert(code);
+        } else {
+            self.held_keys.remove(&code);
+        }
     }
 
-    fn egui_context(&self) -> Context {
-        self.egui_state.egui_ctx().clone()
+    // Releases go to the focused window, which is none after focus moved elsewhere
+    pub fn release_keys(&mut self) {
+        self.held_keys.clear();
     }
 
-    pub fn handle_input(&mut self, window: &Window, event: &WindowEvent) -> bool {
-        let response = self.egui_state.on_window_event(window, event);
-        response.consumed
+    pub fn scripts(&self) -> Vec<ScriptInfo> {
+        self.scripts.scripts()
     }
 
-    pub fn ppp(&mut self, v: f32) {
-        self.egui_context().set_pixels_per_point(v);
+    pub fn set_script_enabled(&mut self, name: &str, enabled: bool) {
+        self.scripts.set_enabled(name, enabled);
     }
 
-    pub fn begin_frame(&mut self, window: &Window) {
-        let raw_input = self.egui_state.take_egui_input(window);
-        self.egui_state.egui_ctx().begin_pass(raw_input);
-        self.egui_frame_started = true;
+    pub fn reload_scripts(&mut self) {
+        self.scripts.reload();
+        self.report_script_errors();
     }
 
-    pub fn end_frame_and_draw(
-        &mut self,
-        device: &Device,
-        queue: &Queue,
-        encoder: &mut CommandEncoder,
-        window: &Window,
-        window_surface_view: &TextureView,
-        screen_descriptor: ScreenDescriptor,
-    ) {
-        if !self.egui_frame_started {
-            panic!("begin_frame must be called before end_frame_and_draw can be called!");
-        }
-
-        self.ppp(screen_descriptor.pixels_per_point);
-
-        let full_output = self.egui_state.egui_ctx().end_pass();
-
-        self.egui_state
-            .handle_platform_output(window, full_output.platform_output);
-
-        let tris = self
-            .egui_state
-            .egui_ctx()
-            .tessellate(full_output.shapes, self.egui_state.egui_ctx().pixels_per_point());
-        for (id, image_delta) in &full_output.textures_delta.set {
-            self.egui_renderer
-                .update_texture(device, queue, *id, image_delta);
-        }
-        self.egui_renderer
-            .update_buffers(device, queue, encoder, &tris, &screen_descriptor);
-        let rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: window_surface_view,
-                resolve_target: None,
-                ops: egui_wgpu::wgpu::Operations {
-                    load: egui_wgpu::wgpu::LoadOp::Load,
-                    store: StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-            label: Some("egui main render pass"),
-            occlusion_query_set: None,
-        });
+    // The folder and whether edits in it are picked up
+    pub fn script_dir(&self) -> (&std::path::Path, bool) {
+        (self.scripts.dir(), self.scripts.is_watching())
+    }
+
+    // Rebuild the instance grid and its buffers, only needed when the layout changes
+    fn redraw_instances(&mut self) {
+        let initial_position = cgmath::Vector3 { x: self.instance_position_x, y: self.instance_position_y, z: self.instance_position_z };
 
-        self.egui_renderer
-            .render(&mut rpass.forget_lifetime(), &tris, &screen_descriptor);
-        for x in &full_output.textures_delta.free {
-            self.egui_renderer.free_texture(x)
+        let mut instances = self.instance_distribution.generate(self.num_of_instances, self.num_of_instance_rows, self.units.instance_spacing(), self.instance_seed);
+        for instance in &mut instances {
+            instance.initial_position = initial_position;
+            instance.spin_speed = INSTANCE_SPIN_SPEED;
         }
 
-        self.egui_frame_started = false;
+        if self.atlas_demo {
+            self.assign_atlas_regions(instances.len());
+            for (instance, uv_transform) in instances.iter_mut().zip(&self.atlas_assignment) {
+                instance.uv_transform = *uv_transform;
+            }
+        }
+
+        let count = instances.len();
+        let grid_model = self.grid_model;
+        self.forget_undo(grid_model, "the grid was rebuilt");
+        if let Some(entry) = self.model_mut(grid_model) {
+            entry.set_instances(instances);
+        }
+        if self.scale_jitter > 0.0 {
+            self.apply_scale_jitter(count);
+        }
+        self.instance_layout = Some(self.current_instance_layout());
+        self.animation_stats = InstanceAnimationStats::default();
     }
 
-    pub fn draw_overlay(&mut self) {
-        egui::TopBottomPanel::top("menu_bar").show(&self.egui_context(), |ui| {
-            if ui.button("Quit").clicked() {
-                std::process::exit(0);
-            }
-        });
+    fn current_instance_layout(&self) -> InstanceLayout {
+        InstanceLayout {
+            distribution: self.instance_distribution,
+            seed: self.instance_seed,
+            columns: self.num_of_instances,
+            rows: self.num_of_instance_rows,
+            offset: [self.instance_position_x, self.instance_position_y, self.instance_position_z],
+            atlas_demo: self.atlas_demo,
+            atlas_regions: self.atlas_assignment.len(),
+            scale_jitter: self.scale_jitter,
+        }
     }
 
-    pub fn draw_menu(&mut self, device: &wgpu::Device) {
-        egui::Window::new("winit + egui + wgpu says hello!")
-            .resizable(true)
-            .vscroll(true)
-            .default_open(false)
-            .show(&self.egui_context(), |ui| {
-                ui.label("Label!");
+    // Switch between rebuilding instance matrices on the CPU and the compute pass.
+    // Both produce the same transforms for the same animation time.
+    pub fn set_instance_animation_gpu(&mut self, gpu: bool) {
+        self.instance_animation_gpu = gpu;
+    }
 
-                if ui.button("Button!").clicked() {
-                    println!("boom!")
+    // The culling the main pass gets, GPU culling falls back to the CPU where the adapter can't do it
+    pub fn instance_cull_mode(&self) -> CullMode {
+        match self.instance_culling {
+            CullMode::Gpu if self.context.instance_cull.is_none() => CullMode::Cpu,
+            mode => mode,
+        }
+    }
+
+    pub fn set_instance_culling(&mut self, mode: CullMode) {
+        self.instance_culling = mode;
+        if mode == CullMode::Off {
+            self.cull_frustum = None;
+        }
+    }
+
+    // Occlusion tests against the depth pre-pass's depth, on the GPU path only
+    pub fn occlusion_culling_active(&self) -> bool {
+        self.occlusion_culling && self.instance_cull_mode() == CullMode::Gpu && self.depth_prepass_active()
+    }
+
+    // Cull every model's instances for the main window's camera, `view_proj`, recorded after
+    // the depth pre-pass and before the main pass, which then draws the culled buffers. `hiz`
+    // must be built from that pre-pass. Frozen, the last cull's buffers are drawn again.
+    pub fn cull_instances(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: cgmath::Matrix4<f32>, hiz: Option<&HiZTargets>) {
+        let mode = self.instance_cull_mode();
+        if mode == CullMode::Off {
+            self.cull_frustum = None;
+        } else if !self.freeze_culling || self.cull_frustum.is_none() {
+            self.cull_frustum = Frustum::from_view_proj(view_proj);
+            if let Some(frustum) = self.cull_frustum {
+                let context = &self.context;
+                let hiz = hiz.filter(|_| self.occlusion_culling_active());
+                encoder.push_debug_group("instance culling");
+                for entry in &mut self.models {
+                    entry.cull(&context.device, &context.queue, encoder, context.instance_cull.as_ref(), mode, &frustum, view_proj, hiz, self.animation_time);
                 }
+                encoder.pop_debug_group();
+            }
+        }
+        self.draw_culled = self.cull_frustum.is_some();
+    }
 
-                ui.separator();
-                ui.horizontal(|ui| {
-                    ui.label(format!(
-                        "# of Instances: {}",
-                        self.num_of_instances
-                    ));
-                    if ui.button("-").clicked() {
-                        if self.num_of_instances > 1 {
-                            self.num_of_instances -= 1;
-                            self.redraw_instances(self.num_of_instances, self.instance_position_x, self.instance_position_y, self.instance_position_z, &device);
-                        }
-                    }
-                    if ui.button("+").clicked() {
-                        self.num_of_instances += 1;
-                        self.redraw_instances(self.num_of_instances, self.instance_position_x, self.instance_position_y, self.instance_position_z, &device);
-                    }
-                    });
-                ui.separator();
-                ui.horizontal(|ui| {
-                    ui.label(format!(
-                        "Instance X Position: {}",
-                        self.instance_position_x
-                    ));
-                    ui.add(egui::DragValue::new(&mut self.instance_position_x).speed(0.1));
-                    if ui.button("-").clicked() {
-                        self.instance_position_x -= 1.0;
-                    }
-                    if ui.button("+").clicked() {
-                        self.instance_position_x += 1.0;
-                    }
-                });
-                ui.horizontal(|ui| {
-                    ui.label(format!(
-                        "Instance Y Position: {}",
-                        self.instance_position_y
-                    ));
-                    ui.add(egui::DragValue::new(&mut self.instance_position_y).speed(0.1));
-                    if ui.button("-").clicked() {
-                        self.instance_position_y -= 1.0;
-                    }
-                    if ui.button("+").clicked() {
-                        self.instance_position_y += 1.0;
-                    }
-                });
-                ui.horizontal(|ui| {
-                    ui.label(format!(
-                        "Instance Z Position: {}",
-                        self.instance_position_z
-                    ));
-                    ui.add(egui::DragValue::new(&mut self.instance_position_z).speed(0.1));
-                    if ui.button("-").clicked() {
-                        self.instance_position_z -= 1.0;
-                    }
-                    if ui.button("+").clicked() {
-                        self.instance_position_z += 1.0;
-                    }
-                })
-            });
+    // After the encoder with the cull was submitted
+    pub fn request_cull_readbacks(&mut self) {
+        self.models.iter_mut().filter_map(ModelEntry::culled_mut).for_each(CulledInstances::request_readback);
     }
 
-    // Render a single frame (clear screen to a color)
-    pub fn render(&mut self, window: Arc<Window>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<(), wgpu::SurfaceError> {
-        // self.window.request_redraw();
-        // 1. Acquire next frame from surface
-        // Refine error handling
-        match self.surface.get_current_texture() {
-            Ok(output) => {
-                // 2. Create a view into the frame (like a convas we draw on)
-                let view = output
-                    .texture
-                    .create_view(&wgpu::TextureViewDescriptor::default());
+    // Picks up the GPU's counts once they are read back
+    pub fn poll_cull_readbacks(&mut self) {
+        if self.instance_cull_mode() != CullMode::Gpu {
+            return;
+        }
+        if let Err(e) = self.context.device.poll(wgpu::PollType::Poll) {
+            log::warn!("Polling for culled instance counts failed: {}", e);
+        }
+        self.models.iter_mut().filter_map(ModelEntry::culled_mut).for_each(CulledInstances::poll_readback);
+    }
 
-                // 3. Create command encoder (records GPU commands)
-                let mut encoder = self
-                    .device
-                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {label: Some("Render Encoder")});
+    // What the last cull did with the instances, None while culling is off or the GPU's counts
+    // are still on their way
+    pub fn cull_counts(&self) -> Option<CullCounts> {
+        let frustum = self.cull_frustum?;
+        let total = self.models.iter().map(ModelEntry::instance_count).sum();
+        let in_frustum = self.count_visible_instances(&frustum);
+        let culled = || self.models.iter().filter_map(ModelEntry::culled);
+        Some(CullCounts {
+            total,
+            outside_frustum: total - in_frustum,
+            occluded: culled().map(CulledInstances::occluded).sum::<Option<u32>>()?,
+            drawn: culled().map(CulledInstances::visible).sum::<Option<u32>>()?,
+        })
+    }
 
-                // Screen descriptor for egui
-                let screen_descriptor = egui_wgpu::ScreenDescriptor {
-                    size_in_pixels: [self.config.width, self.config.height],
-                    pixels_per_point: self.window.scale_factor() as f32 * self.scale_factor,
-                };
+    // Levels for the camera the main window drew with last frame, the finest everywhere while
+    // LOD is off. Also uploads the shapes' spin, paused or not.
+    fn prepare_shape_lods(&mut self) {
+        let view = self.lod_view.as_ref().filter(|_| self.shape_lod.enabled);
+        let show_levels = self.shape_lod.tint;
+        let mut stats = LodStats::default();
+        if let Some(scene) = self.shape_scene.as_mut() {
+            scene.prepare(&self.context.queue, view, show_levels);
+            stats.merge(&scene.lod_stats());
+        }
+        if self.show_grass && let Some(field) = self.grass_field.as_mut() {
+            stats.merge(&field.prepare_ground(&self.context.queue, view, show_levels));
+        }
+        self.lod_stats = stats;
+    }
+
+    // The offscreen benchmark has no main window to take the camera from
+    pub fn set_lod_view(&mut self, view: LodView) {
+        self.lod_view = Some(view);
+    }
+
+    // LOD toggles, the shapes at each level and the triangles that saved
+    fn draw_lod_stats(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.shape_lod.enabled, "Shape LOD")
+                .on_hover_text("Tessellate the random scene's spheres and the grass ground by their size on screen");
+            ui.checkbox(&mut self.shape_lod.tint, "Tint by level")
+                .on_hover_text("Green is the finest level, then yellow, orange and red. Grey shapes have a single mesh.");
+        });
+        let stats = self.lod_stats;
+        if stats.full_triangles == 0 {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Shapes per level:");
+            for (tint, shapes) in LOD_TINTS.iter().zip(stats.shapes) {
+                ui.colored_label(color::swatch(*tint), shapes.to_string());
+            }
+        });
+        ui.label(format!(
+            "Shape triangles: {} of {}, {} saved ({:.0}%)",
+            stats.triangles,
+            stats.full_triangles,
+            stats.saved(),
+            stats.saved() as f32 / stats.full_triangles as f32 * 100.0
+        ));
+    }
+
+    // Culling mode and what it let through, with the CPU's count next to the GPU's to compare
+    fn draw_cull_stats(&mut self, ui: &mut egui::Ui) {
+        let gpu_supported = self.context.instance_cull.is_some();
+        let mut mode = self.instance_culling;
+        egui::ComboBox::from_label("Instance frustum culling")
+            .selected_text(self.instance_cull_mode().label())
+            .show_ui(ui, |ui| {
+                for option in CullMode::ALL {
+                    ui.add_enabled_ui(option != CullMode::Gpu || gpu_supported, |ui| {
+                        ui.selectable_value(&mut mode, option, option.label())
+                            .on_disabled_hover_text("The adapter has no compute shaders or indirect draws");
+                    });
+                }
+            });
+        if mode != self.instance_culling {
+            self.set_instance_culling(mode);
+        }
+        ui.horizontal(|ui| {
+            ui.add_enabled(self.instance_cull_mode() == CullMode::Gpu, egui::Checkbox::new(&mut self.occlusion_culling, "Occlusion culling"))
+                .on_hover_text("Also drop the instances hidden behind what the depth pre-pass drew, tested against a pyramid of its depth")
+                .on_disabled_hover_text("Needs GPU culling");
+            ui.add_enabled(self.instance_culling != CullMode::Off, egui::Checkbox::new(&mut self.freeze_culling, "Freeze culling"))
+                .on_hover_text("Keep drawing what the last cull let through while the camera moves, to see what it dropped");
+        });
+        if self.occlusion_culling && self.instance_cull_mode() == CullMode::Gpu && !self.occlusion_culling_active() {
+            ui.weak("Occlusion culling waits for the depth pre-pass (realistic style, no clip planes, custom shader or stereo)");
+        }
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show_occluder_wall, "Occluder wall")
+                .on_hover_text("A wall across the near side of the instance grid, hiding most of it from the view over it");
+            if ui.add_enabled(self.occluder_wall.is_some(), egui::Button::new("View over the wall")).clicked() {
+                self.occluder_view_request = true;
+            }
+        });
+        let Some(frustum) = self.cull_frustum else {
+            return;
+        };
+        let total: u32 = self.models.iter().map(ModelEntry::instance_count).sum();
+        let drawn = self.models.iter().filter_map(ModelEntry::culled).map(CulledInstances::visible).sum::<Option<u32>>();
+        let checked = self.count_visible_instances(&frustum);
+        match (self.instance_cull_mode(), drawn) {
+            (CullMode::Gpu, Some(gpu)) => ui.label(format!("Visible instances: GPU {} (a frame late), CPU check {} of {}", gpu, checked, total)),
+            (CullMode::Gpu, None) => ui.label(format!("Visible instances: GPU reading back, CPU check {} of {}", checked, total)),
+            (_, drawn) => ui.label(format!("Visible instances: {} of {}", drawn.unwrap_or(checked), total)),
+        };
+        let occluded = self.models.iter().filter_map(ModelEntry::culled).map(CulledInstances::occluded).sum::<Option<u32>>();
+        let occluded = occluded.map_or("reading back".to_string(), |occluded| occluded.to_string());
+        ui.label(format!("Rejected: {} outside the frustum, {} occluded", total - checked, occluded));
+    }
+
+    // Instances in the last culled frustum as the CPU counts them, to check the GPU's count against
+    fn count_visible_instances(&self, frustum: &Frustum) -> u32 {
+        let time = self.animation_time;
+        let mut visible = 0;
+        for entry in &self.models {
+            let Some(bounds) = entry.model.bounds() else {
+                continue;
+            };
+            let sphere = instance_cull::bounding_sphere(&bounds);
+            visible += entry
+                .instances()
+                .filter(|(_, instance)| frustum.contains_sphere(&instance_cull::instance_sphere(instance.to_raw(time).model_matrix(), &sphere)))
+                .count() as u32;
+        }
+        visible
+    }
+
+    // Adapter, surface and settings for bug reports. Only reads state, so it is safe to call
+    // between frames or before the first one; without a view the surface part is left out.
+    pub fn diagnostics_report(&self, view: Option<&ViewWindow>) -> String {
+        let settings = &self.context.settings;
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        let instances: u32 = self.models.iter().map(ModelEntry::instance_count).sum();
+        let engine = [
+            ("vsync", on_off(settings.vsync)),
+            ("msaa samples", settings.msaa_samples.to_string()),
+            ("hdr", on_off(settings.hdr)),
+            ("tonemapper", self.hdr_settings.tonemapper.label().to_string()),
+            ("render style", self.render_style.label().to_string()),
+            ("scene units", self.units.to_string()),
+            ("day-night cycle", on_off(self.day_night.enabled)),
+            ("sky", self.sky_settings.mode.label().to_string()),
+            ("outline", self.outline.mode.label().to_string()),
+            ("ssao", on_off(self.ssao_settings.enabled)),
+            ("motion blur", on_off(self.motion_blur.enabled)),
+            ("taa", on_off(self.taa.enabled && self.context.taa.is_some())),
+            ("stereo", self.stereo.mode.label().to_string()),
+            ("depth pre-pass", on_off(self.depth_prepass)),
+            ("vertex path", self.vertex_path().to_string()),
+            ("shading override", settings.shading_model.map_or("none", ShadingModel::label).to_string()),
+            ("mesh weld / smoothing / cache optimize", format!(
+                "{} / {} / {}",
+                on_off(settings.mesh_load.weld),
+                settings.mesh_load.smoothing_angle.map_or("off".to_string(), |angle| format!("{} deg", angle)),
+                on_off(settings.mesh_load.cache_optimize)
+            )),
+            ("pack textures", on_off(settings.mesh_load.pack_textures)),
+            ("uv fallback", settings.mesh_load.uv_fallback.label().to_string()),
+            ("render mode", self.render_mode.label().to_string()),
+            ("hot reload", on_off(self.texture_watcher.is_some())),
+            ("scripts running / loaded", format!("{} / {}", self.scripts.running(), self.scripts.len())),
+            ("custom shader", self.custom_shader.as_ref().map_or("none".to_string(), |shader| shader.path.display().to_string())),
+            ("fps cap foreground / background", format!("{} / {}", self.frame_caps.foreground, self.frame_caps.background)),
+            ("refresh pacing", match (self.frame_caps.refresh_pacing, self.frame_caps.pacing_target) {
+                (false, _) => "off".to_string(),
+                (true, 0) => "monitor refresh rate".to_string(),
+                (true, target) => format!("{} fps", target),
+            }),
+            ("instance animation", if self.instance_animation_gpu { "gpu" } else { "cpu" }.to_string()),
+            ("instance culling", format!(
+                "{} (gpu {}), occlusion {}",
+                self.instance_cull_mode().label(),
+                if self.context.instance_cull.is_some() { "supported" } else { "unsupported" },
+                on_off(self.occlusion_culling_active())
+            )),
+            ("skinning demo", self.skinning_demo.as_ref().map_or("off", |demo| if demo.gpu() { "gpu" } else { "cpu" }).to_string()),
+            ("surface format", format!("{:?}", self.context.surface_format)),
+            ("scene format", format!("{:?}", self.context.scene_format)),
+            ("models / instances", format!("{} / {}", self.models.len(), instances)),
+        ];
+        let report = diagnostics::report(&self.context, view.map(ViewWindow::surface_diagnostics), &engine);
+        format!("{}\n[gpu memory]\n{}", report, self.memory_report())
+    }
+
+    // Live buffers and textures by category and the biggest of them, against the budget
+    pub fn memory_report(&self) -> String {
+        gpu_memory::report(self.context.memory_budget) + self.texture_residency.report().as_str()
+    }
+
+    // The procedural sky's horizon when asked for, the day-night cycle's sky, otherwise the usual blue
+    pub fn clear_color(&self) -> wgpu::Color {
+        let [r, g, b] = match self.sky_settings {
+            SkySettings { mode: SkyMode::Procedural, clear_from_horizon: true, .. } => self.sky_colors().horizon,
+            _ if self.day_night.enabled => self.day_night.sky_color(),
+            _ => self.clear_color,
+        };
+        color::clear_color([r, g, b])
+    }
+
+    // The sun follows the sun light. Without the day-night cycle that is the scene light, seen
+    // from the origin, and the gradient is the menu's.
+    fn sky_colors(&self) -> SkyColors {
+        if self.day_night.enabled {
+            let (horizon, zenith) = self.day_night.sky_gradient();
+            let (sun_color, sun_intensity) = self.day_night.sun_disk();
+            return SkyColors { horizon, zenith, sun_direction: self.day_night.sun_direction(), sun_color, sun_intensity };
+        }
+        let light = &self.light_uniform;
+        SkyColors {
+            horizon: self.sky_settings.horizon,
+            zenith: self.sky_settings.zenith,
+            sun_direction: light.position.into(),
+            sun_color: light.color,
+            sun_intensity: light.intensity,
+        }
+    }
+
+    // Ignored while the day-night cycle or the procedural sky colors the background
+    pub fn set_clear_color(&mut self, color: [f32; 3]) {
+        self.clear_color = color;
+        self.request_redraw();
+    }
+
+    // Color and intensity
+    pub fn light(&self) -> ([f32; 3], f32) {
+        (self.light_uniform.color, self.light_uniform.intensity)
+    }
+
+    // All of it, as the shaders get it
+    pub fn light_uniform(&self) -> light::LightUniform {
+        self.light_uniform
+    }
+
+    // `code` goes on the clipboard, the toast names `what` it was
+    pub fn copy_as_code(&mut self, ctx: &Context, what: &str, code: String) {
+        ctx.copy_text(code);
+        self.toast.show(format!("Copied {} as Rust", what));
+    }
+
+    // The intensity stays as it is when None. Undoable, a drag of the picker is one edit.
+    pub fn set_light(&mut self, color: [f32; 3], intensity: Option<f32>) {
+        let before = self.light();
+        self.apply_light(color, intensity.unwrap_or(before.1));
+        self.record(Command::Light { before, after: self.light() });
+    }
+
+    // set_light without the undo history
+    pub fn apply_light(&mut self, color: [f32; 3], intensity: f32) {
+        self.light_uniform.color = color;
+        self.light_uniform.intensity = intensity.max(0.0);
+        self.request_redraw();
+    }
+
+    // The main window's next frame goes to `path` as a PNG
+    pub fn request_screenshot(&mut self, path: &std::path::Path) {
+        self.screenshot_request = Some(path.to_path_buf());
+        self.request_redraw();
+    }
+
+    // The main window's view rendered once per variant of `preset` before its next frame, see
+    // render_matrix
+    pub fn request_render_matrix(&mut self, preset: MatrixPreset, path: &std::path::Path) {
+        self.matrix_request = Some((preset, path.to_path_buf()));
+        self.request_redraw();
+    }
+
+    // Renders the view once per variant through the offscreen path (the scene and tonemapping, no
+    // SSAO, TAA or motion blur) and writes a labeled sheet of them to `path`, every frame next to
+    // it. The render settings are put back after each variant, also when it fails or panics, and a
+    // failed variant leaves an empty tile. Returns how many failed.
+    pub fn render_matrix(&mut self, camera: &Camera, projection: &camera::Projection, size: (u32, u32), variants: &[RenderVariant], path: &std::path::Path) -> anyhow::Result<usize> {
+        let snapshot = self.render_snapshot();
+        let mut frames = Vec::with_capacity(variants.len());
+        for (index, variant) in variants.iter().enumerate() {
+            let rendered = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                variant.apply(self);
+                self.render_offscreen(camera, projection, size)
+            }));
+            self.restore_render_snapshot(&snapshot);
+            let frame = match rendered {
+                Ok(Ok(frame)) => {
+                    let frame_path = render_matrix::frame_path(path, index, &variant.label);
+                    frame.save(&frame_path).map_err(|e| anyhow::anyhow!("could not save {}: {}", frame_path.display(), e))?;
+                    Some(frame)
+                }
+                Ok(Err(e)) => {
+                    log::error!("Rendering the '{}' variant failed: {}", variant.label, e);
+                    None
+                }
+                Err(_) => {
+                    log::error!("The '{}' variant panicked, the settings were put back", variant.label);
+                    None
+                }
+            };
+            frames.push((variant.label.clone(), frame));
+        }
+        render_matrix::contact_sheet(&frames)?.save(path)?;
+        Ok(frames.iter().filter(|(_, frame)| frame.is_none()).count())
+    }
+
+    // What a RenderVariant may change
+    fn render_snapshot(&self) -> RenderSnapshot {
+        let materials = self
+            .models
+            .iter()
+            .flat_map(|entry| entry.model.materials.iter().enumerate().map(move |(index, material)| (entry.handle, index, material.params())))
+            .collect();
+        RenderSnapshot {
+            hdr_settings: self.hdr_settings,
+            ssao_settings: self.ssao_settings,
+            render_style: self.render_style,
+            toon_settings: self.toon_settings,
+            clear_color: self.clear_color,
+            materials,
+        }
+    }
+
+    // Models a variant removed stay removed, what remains is as it was
+    fn restore_render_snapshot(&mut self, snapshot: &RenderSnapshot) {
+        self.hdr_settings = snapshot.hdr_settings;
+        self.ssao_settings = snapshot.ssao_settings;
+        self.render_style = snapshot.render_style;
+        self.toon_settings = snapshot.toon_settings;
+        self.clear_color = snapshot.clear_color;
+        for &(handle, index, params) in &snapshot.materials {
+            if let Some(material) = self.model(handle).and_then(|entry| entry.model.materials.get(index))
+                && material.params() != params
+            {
+                material.set_params(&self.context.queue, params);
+            }
+        }
+        self.request_redraw();
+    }
+
+    // One frame of the scene seen by `camera`, drawn the way the headless benchmark draws it
+    fn render_offscreen(&self, camera: &Camera, projection: &camera::Projection, (width, height): (u32, u32)) -> anyhow::Result<image::RgbaImage> {
+        let context = &self.context;
+        let device = &context.device;
+        let sample_count = context.settings.msaa_samples;
+        // Stand-in for a surface configuration so the usual texture helpers can be reused
+        let target_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: context.surface_format,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let color_texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("Offscreen Color Target"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: context.surface_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let msaa_view = (sample_count > 1).then(|| Texture::create_msaa_texture(device, &target_config, context.scene_format, sample_count));
+        let mut hdr_targets = context.hdr.as_ref().map(|pipelines| HdrTargets::new(device, pipelines, width, height));
+        let depth_texture = Texture::create_depth_texture(device, &target_config, sample_count, "offscreen_depth_texture");
+
+        let mut camera_uniform = camera::CameraUniform::new();
+        camera_uniform.update_view_proj(camera, projection);
+        let camera_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Offscreen Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: camera_buffer.as_entire_binding() }],
+            label: Some("Offscreen Camera Bind Group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Offscreen Encoder") });
+        let depth_load = self.encode_depth_prepass(&mut encoder, &depth_texture.view, &camera_bind_group, None);
+        {
+            // With HDR on the scene goes to the float target first, then gets tonemapped below
+            let scene_view = hdr_targets.as_ref().map_or(&color_view, |targets| targets.color_view());
+            let (view, resolve_target) = match &msaa_view {
+                Some(msaa_view) => (&**msaa_view, Some(scene_view)),
+                None => (scene_view, None),
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Offscreen Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(self.clear_color()), store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations { load: depth_load, store: wgpu::StoreOp::Store }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            self.draw_scene(&mut render_pass, &camera_bind_group);
+        }
+        if let (Some(targets), Some(pipelines)) = (hdr_targets.as_mut(), context.hdr.as_ref()) {
+            targets.encode_tonemap(&mut encoder, &context.queue, pipelines, &self.hdr_settings, &color_view);
+        }
+        context.queue.submit(std::iter::once(encoder.finish()));
+        ViewWindow::capture_frame(context, &color_texture)
+    }
+
+    // Records `span` at `fps` into `path` from the main window's camera, one frame for each of its
+    // frames, rendered offscreen at `size` like the comparison sheet. The simulation steps by
+    // exactly 1 / fps per frame, paused or not, and input is ignored until it is done or cancelled
+    // from the progress modal. Frames are piped to ffmpeg, or saved as PNGs next to `path` when
+    // it is a .png or ffmpeg isn't installed.
+    pub fn export_video(&mut self, path: &std::path::Path, fps: u32, span: ExportSpan, size: (u32, u32)) -> anyhow::Result<()> {
+        if self.video_export.is_some() {
+            anyhow::bail!("a video export is already running");
+        }
+        let export = VideoExport::start(path, fps, span, size)?;
+        if !export.is_ffmpeg() && !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
+            self.toast.show(format!("ffmpeg not found, saving a PNG sequence instead: {}", export.output().display()));
+        }
+        log::info!("Exporting {} frames at {}x{} to {}", export.progress().1, export.size().0, export.size().1, export.output().display());
+        self.video_export = Some(export);
+        self.request_redraw();
+        Ok(())
+    }
+
+    pub fn is_exporting_video(&self) -> bool {
+        self.video_export.is_some()
+    }
+
+    // The export's next frame, before the main window's own. Puts the camera back and lets input
+    // through again once the export is done, failed or cancelled.
+    fn export_video_frame(&mut self, view: &mut ViewWindow) {
+        let Some(mut export) = self.video_export.take() else {
+            return;
+        };
+        if export.cancel_requested {
+            let (written, _) = export.progress();
+            export.cancel();
+            self.toast.show(format!("Video export cancelled after {} frames", written));
+            self.end_video_export(view);
+            return;
+        }
+        if export.progress().0 == 0 {
+            view.release_input();
+            if export.camera_pose().is_some() {
+                let camera = &view.camera;
+                self.camera_before_export = Some(Camera::new(camera.position, camera.yaw(), camera.pitch()).with_roll(camera.roll()));
+            }
+        }
+        if let Some((eye, target)) = export.camera_pose() {
+            view.place_camera(eye, target);
+        }
+
+        let (width, height) = export.size();
+        let projection = camera::Projection::new(width, height, view.projection.fovy(), view.projection.depth_range().0, view.projection.depth_range().1);
+        match self.render_offscreen(&view.camera, &projection, (width, height)).and_then(|frame| export.write_frame(&frame)) {
+            Err(e) => {
+                let ffmpeg = export.cancel();
+                let reason = if ffmpeg.is_empty() { e.to_string() } else { ffmpeg };
+                self.report_error(Severity::Error, format!("Video export failed: {}", reason));
+                self.end_video_export(view);
+            }
+            Ok(()) if export.is_done() => {
+                match export.finish() {
+                    Ok(saved) => self.toast.show(saved),
+                    Err(e) => self.report_error(Severity::Error, format!("Video export failed: {}", e)),
+                }
+                self.end_video_export(view);
+            }
+            Ok(()) => self.video_export = Some(export),
+        }
+    }
+
+    fn end_video_export(&mut self, view: &mut ViewWindow) {
+        if let Some(camera) = self.camera_before_export.take() {
+            view.camera.position = camera.position;
+            view.camera.set_orientation(camera.yaw(), camera.pitch());
+            view.camera.set_roll(camera.roll());
+        }
+        self.request_redraw();
+    }
+
+    // Over everything else while exporting, egui's modal keeps the other windows from being used.
+    // Escape cancels too.
+    fn draw_video_export_modal(&mut self, ctx: &egui::Context) {
+        let Some(export) = self.video_export.as_mut() else {
+            return;
+        };
+        let (written, frames) = export.progress();
+        egui::Modal::new(egui::Id::new("video_export")).show(ctx, |ui| {
+            ui.heading("Exporting video");
+            ui.label(export.output().display().to_string());
+            ui.add(egui::ProgressBar::new(written as f32 / frames as f32).text(format!("Frame {} of {}", written, frames)).desired_width(280.0));
+            ui.add_enabled_ui(!export.cancel_requested, |ui| {
+                if ui.button("Cancel").clicked() || ctx.input(|input| input.key_pressed(egui::Key::Escape)) {
+                    export.cancel_requested = true;
+                }
+            });
+        });
+    }
+
+    pub fn request_quit(&mut self) {
+        self.quit_requested = true;
+    }
+
+    pub fn take_quit_request(&mut self) -> bool {
+        std::mem::take(&mut self.quit_requested)
+    }
+
+    // Frame times, every model with its handle, and GPU memory, for the console's stats command
+    pub fn stats_report(&mut self) -> String {
+        let summary = self.frame_stats.summary();
+        let mut report = format!(
+            "frames: p50 {:.2} ms, p95 {:.2} ms, max {:.2} ms, {} hitches, {} redraws/s\n",
+            summary.p50_ms,
+            summary.p95_ms,
+            summary.max_ms,
+            summary.hitches,
+            self.frame_stats.redraws_per_second(),
+        );
+        for entry in &self.models {
+            report.push_str(&format!("model {}: {} ({} instances)\n", entry.handle.0, entry.name, entry.instance_count()));
+        }
+        report + self.memory_report().as_str()
+    }
+
+    // Shown in the error overlay, which opens for messages it hasn't seen yet
+    pub fn report_error(&mut self, severity: Severity, message: impl Into<String>) {
+        self.context.error_log.lock().unwrap().push(severity, message);
+        self.request_redraw();
+    }
+
+    // How a window on a `refresh_hz` monitor is held back right now, see FrameCaps::pace
+    pub fn frame_pace(&self, refresh_hz: Option<f64>) -> Option<Pace> {
+        self.frame_caps.pace(!self.in_background, self.context.settings.vsync, refresh_hz)
+    }
+
+    // Ask for every window to be drawn again, for changes on-demand rendering can't see
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
+    }
+
+    pub fn take_redraw_request(&mut self) -> bool {
+        std::mem::take(&mut self.redraw_requested)
+    }
+
+    // Something in the scene keeps changing on its own, so on-demand rendering has to keep
+    // drawing: the light animation or day-night cycle, spinning instances, the random scene, particles, the 2D demo,
+    // textures still streaming in, probes still baking, or a video export
+    pub fn is_animating(&self) -> bool {
+        let baking = self.reflection_probes.iter().any(ReflectionProbe::is_baking);
+        let exporting = self.video_export.is_some();
+        let simulating = !self.paused
+            && ((self.day_night.enabled && self.day_night.playing)
+                || (!self.day_night.enabled && self.orbit_light)
+                || self.show_particles
+                || self.quad_demo.enabled
+                || (self.show_grass && self.grass_wind_strength > 0.0)
+                || self.shape_scene.is_some()
+                || self.skinning_demo.is_some()
+                || self.models.iter().any(ModelEntry::is_animated));
+        let streaming = self.context.texture_streamer.lock().unwrap().stats().active_streams > 0
+            || self.models.iter().any(ModelEntry::is_streaming)
+            || self.texture_residency.stats().loading > 0;
+        simulating || streaming || baking || exporting
+    }
+
+    fn model(&self, handle: ModelHandle) -> Option<&ModelEntry> {
+        self.models.iter().find(|entry| entry.handle == handle)
+    }
+
+    fn model_mut(&mut self, handle: ModelHandle) -> Option<&mut ModelEntry> {
+        self.models.iter_mut().find(|entry| entry.handle == handle)
+    }
+
+    // None once the model was removed
+    pub fn instance_count(&self, handle: ModelHandle) -> Option<usize> {
+        self.model(handle).map(|entry| entry.instance_count() as usize)
+    }
+
+    // What the last import of a file with the same extension used
+    pub fn import_options_for(&self, path: &str) -> ImportOptions {
+        ImportOptions::from_settings(&self.user_settings, &ImportOptions::extension_of(path))
+    }
+
+    // Load an OBJ (relative to res/, like --model) as a new scene entry without any instances yet.
+    // The options are remembered for the next file with the same extension.
+    pub fn add_model(&mut self, path: &str, import: &ImportOptions) -> anyhow::Result<ModelHandle> {
+        import.write_settings(&mut self.user_settings, &ImportOptions::extension_of(path));
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the import options: {}", e);
+        }
+        let context = &self.context;
+        let mut streamer = TextureStreamer::default();
+        let loaded = resources::load_model(
+            path,
+            &context.device,
+            &context.queue,
+            &context.texture_bind_group_layout,
+            &context.material_array.bind_group_layout,
+            &mut streamer,
+            &LoadOptions { import: *import, ..context.settings.mesh_load },
+        )
+        .block_on();
+        let model = match loaded {
+            Ok(model) => model,
+            Err(e) => {
+                self.events.push(EngineEvent::AssetFailed { path: path.to_string(), error: e.to_string() });
+                return Err(e);
+            }
+        };
+        let handle = ModelHandle(self.next_model_handle);
+        self.next_model_handle += 1;
+        if let Some(watcher) = self.texture_watcher.as_mut() {
+            watcher.watch_model(handle, &model);
+        }
+        if self.show_shading_models {
+            for material in &model.materials {
+                material.set_debug_view(&context.queue, true);
+            }
+        }
+        self.models.push(ModelEntry::new(handle, path.to_string(), Arc::new(model), Some(streamer)));
+        self.events.push(EngineEvent::AssetLoaded { path: path.to_string(), handle });
+        self.request_redraw();
+        Ok(handle)
+    }
+
+    // An instance of `shape` wearing the texture pack at `prefix`, see resources::load_material_set.
+    // The same shape and pack share one model, spawning it again adds an instance.
+    pub fn spawn_shape_with_material(&mut self, shape: ShapeKind, prefix: &str, position: cgmath::Vector3<f32>) -> anyhow::Result<InstanceId> {
+        let key = (material_set::normalize_prefix(prefix), shape);
+        let handle = match self.material_set_models.get(&key).copied().filter(|&handle| self.model(handle).is_some()) {
+            Some(handle) => handle,
+            None => {
+                let set = match self.material_sets.get_or_load(prefix, resources::load_material_set) {
+                    Ok(set) => set,
+                    Err(e) => {
+                        self.events.push(EngineEvent::AssetFailed { path: key.0, error: e.to_string() });
+                        return Err(e);
+                    }
+                };
+                let context = &self.context;
+                let name = format!("{} {}", set.report.prefix, shape.label().to_lowercase());
+                let (vertices, indices) = shape_renderer::mesh_keys(shape)[0].build();
+                let mesh = resources::mesh_from_shape(&context.device, &name, &vertices, &indices);
+                let material = set.material(&context.device, &context.queue, &context.texture_bind_group_layout)?;
+                material.set_debug_view(&context.queue, self.show_shading_models);
+                let model = model::Model { meshes: vec![mesh], materials: vec![material], optimize_stats: None, packed: None };
+                let handle = ModelHandle(self.next_model_handle);
+                self.next_model_handle += 1;
+                self.models.push(ModelEntry::new(handle, name, Arc::new(model), None));
+                self.events.push(EngineEvent::AssetLoaded { path: key.0.clone(), handle });
+                self.material_set_models.insert(key, handle);
+                handle
+            }
+        };
+        self.add_instance_of(handle, position, cgmath::Quaternion::one()).ok_or_else(|| anyhow::anyhow!("the model was removed"))
+    }
+
+    // Despawns every instance of the model and releases its GPU buffers. The instance grid's
+    // model is driven by the grid controls and stays.
+    pub fn remove_model(&mut self, handle: ModelHandle) -> bool {
+        if handle == self.grid_model {
+            return false;
+        }
+        if let Some(entry) = self.models.iter().find(|entry| entry.handle == handle) {
+            for (index, _) in entry.instances() {
+                self.events.push(EngineEvent::InstanceDespawned { id: InstanceId { model: handle, index } });
+            }
+        }
+        let count = self.models.len();
+        self.models.retain(|entry| entry.handle != handle);
+        if let Some(watcher) = self.texture_watcher.as_mut() {
+            watcher.unwatch_model(handle);
+        }
+        self.request_redraw();
+        if self.selected_instance.is_some_and(|id| id.model == handle) {
+            self.selected_instance = None;
+        }
+        if self.placing == Some(handle) {
+            self.cancel_placement();
+        }
+        self.pasted_textures.retain(|pasted| pasted.model != handle);
+        self.models.len() != count
+    }
+
+    // False if the model or mesh doesn't exist. The model is shared, so this applies to every
+    // entry drawing it (the grid and the --model light marker share one).
+    pub fn set_mesh_visible<'a>(&mut self, handle: ModelHandle, mesh: impl Into<MeshRef<'a>>, visible: bool) -> bool {
+        let Some(mesh) = self.model(handle).and_then(|entry| entry.model.mesh(mesh.into())) else {
+            return false;
+        };
+        mesh.set_visible(visible);
+        self.request_redraw();
+        true
+    }
+
+    // Switches the material of a mesh to another shading model. Materials are shared by every
+    // mesh using them, and the model by every entry drawing it. False if nothing matched.
+    pub fn set_material_shading<'a>(&mut self, handle: ModelHandle, mesh: impl Into<MeshRef<'a>>, shading_model: ShadingModel) -> bool {
+        let Some(entry) = self.model(handle) else {
+            return false;
+        };
+        let Some(material) = entry.model.mesh(mesh.into()).and_then(|mesh| entry.model.materials.get(mesh.material)) else {
+            return false;
+        };
+        material.set_params(&self.context.queue, MaterialParams { shading_model, ..material.params() });
+        self.request_redraw();
+        true
+    }
+
+    // Every material of every model, the preset matrix of shading models uses it
+    pub fn set_every_material_shading(&mut self, shading_model: ShadingModel) {
+        let queue = &self.context.queue;
+        for material in self.models.iter().flat_map(|entry| entry.model.materials.iter()) {
+            material.set_params(queue, MaterialParams { shading_model, ..material.params() });
+        }
+        self.request_redraw();
+    }
+
+    // Debug view: every material paints the shading model it uses instead of lighting
+    pub fn set_shading_model_view(&mut self, enabled: bool) {
+        self.show_shading_models = enabled;
+        let queue = &self.context.queue;
+        let materials = self.models.iter().flat_map(|entry| entry.model.materials.iter());
+        for material in materials.chain(std::iter::once(&self.context.atlas_material)) {
+            material.set_debug_view(queue, enabled);
+        }
+        self.request_redraw();
+    }
+
+    // Every mesh of every model whose name contains `pattern`, e.g. "wheel". Returns how many matched.
+    pub fn set_visible_matching(&mut self, pattern: &str, visible: bool) -> usize {
+        let matched = self.models.iter().map(|entry| entry.model.set_visible_matching(pattern, visible)).sum();
+        self.request_redraw();
+        matched
+    }
+
+    // A static (not spinning) instance of a loaded model, None if the handle is stale
+    pub fn add_instance_of(
+        &mut self,
+        handle: ModelHandle,
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+    ) -> Option<InstanceId> {
+        let instance = Instance {
+            initial_position: position,
+            position: cgmath::Vector3::zero(),
+            rotation,
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            spin_axis: cgmath::Vector3::unit_y(),
+            spin_speed: 0.0,
+            uv_transform: Atlas::FULL_RECT,
+        };
+        let id = self.spawn_instance(handle, instance.clone())?;
+        self.record(Command::Spawn { id, instance, user_data: None });
+        Some(id)
+    }
+
+    // Adds `instance` to its model without touching the undo history
+    fn spawn_instance(&mut self, handle: ModelHandle, instance: Instance) -> Option<InstanceId> {
+        let index = self.model_mut(handle)?.push_instance(instance);
+        let id = InstanceId { model: handle, index };
+        self.events.push(EngineEvent::InstanceSpawned { id });
+        self.request_redraw();
+        Some(id)
+    }
+
+    // Brings a despawned instance back as `id` without touching the undo history, undo and redo
+    // put instances back through here. False if its model is gone or `id` is taken.
+    pub fn restore_instance(&mut self, id: InstanceId, instance: Instance) -> bool {
+        if !self.model_mut(id.model).is_some_and(|entry| entry.insert_instance(id.index, instance)) {
+            return false;
+        }
+        self.events.push(EngineEvent::InstanceSpawned { id });
+        self.request_redraw();
+        true
+    }
+
+    // Shows `context`'s cursor until it is popped again, unless something with a higher priority
+    // (see CursorContext::priority) is going on
+    pub fn push_cursor(&mut self, context: CursorContext) {
+        self.cursor_stack.push(context);
+        self.request_redraw();
+    }
+
+    // Removes the newest pushed context
+    pub fn pop_cursor(&mut self) -> Option<CursorContext> {
+        self.request_redraw();
+        self.cursor_stack.pop()
+    }
+
+    // The next left click in the main window's scene puts an instance of the model on the
+    // ground, a right click cancels
+    pub fn begin_placement(&mut self, handle: ModelHandle) {
+        let was_placing = self.is_placing();
+        self.placing_light = false;
+        self.measurements.cancel();
+        self.road.stop_drawing();
+        self.placing = Some(handle);
+        if !was_placing {
+            self.push_cursor(CursorContext::Placement);
+        }
+    }
+
+    // Like begin_placement, but the click puts a point light just off whatever it hits
+    pub fn begin_light_placement(&mut self) {
+        let was_placing = self.is_placing();
+        self.placing = None;
+        self.measurements.cancel();
+        self.road.stop_drawing();
+        self.placing_light = true;
+        if !was_placing {
+            self.push_cursor(CursorContext::Placement);
+        }
+    }
+
+    // Clicks in the main window pick the ends of measurements until it is toggled off again or
+    // cancelled like a placement
+    pub fn toggle_measuring(&mut self) {
+        if self.measurements.is_active() {
+            self.cancel_placement();
+            return;
+        }
+        let was_placing = self.is_placing();
+        self.placing = None;
+        self.placing_light = false;
+        self.road.stop_drawing();
+        self.measurements.begin();
+        if !was_placing {
+            self.push_cursor(CursorContext::Placement);
+        }
+        self.gui_windows.open(gui_window::MEASURE_WINDOW);
+        self.save_gui_windows();
+    }
+
+    // Clicks on the ground add road control points until it is toggled off again or cancelled
+    // like a placement
+    pub fn toggle_road_drawing(&mut self) {
+        if self.road.is_drawing() {
+            self.cancel_placement();
+            return;
+        }
+        let was_placing = self.is_placing();
+        self.placing = None;
+        self.placing_light = false;
+        self.measurements.cancel();
+        self.road.enabled = true;
+        self.road.begin_drawing();
+        if !was_placing {
+            self.push_cursor(CursorContext::Placement);
+        }
+    }
+
+    pub fn is_placing(&self) -> bool {
+        self.placing.is_some() || self.placing_light || self.measurements.is_active() || self.road.is_drawing()
+    }
+
+    pub fn cancel_placement(&mut self) {
+        if self.is_placing() {
+            self.placing = None;
+            self.placing_light = false;
+            self.measurements.cancel();
+            self.road.stop_drawing();
+            self.pop_cursor();
+        }
+    }
+
+    // Where the cursor ray meets the ground (y = 0). Stays in placement mode when the cursor
+    // points above the horizon. `keep_placing` (Shift held) leaves light placement on for the
+    // next light.
+    pub fn place_at_cursor(&mut self, view: &ViewWindow, keep_placing: bool) {
+        if self.road.is_drawing() {
+            if let Some(point) = view.cursor_ray().and_then(|ray| self.ground_hit(&ray)) {
+                self.road.add_point(point);
+            }
+            return;
+        }
+        if self.measurements.is_active() {
+            // The hover point already snapped, it is only missing before the first frame
+            let point = self.measurements.hover.or_else(|| self.cursor_surface_point(view));
+            if let Some(point) = point {
+                self.measurements.click(point);
+            }
+            return;
+        }
+        if self.placing_light {
+            let Some(ray) = view.cursor_ray() else {
+                return;
+            };
+            let position = self.light_placement_point(&ray);
+            if !keep_placing {
+                self.cancel_placement();
+            }
+            if self.point_lights.add(position, self.units.units_per_meter()).is_none() {
+                self.report_error(Severity::Warning, format!("There are already {} point lights, remove one to place another", MAX_POINT_LIGHTS));
+                return;
+            }
+            self.gui_windows.open(gui_window::LIGHT_WINDOW);
+            self.save_gui_windows();
+            return;
+        }
+        let Some(handle) = self.placing else {
+            return;
+        };
+        let ground = Plane { normal: cgmath::Vector3::unit_y(), distance: 0.0 };
+        let Some(position) = view.cursor_ray().and_then(|ray| math::ray_plane_intersect(&ray, &ground).map(|t| ray.at(t))) else {
+            return;
+        };
+        self.cancel_placement();
+        self.selected_instance = self.add_instance_of(handle, position, cgmath::Quaternion::one());
+    }
+
+    // Every model entry that has instances on the GPU, numbered one after another for the ID pass
+    fn pick_draws(&self) -> Vec<PickDraw<'_>> {
+        let mut next_id = FIRST_PICK_ID;
+        self.models
+            .iter()
+            .filter_map(|entry| {
+                let draw = PickDraw {
+                    handle: entry.handle,
+                    first_id: next_id,
+                    model: &entry.model,
+                    instance_buffer: entry.instance_buffer()?,
+                    instance_count: entry.instance_count(),
+                };
+                next_id += draw.instance_count;
+                Some(draw)
+            })
+            .collect()
+    }
+
+    // The instance whose pixel is at `position`, exact to the triangle and hidden by whatever
+    // is in front of it. Stalls for one readback, read from the outline's IDs while it is on.
+    pub fn pick_precise(&self, view: &mut ViewWindow, position: (f32, f32)) -> Option<PickResult> {
+        // The ID pass numbers instances by their slot in the buffer
+        let picked = view.pick_instance(&self.context, &self.pick_draws(), position)?.instance;
+        let index = self.model(picked.model)?.index_at(picked.index)?;
+        Some(PickResult { instance: InstanceId { model: picked.model, index } })
+    }
+
+    // The selected instance's ID among `draws`
+    fn selected_pick_id(&self, draws: &[PickDraw]) -> Option<u32> {
+        let id = self.selected_instance?;
+        let slot = self.model(id.model)?.slot(id.index)?;
+        draws.iter().find(|draw| draw.handle == id.model)?.id(slot)
+    }
+
+    // The nearest instance whose bounding box the ray through `position` hits. Cheap, but the
+    // box is bigger than the shape and nothing in front of it hides it.
+    pub fn pick_ray(&self, view: &ViewWindow, position: (f32, f32)) -> Option<PickResult> {
+        let ray = view.ray_through(position)?;
+        let mut nearest: Option<(f32, InstanceId)> = None;
+        for entry in &self.models {
+            for (index, world_bounds) in self.instance_bounds(entry) {
+                if let Some(t) = math::ray_aabb_intersect(&ray, &world_bounds)
+                    && nearest.is_none_or(|(nearest_t, _)| t < nearest_t)
+                {
+                    nearest = Some((t, InstanceId { model: entry.handle, index }));
+                }
+            }
+        }
+        nearest.map(|(_, instance)| PickResult { instance })
+    }
+
+    // Just off the nearest instance box or the ground the ray hits, in the air without a hit
+    fn light_placement_point(&self, ray: &math::Ray) -> cgmath::Vector3<f32> {
+        point_lights::placement_point(ray, self.surface_hit(ray), self.units.units_per_meter())
+    }
+
+    // Nearest of the instance bounding boxes and the ground plane along the ray, the distance and
+    // the surface normal
+    fn surface_hit(&self, ray: &math::Ray) -> Option<(f32, cgmath::Vector3<f32>)> {
+        let ground = Plane { normal: cgmath::Vector3::unit_y(), distance: 0.0 };
+        let mut nearest = math::ray_plane_intersect(ray, &ground).map(|t| (t, ground.normal));
+        for entry in &self.models {
+            for (_, world_bounds) in self.instance_bounds(entry) {
+                // A box around the camera would catch every click
+                if let Some(t) = math::ray_aabb_intersect(ray, &world_bounds).filter(|t| *t > 0.0)
+                    && nearest.is_none_or(|(nearest_t, _)| t < nearest_t)
+                {
+                    nearest = Some((t, world_bounds.face_normal(ray.at(t))));
+                }
+            }
+        }
+        nearest
+    }
+
+    // Where the cursor ray meets a surface, without snapping
+    fn cursor_surface_point(&self, view: &ViewWindow) -> Option<cgmath::Vector3<f32>> {
+        let ray = view.cursor_ray()?;
+        let (t, _) = self.surface_hit(&ray)?;
+        Some(ray.at(t))
+    }
+
+    // Where a click would measure from, the lines and their length labels. Snaps to the origin of
+    // an instance near the cursor on screen while snapping is on.
+    fn update_measurements(&mut self, ctx: &egui::Context, view: &ViewWindow) {
+        let screen = transform_gizmo::ScreenProjection::new(ctx, &view.camera, &view.projection);
+        self.measurements.hover = None;
+        if self.measurements.is_active()
+            && let Some((x, y)) = view.cursor_position()
+        {
+            let cursor = egui::pos2(x, y) / ctx.pixels_per_point();
+            let snapped = self.measurements.snap.then(|| {
+                let origins = self
+                    .models
+                    .iter()
+                    .flat_map(|entry| entry.instances().map(|(_, instance)| instance.initial_position + instance.position));
+                measure::snap_point(&screen, cursor, origins)
+            });
+            self.measurements.hover = snapped.flatten().or_else(|| self.cursor_surface_point(view));
+        }
+        self.measurements.draw_labels(ctx, &screen, self.units);
+        let lines = self.measurements.lines(self.gizmo_scale * self.units.gizmo_scale());
+        self.measurement_lines.upload(&self.context.device, &self.context.queue, &lines);
+    }
+
+    // What the overlays mark this frame: the placement footprint under the cursor and the instance
+    // the cursor is over while measuring. The selection is drawn as it is.
+    fn update_overlays(&mut self, view: &ViewWindow) {
+        let ground = Plane { normal: cgmath::Vector3::unit_y(), distance: 0.0 };
+        let footprint = self.placing.and_then(|handle| {
+            let bounds = self.model(handle)?.model.bounds()?;
+            let ray = view.cursor_ray()?;
+            let position = ray.at(math::ray_plane_intersect(&ray, &ground)?);
+            let center = bounds.center();
+            Some((position + cgmath::Vector3::new(center.x, 0.0, center.z), bounds))
+        });
+        self.overlay.set_footprint(&self.context.queue, footprint.as_ref().map(|(position, bounds)| (*position, bounds)));
+        self.measure_hover_instance = match view.cursor_position() {
+            Some(position) if self.measurements.is_active() => self.pick_ray(view, position).map(|picked| picked.instance),
+            _ => None,
+        };
+    }
+
+    // OutlineMode::Hull, the toon outline's inverted hull around the selected instance. Written
+    // depth and all, before the sky fills in behind it.
+    fn draw_hull_outline(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        if self.outline.mode != OutlineMode::Hull {
+            return;
+        }
+        let Some(id) = self.selected_instance else {
+            return;
+        };
+        let Some(entry) = self.model(id.model) else {
+            return;
+        };
+        let (Some(slot), Some(instance_buffer)) = (entry.slot(id.index), entry.instance_buffer()) else {
+            return;
+        };
+        let toon = &self.context.toon;
+        render_pass.set_pipeline(&toon.model_outline_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &toon.selection_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        let slot = slot as u32;
+        render_pass.draw_model_geometry_instanced(&entry.model, slot..slot + 1);
+    }
+
+    // The overlays over the scene just drawn, see overlay.rs
+    fn draw_overlays(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        let marked = [(OverlayKind::Selection, self.selected_instance), (OverlayKind::Measure, self.measure_hover_instance)];
+        for (kind, id) in marked {
+            let Some(id) = id else {
+                continue;
+            };
+            let Some(entry) = self.model(id.model) else {
+                continue;
+            };
+            if let (Some(slot), Some(instance_buffer)) = (entry.slot(id.index), entry.instance_buffer()) {
+                let slot = slot as u32;
+                self.overlay.draw_model(render_pass, kind, &entry.model, instance_buffer, slot..slot + 1, camera_bind_group);
+            }
+        }
+        self.overlay.draw_footprint(render_pass, camera_bind_group);
+    }
+
+    // A ring where the next click would put the light, ahead of the click itself
+    fn draw_light_preview(&self, ctx: &egui::Context, view: &ViewWindow) {
+        let preview = self.placing_light.then(|| view.cursor_ray()).flatten().map(|ray| self.light_placement_point(&ray));
+        let screen = transform_gizmo::ScreenProjection::new(ctx, &view.camera, &view.projection);
+        let Some(center) = preview.and_then(|position| screen.project(position)) else {
+            return;
+        };
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("light_preview")));
+        painter.circle(center, 9.0, egui::Color32::from_rgba_unmultiplied(255, 240, 180, 60), egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 220, 120)));
+    }
+
+    pub fn point_lights(&self) -> impl Iterator<Item = &PointLight> {
+        self.point_lights.iter()
+    }
+
+    pub fn point_light_mut(&mut self, id: PointLightId) -> Option<&mut PointLight> {
+        self.point_lights.get_mut(id)
+    }
+
+    pub fn remove_point_light(&mut self, id: PointLightId) -> bool {
+        self.point_lights.remove(id)
+    }
+
+    pub fn selected_point_light(&self) -> Option<PointLightId> {
+        self.point_lights.selected
+    }
+
+    pub fn select_point_light(&mut self, id: Option<PointLightId>) {
+        self.point_lights.selected = id;
+    }
+
+    // Models with no instance in the stereo frustum, for the main pass to skip. Without stereo
+    // nothing is culled, the tree has no CPU culling of its own.
+    fn cull_for_stereo(&mut self, frustum: Option<Frustum>) {
+        self.stereo_culled.clear();
+        let Some(frustum) = frustum else {
+            return;
+        };
+        let culled: Vec<ModelHandle> = self
+            .models
+            .iter()
+            .filter(|entry| entry.model.bounds().is_some() && !self.instance_bounds(entry).any(|(_, bounds)| frustum.intersects_aabb(&bounds)))
+            .map(|entry| entry.handle)
+            .collect();
+        self.stereo_culled = culled;
+    }
+
+    // World space box of every instance of the entry, posed like the last upload
+    fn instance_bounds<'a>(&self, entry: &'a ModelEntry) -> impl Iterator<Item = (usize, Aabb)> + 'a {
+        let bounds = entry.model.bounds();
+        let time = self.animation_time;
+        entry.instances().filter_map(move |(index, instance)| Some((index, bounds?.transformed(instance.to_raw(time).model_matrix()))))
+    }
+
+    // Flies the window's camera back along its view direction until every instance of the model
+    // fits. False if the model has nothing to frame (no instances, every mesh hidden).
+    pub fn frame_model(&self, view: &mut ViewWindow, handle: ModelHandle) -> bool {
+        let bounds = self.model(handle).and_then(|entry| self.instance_bounds(entry).map(|(_, bounds)| bounds).reduce(|a, b| a.union(&b)));
+        self.frame_bounds(view, bounds)
+    }
+
+    // Every instance of every model
+    pub fn frame_all(&self, view: &mut ViewWindow) -> bool {
+        let bounds = self
+            .models
+            .iter()
+            .flat_map(|entry| self.instance_bounds(entry))
+            .map(|(_, bounds)| bounds)
+            .reduce(|a, b| a.union(&b));
+        self.frame_bounds(view, bounds)
+    }
+
+    // Saves the window's depth from its last frame as a grayscale PNG, linearized with the camera's
+    // near/far planes and stretched from the nearest (black) to the farthest (white) geometry
+    pub fn capture_depth(&self, view: &ViewWindow, path: &std::path::Path) -> anyhow::Result<()> {
+        let image = view.capture_depth(&self.context)?;
+        image.save(path)?;
+        log::info!("Saved the depth buffer to {}", path.display());
+        Ok(())
+    }
+
+    // The main window's camera keeps `offset` away from the instance and looks at it, easing after
+    // it on a spring (`stiffness` per second). Dragging the mouse orbits it, scrolling moves closer,
+    // the movement keys go back to free flight. None stops following.
+    pub fn set_camera_follow(&mut self, instance: Option<InstanceId>, offset: cgmath::Vector3<f32>, stiffness: f32) {
+        self.camera_follow = instance.map(|instance| CameraFollowTarget { instance, offset, stiffness, started: false });
+        self.request_redraw();
+    }
+
+    // Pivot of the instance in world space
+    fn instance_position(&self, id: InstanceId) -> Option<cgmath::Point3<f32>> {
+        let instance = self.model(id.model)?.instance(id.index)?;
+        Some(cgmath::Point3::from_vec(instance.initial_position + instance.position))
+    }
+
+    // Feeds the followed instance's position to the main window's camera. A despawned instance
+    // drops back to free flight where the camera is, without snapping anywhere.
+    fn update_camera_follow(&mut self, view: &mut ViewWindow) {
+        let Some(follow) = self.camera_follow else {
+            view.stop_following();
+            return;
+        };
+        match self.instance_position(follow.instance) {
+            None => {
+                log::info!("The followed instance is gone, back to free flight");
+                self.camera_follow = None;
+                view.stop_following();
+            }
+            Some(target) if !follow.started => {
+                view.start_following(target, follow.offset, follow.stiffness);
+                self.camera_follow = Some(CameraFollowTarget { started: true, ..follow });
+            }
+            Some(target) => {
+                if !view.follow_target(target, follow.stiffness) {
+                    self.camera_follow = None;
+                }
+            }
+        }
+    }
+
+    // F: the selected instance, or everything when nothing is selected
+    pub fn frame_selection(&self, view: &mut ViewWindow) -> bool {
+        let Some(id) = self.selected_instance else {
+            return self.frame_all(view);
+        };
+        let bounds = self
+            .model(id.model)
+            .and_then(|entry| self.instance_bounds(entry).find(|(index, _)| *index == id.index))
+            .map(|(_, bounds)| bounds);
+        self.frame_bounds(view, bounds)
+    }
+
+    // Home: every instance of the selected instance's model, or everything when nothing is selected
+    pub fn frame_selected_model(&self, view: &mut ViewWindow) -> bool {
+        match self.selected_instance {
+            Some(id) => self.frame_model(view, id.model),
+            None => self.frame_all(view),
+        }
+    }
+
+    fn frame_bounds(&self, view: &mut ViewWindow, bounds: Option<Aabb>) -> bool {
+        let Some(bounds) = bounds else {
+            return false;
+        };
+        view.fly_to(camera::framing_position(&view.camera, &view.projection, &bounds));
+        true
+    }
+
+    // A click in the scene selects what is under it, or clears the selection over empty space
+    pub fn select_at(&mut self, view: &mut ViewWindow, position: (f32, f32)) {
+        let picked = if self.precise_picking {
+            self.pick_precise(view, position)
+        } else {
+            self.pick_ray(view, position)
+        };
+        self.selected_instance = picked.map(|picked| picked.instance);
+        self.request_redraw();
+    }
+
+    // Copies every property of the instance into a new one of the same model, `offset` away.
+    // Several in one frame are fine, the instance buffer is rebuilt once on the next update.
+    pub fn duplicate_instance(&mut self, id: InstanceId, offset: cgmath::Vector3<f32>) -> Option<InstanceId> {
+        let mut copy = self.model(id.model)?.instance(id.index)?.clone();
+        copy.initial_position += offset;
+        let copy_id = self.spawn_instance(id.model, copy.clone())?;
+        self.record(Command::Spawn { id: copy_id, instance: copy, user_data: None });
+        Some(copy_id)
+    }
+
+    // Ctrl+D, the copy is selected
+    pub fn duplicate_selected(&mut self) -> bool {
+        let Some(copy) = self.selected_instance.and_then(|id| self.duplicate_instance(id, DUPLICATE_OFFSET)) else {
+            return false;
+        };
+        self.selected_instance = Some(copy);
+        true
+    }
+
+    // Onto the undo stack. Edits of the same thing merge until the mouse button comes up, see
+    // UndoStack::record.
+    pub fn record(&mut self, command: Command) {
+        let name = command.model().and_then(|handle| self.model(handle)).map_or(String::new(), |entry| entry.name.clone());
+        let description = command.describe(&name);
+        self.undo.record(command, description);
+    }
+
+    // Ctrl+Z. A command that can't be reverted any more is dropped and the one before it tried.
+    pub fn undo(&mut self) -> bool {
+        while let Some(mut entry) = self.undo.take_undo() {
+            match entry.command.revert(self) {
+                Ok(()) => {
+                    self.toast.show(format!("Undo: {}", entry.description));
+                    self.undo.push_undone(entry);
+                    return true;
+                }
+                Err(reason) => log::info!("Dropped \"{}\" from the undo history: {}", entry.description, reason),
+            }
+        }
+        false
+    }
+
+    // Ctrl+Shift+Z, like undo the other way
+    pub fn redo(&mut self) -> bool {
+        while let Some(mut entry) = self.undo.take_redo() {
+            match entry.command.apply(self) {
+                Ok(()) => {
+                    self.toast.show(format!("Redo: {}", entry.description));
+                    self.undo.push_redone(entry);
+                    return true;
+                }
+                Err(reason) => log::info!("Dropped \"{}\" from the redo history: {}", entry.description, reason),
+            }
+        }
+        false
+    }
+
+    // The model's instances were replaced, the ids in its commands would name other ones
+    fn forget_undo(&mut self, handle: ModelHandle, why: &str) {
+        let dropped = self.undo.forget_model(handle);
+        if dropped > 0 {
+            log::info!("Dropped {} edits of model {} from the undo history, {}", dropped, handle.0, why);
+        }
+    }
+
+    // Ctrl+V: the clipboard's image as the diffuse of every material of the selected instance's
+    // model, in place of a render-to-texture camera showing there. Materials are shared by all
+    // of the model's instances. False when nothing is selected or the clipboard has no image.
+    pub fn paste_clipboard_texture(&mut self) -> bool {
+        let Some(handle) = self.selected_instance.map(|id| id.model) else {
+            return false;
+        };
+        let context = self.context.clone();
+        let Some(image) = clipboard_image::read_image(context.device.limits().max_texture_dimension_2d) else {
+            return false;
+        };
+        self.pasted_count += 1;
+        let name = clipboard_image::generated_name(self.pasted_count);
+        let texture = match Texture::from_image(&context.device, &context.queue, &image, Some(&name), false) {
+            Ok(texture) => texture,
+            Err(e) => {
+                self.report_error(Severity::Error, format!("Could not upload the pasted image: {}", e));
+                return false;
+            }
+        };
+        let pasted = PastedTexture::new(name, handle, texture);
+        // Through the field, the RTT cameras are updated while the entry is borrowed
+        let Some(entry) = self.models.iter().find(|entry| entry.handle == handle) else {
+            return false;
+        };
+        for (index, material) in entry.model.materials.iter().enumerate() {
+            material.set_diffuse_override(&context.device, Some(pasted.display()));
+            for rtt in &mut self.rtt_cameras {
+                rtt.remove_material(handle, index);
+            }
+        }
+        log::info!("Pasted a {}x{} image onto {} as {}", pasted.size.0, pasted.size.1, entry.name, pasted.name);
+        self.pasted_textures.retain(|existing| existing.model != handle);
+        self.pasted_textures.push(pasted);
+        self.request_redraw();
+        true
+    }
+
+    // The model's materials go back to their own diffuse texture
+    pub fn remove_pasted_texture(&mut self, handle: ModelHandle) -> bool {
+        let count = self.pasted_textures.len();
+        self.pasted_textures.retain(|pasted| pasted.model != handle);
+        if self.pasted_textures.len() == count {
+            return false;
+        }
+        if let Some(entry) = self.model(handle) {
+            for material in &entry.model.materials {
+                material.set_diffuse_override(&self.context.device, None);
+            }
+        }
+        self.request_redraw();
+        true
+    }
+
+    // Attaches gameplay data (an entity id, health, ...) to the instance, replacing any it had. It
+    // is dropped when the instance is despawned, its model removed or the grid rebuilt, and
+    // duplicates start without any. False if there is no such instance.
+    pub fn set_instance_user_data(&mut self, id: InstanceId, data: Box<dyn std::any::Any + Send>) -> bool {
+        self.model_mut(id.model).is_some_and(|entry| entry.set_user_data(id.index, data))
+    }
+
+    // None without data or with data of another type
+    pub fn take_instance_user_data(&mut self, id: InstanceId) -> Option<Box<dyn std::any::Any + Send>> {
+        self.model_mut(id.model)?.take_user_data(id.index)
+    }
+
+    pub fn get_instance_user_data<T: std::any::Any>(&self, id: InstanceId) -> Option<&T> {
+        self.model(id.model)?.user_data(id.index)
+    }
+
+    pub fn get_instance_user_data_mut<T: std::any::Any>(&mut self, id: InstanceId) -> Option<&mut T> {
+        self.model_mut(id.model)?.user_data_mut(id.index)
+    }
+
+    // Every instance whose data is a T, by model then index, skipping the ones holding anything else
+    pub fn instances_with_user_data<T: std::any::Any>(&self) -> impl Iterator<Item = (InstanceId, &T)> {
+        self.models
+            .iter()
+            .flat_map(|entry| entry.iter_user_data().map(move |(index, data)| (InstanceId { model: entry.handle, index }, data)))
+    }
+
+    // Undone by Ctrl+Z under the same id and with its user data. The other instances keep
+    // theirs. False if there is no such instance.
+    pub fn despawn_instance(&mut self, id: InstanceId) -> bool {
+        let user_data = self.take_instance_user_data(id);
+        let Some(instance) = self.remove_instance(id) else {
+            return false;
+        };
+        self.record(Command::Despawn { id, instance, user_data });
+        true
+    }
+
+    // Delete
+    pub fn despawn_selected(&mut self) -> bool {
+        self.selected_instance.is_some_and(|id| self.despawn_instance(id))
+    }
+
+    // The most recently spawned instance of the model that is still there, None without any
+    pub fn newest_instance(&self, handle: ModelHandle) -> Option<InstanceId> {
+        Some(InstanceId { model: handle, index: self.model(handle)?.newest_index()? })
+    }
+
+    // despawn_instance without the undo history
+    pub fn remove_instance(&mut self, id: InstanceId) -> Option<Instance> {
+        let instance = self.model_mut(id.model)?.remove_instance(id.index)?;
+        self.events.push(EngineEvent::InstanceDespawned { id });
+        if self.selected_instance == Some(id) {
+            self.selected_instance = None;
+        }
+        self.request_redraw();
+        Some(instance)
+    }
+
+    // The change is uploaded on the next update. Grid instances are rebuilt with the grid.
+    pub fn instance_mut(&mut self, id: InstanceId) -> Option<&mut Instance> {
+        self.request_redraw();
+        self.model_mut(id.model)?.instance_mut(id.index)
+    }
+
+    // Zero axes are clamped to MIN_INSTANCE_SCALE. False if the instance doesn't exist.
+    pub fn set_instance_scale(&mut self, id: InstanceId, scale: cgmath::Vector3<f32>) -> bool {
+        match self.instance_mut(id) {
+            Some(instance) => {
+                instance.scale = clamp_scale(scale);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Uploaded right away, materials are shared by every instance of the model. False if there is
+    // no such model or material.
+    pub fn set_material_params(&mut self, handle: ModelHandle, index: usize, params: MaterialParams) -> bool {
+        let Some(material) = self.model(handle).and_then(|entry| entry.model.materials.get(index)) else {
+            return false;
+        };
+        material.set_params(&self.context.queue, params);
+        self.request_redraw();
+        true
+    }
+
+    // Pose every model's instances for the current animation time, timing how long the CPU
+    // spends on it. Only entries whose instances changed or spin are uploaded.
+    fn animate_instances(&mut self) {
+        if self.instance_layout != Some(self.current_instance_layout()) {
+            self.redraw_instances();
+        }
+        let context = &self.context;
+        // Submitted on its own so the copies see last frame's poses, not the writes below.
+        // Motion blur and TAA both read the velocity these give.
+        if (self.motion_blur.enabled || self.taa.enabled) && context.motion_blur.is_some() {
+            let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Previous Instances Encoder"),
+            });
+            for entry in &mut self.models {
+                entry.retain_previous_instances(&context.device, &mut encoder);
+            }
+            context.queue.submit(std::iter::once(encoder.finish()));
+        } else {
+            self.models.iter_mut().for_each(ModelEntry::release_previous_instances);
+        }
+        let time = self.animation_time;
+        let start = std::time::Instant::now();
+        let mut uploaded = false;
+        if self.instance_animation_gpu {
+            let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Instance Animation Encoder"),
+            });
+            encoder.push_debug_group("instance animation");
+            for entry in &mut self.models {
+                uploaded |= entry.upload(&context.device, &context.queue, &context.instance_animation, Some(&mut encoder), time);
+            }
+            encoder.pop_debug_group();
+            context.queue.submit(std::iter::once(encoder.finish()));
+        } else {
+            for entry in &mut self.models {
+                uploaded |= entry.upload(&context.device, &context.queue, &context.instance_animation, None, time);
+            }
+        }
+        if !uploaded {
+            return;
+        }
+        let ms = start.elapsed().as_secs_f32() * 1000.0;
+        let slot = if self.instance_animation_gpu { &mut self.animation_stats.gpu_path_ms } else { &mut self.animation_stats.cpu_path_ms };
+        // Smooth it so the panel is readable
+        *slot = Some(slot.map_or(ms, |previous| previous + (ms - previous) * 0.1));
+    }
+
+    // Remesh the SDF demo when its settings changed since the last build
+    fn update_sdf_demo(&mut self) {
+        let settings = self.sdf_demo_settings;
+        if self.sdf_demo.as_ref().is_some_and(|(built, _)| *built == settings) {
+            return;
+        }
+        let start = std::time::Instant::now();
+        let (vertices, indices) = shapes::mesh_from_sdf(
+            |p| settings.shape.distance(p, settings.blend),
+            settings.shape.bounds(),
+            settings.resolution,
+        );
+        let build_ms = start.elapsed().as_secs_f32() * 1000.0;
+
+        let context = &self.context;
+        let (built, shape) = self.sdf_demo.get_or_insert_with(|| {
+            let shape = DynamicShape::new(&context.device, &context.shape_pipeline, "SDF Demo", SDF_DEMO_POSITION, [0.9, 0.6, 0.3]);
+            (settings, shape)
+        });
+        shape.set_mesh(&context.device, &context.queue, &vertices, &indices);
+        *built = settings;
+        self.sdf_demo_stats = Some(SdfDemoStats {
+            vertices: vertices.len(),
+            triangles: indices.len() / 3,
+            build_ms,
+        });
+    }
+
+    // Adds or removes the demo's model to match the menu, then deforms it for the current time
+    fn update_skinning_demo(&mut self) {
+        // Removed from the model list
+        if self.skinning_demo.as_ref().is_some_and(|demo| self.model(demo.handle).is_none()) {
+            self.skinning_demo = None;
+            self.show_skinning_demo = false;
+        }
+        match (self.show_skinning_demo, &self.skinning_demo) {
+            (true, None) => {
+                let handle = ModelHandle(self.next_model_handle);
+                match SkinningDemo::new(&self.context, handle) {
+                    Ok((demo, model)) => {
+                        self.next_model_handle += 1;
+                        self.models.push(ModelEntry::new(handle, "Skinning demo".to_string(), Arc::new(model), None));
+                        self.add_instance_of(handle, SKINNING_DEMO_POSITION.into(), cgmath::Quaternion::one());
+                        self.skinning_demo = Some(demo);
+                    }
+                    Err(e) => {
+                        self.show_skinning_demo = false;
+                        self.report_error(Severity::Error, format!("Could not build the skinning demo: {}", e));
+                    }
+                }
+            }
+            (false, Some(demo)) => {
+                let handle = demo.handle;
+                self.skinning_demo = None;
+                self.remove_model(handle);
+            }
+            _ => {}
+        }
+        if let Some(demo) = self.skinning_demo.as_mut() {
+            demo.deform(&self.context);
+        }
+    }
+
+    // Adds or removes the mirror's model and camera to match the menu. The camera follows the
+    // viewer in render_rtt_cameras.
+    fn update_mirror_demo(&mut self) {
+        if let Some(demo) = &self.mirror_demo
+            && self.model(demo.handle).is_none()
+        {
+            let rtt = demo.rtt;
+            self.mirror_demo = None;
+            self.show_mirror_demo = false;
+            self.remove_rtt_camera(rtt);
+        }
+        match (self.show_mirror_demo, &self.mirror_demo) {
+            (true, None) => match MirrorDemo::model(&self.context) {
+                Ok(model) => {
+                    let handle = ModelHandle(self.next_model_handle);
+                    self.next_model_handle += 1;
+                    let mut entry = ModelEntry::new(handle, "Mirror demo".to_string(), Arc::new(model), None);
+                    entry.layers = rtt::MIRROR_LAYER;
+                    self.models.push(entry);
+                    let position = cgmath::Vector3::from(MIRROR_DEMO_POSITION) * self.units.units_per_meter();
+                    self.add_instance_of(handle, position, cgmath::Quaternion::one());
+                    let camera = Camera::new(cgmath::Point3::from_vec(position), cgmath::Deg(0.0), cgmath::Deg(0.0));
+                    let rtt = self.add_rtt_camera(camera, rtt::MIRROR_RESOLUTION);
+                    if let Some(camera) = self.rtt_camera_mut(rtt) {
+                        camera.layer_mask = ALL_LAYERS & !rtt::MIRROR_LAYER;
+                    }
+                    self.set_material_rtt(handle, 0, rtt);
+                    self.mirror_demo = Some(MirrorDemo { handle, rtt });
+                }
+                Err(e) => {
+                    self.show_mirror_demo = false;
+                    self.report_error(Severity::Error, format!("Could not build the mirror demo: {}", e));
+                }
+            },
+            (false, Some(demo)) => {
+                let (handle, rtt) = (demo.handle, demo.rtt);
+                self.mirror_demo = None;
+                self.remove_rtt_camera(rtt);
+                self.remove_model(handle);
+            }
+            _ => {}
+        }
+    }
+
+    // Adds or removes the die's model to match the menu
+    fn update_dice_demo(&mut self) {
+        // Removed from the model list
+        if self.dice_demo.is_some_and(|handle| self.model(handle).is_none()) {
+            self.dice_demo = None;
+            self.show_dice_demo = false;
+        }
+        match (self.show_dice_demo, self.dice_demo) {
+            (true, None) => match dice_demo::model(&self.context) {
+                Ok(model) => {
+                    let handle = ModelHandle(self.next_model_handle);
+                    self.next_model_handle += 1;
+                    self.models.push(ModelEntry::new(handle, "Dice demo".to_string(), Arc::new(model), None));
+                    let position = cgmath::Vector3::from(DICE_DEMO_POSITION) * self.units.units_per_meter();
+                    self.add_instance_of(handle, position, cgmath::Quaternion::one());
+                    self.dice_demo = Some(handle);
+                }
+                Err(e) => {
+                    self.show_dice_demo = false;
+                    self.report_error(Severity::Error, format!("Could not build the dice demo: {}", e));
+                }
+            },
+            (false, Some(handle)) => {
+                self.dice_demo = None;
+                self.remove_model(handle);
+            }
+            _ => {}
+        }
+    }
+
+    // Adds or removes the click-to-move actor's model to match the menu
+    fn update_click_move_demo(&mut self) {
+        // Removed from the model list, or its instance
+        if self.click_move_actor.is_some_and(|id| self.get_instance_user_data::<ActorState>(id).is_none()) {
+            if let Some(id) = self.click_move_actor.take() {
+                self.remove_model(id.model);
+            }
+            self.show_click_move_demo = false;
+        }
+        match (self.show_click_move_demo, self.click_move_actor) {
+            (true, None) => match click_move::model(&self.context) {
+                Ok(model) => {
+                    let handle = ModelHandle(self.next_model_handle);
+                    self.next_model_handle += 1;
+                    self.models.push(ModelEntry::new(handle, "Click-to-move actor".to_string(), Arc::new(model), None));
+                    let units_per_meter = self.units.units_per_meter();
+                    let [x, z] = CLICK_MOVE_START.map(|meters| meters * units_per_meter);
+                    let instance = Instance {
+                        initial_position: cgmath::Vector3::new(x, self.ground_height(x, z) + click_move::ACTOR_HALF_HEIGHT * units_per_meter, z),
+                        scale: cgmath::Vector3::new(units_per_meter, units_per_meter, units_per_meter),
+                        ..Instance::placed(cgmath::Vector3::zero(), cgmath::Quaternion::one(), cgmath::Vector3::unit_y())
+                    };
+                    // Not an edit, it stays off the undo stack
+                    if let Some(id) = self.spawn_instance(handle, instance) {
+                        self.set_instance_user_data(id, Box::new(ActorState::Idle));
+                        self.click_move_actor = Some(id);
+                    }
+                }
+                Err(e) => {
+                    self.show_click_move_demo = false;
+                    self.report_error(Severity::Error, format!("Could not build the click-to-move actor: {}", e));
+                }
+            },
+            (false, Some(id)) => {
+                self.click_move_actor = None;
+                self.remove_model(id.model);
+            }
+            _ => {}
+        }
+    }
+
+    // Height of what the click-to-move actor walks on: the grass demo's terrain where it is shown,
+    // the ground plane elsewhere
+    fn ground_height(&self, x: f32, z: f32) -> f32 {
+        self.grass_field.as_ref().filter(|_| self.show_grass).and_then(|field| field.ground_height(x, z)).unwrap_or(0.0)
+    }
+
+    // Where the ray meets what the actor walks on. The terrain lies below the ground plane, so
+    // it comes first wherever the ray reaches it.
+    fn ground_hit(&self, ray: &math::Ray) -> Option<cgmath::Vector3<f32>> {
+        let plane = Plane { normal: cgmath::Vector3::unit_y(), distance: 0.0 };
+        let terrain = self.grass_field.as_ref().filter(|_| self.show_grass).and_then(|field| field.ray_hit(ray));
+        let t = terrain.or_else(|| math::ray_plane_intersect(ray, &plane))?;
+        Some(ray.at(t))
+    }
+
+    // A right click in the main window's scene: the click-to-move actor walks to the ground under
+    // it, a new click while it walks redirects it. Nothing happens when the demo is off or the
+    // click missed the ground.
+    pub fn move_actor_to(&mut self, view: &ViewWindow, position: (f32, f32)) {
+        let Some(id) = self.click_move_actor else {
+            return;
+        };
+        let Some(target) = view.ray_through(position).and_then(|ray| self.ground_hit(&ray)) else {
+            return;
+        };
+        if let Some(actor) = self.get_instance_user_data_mut::<ActorState>(id) {
+            *actor = ActorState::MovingTo(target);
+            self.request_redraw();
+        }
+    }
+
+    // Every instance carrying an ActorState walks, the demo's actor is only the one made so far
+    fn step_click_move_actors(&mut self, dt: f32) {
+        let walking: Vec<_> = self
+            .instances_with_user_data::<ActorState>()
+            .filter(|(_, actor)| **actor != ActorState::Idle)
+            .map(|(id, actor)| (id, *actor))
+            .collect();
+        for (id, mut actor) in walking {
+            let Some(mut instance) = self.model(id.model).and_then(|entry| entry.instance(id.index)).cloned() else {
+                continue;
+            };
+            click_move::step(&mut actor, &mut instance, dt, self.click_move_speed, self.units.units_per_meter(), |x, z| self.ground_height(x, z));
+            if let Some(state) = self.get_instance_user_data_mut::<ActorState>(id) {
+                *state = actor;
+            }
+            if let Some(current) = self.instance_mut(id) {
+                *current = instance;
+            }
+        }
+    }
+
+    fn draw_click_move_settings(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.show_click_move_demo, "Show the actor")
+            .on_hover_text("A capsule that walks to where the ground or the grass demo's terrain is right-clicked");
+        let Some(id) = self.click_move_actor else {
+            return;
+        };
+        ui.add(egui::Slider::new(&mut self.click_move_speed, 0.5..=10.0).text("Speed (m/s)"));
+        let mut follow = self.camera_follow.is_some_and(|follow| follow.instance == id);
+        if ui.checkbox(&mut follow, "Camera follows the actor").changed() {
+            let offset = cgmath::Vector3::from(CLICK_MOVE_FOLLOW_OFFSET) * self.units.units_per_meter();
+            self.set_camera_follow(follow.then_some(id), offset, DEFAULT_FOLLOW_STIFFNESS);
+        }
+        let state = self.get_instance_user_data::<ActorState>(id).map_or("?", |actor| actor.label());
+        ui.label(format!("{}. Right-click the ground to walk there.", state));
+    }
+
+    // Turning the tool off ends drawing, the points stay for when it is turned back on
+    fn update_road(&mut self) {
+        if !self.road.enabled && self.road.is_drawing() {
+            self.cancel_placement();
+        }
+        self.road.update(&self.context, self.units.units_per_meter());
+    }
+
+    // A dragged handle follows the ground under the pointer, the mesh catches up in the next update
+    fn drag_road_handles(&mut self, ctx: &egui::Context, view: &ViewWindow) {
+        let Some((index, pointer)) = self.road.show_handles(ctx, &view.camera, &view.projection) else {
+            return;
+        };
+        let pixel = pointer * ctx.pixels_per_point();
+        if let Some(point) = view.ray_through((pixel.x, pixel.y)).and_then(|ray| self.ground_hit(&ray)) {
+            self.road.move_point(index, point);
+            self.request_redraw();
+        }
+    }
+
+    fn draw_road_settings(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.road.enabled, "Show the road")
+            .on_hover_text("A road along a spline through points clicked onto the ground or the grass demo's terrain");
+        ui.add_enabled_ui(self.road.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = if self.road.is_drawing() { "Stop adding points" } else { "Add points" };
+                if ui.button(label).on_hover_text("Left-click the ground to add a point, right-click to stop").clicked() {
+                    self.toggle_road_drawing();
+                }
+                if ui.add_enabled(!self.road.points.is_empty(), egui::Button::new("Remove last")).clicked() {
+                    self.road.points.pop();
+                }
+                if ui.add_enabled(!self.road.points.is_empty(), egui::Button::new("Clear")).clicked() {
+                    self.road.points.clear();
+                }
+            });
+            let settings = &mut self.road.settings;
+            ui.add(egui::Slider::new(&mut settings.width, 0.5..=20.0).text("Width (m)"));
+            ui.add(egui::Slider::new(&mut settings.segments_per_span, 1..=64).text("Segments per span"));
+            ui.add(egui::Slider::new(&mut settings.uv_tiling, 0.01..=2.0).logarithmic(true).text("UV tiling (per m)"));
+            ui.checkbox(&mut settings.closed, "Closed loop").on_hover_text("Joins the last point back to the first");
+            ui.label(format!("{} points, {} triangles. Drag a handle to move its point.", self.road.points.len(), self.road.triangles()));
+        });
+    }
+
+    // The overlay pipeline's depth bias, automatic or by hand
+    fn draw_overlay_bias_settings(&mut self, ui: &mut egui::Ui, view: &ViewWindow) {
+        let bias = &mut self.overlay_bias;
+        ui.checkbox(&mut bias.auto, "Automatic").on_hover_text("Worked out from the near/far planes and the direction of the depth test");
+        let depth_range = view.projection.depth_range();
+        if bias.auto {
+            (bias.constant, bias.slope_scale) = bias.amounts(depth_range);
+        }
+        ui.add_enabled_ui(!bias.auto, |ui| {
+            ui.add(egui::Slider::new(&mut bias.constant, 0..=64).text("Constant"));
+            ui.add(egui::Slider::new(&mut bias.slope_scale, 0.0..=8.0).text("Slope scale"));
+        });
+        ui.add(egui::Slider::new(&mut bias.max_lift, 0.0..=0.5).text("Max lift (m)"))
+            .on_hover_text("Clamps the bias, how far the overlay may come off the surface in the middle of the depth range");
+        // As of the last frame, without a clamp where the adapter has none
+        let state = self.overlay.bias();
+        ui.label(format!("Pipeline: constant {}, slope scale {:.2}, clamp {:.2e}", state.constant, state.slope_scale, state.clamp));
+        ui.horizontal_wrapped(|ui| {
+            for kind in OverlayKind::ALL {
+                let [r, g, b, _] = kind.color();
+                ui.colored_label(color::swatch([r, g, b]), kind.label());
+            }
+        });
+    }
+
+    // Adds or removes the overlay regression scene's ground to match the menu
+    fn update_overlay_ground(&mut self) {
+        // Removed from the model list
+        if self.overlay_ground.is_some_and(|handle| self.model(handle).is_none()) {
+            self.overlay_ground = None;
+            self.show_overlay_ground = false;
+        }
+        match (self.show_overlay_ground, self.overlay_ground) {
+            (true, None) => match overlay::ground_model(&self.context, self.units.units_per_meter()) {
+                Ok(model) => {
+                    let handle = ModelHandle(self.next_model_handle);
+                    self.next_model_handle += 1;
+                    self.models.push(ModelEntry::new(handle, "Overlay test ground".to_string(), Arc::new(model), None));
+                    self.add_instance_of(handle, cgmath::Vector3::zero(), cgmath::Quaternion::one());
+                    self.overlay_ground = Some(handle);
+                }
+                Err(e) => {
+                    self.show_overlay_ground = false;
+                    self.report_error(Severity::Error, format!("Could not build the overlay test ground: {}", e));
+                }
+            },
+            (false, Some(handle)) => {
+                self.overlay_ground = None;
+                self.remove_model(handle);
+            }
+            _ => {}
+        }
+    }
+
+    // Selects the test ground and looks across it from just above, where a coplanar overlay
+    // without enough bias breaks up into stripes
+    fn view_overlay_ground(&mut self, view: &mut ViewWindow) {
+        let Some(handle) = self.overlay_ground else {
+            return;
+        };
+        let units_per_meter = self.units.units_per_meter();
+        let [height, distance] = OVERLAY_GRAZING_VIEW.map(|meters| meters * units_per_meter);
+        view.camera.position = cgmath::Point3::new(0.0, height, -distance);
+        view.camera.look_at(cgmath::Point3::new(0.0, 0.0, distance));
+        self.selected_instance = Some(InstanceId { model: handle, index: 0 });
+        self.request_redraw();
+    }
+
+    // The occlusion culling test scene: a wall across the near (+Z) side of the instance grid,
+    // a unit box scaled to the grid's width and above its instances
+    fn update_occluder_wall(&mut self) {
+        // Removed from the model list
+        if self.occluder_wall.is_some_and(|handle| self.model(handle).is_none()) {
+            self.occluder_wall = None;
+            self.show_occluder_wall = false;
+        }
+        match (self.show_occluder_wall, self.occluder_wall) {
+            (true, None) => match hiz::wall_model(&self.context) {
+                Ok(model) => {
+                    let handle = ModelHandle(self.next_model_handle);
+                    self.next_model_handle += 1;
+                    self.models.push(ModelEntry::new(handle, "Occluder wall".to_string(), Arc::new(model), None));
+                    let (position, scale) = self.occluder_wall_placement();
+                    let instance = Instance {
+                        scale,
+                        ..Instance::placed(position, cgmath::Quaternion::one(), cgmath::Vector3::unit_y())
+                    };
+                    // Not an edit, it stays off the undo stack
+                    self.spawn_instance(handle, instance);
+                    self.occluder_wall = Some(handle);
+                }
+                Err(e) => {
+                    self.show_occluder_wall = false;
+                    self.report_error(Severity::Error, format!("Could not build the occluder wall: {}", e));
+                }
+            },
+            (false, Some(handle)) => {
+                self.occluder_wall = None;
+                self.remove_model(handle);
+            }
+            _ => {}
+        }
+    }
+
+    // Center and size of the occluder wall for the instance grid as it is now
+    fn occluder_wall_placement(&self) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let units_per_meter = self.units.units_per_meter();
+        let time = self.animation_time;
+        let grid = self.model(self.grid_model);
+        let positions = grid
+            .into_iter()
+            .flat_map(ModelEntry::instances)
+            .map(|(_, instance)| instance.to_raw(time).model_matrix().w.truncate());
+        let centers = Aabb::from_points(positions).unwrap_or(Aabb::new(math::Vec3::zero(), math::Vec3::zero()));
+        // Instances reach out from their centers by about their model's bounds
+        let reach = grid
+            .and_then(|entry| entry.model.bounds())
+            .map_or(units_per_meter, |bounds| instance_cull::bounding_sphere(&bounds).radius);
+        let [rise, gap, thickness] = [OCCLUDER_WALL_RISE, OCCLUDER_WALL_GAP, OCCLUDER_WALL_THICKNESS].map(|meters| meters * units_per_meter);
+        let bottom = centers.min.y - reach;
+        let top = centers.max.y + reach + rise;
+        let scale = cgmath::Vector3::new(centers.max.x - centers.min.x + 2.0 * (reach + gap), top - bottom, thickness);
+        let position = cgmath::Vector3::new(centers.center().x, (bottom + top) * 0.5, centers.max.z + reach + gap + thickness * 0.5);
+        (position, scale)
+    }
+
+    // Eye and target of the view over the occluder wall at the grid behind it, None while the
+    // wall isn't up
+    pub fn occluder_wall_view(&self) -> Option<(cgmath::Point3<f32>, cgmath::Point3<f32>)> {
+        let wall = self.model(self.occluder_wall?)?.instance(0)?;
+        let [above, distance] = OCCLUDER_VIEW.map(|meters| meters * self.units.units_per_meter());
+        let center = wall.initial_position + wall.position;
+        let half_height = wall.scale.y * 0.5;
+        // Down at the wall's foot as far behind it as the eye is in front
+        let eye = cgmath::Point3::new(center.x, center.y + half_height + above, center.z + distance);
+        let target = cgmath::Point3::new(center.x, center.y - half_height, center.z - distance);
+        Some((eye, target))
+    }
+
+    fn view_occluder_wall(&mut self, view: &mut ViewWindow) {
+        let Some((eye, target)) = self.occluder_wall_view() else {
+            return;
+        };
+        view.camera.position = eye;
+        view.camera.look_at(target);
+        self.request_redraw();
+    }
+
+    fn rtt_desc(context: &RenderContext) -> RttDesc<'_> {
+        RttDesc {
+            camera_bind_group_layout: &context.camera_bind_group_layout,
+            color_format: context.scene_format,
+            sample_count: context.settings.msaa_samples,
+        }
+    }
+
+    // Renders every frame from now on, before the main window's main pass. Show it on a material
+    // with set_material_rtt.
+    pub fn add_rtt_camera(&mut self, camera: Camera, resolution: (u32, u32)) -> RttId {
+        let id = RttId(self.next_rtt_id);
+        self.next_rtt_id += 1;
+        let context = &self.context;
+        let rtt = RttCamera::new(&context.device, &Self::rtt_desc(context), id, camera, resolution, self.units.depth_range());
+        self.rtt_cameras.push(rtt);
+        self.request_redraw();
+        id
+    }
+
+    // Its materials go back to their own diffuse texture
+    pub fn remove_rtt_camera(&mut self, id: RttId) -> bool {
+        let Some(index) = self.rtt_cameras.iter().position(|rtt| rtt.id == id) else {
+            return false;
+        };
+        let rtt = self.rtt_cameras.remove(index);
+        for &(handle, material) in rtt.materials() {
+            if let Some(material) = self.model(handle).and_then(|entry| entry.model.materials.get(material)) {
+                material.set_diffuse_override(&self.context.device, None);
+            }
+        }
+        self.request_redraw();
+        true
+    }
+
+    // To move it, aim it or change its layer mask
+    pub fn rtt_camera_mut(&mut self, id: RttId) -> Option<&mut RttCamera> {
+        self.rtt_cameras.iter_mut().find(|rtt| rtt.id == id)
+    }
+
+    // New targets at the new size, the materials showing the camera are rebound to them
+    pub fn set_rtt_resolution(&mut self, id: RttId, resolution: (u32, u32)) -> bool {
+        let context = self.context.clone();
+        let Some(rtt) = self.rtt_cameras.iter_mut().find(|rtt| rtt.id == id) else {
+            return false;
+        };
+        rtt.set_resolution(&context.device, &Self::rtt_desc(&context), resolution);
+        let rtt = self.rtt_cameras.iter().find(|rtt| rtt.id == id).expect("found above");
+        for &(handle, material) in rtt.materials() {
+            if let Some(material) = self.model(handle).and_then(|entry| entry.model.materials.get(material)) {
+                material.set_diffuse_override(&context.device, Some(rtt.display()));
+            }
+        }
+        self.request_redraw();
+        true
+    }
+
+    // Shows the camera's image as the diffuse of a mesh's material. Materials are shared by
+    // every mesh using them, and the model by every entry drawing it. False if nothing matched.
+    pub fn set_material_rtt<'a>(&mut self, handle: ModelHandle, mesh: impl Into<MeshRef<'a>>, id: RttId) -> bool {
+        let Some(entry) = self.model(handle) else {
+            return false;
+        };
+        let Some(index) = entry.model.mesh(mesh.into()).map(|mesh| mesh.material) else {
+            return false;
+        };
+        let Some(rtt) = self.rtt_cameras.iter().find(|rtt| rtt.id == id) else {
+            return false;
+        };
+        let Some(material) = entry.model.materials.get(index) else {
+            return false;
+        };
+        material.set_diffuse_override(&self.context.device, Some(rtt.display()));
+        for rtt in &mut self.rtt_cameras {
+            match rtt.id == id {
+                true => rtt.add_material(handle, index),
+                false => rtt.remove_material(handle, index),
+            }
+        }
+        self.request_redraw();
+        true
+    }
+
+    // Every RTT camera, `rtt_recursion_depth` times. Each level sees the previous one on its
+    // monitors, the first sees them cleared, so a camera looking at its own monitor stops there.
+    fn render_rtt_cameras(&mut self, encoder: &mut wgpu::CommandEncoder, viewer: &Camera) {
+        if self.rtt_cameras.is_empty() {
+            return;
+        }
+        if let Some(demo) = &self.mirror_demo
+            && let Some(instance) = self.model(demo.handle).and_then(|entry| entry.instance(0))
+        {
+            let (position, rotation) = (instance.initial_position + instance.position, instance.rotation);
+            let rtt = demo.rtt;
+            if let Some(camera) = self.rtt_camera_mut(rtt) {
+                MirrorDemo::place_camera(viewer, position, rotation, camera);
+            }
+        }
+        let clear = self.clear_color();
+        for rtt in &self.rtt_cameras {
+            rtt.update(&self.context.queue);
+            rtt.clear_display(encoder, clear);
+        }
+        encoder.push_debug_group("render to texture");
+        for _ in 0..self.rtt_recursion_depth {
+            for rtt in &self.rtt_cameras {
+                {
+                    let mut render_pass = rtt.begin_pass(encoder, clear);
+                    self.draw_scene_objects(&mut render_pass, rtt.camera_bind_group(), false, rtt.layer_mask);
+                }
+                rtt.finish_pass(encoder);
+            }
+        }
+        encoder.pop_debug_group();
+    }
+
+    // Captured the next time bake_probes runs, until then it shows the sky like the fallback
+    pub fn add_reflection_probe(&mut self, position: impl Into<cgmath::Point3<f32>>, resolution: u32) -> ProbeId {
+        let id = ProbeId(self.next_probe_id);
+        self.next_probe_id += 1;
+        let context = &self.context;
+        let desc = ProbeDesc {
+            pipelines: &context.probe_pipelines,
+            camera_bind_group_layout: &context.camera_bind_group_layout,
+            color_format: context.scene_format,
+            sample_count: context.settings.msaa_samples,
+            world_scale: self.units.gizmo_scale(),
+        };
+        let probe = ReflectionProbe::new(&context.device, &desc, id, position.into(), resolution.max(1));
+        self.reflection_probes.push(probe);
+        self.request_redraw();
+        id
+    }
+
+    pub fn remove_reflection_probe(&mut self, id: ProbeId) {
+        self.reflection_probes.retain(|probe| probe.id != id);
+        self.request_redraw();
+    }
+
+    // Rerender every probe. The faces are spread over the next updates, six per probe.
+    pub fn bake_probes(&mut self) {
+        for probe in &mut self.reflection_probes {
+            probe.request_bake();
+        }
+        self.request_redraw();
+    }
+
+    // One face of the first probe still baking. Its own submission, so the probe camera can be
+    // pointed elsewhere for the next face. Gizmos, particles and egui stay out of the capture.
+    fn bake_next_probe_face(&mut self) {
+        let Some(index) = self.reflection_probes.iter().position(ReflectionProbe::is_baking) else {
+            return;
+        };
+        let context = self.context.clone();
+        let Some(target) = self.reflection_probes[index].begin_face(&context.queue) else {
+            return;
+        };
+        let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Probe Bake Encoder") });
+        encoder.push_debug_group("probe bake");
+        {
+            let mut render_pass = target.begin_pass(&mut encoder, self.clear_color());
+            self.draw_scene_objects(&mut render_pass, &target.camera_bind_group, false, ALL_LAYERS);
+        }
+        encoder.pop_debug_group();
+        context.queue.submit(std::iter::once(encoder.finish()));
+        log::debug!("Baked face {} of probe {:?}", target.face, self.reflection_probes[index].id);
+        self.reflection_probes[index].finish_face(&context.queue);
+        self.request_redraw();
+    }
+
+    // Reflective entries mirror the baked probe closest to their instances, or the sky
+    fn probe_bind_group_for(&self, entry: &ModelEntry) -> &wgpu::BindGroup {
+        let center = entry.center().unwrap_or(cgmath::Vector3::new(0.0, 0.0, 0.0));
+        self.reflection_probes
+            .iter()
+            .filter(|probe| probe.is_baked())
+            .min_by(|a, b| {
+                let distance = |probe: &ReflectionProbe| (probe.position.to_vec() - center).magnitude2();
+                distance(a).total_cmp(&distance(b))
+            })
+            .map_or(&self.context.probe_pipelines.fallback_bind_group, ReflectionProbe::bind_group)
+    }
+
+    // Scatter the grass demo again with the current settings, building its field the first time
+    fn regenerate_grass(&mut self) {
+        let context = &self.context;
+        let field = self.grass_field.get_or_insert_with(|| {
+            let height_map = HeightMap::rolling_hills(GRASS_FIELD_EXTENT, 7);
+            GrassField::new(&context.device, &context.grass_pipeline, &context.shape_pipeline, GRASS_FIELD_ORIGIN, height_map)
+        });
+        let start = std::time::Instant::now();
+        field.regenerate(&context.device, &self.grass_density, &self.grass_settings);
+        self.grass_scatter_ms = start.elapsed().as_secs_f32() * 1000.0;
+        self.request_redraw();
+    }
+
+    // Scale the first `count` grid instances by their random direction times the jitter
+    fn apply_scale_jitter(&mut self, count: usize) {
+        let mut rng = rand::thread_rng();
+        while self.scale_jitter_directions.len() < count {
+            let mut direction = || rng.gen_range(-1.0..=1.0);
+            self.scale_jitter_directions.push(cgmath::Vector3::new(direction(), direction(), direction()));
+        }
+        let grid_model = self.grid_model;
+        let indices: Vec<usize> = self.model(grid_model).into_iter().flat_map(ModelEntry::instances).map(|(index, _)| index).take(count).collect();
+        for (slot, index) in indices.into_iter().enumerate() {
+            let scale = cgmath::Vector3::new(1.0, 1.0, 1.0) + self.scale_jitter_directions[slot] * self.scale_jitter;
+            self.set_instance_scale(InstanceId { model: grid_model, index }, scale);
+        }
+    }
+
+    // Give every instance without one a random atlas region
+    fn assign_atlas_regions(&mut self, count: usize) {
+        let atlas = &self.context.atlas;
+        let names = atlas.names();
+        let mut rng = rand::thread_rng();
+        while self.atlas_assignment.len() < count {
+            let uv_rect = names.choose(&mut rng).map_or(Atlas::FULL_RECT, |name| atlas.uv_rect(name));
+            self.atlas_assignment.push(uv_rect);
+        }
+    }
+
+    pub fn draw_overlay(&mut self, ctx: &Context) {
+        let mut windows_changed = false;
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Quit").clicked() {
+                    std::process::exit(0);
+                }
+                ui.menu_button("Windows", |ui| {
+                    windows_changed = self.gui_windows.draw_menu(ui);
+                });
+            });
+        });
+        if windows_changed {
+            self.save_gui_windows();
+        }
+    }
+
+    // Shows or hides a registered window, False if none has that title
+    pub fn toggle_gui_window(&mut self, title: &str) -> bool {
+        let toggled = self.gui_windows.toggle(title);
+        if toggled {
+            self.save_gui_windows();
+        }
+        toggled
+    }
+
+    // Open windows are opened again on the next start
+    fn save_gui_windows(&mut self) {
+        self.gui_windows.write_settings(&mut self.user_settings);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save which windows are open: {}", e);
+        }
+    }
+
+    // Contents of the frame pacing window, see gui_window::StatsWindow
+    pub fn draw_frame_stats(&mut self, ui: &mut egui::Ui, gpu_timer: Option<&GpuTimer>, pacer: &FramePacer) {
+        let summary = self.frame_stats.summary();
+        ui.label(format!(
+            "p50 {:.1} ms | p95 {:.1} ms | p99 {:.1} ms | max {:.1} ms",
+            summary.p50_ms, summary.p95_ms, summary.p99_ms, summary.max_ms
+        ));
+        ui.label(format!("{} hitches in the last {} frames", summary.hitches, summary.frames));
+        ui.label(format!(
+            "Redraws: {}/s ({})",
+            self.frame_stats.redraws_per_second(),
+            self.render_mode.label()
+        ));
+        let focus = if self.in_background { "background" } else { "foreground" };
+        match self.frame_pace(pacer.refresh_rate()) {
+            Some(Pace::Refresh(fps)) => ui.label(format!(
+                "Refresh pacing: {:.1} FPS, waking {:.2} ms early",
+                fps,
+                pacer.sleep_bias().as_secs_f32() * 1000.0
+            )),
+            Some(Pace::Cap(cap)) => ui.label(format!("Frame cap: {} FPS ({})", cap, focus)),
+            None => ui.label(format!("Frame cap: none ({})", focus)),
+        };
+        ui.label(format!("Monitor: {}", pacer.refresh_rate().map_or("refresh rate unknown".to_string(), |hz| format!("{:.2} Hz", hz))));
+        if let Some(intervals) = pacer.interval_stats() {
+            ui.label(format!(
+                "Present interval: {:.2} ms, jitter {:.3} ms (stddev of {} frames)",
+                intervals.mean_ms, intervals.stddev_ms, intervals.samples
+            ));
+        }
+        let meshes = self.context.shape_pipeline.meshes.stats();
+        ui.label(format!("Shape meshes: {} resident, {:.1} KiB", meshes.meshes, meshes.bytes as f32 / 1024.0));
+        ui.label(format!("Material bind group switches: {} per frame", self.material_binds));
+        let (quads, draws) = self.quads_2d.stats();
+        ui.label(format!("2D: {} quads in {} draw calls", quads, draws));
+        self.draw_memory_stats(ui);
+        let mut compact = false;
+        ui.horizontal(|ui| {
+            let live: u32 = self.models.iter().map(ModelEntry::instance_count).sum();
+            let capacity: u32 = self.models.iter().map(ModelEntry::instance_capacity).sum();
+            ui.label(format!("Instance buffers: {} live of {} slots", live, capacity));
+            compact = ui.button("Compact now").on_hover_text("Shrink every model's instance buffers to fit").clicked();
+        });
+        if compact {
+            self.models.iter_mut().for_each(ModelEntry::request_compaction);
+            self.request_redraw();
+        }
+        self.draw_cull_stats(ui);
+        self.draw_lod_stats(ui);
+        egui::CollapsingHeader::new("Recent events").show(ui, |ui| {
+            let log = self.event_log.borrow();
+            if log.is_empty() {
+                ui.weak("None yet");
+            }
+            for line in log.iter() {
+                ui.label(line);
+            }
+        });
+        for entry in &self.models {
+            if let Some(stats) = &entry.model.optimize_stats {
+                ui.label(format!("{}: {}", entry.name, stats.summary()));
+            }
+            if let Some(packed) = &entry.model.packed {
+                let (width, height) = packed.layer_size;
+                ui.label(format!("{}: {} materials packed into {}x{} texture arrays", entry.name, packed.layers, width, height));
+            }
+        }
+        match gpu_timer {
+            Some(timer) => {
+                for pass in GpuPass::ALL {
+                    match timer.pass_ms(pass) {
+                        Some(ms) => ui.label(format!("{}: {:.2} ms GPU", pass.label(), ms)),
+                        None => ui.label(format!("{}: not running", pass.label())),
+                    };
+                }
+            }
+            None => {
+                ui.label("GPU pass times: the adapter has no timestamp queries");
+            }
+        }
+        self.frame_stats.draw_graph(ui, &summary, 120.0);
+        ui.label("Blue: update, orange: render encode, grey: rest of the frame, red: hitch");
+    }
+
+    fn draw_profiler(&mut self, ctx: &Context) {
+        let mut dump = false;
+        let profiler = &mut self.profiler;
+        egui::Window::new("Profiler")
+            .resizable(true)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut profiler.flame, "Flame bars");
+                    let mut frozen = profiler.frozen.is_some();
+                    if ui.checkbox(&mut frozen, "Freeze").changed() {
+                        profiler.frozen = if frozen { profiler.latest().cloned() } else { None };
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut profiler.dump_frames).range(1..=profiler::MAX_FRAMES).suffix(" frames"));
+                    dump = ui.button("Dump Chrome trace").clicked();
+                });
+                ui.label(format!("{} frames buffered, traces go to {}", profiler.frame_count(), PROFILER_TRACE_PATH));
+                if let Some(frame) = profiler.frozen.as_ref().or(profiler.latest()) {
+                    profiler.draw(ui, frame);
+                }
+            });
+        if dump {
+            match self.profiler.dump_chrome_trace(PROFILER_TRACE_PATH, self.profiler.dump_frames) {
+                Ok(()) => log::info!("Wrote the last {} frames to {}", self.profiler.dump_frames, PROFILER_TRACE_PATH),
+                Err(e) => self.report_error(Severity::Error, format!("Could not write {}: {}", PROFILER_TRACE_PATH, e)),
+            }
+        }
+    }
+
+    fn draw_quad_demo_settings(&mut self, ui: &mut egui::Ui) {
+        let demo = &mut self.quad_demo;
+        ui.checkbox(&mut demo.enabled, "2D quad demo");
+        ui.add_enabled_ui(demo.enabled, |ui| {
+            ui.add(egui::Slider::new(&mut demo.count, 0..=quad_2d::MAX_DEMO_QUADS).logarithmic(true).text("Quads"));
+            let mut nearest = demo.filter == quad_2d::QuadFilter::Nearest;
+            if ui.checkbox(&mut nearest, "Pixel perfect (nearest)").changed() {
+                demo.filter = if nearest { quad_2d::QuadFilter::Nearest } else { quad_2d::QuadFilter::Linear };
+            }
+        });
+    }
+
+    fn draw_animation_path_settings(&mut self, ui: &mut egui::Ui) {
+        let paths = &mut self.animation_paths;
+        ui.checkbox(&mut paths.enabled, "Animation paths");
+        ui.add_enabled_ui(paths.enabled, |ui| {
+            ui.add(egui::Slider::new(&mut paths.samples_per_second, 1.0..=120.0).logarithmic(true).text("Path samples per second"));
+            let mut visible = paths.is_visible(LIGHT_PATH_ID);
+            ui.horizontal(|ui| {
+                egui::color_picker::show_color(ui, color::swatch(animation_path::path_color(LIGHT_PATH_ID)), egui::vec2(12.0, 12.0));
+                if ui.checkbox(&mut visible, "Light path").changed() {
+                    paths.set_visible(LIGHT_PATH_ID, visible);
+                }
+            });
+        });
+    }
+
+    fn draw_memory_stats(&mut self, ui: &mut egui::Ui) {
+        self.draw_texture_residency(ui);
+        if !gpu_memory::ENABLED {
+            ui.label("GPU memory: not tracked in this build");
+            return;
+        }
+        let snapshot = gpu_memory::snapshot();
+        let budget = self.context.memory_budget;
+        let header = format!(
+            "GPU memory: {} of {} (peak {})",
+            gpu_memory::format_bytes(snapshot.total),
+            gpu_memory::format_bytes(budget),
+            gpu_memory::format_bytes(snapshot.peak)
+        );
+        egui::CollapsingHeader::new(header).id_salt("gpu_memory").show(ui, |ui| {
+            ui.add(egui::ProgressBar::new((snapshot.total as f64 / budget as f64).min(1.0) as f32));
+            egui::Grid::new("gpu_memory_categories").striped(true).show(ui, |ui| {
+                for usage in snapshot.categories.iter().filter(|usage| usage.count > 0) {
+                    ui.label(usage.category.label());
+                    ui.label(gpu_memory::format_bytes(usage.bytes));
+                    ui.label(format!("{} allocations", usage.count));
+                    ui.end_row();
+                }
+            });
+            ui.label("Largest:");
+            for allocation in gpu_memory::largest(5) {
+                ui.label(format!("{}: {}", allocation.label, gpu_memory::format_bytes(allocation.size)));
+            }
+        });
+    }
+
+    // The texture budget and how the model textures fare under it
+    fn draw_texture_residency(&mut self, ui: &mut egui::Ui) {
+        let stats = self.texture_residency.stats();
+        ui.horizontal(|ui| {
+            let mut limited = self.texture_residency.budget.is_some();
+            if ui.checkbox(&mut limited, "Texture budget").on_hover_text("Evict the least recently drawn model textures beyond it, they show low-res until they fit again").changed() {
+                self.texture_residency.budget = limited.then_some(512 * 1024 * 1024);
+            }
+            if let Some(budget) = self.texture_residency.budget.as_mut() {
+                let mut mib = *budget / (1024 * 1024);
+                if ui.add(egui::DragValue::new(&mut mib).range(16..=65536).suffix(" MiB")).changed() {
+                    *budget = mib * 1024 * 1024;
+                }
+            }
+        });
+        match self.texture_residency.budget {
+            Some(budget) => {
+                ui.add(egui::ProgressBar::new((stats.usage as f64 / budget as f64).min(1.0) as f32).text(format!(
+                    "{} of {}",
+                    gpu_memory::format_bytes(stats.usage),
+                    gpu_memory::format_bytes(budget)
+                )));
+            }
+            None => {
+                ui.label(format!("Textures: {}", gpu_memory::format_bytes(stats.usage)));
+            }
+        }
+        ui.label(format!(
+            "{} resident, {} evicted, {} loading back ({} evictions, {} reloads)",
+            stats.resident, stats.evicted, stats.loading, stats.evictions, stats.reloads
+        ));
+    }
+
+    pub fn draw_inspector_overlay(&mut self, ctx: &Context, view: &ViewWindow) {
+        egui::TopBottomPanel::top("inspector_bar").show(ctx, |ui| {
+            ui.label(format!(
+                "Inspector | {}x{} | camera at ({:.1}, {:.1}, {:.1})",
+                view.size.width,
+                view.size.height,
+                view.camera.position.x,
+                view.camera.position.y,
+                view.camera.position.z,
+            ));
+        });
+    }
+
+    pub fn take_window_size_request(&mut self) -> Option<winit::dpi::PhysicalSize<u32>> {
+        self.window_size_request.take()
+    }
+
+    // Restyles every window from its next frame on and remembers the choice for the next run
+    pub fn set_theme(&mut self, theme: EngineTheme) {
+        if theme == self.theme {
+            return;
+        }
+        self.theme = theme;
+        self.request_redraw();
+        theme.write_settings(&mut self.user_settings);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the UI theme: {}", e);
+        }
+        self.events.push(EngineEvent::SettingsChanged);
+    }
+
+    // Remembered for the next run like the theme
+    pub fn set_frame_caps(&mut self, caps: FrameCaps) {
+        if caps == self.frame_caps {
+            return;
+        }
+        self.frame_caps = caps;
+        caps.write_settings(&mut self.user_settings);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the frame caps: {}", e);
+        }
+        self.events.push(EngineEvent::SettingsChanged);
+    }
+
+    pub fn units(&self) -> SceneUnits {
+        self.units
+    }
+
+    // Rescales what is derived from the units, instance transforms stay as they are
+    pub fn set_units(&mut self, units: SceneUnits) {
+        self.units = units;
+        for probe in &mut self.reflection_probes {
+            probe.set_world_scale(&self.context.queue, units.gizmo_scale());
+        }
+        for rtt in &mut self.rtt_cameras {
+            rtt.set_depth_range(units.depth_range());
+        }
+        self.request_redraw();
+    }
+
+    // Remembered for the next run like the theme
+    pub fn set_invert_mouse_y(&mut self, invert: bool) {
+        self.invert_mouse_y = invert;
+        self.user_settings.set("mouse.invert_y", invert);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the mouse settings: {}", e);
+        }
+        self.events.push(EngineEvent::SettingsChanged);
+    }
+
+    // Remembered for the next run like the theme, windows switch on their next frame
+    pub fn set_controller_profile(&mut self, profile: ControllerProfile) {
+        if profile == self.controller_profile {
+            return;
+        }
+        self.controller_profile = profile;
+        self.request_redraw();
+        profile.write_settings(&mut self.user_settings);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the camera profile: {}", e);
+        }
+        self.events.push(EngineEvent::SettingsChanged);
+    }
+
+    pub fn set_controller_tunables(&mut self, tunables: ControllerTunables) {
+        if tunables == self.controller_tunables {
+            return;
+        }
+        self.controller_tunables = tunables;
+        tunables.write_settings(&mut self.user_settings);
+        if let Err(e) = self.user_settings.save() {
+            log::warn!("Could not save the camera tunables: {}", e);
+        }
+        self.events.push(EngineEvent::SettingsChanged);
+    }
+
+    // The settings window, see gui_window::SettingsWindow
+    pub fn draw_menu(&mut self, ctx: &Context, open: &mut bool, view: &ViewWindow) {
+        egui::Window::new(gui_window::SETTINGS_WINDOW)
+            .open(open)
+            .resizable(true)
+            .vscroll(true)
+            .show(ctx, |ui| {
+                ui.label("Label!");
+
+                if ui.button("Button!").clicked() {
+                    println!("boom!")
+                }
+                if ui.button("Copy diagnostics").on_hover_text("GPU, surface and settings report for bug reports").clicked() {
+                    ctx.copy_text(self.diagnostics_report(Some(view)));
+                }
+                let mut error_log = self.context.error_log.lock().unwrap();
+                if !error_log.is_empty() && ui.button(format!("Show errors ({})", error_log.len())).clicked() {
+                    error_log.open = true;
+                }
+                drop(error_log);
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!("Resolution: {}x{}", view.size.width, view.size.height));
+                    for (width, height) in [(1280, 720), (1920, 1080)] {
+                        if ui.button(format!("{}x{}", width, height)).clicked() {
+                            self.window_size_request = Some(winit::dpi::PhysicalSize::new(width, height));
+                        }
+                    }
+                });
+                ui.separator();
+                let mut theme = self.theme;
+                egui::ComboBox::from_label("UI theme")
+                    .selected_text(theme.preset_name().unwrap_or("Custom"))
+                    .show_ui(ui, |ui| {
+                        for (name, preset) in ui_theme::PRESETS {
+                            ui.selectable_value(&mut theme, preset, name);
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut theme.dark, "Dark");
+                    ui.label("Accent");
+                    egui::color_picker::color_edit_button_srgb(ui, &mut theme.accent);
+                });
+                ui.add(egui::Slider::new(&mut theme.window_opacity, 0.2..=1.0).text("Window opacity"));
+                ui.add(egui::Slider::new(&mut theme.font_size, 9.0..=20.0).step_by(0.5).text("Font size"));
+                self.set_theme(theme);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "# of Instances: {}x{}",
+                        self.num_of_instances,
+                        self.num_of_instance_rows
+                    ));
+                    if ui.button("-").clicked()
+                        && self.num_of_instances > 1 && self.num_of_instance_rows > 1 {
+                            self.num_of_instances -= 1;
+                            self.num_of_instance_rows -= 1;
+                        }
+                    let grown = (self.num_of_instances + 1).checked_mul(self.num_of_instance_rows + 1);
+                    if ui.button("+").clicked() && grown.is_some_and(|count| count <= MAX_INSTANCES) {
+                        self.num_of_instances += 1;
+                        self.num_of_instance_rows += 1;
+                    }
+                    });
+                egui::ComboBox::from_label("Layout")
+                    .selected_text(self.instance_distribution.label())
+                    .show_ui(ui, |ui| {
+                        for distribution in Distribution::ALL {
+                            ui.selectable_value(&mut self.instance_distribution, distribution, distribution.label());
+                        }
+                    });
+                if matches!(self.instance_distribution, Distribution::RandomBox | Distribution::RandomSphere) {
+                    ui.horizontal(|ui| {
+                        ui.label("Seed");
+                        ui.add(egui::DragValue::new(&mut self.instance_seed));
+                        if ui.button("Reroll").clicked() {
+                            self.instance_seed = self.instance_seed.wrapping_add(1);
+                        }
+                    });
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Instance X Position: {}",
+                        self.instance_position_x
+                    ));
+                    ui.add(egui::DragValue::new(&mut self.instance_position_x).speed(0.1));
+                    if ui.button("-").clicked() {
+                        self.instance_position_x -= 1.0;
+                    }
+                    if ui.button("+").clicked() {
+                        self.instance_position_x += 1.0;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Instance Y Position: {}",
+                        self.instance_position_y
+                    ));
+                    ui.add(egui::DragValue::new(&mut self.instance_position_y).speed(0.1));
+                    if ui.button("-").clicked() {
+                        self.instance_position_y -= 1.0;
+                    }
+                    if ui.button("+").clicked() {
+                        self.instance_position_y += 1.0;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Instance Z Position: {}",
+                        self.instance_position_z
+                    ));
+                    ui.add(egui::DragValue::new(&mut self.instance_position_z).speed(0.1));
+                    if ui.button("-").clicked() {
+                        self.instance_position_z -= 1.0;
+                    }
+                    if ui.button("+").clicked() {
+                        self.instance_position_z += 1.0;
+                    }
+                });
+                let mut gpu_animation = self.instance_animation_gpu;
+                if ui.checkbox(&mut gpu_animation, "Animate instances on the GPU").changed() {
+                    self.set_instance_animation_gpu(gpu_animation);
+                }
+                let instance_count = self.models.iter().map(ModelEntry::instance_count).sum::<u32>();
+                let stats = self.animation_stats;
+                let format_ms = |ms: Option<f32>| ms.map_or("-".to_string(), |ms| format!("{:.3} ms", ms));
+                ui.label(format!(
+                    "Instance animation CPU time ({} instances): CPU path {}, GPU path {}",
+                    instance_count,
+                    format_ms(stats.cpu_path_ms),
+                    format_ms(stats.gpu_path_ms)
+                ));
+                if let (Some(cpu_ms), Some(gpu_ms)) = (stats.cpu_path_ms, stats.gpu_path_ms) {
+                    ui.label(format!("GPU path saves {:.3} ms per frame", cpu_ms - gpu_ms));
+                }
+                ui.separator();
+                self.draw_model_list(ui, view.camera.position);
+                ui.separator();
+                ui.add(egui::Slider::new(&mut self.scale_jitter, 0.0..=0.9).text("Grid scale jitter"));
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.atlas_demo, "Atlas demo");
+                    if ui.button("Shuffle regions").clicked() {
+                        self.atlas_assignment.clear();
+                    }
+                });
+                let atlas = &self.context.atlas;
+                ui.label(format!(
+                    "Atlas {}x{}: {} regions, {} rejected, {:.1}% occupied",
+                    atlas.width,
+                    atlas.height,
+                    atlas.names().len(),
+                    atlas.rejected.len(),
+                    atlas.occupancy() * 100.0
+                ));
+                let stream_stats = self.context.texture_streamer.lock().unwrap().stats();
+                ui.label(format!(
+                    "Texture streaming: {} active, {:.1} KB in flight, {:.1} KB pending",
+                    stream_stats.active_streams,
+                    stream_stats.bytes_in_flight as f32 / 1024.0,
+                    stream_stats.bytes_pending as f32 / 1024.0
+                ));
+                ui.separator();
+                ui.checkbox(&mut self.show_gizmo, "Orientation gizmo");
+                ui.add(egui::Slider::new(&mut self.gizmo_scale, 0.1..=4.0).logarithmic(true).text("Gizmo scale"));
+                ui.checkbox(&mut self.profiler.show, "Profiler");
+                egui::ComboBox::from_label("Redraw")
+                    .selected_text(self.render_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in RenderMode::ALL {
+                            ui.selectable_value(&mut self.render_mode, mode, mode.label());
+                        }
+                    });
+                ui.add_enabled(!self.day_night.enabled, egui::Checkbox::new(&mut self.orbit_light, "Animate light"));
+                self.draw_animation_path_settings(ui);
+                if ui.checkbox(&mut self.day_night.enabled, "Day-night cycle").changed() && !self.day_night.enabled {
+                    // Give the light back its own color, the track puts it back on its path
+                    self.light_uniform.color = [1.0, 1.0, 1.0];
+                    self.light_uniform.intensity = 1.0;
+                    self.light_animation.apply(self.light_animation_time, &mut self.light_uniform);
+                }
+                ui.add_enabled_ui(self.day_night.enabled, |ui| {
+                    let day_night = &mut self.day_night;
+                    ui.horizontal(|ui| {
+                        if ui.button(if day_night.playing { "Pause" } else { "Play" }).clicked() {
+                            day_night.playing = !day_night.playing;
+                        }
+                        ui.add(egui::Slider::new(&mut day_night.time_of_day, 0.0..=1.0).text("Time of day"));
+                    });
+                    ui.add(egui::Slider::new(&mut day_night.day_length, 5.0..=600.0).logarithmic(true).text("Day length (s)"));
+                });
+                self.sky_settings.draw(ui, self.day_night.enabled);
+                ui.checkbox(&mut self.pause_on_focus_loss, "Pause when unfocused");
+                let mut invert_mouse_y = self.invert_mouse_y;
+                if ui.checkbox(&mut invert_mouse_y, "Invert mouse Y").changed() {
+                    self.set_invert_mouse_y(invert_mouse_y);
+                }
+                let mut profile = self.controller_profile;
+                egui::ComboBox::from_label("Camera controls (1-4)")
+                    .selected_text(profile.label())
+                    .show_ui(ui, |ui| {
+                        for option in ControllerProfile::ALL {
+                            ui.selectable_value(&mut profile, option, option.label());
+                        }
+                    });
+                self.set_controller_profile(profile);
+                let mut tunables = self.controller_tunables;
+                tunables.draw(ui, profile);
+                self.set_controller_tunables(tunables);
+                let mut units = self.units;
+                egui::ComboBox::from_label("Scene units")
+                    .selected_text(units.label())
+                    .show_ui(ui, |ui| {
+                        for preset in SceneUnits::PRESETS {
+                            ui.selectable_value(&mut units, preset, preset.label());
+                        }
+                        if ui.selectable_label(matches!(units, SceneUnits::Custom(_)), "Custom").clicked() {
+                            units = SceneUnits::Custom(units.units_per_meter());
+                        }
+                    });
+                if let SceneUnits::Custom(factor) = &mut units {
+                    ui.add(egui::DragValue::new(factor).range(0.001..=10000.0).speed(0.1).prefix("Units per meter: "));
+                }
+                if units != self.units {
+                    self.set_units(units);
+                }
+                let mut caps = self.frame_caps;
+                ui.add(egui::Slider::new(&mut caps.foreground, 0..=MAX_FPS_CAP).text("FPS cap (0 = off)"));
+                ui.add(egui::Slider::new(&mut caps.background, 0..=MAX_FPS_CAP).text("FPS cap in background"));
+                ui.add_enabled_ui(!self.context.settings.vsync, |ui| {
+                    ui.checkbox(&mut caps.refresh_pacing, "Pace to the monitor's refresh rate")
+                        .on_hover_text("Sleeps out each frame instead of rendering as fast as possible, the window's current monitor sets the rate");
+                    ui.add_enabled(caps.refresh_pacing, egui::Slider::new(&mut caps.pacing_target, 0..=MAX_FPS_CAP).text("Pacing target FPS (0 = monitor)"));
+                })
+                .response
+                .on_disabled_hover_text("Vsync already paces frames, start with --vsync off");
+                self.set_frame_caps(caps);
+                ui.checkbox(&mut self.depth_prepass, "Depth pre-pass")
+                    .on_hover_text("Models write depth first and are shaded once per pixel. Realistic style only, reflective models are drawn as before. Off while clip planes are on.");
+                ui.add_enabled_ui(self.context.vertex_pulling.is_some(), |ui| {
+                    ui.checkbox(&mut self.vertex_pulling, "Vertex pulling").on_hover_text(
+                        "Models and shapes read their vertices from storage buffers in the vertex shader, one pipeline each whatever the mesh layout. Realistic style without the depth pre-pass or a custom shader only.",
+                    );
+                })
+                .response
+                .on_disabled_hover_text("The adapter can't read storage buffers in vertex shaders");
+                ui.collapsing("Overlay depth bias", |ui| self.draw_overlay_bias_settings(ui, view));
+                ui.collapsing(format!("Clip planes ({} on)", self.clip_planes.active_count()), |ui| {
+                    self.clip_planes.draw_settings(ui, &view.camera);
+                });
+                ui.add_enabled_ui(self.models.iter().any(|entry| entry.model.packed.is_some()), |ui| {
+                    ui.checkbox(&mut self.draw_packed_materials, "Packed material textures").on_hover_text(
+                        "Models loaded with --pack-textures draw every material from one texture array bind group. Realistic style without the depth pre-pass only.",
+                    );
+                });
+                ui.checkbox(&mut self.show_depth_thumbnail, "Depth thumbnail")
+                    .on_hover_text("Linearized depth in the bottom left corner, log scale from the near to the far plane. Shift+F12 saves it as a PNG.");
+                let ssao_settings = &mut self.ssao_settings;
+                ui.checkbox(&mut ssao_settings.enabled, "Ambient occlusion (SSAO)");
+                ui.add_enabled_ui(ssao_settings.enabled, |ui| {
+                    ui.add(egui::Slider::new(&mut ssao_settings.kernel_size, 1..=ssao::MAX_KERNEL_SIZE as u32).text("Kernel size"));
+                    ui.add(egui::Slider::new(&mut ssao_settings.radius, 0.05..=3.0).text("Radius"));
+                    ui.add(egui::Slider::new(&mut ssao_settings.bias, 0.0..=0.2).text("Bias"));
+                    ui.add(egui::Slider::new(&mut ssao_settings.intensity, 0.5..=4.0).text("Intensity"));
+                });
+                if self.context.motion_blur.is_some() {
+                    self.motion_blur.draw(ui);
+                } else {
+                    ui.label("Motion blur needs HDR (--hdr on)");
+                }
+                match (self.context.settings.hdr, self.context.settings.msaa_samples) {
+                    (true, 1) => self.taa.draw(ui),
+                    (false, _) => {
+                        ui.label("TAA needs HDR (--hdr on)");
+                    }
+                    (true, samples) => {
+                        ui.label(format!("TAA is off while {}x MSAA smooths the edges (start with --msaa 1)", samples));
+                    }
+                }
+                self.stereo.draw(ui);
+                ui.separator();
+                ui.checkbox(&mut self.show_particles, "Soft particles");
+                self.draw_quad_demo_settings(ui);
+                ui.add_enabled_ui(self.show_particles, |ui| {
+                    let settings = &mut self.particles.settings;
+                    ui.add(egui::Slider::new(&mut settings.fade_distance, 0.0..=2.0).text("Fade distance"));
+                    ui.add(egui::Slider::new(&mut settings.rate, 0.0..=500.0).text("Particles per second"));
+                    ui.add(egui::Slider::new(&mut settings.size, 0.05..=1.0).text("Particle size"));
+                });
+                ui.separator();
+                ui.checkbox(&mut self.show_sdf_demo, "SDF mesh demo");
+                ui.add_enabled_ui(self.show_sdf_demo, |ui| {
+                    let settings = &mut self.sdf_demo_settings;
+                    egui::ComboBox::from_label("SDF shape")
+                        .selected_text(settings.shape.label())
+                        .show_ui(ui, |ui| {
+                            for shape in SdfShape::ALL {
+                                ui.selectable_value(&mut settings.shape, shape, shape.label());
+                            }
+                        });
+                    ui.add_enabled(
+                        settings.shape == SdfShape::BlendedSpheres,
+                        egui::Slider::new(&mut settings.blend, 0.0..=1.0).text("Blend"),
+                    );
+                    ui.add(egui::Slider::new(&mut settings.resolution, 8..=96).text("Resolution"));
+                    if let Some(stats) = self.sdf_demo_stats {
+                        ui.label(format!(
+                            "{} vertices, {} triangles, meshed in {:.1} ms",
+                            stats.vertices, stats.triangles, stats.build_ms
+                        ));
+                    }
+                });
+                ui.separator();
+                ui.checkbox(&mut self.show_dice_demo, "Dice demo")
+                    .on_hover_text("A cube whose six faces each show their own cell of one texture");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.show_overlay_ground, "Overlay test ground")
+                        .on_hover_text("A 200 m plane to check the selection overlay for z-fighting");
+                    if ui.add_enabled(self.overlay_ground.is_some(), egui::Button::new("View at a grazing angle")).clicked() {
+                        self.grazing_view_request = true;
+                    }
+                });
+                ui.checkbox(&mut self.show_skinning_demo, "Skinning demo");
+                if let Some(demo) = self.skinning_demo.as_mut() {
+                    let mut gpu = demo.gpu();
+                    if ui.checkbox(&mut gpu, "Skin on the GPU").changed() {
+                        demo.set_gpu(gpu);
+                    }
+                    let format_ms = |ms: Option<f32>| ms.map_or("-".to_string(), |ms| format!("{:.3} ms", ms));
+                    ui.label(format!(
+                        "Skinning CPU time ({} vertices): CPU path {}, GPU path {}",
+                        demo.vertex_count(),
+                        format_ms(demo.stats.cpu_path_ms),
+                        format_ms(demo.stats.gpu_path_ms)
+                    ));
+                }
+                ui.separator();
+                self.draw_probe_settings(ui, view);
+                ui.separator();
+                self.draw_rtt_settings(ui, view);
+                ui.separator();
+                ui.checkbox(&mut self.show_grass, "Grass demo");
+                ui.add_enabled_ui(self.show_grass, |ui| self.draw_grass_settings(ui));
+                ui.collapsing("Demo: click to move", |ui| self.draw_click_move_settings(ui));
+                ui.collapsing("Road tool", |ui| self.draw_road_settings(ui));
+                ui.separator();
+                egui::ComboBox::from_label("Render style")
+                    .selected_text(self.render_style.label())
+                    .show_ui(ui, |ui| {
+                        for style in RenderStyle::ALL {
+                            ui.selectable_value(&mut self.render_style, style, style.label());
+                        }
+                    });
+                let mut show_shading_models = self.show_shading_models;
+                if ui
+                    .checkbox(&mut show_shading_models, "Show shading models")
+                    .on_hover_text("Grey: unlit, blue: Lambert, green: Blinn-Phong, orange: PBR-lite")
+                    .changed()
+                {
+                    self.set_shading_model_view(show_shading_models);
+                }
+                ui.add_enabled_ui(self.render_style == RenderStyle::Toon, |ui| {
+                    let toon = &mut self.toon_settings;
+                    ui.add(egui::Slider::new(&mut toon.bands, 1..=8).text("Light bands"));
+                    ui.add(egui::Slider::new(&mut toon.outline_width, 0.0..=8.0).text("Outline width (px)"));
+                    ui.horizontal(|ui| {
+                        ui.label("Outline color");
+                        color::edit_srgb(ui, &mut toon.outline_color);
+                    });
+                });
+                ui.separator();
+                self.outline.draw(ui);
+                ui.separator();
+                if self.context.hdr.is_some() {
+                    let hdr_settings = &mut self.hdr_settings;
+                    egui::ComboBox::from_label("Tonemapper")
+                        .selected_text(hdr_settings.tonemapper.label())
+                        .show_ui(ui, |ui| {
+                            for tonemapper in Tonemapper::ALL {
+                                ui.selectable_value(&mut hdr_settings.tonemapper, tonemapper, tonemapper.label());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut hdr_settings.exposure_ev, -5.0..=5.0).text("Exposure (EV)"));
+                    ui.checkbox(&mut hdr_settings.auto_exposure, "Auto exposure");
+                } else {
+                    ui.label("HDR is off (--hdr off)");
+                }
+            });
+    }
+
+    // Loaded models with their instance counts, loading more and placing instances of them
+    fn draw_probe_settings(&mut self, ui: &mut egui::Ui, view: &ViewWindow) {
+        ui.label("Reflection probes");
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Resolution")
+                .selected_text(self.probe_resolution.to_string())
+                .show_ui(ui, |ui| {
+                    for resolution in PROBE_RESOLUTIONS {
+                        ui.selectable_value(&mut self.probe_resolution, resolution, resolution.to_string());
+                    }
+                });
+            if ui.button("Add probe at camera").clicked() {
+                self.add_reflection_probe(view.camera.position, self.probe_resolution);
+            }
+            if ui.add_enabled(!self.reflection_probes.is_empty(), egui::Button::new("Bake probes")).clicked() {
+                self.bake_probes();
+            }
+        });
+        let mut remove = None;
+        for probe in &self.reflection_probes {
+            ui.horizontal(|ui| {
+                let status = match probe.bake_progress() {
+                    Some(face) => format!("baking {}/6", face),
+                    None if probe.is_baked() => "baked".to_string(),
+                    None => "not baked".to_string(),
+                };
+                let [x, y, z]: [f32; 3] = probe.position.into();
+                ui.label(format!("Probe {} at ({:.1}, {:.1}, {:.1}), {}px, {}", probe.id.0, x, y, z, probe.resolution, status));
+                if ui.button("Remove").clicked() {
+                    remove = Some(probe.id);
+                }
+            });
+        }
+        if let Some(id) = remove {
+            self.remove_reflection_probe(id);
+        }
+    }
+
+    // The mirror demo, recursion depth and every RTT camera with its resolution
+    fn draw_rtt_settings(&mut self, ui: &mut egui::Ui, view: &ViewWindow) {
+        ui.label("Render to texture");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show_mirror_demo, "Mirror demo");
+            if ui.button("Add camera at camera").clicked() {
+                let camera = Camera::new(view.camera.position, view.camera.yaw(), view.camera.pitch()).with_roll(view.camera.roll());
+                self.add_rtt_camera(camera, rtt::MIRROR_RESOLUTION);
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.rtt_recursion_depth, 1..=rtt::MAX_RECURSION_DEPTH).text("Recursion depth"))
+            .on_hover_text("How many levels deep a monitor showing a monitor goes, every camera renders this many times");
+        let mut remove = None;
+        let mut resize = None;
+        for rtt in &self.rtt_cameras {
+            ui.horizontal(|ui| {
+                let (mut width, mut height) = rtt.resolution();
+                ui.label(format!("Camera {}, {} materials", rtt.id.0, rtt.materials().len()));
+                let range = rtt::MIN_RESOLUTION..=rtt::MAX_RESOLUTION;
+                let mut changed = ui.add(egui::DragValue::new(&mut width).range(range.clone()).suffix(" px")).changed();
+                ui.label("x");
+                changed |= ui.add(egui::DragValue::new(&mut height).range(range).suffix(" px")).changed();
+                if changed {
+                    resize = Some((rtt.id, (width, height)));
+                }
+                if ui.button("Remove").clicked() {
+                    remove = Some(rtt.id);
+                }
+            });
+        }
+        if let Some((id, resolution)) = resize {
+            self.set_rtt_resolution(id, resolution);
+        }
+        if let Some(id) = remove {
+            // The demo's own camera goes with the demo
+            match &self.mirror_demo {
+                Some(demo) if demo.rtt == id => self.show_mirror_demo = false,
+                _ => {
+                    self.remove_rtt_camera(id);
+                }
+            }
+        }
+    }
+
+    // Scatter settings only apply on Regenerate, the wind applies right away
+    fn draw_grass_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Density: {}", self.grass_density.label()));
+            if ui.button("Uniform").clicked() {
+                self.grass_density = DensityMap::Uniform;
+            }
+            if ui.button("Patches").clicked() {
+                self.grass_density = DensityMap::patches(self.grass_settings.seed);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.grass_density_input);
+            if ui.button("Load density map").clicked() {
+                let path = self.grass_density_input.trim().to_string();
+                match DensityMap::from_file(&path) {
+                    Ok(density) => self.grass_density = density,
+                    Err(e) => self.report_error(Severity::Error, format!("Could not load density map {}: {}", path, e)),
+                }
+            }
+        });
+        let settings = &mut self.grass_settings;
+        ui.add(egui::Slider::new(&mut settings.density, 1.0..=1000.0).logarithmic(true).text("Clumps per m²"));
+        ui.add(egui::Slider::new(&mut settings.extent, 1.0..=GRASS_FIELD_EXTENT).text("Extent (m)"));
+        ui.add(egui::Slider::new(&mut settings.max_slope, 0.0..=90.0).text("Max slope (°)"));
+        ui.add(egui::Slider::new(&mut settings.scale_jitter, 0.0..=0.9).text("Scale jitter"));
+        ui.checkbox(&mut settings.align_to_normal, "Tilt with the ground");
+        let regenerate = ui
+            .horizontal(|ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(&mut settings.seed));
+                ui.button("Regenerate").clicked()
+            })
+            .inner;
+        if regenerate {
+            self.regenerate_grass();
+        }
+        ui.add(egui::Slider::new(&mut self.grass_wind_strength, 0.0..=1.0).text("Wind strength"));
+        if let Some(field) = &self.grass_field {
+            ui.label(format!("{} clumps in one draw call, scattered in {:.1} ms", field.instance_count(), self.grass_scatter_ms));
+        }
+    }
+
+    // Confirms a load from the menu with the options it is imported with
+    fn draw_import_dialog(&mut self, ctx: &Context) {
+        let Some((path, import)) = self.import_dialog.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut load = false;
+        let mut cancel = false;
+        egui::Window::new(format!("Import {}", path))
+            .id(egui::Id::new("import_dialog"))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                import.draw(ui);
+                ui.horizontal(|ui| {
+                    load = ui.button("Load").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+        if load && let Some((path, import)) = self.import_dialog.take() {
+            self.model_load_error = self.add_model(&path, &import).err().map(|e| format!("Could not load {}: {}", path, e));
+            if let Some(error) = &self.model_load_error {
+                self.report_error(Severity::Error, error.clone());
+            }
+        } else if !open || cancel {
+            self.import_dialog = None;
+        }
+    }
+
+    // `camera_position` is the main window's, following starts from where the camera is
+    fn draw_model_list(&mut self, ui: &mut egui::Ui, camera_position: cgmath::Point3<f32>) {
+        ui.label("Models");
+        let mut spawn = None;
+        let mut place = None;
+        let mut frame = None;
+        let mut remove = None;
+        let mut visibility_changes = Vec::new();
+        let mut reflective_changes = Vec::new();
+        let mut pin_changes = Vec::new();
+        let mut material_changes = Vec::new();
+        let mut unpaste = None;
+        for entry in &self.models {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {} instances", entry.name, entry.instance_count()));
+                let mut reflective = entry.reflective;
+                if ui.checkbox(&mut reflective, "Reflective").changed() {
+                    reflective_changes.push((entry.handle, reflective));
+                }
+                let mut pinned = entry.pin_textures;
+                if ui.checkbox(&mut pinned, "Pin textures").on_hover_text("Never evicted under the texture budget").changed() {
+                    pin_changes.push((entry.handle, pinned));
+                }
+                if entry.handle == self.grid_model {
+                    ui.label("(instance grid)");
+                    return;
+                }
+                if ui.button("Add instance").clicked() {
+                    spawn = Some(entry.handle);
+                }
+                if ui.button("Place").on_hover_text("Click in the scene to place an instance, right click cancels").clicked() {
+                    place = Some(entry.handle);
+                }
+                if ui
+                    .add_enabled(entry.instance_count() > 0, egui::Button::new("Frame"))
+                    .on_hover_text("Move the camera back until every instance is in view (Home frames the selected model)")
+                    .clicked()
+                {
+                    frame = Some(entry.handle);
+                }
+                if ui.button("Remove").clicked() {
+                    remove = Some(entry.handle);
+                }
+            });
+            egui::CollapsingHeader::new(format!("{} meshes", entry.model.meshes.len()))
+                .id_salt(("model_meshes", entry.handle.0))
+                .show(ui, |ui| {
+                    for (index, mesh) in entry.model.meshes.iter().enumerate() {
+                        let mut visible = mesh.is_visible();
+                        if ui.checkbox(&mut visible, &mesh.name).changed() {
+                            visibility_changes.push((entry.handle, index, visible));
+                        }
+                    }
+                });
+            egui::CollapsingHeader::new(format!("{} materials", entry.model.materials.len()))
+                .id_salt(("model_materials", entry.handle.0))
+                .show(ui, |ui| {
+                    for (index, material) in entry.model.materials.iter().enumerate() {
+                        let mut params = material.params();
+                        egui::ComboBox::from_id_salt(("material_shading", entry.handle.0, index))
+                            .selected_text(params.shading_model.label())
+                            .show_ui(ui, |ui| {
+                                for shading_model in ShadingModel::ALL {
+                                    ui.selectable_value(&mut params.shading_model, shading_model, shading_model.label());
+                                }
+                            });
+                        ui.label(&material._name);
+                        if let Some(set) = &material.material_set {
+                            for kind in MapKind::ALL {
+                                let text = format!("{}: {}", kind.label(), set.describe(kind));
+                                match set.source(kind) {
+                                    MapSource::File(_) => ui.label(text),
+                                    MapSource::Defaulted | MapSource::Unsupported(_) => ui.weak(text),
+                                };
+                            }
+                        }
+                        if let Some(fallback) = material.uv_fallback {
+                            ui.label(format!("Generated UVs ({} projection)", fallback.label().to_lowercase()))
+                                .on_hover_text("The OBJ had no texture coordinates for a mesh using this material");
+                        }
+                        ui.horizontal(|ui| {
+                            let [r, g, b, a] = params.color;
+                            let mut rgb = [r, g, b];
+                            color::edit_srgb(ui, &mut rgb);
+                            params.color = [rgb[0], rgb[1], rgb[2], a];
+                            ui.label("Color").on_hover_text("Multiplies the diffuse texture");
+                        });
+                        match params.shading_model {
+                            ShadingModel::BlinnPhong => {
+                                ui.add(egui::Slider::new(&mut params.specular_strength, 0.0..=2.0).text("Specular"));
+                                ui.add(egui::Slider::new(&mut params.shininess, 1.0..=256.0).logarithmic(true).text("Shininess"));
+                            }
+                            ShadingModel::PbrLite => {
+                                ui.add(egui::Slider::new(&mut params.roughness, 0.0..=1.0).text("Roughness"));
+                                ui.add(egui::Slider::new(&mut params.metallic, 0.0..=1.0).text("Metallic"));
+                            }
+                            ShadingModel::Unlit | ShadingModel::Lambert => {}
+                        }
+                        if params != material.params() {
+                            material_changes.push((entry.handle, index, params));
+                        }
+                    }
+                });
+            if let Some(pasted) = self.pasted_textures.iter().find(|pasted| pasted.model == entry.handle) {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Diffuse pasted from the clipboard: {} ({}x{})", pasted.name, pasted.size.0, pasted.size.1));
+                    if ui.small_button("Restore").on_hover_text("Back to the materials' own diffuse textures").clicked() {
+                        unpaste = Some(entry.handle);
+                    }
+                });
+            }
+        }
+        if let Some(handle) = unpaste {
+            self.remove_pasted_texture(handle);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Meshes named like");
+            ui.text_edit_singleline(&mut self.mesh_filter_input);
+        });
+        ui.horizontal(|ui| {
+            let pattern = self.mesh_filter_input.trim().to_string();
+            for (label, visible) in [("Show", true), ("Hide", false)] {
+                if ui.add_enabled(!pattern.is_empty(), egui::Button::new(label)).clicked() {
+                    let matched = self.set_visible_matching(&pattern, visible);
+                    log::info!("{} {} meshes matching {:?}", label, matched, pattern);
+                }
+            }
+        });
+        for (handle, index, visible) in visibility_changes {
+            self.set_mesh_visible(handle, index, visible);
+        }
+        if frame.is_some() {
+            self.frame_request = frame;
+        }
+        for (handle, index, params) in material_changes {
+            if let Some(before) = self.model(handle).and_then(|entry| entry.model.materials.get(index)).map(|material| material.params())
+                && self.set_material_params(handle, index, params)
+            {
+                self.record(Command::Material { model: handle, material: index, before, after: params });
+            }
+        }
+        for (handle, reflective) in reflective_changes {
+            if let Some(entry) = self.model_mut(handle) {
+                entry.reflective = reflective;
+            }
+        }
+        for (handle, pinned) in pin_changes {
+            if let Some(entry) = self.model_mut(handle) {
+                entry.pin_textures = pinned;
+            }
+        }
+        if let Some(handle) = spawn {
+            // Somewhere around the grid, facing a random way
+            let mut rng = rand::thread_rng();
+            let position = cgmath::Vector3::new(rng.gen_range(-10.0..10.0), 2.0, rng.gen_range(-10.0..10.0));
+            let rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(rng.gen_range(0.0..360.0)));
+            self.selected_instance = self.add_instance_of(handle, position, rotation);
+        }
+        if let Some(handle) = place {
+            self.begin_placement(handle);
+        }
+        if let Some(handle) = remove {
+            self.remove_model(handle);
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.model_path_input);
+            if ui.button("Load model").clicked() {
+                let path = self.model_path_input.trim().to_string();
+                let import = self.import_options_for(&path);
+                self.import_dialog = Some((path, import));
+            }
+        });
+        let mut spawn_textured = false;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.material_set_input)
+                .on_hover_text("A texture pack like textures/brick, with maps named brick_albedo.png, brick_normal.png, brick_roughness.png, ...");
+            egui::ComboBox::from_id_salt("material_set_shape").selected_text(self.material_set_shape.label()).show_ui(ui, |ui| {
+                for kind in ShapeKind::ALL {
+                    ui.selectable_value(&mut self.material_set_shape, kind, kind.label());
+                }
+            });
+            spawn_textured = ui.add_enabled(!self.material_set_input.trim().is_empty(), egui::Button::new("Spawn textured")).clicked();
+        });
+        if spawn_textured {
+            let prefix = self.material_set_input.trim().to_string();
+            let mut rng = rand::thread_rng();
+            let position = cgmath::Vector3::new(rng.gen_range(-10.0..10.0), 2.0, rng.gen_range(-10.0..10.0));
+            match self.spawn_shape_with_material(self.material_set_shape, &prefix, position) {
+                Ok(id) => {
+                    self.selected_instance = Some(id);
+                    self.model_load_error = None;
+                }
+                Err(e) => {
+                    let error = format!("Could not load the texture set {}: {}", prefix, e);
+                    self.report_error(Severity::Error, error.clone());
+                    self.model_load_error = Some(error);
+                }
+            }
+        }
+        self.draw_import_dialog(ui.ctx());
+        if let Some(error) = &self.model_load_error {
+            ui.colored_label(egui::Color32::from_rgb(220, 70, 60), error);
+        }
+        ui.checkbox(&mut self.precise_picking, "Pixel-perfect picking")
+            .on_hover_text("Clicking an instance selects it. Off, clicks test bounding boxes: cheaper, but they reach past the shape.");
+
+        if let Some(id) = self.selected_instance {
+            ui.horizontal(|ui| {
+                ui.label(format!("Selected instance #{}", id.index));
+                if ui.small_button("Copy as code").on_hover_text("The Instance as a Rust struct literal").clicked()
+                    && let Some(code) = self.model(id.model).and_then(|entry| entry.instance(id.index)).map(Instance::to_rust_literal)
+                {
+                    self.copy_as_code(ui.ctx(), "instance", code);
+                }
+            });
+            self.draw_selection_transform(ui, id);
+            ui.horizontal(|ui| {
+                ui.label("Gizmo:");
+                for mode in GizmoMode::ALL {
+                    ui.selectable_value(&mut self.transform_gizmo.mode, mode, mode.label());
+                }
+            });
+            ui.label("Hold Ctrl while dragging to snap. With the cursor unlocked (L), W/E/R pick the mode.");
+            ui.label("Alt-drag a move handle or press Ctrl+D to duplicate, Delete despawns. Ctrl+Z undoes, Ctrl+Shift+Z redoes.");
+            ui.label("F frames the selected instance, Home every instance of its model.");
+            if self.camera_follow.is_none_or(|follow| follow.instance != id)
+                && ui.button("Follow with the camera").clicked()
+                && let Some(target) = self.instance_position(id)
+            {
+                self.set_camera_follow(Some(id), camera_position - target, DEFAULT_FOLLOW_STIFFNESS);
+            }
+        }
+        if let Some(mut follow) = self.camera_follow {
+            let name = self.model(follow.instance.model).map_or("?".to_string(), |entry| entry.name.clone());
+            ui.horizontal(|ui| {
+                ui.label(format!("Camera follows {} #{}", name, follow.instance.index));
+                if ui.button("Clear").clicked() {
+                    self.set_camera_follow(None, follow.offset, follow.stiffness);
+                }
+            });
+            if ui.add(egui::Slider::new(&mut follow.stiffness, 0.5..=20.0).text("Follow stiffness")).changed() {
+                self.camera_follow = self.camera_follow.map(|current| CameraFollowTarget { stiffness: follow.stiffness, ..current });
+            }
+            ui.label("Drag to orbit the instance, scroll to zoom, the movement keys fly free again.");
+        }
+    }
+
+    // Position, rotation as Euler degrees and scale of the selection, written through instance_mut
+    // like the gizmo's edits. Only the fields that changed are written back.
+    fn draw_selection_transform(&mut self, ui: &mut egui::Ui, id: InstanceId) {
+        let Some(instance) = self.model(id.model).and_then(|entry| entry.instance(id.index)) else {
+            return;
+        };
+        let offset = instance.position;
+        let mut position: [f32; 3] = (instance.initial_position + offset).into();
+        let rotation = instance.rotation;
+        let mut scale: [f32; 3] = instance.scale.into();
+        let mut euler = match self.selection_euler {
+            Some((shown, from, euler)) if shown == id && from == rotation => euler,
+            _ => {
+                let euler = cgmath::Euler::from(rotation);
+                [euler.x, euler.y, euler.z].map(|angle| cgmath::Deg::from(angle).0)
+            }
+        };
+        let steps = self.transform_gizmo.snap;
+        let ctrl = ui.input(|input| input.modifiers.ctrl);
+        let mut changed = [false; 3];
+        egui::Grid::new("selection_transform").num_columns(4).show(ui, |ui| {
+            changed[0] = transform_gizmo::drag_vector(ui, "Position", &mut position, 0.05, "", ctrl.then_some(steps.translate));
+            changed[1] = transform_gizmo::drag_vector(ui, "Rotation", &mut euler, 0.5, "°", ctrl.then_some(steps.rotate_degrees));
+            changed[2] = transform_gizmo::drag_vector(ui, "Scale", &mut scale, 0.01, "", ctrl.then_some(steps.scale));
+        });
+        self.transform_gizmo.snap.draw(ui);
+        if !changed.contains(&true) {
+            return;
+        }
+        let rotation = cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(euler[0]), cgmath::Deg(euler[1]), cgmath::Deg(euler[2])));
+        if changed[1] {
+            self.selection_euler = Some((id, rotation, euler));
+        }
+        if let Some(instance) = self.instance_mut(id) {
+            let before = InstanceTransform::of(instance);
+            if changed[0] {
+                instance.initial_position = cgmath::Vector3::from(position) - offset;
+            }
+            if changed[1] {
+                instance.rotation = rotation;
+            }
+            if changed[2] {
+                instance.scale = clamp_scale(scale.into());
+            }
+            let after = InstanceTransform::of(instance);
+            self.record(Command::Transform { id, before, after });
+        }
+    }
+
+    // Relative to res/ like model textures, each path is loaded once
+    pub fn load_texture_2d(&mut self, path: &str) -> anyhow::Result<QuadTexture> {
+        let context = &self.context;
+        self.quads_2d.load_texture(&context.device, &context.queue, &context.quad_2d, path)
+    }
+
+    // Queue a quad for this frame's 2D pass. Top-left corner and size in pixels with y down,
+    // rotation in radians around the quad's center, tint multiplies the texture. Layer, filter
+    // and UV rectangle chain on the result, e.g. `.layer(2).filter(QuadFilter::Nearest)`.
+    pub fn draw_quad_2d(&mut self, texture: QuadTexture, pos_px: [f32; 2], size_px: [f32; 2], rotation: f32, tint: [f32; 4]) -> &mut Quad2D {
+        self.quads_2d.push(texture, pos_px, size_px, rotation, tint)
+    }
+
+    // The demo's quads follow the window's size, so they are queued once the view is known
+    fn queue_quad_demo(&mut self, view: &ViewWindow) {
+        if !self.quad_demo.enabled {
+            return;
+        }
+        let texture = match self.load_texture_2d(quad_2d::DEMO_TEXTURE) {
+            Ok(texture) => texture,
+            Err(e) => {
+                self.quad_demo.enabled = false;
+                self.report_error(Severity::Error, format!("2D quad demo: {}", e));
+                return;
+            }
+        };
+        let filter = self.quad_demo.filter;
+        let quads: Vec<_> = self.quad_demo.quads(view.camera_2d.size(), self.animation_time).collect();
+        for quad in quads {
+            self.draw_quad_2d(texture, quad.position, quad.size, quad.rotation, quad.tint)
+                .layer(quad.layer)
+                .uv_rect(quad.uv_rect)
+                .filter(filter);
+        }
+        let (width, height) = self.quads_2d.texture_size(texture);
+        self.draw_quad_2d(texture, [0.0, 0.0], [width as f32, height as f32], 0.0, [1.0; 4]).layer(3).filter(filter);
+        if let Some((x, y)) = view.cursor_position() {
+            let cursor = view.camera_2d.screen_to_world(cgmath::Vector2::new(x, y));
+            self.draw_quad_2d(texture, [cursor.x - 8.0, cursor.y - 8.0], [16.0; 2], std::f32::consts::FRAC_PI_4, [1.0, 0.9, 0.2, 1.0]).layer(4);
+        }
+    }
+
+    // Record the scene's draw calls into an already started render pass
+    pub fn draw_scene(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup) {
+        let context = self.context.clone();
+        // The light marker shows up together with the instance grid. It is emissive, so it keeps
+        // its own unbanded, unoutlined pipeline in every style.
+        if self.model(self.grid_model).and_then(ModelEntry::instance_buffer).is_some() {
+            render_pass.set_pipeline(&context.light_render_pipeline);
+            render_pass.draw_light_model(&context.obj_model, camera_bind_group, &self.light_bind_group);
+        }
+        self.draw_scene_objects(render_pass, camera_bind_group, true, ALL_LAYERS);
+        self.draw_hull_outline(render_pass, camera_bind_group);
+        if self.sky_settings.mode == SkyMode::Procedural {
+            self.sky.draw(render_pass, camera_bind_group);
+        }
+        self.draw_overlays(render_pass, camera_bind_group);
+        context.probe_pipelines.draw_gizmos(render_pass, camera_bind_group, self.reflection_probes.iter());
+        self.animation_paths.draw(render_pass, &context.debug_lines, camera_bind_group);
+        self.point_light_markers.draw(render_pass, &context.debug_lines, camera_bind_group);
+        self.measurement_lines.draw(render_pass, &context.debug_lines, camera_bind_group);
+    }
+
+    // Everything but debug gizmos, what reflection probes capture. `main_pass` draws after the
+    // depth pre-pass (when it is on) and with reflections. Probe bakes draw without either,
+    // reflective models then look like the others, a probe can't sample the cubemap it is baking.
+    // Models outside `layer_mask` are skipped, everything else is on DEFAULT_LAYER.
+    fn draw_scene_objects(&self, render_pass: &mut wgpu::RenderPass<'_>, camera_bind_group: &wgpu::BindGroup, main_pass: bool, layer_mask: u32) {
+        let context = &self.context;
+
+        let toon = (self.render_style == RenderStyle::Toon).then_some(&context.toon);
+        let prepass = main_pass && self.depth_prepass_active();
+        let custom = self.custom_shader.as_ref().filter(|_| toon.is_none());
+        let model_pipeline = match custom {
+            _ if prepass => &context.depth_prepass.model_pipeline,
+            Some(custom) => &custom.pipeline,
+            None => &context.render_pipeline,
+        };
+        // The packed and pulling pipelines only stand in for render_pipeline
+        let packed = self.draw_packed_materials && toon.is_none() && !prepass && custom.is_none();
+        let pulling = self.context.vertex_pulling.as_ref().filter(|_| self.vertex_pulling && toon.is_none() && !prepass && custom.is_none());
+        let custom_frame = custom.is_some_and(|custom| custom.uses_frame);
+        match toon {
+            Some(toon) => {
+                render_pass.set_pipeline(&toon.model_pipeline);
+                render_pass.set_bind_group(3, &toon.bind_group, &[]);
+            }
+            None => render_pass.set_pipeline(model_pipeline),
+        }
+        if custom_frame {
+            render_pass.set_bind_group(custom_shader::FRAME_GROUP, &self.frame_bind_group, &[]);
+        }
+        for entry in self.models.iter().filter(|entry| entry.layers & layer_mask != 0) {
+            let Some(instance_buffer) = entry.instance_buffer() else {
+                continue;
+            };
+            if main_pass && self.stereo_culled.contains(&entry.handle) {
+                continue;
+            }
+            // The main window's main pass draws what survived the cull, the other passes everything
+            let culled = entry.culled().filter(|_| main_pass && self.draw_culled).map(CulledInstances::draw);
+            let (instance_buffer, instances, indirect) = match culled {
+                Some((buffer, CulledDraw::Instances(instances))) => (buffer, instances, None),
+                Some((buffer, CulledDraw::Indirect(indirect))) => (buffer, 0..entry.instance_count(), Some(indirect)),
+                None => (instance_buffer, 0..entry.instance_count(), None),
+            };
+            render_pass.insert_debug_marker(&entry.name);
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            let reflective = main_pass && toon.is_none() && entry.reflective;
+            if reflective {
+                render_pass.set_pipeline(&context.probe_pipelines.reflective_model_pipeline);
+                render_pass.set_bind_group(3, self.probe_bind_group_for(entry), &[]);
+            }
+            if self.atlas_demo && entry.handle == self.grid_model {
+                for (index, mesh) in entry.model.meshes.iter().enumerate().filter(|(_, mesh)| mesh.is_visible()) {
+                    match indirect {
+                        Some(indirect) => render_pass.draw_mesh_indirect(mesh, &context.atlas_material, indirect, instance_cull::indirect_offset(index), camera_bind_group, &self.light_bind_group),
+                        None => render_pass.draw_mesh_instanced(mesh, &context.atlas_material, instances.clone(), camera_bind_group, &self.light_bind_group),
+                    }
+                }
+            } else if let Some(pulling) = pulling
+                && !reflective
+                && !(packed && entry.model.packed.is_some())
+            {
+                render_pass.draw_model_pulled(&entry.model, instance_buffer, instances, indirect, pulling, &context.device, model_pipeline, camera_bind_group, &self.light_bind_group);
+            } else if let Some(indirect) = indirect {
+                // Packed materials draw through their own bind groups here, the packed pipeline has no indirect path
+                render_pass.draw_model_indirect(&entry.model, indirect, camera_bind_group, &self.light_bind_group);
+            } else if packed && !reflective {
+                render_pass.draw_model_packed(&entry.model, instances, &context.material_array, model_pipeline, camera_bind_group, &self.light_bind_group);
+            } else {
+                render_pass.draw_model_instanced(&entry.model, instances, camera_bind_group, &self.light_bind_group);
+            }
+            if reflective {
+                render_pass.set_pipeline(model_pipeline);
+                if custom_frame {
+                    render_pass.set_bind_group(custom_shader::FRAME_GROUP, &self.frame_bind_group, &[]);
+                }
+            }
+        }
+        if let Some(toon) = toon
+            && self.toon_settings.outline_width > 0.0
+        {
+            render_pass.set_pipeline(&toon.model_outline_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &toon.bind_group, &[]);
+            self.draw_scene_geometry(render_pass);
+        }
+
+        if layer_mask & DEFAULT_LAYER == 0 {
+            return;
+        }
+        let shape_style = match toon {
+            Some(toon) => ShapeStyle::Toon(&toon.bind_group),
+            None if pulling.is_some() => ShapeStyle::Pulled(&context.device),
+            None => ShapeStyle::Realistic,
+        };
+        if let Some(shape_scene) = &self.shape_scene {
+            shape_scene.draw(render_pass, &context.shape_pipeline, camera_bind_group, shape_style);
+        }
+        if self.show_sdf_demo && let Some((_, shape)) = &self.sdf_demo {
+            shape.draw(render_pass, &context.shape_pipeline, camera_bind_group, shape_style);
+        }
+        if let Some(road) = self.road.shape() {
+            road.draw(render_pass, &context.shape_pipeline, camera_bind_group, shape_style);
+        }
+        if self.show_grass && let Some(field) = &self.grass_field {
+            field.draw(render_pass, &context.grass_pipeline, &context.shape_pipeline, [camera_bind_group, &self.light_bind_group], shape_style);
+        }
+    }
+
+    // The camera's context follows the mouse button, placement and hooks only show in the main window
+    fn apply_cursor(&self, ctx: &Context, view: &ViewWindow) {
+        let camera = view.is_dragging().then_some(if view.cursor_grabbed { CursorContext::MouseLook } else { CursorContext::CameraDrag });
+        match view.kind {
+            ViewKind::Primary => self.cursor_stack.apply(ctx, camera.into_iter().chain(self.transform_gizmo.cursor_context()), view.is_dragging()),
+            ViewKind::Inspector => CursorStack::default().apply(ctx, camera, view.is_dragging()),
+        }
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.selected_instance.is_some()
+    }
+
+    // Handles around the selected instance, edits go through instance_mut like the menu's
+    fn draw_transform_gizmo(&mut self, ctx: &Context, view: &ViewWindow) {
+        let Some(mut id) = self.selected_instance else {
+            self.transform_gizmo.release();
+            return;
+        };
+        let Some(instance) = self.model(id.model).and_then(|entry| entry.instance(id.index)) else {
+            self.transform_gizmo.release();
+            return;
+        };
+        let offset = instance.position;
+        let transform = GizmoTransform {
+            position: instance.initial_position + offset,
+            rotation: instance.rotation,
+            scale: instance.scale,
+        };
+        let edited = self.transform_gizmo.show(ctx, &view.camera, &view.projection, transform);
+        // Alt-drag: the drag carries on with a copy, which starts out exactly where the original is
+        if self.transform_gizmo.take_duplicate_request()
+            && let Some(copy) = self.duplicate_instance(id, cgmath::Vector3::zero())
+        {
+            self.selected_instance = Some(copy);
+            id = copy;
+        }
+        if let Some(edited) = edited
+            && edited != transform
+            && let Some(instance) = self.instance_mut(id)
+        {
+            let before = InstanceTransform::of(instance);
+            instance.initial_position = edited.position - offset;
+            instance.rotation = edited.rotation;
+            instance.scale = clamp_scale(edited.scale);
+            let after = InstanceTransform::of(instance);
+            // The whole drag is one command, closed when the button comes up
+            self.record(Command::Transform { id, before, after });
+        }
+    }
+
+    // Every binding of the InputMap by category, filtered by key or description. Keys bound to
+    // more than one action in the same context are red.
+    fn draw_help_overlay(&mut self, ctx: &Context) {
+        let screen = ctx.screen_rect();
+        // Dims the scene and keeps clicks from reaching it
+        egui::Area::new(egui::Id::new("help_backdrop"))
+            .fixed_pos(screen.min)
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                let (rect, _) = ui.allocate_exact_size(screen.size(), egui::Sense::click());
+                ui.painter().rect_filled(rect, 0.0, egui::Color32::from_black_alpha(170));
+            });
+        let conflicts = self.input_map.conflicts();
+        let filter = self.help_filter.to_lowercase();
+        let mut open = true;
+        egui::Window::new("Keyboard shortcuts")
+            .order(egui::Order::Foreground)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.help_filter).hint_text("Filter by key or action"));
+                egui::ScrollArea::vertical().max_height(screen.height() * 0.7).show(ui, |ui| {
+                    for category in Category::ALL {
+                        let rows: Vec<_> = self
+                            .input_map
+                            .bindings()
+                            .iter()
+                            .filter(|(binding, action)| action.category() == category && (filter.is_empty() || binding.label().to_lowercase().contains(&filter) || action.description().to_lowercase().contains(&filter)))
+                            .collect();
+                        if rows.is_empty() {
+                            continue;
+                        }
+                        ui.heading(category.label());
+                        egui::Grid::new(("help", category.label())).num_columns(2).spacing([24.0, 4.0]).show(ui, |ui| {
+                            for (binding, action) in rows {
+                                let key = egui::RichText::new(binding.label()).monospace().strong();
+                                if conflicts.contains(binding) {
+                                    ui.label(key.color(egui::Color32::from_rgb(255, 90, 80))).on_hover_text("Bound to more than one action");
+                                } else {
+                                    ui.label(key);
+                                }
+                                match binding.when {
+                                    When::Always => ui.label(action.description()),
+                                    When::Selection => ui.label(format!("{} (with a selection, cursor free)", action.description())),
+                                };
+                                ui.end_row();
+                            }
+                        });
+                    }
+                });
+            });
+        if !open || ctx.input(|input| input.key_pressed(egui::Key::Escape)) {
+            self.show_help = false;
+        }
+    }
+
+    // Axis labels on the faces of the gizmo cube that face the viewer
+    // Red-tinted list of recent errors, opens by itself when a new one arrives
+    fn draw_error_overlay(ctx: &Context, context: &RenderContext) {
+        let mut error_log = context.error_log.lock().unwrap();
+        if !error_log.open {
+            return;
+        }
+        let frame = egui::Frame::window(&ctx.style()).fill(egui::Color32::from_rgba_unmultiplied(70, 12, 12, 235));
+        egui::Window::new(format!("Errors ({})", error_log.len()))
+            .id(egui::Id::new("error_overlay"))
+            .frame(frame)
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+            .default_width(420.0)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().max_height(220.0).stick_to_bottom(true).show(ui, |ui| {
+                    for entry in error_log.entries() {
+                        let color = match entry.severity {
+                            Severity::Error => egui::Color32::from_rgb(255, 140, 130),
+                            Severity::Warning => egui::Color32::from_rgb(255, 210, 120),
+                        };
+                        let repeats = if entry.count > 1 { format!(" (x{})", entry.count) } else { String::new() };
+                        ui.colored_label(color, format!("[{:.1}s] {}{}", entry.last_seen.as_secs_f32(), entry.message, repeats));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Copy all").clicked() {
+                        ctx.copy_text(error_log.report());
+                    }
+                    if ui.button("Clear").clicked() {
+                        error_log.clear();
+                    }
+                    if ui.button("Dismiss").clicked() {
+                        error_log.open = false;
+                    }
+                });
+            });
+    }
+
+    fn draw_pause_overlay(ctx: &Context) {
+        egui::Area::new(egui::Id::new("pause_overlay"))
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.heading("Paused");
+                    ui.label("Click the window to resume");
+                });
+            });
+    }
+
+    fn draw_gizmo_labels(ctx: &Context, view: &ViewWindow) {
+        let pixels_per_point = view.screen_descriptor().pixels_per_point;
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("gizmo_labels")));
+        for (label, [x, y]) in crate::gizmo::face_labels(&view.camera, view.gizmo_rect()) {
+            painter.text(
+                egui::pos2(x / pixels_per_point, y / pixels_per_point),
+                egui::Align2::CENTER_CENTER,
+                label,
+                egui::FontId::proportional(12.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+
+    // Record only the instanced geometry, for prepasses that bind their own pipeline and groups
+    // The pre-pass only takes the realistic style's opaque models. Toon and reflective models
+    // (and everything that isn't a model entry) keep their usual Less depth test in the main
+    // pass, which works against the pre-pass depth all the same.
+    // The pre-pass has no fragment stage to clip with, it would hide what the clip planes cut open
+    fn depth_prepass_active(&self) -> bool {
+        // A custom shader's pipeline tests depth with Less, it would fail against the pre-pass depth.
+        // Stereo would need a pre-pass per eye.
+        self.depth_prepass
+            && self.render_style == RenderStyle::Realistic
+            && self.clip_planes.active_count() == 0
+            && self.custom_shader.is_none()
+            && self.stereo.mode == StereoMode::Off
+    }
+
+    // How the models' vertices reach the shader, for the stats panel and the report
+    pub fn vertex_path(&self) -> &'static str {
+        vertex_pulling::path_label(self.vertex_pulling, self.context.vertex_pulling.is_some())
+    }
+
+    // Fills `depth_view` with the depth of the opaque models when the pre-pass is on. Returns how
+    // the main pass loads that depth attachment: keeping the pre-pass depth, or clearing it.
+    pub fn encode_depth_prepass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        gpu_timer: Option<&GpuTimer>,
+    ) -> wgpu::LoadOp<f32> {
+        if !self.depth_prepass_active() {
+            return wgpu::LoadOp::Clear(1.0);
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: gpu_timer.and_then(|timer| timer.pass_writes(GpuPass::DepthPrepass)),
+        });
+        render_pass.set_pipeline(&self.context.depth_prepass.prepass_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for entry in self.models.iter().filter(|entry| !entry.reflective) {
+            let Some(instance_buffer) = entry.instance_buffer() else {
+                continue;
+            };
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.draw_model_geometry_instanced(&entry.model, 0..entry.instance_count());
+        }
+        wgpu::LoadOp::Load
+    }
+
+    pub fn draw_scene_geometry(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        for entry in &self.models {
+            let Some(instance_buffer) = entry.instance_buffer() else {
+                continue;
+            };
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.draw_model_geometry_instanced(&entry.model, 0..entry.instance_count());
+        }
+    }
+
+    // The models for the motion blur velocity prepass, with last frame's instances in slot 2
+    fn draw_scene_velocity(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        for entry in &self.models {
+            let (Some(instance_buffer), Some(previous_buffer)) = (entry.instance_buffer(), entry.previous_instance_buffer()) else {
+                continue;
+            };
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_vertex_buffer(2, previous_buffer.slice(..));
+            render_pass.draw_model_geometry_instanced(&entry.model, 0..entry.instance_count());
+        }
+    }
+
+    // Render a single frame into the given window. Each window records and
+    // submits its own encoder so surfaces are never shared across submissions.
+    pub fn render(&mut self, view: &mut ViewWindow) -> Result<(), wgpu::SurfaceError> {
+        let _render = profiler::scope("render");
+        let context = self.context.clone();
+        let device = &context.device;
+        let queue = &context.queue;
+        let clear_color = self.clear_color();
+
+        if view.kind == ViewKind::Primary {
+            self.update_camera_follow(view);
+        }
+        let primary = view.kind == ViewKind::Primary;
+        if primary && self.video_export.is_some() {
+            self.export_video_frame(view);
+        }
+        view.update_camera(queue);
+        if primary && let Some((preset, path)) = self.matrix_request.take() {
+            let size = (view.config.width, view.config.height);
+            match self.render_matrix(&view.camera, &view.projection, size, &preset.variants(), &path) {
+                Ok(0) => self.toast.show(format!("Saved {}", path.display())),
+                Ok(failed) => self.toast.show(format!("Saved {}, {} variants failed", path.display(), failed)),
+                Err(e) => self.report_error(Severity::Error, format!("Could not render the {} sheet: {}", preset.label().to_lowercase(), e)),
+            }
+        }
+        if let Some(timer) = view.gpu_timer_mut() {
+            timer.poll(device);
+            if primary {
+                for (pass, ms) in timer.take_latest() {
+                    self.profiler.record_gpu(pass.label(), ms);
+                }
+            }
+        }
+        if primary {
+            self.poll_cull_readbacks();
+        }
+
+        // 1. Acquire next frame from surface
+        // Refine error handling
+        match view.current_texture(&context) {
+            Ok(output) => {
+                // 2. Create a view into the frame (like a convas we draw on)
+                let surface_view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+
+                let encode_start = std::time::Instant::now();
+
+                // 3. Create command encoder (records GPU commands)
+                let mut encoder = device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {label: Some("Render Encoder")});
+
+                // Screen descriptor for egui
+                let screen_descriptor = view.screen_descriptor();
                 // Begin egui frame
-                self.begin_frame(&window);
+                view.begin_frame(&self.theme);
+                view.controller.input_mut().invert_y = self.invert_mouse_y;
+                view.set_controller_profile(self.controller_profile, &self.controller_tunables);
+                view.apply_units(self.units);
                 // Build egui overlay UI
-                self.draw_overlay();
-                if self.show_menu {
-                    self.draw_menu(device);
+                let ui_scope = profiler::scope("build ui");
+                let ctx = view.egui_context();
+                match view.kind {
+                    ViewKind::Primary => {
+                        if view.custom_title_bar {
+                            view.custom_title_bar = title_bar::draw(&ctx, view.window(), "Rusty Engine");
+                        }
+                        self.draw_overlay(&ctx);
+                        self.draw_transform_gizmo(&ctx, view);
+                        self.clip_planes.show_handles(&ctx, &view.camera, &view.projection);
+                        self.drag_road_handles(&ctx, view);
+                        self.draw_light_preview(&ctx, view);
+                        self.update_measurements(&ctx, view);
+                        self.update_overlays(view);
+                        // Out of self while the windows borrow it through EngineApi
+                        let mut gui_windows = std::mem::take(&mut self.gui_windows);
+                        let closed = gui_windows.show(&ctx, &mut EngineApi::new(self, view));
+                        self.gui_windows = gui_windows;
+                        if closed {
+                            self.save_gui_windows();
+                        }
+                        if let Some(handle) = self.frame_request.take() {
+                            self.frame_model(view, handle);
+                        }
+                        if std::mem::take(&mut self.grazing_view_request) {
+                            self.view_overlay_ground(view);
+                        }
+                        if std::mem::take(&mut self.occluder_view_request) {
+                            self.view_occluder_wall(view);
+                        }
+                        if self.profiler.show {
+                            self.draw_profiler(&ctx);
+                        }
+                        Self::draw_error_overlay(&ctx, &context);
+                        self.toast.draw(&ctx);
+                        self.draw_video_export_modal(&ctx);
+                        if self.show_help {
+                            self.draw_help_overlay(&ctx);
+                        }
+                        if let Some(line) = self.console.draw(&ctx) {
+                            Console::execute(self, &line);
+                        }
+                        self.undo.settle(ctx.input(|input| input.pointer.any_down()));
+                    }
+                    ViewKind::Inspector => self.draw_inspector_overlay(&ctx, view),
                 }
-                
-                {
+                if self.show_gizmo {
+                    Self::draw_gizmo_labels(&ctx, view);
+                }
+                if self.paused {
+                    Self::draw_pause_overlay(&ctx);
+                }
+                self.apply_cursor(&ctx, view);
+                drop(ui_scope);
+
+                // Render-to-texture cameras, once a frame before the main window's passes sample them
+                if primary {
+                    self.lod_view = Some(LodView::new(&view.camera, &view.projection, view.config.height));
+                    let bias = self.overlay_bias.depth_bias(OverlayRenderer::SCENE_DEPTH_COMPARE, view.projection.depth_range(), self.units.units_per_meter());
+                    self.overlay.set_bias(&context.device, bias);
+                    let _rtt = profiler::scope("render to texture");
+                    self.render_rtt_cameras(&mut encoder, &view.camera);
+                }
+
+                // Stereo is the main window's, the inspector keeps looking through its one camera
+                let stereo_mode = if primary { self.stereo.mode } else { StereoMode::Off };
+                let stereo_frustum = match primary {
+                    true => view.prepare_stereo(&context, &self.stereo, self.units.units_per_meter()),
+                    false => None,
+                };
+                self.cull_for_stereo(stereo_frustum);
+                let stereo = stereo_mode != StereoMode::Off;
+                // SSAO, motion blur and TAA work from one camera's depth and velocity, they sit stereo out
+                let ssao_enabled = self.ssao_settings.enabled && !stereo;
+                let taa_enabled = self.taa.enabled && !stereo;
+                view.prepare_transients(&context, ssao_enabled, self.motion_blur.enabled && !stereo, taa_enabled, stereo_mode == StereoMode::Anaglyph);
+                // Before any pass draws with the camera, TAA jitters it
+                let reprojection = view.prepare_velocity(&context, &self.motion_blur);
+                view.prepare_taa(&context, &self.taa, reprojection.as_ref());
+                // Stereo draws two cameras from one pass, it keeps its own per-model cull
+                let cull = primary && !stereo && self.instance_cull_mode() != CullMode::Off;
+                view.prepare_hiz(&context, cull && self.occlusion_culling_active());
+                // SSAO: normals + depth prepass, then occlusion and blur into offscreen targets
+                if ssao_enabled {
+                    view.prepare_ssao(&context, &self.ssao_settings);
+                }
+                if ssao_enabled && let Some(targets) = view.ssao_targets() {
+                    let _ssao = profiler::scope("ssao");
+                    encoder.push_debug_group("ssao");
+                    {
+                        let mut prepass = targets.begin_prepass(&mut encoder, &context.ssao, &view.camera_bind_group);
+                        self.draw_scene_geometry(&mut prepass);
+                    }
+                    targets.encode_occlusion(&mut encoder, &context.ssao);
+                    encoder.pop_debug_group();
+                }
+
+                if self.render_style == RenderStyle::Toon {
+                    context.toon.write(queue, &self.toon_settings, (view.config.width, view.config.height));
+                }
+                if self.outline.mode == OutlineMode::Hull {
+                    context.toon.write_selection(queue, self.outline.width, self.outline.color, (view.config.width, view.config.height));
+                }
+                let scene_scope = profiler::scope("scene");
+                encoder.push_debug_group("scene");
+                let depth_load = self.encode_depth_prepass(&mut encoder, &view.depth_texture.view, &view.camera_bind_group, view.gpu_timer());
+                // After the pre-pass, occlusion is tested against its depth
+                if cull {
+                    let _cull = profiler::scope("instance culling");
+                    let hiz = view.encode_hiz(&context, &mut encoder);
+                    self.cull_instances(&mut encoder, view.projection.calc_matrix() * view.camera.calc_matrix(), hiz);
+                }
+                // Anaglyph renders each eye into a target of its own, everything else into the frame
+                let anaglyph = view.anaglyph_targets().filter(|_| stereo_mode == StereoMode::Anaglyph);
+                let scene_passes: Vec<(Option<Eye>, &wgpu::TextureView)> = match anaglyph {
+                    Some(targets) => Eye::BOTH.iter().map(|eye| (Some(*eye), targets.eye_view(*eye))).collect(),
+                    None => vec![(None, view.scene_target(&surface_view))],
+                };
+                for (index, (eye, target)) in scene_passes.into_iter().enumerate() {
                     // 4. Begin render pass (define clear color + attachments)
+                    let (color_view, resolve_target) = view.color_attachment(target);
                     let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                         label: Some("Render Pass"),
                         color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
+                            view: color_view,
+                            resolve_target,
                             ops: wgpu::Operations {
                                 // This clears the screen every frame
-                                load: wgpu::LoadOp::Clear(wgpu::Color {
-                                    r: 0.1,
-                                    g: 0.2,
-                                    b: 0.3,
-                                    a: 1.0,
-                                }),
+                                load: wgpu::LoadOp::Clear(clear_color),
                                 store: wgpu::StoreOp::Store,
                             },
                         })],
                         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: &self.depth_texture.view,
+                            view: &view.depth_texture.view,
                             depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
+                                load: depth_load,
                                 store: wgpu::StoreOp::Store,
                             }),
                             stencil_ops: None,
                         }),
                         occlusion_query_set: None,
-                        timestamp_writes: None,
+                        // Only the first pass is timed, in anaglyph that is the left eye
+                        timestamp_writes: view.gpu_timer().filter(|_| index == 0).and_then(|timer| timer.pass_writes(GpuPass::Main)),
                     });
-                    let num_of_instances = self.num_of_instances;
-                    if num_of_instances < 1 {
-                        render_pass.set_pipeline(&self.light_render_pipeline);
-                        render_pass.set_pipeline(&self.render_pipeline);
-                    } else {
-                        let (instances, instance_buffer) = self.redraw_instances(num_of_instances, self.instance_position_x, self.instance_position_y, self.instance_position_z, &device);
-                        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
-                        render_pass.set_pipeline(&self.light_render_pipeline);
-                        render_pass.draw_light_model(&self.obj_model, &self.camera_bind_group, &self.light_bind_group);
-
-                        render_pass.set_pipeline(&self.render_pipeline);
-                        render_pass.draw_model_instanced(&self.obj_model, 0..instances.len() as u32, &self.camera_bind_group, &self.light_bind_group);
-                    }
-                    
+                    let eye_camera = |eye: Eye| view.eye_camera_bind_group(eye).unwrap_or(&view.camera_bind_group);
+                    match (stereo_mode, eye) {
+                        (StereoMode::SideBySide, _) => {
+                            for eye in Eye::BOTH {
+                                let [x, y, width, height] = stereo::eye_viewport(eye, (view.config.width, view.config.height));
+                                render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+                                self.draw_scene(&mut render_pass, eye_camera(eye));
+                            }
+                        }
+                        (_, Some(eye)) => self.draw_scene(&mut render_pass, eye_camera(eye)),
+                        _ => self.draw_scene(&mut render_pass, &view.camera_bind_group),
+                    }
                     // Render pass dropped here, finishing recording
                 }
+                self.draw_culled = false;
+                if let Some(targets) = anaglyph {
+                    targets.encode_composite(&mut encoder, &context.anaglyph, view.scene_target(&surface_view));
+                }
+                // Darken the resolved frame with the occlusion before the UI goes on top
+                if ssao_enabled && let Some(targets) = view.ssao_targets() {
+                    targets.encode_composite(&mut encoder, &context.ssao, view.scene_target(&surface_view));
+                }
+                encoder.pop_debug_group();
+                drop(scene_scope);
+                // Additive particles go over the finished (resolved, occluded) scene and read its
+                // depth, which in stereo only has the last eye's
+                if self.show_particles && !stereo {
+                    encoder.push_debug_group("particles");
+                    self.particles.encode(&mut encoder, &context.particle_pipeline, view.scene_target(&surface_view), &view.camera_bind_group, view.particle_bindings());
+                    encoder.pop_debug_group();
+                }
+                // Velocity of the instanced scene, then the jittered frame resolved against the last
+                // one and the HDR frame smeared along it
+                if reprojection.is_some()
+                    && let (Some(targets), Some(pipelines)) = (view.motion_blur_targets(), context.motion_blur.as_ref())
+                {
+                    encoder.push_debug_group("velocity prepass");
+                    let mut prepass = targets.begin_velocity_pass(&mut encoder, pipelines);
+                    self.draw_scene_velocity(&mut prepass);
+                    drop(prepass);
+                    encoder.pop_debug_group();
+                }
+                if taa_enabled {
+                    let _taa = profiler::scope("taa");
+                    encoder.push_debug_group("taa");
+                    view.encode_taa(&context, &mut encoder);
+                    encoder.pop_debug_group();
+                }
+                if reprojection.is_some() && self.motion_blur.enabled {
+                    let _motion_blur = profiler::scope("motion blur");
+                    encoder.push_debug_group("motion blur");
+                    view.encode_motion_blur(&context, &mut encoder);
+                    encoder.pop_debug_group();
+                }
+                // Bring the HDR scene into display range, everything after this draws in display space
+                encoder.push_debug_group("tonemap");
+                view.encode_tonemap(&context, &mut encoder, &self.hdr_settings, &surface_view);
+                encoder.pop_debug_group();
+                // In display space so the color is the one picked. The IDs are drawn every frame
+                // while it is on, clicks read them instead of drawing their own. They come from
+                // one camera, stereo goes without.
+                let outline = self.outline.mode.screen_space() && !stereo;
+                view.prepare_outline(&context, outline);
+                if outline {
+                    let _outline = profiler::scope("outline");
+                    encoder.push_debug_group("outline");
+                    let draws = self.pick_draws();
+                    view.encode_outline_ids(&context, &mut encoder, &draws);
+                    let selected_id = self.selected_pick_id(&draws);
+                    if self.outline.mode == OutlineMode::FullScene || selected_id.is_some() {
+                        view.encode_outline(&context, &mut encoder, &self.outline, selected_id, &surface_view);
+                    }
+                    encoder.pop_debug_group();
+                }
+                // Quads are screen space, a game's HUD and sprites belong to the main window
+                if view.kind == ViewKind::Primary {
+                    let _quads = profiler::scope("2d");
+                    self.queue_quad_demo(view);
+                    self.quads_2d.flush(device, queue);
+                    encoder.push_debug_group("2d");
+                    view.draw_quads(&context, &mut encoder, &self.quads_2d, &surface_view);
+                    encoder.pop_debug_group();
+                }
+                if self.show_depth_thumbnail && view.kind == ViewKind::Primary {
+                    encoder.push_debug_group("depth thumbnail");
+                    view.draw_depth_thumbnail(&context, &mut encoder, &surface_view);
+                    encoder.pop_debug_group();
+                }
+                // Gizmo goes over the finished scene, egui still draws above it
+                if self.show_gizmo {
+                    encoder.push_debug_group("gizmo");
+                    view.draw_gizmo(&context, &mut encoder, &surface_view);
+                    encoder.pop_debug_group();
+                }
                 // Render egui on top
-                self.end_frame_and_draw(
-                    &device,
-                    &queue,
+                let egui_scope = profiler::scope("draw ui");
+                encoder.push_debug_group("ui");
+                view.end_frame_and_draw(
+                    device,
+                    queue,
                     &mut encoder,
-                    &window,
-                    &view,
+                    &surface_view,
                     screen_descriptor,
                 );
+                encoder.pop_debug_group();
+                drop(egui_scope);
+                if let Some(timer) = view.gpu_timer_mut() {
+                    timer.resolve(&mut encoder);
+                }
+
+                self.frame_stats.record_encode(encode_start.elapsed().as_secs_f32() * 1000.0);
+                if view.kind == ViewKind::Primary {
+                    self.frame_stats.record_redraw(encode_start);
+                }
 
                 // 5. Submit recording command to GPU queue
-                self.queue.submit(std::iter::once(encoder.finish()));
+                let submit = profiler::scope("submit");
+                queue.submit(std::iter::once(encoder.finish()));
+                if let Some(timer) = view.gpu_timer_mut() {
+                    timer.request_readback();
+                }
+                if primary {
+                    self.request_cull_readbacks();
+                }
+
+                drop(submit);
+
+                if primary && let Some(path) = self.screenshot_request.take() {
+                    match ViewWindow::capture_frame(&context, &output.texture).and_then(|image| Ok(image.save(&path)?)) {
+                        Ok(()) => log::info!("Saved a screenshot to {}", path.display()),
+                        Err(e) => self.report_error(Severity::Error, format!("Could not save a screenshot to {}: {}", path.display(), e)),
+                    }
+                }
 
                 // 6. Present frame to screen
+                let _present = profiler::scope("present");
                 output.present();
 
                 Ok(())
             }
             Err(wgpu::SurfaceError::Lost) => {
                 // Reconfigure with the current state
-                self.resize(self.size.width, self.config.height);
+                view.resize(&context, view.size.width, view.size.height);
                 Ok(())
             }
             Err(wgpu::SurfaceError::OutOfMemory) => {
@@ -733,8 +5311,457 @@ impl State {
             }
             Err(e) => {
                 eprintln!("Render error: {:?}", e);
+                self.report_error(Severity::Error, format!("Render error: {}", e));
                 Ok(())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RenderSettings, texture_stream::StreamTarget, uv_fallback::UvFallback};
+
+    fn headless() -> State {
+        State::new_headless(&EngineConfig::default()).block_on().expect("no usable GPU adapter")
+    }
+
+    #[test]
+    fn subscribed_closures_hear_events_until_they_unsubscribe() {
+        let mut state = headless();
+        let heard = Rc::new(RefCell::new(Vec::new()));
+        let listener = state.subscribe({
+            let heard = heard.clone();
+            move |event: &EngineEvent| heard.borrow_mut().push(event.clone())
+        });
+        state.push_event(EngineEvent::SettingsChanged);
+        state.update();
+        assert!(heard.borrow().contains(&EngineEvent::SettingsChanged), "{:?}", heard.borrow());
+
+        assert!(state.unsubscribe(listener));
+        assert!(!state.unsubscribe(listener));
+        heard.borrow_mut().clear();
+        state.push_event(EngineEvent::DeviceLost);
+        state.update();
+        assert!(heard.borrow().is_empty(), "{:?}", heard.borrow());
+    }
+
+    #[test]
+    fn undoing_a_despawn_brings_the_user_data_back() {
+        let mut state = headless();
+        let model = state.grid_model;
+        let first = state.add_instance_of(model, cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Quaternion::one()).unwrap();
+        let id = state.add_instance_of(model, cgmath::Vector3::new(1.0, 0.0, 0.0), cgmath::Quaternion::one()).unwrap();
+        let last = state.add_instance_of(model, cgmath::Vector3::new(2.0, 0.0, 0.0), cgmath::Quaternion::one()).unwrap();
+        assert!(state.set_instance_user_data(id, Box::new("goblin")));
+        state.selected_instance = Some(id);
+
+        // Not the newest
+        assert!(state.despawn_selected());
+        assert!(state.model(model).unwrap().instance(id.index).is_none());
+        assert!(state.get_instance_user_data::<&str>(id).is_none());
+        assert!(state.model(model).unwrap().instance(first.index).is_some() && state.model(model).unwrap().instance(last.index).is_some());
+
+        assert!(state.undo());
+        assert_eq!(state.get_instance_user_data::<&str>(id), Some(&"goblin"));
+        assert!(state.redo());
+        assert!(state.get_instance_user_data::<&str>(id).is_none());
+        assert!(state.undo());
+        assert_eq!(state.get_instance_user_data::<&str>(id), Some(&"goblin"));
+    }
+
+    // The default scene from where the headless benchmark looks at it
+    fn render(state: &State) -> image::RgbaImage {
+        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let projection = camera::Projection::new(128, 96, cgmath::Deg(45.0), 0.1, 100.0);
+        state.render_offscreen(&camera, &projection, (128, 96)).unwrap()
+    }
+
+    // Largest difference of any channel of any pixel
+    fn max_difference(a: &image::RgbaImage, b: &image::RgbaImage) -> u8 {
+        a.as_raw().iter().zip(b.as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn instances_spin_the_same_on_the_cpu_and_the_gpu() {
+        let config = EngineConfig { instances: (3, 3), ..EngineConfig::default() };
+        let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+
+        let at = |state: &mut State, gpu: bool, time: f32| {
+            state.set_instance_animation_gpu(gpu);
+            state.animation_time = time;
+            state.animate_instances();
+            render(state)
+        };
+        let cpu = at(&mut state, false, 2.5);
+        let gpu = at(&mut state, true, 2.5);
+        assert!(max_difference(&cpu, &gpu) <= 2, "{}", max_difference(&cpu, &gpu));
+        // Not two blank frames: a second later they have turned
+        let later = at(&mut state, true, 3.5);
+        assert!(max_difference(&gpu, &later) > 16);
+    }
+
+    // The default cube with every face corner its own v/vt/vn, the way some exporters write it,
+    // in a folder of its own next to the cube's material and textures
+    fn unshared_cube() -> String {
+        let res = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        let dir = std::env::temp_dir().join(format!("rusty-engine-unshared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["cube.mtl", "cube-diffuse.jpg", "cube-normal.png"] {
+            std::fs::copy(res.join(file), dir.join(file)).unwrap();
+        }
+        let text = std::fs::read_to_string(res.join("cube.obj")).unwrap();
+        let lines = |prefix: &str| text.lines().filter(|line| line.starts_with(prefix)).collect::<Vec<_>>();
+        let (positions, uvs, normals) = (lines("v "), lines("vt "), lines("vn "));
+        let mut out = String::new();
+        let mut corners = 0;
+        for line in text.lines().filter(|line| !line.starts_with('v')) {
+            let Some(face) = line.strip_prefix("f ") else {
+                out += line;
+                out += "\n";
+                continue;
+            };
+            let mut new_face = "f".to_string();
+            for corner in face.split_whitespace() {
+                let [v, vt, vn] = [0, 1, 2].map(|n| corner.split('/').nth(n).unwrap().parse::<usize>().unwrap() - 1);
+                out += &format!("{}\n{}\n{}\n", positions[v], uvs[vt], normals[vn]);
+                corners += 1;
+                new_face += &format!(" {0}/{0}/{0}", corners);
+            }
+            out += &new_face;
+            out += "\n";
+        }
+        let path = dir.join("cube-unshared.obj");
+        std::fs::write(&path, out).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn welding_an_unshared_cube_renders_the_same_with_far_fewer_vertices() {
+        let grid = |model_path: String, mesh_load| {
+            let render = RenderSettings { mesh_load, ..RenderSettings::default() };
+            let config = EngineConfig { instances: (3, 3), model_path, render, ..EngineConfig::default() };
+            let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+            state.animate_instances();
+            state
+        };
+        let vertices = |state: &State| state.context.obj_model.meshes.iter().map(|mesh| (mesh.vertex_buffer.size() / (mesh.layout.stride as u64 * 4)) as usize).sum::<usize>();
+        let unshared_path = unshared_cube();
+        let original = grid("cube.obj".to_string(), LoadOptions::default());
+        let unshared = grid(unshared_path.clone(), LoadOptions::default());
+        let welded = grid(unshared_path, LoadOptions { weld: true, cache_optimize: true, ..LoadOptions::default() });
+
+        let stats = welded.context.obj_model.optimize_stats.expect("the pass ran");
+        assert_eq!(stats.vertices_before, vertices(&unshared));
+        assert_eq!(stats.vertices_after, vertices(&welded));
+        assert_eq!(stats.indices_after, stats.indices_before);
+        // Back to what the shared file has
+        assert_eq!(vertices(&welded), vertices(&original));
+        assert!(vertices(&welded) * 4 < vertices(&unshared), "{}", stats.summary());
+
+        // Not compared with the unshared copy itself: its tangents aren't averaged across faces
+        assert!(max_difference(&render(&original), &render(&welded)) <= 2);
+    }
+
+    #[test]
+    fn the_column_skins_the_same_on_the_cpu_and_the_gpu() {
+        let mut state = headless();
+        state.show_skinning_demo = true;
+        state.update_skinning_demo();
+        state.animate_instances();
+        // Close up on the column, it is a sliver from the benchmark's view
+        let camera = Camera::new((-4.0, 4.5, 6.0), cgmath::Deg(-90.0), cgmath::Deg(-5.0));
+        let projection = camera::Projection::new(96, 128, cgmath::Deg(45.0), 0.1, 100.0);
+        let at = |state: &mut State, gpu: bool, dt: f32| {
+            let demo = state.skinning_demo.as_mut().unwrap();
+            demo.set_gpu(gpu);
+            demo.advance(dt);
+            state.update_skinning_demo();
+            state.render_offscreen(&camera, &projection, (96, 128)).unwrap()
+        };
+        let rest = at(&mut state, false, 0.0);
+        let cpu = at(&mut state, false, 1.3);
+        let gpu = at(&mut state, true, 0.0);
+        assert!(max_difference(&cpu, &gpu) <= 2, "{}", max_difference(&cpu, &gpu));
+        // Bent and bulging by then, so not the rest pose twice
+        assert!(max_difference(&rest, &gpu) > 16);
+    }
+
+    #[test]
+    fn pulled_vertices_render_like_the_vertex_buffers() {
+        let grid = EngineConfig { instances: (3, 3), ..EngineConfig::default() };
+        // In front of the grid's spot
+        let shapes = EngineConfig { random_scene: Some(scene_gen::SceneGenOptions { shape_count: 12, extent: 3.0, light_count: 2 }), ..EngineConfig::default() };
+        let overview = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        // The column is a sliver from the overview, see the_column_skins_the_same_on_the_cpu_and_the_gpu
+        let column = Camera::new((-4.0, 4.5, 6.0), cgmath::Deg(-90.0), cgmath::Deg(-5.0));
+        // One at a time, so each has to show up on its own
+        type Subject<'a> = (&'a str, EngineConfig, fn(&mut State), &'a Camera);
+        let subjects: [Subject; 4] = [
+            ("OBJ grid", grid, |_| {}, &overview),
+            ("generated shapes", shapes, |_| {}, &overview),
+            ("skinned column", EngineConfig::default(), |state| state.show_skinning_demo = true, &column),
+            ("die", EngineConfig::default(), |state| state.show_dice_demo = true, &overview),
+        ];
+        let projection = camera::Projection::new(128, 96, cgmath::Deg(45.0), 0.1, 100.0);
+        let empty = headless();
+        for (name, config, show, camera) in subjects {
+            let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+            if state.context.vertex_pulling.is_none() {
+                eprintln!("Skipped: the adapter can't pull vertices");
+                return;
+            }
+            show(&mut state);
+            // Builds the demos, their instances are uploaded by the next animate
+            state.update();
+            state.animate_instances();
+            let render = |state: &State| state.render_offscreen(camera, &projection, (128, 96)).unwrap();
+            state.vertex_pulling = false;
+            let classic = render(&state);
+            state.vertex_pulling = true;
+            let pulled = render(&state);
+            assert!(max_difference(&render(&empty), &classic) > 16, "the {} isn't in view", name);
+            assert!(max_difference(&classic, &pulled) <= 2, "{}: {}", name, max_difference(&classic, &pulled));
+        }
+    }
+
+    #[test]
+    fn a_streamed_texture_renders_like_one_uploaded_at_once() {
+        let render_settings = RenderSettings { shading_model: Some(ShadingModel::Unlit), ..RenderSettings::default() };
+        let config = EngineConfig { instances: (3, 3), render: render_settings, ..EngineConfig::default() };
+        let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+        state.paused = true;
+        state.animate_instances();
+        // Over the streaming threshold, with every strip's rows telling apart from the others'
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(2100, 2100, |x, y| image::Rgba([(y * 7) as u8, (x * 3) as u8, (y / 64 * 40) as u8, 255])));
+        let target = StreamTarget { material: 0, slot: model::TextureSlot::Diffuse };
+        let context = &state.context;
+        let (_, placeholder) = context.texture_streamer.lock().unwrap().stream_image(&context.device, &context.queue, &image, "streamed", false, target).unwrap();
+        context.obj_model.materials[0].replace_texture(&context.device, model::TextureSlot::Diffuse, placeholder);
+        let placeholder = render(&state);
+
+        let mut frames = 0;
+        while state.context.texture_streamer.lock().unwrap().stats().active_streams > 0 {
+            state.update();
+            frames += 1;
+            assert!(frames < 100, "the stream never finished");
+        }
+        // update swapped the finished texture in
+        let streamed = render(&state);
+
+        let context = &state.context;
+        let whole = Texture::from_image(&context.device, &context.queue, &image, Some("whole"), false).unwrap();
+        context.obj_model.materials[0].replace_texture(&context.device, model::TextureSlot::Diffuse, whole);
+        let uploaded = render(&state);
+        assert!(frames > 1);
+        assert_eq!(max_difference(&streamed, &uploaded), 0);
+        assert!(max_difference(&placeholder, &uploaded) > 16);
+    }
+
+    #[test]
+    fn a_second_model_keeps_its_own_instances_until_it_is_removed() {
+        let dir = std::env::temp_dir().join(format!("rusty-engine-models-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // add_model remembers the import options there
+        let config = EngineConfig { instances: (3, 3), settings_path: dir.join("settings.cfg"), ..EngineConfig::default() };
+        let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+        state.paused = true;
+        state.animate_instances();
+        let grid = state.grid_model;
+        let grid_only = render(&state);
+
+        let cubes = state.add_model("cube.obj", &ImportOptions::default()).unwrap();
+        assert_ne!(cubes, grid);
+        assert_eq!(state.model(cubes).unwrap().instance_count(), 0);
+        state.animate_instances();
+        assert_eq!(max_difference(&grid_only, &render(&state)), 0);
+
+        // In front of the grid, between it and the camera
+        let first = state.add_instance_of(cubes, cgmath::Vector3::new(-1.5, 2.0, 4.0), cgmath::Quaternion::one()).unwrap();
+        let second = state.add_instance_of(cubes, cgmath::Vector3::new(1.5, 2.0, 4.0), cgmath::Quaternion::one()).unwrap();
+        assert_eq!((first.model, second.model), (cubes, cubes));
+        assert_ne!(first.index, second.index);
+        assert_eq!(state.model(cubes).unwrap().instance_count(), 2);
+        assert_eq!(state.model(grid).unwrap().instance_count(), 9);
+        state.animate_instances();
+        let both = render(&state);
+        assert!(max_difference(&grid_only, &both) > 16);
+
+        // Gone with its instances and buffers, the grid stays
+        assert!(state.remove_model(cubes));
+        assert!(state.model(cubes).is_none());
+        assert!(state.add_instance_of(cubes, cgmath::Vector3::zero(), cgmath::Quaternion::one()).is_none());
+        assert!(!state.remove_model(grid));
+        assert_eq!(state.model(grid).unwrap().instance_count(), 9);
+        assert_eq!(max_difference(&grid_only, &render(&state)), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn two_windows_step_the_scene_once_per_iteration() {
+        let mut state = headless();
+        let inspector = Camera::new((8.0, 8.0, 8.0), cgmath::Deg(-135.0), cgmath::Deg(-35.0));
+        let projection = camera::Projection::new(64, 48, cgmath::Deg(45.0), 0.1, 100.0);
+        let start = state.frame_uniform.frame;
+        for iteration in 1..=3 {
+            // The primary window, then the inspector
+            state.update_for_view();
+            render(&state);
+            state.update_for_view();
+            state.render_offscreen(&inspector, &projection, (64, 48)).unwrap();
+            state.end_iteration();
+            assert_eq!(state.frame_uniform.frame, start + iteration);
+        }
+    }
+
+    #[test]
+    fn a_half_grey_picker_color_renders_like_a_128_grey_texture() {
+        let config = EngineConfig { instances: (3, 3), ..EngineConfig::default() };
+        let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+        state.animate_instances();
+        let grid = state.grid_model;
+        // Draws the grid with a one texel diffuse texture, multiplied by `color` from the picker
+        let paint = |state: &mut State, texel: u8, color: f32| {
+            let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([texel, texel, texel, 255])));
+            let texture = Texture::from_image(&state.context.device, &state.context.queue, &image, Some("grey"), false).unwrap();
+            let material = &state.model(grid).unwrap().model.materials[0];
+            material.replace_texture(&state.context.device, model::TextureSlot::Diffuse, texture);
+            assert!(state.set_material_params(grid, 0, MaterialParams { color: [color, color, color, 1.0], ..MaterialParams::default() }));
+            render(state)
+        };
+        let white = paint(&mut state, 255, 1.0);
+        let picked = paint(&mut state, 255, 0.5);
+        let textured = paint(&mut state, 128, 1.0);
+        assert!(max_difference(&picked, &textured) <= 1, "{}", max_difference(&picked, &textured));
+        assert!(max_difference(&white, &picked) > 16);
+    }
+
+    #[test]
+    fn packed_material_textures_render_like_their_own_bind_groups() {
+        let grid = |pack_textures| {
+            let render = RenderSettings { mesh_load: LoadOptions { pack_textures, ..LoadOptions::default() }, ..RenderSettings::default() };
+            let config = EngineConfig { instances: (3, 3), render, ..EngineConfig::default() };
+            let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+            state.animate_instances();
+            state
+        };
+        let classic = grid(false);
+        let mut packed = grid(true);
+        assert!(classic.context.obj_model.packed.is_none());
+        assert!(packed.context.obj_model.packed.is_some());
+
+        let golden = render(&classic);
+        assert!(max_difference(&golden, &render(&packed)) <= 1, "{}", max_difference(&golden, &render(&packed)));
+        // The packed model drawn through its materials' own bind groups again
+        packed.draw_packed_materials = false;
+        assert!(max_difference(&golden, &render(&packed)) <= 1);
+    }
+
+    #[test]
+    fn swatch_textures_land_on_their_materials_on_any_number_of_threads() {
+        // Unlit, so every quad shows its texture as it is
+        let render_settings = RenderSettings { shading_model: Some(ShadingModel::Unlit), ..RenderSettings::default() };
+        let config = EngineConfig { instances: (1, 1), model_path: "swatches.obj".to_string(), render: render_settings, ..EngineConfig::default() };
+        let camera = Camera::new((3.5, 0.5, 6.0), cgmath::Deg(-90.0), cgmath::Deg(0.0));
+        let projection = camera::Projection::new(256, 64, cgmath::Deg(20.0), 0.1, 100.0);
+        let load = || {
+            let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+            state.animate_instances();
+            let files: Vec<_> = state.context.obj_model.meshes.iter().map(|mesh| (mesh.name.clone(), state.context.obj_model.materials[mesh.material].texture_files.clone())).collect();
+            (files, state.render_offscreen(&camera, &projection, (256, 64)).unwrap())
+        };
+        // What RAYON_NUM_THREADS=1 gives, then the default pool
+        let (one_files, one_image) = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap().install(load);
+        let (files, image) = load();
+        assert_eq!(files.len(), 5);
+        assert_eq!(one_files, files);
+        assert_eq!(max_difference(&one_image, &image), 0);
+
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        let expected: [[u8; 3]; 5] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0], [255, 0, 0]];
+        for (quad, color) in expected.into_iter().enumerate() {
+            let clip = view_proj * cgmath::Vector4::new(quad as f32 * 1.5 + 0.5, 0.5, 0.0, 1.0);
+            let (x, y) = ((clip.x / clip.w + 1.0) * 0.5 * 256.0, (1.0 - clip.y / clip.w) * 0.5 * 64.0);
+            let pixel = image.get_pixel(x as u32, y as u32);
+            for (channel, want) in color.into_iter().enumerate() {
+                assert!(pixel[channel].abs_diff(want) < 40, "quad {} is {:?}", quad, pixel);
+            }
+        }
+    }
+
+    // Compared with tests/golden/<name>.png, allowing for rasterizer differences along edges.
+    // Run with UPDATE_GOLDEN=1 to write the reference again after an intended change.
+    fn assert_golden(name: &str, image: &image::RgbaImage) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(&path).unwrap();
+            return;
+        }
+        let golden = image::open(&path).unwrap_or_else(|e| panic!("{}: {}, run with UPDATE_GOLDEN=1 to write it", path.display(), e)).to_rgba8();
+        assert_eq!(golden.dimensions(), image.dimensions());
+        let off = golden.pixels().zip(image.pixels()).filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > 8)).count();
+        assert!(off * 100 <= image.len() / 4, "{} of {} pixels differ from {}", off, image.len() / 4, path.display());
+    }
+
+    #[test]
+    fn a_checker_tiles_the_cube_without_uvs() {
+        let cube = |uv_fallback| {
+            let render = RenderSettings {
+                shading_model: Some(ShadingModel::Unlit),
+                mesh_load: LoadOptions { uv_fallback, ..LoadOptions::default() },
+                ..RenderSettings::default()
+            };
+            let config = EngineConfig { instances: (1, 1), model_path: "cube-no-uv.obj".to_string(), render, ..EngineConfig::default() };
+            let mut state = State::new_headless(&config).block_on().expect("no usable GPU adapter");
+            state.animate_instances();
+            // Eight cells across, light and dark far enough apart to survive filtering
+            let checker = image::RgbaImage::from_fn(64, 64, |x, y| if (x / 8 + y / 8) % 2 == 0 { image::Rgba([230, 230, 230, 255]) } else { image::Rgba([30, 30, 30, 255]) });
+            let texture = Texture::from_image(&state.context.device, &state.context.queue, &image::DynamicImage::ImageRgba8(checker), Some("checker"), false).unwrap();
+            state.context.obj_model.materials[0].replace_texture(&state.context.device, model::TextureSlot::Diffuse, texture);
+            // Three faces at once, from above the corner away from the light marker at (2, 2, 2)
+            let camera = Camera::new((-3.0, 3.0, -3.0), cgmath::Deg(45.0), cgmath::Deg(-35.0));
+            let projection = camera::Projection::new(128, 128, cgmath::Deg(45.0), 0.1, 100.0);
+            state.render_offscreen(&camera, &projection, (128, 128)).unwrap()
+        };
+        let boxed = cube(UvFallback::Box);
+        assert_golden("uv_fallback_box", &boxed);
+        // Without UVs the whole cube is the one texel at (0, 0)
+        assert!(max_difference(&boxed, &cube(UvFallback::None)) > 100);
+    }
+
+    #[test]
+    fn an_export_steps_the_simulation_once_per_written_frame() {
+        let mut state = headless();
+        state.paused = true;
+        let dir = std::env::temp_dir().join(format!("rusty-engine-stepping-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        state.export_video(&dir.join("clip.png"), 10, ExportSpan::Seconds(0.3), (32, 24)).unwrap();
+        assert!(state.export_video(&dir.join("other.png"), 10, ExportSpan::Seconds(0.3), (32, 24)).is_err());
+
+        // Paused, and no frame written yet
+        state.update();
+        assert_eq!(state.animation_time, 0.0);
+        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let projection = camera::Projection::new(32, 24, cgmath::Deg(45.0), 0.1, 100.0);
+        for written in 1..=3 {
+            let mut export = state.video_export.take().unwrap();
+            export.write_frame(&state.render_offscreen(&camera, &projection, export.size()).unwrap()).unwrap();
+            state.video_export = Some(export);
+            // However long the frame took
+            std::thread::sleep(std::time::Duration::from_millis(30));
+            state.update();
+            assert!((state.animation_time - written as f32 * 0.1).abs() < 1e-6, "{} after {} frames", state.animation_time, written);
+            assert_eq!(state.frame_uniform.delta_time, 0.1);
+            // Another window's update in between doesn't step again
+            state.update();
+            assert!((state.animation_time - written as f32 * 0.1).abs() < 1e-6, "{} after {} frames", state.animation_time, written);
+        }
+        let export = state.video_export.take().unwrap();
+        assert!(export.is_done());
+        export.finish().unwrap();
+        assert!(crate::video_export::frame_path(&dir.join("clip.png"), 2).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
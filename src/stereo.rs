@@ -0,0 +1,328 @@
+/*
+Purpose: Stereo 3D, the main window's scene rendered once per eye
+Responsibilities:
+    - Define StereoMode (side by side or red/cyan anaglyph) and its eye separation and
+      convergence distance
+    - Derive each eye's view and off-axis projection from the window's camera, so the two
+      frusta share one rectangle at the convergence distance instead of toeing in
+    - Build one culling frustum that holds both eyes' frusta
+    - Own the eye camera buffers and, for anaglyph, the eye targets and the composite that
+      takes red from the left eye and green and blue from the right
+    - ex: the two lenses of a View-Master, one photo each, taken a hand's width apart
+*/
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Vector3};
+
+use crate::{
+    camera::{self, Camera, CameraUniform, Projection},
+    frame_graph::{FrameGraph, TransientDesc, TransientId, Transients},
+    gpu_memory::Tracked,
+    math::{Frustum, Plane},
+    render_context::{self, RenderContext},
+};
+
+// In meters, multiplied by the scene units
+const DEFAULT_EYE_SEPARATION: f32 = 0.065;
+const DEFAULT_CONVERGENCE: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StereoMode {
+    #[default]
+    Off,
+    // Left eye in the left half of the window, each squeezed to half the width
+    SideBySide,
+    // Both eyes over the whole window, for red/cyan glasses
+    Anaglyph,
+}
+
+impl StereoMode {
+    pub const ALL: [StereoMode; 3] = [StereoMode::Off, StereoMode::SideBySide, StereoMode::Anaglyph];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StereoMode::Off => "Off",
+            StereoMode::SideBySide => "Side by side",
+            StereoMode::Anaglyph => "Anaglyph (red/cyan)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StereoSettings {
+    pub mode: StereoMode,
+    // Between the eyes, in meters
+    pub eye_separation: f32,
+    // Where the eyes' images line up (zero parallax), in meters. Nearer things pop out of the
+    // screen, further ones sink into it.
+    pub convergence: f32,
+}
+
+impl Default for StereoSettings {
+    fn default() -> Self {
+        Self { mode: StereoMode::Off, eye_separation: DEFAULT_EYE_SEPARATION, convergence: DEFAULT_CONVERGENCE }
+    }
+}
+
+impl StereoSettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Stereo 3D")
+            .selected_text(self.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in StereoMode::ALL {
+                    ui.selectable_value(&mut self.mode, mode, mode.label());
+                }
+            })
+            .response
+            .on_hover_text("Main window only. SSAO, motion blur, soft particles and the depth pre-pass are off while it is on.");
+        ui.add_enabled_ui(self.mode != StereoMode::Off, |ui| {
+            ui.add(egui::Slider::new(&mut self.eye_separation, 0.0..=0.3).text("Eye separation (m)"));
+            ui.add(egui::Slider::new(&mut self.convergence, 0.5..=50.0).logarithmic(true).text("Convergence (m)"));
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub const BOTH: [Eye; 2] = [Eye::Left, Eye::Right];
+
+    // Which way along the camera's right axis the eye sits
+    fn side(self) -> f32 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+// Half the separation to the camera's side, looking the same way. The axes stay parallel, the
+// convergence is in the projection.
+pub fn eye_view(camera: &Camera, eye: Eye, separation: f32) -> (Point3<f32>, Matrix4<f32>) {
+    let position = camera.position + camera.right() * eye.side() * separation * 0.5;
+    (position, Matrix4::look_to_rh(position, camera.forward(), camera.up()))
+}
+
+// Off-axis projection of one eye: its frustum is sheared towards the other eye until both cover
+// the camera's own window at the convergence distance. Toeing the eyes in instead would tilt
+// their image planes apart and add vertical parallax towards the sides.
+pub fn eye_projection(tan_half_fovy: f32, aspect: f32, (near, far): (f32, f32), eye: Eye, separation: f32, convergence: f32) -> Matrix4<f32> {
+    let top = near * tan_half_fovy;
+    let half_width = top * aspect;
+    // The eye's offset, scaled back from the convergence plane to the near plane
+    let shift = -eye.side() * separation * 0.5 * near / convergence.max(1e-3);
+    camera::OPENGL_TO_WGPU_MATRIX * cgmath::frustum(-half_width + shift, half_width + shift, -top, top, near, far)
+}
+
+// Culling frustum holding both eyes' frusta. Top, bottom, near and far are the camera's, the
+// eyes only move along its right axis so they share those planes. Each side is the chord of the
+// two eyes' edges on that side between the near and far plane: which edge is further out swaps
+// at the convergence distance, and the chord stays outside both.
+pub fn enclosing_frustum(camera: &Camera, tan_half_fovy: f32, aspect: f32, (near, far): (f32, f32), separation: f32, convergence: f32) -> Option<Frustum> {
+    let projection = camera::OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Rad(2.0 * tan_half_fovy.atan()), aspect, near, far);
+    let mut frustum = Frustum::from_view_proj(projection * camera.calc_matrix())?;
+    let tan_half_fovx = tan_half_fovy * aspect;
+    let half = separation * 0.5;
+    let convergence = convergence.max(1e-3);
+    // Camera space x of the outermost left edge at distance z in front of the camera
+    let left_edge = |z: f32| f32::min(-half + z * (half / convergence - tan_half_fovx), half - z * (half / convergence + tan_half_fovx));
+    let slope = (left_edge(far) - left_edge(near)) / (far - near);
+    let offset = left_edge(near) - slope * near;
+    // Inside is x >= offset + slope * z on the left and, mirrored, x <= -offset - slope * z
+    let (right, forward, origin) = (camera.right(), camera.forward(), camera.position.to_vec());
+    let side = |normal: Vector3<f32>| Plane::from_coefficients(normal.extend(-normal.dot(origin) - offset));
+    frustum.planes[0] = side(right - forward * slope)?;
+    frustum.planes[1] = side(-right - forward * slope)?;
+    Some(frustum)
+}
+
+// The window's eye cameras, bound in place of its own camera for each eye's draws
+pub struct EyeCameras {
+    buffers: [Tracked<wgpu::Buffer>; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl EyeCameras {
+    pub fn new(context: &RenderContext) -> Self {
+        let buffers = Eye::BOTH.map(|eye| {
+            context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(match eye {
+                    Eye::Left => "Left Eye Camera Buffer",
+                    Eye::Right => "Right Eye Camera Buffer",
+                }),
+                contents: bytemuck::bytes_of(&CameraUniform::new()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            })
+        });
+        let bind_groups = std::array::from_fn(|index| {
+            context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &context.camera_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffers[index].as_entire_binding() }],
+                label: Some("Eye Camera Bind Group"),
+            })
+        });
+        Self { buffers, bind_groups }
+    }
+
+    // This frame's eye cameras, the settings' meters scaled to scene units. Returns the culling
+    // frustum holding both eyes.
+    pub fn update(&self, queue: &wgpu::Queue, settings: &StereoSettings, units_per_meter: f32, camera: &Camera, projection: &Projection) -> Option<Frustum> {
+        let (separation, convergence) = (settings.eye_separation * units_per_meter, settings.convergence * units_per_meter);
+        let (tan_half_fovx, tan_half_fovy) = projection.half_fov_tangents();
+        let aspect = match settings.mode {
+            StereoMode::SideBySide => tan_half_fovx / tan_half_fovy * 0.5,
+            _ => tan_half_fovx / tan_half_fovy,
+        };
+        let depth_range = projection.depth_range();
+        for (eye, buffer) in Eye::BOTH.into_iter().zip(&self.buffers) {
+            let (position, view) = eye_view(camera, eye, separation);
+            let projection = eye_projection(tan_half_fovy, aspect, depth_range, eye, separation, convergence);
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&CameraUniform::from_view_proj(position, projection * view)));
+        }
+        enclosing_frustum(camera, tan_half_fovy, aspect, depth_range, separation, convergence)
+    }
+
+    pub fn bind_group(&self, eye: Eye) -> &wgpu::BindGroup {
+        &self.bind_groups[eye as usize]
+    }
+}
+
+// Side by side: where the eye goes in a window of this size, as x, y, width, height
+pub fn eye_viewport(eye: Eye, (width, height): (u32, u32)) -> [f32; 4] {
+    let half = width as f32 * 0.5;
+    [if eye == Eye::Left { 0.0 } else { half }, 0.0, half, height as f32]
+}
+
+// Shared between windows, lives in the RenderContext
+pub struct AnaglyphPipeline {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl AnaglyphPipeline {
+    pub fn new(device: &wgpu::Device, scene_format: wgpu::TextureFormat) -> Self {
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Anaglyph Bind Group Layout"),
+            entries: &[render_context::texture_entry(0, float), render_context::texture_entry(1, float)],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Anaglyph Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("anaglyph.wgsl").into()),
+        });
+        let pipeline = render_context::fullscreen_pipeline(device, "Anaglyph Pipeline", &layout, &shader, "fs_main", scene_format, None);
+        Self { layout, pipeline }
+    }
+}
+
+// The window's frame graph entries: written by the eye passes, read by the composite
+pub struct AnaglyphTransients {
+    eyes: [TransientId; 2],
+}
+
+impl AnaglyphTransients {
+    // In place of the main pass
+    pub fn declare(graph: &mut FrameGraph, config: &wgpu::SurfaceConfiguration, scene_format: wgpu::TextureFormat) -> Self {
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let left = graph.texture(TransientDesc::new("anaglyph_left_eye", config, scene_format, usage));
+        let right = graph.texture(TransientDesc::new("anaglyph_right_eye", config, scene_format, usage));
+        graph.pass("left eye", &[], &[left]);
+        graph.pass("right eye", &[], &[right]);
+        graph.pass("anaglyph composite", &[left, right], &[]);
+        Self { eyes: [left, right] }
+    }
+}
+
+// Eye targets and the composite's bind group for one window, recreated with its frame graph
+pub struct AnaglyphTargets {
+    eye_views: [wgpu::TextureView; 2],
+    bind_group: wgpu::BindGroup,
+}
+
+impl AnaglyphTargets {
+    pub fn new(device: &wgpu::Device, pipeline: &AnaglyphPipeline, transients: &Transients, ids: &AnaglyphTransients) -> Self {
+        let eye_views = ids.eyes.map(|id| transients.view(id));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Anaglyph Bind Group"),
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&eye_views[0]) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&eye_views[1]) },
+            ],
+        });
+        Self { eye_views, bind_group }
+    }
+
+    // What the eye's pass renders (or resolves) into
+    pub fn eye_view(&self, eye: Eye) -> &wgpu::TextureView {
+        &self.eye_views[eye as usize]
+    }
+
+    // Both eyes into `target`, which it covers completely
+    pub fn encode_composite(&self, encoder: &mut wgpu::CommandEncoder, pipeline: &AnaglyphPipeline, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Anaglyph Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipeline.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Vector4};
+
+    const TOLERANCE: f32 = 1e-4;
+    const SEPARATION: f32 = 0.2;
+    const CONVERGENCE: f32 = 3.0;
+
+    // Normalized device coordinates of `point` seen by `eye`
+    fn ndc(camera: &Camera, eye: Eye, point: Point3<f32>) -> Vector3<f32> {
+        let (_, view) = eye_view(camera, eye, SEPARATION);
+        let projection = eye_projection(Rad::from(Deg(30.0_f32)).0.tan(), 16.0 / 9.0, (0.1, 100.0), eye, SEPARATION, CONVERGENCE);
+        let clip: Vector4<f32> = projection * view * point.to_homogeneous();
+        clip.truncate() / clip.w
+    }
+
+    fn camera() -> Camera {
+        Camera::new((1.0, 2.0, 3.0), Deg(-60.0), Deg(15.0)).with_roll(Deg(20.0))
+    }
+
+    // Off the view axis, at `distance` in front of the camera
+    fn point(camera: &Camera, distance: f32) -> Point3<f32> {
+        camera.position + camera.forward() * distance + camera.right() * 0.4 * distance / CONVERGENCE + camera.up() * 0.3 * distance / CONVERGENCE
+    }
+
+    #[test]
+    fn the_convergence_plane_lines_up_in_both_eyes() {
+        let camera = camera();
+        let (left, right) = (ndc(&camera, Eye::Left, point(&camera, CONVERGENCE)), ndc(&camera, Eye::Right, point(&camera, CONVERGENCE)));
+        assert!((left.x - right.x).abs() < TOLERANCE && (left.y - right.y).abs() < TOLERANCE, "{:?} {:?}", left, right);
+        assert!(left.x.abs() < 1.0 && left.y.abs() < 1.0, "{:?}", left);
+    }
+
+    #[test]
+    fn parallax_is_only_horizontal() {
+        let camera = camera();
+        for distance in [0.5, 1.0, 8.0, 40.0] {
+            let (left, right) = (ndc(&camera, Eye::Left, point(&camera, distance)), ndc(&camera, Eye::Right, point(&camera, distance)));
+            assert!((left.y - right.y).abs() < TOLERANCE, "at {}: {:?} {:?}", distance, left, right);
+            // Nearer points pop out: the left eye sees them further right
+            assert_eq!(left.x > right.x, distance < CONVERGENCE, "at {}: {:?} {:?}", distance, left, right);
+        }
+    }
+}
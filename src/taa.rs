@@ -0,0 +1,378 @@
+/*
+Purpose: Temporal anti-aliasing of the HDR frame
+Responsibilities:
+    - Offset the main window's projection by a sub-pixel Halton jitter, a new one every frame
+    - Own the resolve pipeline (shared by every window, HDR without MSAA only)
+    - Keep last frame's resolved image per window, dropped when the window's frame graph is
+      rebuilt and ignored for a frame after the camera jumped
+    - Record the resolve, which reprojects the history along the velocity prepass, clamps it to
+      the current pixel's neighborhood and blends it in before motion blur and tonemapping
+    - ex: a photographer shooting a bracket of slightly shifted frames and stacking them
+*/
+
+use cgmath::{Matrix4, SquareMatrix, Vector2, Vector3};
+
+use crate::{
+    frame_graph::{FrameGraph, TransientDesc, TransientId, Transients},
+    gpu_layout::{UniformCheck, rust_layout},
+    gpu_memory::{self, Tracked},
+    hdr::HDR_FORMAT,
+    motion_blur::{MotionBlurTransients, Reprojection},
+    render_context::{fullscreen_pipeline, texture_entry},
+};
+
+// Frames before the jitter repeats, enough to cover a pixel evenly
+pub const JITTER_SAMPLES: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaaSettings {
+    // When off the projection isn't jittered and no history exists
+    pub enabled: bool,
+    // Share of the history in every resolved pixel, higher is smoother and ghosts longer
+    pub feedback: f32,
+    // Keep drawing with one jitter offset, to tell jitter from other flicker
+    pub freeze_jitter: bool,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            feedback: 0.9,
+            freeze_jitter: false,
+        }
+    }
+}
+
+impl TaaSettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Temporal anti-aliasing (TAA)");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.add(egui::Slider::new(&mut self.feedback, 0.5..=0.98).text("History feedback"));
+            ui.checkbox(&mut self.freeze_jitter, "Freeze jitter");
+        });
+    }
+}
+
+// Radical inverse of `index` in `base`, the Halton sequence's coordinate along one axis
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Offset of frame `frame` in pixels, within half a pixel of the center. Halton(2, 3) from its
+// second point, the first is the corner.
+pub fn jitter_pixels(frame: u32) -> Vector2<f32> {
+    let index = frame % JITTER_SAMPLES + 1;
+    Vector2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+// Moves everything drawn with `view_proj * jitter_matrix(..)` by `pixels`, clip space spans two
+// units across the target
+pub fn jitter_matrix(pixels: Vector2<f32>, size: (u32, u32)) -> Matrix4<f32> {
+    let ndc = Vector3::new(2.0 * pixels.x / size.0.max(1) as f32, -2.0 * pixels.y / size.1.max(1) as f32, 0.0);
+    Matrix4::from_translation(ndc)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ResolveUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    previous_view_proj: [[f32; 4]; 4],
+    // 0 when the history is not to be used this frame
+    feedback: f32,
+    _padding: [u32; 3],
+}
+
+impl ResolveUniform {
+    fn new(settings: &TaaSettings, reprojection: Option<&Reprojection>, history_valid: bool) -> Self {
+        let feedback = match reprojection {
+            Some(_) if history_valid => settings.feedback.clamp(0.0, 0.98),
+            _ => 0.0,
+        };
+        let (view_proj, previous_view_proj) = reprojection.map_or((Matrix4::identity(), Matrix4::identity()), |r| (r.view_proj, r.previous_view_proj));
+        Self {
+            inverse_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+            previous_view_proj: previous_view_proj.into(),
+            feedback,
+            _padding: [0; 3],
+        }
+    }
+}
+
+pub const RESOLVE_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(ResolveUniform, [inverse_view_proj, previous_view_proj, feedback]),
+    wgsl: &[("taa.wgsl", "ResolveUniform")],
+};
+
+// Shared by every window, only created when HDR is on and MSAA off
+pub struct TaaPipeline {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+}
+
+impl TaaPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let unfiltered = wgpu::TextureSampleType::Float { filterable: false };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, unfiltered),
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                texture_entry(2, unfiltered),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("TAA Bind Group Layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("taa.wgsl").into()),
+        });
+        let pipeline = fullscreen_pipeline(device, "TAA Resolve Pipeline", &layout, &shader, "fs_resolve", HDR_FORMAT, None);
+        // History is read between texels, clamped so the border doesn't wrap to the far side
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA History Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self { layout, pipeline, sampler }
+    }
+}
+
+// The window's frame graph entry, a copy of the jittered frame the resolve reads
+pub struct TaaTransients {
+    scene_copy: TransientId,
+}
+
+impl TaaTransients {
+    // After the velocity prepass and before the motion blur
+    pub fn declare(graph: &mut FrameGraph, config: &wgpu::SurfaceConfiguration, velocity: &MotionBlurTransients) -> Self {
+        let scene_copy = graph.texture(TransientDesc::new(
+            "taa_scene_copy",
+            config,
+            HDR_FORMAT,
+            wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        graph.pass("taa resolve", &[velocity.velocity()], &[scene_copy]);
+        Self { scene_copy }
+    }
+}
+
+// History and frame copy for one window, recreated with its frame graph
+pub struct TaaTargets {
+    scene_copy: wgpu::Texture,
+    // Last frame's result, outlives the frame so it can't be a transient
+    history: Tracked<wgpu::Texture>,
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+    // Frames jittered so far, picks the offset
+    frame: u32,
+    // False until a frame was resolved into the history
+    history_valid: bool,
+}
+
+impl TaaTargets {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline: &TaaPipeline,
+        transients: &Transients,
+        ids: &TaaTransients,
+        velocity: &MotionBlurTransients,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let scene_copy = transients.texture(ids.scene_copy);
+        let scene_copy_view = scene_copy.create_view(&wgpu::TextureViewDescriptor::default());
+        let velocity_view = transients.view(velocity.velocity());
+        let history = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("taa_history"),
+            size: wgpu::Extent3d { width: config.width.max(1), height: config.height.max(1), depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let history_view = history.create_view(&wgpu::TextureViewDescriptor::default());
+        let buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("TAA Resolve Buffer"),
+            size: std::mem::size_of::<ResolveUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pipeline.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene_copy_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&velocity_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&pipeline.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("TAA Bind Group"),
+        });
+        Self { scene_copy, history, buffer, bind_group, frame: 0, history_valid: false }
+    }
+
+    // This frame's jitter in pixels, the next one unless frozen
+    pub fn next_jitter(&mut self, settings: &TaaSettings) -> Vector2<f32> {
+        if !settings.freeze_jitter {
+            self.frame = self.frame.wrapping_add(1);
+        }
+        jitter_pixels(self.frame)
+    }
+
+    // Upload the resolve parameters. Without a reprojection (first frame, the camera jumped) the
+    // history is dropped and the frame starts a new one.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &TaaSettings, reprojection: Option<&Reprojection>) {
+        let uniform = ResolveUniform::new(settings, reprojection, self.history_valid);
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+        self.history_valid = true;
+    }
+
+    // Resolve the HDR frame in place and keep the result for the next frame
+    pub fn encode_resolve(&self, encoder: &mut wgpu::CommandEncoder, pipeline: &TaaPipeline, scene: &wgpu::Texture, scene_view: &wgpu::TextureView) {
+        encoder.copy_texture_to_texture(scene.as_image_copy(), self.scene_copy.as_image_copy(), scene.size());
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("TAA Resolve Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: scene_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&pipeline.pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_texture(scene.as_image_copy(), self.history.as_image_copy(), scene.size());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, ElementWise, InnerSpace, Vector4};
+
+    use crate::camera::{Camera, Projection};
+
+    const TOLERANCE: f32 = 1e-4;
+    const SIZE: (u32, u32) = (320, 240);
+
+    fn view_proj(yaw: f32) -> Matrix4<f32> {
+        let camera = Camera::new((0.0, 1.0, 5.0), Deg(yaw), Deg(-10.0));
+        Projection::new(SIZE.0, SIZE.1, Deg(45.0), 0.1, 100.0).calc_matrix() * camera.calc_matrix()
+    }
+
+    // Where a clip space point lands on the target, in pixels from the top left
+    fn to_pixels(clip: Vector4<f32>) -> Vector2<f32> {
+        let ndc = clip.truncate().truncate() / clip.w;
+        Vector2::new((ndc.x + 1.0) * 0.5 * SIZE.0 as f32, (1.0 - ndc.y) * 0.5 * SIZE.1 as f32)
+    }
+
+    #[test]
+    fn the_jitter_walks_halton_2_3_inside_the_pixel() {
+        let expected = [(1.0 / 2.0, 1.0 / 3.0), (1.0 / 4.0, 2.0 / 3.0), (3.0 / 4.0, 1.0 / 9.0), (1.0 / 8.0, 4.0 / 9.0)];
+        for (frame, (x, y)) in expected.into_iter().enumerate() {
+            let jitter = jitter_pixels(frame as u32);
+            assert!((jitter - Vector2::new(x - 0.5, y - 0.5)).magnitude() < TOLERANCE, "frame {}: {:?}", frame, jitter);
+        }
+
+        let offsets: Vec<_> = (0..JITTER_SAMPLES).map(jitter_pixels).collect();
+        for (index, offset) in offsets.iter().enumerate() {
+            assert!(offset.x.abs() < 0.5 && offset.y.abs() < 0.5);
+            assert!(offsets[..index].iter().all(|other| (other - offset).magnitude() > TOLERANCE), "{:?} repeats", offset);
+        }
+        // Spread around the center rather than to one side
+        let mean = offsets.iter().sum::<Vector2<f32>>() / JITTER_SAMPLES as f32;
+        assert!(mean.magnitude() < 0.1, "{:?}", mean);
+        assert_eq!(jitter_pixels(JITTER_SAMPLES), jitter_pixels(0));
+    }
+
+    #[test]
+    fn the_jitter_matrix_moves_every_depth_by_the_same_pixels() {
+        let view_proj = view_proj(-90.0);
+        let offset = Vector2::new(0.25, -0.375);
+        let jittered = jitter_matrix(offset, SIZE) * view_proj;
+        for point in [Vector4::new(0.0, 0.0, 0.0, 1.0), Vector4::new(1.5, 0.5, -3.0, 1.0), Vector4::new(-20.0, 4.0, -60.0, 1.0)] {
+            let moved = to_pixels(jittered * point) - to_pixels(view_proj * point);
+            assert!((moved - offset).magnitude() < 1e-3, "{:?} moved by {:?}", point, moved);
+        }
+    }
+
+    #[test]
+    fn the_resolve_gets_this_frames_inverse_and_last_frames_camera() {
+        let settings = TaaSettings { enabled: true, ..TaaSettings::default() };
+        let reprojection = Reprojection { view_proj: view_proj(-88.0), previous_view_proj: view_proj(-90.0) };
+        let uniform = ResolveUniform::new(&settings, Some(&reprojection), true);
+
+        let round_trip = Matrix4::from(uniform.inverse_view_proj) * reprojection.view_proj;
+        for (column, identity) in [round_trip.x, round_trip.y, round_trip.z, round_trip.w].into_iter().zip([Vector4::unit_x(), Vector4::unit_y(), Vector4::unit_z(), Vector4::unit_w()]) {
+            assert!((column - identity).magnitude() < TOLERANCE, "{:?}", round_trip);
+        }
+        assert_eq!(Matrix4::from(uniform.previous_view_proj), reprojection.previous_view_proj);
+        assert_eq!(uniform.feedback, settings.feedback);
+
+        // camera_velocity in taa.wgsl: the far point behind the center pixel, seen last frame.
+        // The camera turned right, so the scene moved left since then.
+        let world = Matrix4::from(uniform.inverse_view_proj) * Vector4::new(0.0, 0.0, 1.0, 1.0);
+        let previous = Matrix4::from(uniform.previous_view_proj) * (world / world.w);
+        let velocity = (Vector2::new(0.0, 0.0) - previous.truncate().truncate() / previous.w).mul_element_wise(Vector2::new(0.5, -0.5));
+        assert!(velocity.x < 0.0 && velocity.y.abs() < 0.01, "{:?}", velocity);
+    }
+
+    #[test]
+    fn the_history_is_ignored_until_it_can_be_reprojected() {
+        let settings = TaaSettings { enabled: true, feedback: 1.5, ..TaaSettings::default() };
+        let reprojection = Reprojection { view_proj: view_proj(-90.0), previous_view_proj: view_proj(-90.0) };
+        assert_eq!(ResolveUniform::new(&settings, None, true).feedback, 0.0);
+        assert_eq!(ResolveUniform::new(&settings, Some(&reprojection), false).feedback, 0.0);
+        assert_eq!(ResolveUniform::new(&settings, Some(&reprojection), true).feedback, 0.98);
+    }
+}
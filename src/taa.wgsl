@@ -0,0 +1,89 @@
+/*
+Purpose: Blend the jittered HDR frame with last frame's resolved image
+Responsibilites:
+    - Find where each pixel was last frame from the velocity prepass, or by reprojecting the far
+      plane for pixels it didn't cover (the sky)
+    - Clamp the history to the min/max of the pixel's 3x3 neighborhood, so disoccluded and
+      changed pixels don't drag old colors along
+    - Mix by the feedback, the frame alone when the history is dropped or off screen
+*/
+
+struct ResolveUniform {
+    inverse_view_proj: mat4x4<f32>,
+    previous_view_proj: mat4x4<f32>,
+    // 0 drops the history
+    feedback: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Fullscreen triangle, no vertex buffer needed
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+// A copy of this frame, the pass writes back over the original
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var t_history: texture_2d<f32>;
+// xy: velocity, z: 1 where the prepass drew something
+@group(0) @binding(2)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(3)
+var s_history: sampler;
+@group(0) @binding(4)
+var<uniform> resolve: ResolveUniform;
+
+// Screen movement since last frame of whatever is infinitely far away behind this pixel
+fn camera_velocity(pixel: vec2<f32>, size: vec2<f32>) -> vec2<f32> {
+    let uv = (pixel + 0.5) / size;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let world = resolve.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let previous = resolve.previous_view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    if (previous.w <= 0.0) {
+        return vec2<f32>(0.0);
+    }
+    return (ndc - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
+
+@fragment
+fn fs_resolve(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dimensions = textureDimensions(t_scene);
+    let size = vec2<f32>(dimensions);
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let current = textureLoad(t_scene, pixel, 0);
+    // The velocity target may hold another pass's leftovers when there is no history
+    if (resolve.feedback <= 0.0) {
+        return current;
+    }
+
+    let covered = textureLoad(t_velocity, pixel, 0);
+    var velocity = covered.xy;
+    if (covered.z == 0.0) {
+        velocity = camera_velocity(in.clip_position.xy - 0.5, size);
+    }
+    let previous_uv = in.clip_position.xy / size - velocity;
+    if (any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0))) {
+        return current;
+    }
+
+    let last = vec2<i32>(dimensions) - 1;
+    var low = current.rgb;
+    var high = current.rgb;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbor = textureLoad(t_scene, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), last), 0).rgb;
+            low = min(low, neighbor);
+            high = max(high, neighbor);
+        }
+    }
+    let history = clamp(textureSampleLevel(t_history, s_history, previous_uv, 0.0).rgb, low, high);
+    return vec4<f32>(mix(current.rgb, history, resolve.feedback), current.a);
+}
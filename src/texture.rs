@@ -1,48 +1,47 @@
 use image::GenericImageView;
 use anyhow::*;
+use std::collections::HashMap;
+use crate::{gpu_debug::debug_label, gpu_memory::{self, Tracked}};
 
 pub struct Texture {
     #[allow(unused)]
-    pub texture: wgpu::Texture,
+    pub texture: Tracked<wgpu::Texture>,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-        is_normal_map: bool,
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
-    }
     // DEPTH_FORMAT for creating the depth stage of the render_pipeline and for creating the depth texture itself
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
         let size = wgpu::Extent3d { // depth texture needs to be the same size as our screen if we want things to render correctly
             width: config.width.max(1),
             height: config.height.max(1),
             depth_or_array_layers: 1,
         };
+        // Single sampled depth can be copied out by the depth capture (depth_debug.rs)
+        let mut usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        if sample_count == 1 {
+            usage |= wgpu::TextureUsages::COPY_SRC;
+        }
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            // must match the sample count of the color target it's paired with
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         };
-        let texture = device.create_texture(&desc);
+        let texture = gpu_memory::create_texture(device, &desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
+                label: debug_label!("{} Sampler", label).as_deref(),
                 address_mode_u: wgpu::AddressMode::ClampToEdge,
                 address_mode_v: wgpu::AddressMode::ClampToEdge,
                 address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -59,6 +58,48 @@ impl Texture {
         Self { texture, view, sampler }
     }
 
+    // Depth32Float as shaders read it (soft particles, depth debug views): an unfilterable float
+    // texture for textureLoad, multisampled when the depth texture is. Comparisons aren't wanted.
+    pub fn depth_read_layout_entry(binding: u32, sample_count: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: sample_count > 1,
+            },
+            count: None,
+        }
+    }
+
+    pub fn create_depth_read_view(depth_texture: &wgpu::Texture, label: &str) -> wgpu::TextureView {
+        depth_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
+
+    // Multisampled color target that gets resolved into the scene target when MSAA is on
+    pub fn create_msaa_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat, sample_count: u32) -> Tracked<wgpu::TextureView> {
+        let texture = gpu_memory::create_texture(device, &wgpu::TextureDescriptor {
+            label: Some("msaa_color_texture"),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        texture.map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -68,10 +109,37 @@ impl Texture {
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
+        let texture = Self::create_blank(device, dimensions.0, dimensions.1, label, is_normal_map);
+        let size = texture.texture.size();
+
+        queue.write_texture(
+            // tells wgpu hwere to copy the pixel data
+            texture.texture.as_image_copy(),
+            // the actual pixel data
+            &rgba,
+            // the layout of the texture
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * dimensions.0),
+                rows_per_image: Some(dimensions.1),
+            },
+            size,
+        );
 
+        Ok(texture)
+    }
+
+    // An empty color texture with a view and sampler, filled in later by from_image or texture_stream
+    pub fn create_blank(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+        is_normal_map: bool,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width,
+            height,
             // all textures are stored as 3D, we represent our 2D texture by setting depth to 1
             depth_or_array_layers: 1,
         };
@@ -81,7 +149,8 @@ impl Texture {
         } else {
             wgpu::TextureFormat::Rgba8UnormSrgb
         };
-        let texture = device.create_texture(
+        let texture = gpu_memory::create_texture(
+            device,
             &wgpu::TextureDescriptor {
                 label,
                 size,
@@ -96,23 +165,10 @@ impl Texture {
             }
         );
 
-        queue.write_texture(
-            // tells wgpu hwere to copy the pixel data
-            texture.as_image_copy(),
-            // the actual pixel data
-            &rgba,
-            // the layout of the texture
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
+                label: label.and_then(|label| debug_label!("{} Sampler", label)).as_deref(),
                 address_mode_u: wgpu::AddressMode::ClampToEdge,
                 address_mode_v: wgpu::AddressMode::ClampToEdge,
                 address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -123,7 +179,116 @@ impl Texture {
             }
         );
 
-        Ok(Self { texture, view, sampler })
+        Self { texture, view, sampler }
+    }
+}
+// Several small images packed into one texture. Each image is addressed by name
+// and described by its UV rectangle inside the atlas.
+pub struct Atlas {
+    pub width: u32,
+    pub height: u32,
+    // name -> [u offset, v offset, u scale, v scale]
+    regions: HashMap<String, [f32; 4]>,
+    // Names of the images that didn't fit
+    pub rejected: Vec<String>,
+    used_area: u64,
+}
+
+impl Atlas {
+    // Rect covering the whole texture, what non-atlas textures use
+    pub const FULL_RECT: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+    // Gap between packed images so linear filtering doesn't bleed into neighbours
+    const PADDING: u32 = 2;
+
+    // Pack the images and upload the result as a single texture
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[(String, image::DynamicImage)],
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Result<(Self, Texture)> {
+        let (atlas, pixels) = Self::pack(images, width, height);
+        for name in &atlas.rejected {
+            log::warn!("{}: '{}' doesn't fit in the {}x{} atlas", label, name, width, height);
+        }
+        log::info!("{}: packed {} images, {:.1}% occupied", label, atlas.regions.len(), atlas.occupancy() * 100.0);
+
+        let texture = Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(pixels), Some(label), false)?;
+        Ok((atlas, texture))
+    }
+
+    // Shelf packing: tallest images first, placed left to right along a row (shelf).
+    // When an image doesn't fit on the current shelf a new one is started below it.
+    // Images that don't fit anywhere are skipped and listed in `rejected`.
+    pub fn pack(images: &[(String, image::DynamicImage)], width: u32, height: u32) -> (Self, image::RgbaImage) {
+        let mut order = (0..images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| std::cmp::Reverse(images[i].1.height()));
+
+        let mut pixels = image::RgbaImage::new(width, height);
+        let mut regions = HashMap::new();
+        let mut rejected = Vec::new();
+        let mut used_area = 0;
+        let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+
+        for i in order {
+            let (name, img) = &images[i];
+            let (w, h) = img.dimensions();
+
+            // Start a new shelf if this one is full, but only if the image fits there
+            let (mut x, mut y) = (shelf_x, shelf_y);
+            if x + w > width {
+                (x, y) = (0, shelf_y + shelf_height);
+            }
+            if x + w > width || y + h > height {
+                rejected.push(name.clone());
+                continue;
+            }
+            if y != shelf_y {
+                shelf_y = y;
+                shelf_height = 0;
+            }
 
+            image::imageops::replace(&mut pixels, &img.to_rgba8(), x as i64, y as i64);
+            regions.insert(
+                name.clone(),
+                [
+                    x as f32 / width as f32,
+                    y as f32 / height as f32,
+                    w as f32 / width as f32,
+                    h as f32 / height as f32,
+                ],
+            );
+            used_area += w as u64 * h as u64;
+            shelf_x = x + w + Self::PADDING;
+            shelf_height = shelf_height.max(h + Self::PADDING);
+        }
+
+        let atlas = Self {
+            width,
+            height,
+            regions,
+            rejected,
+            used_area,
+        };
+        (atlas, pixels)
     }
-}
\ No newline at end of file
+
+    // UV offset and scale of a packed image, unknown names get the whole atlas
+    pub fn uv_rect(&self, name: &str) -> [f32; 4] {
+        self.regions.get(name).copied().unwrap_or(Self::FULL_RECT)
+    }
+
+    // Packed image names in a stable order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.regions.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    // Fraction of the atlas covered by packed images
+    pub fn occupancy(&self) -> f32 {
+        self.used_area as f32 / (self.width as u64 * self.height as u64) as f32
+    }
+}
@@ -0,0 +1,251 @@
+/*
+Purpose: Keep the models' textures under a GPU memory budget
+Responsibilities:
+    - Note which materials were drawn each frame (Material::bind_group marks them) and the frame
+      each of their textures was last used in
+    - While textures take more than the budget, evict the least recently used: the material binds
+      the low-res copy kept at load and the full texture's memory is freed
+    - Bring an evicted texture back once it is drawn again and fits the budget: decoded from its
+      file on the rayon pool, then uploaded a few strips a frame by the model's TextureStreamer
+    - Evict and reload only a few a frame, so a budget change or a turn of the camera doesn't hitch
+    - Leave alone what can't come back or isn't worth it: textures without a file (material sets,
+      generated ones), packed materials, pinned models, and everything that isn't a material
+      texture (UI, the sky, render targets)
+    - ex: the library's reading room, the books unread the longest go back to the stacks
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{gpu_memory::{self, MemoryCategory}, model::TextureSlot, model_entry::{ModelEntry, ModelHandle}, resources, texture_stream::StreamTarget};
+
+const MAX_EVICTIONS_PER_FRAME: usize = 4;
+// Each one starts a decode and, once that is done, a stream
+const MAX_RELOADS_PER_FRAME: usize = 1;
+// The low-res copies are 64x64, evicting anything that small frees nothing
+const MIN_EVICT_BYTES: u64 = 64 * 64 * 4 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TextureKey {
+    model: ModelHandle,
+    material: usize,
+    slot: TextureSlot,
+}
+
+struct Residency {
+    last_used: u64,
+    // Of the full texture, remembered while it is evicted to see whether it fits again
+    bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResidencyStats {
+    pub resident: usize,
+    pub evicted: usize,
+    // Being decoded or streamed back in
+    pub loading: usize,
+    // What the budget is held against: every tracked texture, or just the material textures in
+    // builds without gpu-memory-tracking
+    pub usage: u64,
+    // Since startup
+    pub evictions: u64,
+    pub reloads: u64,
+}
+
+type Decoded = (TextureKey, anyhow::Result<image::DynamicImage>);
+
+pub struct TextureResidency {
+    // None never evicts, and lets what was evicted come back
+    pub budget: Option<u64>,
+    frame: u64,
+    textures: HashMap<TextureKey, Residency>,
+    // Decoding on the rayon pool, or handed to a streamer and not bound yet
+    loading: HashSet<TextureKey>,
+    // Files that failed to decode are not tried again
+    failed: HashSet<TextureKey>,
+    sender: Sender<Decoded>,
+    receiver: Receiver<Decoded>,
+    stats: ResidencyStats,
+}
+
+impl TextureResidency {
+    pub fn new(budget: Option<u64>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            budget,
+            frame: 0,
+            textures: HashMap::new(),
+            loading: HashSet::new(),
+            failed: HashSet::new(),
+            sender,
+            receiver,
+            stats: ResidencyStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ResidencyStats {
+        self.stats
+    }
+
+    // Once a frame after the textures were pumped: settle what the last frame drew, evict down to
+    // the budget, start reloading what is wanted again and stream in what finished decoding
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &mut [ModelEntry]) {
+        self.frame += 1;
+        self.stream_decoded(device, queue, models);
+
+        let mut resident = Vec::new();
+        let mut wanted = Vec::new();
+        let mut material_bytes = 0;
+        let (mut resident_count, mut evicted_count) = (0, 0);
+        for entry in models.iter() {
+            for (index, material) in entry.model.materials.iter().enumerate() {
+                let drawn = material.take_drawn();
+                let evictable = !entry.pin_textures && material.packed_offset().is_none();
+                for slot in TextureSlot::ALL {
+                    // Without a file there is nothing to bring it back from
+                    if !material.texture_files.iter().any(|(file_slot, _)| *file_slot == slot) {
+                        continue;
+                    }
+                    let key = TextureKey { model: entry.handle, material: index, slot };
+                    let texture = self.textures.entry(key).or_insert(Residency { last_used: self.frame, bytes: 0 });
+                    if drawn {
+                        texture.last_used = self.frame;
+                    }
+                    if material.is_evicted(slot) {
+                        evicted_count += 1;
+                        if self.loading.contains(&key) {
+                            continue;
+                        }
+                        if drawn && !self.failed.contains(&key) {
+                            wanted.push((key, texture.bytes));
+                        }
+                    } else {
+                        resident_count += 1;
+                        self.loading.remove(&key);
+                        texture.bytes = material.texture_bytes(slot);
+                        material_bytes += texture.bytes;
+                        if evictable && texture.bytes >= MIN_EVICT_BYTES {
+                            resident.push((texture.last_used, key, texture.bytes));
+                        }
+                    }
+                }
+            }
+        }
+        // Removed models
+        self.textures.retain(|key, _| models.iter().any(|entry| entry.handle == key.model));
+        self.loading.retain(|key| models.iter().any(|entry| entry.handle == key.model));
+
+        let mut usage = if gpu_memory::ENABLED {
+            gpu_memory::snapshot().categories.iter().filter(|usage| usage.category == MemoryCategory::Texture).map(|usage| usage.bytes).sum()
+        } else {
+            material_bytes
+        };
+        if let Some(budget) = self.budget {
+            // Least recently used first, the biggest of those first
+            resident.sort_by_key(|&(last_used, _, bytes)| (last_used, std::cmp::Reverse(bytes)));
+            for (_, key, bytes) in resident.into_iter().take(MAX_EVICTIONS_PER_FRAME) {
+                if usage <= budget {
+                    break;
+                }
+                if self.evict(device, queue, models, key) {
+                    usage = usage.saturating_sub(bytes);
+                    resident_count -= 1;
+                    evicted_count += 1;
+                }
+            }
+        }
+        // Only what fits comes back, so a reload never pushes another texture out
+        for (key, bytes) in wanted.into_iter().take(MAX_RELOADS_PER_FRAME) {
+            if self.budget.is_some_and(|budget| usage + bytes > budget) {
+                break;
+            }
+            self.start_reload(models, key);
+            usage += bytes;
+        }
+        self.stats = ResidencyStats { resident: resident_count, evicted: evicted_count, loading: self.loading.len(), usage, ..self.stats };
+    }
+
+    fn evict(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &[ModelEntry], key: TextureKey) -> bool {
+        let Some(material) = models.iter().find(|entry| entry.handle == key.model).and_then(|entry| entry.model.materials.get(key.material)) else {
+            return false;
+        };
+        match material.evict_texture(device, queue, key.slot) {
+            Ok(()) => {
+                self.stats.evictions += 1;
+                true
+            }
+            Err(e) => {
+                log::warn!("Could not evict the {:?} texture of {}: {}", key.slot, material._name, e);
+                false
+            }
+        }
+    }
+
+    fn start_reload(&mut self, models: &[ModelEntry], key: TextureKey) {
+        let Some(file) = models
+            .iter()
+            .find(|entry| entry.handle == key.model)
+            .and_then(|entry| entry.model.materials.get(key.material))
+            .and_then(|material| material.texture_files.iter().find(|(slot, _)| *slot == key.slot))
+            .map(|(_, file)| file.clone())
+        else {
+            return;
+        };
+        self.loading.insert(key);
+        let sender = self.sender.clone();
+        rayon::spawn(move || {
+            // Converted here so the streamer's copy of the pixels is only a copy
+            let decoded = resources::decode_image(&file).map(|img| image::DynamicImage::ImageRgba8(img.to_rgba8()));
+            let _ = sender.send((key, decoded));
+        });
+    }
+
+    // Hands decoded images to their model's streamer, it binds them once the last strip is in
+    fn stream_decoded(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &mut [ModelEntry]) {
+        for (key, decoded) in self.receiver.try_iter().take(MAX_RELOADS_PER_FRAME).collect::<Vec<_>>() {
+            let Some(entry) = models.iter_mut().find(|entry| entry.handle == key.model) else {
+                continue;
+            };
+            let model = entry.model.clone();
+            let Some(material) = model.materials.get(key.material).filter(|material| material.is_evicted(key.slot)) else {
+                // Reloaded some other way meanwhile
+                self.loading.remove(&key);
+                continue;
+            };
+            let img = match decoded {
+                Ok(img) => img,
+                Err(e) => {
+                    log::warn!("Could not load the {:?} texture of {} back: {}", key.slot, material._name, e);
+                    self.loading.remove(&key);
+                    self.failed.insert(key);
+                    continue;
+                }
+            };
+            let target = StreamTarget { material: key.material, slot: key.slot };
+            // The low-res copy stays bound, the streamer's own placeholder isn't needed
+            match entry.streamer_mut().stream_image(device, queue, &img, &material._name, key.slot == TextureSlot::Normal, target) {
+                Ok(_) => self.stats.reloads += 1,
+                Err(e) => {
+                    log::warn!("Could not stream the {:?} texture of {} back: {}", key.slot, material._name, e);
+                    self.loading.remove(&key);
+                }
+            }
+        }
+    }
+
+    // One line for the console's stats and diagnostics
+    pub fn report(&self) -> String {
+        let stats = self.stats;
+        let budget = self.budget.map_or("no budget".to_string(), |budget| format!("{} budget", gpu_memory::format_bytes(budget)));
+        format!(
+            "textures: {} resident, {} evicted, {} loading, {} of {}, {} evictions and {} reloads so far\n",
+            stats.resident,
+            stats.evicted,
+            stats.loading,
+            gpu_memory::format_bytes(stats.usage),
+            budget,
+            stats.evictions,
+            stats.reloads
+        )
+    }
+}
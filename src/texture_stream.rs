@@ -0,0 +1,315 @@
+/*
+Purpose: Upload big textures over several frames
+Responsibilities:
+    - Split large images into row strips and copy a few strips per frame through reusable staging buffers
+    - Hand out a small downscaled placeholder to bind until the full texture is ready
+    - Report finished textures so their owner can swap them in, and let streams be cancelled
+    - ex: unloading a moving truck one box at a time instead of tipping it over
+*/
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use crate::{gpu_memory::{self, Tracked}, model::TextureSlot, texture};
+
+// Images with more pixels than this are streamed instead of uploaded in one go
+pub const STREAM_THRESHOLD_PIXELS: u32 = 2048 * 2048;
+// Size of one staging buffer, a strip is as many rows as fit in it
+const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 2 * 1024 * 1024;
+const STRIPS_PER_FRAME: usize = 2;
+// Mapped chunks kept around for reuse while streams are active
+const MAX_FREE_CHUNKS: usize = 4;
+const PLACEHOLDER_SIZE: u32 = 64;
+
+// What a finished texture should replace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamTarget {
+    pub material: usize,
+    pub slot: TextureSlot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamId(u64);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamStats {
+    pub active_streams: usize,
+    // Copied into staging buffers, waiting for the GPU to finish with them
+    pub bytes_in_flight: u64,
+    // Still only on the CPU side
+    pub bytes_pending: u64,
+}
+
+struct TextureStream {
+    id: StreamId,
+    target: StreamTarget,
+    texture: texture::Texture,
+    rgba: image::RgbaImage,
+    next_row: u32,
+}
+
+impl TextureStream {
+    fn bytes_pending(&self) -> u64 {
+        (self.rgba.height() - self.next_row) as u64 * self.rgba.width() as u64 * 4
+    }
+}
+
+// A staging buffer that has been submitted and is being mapped again for reuse
+struct RecallingChunk {
+    buffer: Tracked<wgpu::Buffer>,
+    used: u64,
+    mapped: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+pub struct TextureStreamer {
+    streams: Vec<TextureStream>,
+    // Mapped staging buffers ready to be written
+    free_chunks: Vec<Tracked<wgpu::Buffer>>,
+    recalling: Vec<RecallingChunk>,
+    next_id: u64,
+}
+
+// What is bound while the full image streams in, texture_residency binds it again for evicted textures
+pub fn placeholder_image(img: &image::DynamicImage) -> image::DynamicImage {
+    img.resize_exact(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, image::imageops::FilterType::Triangle)
+}
+
+impl TextureStreamer {
+    pub fn should_stream(img: &image::DynamicImage) -> bool {
+        img.width().saturating_mul(img.height()) > STREAM_THRESHOLD_PIXELS
+    }
+
+    // Starts streaming img and returns a placeholder to bind in the meantime.
+    // A stream already going to the same target is cancelled first.
+    pub fn stream_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: &str,
+        is_normal_map: bool,
+        target: StreamTarget,
+    ) -> anyhow::Result<(StreamId, texture::Texture)> {
+        if let Some(old) = self.streams.iter().find(|s| s.target == target).map(|s| s.id) {
+            self.cancel(old);
+        }
+
+        let placeholder = texture::Texture::from_image(device, queue, &placeholder_image(img), Some(&format!("{} (placeholder)", label)), is_normal_map)?;
+
+        let id = StreamId(self.next_id);
+        self.next_id += 1;
+        self.streams.push(TextureStream {
+            id,
+            target,
+            texture: texture::Texture::create_blank(device, img.width(), img.height(), Some(label), is_normal_map),
+            rgba: img.to_rgba8(),
+            next_row: 0,
+        });
+        Ok((id, placeholder))
+    }
+
+    // Drops the stream's pixels and its half filled texture, staging buffers go back to the pool
+    pub fn cancel(&mut self, id: StreamId) {
+        self.streams.retain(|s| s.id != id);
+        if self.streams.is_empty() {
+            self.free_chunks.clear();
+        }
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            active_streams: self.streams.len(),
+            bytes_in_flight: self.recalling.iter().map(|c| c.used).sum(),
+            bytes_pending: self.streams.iter().map(|s| s.bytes_pending()).sum(),
+        }
+    }
+
+    // Call once per frame, copies the next few strips and returns the textures that finished
+    pub fn pump(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<(StreamTarget, texture::Texture)> {
+        self.reclaim_chunks(device);
+        if self.streams.is_empty() {
+            return Vec::new();
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Stream Encoder"),
+        });
+        let mut submitted = Vec::new();
+        for _ in 0..STRIPS_PER_FRAME {
+            let Some(stream) = self.streams.iter_mut().find(|s| s.next_row < s.rgba.height()) else {
+                break;
+            };
+
+            // 1. Grab a mapped staging buffer, or make a new one
+            let buffer = self.free_chunks.pop().unwrap_or_else(|| {
+                gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+                    label: Some("Texture Stream Staging Buffer"),
+                    size: STAGING_CHUNK_SIZE,
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                })
+            });
+
+            // 2. Copy as many rows as fit, each padded to the copy alignment
+            let (width, height) = stream.rgba.dimensions();
+            let row_bytes = width * 4;
+            let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+            let rows = ((STAGING_CHUNK_SIZE / padded_row_bytes as u64) as u32).clamp(1, height - stream.next_row);
+            {
+                let mut mapped = buffer.slice(..).get_mapped_range_mut();
+                let pixels = stream.rgba.as_raw();
+                for row in 0..rows {
+                    let src = (stream.next_row + row) as usize * row_bytes as usize;
+                    let dst = row as usize * padded_row_bytes as usize;
+                    mapped[dst..dst + row_bytes as usize].copy_from_slice(&pixels[src..src + row_bytes as usize]);
+                }
+            }
+            buffer.unmap();
+
+            // 3. Record the copy into the strip's rows of the texture
+            encoder.copy_buffer_to_texture(
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &buffer,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_row_bytes),
+                        rows_per_image: Some(rows),
+                    },
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture: &stream.texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: stream.next_row, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height: rows,
+                    depth_or_array_layers: 1,
+                },
+            );
+            stream.next_row += rows;
+            submitted.push((buffer, (rows * padded_row_bytes) as u64));
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // 4. Map the staging buffers again so they can be reused once the GPU is done
+        for (buffer, used) in submitted {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let failed = Arc::new(AtomicBool::new(false));
+            let (mapped_flag, failed_flag) = (mapped.clone(), failed.clone());
+            buffer.slice(..).map_async(wgpu::MapMode::Write, move |result| match result {
+                Ok(()) => mapped_flag.store(true, Ordering::Release),
+                Err(_) => failed_flag.store(true, Ordering::Release),
+            });
+            self.recalling.push(RecallingChunk { buffer, used, mapped, failed });
+        }
+
+        // Finished streams can be bound as soon as their last copy is submitted
+        let (finished, active): (Vec<_>, Vec<_>) = self
+            .streams
+            .drain(..)
+            .partition(|s| s.next_row == s.rgba.height());
+        self.streams = active;
+        if self.streams.is_empty() {
+            self.free_chunks.clear();
+        }
+        finished.into_iter().map(|s| (s.target, s.texture)).collect()
+    }
+
+    fn reclaim_chunks(&mut self, device: &wgpu::Device) {
+        if self.recalling.is_empty() {
+            return;
+        }
+        if let Err(e) = device.poll(wgpu::PollType::Poll) {
+            log::warn!("Polling the device for staging buffers failed: {e}");
+        }
+        let keep_chunks = !self.streams.is_empty();
+        let mut still_recalling = Vec::new();
+        for chunk in self.recalling.drain(..) {
+            if chunk.mapped.load(Ordering::Acquire) {
+                if keep_chunks && self.free_chunks.len() < MAX_FREE_CHUNKS {
+                    self.free_chunks.push(chunk.buffer);
+                }
+            } else if !chunk.failed.load(Ordering::Acquire) {
+                still_recalling.push(chunk);
+            }
+        }
+        self.recalling = still_recalling;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::FutureExt;
+
+    const TARGET: StreamTarget = StreamTarget { material: 0, slot: TextureSlot::Diffuse };
+    // Just over the threshold, its rows don't fill the copy alignment
+    const SIZE: u32 = 2100;
+
+    fn device() -> (wgpu::Device, wgpu::Queue) {
+        let adapter = wgpu::Instance::default().request_adapter(&wgpu::RequestAdapterOptions::default()).block_on().expect("no usable GPU adapter");
+        adapter.request_device(&wgpu::DeviceDescriptor::default()).block_on().unwrap()
+    }
+
+    fn big_image() -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| image::Rgba([(y * 7) as u8, (x * 3) as u8, (y / 64 * 40) as u8, 255])))
+    }
+
+    #[test]
+    fn a_big_image_arrives_a_few_strips_per_pump() {
+        let (device, queue) = device();
+        let img = big_image();
+        assert!(TextureStreamer::should_stream(&img));
+        assert!(!TextureStreamer::should_stream(&image::DynamicImage::new_rgba8(2048, 2048)));
+
+        let mut streamer = TextureStreamer::default();
+        let (_, placeholder) = streamer.stream_image(&device, &queue, &img, "big", false, TARGET).unwrap();
+        assert_eq!(placeholder.texture.width(), PLACEHOLDER_SIZE);
+        let row_bytes = SIZE as u64 * 4;
+        assert_eq!(streamer.stats().bytes_pending, SIZE as u64 * row_bytes);
+
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+        let rows_per_pump = STAGING_CHUNK_SIZE / padded_row_bytes * STRIPS_PER_FRAME as u64;
+        let mut pumps = 0;
+        let finished = loop {
+            let before = streamer.stats().bytes_pending;
+            let finished = streamer.pump(&device, &queue);
+            pumps += 1;
+            if !finished.is_empty() {
+                break finished;
+            }
+            assert_eq!(before - streamer.stats().bytes_pending, rows_per_pump * row_bytes);
+            assert!(streamer.stats().bytes_in_flight > 0);
+        };
+        assert_eq!(pumps, (SIZE as u64).div_ceil(rows_per_pump));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].0, TARGET);
+        assert_eq!((finished[0].1.texture.width(), finished[0].1.texture.height()), (SIZE, SIZE));
+        assert_eq!(streamer.stats().active_streams, 0);
+    }
+
+    #[test]
+    fn cancelled_and_replaced_streams_let_go_of_their_staging_buffers() {
+        let (device, queue) = device();
+        let img = big_image();
+        let mut streamer = TextureStreamer::default();
+        // A second stream to the same target takes over from the first
+        streamer.stream_image(&device, &queue, &img, "first", false, TARGET).unwrap();
+        let (id, _) = streamer.stream_image(&device, &queue, &img, "second", false, TARGET).unwrap();
+        assert_eq!(streamer.stats().active_streams, 1);
+
+        assert!(streamer.pump(&device, &queue).is_empty());
+        streamer.cancel(id);
+        assert_eq!(streamer.stats().active_streams, 0);
+        assert_eq!(streamer.stats().bytes_pending, 0);
+
+        // Once the GPU is done with the submitted strips nothing is kept for reuse
+        device.poll(wgpu::PollType::Wait).unwrap();
+        assert!(streamer.pump(&device, &queue).is_empty());
+        assert_eq!(streamer.stats().bytes_in_flight, 0);
+        assert!(streamer.free_chunks.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+/*
+Purpose: Pick up texture files edited on disk while the engine runs
+Responsibilities:
+    - Remember the file and modification time behind every material texture of the loaded models
+    - Poll those files a few times a second and decode the ones that changed
+    - Wait for a file to settle before reading it, retry when an editor was still writing it
+    - ex: the proofreader who rereads a page whenever the author touches it
+*/
+
+use crate::{asset_source, model::{Model, TextureSlot}, model_entry::ModelHandle};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Editors often truncate the file then write it, it is only read once it stopped changing this long
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+// Decode failures in a row before the watcher waits for the next change instead
+const MAX_DECODE_ATTEMPTS: u32 = 5;
+
+// One material texture slot showing a watched file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureUse {
+    pub model: ModelHandle,
+    pub material: usize,
+    pub slot: TextureSlot,
+}
+
+// A changed file, decoded and ready to upload
+pub struct TextureReload {
+    pub path: PathBuf,
+    pub image: image::DynamicImage,
+    pub users: Vec<TextureUse>,
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    // None while the file is missing (some editors delete and recreate it)
+    modified: Option<SystemTime>,
+    // Last time the modification time moved, cleared once the new content was read
+    changed_at: Option<Instant>,
+    failed_attempts: u32,
+    users: Vec<TextureUse>,
+}
+
+pub struct TextureWatcher {
+    files: Vec<WatchedFile>,
+    last_poll: Instant,
+}
+
+impl Default for TextureWatcher {
+    fn default() -> Self {
+        Self { files: Vec::new(), last_poll: Instant::now() }
+    }
+}
+
+impl TextureWatcher {
+    // Textures built into the binary or not loaded from a file aren't watched
+    pub fn watch_model(&mut self, handle: ModelHandle, model: &Model) {
+        for (material_index, material) in model.materials.iter().enumerate() {
+            for (slot, file_name) in &material.texture_files {
+                let Some(path) = asset_source::current().resolve(file_name) else {
+                    continue;
+                };
+                let user = TextureUse { model: handle, material: material_index, slot: *slot };
+                match self.files.iter_mut().find(|file| file.path == path) {
+                    Some(file) => file.users.push(user),
+                    None => self.files.push(WatchedFile {
+                        modified: modified(&path),
+                        path,
+                        changed_at: None,
+                        failed_attempts: 0,
+                        users: vec![user],
+                    }),
+                }
+            }
+        }
+    }
+
+    pub fn unwatch_model(&mut self, handle: ModelHandle) {
+        for file in &mut self.files {
+            file.users.retain(|user| user.model != handle);
+        }
+        self.files.retain(|file| !file.users.is_empty());
+    }
+
+    pub fn watched_files(&self) -> usize {
+        self.files.len()
+    }
+
+    // Cheap to call every frame, the files are only looked at every POLL_INTERVAL
+    pub fn poll(&mut self, now: Instant) -> Vec<TextureReload> {
+        if now.duration_since(self.last_poll) < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = now;
+        let mut reloads = Vec::new();
+        for file in &mut self.files {
+            let current = modified(&file.path);
+            if current != file.modified {
+                file.modified = current;
+                file.changed_at = Some(now);
+                file.failed_attempts = 0;
+                continue;
+            }
+            let settled = file.changed_at.is_some_and(|at| now.duration_since(at) >= SETTLE_TIME);
+            if !settled || current.is_none() {
+                continue;
+            }
+            match std::fs::read(&file.path).map_err(anyhow::Error::from).and_then(|data| Ok(image::load_from_memory(&data)?)) {
+                Ok(image) => {
+                    file.changed_at = None;
+                    reloads.push(TextureReload { path: file.path.clone(), image, users: file.users.clone() });
+                }
+                Err(e) => {
+                    // Most likely still being written, the old texture stays until a read succeeds
+                    file.failed_attempts += 1;
+                    if file.failed_attempts >= MAX_DECODE_ATTEMPTS {
+                        log::warn!("Could not reload {}: {}, waiting for the next change", file.path.display(), e);
+                        file.changed_at = None;
+                    } else {
+                        log::debug!("Could not reload {} yet ({}), retrying", file.path.display(), e);
+                        file.changed_at = Some(now);
+                    }
+                }
+            }
+        }
+        reloads
+    }
+}
+
+fn modified(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
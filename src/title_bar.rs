@@ -0,0 +1,115 @@
+/*
+Purpose: Title bar drawn with egui for windows created without OS decorations
+Responsibilities:
+    - Draw the title and the minimize / maximize / close buttons
+    - Move the window when the strip is dragged, toggle maximize on double-click
+    - Resize from invisible grab areas along the edges and corners
+    - ex: a custom dashboard in a car, the same pedals and wheel underneath
+*/
+
+use winit::window::{ResizeDirection, Window};
+
+pub const TITLE_BAR_HEIGHT: f32 = 28.0;
+// Width of the grab areas along the window edges, in points
+const RESIZE_BORDER: f32 = 5.0;
+
+// Platforms where winit can start an OS window move from our own strip. Elsewhere the window
+// keeps its decorations, an undecorated window there couldn't be moved at all.
+pub fn supported() -> bool {
+    cfg!(any(
+        target_os = "windows",
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))
+}
+
+// Returns false when moving the window failed at runtime, the caller then gives the window
+// its OS decorations back and stops drawing the strip
+pub fn draw(ctx: &egui::Context, window: &Window, title: &str) -> bool {
+    let mut keep = true;
+    egui::TopBottomPanel::top("title_bar")
+        .exact_height(TITLE_BAR_HEIGHT)
+        .show(ctx, |ui| {
+            // Registered first so the buttons drawn on top of it take their clicks
+            let strip = ui.interact(ui.max_rect(), ui.id().with("title_bar_strip"), egui::Sense::click_and_drag());
+            if strip.double_clicked() {
+                window.set_maximized(!window.is_maximized());
+            } else if strip.drag_started_by(egui::PointerButton::Primary)
+                && let Err(e) = window.drag_window()
+            {
+                log::warn!("Unable to move the window ({}), falling back to the system title bar", e);
+                window.set_decorations(true);
+                keep = false;
+            }
+
+            ui.horizontal_centered(|ui| {
+                ui.label(egui::RichText::new(title).strong());
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.button("🗙").on_hover_text("Close").clicked() {
+                        std::process::exit(0);
+                    }
+                    let maximized = window.is_maximized();
+                    let (icon, hint) = if maximized { ("🗗", "Restore") } else { ("🗖", "Maximize") };
+                    if ui.button(icon).on_hover_text(hint).clicked() {
+                        window.set_maximized(!maximized);
+                    }
+                    if ui.button("🗕").on_hover_text("Minimize").clicked() {
+                        window.set_minimized(true);
+                    }
+                });
+            });
+        });
+    if keep && !window.is_maximized() {
+        draw_resize_borders(ctx, window);
+    }
+    keep
+}
+
+// Invisible areas claim the pointer near the edges so the camera doesn't turn while resizing
+fn draw_resize_borders(ctx: &egui::Context, window: &Window) {
+    let screen = ctx.screen_rect();
+    let b = RESIZE_BORDER;
+    let (left, right, top, bottom) = (screen.left(), screen.right(), screen.top(), screen.bottom());
+    let areas = [
+        (ResizeDirection::NorthWest, egui::Rect::from_min_max(egui::pos2(left, top), egui::pos2(left + b, top + b))),
+        (ResizeDirection::NorthEast, egui::Rect::from_min_max(egui::pos2(right - b, top), egui::pos2(right, top + b))),
+        (ResizeDirection::SouthWest, egui::Rect::from_min_max(egui::pos2(left, bottom - b), egui::pos2(left + b, bottom))),
+        (ResizeDirection::SouthEast, egui::Rect::from_min_max(egui::pos2(right - b, bottom - b), egui::pos2(right, bottom))),
+        (ResizeDirection::North, egui::Rect::from_min_max(egui::pos2(left + b, top), egui::pos2(right - b, top + b))),
+        (ResizeDirection::South, egui::Rect::from_min_max(egui::pos2(left + b, bottom - b), egui::pos2(right - b, bottom))),
+        (ResizeDirection::West, egui::Rect::from_min_max(egui::pos2(left, top + b), egui::pos2(left + b, bottom - b))),
+        (ResizeDirection::East, egui::Rect::from_min_max(egui::pos2(right - b, top + b), egui::pos2(right, bottom - b))),
+    ];
+    for (direction, rect) in areas {
+        egui::Area::new(egui::Id::new(("resize_border", format!("{:?}", direction))))
+            .fixed_pos(rect.min)
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                let (_, response) = ui.allocate_exact_size(rect.size(), egui::Sense::drag());
+                let response = response.on_hover_cursor(resize_cursor(direction));
+                // Not every platform can resize from the client area (macOS can't), nothing to do then
+                if response.drag_started_by(egui::PointerButton::Primary)
+                    && let Err(e) = window.drag_resize_window(direction)
+                {
+                    log::debug!("Unable to resize the window from its edge: {}", e);
+                }
+            });
+    }
+}
+
+fn resize_cursor(direction: ResizeDirection) -> egui::CursorIcon {
+    match direction {
+        ResizeDirection::North => egui::CursorIcon::ResizeNorth,
+        ResizeDirection::South => egui::CursorIcon::ResizeSouth,
+        ResizeDirection::East => egui::CursorIcon::ResizeEast,
+        ResizeDirection::West => egui::CursorIcon::ResizeWest,
+        ResizeDirection::NorthEast => egui::CursorIcon::ResizeNorthEast,
+        ResizeDirection::NorthWest => egui::CursorIcon::ResizeNorthWest,
+        ResizeDirection::SouthEast => egui::CursorIcon::ResizeSouthEast,
+        ResizeDirection::SouthWest => egui::CursorIcon::ResizeSouthWest,
+    }
+}
@@ -0,0 +1,42 @@
+/*
+Purpose: Confirm a quick action with a short message that goes away on its own
+Responsibilities:
+    - Keep the latest message and when it was shown, a new one replaces it
+    - Draw it at the bottom of the main window until it expires, asking egui to repaint then
+    - ex: the receipt printer's beep, heard once and forgotten
+*/
+
+use std::time::{Duration, Instant};
+
+const SHOW_FOR: Duration = Duration::from_millis(1800);
+
+#[derive(Default)]
+pub struct Toast {
+    current: Option<(String, Instant)>,
+}
+
+impl Toast {
+    pub fn show(&mut self, message: impl Into<String>) {
+        self.current = Some((message.into(), Instant::now()));
+    }
+
+    pub fn draw(&mut self, ctx: &egui::Context) {
+        let Some((message, shown)) = &self.current else {
+            return;
+        };
+        let Some(remaining) = SHOW_FOR.checked_sub(shown.elapsed()) else {
+            self.current = None;
+            ctx.request_repaint();
+            return;
+        };
+        egui::Area::new(egui::Id::new("toast"))
+            .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -24.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(message.as_str());
+                });
+            });
+        ctx.request_repaint_after(remaining);
+    }
+}
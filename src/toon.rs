@@ -0,0 +1,244 @@
+/*
+Purpose: Toon render style, flat light bands with dark silhouette outlines
+Responsibilities:
+    - Define RenderStyle and the settings the toon look is tuned with
+    - Own the toon uniform and the model pipelines (toon shading, inverted hull outline),
+      created at startup next to the realistic ones so switching styles is instant
+    - Build scene pipelines with custom entry points, culling and bind groups
+    - Keep a second outline uniform for the selection's hull (OutlineMode::Hull), drawn in
+      any style
+    - ex: the comic book inker tracing over the pencils
+*/
+
+use crate::{color, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, instance::InstanceRaw, model::{self, Vertex}, texture};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderStyle {
+    Realistic,
+    Toon,
+}
+
+impl RenderStyle {
+    pub const ALL: [RenderStyle; 2] = [RenderStyle::Realistic, RenderStyle::Toon];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RenderStyle::Realistic => "Realistic",
+            RenderStyle::Toon => "Toon",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToonSettings {
+    // Flat steps the diffuse light is split into
+    pub bands: u32,
+    // Outline width in physical pixels, 0 hides the outlines
+    pub outline_width: f32,
+    pub outline_color: [f32; 3],
+}
+
+impl Default for ToonSettings {
+    fn default() -> Self {
+        Self {
+            bands: 3,
+            outline_width: 2.0,
+            outline_color: [0.02, 0.02, 0.03],
+        }
+    }
+}
+
+// Must match the Toon struct in shader.wgsl, shape.wgsl and outline.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToonUniform {
+    outline_color: [f32; 4],
+    viewport: [f32; 2],
+    outline_width: f32,
+    bands: u32,
+}
+
+pub const TOON_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(ToonUniform, [outline_color, viewport, outline_width, bands]),
+    wgsl: &[("shader.wgsl", "Toon"), ("outline.wgsl", "Toon"), ("shape.wgsl", "Toon")],
+};
+
+// What scene_pipeline builds, the color target and depth are the same for every scene pipeline
+pub struct ScenePipelineDesc<'a> {
+    pub label: &'a str,
+    pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
+    pub shader: &'a wgpu::ShaderModule,
+    // Vertex and fragment entry point
+    pub entry_points: (&'a str, &'a str),
+    pub vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    pub cull_mode: Option<wgpu::Face>,
+}
+
+// Pipeline for scene geometry drawn into the scene target with depth, like create_render_pipeline
+// but with the entry points, culling and pipeline layout picked by the caller
+pub fn scene_pipeline(
+    device: &wgpu::Device,
+    desc: ScenePipelineDesc,
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let ScenePipelineDesc { label, bind_group_layouts, shader, entry_points, vertex_layouts, cull_mode } = desc;
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(entry_points.0),
+            buffers: vertex_layouts,
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_points.1),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            cull_mode,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+// Shared between windows, lives in the RenderContext. Procedural shapes keep their toon
+// pipelines in the ShapePipeline, next to the vertex layouts they need.
+pub struct ToonPipelines {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    buffer: Tracked<wgpu::Buffer>,
+    pub bind_group: wgpu::BindGroup,
+    // The outline pipeline's uniform for the selection's hull, its own width and color
+    selection_buffer: Tracked<wgpu::Buffer>,
+    pub selection_bind_group: wgpu::BindGroup,
+    // shader.wgsl's fs_toon, drawn with the same bind groups as the realistic pipeline plus the toon group
+    pub model_pipeline: wgpu::RenderPipeline,
+    pub model_outline_pipeline: wgpu::RenderPipeline,
+}
+
+impl ToonPipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let [texture_layout, camera_layout, light_layout] = layouts;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("Toon Bind Group Layout"),
+        });
+        let uniform = |label: &str| {
+            let buffer = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(&[toon_uniform(&ToonSettings::default(), (1, 1))]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+                label: Some(label),
+            });
+            (buffer, bind_group)
+        };
+        let (buffer, bind_group) = uniform("Toon Bind Group");
+        let (selection_buffer, selection_bind_group) = uniform("Selection Outline Bind Group");
+
+        let model_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Toon Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        });
+        let model_pipeline = scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Toon Pipeline",
+                bind_group_layouts: &[texture_layout, camera_layout, light_layout, &bind_group_layout],
+                shader: &model_shader,
+                entry_points: ("vs_main", "fs_toon"),
+                vertex_layouts: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                cull_mode: Some(wgpu::Face::Back),
+            },
+            color_format,
+            sample_count,
+        );
+        let outline_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+        });
+        // Front faces culled: only the hull's far side is drawn, peeking out around the model
+        let model_outline_pipeline = scene_pipeline(
+            device,
+            ScenePipelineDesc {
+                label: "Model Outline Pipeline",
+                bind_group_layouts: &[camera_layout, &bind_group_layout],
+                shader: &outline_shader,
+                entry_points: ("vs_model", "fs_outline"),
+                vertex_layouts: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                cull_mode: Some(wgpu::Face::Front),
+            },
+            color_format,
+            sample_count,
+        );
+
+        Self { bind_group_layout, buffer, bind_group, selection_buffer, selection_bind_group, model_pipeline, model_outline_pipeline }
+    }
+
+    // Every window renders with its own size, written right before its frame is recorded
+    pub fn write(&self, queue: &wgpu::Queue, settings: &ToonSettings, viewport: (u32, u32)) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[toon_uniform(settings, viewport)]));
+    }
+
+    // Like write, for the selection's hull
+    pub fn write_selection(&self, queue: &wgpu::Queue, width: f32, color: [f32; 3], viewport: (u32, u32)) {
+        let settings = ToonSettings { outline_width: width, outline_color: color, ..ToonSettings::default() };
+        queue.write_buffer(&self.selection_buffer, 0, bytemuck::cast_slice(&[toon_uniform(&settings, viewport)]));
+    }
+}
+
+fn toon_uniform(settings: &ToonSettings, (width, height): (u32, u32)) -> ToonUniform {
+    let [r, g, b] = color::srgb_to_linear_rgb(settings.outline_color);
+    ToonUniform {
+        outline_color: [r, g, b, 1.0],
+        viewport: [width as f32, height as f32],
+        outline_width: settings.outline_width,
+        bands: settings.bands,
+    }
+}
@@ -0,0 +1,400 @@
+/*
+Purpose: Move, rotate and scale the selected instance with handles drawn over the scene
+Responsibilities:
+    - Draw the handles of the active mode (axis arrows, axis circles, or axis boxes plus a center
+      box) with egui, at a fixed size on screen however far away the instance is
+    - Pick the handle under the cursor and turn the drag into a new position, rotation or scale
+    - Snap to the SnapSettings steps while Ctrl is held, the selection's number fields too
+    - Ask for a copy of the selection when a move starts with Alt held, the drag then moves the copy
+    - ex: the handles on a picture frame in a drawing program
+*/
+
+use crate::{camera::{Camera, Projection}, cursor::CursorContext, instance::MIN_INSTANCE_SCALE};
+use cgmath::{Deg, InnerSpace, Matrix4, Quaternion, Rotation3, Vector3, Vector4};
+
+// Length of the axis handles and radius of the rotation circles, in points
+pub const HANDLE_LENGTH: f32 = 80.0;
+// How close (in points) the cursor has to be to a handle to grab it
+pub const GRAB_DISTANCE: f32 = 8.0;
+const CENTER_BOX: f32 = 7.0;
+const CIRCLE_SEGMENTS: usize = 64;
+// A scale drag can shrink to this fraction of where it started, but no further
+const MIN_SCALE_FACTOR: f32 = 0.01;
+
+const AXIS_COLORS: [egui::Color32; 3] = [
+    egui::Color32::from_rgb(220, 70, 60),
+    egui::Color32::from_rgb(90, 200, 80),
+    egui::Color32::from_rgb(70, 120, 230),
+];
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::from_rgb(250, 210, 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [GizmoMode; 3] = [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "Move (W)",
+            GizmoMode::Rotate => "Rotate (E)",
+            GizmoMode::Scale => "Scale (R)",
+        }
+    }
+}
+
+// Steps Ctrl snaps to, edited in the menu next to the selection's transform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapSettings {
+    pub translate: f32,
+    pub rotate_degrees: f32,
+    pub scale: f32,
+}
+
+impl Default for SnapSettings {
+    fn default() -> Self {
+        Self { translate: 0.25, rotate_degrees: 15.0, scale: 0.25 }
+    }
+}
+
+impl SnapSettings {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Ctrl snaps to");
+            ui.add(egui::DragValue::new(&mut self.translate).speed(0.01).range(0.001..=100.0).suffix(" units"));
+            ui.add(egui::DragValue::new(&mut self.rotate_degrees).speed(0.5).range(0.1..=180.0).suffix("°"));
+            ui.add(egui::DragValue::new(&mut self.scale).speed(0.01).range(0.001..=10.0).suffix(" scale"));
+        });
+    }
+}
+
+// A labelled row of three drag values, for an x/y/z grid. With `step` the edited values land on
+// multiples of it. True when a value changed.
+pub fn drag_vector(ui: &mut egui::Ui, label: &str, values: &mut [f32; 3], speed: f32, suffix: &str, step: Option<f32>) -> bool {
+    ui.label(label);
+    let mut changed = false;
+    for value in values.iter_mut() {
+        if ui.add(egui::DragValue::new(value).speed(speed).suffix(suffix)).changed() {
+            changed = true;
+            if let Some(step) = step {
+                *value = snap(*value, step);
+            }
+        }
+    }
+    ui.end_row();
+    changed
+}
+
+// What the gizmo edits, in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GizmoTransform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Handle {
+    Axis(usize),
+    // The center box of the scale gizmo
+    Uniform,
+}
+
+struct Drag {
+    handle: Handle,
+    start_pointer: egui::Pos2,
+    start: GizmoTransform,
+    // Where the selection was on screen when the drag started, translating moves it since
+    start_center: egui::Pos2,
+    // Rotate only: screen angle last frame and the unwrapped total since the drag started
+    last_angle: f32,
+    accrued_degrees: f32,
+}
+
+// World space to egui points for one window, also used by the clip plane handles
+pub struct ScreenProjection {
+    view_proj: Matrix4<f32>,
+    screen: egui::Rect,
+}
+
+impl ScreenProjection {
+    pub fn new(ctx: &egui::Context, camera: &Camera, projection: &Projection) -> Self {
+        Self {
+            view_proj: projection.calc_matrix() * camera.calc_matrix(),
+            screen: ctx.screen_rect(),
+        }
+    }
+
+    // None behind the camera
+    pub fn project(&self, point: Vector3<f32>) -> Option<egui::Pos2> {
+        let clip = self.view_proj * Vector4::new(point.x, point.y, point.z, 1.0);
+        if clip.w <= 1e-4 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(egui::pos2(
+            self.screen.left() + (ndc.x + 1.0) * 0.5 * self.screen.width(),
+            self.screen.top() + (1.0 - ndc.y) * 0.5 * self.screen.height(),
+        ))
+    }
+
+    // Screen direction of a world axis at `origin`, and how many points one world unit along it covers
+    pub fn axis(&self, origin: Vector3<f32>, center: egui::Pos2, axis: Vector3<f32>) -> Option<(egui::Vec2, f32)> {
+        let offset = self.project(origin + axis)? - center;
+        let length = offset.length();
+        (length > 1e-3).then(|| (offset / length, length))
+    }
+}
+
+pub struct TransformGizmo {
+    pub mode: GizmoMode,
+    pub snap: SnapSettings,
+    drag: Option<Drag>,
+    // Handle under the cursor last frame, for the cursor icon
+    hovered: Option<Handle>,
+    // A move handle was grabbed with Alt held, see take_duplicate_request
+    duplicate_requested: bool,
+}
+
+impl Default for TransformGizmo {
+    fn default() -> Self {
+        Self { mode: GizmoMode::Translate, snap: SnapSettings::default(), drag: None, hovered: None, duplicate_requested: false }
+    }
+}
+
+impl TransformGizmo {
+    // Draws the handles around `transform` and returns the edited transform while a handle is
+    // dragged. Pointer input near the handles is claimed so the camera doesn't turn meanwhile.
+    pub fn show(&mut self, ctx: &egui::Context, camera: &Camera, projection: &Projection, transform: GizmoTransform) -> Option<GizmoTransform> {
+        let screen = ScreenProjection::new(ctx, camera, projection);
+        let Some(center) = screen.project(transform.position) else {
+            self.release();
+            return None;
+        };
+        let camera_position = Vector3::new(camera.position.x, camera.position.y, camera.position.z);
+        let to_camera = (camera_position - transform.position).normalize();
+
+        let reach = HANDLE_LENGTH + GRAB_DISTANCE;
+        let area_rect = egui::Rect::from_center_size(center, egui::vec2(reach, reach) * 2.0);
+        let response = egui::Area::new(egui::Id::new("transform_gizmo"))
+            .fixed_pos(area_rect.min)
+            .order(egui::Order::Background)
+            .show(ctx, |ui| ui.allocate_exact_size(area_rect.size(), egui::Sense::drag()).1)
+            .inner;
+        let ctrl = ctx.input(|input| input.modifiers.ctrl);
+
+        let hovered = response.hover_pos().and_then(|pointer| self.pick(&screen, transform, center, to_camera, pointer));
+        self.hovered = hovered;
+        if response.drag_started()
+            && let (Some(handle), Some(pointer)) = (hovered, response.interact_pointer_pos())
+        {
+            self.duplicate_requested = self.mode == GizmoMode::Translate && ctx.input(|input| input.modifiers.alt);
+            self.drag = Some(Drag {
+                handle,
+                start_pointer: pointer,
+                start: transform,
+                start_center: center,
+                last_angle: screen_angle(center, pointer),
+                accrued_degrees: 0.0,
+            });
+        }
+        if response.drag_stopped() || !response.dragged() {
+            self.drag = None;
+        }
+
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(hovered);
+        let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("transform_gizmo_handles")));
+        self.paint(&painter, &screen, transform, center, to_camera, active);
+
+        let pointer = response.interact_pointer_pos()?;
+        let (mode, steps) = (self.mode, self.snap);
+        let drag = self.drag.as_mut()?;
+        let edited = match (mode, drag.handle) {
+            (GizmoMode::Translate, Handle::Axis(axis)) => {
+                let (direction, points_per_unit) = screen.axis(drag.start.position, drag.start_center, unit(axis))?;
+                let mut distance = (pointer - drag.start_pointer).dot(direction) / points_per_unit;
+                if ctrl {
+                    distance = snap(distance, steps.translate);
+                }
+                GizmoTransform { position: drag.start.position + unit(axis) * distance, ..drag.start }
+            }
+            (GizmoMode::Rotate, Handle::Axis(axis)) => {
+                let angle = screen_angle(center, pointer);
+                drag.accrued_degrees += wrap_degrees(angle - drag.last_angle);
+                drag.last_angle = angle;
+                // Counter-clockwise on screen is a positive turn about an axis pointing at the viewer
+                let facing = if unit(axis).dot(to_camera) >= 0.0 { 1.0 } else { -1.0 };
+                let mut degrees = drag.accrued_degrees * facing;
+                if ctrl {
+                    degrees = snap(degrees, steps.rotate_degrees);
+                }
+                painter.text(
+                    pointer + egui::vec2(14.0, -14.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    format!("{:.0}°", degrees),
+                    egui::FontId::proportional(14.0),
+                    egui::Color32::WHITE,
+                );
+                GizmoTransform { rotation: Quaternion::from_axis_angle(unit(axis), Deg(degrees)) * drag.start.rotation, ..drag.start }
+            }
+            (GizmoMode::Scale, handle) => {
+                let travel = match handle {
+                    Handle::Axis(axis) => {
+                        let (direction, _) = screen.axis(drag.start.position, drag.start_center, drag.start.rotation * unit(axis))?;
+                        (pointer - drag.start_pointer).dot(direction)
+                    }
+                    // Right and up grow, left and down shrink
+                    Handle::Uniform => {
+                        let delta = pointer - drag.start_pointer;
+                        delta.x - delta.y
+                    }
+                };
+                // Dragging one handle length doubles the size
+                let factor = (1.0 + travel / HANDLE_LENGTH).max(MIN_SCALE_FACTOR);
+                let scale_axis = |value: f32| {
+                    let scaled = value * factor;
+                    let scaled = if ctrl { snap(scaled, steps.scale) } else { scaled };
+                    if scaled.abs() < MIN_INSTANCE_SCALE { MIN_INSTANCE_SCALE.copysign(value) } else { scaled }
+                };
+                let mut scale = drag.start.scale;
+                match handle {
+                    Handle::Axis(axis) => scale[axis] = scale_axis(scale[axis]),
+                    Handle::Uniform => scale = Vector3::new(scale_axis(scale.x), scale_axis(scale.y), scale_axis(scale.z)),
+                }
+                GizmoTransform { scale, ..drag.start }
+            }
+            _ => return None,
+        };
+        Some(edited)
+    }
+
+    // True once after a move started with Alt held. The caller copies the selection and applies
+    // this and every following edit of the drag to the copy, the original stays where it was.
+    pub fn take_duplicate_request(&mut self) -> bool {
+        std::mem::take(&mut self.duplicate_requested)
+    }
+
+    // Forget the drag and hover, for frames where the handles aren't shown
+    pub fn release(&mut self) {
+        self.drag = None;
+        self.hovered = None;
+    }
+
+    // Cursor for the handle being dragged or hovered, None when the cursor is elsewhere
+    pub fn cursor_context(&self) -> Option<CursorContext> {
+        let dragging = self.drag.is_some();
+        (dragging || self.hovered.is_some()).then_some(CursorContext::GizmoHandle { mode: self.mode, dragging })
+    }
+
+    // The handle closest to `pointer` within grabbing distance
+    fn pick(&self, screen: &ScreenProjection, transform: GizmoTransform, center: egui::Pos2, to_camera: Vector3<f32>, pointer: egui::Pos2) -> Option<Handle> {
+        if self.mode == GizmoMode::Scale && (pointer - center).length() <= CENTER_BOX + GRAB_DISTANCE * 0.5 {
+            return Some(Handle::Uniform);
+        }
+        (0..3)
+            .filter_map(|axis| {
+                let distance = match self.mode {
+                    GizmoMode::Rotate => {
+                        let circle = circle_points(screen, transform.position, unit(axis), to_camera, center)?;
+                        circle.windows(2).map(|pair| segment_distance(pointer, pair[0], pair[1])).fold(f32::INFINITY, f32::min)
+                    }
+                    _ => {
+                        let (direction, _) = screen.axis(transform.position, center, self.handle_axis(transform, axis))?;
+                        segment_distance(pointer, center, center + direction * HANDLE_LENGTH)
+                    }
+                };
+                (distance <= GRAB_DISTANCE).then_some((axis, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| Handle::Axis(axis))
+    }
+
+    // Scale handles follow the instance's own axes, the others are world aligned
+    fn handle_axis(&self, transform: GizmoTransform, axis: usize) -> Vector3<f32> {
+        match self.mode {
+            GizmoMode::Scale => transform.rotation * unit(axis),
+            _ => unit(axis),
+        }
+    }
+
+    fn paint(&self, painter: &egui::Painter, screen: &ScreenProjection, transform: GizmoTransform, center: egui::Pos2, to_camera: Vector3<f32>, active: Option<Handle>) {
+        let color = |handle: Handle, base: egui::Color32| if active == Some(handle) { ACTIVE_COLOR } else { base };
+        for (axis, base) in AXIS_COLORS.iter().enumerate() {
+            let stroke = egui::Stroke::new(2.5, color(Handle::Axis(axis), *base));
+            if self.mode == GizmoMode::Rotate {
+                if let Some(circle) = circle_points(screen, transform.position, unit(axis), to_camera, center) {
+                    painter.add(egui::Shape::line(circle, stroke));
+                }
+                continue;
+            }
+            let Some((direction, _)) = screen.axis(transform.position, center, self.handle_axis(transform, axis)) else {
+                continue;
+            };
+            let tip = center + direction * HANDLE_LENGTH;
+            painter.line_segment([center, tip], stroke);
+            if self.mode == GizmoMode::Scale {
+                painter.rect_filled(egui::Rect::from_center_size(tip, egui::vec2(8.0, 8.0)), 0.0, stroke.color);
+            } else {
+                // Arrow head
+                let normal = egui::vec2(-direction.y, direction.x);
+                let back = tip - direction * 12.0;
+                painter.add(egui::Shape::convex_polygon(
+                    vec![tip, back + normal * 5.0, back - normal * 5.0],
+                    stroke.color,
+                    egui::Stroke::NONE,
+                ));
+            }
+        }
+        if self.mode == GizmoMode::Scale {
+            let rect = egui::Rect::from_center_size(center, egui::vec2(CENTER_BOX, CENTER_BOX) * 2.0);
+            painter.rect_filled(rect, 1.0, color(Handle::Uniform, egui::Color32::from_gray(220)));
+        }
+    }
+}
+
+fn unit(axis: usize) -> Vector3<f32> {
+    let mut v = Vector3::new(0.0, 0.0, 0.0);
+    v[axis] = 1.0;
+    v
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+    (value / step).round() * step
+}
+
+// Degrees, counter-clockwise from the right with y pointing up the screen
+fn screen_angle(center: egui::Pos2, pointer: egui::Pos2) -> f32 {
+    (center.y - pointer.y).atan2(pointer.x - center.x).to_degrees()
+}
+
+// Into -180..180, so crossing the ±180 line doesn't jump a full turn
+fn wrap_degrees(degrees: f32) -> f32 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+pub fn segment_distance(point: egui::Pos2, a: egui::Pos2, b: egui::Pos2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_sq() > 0.0 { ((point - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0) } else { 0.0 };
+    (point - (a + ab * t)).length()
+}
+
+// Closed outline (screen points) of the rotation circle around `axis`, HANDLE_LENGTH points in radius
+fn circle_points(screen: &ScreenProjection, position: Vector3<f32>, axis: Vector3<f32>, to_camera: Vector3<f32>, center: egui::Pos2) -> Option<Vec<egui::Pos2>> {
+    // Measured across the view direction, where nothing is foreshortened
+    let helper = if to_camera.y.abs() < 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let (_, points_per_unit) = screen.axis(position, center, to_camera.cross(helper).normalize())?;
+    let radius = HANDLE_LENGTH / points_per_unit;
+    let helper = if axis.y.abs() < 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let u = axis.cross(helper).normalize();
+    let v = axis.cross(u);
+    (0..=CIRCLE_SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            screen.project(position + (u * angle.cos() + v * angle.sin()) * radius)
+        })
+        .collect()
+}
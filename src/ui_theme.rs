@@ -0,0 +1,156 @@
+/*
+Purpose: Styling of the egui layer drawn over the scene
+Responsibilities:
+    - Define EngineTheme (dark/light, accent color, window opacity, font size) and the built-in presets
+    - Apply a theme to an egui context without touching its memory (window positions) or scale factor
+    - Load an optional custom UI font from res/ into egui's font definitions
+    - Save and restore the theme through the user settings file
+    - ex: the paint and upholstery, the engine underneath stays the same
+*/
+
+use crate::{resources, user_settings::UserSettings};
+use pollster::FutureExt;
+use std::sync::Arc;
+
+// Dropped into res/ to replace egui's built-in proportional font
+pub const CUSTOM_FONT_FILE: &str = "fonts/ui.ttf";
+const CUSTOM_FONT_NAME: &str = "engine_ui";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineTheme {
+    pub dark: bool,
+    pub accent: [u8; 3],
+    // Alpha of window and panel backgrounds, 1 is opaque
+    pub window_opacity: f32,
+    // Body text size in points, headings and small text scale with it
+    pub font_size: f32,
+}
+
+impl Default for EngineTheme {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+pub const PRESETS: [(&str, EngineTheme); 3] = [
+    (
+        "Engine dark",
+        EngineTheme { dark: true, accent: [80, 150, 230], window_opacity: 0.92, font_size: 13.0 },
+    ),
+    (
+        "Engine light",
+        EngineTheme { dark: false, accent: [30, 110, 200], window_opacity: 0.96, font_size: 13.0 },
+    ),
+    // See-through windows so the scene stays visible behind the menus
+    (
+        "Glass",
+        EngineTheme { dark: true, accent: [240, 170, 60], window_opacity: 0.6, font_size: 12.5 },
+    ),
+];
+
+impl EngineTheme {
+    // Name of the preset this theme matches, None once it has been customised
+    pub fn preset_name(&self) -> Option<&'static str> {
+        PRESETS.iter().find(|(_, preset)| preset == self).map(|(name, _)| *name)
+    }
+
+    pub fn accent_color(&self) -> egui::Color32 {
+        let [r, g, b] = self.accent;
+        egui::Color32::from_rgb(r, g, b)
+    }
+
+    // Only visuals and text sizes change, so window positions (egui memory) and
+    // pixels per point survive switching themes at runtime
+    pub fn apply(&self, ctx: &egui::Context) {
+        let theme = if self.dark { egui::Theme::Dark } else { egui::Theme::Light };
+        ctx.set_theme(theme);
+        ctx.style_mut_of(theme, |style| {
+            let mut visuals = if self.dark { egui::Visuals::dark() } else { egui::Visuals::light() };
+            let accent = self.accent_color();
+            visuals.selection.bg_fill = accent.gamma_multiply(0.6);
+            visuals.selection.stroke.color = accent;
+            visuals.hyperlink_color = accent;
+            visuals.widgets.hovered.bg_stroke.color = accent;
+            visuals.widgets.active.bg_fill = accent.gamma_multiply(0.8);
+            visuals.widgets.active.weak_bg_fill = accent.gamma_multiply(0.8);
+
+            let alpha = (self.window_opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+            let translucent = |color: egui::Color32| {
+                egui::Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), alpha)
+            };
+            visuals.window_fill = translucent(visuals.window_fill);
+            visuals.panel_fill = translucent(visuals.panel_fill);
+            style.visuals = visuals;
+
+            let size = self.font_size.clamp(8.0, 32.0);
+            for (text_style, font_id) in style.text_styles.iter_mut() {
+                font_id.size = match text_style {
+                    egui::TextStyle::Heading => size * 1.4,
+                    egui::TextStyle::Small => size * 0.75,
+                    _ => size,
+                };
+            }
+        });
+    }
+
+    // Anything missing from the file keeps its default
+    pub fn from_settings(settings: &UserSettings) -> Self {
+        let default = Self::default();
+        Self {
+            dark: settings.parse("theme.dark").unwrap_or(default.dark),
+            accent: settings.get("theme.accent").and_then(parse_hex_color).unwrap_or(default.accent),
+            window_opacity: settings.parse("theme.window_opacity").unwrap_or(default.window_opacity),
+            font_size: settings.parse("theme.font_size").unwrap_or(default.font_size),
+        }
+    }
+
+    pub fn write_settings(&self, settings: &mut UserSettings) {
+        let [r, g, b] = self.accent;
+        settings.set("theme.dark", self.dark);
+        settings.set("theme.accent", format!("#{:02x}{:02x}{:02x}", r, g, b));
+        settings.set("theme.window_opacity", self.window_opacity);
+        settings.set("theme.font_size", self.font_size);
+    }
+}
+
+// "#rrggbb"
+fn parse_hex_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+// egui panics on font data it can't parse, so only hand it files that look like fonts
+fn looks_like_font(data: &[u8]) -> bool {
+    matches!(data.get(..4), Some([0, 1, 0, 0] | b"OTTO" | b"true" | b"ttcf"))
+}
+
+// Put res/fonts/ui.ttf in front of egui's proportional fonts, the built-in ones stay as
+// fallback for missing glyphs. Without the file the defaults are used as is.
+pub fn install_custom_font(ctx: &egui::Context) {
+    let data = match resources::load_binary(CUSTOM_FONT_FILE).block_on() {
+        Ok(data) => data,
+        Err(_) => {
+            log::debug!("No {} in res/, using egui's default fonts", CUSTOM_FONT_FILE);
+            return;
+        }
+    };
+    if !looks_like_font(&data) {
+        log::warn!("{} is not a TrueType/OpenType font, using egui's default fonts", CUSTOM_FONT_FILE);
+        return;
+    }
+
+    let mut fonts = egui::FontDefinitions::default();
+    fonts
+        .font_data
+        .insert(CUSTOM_FONT_NAME.to_string(), Arc::new(egui::FontData::from_owned(data)));
+    fonts
+        .families
+        .entry(egui::FontFamily::Proportional)
+        .or_default()
+        .insert(0, CUSTOM_FONT_NAME.to_string());
+    ctx.set_fonts(fonts);
+}
@@ -0,0 +1,348 @@
+/*
+Purpose: Undo and redo for the editor's edits
+Responsibilities:
+    - Describe each edit as a Command holding the state before and after it, so it can be
+      applied and reverted again: transforms, spawns and despawns, the scene light, point lights
+      and material parameters (the color tint among them). A despawned instance's user data
+      waits in its command to come back with it.
+    - Keep the applied commands on a stack bounded to a depth, the oldest are forgotten past it,
+      and the undone ones for redo until a new edit comes in
+    - Merge the edits of one drag (gizmo, slider, color picker) into a single command, closed once
+      no mouse button is held any more
+    - Report a command that can't apply any more (its model or instance was removed) so the
+      caller drops it instead of acting on the wrong thing
+    - ex: the draft history of a document, every revision kept until there are too many
+*/
+
+use std::any::Any;
+use std::collections::VecDeque;
+
+use crate::{instance::Instance, model::MaterialParams, model_entry::{InstanceId, ModelHandle}, point_lights::PointLight, state::State};
+
+pub const DEFAULT_UNDO_DEPTH: usize = 100;
+
+// What the gizmo and the inspector edit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstanceTransform {
+    pub initial_position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl InstanceTransform {
+    pub fn of(instance: &Instance) -> Self {
+        Self { initial_position: instance.initial_position, rotation: instance.rotation, scale: instance.scale }
+    }
+
+    fn write(&self, instance: &mut Instance) {
+        instance.initial_position = self.initial_position;
+        instance.rotation = self.rotation;
+        instance.scale = self.scale;
+    }
+}
+
+pub enum Command {
+    Transform { id: InstanceId, before: InstanceTransform, after: InstanceTransform },
+    // Ids aren't reused, so a despawned instance comes back under the one it had. `user_data`
+    // holds the instance's while it is gone.
+    Spawn { id: InstanceId, instance: Instance, user_data: Option<Box<dyn Any + Send>> },
+    Despawn { id: InstanceId, instance: Instance, user_data: Option<Box<dyn Any + Send>> },
+    // Color and intensity
+    Light { before: ([f32; 3], f32), after: ([f32; 3], f32) },
+    PointLight { before: PointLight, after: PointLight },
+    // Shared by all of the model's instances
+    Material { model: ModelHandle, material: usize, before: MaterialParams, after: MaterialParams },
+}
+
+impl Command {
+    // "Move Cube #3", for the History window. `name` is the model's, where there is one.
+    pub fn describe(&self, name: &str) -> String {
+        match self {
+            Command::Transform { id, before, after } => {
+                let verb = if before.initial_position != after.initial_position {
+                    "Move"
+                } else if before.rotation != after.rotation {
+                    "Rotate"
+                } else {
+                    "Scale"
+                };
+                format!("{} {} #{}", verb, name, id.index)
+            }
+            Command::Spawn { id, .. } => format!("Spawn {} #{}", name, id.index),
+            Command::Despawn { id, .. } => format!("Despawn {} #{}", name, id.index),
+            Command::Light { .. } => "Edit the scene light".to_string(),
+            Command::PointLight { after, .. } => format!("Edit light {}", after.id),
+            Command::Material { material, .. } => format!("Edit {} material {}", name, material),
+        }
+    }
+
+    // The model whose instances or materials it edits
+    pub fn model(&self) -> Option<ModelHandle> {
+        match self {
+            Command::Transform { id, .. } | Command::Spawn { id, .. } | Command::Despawn { id, .. } => Some(id.model),
+            Command::Material { model, .. } => Some(*model),
+            Command::Light { .. } | Command::PointLight { .. } => None,
+        }
+    }
+
+    // An edit back to where it started, not worth a history entry
+    fn is_noop(&self) -> bool {
+        match self {
+            Command::Transform { before, after, .. } => before == after,
+            Command::Light { before, after } => before == after,
+            Command::PointLight { before, after } => before == after,
+            Command::Material { before, after, .. } => before == after,
+            Command::Spawn { .. } | Command::Despawn { .. } => false,
+        }
+    }
+
+    // Takes over `next`'s end state when both edit the same thing, keeping this one's start
+    fn merge(&mut self, next: &Command) -> bool {
+        match (self, next) {
+            (Command::Transform { id, after, .. }, Command::Transform { id: next_id, after: next_after, .. }) if id == next_id => *after = *next_after,
+            (Command::Light { after, .. }, Command::Light { after: next_after, .. }) => *after = *next_after,
+            (Command::PointLight { after, .. }, Command::PointLight { after: next_after, .. }) if after.id == next_after.id => *after = *next_after,
+            (Command::Material { model, material, after, .. }, Command::Material { model: next_model, material: next_material, after: next_after, .. })
+                if model == next_model && material == next_material =>
+            {
+                *after = *next_after
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Redo. Err says why it can't any more, nothing was changed then.
+    pub fn apply(&mut self, state: &mut State) -> Result<(), String> {
+        self.set(state, true)
+    }
+
+    // Undo
+    pub fn revert(&mut self, state: &mut State) -> Result<(), String> {
+        self.set(state, false)
+    }
+
+    fn set(&mut self, state: &mut State, forward: bool) -> Result<(), String> {
+        match self {
+            Command::Transform { id, before, after } => {
+                let instance = state.instance_mut(*id).ok_or_else(|| format!("instance #{} is gone", id.index))?;
+                pick(forward, before, after).write(instance);
+            }
+            Command::Spawn { id, instance, user_data } if forward => restore(state, *id, instance, user_data)?,
+            Command::Despawn { id, instance, user_data } if !forward => restore(state, *id, instance, user_data)?,
+            Command::Spawn { id, user_data, .. } | Command::Despawn { id, user_data, .. } => remove(state, *id, user_data)?,
+            Command::Light { before, after } => {
+                let (color, intensity) = pick(forward, before, after);
+                state.apply_light(color, intensity);
+            }
+            Command::PointLight { before, after } => {
+                let light = state.point_light_mut(after.id).ok_or_else(|| format!("light {} was removed", after.id))?;
+                *light = pick(forward, before, after);
+            }
+            Command::Material { model, material, before, after } => {
+                if !state.set_material_params(*model, *material, pick(forward, before, after)) {
+                    return Err("its model was removed".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn pick<T: Copy>(forward: bool, before: &T, after: &T) -> T {
+    if forward { *after } else { *before }
+}
+
+// Hands `user_data` back to the instance
+fn restore(state: &mut State, id: InstanceId, instance: &Instance, user_data: &mut Option<Box<dyn Any + Send>>) -> Result<(), String> {
+    state.instance_count(id.model).ok_or("its model was removed")?;
+    if !state.restore_instance(id, instance.clone()) {
+        return Err(format!("#{} is already there", id.index));
+    }
+    if let Some(data) = user_data.take() {
+        state.set_instance_user_data(id, data);
+    }
+    Ok(())
+}
+
+// Keeps the instance's user data in `user_data` until it comes back
+fn remove(state: &mut State, id: InstanceId, user_data: &mut Option<Box<dyn Any + Send>>) -> Result<(), String> {
+    state.instance_count(id.model).ok_or("its model was removed")?;
+    let data = state.take_instance_user_data(id);
+    state.remove_instance(id).ok_or_else(|| format!("#{} was despawned", id.index))?;
+    *user_data = data;
+    Ok(())
+}
+
+pub struct HistoryEntry {
+    pub command: Command,
+    pub description: String,
+}
+
+pub struct UndoStack {
+    // Oldest first
+    done: VecDeque<HistoryEntry>,
+    // Newest undo last, what redo takes next
+    undone: Vec<HistoryEntry>,
+    // Still growing while a mouse button is held, see record
+    open: Option<HistoryEntry>,
+    depth: usize,
+}
+
+impl UndoStack {
+    pub fn new(depth: usize) -> Self {
+        Self { done: VecDeque::new(), undone: Vec::new(), open: None, depth: depth.max(1) }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Edits of the same thing one after another merge until settle closes the command
+    pub fn record(&mut self, command: Command, description: String) {
+        if let Some(open) = &mut self.open
+            && open.command.merge(&command)
+        {
+            return;
+        }
+        self.close();
+        self.open = Some(HistoryEntry { command, description });
+    }
+
+    // Once a frame, after the UI: a drag's command is done when the button comes up
+    pub fn settle(&mut self, pointer_down: bool) {
+        if !pointer_down {
+            self.close();
+        }
+    }
+
+    // Ends the open command, a new edit makes the undone ones unreachable
+    pub fn close(&mut self) {
+        let Some(entry) = self.open.take() else {
+            return;
+        };
+        if entry.command.is_noop() {
+            return;
+        }
+        self.undone.clear();
+        self.done.push_back(entry);
+        while self.done.len() > self.depth {
+            self.done.pop_front();
+        }
+    }
+
+    // Whatever is being recorded is closed first, so it is what comes back
+    pub fn take_undo(&mut self) -> Option<HistoryEntry> {
+        self.close();
+        self.done.pop_back()
+    }
+
+    pub fn take_redo(&mut self) -> Option<HistoryEntry> {
+        self.close();
+        self.undone.pop()
+    }
+
+    // After take_undo's command was reverted
+    pub fn push_undone(&mut self, entry: HistoryEntry) {
+        self.undone.push(entry);
+    }
+
+    // After take_redo's command was applied again
+    pub fn push_redone(&mut self, entry: HistoryEntry) {
+        self.done.push_back(entry);
+    }
+
+    // The open command counts as done
+    pub fn done(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.done.iter().chain(&self.open)
+    }
+
+    pub fn undone(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.undone.iter().rev()
+    }
+
+    // Drops every command on the model, for when its instances were replaced behind the stack's
+    // back and the ids name other instances now. How many were dropped.
+    pub fn forget_model(&mut self, model: ModelHandle) -> usize {
+        let count = self.done.len() + self.undone.len() + self.open.is_some() as usize;
+        self.done.retain(|entry| entry.command.model() != Some(model));
+        self.undone.retain(|entry| entry.command.model() != Some(model));
+        self.open = self.open.take().filter(|entry| entry.command.model() != Some(model));
+        count - (self.done.len() + self.undone.len() + self.open.is_some() as usize)
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.open = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The scene light going to `intensity`, from one less
+    fn light(intensity: f32) -> Command {
+        Command::Light { before: ([1.0; 3], intensity - 1.0), after: ([1.0; 3], intensity) }
+    }
+
+    fn record(stack: &mut UndoStack, intensity: f32) {
+        stack.record(light(intensity), format!("{}", intensity));
+    }
+
+    fn descriptions<'a>(entries: impl Iterator<Item = &'a HistoryEntry>) -> Vec<&'a str> {
+        entries.map(|entry| entry.description.as_str()).collect()
+    }
+
+    #[test]
+    fn the_oldest_edits_go_past_the_depth() {
+        let mut stack = UndoStack::new(3);
+        for intensity in 1..=5 {
+            record(&mut stack, intensity as f32);
+            stack.settle(false);
+        }
+        assert_eq!(descriptions(stack.done()), ["3", "4", "5"]);
+        assert_eq!(UndoStack::new(0).depth(), 1);
+    }
+
+    #[test]
+    fn one_drag_is_one_command() {
+        let mut stack = UndoStack::new(10);
+        for intensity in 1..=4 {
+            record(&mut stack, intensity as f32);
+            stack.settle(true);
+        }
+        stack.settle(false);
+        record(&mut stack, 5.0);
+        stack.settle(false);
+
+        let entries: Vec<_> = stack.done().collect();
+        assert_eq!(entries.len(), 2);
+        let Command::Light { before, after } = entries[0].command else {
+            panic!("not a light edit");
+        };
+        // The drag's first start and last end
+        assert_eq!((before.1, after.1), (0.0, 4.0));
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut stack = UndoStack::new(10);
+        for intensity in 1..=2 {
+            record(&mut stack, intensity as f32);
+            stack.settle(false);
+        }
+        let undone = stack.take_undo().unwrap();
+        stack.push_undone(undone);
+        assert_eq!(descriptions(stack.undone()), ["2"]);
+
+        // Nothing changes until the new edit is closed
+        record(&mut stack, 3.0);
+        assert_eq!(descriptions(stack.undone()), ["2"]);
+        stack.settle(false);
+        assert_eq!(stack.undone().count(), 0);
+        assert!(stack.take_redo().is_none());
+        assert_eq!(descriptions(stack.done()), ["1", "3"]);
+    }
+}
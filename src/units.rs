@@ -0,0 +1,109 @@
+/*
+Purpose: What one scene unit measures, and every size derived from it
+Responsibilities:
+    - Define SceneUnits (meters, centimeters or a custom number of units per meter)
+    - Scale the engine's sizes that are authored in meters: camera speed (and with it scroll
+      speed), near and far planes, instance grid spacing and world-space gizmo sizes
+    - Parse the --units value
+    - Format measured distances
+    - ex: the scale bar in the corner of a map
+*/
+
+use std::fmt;
+
+// Sizes in meters, multiplied by units_per_meter wherever they are used
+const CAMERA_SPEED: f32 = 4.0;
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 100.0;
+const INSTANCE_SPACING: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SceneUnits {
+    #[default]
+    Meters,
+    Centimeters,
+    // Scene units in one meter, 1000 for millimeters, 3.28 for feet
+    Custom(f32),
+}
+
+impl SceneUnits {
+    // Custom keeps its factor when picked from the menu
+    pub const PRESETS: [SceneUnits; 2] = [SceneUnits::Meters, SceneUnits::Centimeters];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SceneUnits::Meters => "Meters",
+            SceneUnits::Centimeters => "Centimeters",
+            SceneUnits::Custom(_) => "Custom",
+        }
+    }
+
+    // Short name for speeds and distances in the UI
+    pub fn suffix(self) -> &'static str {
+        match self {
+            SceneUnits::Meters => "m",
+            SceneUnits::Centimeters => "cm",
+            SceneUnits::Custom(_) => "units",
+        }
+    }
+
+    pub fn units_per_meter(self) -> f32 {
+        match self {
+            SceneUnits::Meters => 1.0,
+            SceneUnits::Centimeters => 100.0,
+            SceneUnits::Custom(factor) => factor,
+        }
+    }
+
+    // A length in scene units for labels, custom units also in meters
+    pub fn format_distance(self, distance: f32) -> String {
+        match self {
+            SceneUnits::Custom(factor) => format!("{:.2} {} ({:.2} m)", distance, self.suffix(), distance / factor),
+            _ => format!("{:.2} {}", distance, self.suffix()),
+        }
+    }
+
+    // Camera flight speed in units per second, before the speed multiplier
+    pub fn camera_speed(self) -> f32 {
+        CAMERA_SPEED * self.units_per_meter()
+    }
+
+    // Near and far plane of every window's projection
+    pub fn depth_range(self) -> (f32, f32) {
+        let scale = self.units_per_meter();
+        (NEAR_PLANE * scale, FAR_PLANE * scale)
+    }
+
+    // Between neighbours of a generated instance layout
+    pub fn instance_spacing(self) -> f32 {
+        INSTANCE_SPACING * self.units_per_meter()
+    }
+
+    // Light marker, probe spheres and other gizmos drawn in the scene, not on screen
+    pub fn gizmo_scale(self) -> f32 {
+        self.units_per_meter()
+    }
+
+    // "meters", "centimeters" or a positive number of units per meter
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "meters" | "m" => Ok(SceneUnits::Meters),
+            "centimeters" | "cm" => Ok(SceneUnits::Centimeters),
+            _ => text
+                .parse::<f32>()
+                .ok()
+                .filter(|factor| factor.is_finite() && *factor > 0.0)
+                .map(SceneUnits::Custom)
+                .ok_or_else(|| format!("--units expects meters, centimeters or a positive number of units per meter, got '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for SceneUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneUnits::Custom(factor) => write!(f, "{} units per meter", factor),
+            _ => f.write_str(self.label()),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+/*
+Purpose: Preferences that survive a restart
+Responsibilities:
+    - Read and write a plain `key = value` text file, one setting per line
+    - Keep unknown keys so an older build doesn't drop what a newer one saved
+    - Never fail startup, a missing or broken file just means defaults
+    - ex: the sticky note left on the monitor for next time
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SETTINGS_FILE: &str = "rusty-engine.cfg";
+
+pub struct UserSettings {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl UserSettings {
+    // Missing file is the normal first run, anything unreadable is logged and skipped
+    pub fn load(path: &Path) -> Self {
+        let mut values = BTreeMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                for (number, line) in text.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    match line.split_once('=') {
+                        Some((key, value)) => {
+                            values.insert(key.trim().to_string(), value.trim().to_string());
+                        }
+                        None => log::warn!("{}:{}: expected `key = value`, ignoring the line", path.display(), number + 1),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Could not read settings from {}: {}", path.display(), e),
+        }
+        Self { path: path.to_path_buf(), values }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    // Parsed value of a key, None when it is missing or doesn't parse
+    pub fn parse<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        let text = self.get(key)?;
+        let parsed = text.parse().ok();
+        if parsed.is_none() {
+            log::warn!("Ignoring setting {} = {} from {}", key, text, self.path.display());
+        }
+        parsed
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut text = String::from("# Written by rusty-engine, edit while the engine is closed\n");
+        for (key, value) in &self.values {
+            text.push_str(&format!("{} = {}\n", key, value));
+        }
+        std::fs::write(&self.path, text)
+    }
+}
@@ -0,0 +1,171 @@
+/*
+Purpose: Texture coordinates for OBJ meshes that come without any
+Responsibilities:
+    - Define UvFallback, how a mesh without `vt` entries gets its UVs
+    - Project the positions onto one plane for the whole mesh (Planar), or onto the plane
+      facing each triangle's dominant normal axis (Box), scaled so the mesh's largest side
+      spans the texture once
+    - Split vertices shared by triangles projected onto different planes, so each keeps its own UV
+    - ex: gift wrapping a box, the paper is folded over every side instead of stretched
+*/
+
+use std::collections::HashMap;
+
+use cgmath::Vector3;
+
+use crate::{math::Aabb, model::ModelVertex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UvFallback {
+    // Every vertex at (0, 0), the mesh shows the one texel smeared over it
+    None,
+    // Down the axis the mesh is thinnest along
+    Planar,
+    #[default]
+    Box,
+}
+
+impl UvFallback {
+    pub fn label(self) -> &'static str {
+        match self {
+            UvFallback::None => "None",
+            UvFallback::Planar => "Planar",
+            UvFallback::Box => "Box",
+        }
+    }
+}
+
+// The other two axes, as U and V, when projecting down `axis`
+const PLANES: [(usize, usize); 3] = [(2, 1), (0, 2), (0, 1)];
+
+// Fills in tex_coords, Box adds vertices along the seams between planes. UvFallback::None
+// leaves them as they are.
+pub fn generate(mode: UvFallback, vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    let Some(bounds) = Aabb::from_points(vertices.iter().map(|vertex| Vector3::from(vertex.position))) else {
+        return;
+    };
+    let axes = match mode {
+        UvFallback::None => return,
+        UvFallback::Planar => {
+            let size = bounds.size();
+            let thinnest = (0..3).min_by(|a, b| size[*a].total_cmp(&size[*b])).unwrap_or(1);
+            vec![thinnest; vertices.len()]
+        }
+        UvFallback::Box => box_axes(vertices, indices),
+    };
+    let size = bounds.size();
+    let extent = size.x.max(size.y).max(size.z).max(1e-6);
+    for (vertex, axis) in vertices.iter_mut().zip(axes) {
+        let (u, v) = PLANES[axis];
+        let position = Vector3::from(vertex.position) - bounds.min;
+        // V runs top to bottom, on the side planes up in the scene is up in the texture
+        vertex.tex_coords = [position[u] / extent, 1.0 - position[v] / extent];
+    }
+}
+
+// The projection axis of every vertex, after copying the ones claimed by more than one axis
+fn box_axes(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) -> Vec<usize> {
+    let mut axes: Vec<Option<usize>> = vec![None; vertices.len()];
+    let mut copies: HashMap<(u32, usize), u32> = HashMap::new();
+    for triangle in indices.chunks_exact_mut(3) {
+        let [p0, p1, p2] = [0, 1, 2].map(|corner| Vector3::from(vertices[triangle[corner] as usize].position));
+        let normal = (p1 - p0).cross(p2 - p0);
+        let axis = (0..3).max_by(|a, b| normal[*a].abs().total_cmp(&normal[*b].abs())).unwrap_or(1);
+        for index in triangle.iter_mut() {
+            match axes[*index as usize] {
+                None => axes[*index as usize] = Some(axis),
+                Some(claimed) if claimed == axis => {}
+                Some(_) => {
+                    *index = *copies.entry((*index, axis)).or_insert_with(|| {
+                        vertices.push(vertices[*index as usize]);
+                        axes.push(Some(axis));
+                        vertices.len() as u32 - 1
+                    });
+                }
+            }
+        }
+    }
+    // Vertices no triangle uses keep the default plane
+    axes.into_iter().map(|axis| axis.unwrap_or(1)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both halves of the default cube without its `vt` lines, as resources::build_mesh sees them
+    fn cube_without_uvs() -> (Vec<ModelVertex>, Vec<u32>) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/cube-no-uv.obj");
+        let options = tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() };
+        let (meshes, _) = tobj::load_obj(path, &options).unwrap();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for mesh in meshes.into_iter().map(|model| model.mesh) {
+            assert!(mesh.texcoords.is_empty());
+            let base = vertices.len() as u32;
+            vertices.extend(mesh.positions.chunks_exact(3).zip(mesh.normals.chunks_exact(3)).map(|(position, normal)| ModelVertex {
+                position: [position[0], position[1], position[2]],
+                tex_coords: [0.0; 2],
+                normal: [normal[0], normal[1], normal[2]],
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            }));
+            indices.extend(mesh.indices.iter().map(|index| base + index));
+        }
+        (vertices, indices)
+    }
+
+    fn generated(mode: UvFallback) -> (Vec<ModelVertex>, Vec<u32>) {
+        let (mut vertices, mut indices) = cube_without_uvs();
+        generate(mode, &mut vertices, &mut indices);
+        (vertices, indices)
+    }
+
+    #[test]
+    fn projected_uvs_are_finite_and_inside_the_texture() {
+        for mode in [UvFallback::Planar, UvFallback::Box] {
+            let (vertices, indices) = generated(mode);
+            assert!(indices.iter().all(|index| (*index as usize) < vertices.len()), "{:?}", mode);
+            for uv in vertices.iter().flat_map(|vertex| vertex.tex_coords) {
+                assert!(uv.is_finite() && (0.0..=1.0).contains(&uv), "{:?} gave {}", mode, uv);
+            }
+            // The largest side spans the texture once
+            let (low, high) = vertices.iter().flat_map(|vertex| vertex.tex_coords).fold((1.0f32, 0.0f32), |(low, high), uv| (low.min(uv), high.max(uv)));
+            assert!(low < 0.01 && high > 0.99, "{:?} spans {}..{}", mode, low, high);
+        }
+    }
+
+    #[test]
+    fn box_projects_every_triangle_down_its_dominant_axis() {
+        let (plain, plain_indices) = cube_without_uvs();
+        let (vertices, indices) = generated(UvFallback::Box);
+        assert_eq!(indices.len(), plain_indices.len());
+        // Split along the seams, never merged
+        assert!(vertices.len() > plain.len());
+        // The cube is two units across
+        let extent = 2.0;
+        // Every triangle projected down its dominant axis, none squashed as Planar squashes the sides
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| cgmath::Vector2::from(vertices[triangle[corner] as usize].tex_coords));
+            let [p0, p1, p2] = [0, 1, 2].map(|corner| Vector3::from(vertices[triangle[corner] as usize].position));
+            let normal = (p1 - p0).cross(p2 - p0);
+            let dominant = normal.x.abs().max(normal.y.abs()).max(normal.z.abs());
+            let projected = (b - a).perp_dot(c - a).abs() * extent * extent;
+            assert!((projected - dominant).abs() <= dominant * 1e-3 + 1e-6, "{:?}: {} vs {}", triangle, projected, dominant);
+        }
+    }
+
+    #[test]
+    fn none_and_degenerate_meshes_stay_finite() {
+        let (vertices, _) = generated(UvFallback::None);
+        assert!(vertices.iter().all(|vertex| vertex.tex_coords == [0.0; 2]));
+
+        // Every corner in one spot, the extent is zero
+        let (mut vertices, mut indices) = cube_without_uvs();
+        vertices.iter_mut().for_each(|vertex| vertex.position = [1.0, 2.0, 3.0]);
+        for mode in [UvFallback::Planar, UvFallback::Box] {
+            generate(mode, &mut vertices, &mut indices);
+            assert!(vertices.iter().flat_map(|vertex| vertex.tex_coords).all(|uv| uv.is_finite() && (0.0..=1.0).contains(&uv)), "{:?}", mode);
+        }
+    }
+}
@@ -1,59 +1,201 @@
-// /*
-// Purpose: Defines your vertex format
-// Responsibilities:
-//     - Define the Vertex struct (e.g., positon, color, maybe normals)
-//     - Implement Vertex::desc() tells WGPU how to read buffer data
-//     - ex: DNA of an object (what it is made up of)
-// */
-
-// use glam::Vec3;
-
-// // Describe what the vertex should look like
-// #[repr(C)]
-// #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-// pub struct Vertex {
-//     position: [f32; 3],
-//     tex_coords: [f32; 2],
-// }
-
-// // data that will make up a shape
-// pub const VERTICES: &[Vertex] = &[
-//     // Changed
-//     Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.00759614], }, // A
-//     Vertex { position: [-0.49513406, 0.06958647, 0.0], tex_coords: [0.0048659444, 0.43041354], }, // B
-//     Vertex { position: [-0.21918549, -0.44939706, 0.0], tex_coords: [0.28081453, 0.949397], }, // C
-//     Vertex { position: [0.35966998, -0.3473291, 0.0], tex_coords: [0.85967, 0.84732914], }, // D
-//     Vertex { position: [0.44147372, 0.2347359, 0.0], tex_coords: [0.9414737, 0.2652641], }, // E
-// ];
-
-
-// pub const INDICES: &[u16] = &[
-//     0, 1, 4,
-//     1, 2, 4,
-//     2, 3, 4,
-// ];
-// pub const NUM_VERTICES: u32 = VERTICES.len() as u32;
-// pub const NUM_INDICES: u32 = INDICES.len() as u32;
-
-// impl Vertex {
-//     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-//         wgpu::VertexBufferLayout {
-//             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress, // defines how wide a vertex is
-//             step_mode: wgpu::VertexStepMode::Vertex, // tells the pipeline whether each element in this buffer represents per-vertex data or per-instance data
-//             attributes: &[ // describes individual parts of the vertex. usually 1:1 mapping with a struct's fields
-//                 wgpu::VertexAttribute {
-//                     offset: 0, // how many bytes until the next attribute starts
-//                     shader_location: 0, // tells the shader what location to store this attribute at
-//                     format: wgpu::VertexFormat::Float32x3, // the shape of the attribute
-//                 },
-//                 wgpu::VertexAttribute {
-//                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress, // sum of the previous attributes size
-//                     shader_location: 1,
-//                     format: wgpu::VertexFormat::Float32x2,
-//                 }
-//             ]
-//         }
-//     }
-// }
+/*
+Purpose: Defines your vertex format
+Responsibilities:
+    - Define the Vertex struct (e.g., positon, color, maybe normals)
+    - Implement Vertex::desc() tells WGPU how to read buffer data
+    - Compute smooth or faceted normals for procedural geometry
+    - ex: DNA of an object (what it is made up of)
+*/
 
+use cgmath::{InnerSpace, Vector3};
+use crate::{color, gpu_layout::{VertexCheck, rust_layout}};
 
+// Faces meeting at a sharper angle than this keep separate normals in the shape builders
+pub const DEFAULT_SMOOTHING_ANGLE: f32 = 60.0;
+
+// Describe what the vertex should look like
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    // sRGB as the shape builders author it, linear() before it is uploaded
+    pub color: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+}
+
+pub const VERTEX_LAYOUT: VertexCheck = VertexCheck {
+    rust: rust_layout!(Vertex, [position, color, tex_coords, normal]),
+    desc: Vertex::desc,
+};
+
+impl Vertex {
+    // With the color shaders light in, see color.rs
+    pub fn linear(self) -> Self {
+        Self { color: color::srgb_to_linear_rgb(self.color), ..self }
+    }
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress, // defines how wide a vertex is
+            step_mode: wgpu::VertexStepMode::Vertex, // tells the pipeline whether each element in this buffer represents per-vertex data or per-instance data
+            attributes: &[ // describes individual parts of the vertex. usually 1:1 mapping with a struct's fields
+                wgpu::VertexAttribute {
+                    offset: 0, // how many bytes until the next attribute starts
+                    shader_location: 0, // tells the shader what location to store this attribute at
+                    format: wgpu::VertexFormat::Float32x3, // the shape of the attribute
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress, // sum of the previous attributes size
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ]
+        }
+    }
+
+    // Average the face normals of every triangle sharing a vertex (fully smooth shading)
+    pub fn compute_normals(vertices: &mut Vec<Vertex>, indices: &mut [u32]) {
+        Self::compute_normals_with_angle(vertices, indices, 180.0);
+    }
+
+    // Like compute_normals, but faces are only smoothed together when their normals are
+    // within `smoothing_angle_deg` of each other. Where faces disagree the shared vertex is
+    // split: a duplicate is appended to `vertices` and the indices of those faces are rewritten.
+    // 0 degrees gives faceted shading, 180 degrees smooths everything.
+    pub fn compute_normals_with_angle(vertices: &mut Vec<Vertex>, indices: &mut [u32], smoothing_angle_deg: f32) {
+        let cos_threshold = smoothing_angle_deg.clamp(0.0, 180.0).to_radians().cos() - 1e-4;
+
+        // Face normals, zero for degenerate triangles so they don't vote for any direction
+        let face_normals = indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let p0: Vector3<f32> = vertices[tri[0] as usize].position.into();
+                let p1: Vector3<f32> = vertices[tri[1] as usize].position.into();
+                let p2: Vector3<f32> = vertices[tri[2] as usize].position.into();
+                let n = (p1 - p0).cross(p2 - p0);
+                if n.magnitude2() > f32::EPSILON {
+                    n.normalize()
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Which index slots (positions in `indices`) reference each vertex
+        let mut corners = vec![Vec::new(); vertices.len()];
+        for (slot, &index) in indices.iter().enumerate() {
+            corners[index as usize].push(slot);
+        }
+
+        for (vertex_index, slots) in corners.into_iter().enumerate() {
+            if slots.is_empty() {
+                continue;
+            }
+
+            // Group the faces around this vertex so every face in a group is within the
+            // smoothing angle of every other face in that group
+            let mut groups: Vec<Vec<usize>> = Vec::new();
+            for slot in slots {
+                let normal = face_normals[slot / 3];
+                let group = groups.iter_mut().find(|group| {
+                    group.iter().all(|&other| normal.dot(face_normals[other / 3]) >= cos_threshold)
+                });
+                match group {
+                    Some(group) => group.push(slot),
+                    None => groups.push(vec![slot]),
+                }
+            }
+
+            for (group_index, group) in groups.into_iter().enumerate() {
+                let sum = group
+                    .iter()
+                    .fold(Vector3::new(0.0, 0.0, 0.0), |acc, &slot| acc + face_normals[slot / 3]);
+                let normal = if sum.magnitude2() > f32::EPSILON {
+                    sum.normalize().into()
+                } else {
+                    vertices[vertex_index].normal
+                };
+
+                // The first group keeps the original vertex, the rest get a copy
+                let target = if group_index == 0 {
+                    vertex_index
+                } else {
+                    vertices.push(vertices[vertex_index]);
+                    vertices.len() - 1
+                };
+                vertices[target].normal = normal;
+                for slot in group {
+                    indices[slot] = target as u32;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The 8 corners of a unit cube, each shared by the 3 faces meeting there
+    fn shared_cube() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = (0..8)
+            .map(|corner| Vertex {
+                position: [(corner & 1) as f32 - 0.5, ((corner >> 1) & 1) as f32 - 0.5, ((corner >> 2) & 1) as f32 - 0.5],
+                color: [1.0; 3],
+                tex_coords: [0.0; 2],
+                normal: [0.0; 3],
+            })
+            .collect();
+        let indices = vec![
+            0, 2, 3, 0, 3, 1, // -Z
+            4, 5, 7, 4, 7, 6, // +Z
+            0, 4, 6, 0, 6, 2, // -X
+            1, 3, 7, 1, 7, 5, // +X
+            0, 1, 5, 0, 5, 4, // -Y
+            2, 6, 7, 2, 7, 3, // +Y
+        ];
+        (vertices, indices)
+    }
+
+    #[test]
+    fn faceted_cube_splits_every_corner() {
+        let (mut vertices, mut indices) = shared_cube();
+        Vertex::compute_normals_with_angle(&mut vertices, &mut indices, 0.0);
+        assert_eq!(vertices.len(), 24);
+        // Every corner of a face points straight out of it
+        for tri in indices.chunks_exact(3) {
+            let normals: Vec<_> = tri.iter().map(|&index| vertices[index as usize].normal).collect();
+            assert!(normals.iter().all(|normal| *normal == normals[0]), "{:?}", normals);
+            assert_eq!(normals[0].iter().map(|n| n.abs()).sum::<f32>(), 1.0, "{:?}", normals[0]);
+        }
+    }
+
+    #[test]
+    fn smoothed_cube_keeps_its_corners() {
+        let (mut vertices, mut indices) = shared_cube();
+        Vertex::compute_normals(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 8);
+        let normal = Vector3::from(vertices[7].normal);
+        assert!((normal - Vector3::new(1.0, 1.0, 1.0).normalize()).magnitude() < 1e-5, "{:?}", normal);
+    }
+
+    #[test]
+    fn smoothed_sphere_has_no_splits() {
+        let (mut vertices, mut indices) = crate::shapes::create_sphere(1.0, 16, 8);
+        let count = vertices.len();
+        assert_eq!(count, 17 * 9);
+        Vertex::compute_normals_with_angle(&mut vertices, &mut indices, 180.0);
+        assert_eq!(vertices.len(), count);
+    }
+}
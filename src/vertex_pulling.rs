@@ -0,0 +1,302 @@
+/*
+Purpose: Draw model meshes with the shader fetching their vertices, instead of through vertex buffers
+Responsibilities:
+    - Describe where each attribute sits in a mesh's vertices (VertexLayout), the ones a mesh
+      doesn't have are filled with defaults by pull.wgsl
+    - Build the pulling pipeline: shader.wgsl with its vertex inputs read by pull.wgsl from the
+      mesh's vertex buffer bound as a storage buffer, one pipeline whatever the meshes' layouts
+    - Hand shape_renderer.rs the same group for its pulled shape pipeline, shape.wgsl on pull.wgsl
+    - Give each mesh a bind group with its vertices and its layout, made on its first pulled draw
+    - Tell whether the adapter can read storage buffers in the vertex stage, without that
+      everything stays on the vertex buffer path
+    - ex: the warehouse picker with a slip saying which shelf each part is on, any rack will do
+*/
+
+use std::ops::Range;
+
+use crate::{gpu_debug::debug_label, gpu_layout::{UniformCheck, rust_layout}, gpu_memory::{self, Tracked}, instance::InstanceRaw, instance_cull, material_array, model::{DrawModel, Mesh, Model, Vertex}, render_context::create_render_pipeline, texture};
+
+// Group 3 of the pulling pipeline, bindings 0-3 of it are shader.wgsl's toon and probe uniforms
+const VERTICES_BINDING: u32 = 4;
+const LAYOUT_BINDING: u32 = 5;
+// An attribute the mesh doesn't have, must match MISSING in pull.wgsl
+const MISSING: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexAttribute {
+    Position,
+    TexCoords,
+    Normal,
+    Tangent,
+    Bitangent,
+    Color,
+}
+
+// A mesh's vertices as plain floats: how far apart they are and where each attribute starts.
+// A new attribute is a variant above, a field in LayoutUniform and a fetch in pull.wgsl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexLayout {
+    pub stride: u32,
+    // By VertexAttribute, None where pull.wgsl reads the default
+    pub offsets: [Option<u32>; 6],
+}
+
+impl VertexLayout {
+    // Must match ModelVertex, which has everything but a color
+    pub const MODEL: VertexLayout = VertexLayout { stride: 14, offsets: [Some(0), Some(3), Some(5), Some(8), Some(11), None] };
+    // Must match vertex::Vertex, colored and without tangents
+    pub const SHAPE: VertexLayout = VertexLayout { stride: 11, offsets: [Some(0), Some(6), Some(8), None, None, Some(3)] };
+
+    pub fn offset(&self, attribute: VertexAttribute) -> Option<u32> {
+        self.offsets[attribute as usize]
+    }
+
+    fn uniform(&self) -> LayoutUniform {
+        let offset = |attribute| self.offset(attribute).unwrap_or(MISSING);
+        LayoutUniform {
+            stride: self.stride,
+            position: offset(VertexAttribute::Position),
+            tex_coords: offset(VertexAttribute::TexCoords),
+            normal: offset(VertexAttribute::Normal),
+            tangent: offset(VertexAttribute::Tangent),
+            bitangent: offset(VertexAttribute::Bitangent),
+            color: offset(VertexAttribute::Color),
+            _padding: 0,
+        }
+    }
+}
+
+// Must match MeshLayout in pull.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayoutUniform {
+    stride: u32,
+    position: u32,
+    tex_coords: u32,
+    normal: u32,
+    tangent: u32,
+    bitangent: u32,
+    color: u32,
+    _padding: u32,
+}
+
+pub const LAYOUT_UNIFORM_LAYOUT: UniformCheck = UniformCheck {
+    rust: rust_layout!(LayoutUniform, [stride, position, tex_coords, normal, tangent, bitangent, color]),
+    wgsl: &[("pull.wgsl", "MeshLayout")],
+};
+
+// What the stats panel and the report say draws the models
+pub fn path_label(enabled: bool, supported: bool) -> &'static str {
+    match (enabled, supported) {
+        (true, true) => "vertex pulling (storage buffers)",
+        (true, false) => "classic (vertex buffers), no vertex pulling on this adapter",
+        (false, _) => "classic (vertex buffers)",
+    }
+}
+
+// `classic` with its vs_main stepped aside for `pulled`'s on top of pull.wgsl, both end in transform()
+fn pulled_source(classic: &str, pulled: &str) -> String {
+    classic.replace("@vertex\nfn vs_main(", "fn vs_classic(") + include_str!("pull.wgsl") + pulled
+}
+
+// The model pipeline's shader
+pub fn shader_source() -> String {
+    pulled_source(include_str!("shader.wgsl"), include_str!("pull_model.wgsl"))
+}
+
+// The shape pipeline's shader, see ShapePipeline
+pub fn shape_shader_source() -> String {
+    pulled_source(include_str!("shape.wgsl"), include_str!("pull_shape.wgsl"))
+}
+
+// Storage buffers in the vertex stage, which WebGL and some GLES drivers don't have
+pub fn supported(adapter: &wgpu::Adapter) -> bool {
+    adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+        && adapter.limits().max_storage_buffers_per_shader_stage > 0
+}
+
+// A mesh's group 3, kept on the Mesh or the shape's GpuMesh
+pub struct PulledMesh {
+    _layout: Tracked<wgpu::Buffer>,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl PulledMesh {
+    // `bind_group_layout` is VertexPullingPipeline's, `vertices` laid out as `layout` says
+    pub fn new(device: &wgpu::Device, bind_group_layout: &wgpu::BindGroupLayout, name: &str, vertices: &wgpu::Buffer, layout: VertexLayout) -> Self {
+        let layout = gpu_memory::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{} Vertex Layout Buffer", name).as_deref(),
+            contents: bytemuck::bytes_of(&layout.uniform()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: VERTICES_BINDING, resource: vertices.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: LAYOUT_BINDING, resource: layout.as_entire_binding() },
+            ],
+            label: debug_label!("{} Vertex Pulling Bind Group", name).as_deref(),
+        });
+        Self { _layout: layout, bind_group }
+    }
+}
+
+// Made with STORAGE usage, within the adapter's storage binding size
+pub fn can_pull(device: &wgpu::Device, vertices: &wgpu::Buffer) -> bool {
+    vertices.usage().contains(wgpu::BufferUsages::STORAGE) && vertices.size() <= device.limits().max_storage_buffer_binding_size as u64
+}
+
+// Shared between windows, lives in the RenderContext when the adapter supports it
+pub struct VertexPullingPipeline {
+    // Group 3 of both pulling pipelines
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    // render_pipeline with the vertex inputs read by pull.wgsl, instances in vertex buffer slot 0
+    pub pipeline: wgpu::RenderPipeline,
+}
+
+impl VertexPullingPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        [texture_layout, camera_layout, light_layout]: [&wgpu::BindGroupLayout; 3],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: VERTICES_BINDING,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: LAYOUT_BINDING,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<LayoutUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Vertex Pulling Bind Group Layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Vertex Pulling Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, camera_layout, light_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Vertex Pulling Shader"),
+            source: wgpu::ShaderSource::Wgsl(shader_source().into()),
+        };
+        let pipeline = create_render_pipeline(
+            device,
+            &layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[InstanceRaw::desc()],
+            sample_count,
+            shader,
+        );
+        Self { bind_group_layout, pipeline }
+    }
+
+    fn mesh_bind_group<'a>(&self, device: &wgpu::Device, mesh: &'a Mesh) -> &'a wgpu::BindGroup {
+        let pulled = mesh.pulled.get_or_init(|| PulledMesh::new(device, &self.bind_group_layout, &mesh.name, &mesh.vertex_buffer, mesh.layout));
+        &pulled.bind_group
+    }
+}
+
+pub trait DrawPulled<'a> {
+    // Like draw_model_instanced, or draw_model_indirect with `indirect`, through the pulling
+    // pipeline and back to `fallback` after. A model with a mesh that can't be pulled is drawn
+    // the classic way, with `fallback` and the instances in slot 1 as the caller set them.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_model_pulled(
+        &mut self,
+        model: &'a Model,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+        indirect: Option<&'a wgpu::Buffer>,
+        pipeline: &'a VertexPullingPipeline,
+        device: &wgpu::Device,
+        fallback: &'a wgpu::RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'b> DrawPulled<'b> for wgpu::RenderPass<'_> {
+    fn draw_model_pulled(
+        &mut self,
+        model: &'b Model,
+        instance_buffer: &'b wgpu::Buffer,
+        instances: Range<u32>,
+        indirect: Option<&'b wgpu::Buffer>,
+        pipeline: &'b VertexPullingPipeline,
+        device: &wgpu::Device,
+        fallback: &'b wgpu::RenderPipeline,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        if !model.meshes.iter().all(|mesh| can_pull(device, &mesh.vertex_buffer)) {
+            match indirect {
+                Some(indirect) => self.draw_model_indirect(model, indirect, camera_bind_group, light_bind_group),
+                None => self.draw_model_instanced(model, instances, camera_bind_group, light_bind_group),
+            }
+            return;
+        }
+        self.set_pipeline(&pipeline.pipeline);
+        self.set_vertex_buffer(0, instance_buffer.slice(..));
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        for (index, mesh) in model.meshes.iter().enumerate().filter(|(_, mesh)| mesh.is_visible()) {
+            self.set_bind_group(0, &model.materials[mesh.material].bind_group(), &[]);
+            material_array::count_material_bind();
+            self.set_bind_group(3, pipeline.mesh_bind_group(device, mesh), &[]);
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            match indirect {
+                Some(indirect) => self.draw_indexed_indirect(indirect, instance_cull::indirect_offset(index)),
+                None => self.draw_indexed(0..mesh.num_elements, 0, instances.clone()),
+            }
+        }
+        self.set_pipeline(fallback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::ModelVertex, vertex::Vertex as ShapeVertex};
+    use std::mem::{offset_of, size_of};
+
+    fn floats(bytes: usize) -> u32 {
+        (bytes / size_of::<f32>()) as u32
+    }
+
+    #[test]
+    fn layouts_match_the_vertex_structs() {
+        let model = VertexLayout::MODEL;
+        assert_eq!(model.stride, floats(size_of::<ModelVertex>()));
+        assert_eq!(model.offset(VertexAttribute::Position), Some(floats(offset_of!(ModelVertex, position))));
+        assert_eq!(model.offset(VertexAttribute::TexCoords), Some(floats(offset_of!(ModelVertex, tex_coords))));
+        assert_eq!(model.offset(VertexAttribute::Normal), Some(floats(offset_of!(ModelVertex, normal))));
+        assert_eq!(model.offset(VertexAttribute::Tangent), Some(floats(offset_of!(ModelVertex, tangent))));
+        assert_eq!(model.offset(VertexAttribute::Bitangent), Some(floats(offset_of!(ModelVertex, bitangent))));
+        assert_eq!(model.offset(VertexAttribute::Color), None);
+
+        let shape = VertexLayout::SHAPE;
+        assert_eq!(shape.stride, floats(size_of::<ShapeVertex>()));
+        assert_eq!(shape.offset(VertexAttribute::Position), Some(floats(offset_of!(ShapeVertex, position))));
+        assert_eq!(shape.offset(VertexAttribute::TexCoords), Some(floats(offset_of!(ShapeVertex, tex_coords))));
+        assert_eq!(shape.offset(VertexAttribute::Normal), Some(floats(offset_of!(ShapeVertex, normal))));
+        assert_eq!(shape.offset(VertexAttribute::Color), Some(floats(offset_of!(ShapeVertex, color))));
+        assert_eq!(shape.offset(VertexAttribute::Tangent), None);
+    }
+}
@@ -0,0 +1,341 @@
+/*
+Purpose: Record the scene to a video, one frame per fixed step of the simulation
+Responsibilities:
+    - Hold what an export renders: the frame rate, a resolution of its own, and how long it runs,
+      a number of seconds or a camera path of eye and target keys
+    - Pipe the frames as raw RGBA into an ffmpeg process that encodes the file, or write them as a
+      numbered PNG sequence when the path asks for PNGs or ffmpeg isn't installed
+    - Hand out the simulation step once per written frame, so frame n is always the scene n / fps
+      seconds in however long encoding takes
+    - Clean up after a cancel or a failure: ffmpeg is killed and its half-written file removed
+    - ex: stop-motion, move the puppets one notch, take a picture, repeat
+*/
+
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use cgmath::Point3;
+
+use crate::light_anim::Track;
+
+pub const DEFAULT_FPS: u32 = 30;
+pub const DEFAULT_SIZE: (u32, u32) = (1280, 720);
+pub const DEFAULT_SECONDS: f32 = 5.0;
+pub const DEFAULT_PATH: &str = "export.mp4";
+pub const FPS_RANGE: RangeInclusive<u32> = 1..=240;
+pub const MAX_SIZE: u32 = 8192;
+// Players expect 4:2:0 in these, ffmpeg would keep the RGBA input's full chroma otherwise
+const YUV420_CONTAINERS: [&str; 4] = ["mp4", "mov", "m4v", "mkv"];
+
+// Where the camera is and what it looks at, moving in straight lines between keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraPath {
+    pub track: Track<(Point3<f32>, Point3<f32>)>,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self { track: Track { keys: Vec::new(), looping: false } }
+    }
+}
+
+impl CameraPath {
+    // `spacing` seconds after the last key, the first key at 0
+    pub fn push_key(&mut self, eye: Point3<f32>, target: Point3<f32>, spacing: f32) {
+        let time = self.track.keys.last().map_or(0.0, |(time, _)| time + spacing.max(0.0));
+        self.track.keys.push((time, (eye, target)));
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.track.keys.len()
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.track.duration()
+    }
+
+    // Eye and target, None without keys
+    pub fn pose(&self, time: f32) -> Option<(Point3<f32>, Point3<f32>)> {
+        let lerp = |a: Point3<f32>, b: Point3<f32>, t: f32| a + (b - a) * t;
+        self.track.sample(time, |(eye_a, target_a), (eye_b, target_b), t| (lerp(eye_a, eye_b, t), lerp(target_a, target_b, t)))
+    }
+}
+
+// How long an export runs
+#[derive(Debug, Clone)]
+pub enum ExportSpan {
+    // From wherever the camera is, it stays there
+    Seconds(f32),
+    // Until the path's last key, which is the last frame
+    CameraPath(CameraPath),
+}
+
+impl ExportSpan {
+    fn frame_count(&self, fps: u32) -> anyhow::Result<u32> {
+        match self {
+            Self::Seconds(seconds) if seconds.is_finite() && *seconds > 0.0 => Ok(((seconds * fps as f32).round() as u32).max(1)),
+            Self::Seconds(seconds) => anyhow::bail!("the length has to be more than 0 seconds, not {}", seconds),
+            Self::CameraPath(path) if path.key_count() < 2 || path.duration() <= 0.0 => anyhow::bail!("the camera path needs at least two keys at different times"),
+            Self::CameraPath(path) => Ok((path.duration() * fps as f32).round() as u32 + 1),
+        }
+    }
+}
+
+// ffmpeg on the PATH, asked once when an export starts
+pub fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+// `clips/demo.mp4` numbers its frames `clips/demo_00000.png` and on
+pub fn frame_path(path: &Path, index: u32) -> PathBuf {
+    let stem = path.file_stem().map_or("frame".into(), |stem| stem.to_string_lossy());
+    path.with_file_name(format!("{}_{:05}.png", stem, index))
+}
+
+enum Sink {
+    Ffmpeg { child: Child, stdin: ChildStdin },
+    Png,
+}
+
+pub struct VideoExport {
+    path: PathBuf,
+    fps: u32,
+    size: (u32, u32),
+    span: ExportSpan,
+    sink: Sink,
+    frame: u32,
+    frames: u32,
+    // Set by each written frame, taken by the next update
+    step_due: bool,
+    // Set from the progress modal, acted on before the next frame is rendered
+    pub cancel_requested: bool,
+}
+
+impl VideoExport {
+    // Spawns ffmpeg unless the path is a PNG or ffmpeg isn't there, see is_ffmpeg
+    pub fn start(path: &Path, fps: u32, span: ExportSpan, (width, height): (u32, u32)) -> anyhow::Result<Self> {
+        if path.as_os_str().is_empty() {
+            anyhow::bail!("no output path");
+        }
+        if !FPS_RANGE.contains(&fps) {
+            anyhow::bail!("the frame rate has to be {} to {}, not {}", FPS_RANGE.start(), FPS_RANGE.end(), fps);
+        }
+        if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+            anyhow::bail!("the resolution has to be 1 to {} on each side, not {}x{}", MAX_SIZE, width, height);
+        }
+        let frames = span.frame_count(fps)?;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let (sink, size) = if is_png(path) || !ffmpeg_available() {
+            (Sink::Png, (width, height))
+        } else {
+            // 4:2:0 halves the chroma both ways, so the sides have to be even
+            let size = ((width & !1).max(2), (height & !1).max(2));
+            let mut command = Command::new("ffmpeg");
+            command
+                .args(["-y", "-hide_banner", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgba"])
+                .args(["-s", &format!("{}x{}", size.0, size.1), "-framerate", &fps.to_string(), "-i", "-"]);
+            let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+            if extension.is_some_and(|extension| YUV420_CONTAINERS.contains(&extension.as_str())) {
+                command.args(["-pix_fmt", "yuv420p"]);
+            }
+            let mut child = command
+                .arg(path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("could not start ffmpeg: {}", e))?;
+            let stdin = child.stdin.take().expect("ffmpeg was spawned with a piped stdin");
+            (Sink::Ffmpeg { child, stdin }, size)
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            fps,
+            size,
+            span,
+            sink,
+            frame: 0,
+            frames,
+            step_due: false,
+            cancel_requested: false,
+        })
+    }
+
+    // False when the frames go to PNGs
+    pub fn is_ffmpeg(&self) -> bool {
+        matches!(self.sink, Sink::Ffmpeg { .. })
+    }
+
+    // What the frames are rendered at, rounded down to even sides for ffmpeg
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn step(&self) -> f32 {
+        1.0 / self.fps as f32
+    }
+
+    // The simulation step for this update, None until the last frame has been written
+    pub fn take_step(&mut self) -> Option<f32> {
+        std::mem::take(&mut self.step_due).then(|| self.step())
+    }
+
+    // Frames written and frames in all
+    pub fn progress(&self) -> (u32, u32) {
+        (self.frame, self.frames)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frame >= self.frames
+    }
+
+    // Where the camera path puts the camera for the next frame, None for a span in seconds
+    pub fn camera_pose(&self) -> Option<(Point3<f32>, Point3<f32>)> {
+        match &self.span {
+            ExportSpan::Seconds(_) => None,
+            ExportSpan::CameraPath(path) => path.pose(self.frame as f32 / self.fps as f32),
+        }
+    }
+
+    // The file, or the first frame of the sequence
+    pub fn output(&self) -> PathBuf {
+        match self.sink {
+            Sink::Ffmpeg { .. } => self.path.clone(),
+            Sink::Png => frame_path(&self.path, 0),
+        }
+    }
+
+    pub fn write_frame(&mut self, frame: &image::RgbaImage) -> anyhow::Result<()> {
+        match &mut self.sink {
+            // A stdin that won't take the frame means ffmpeg quit, finish has its error message
+            Sink::Ffmpeg { stdin, .. } => stdin.write_all(frame.as_raw()).map_err(|e| anyhow::anyhow!("ffmpeg stopped taking frames: {}", e))?,
+            Sink::Png => {
+                let path = frame_path(&self.path, self.frame);
+                frame.save(&path).map_err(|e| anyhow::anyhow!("could not save {}: {}", path.display(), e))?;
+            }
+        }
+        self.frame += 1;
+        self.step_due = true;
+        Ok(())
+    }
+
+    // Closes ffmpeg's input and waits for it to write the file. Returns what was saved.
+    pub fn finish(self) -> anyhow::Result<String> {
+        match self.sink {
+            Sink::Ffmpeg { child, stdin } => {
+                drop(stdin);
+                let output = child.wait_with_output()?;
+                if !output.status.success() {
+                    let _ = std::fs::remove_file(&self.path);
+                    anyhow::bail!("ffmpeg failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim());
+                }
+                Ok(format!("Saved {} frames to {}", self.frame, self.path.display()))
+            }
+            Sink::Png => Ok(format!("Saved {} frames as {}", self.frame, frame_path(&self.path, 0).display())),
+        }
+    }
+
+    // PNGs already written stay, a video that was never finished is deleted. Returns what ffmpeg
+    // complained about, which explains a frame it wouldn't take.
+    pub fn cancel(self) -> String {
+        let Sink::Ffmpeg { mut child, stdin } = self.sink else {
+            return String::new();
+        };
+        drop(stdin);
+        let _ = child.kill();
+        let stderr = child.wait_with_output().map(|output| String::from_utf8_lossy(&output.stderr).trim().to_string());
+        let _ = std::fs::remove_file(&self.path);
+        stderr.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> CameraPath {
+        let mut path = CameraPath::default();
+        path.push_key(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0), 1.0);
+        path.push_key(Point3::new(10.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0), 0.5);
+        path.push_key(Point3::new(10.0, 4.0, 0.0), Point3::new(0.0, 2.0, 0.0), 0.5);
+        path
+    }
+
+    #[test]
+    fn spans_count_their_frames() {
+        assert_eq!(ExportSpan::Seconds(2.0).frame_count(30).unwrap(), 60);
+        // Rounded, and never less than one
+        assert_eq!(ExportSpan::Seconds(0.51).frame_count(10).unwrap(), 5);
+        assert_eq!(ExportSpan::Seconds(0.01).frame_count(10).unwrap(), 1);
+        assert!(ExportSpan::Seconds(0.0).frame_count(30).is_err());
+        assert!(ExportSpan::Seconds(f32::NAN).frame_count(30).is_err());
+        // Both ends of a path are frames
+        assert_eq!(ExportSpan::CameraPath(path()).frame_count(4).unwrap(), 5);
+        let mut single = CameraPath::default();
+        single.push_key(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0), 1.0);
+        assert!(ExportSpan::CameraPath(single.clone()).frame_count(30).is_err());
+        single.push_key(Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(ExportSpan::CameraPath(single).frame_count(30).is_err());
+    }
+
+    #[test]
+    fn each_written_frame_steps_once_along_the_path() {
+        let dir = std::env::temp_dir().join(format!("rusty-engine-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let file = dir.join("clip.png");
+        let mut export = VideoExport::start(&file, 4, ExportSpan::CameraPath(path()), (3, 5)).unwrap();
+        // PNGs keep odd sides
+        assert!(!export.is_ffmpeg());
+        assert_eq!(export.size(), (3, 5));
+        assert_eq!(export.progress(), (0, 5));
+        assert_eq!(export.output(), dir.join("clip_00000.png"));
+
+        // Nothing to step before the first frame is out
+        assert_eq!(export.take_step(), None);
+        let frame = image::RgbaImage::from_pixel(3, 5, image::Rgba([10, 20, 30, 255]));
+        let mut eyes = Vec::new();
+        while !export.is_done() {
+            eyes.push(export.camera_pose().unwrap().0);
+            export.write_frame(&frame).unwrap();
+            // One step of 1 / fps per frame, taken once however many updates ask
+            assert_eq!(export.take_step(), Some(0.25));
+            assert_eq!(export.take_step(), None);
+        }
+        // Frame n is n / fps along the path, the last one on its last key
+        let expected = [(0.0, 0.0, 10.0), (5.0, 0.0, 10.0), (10.0, 0.0, 10.0), (10.0, 2.0, 5.0), (10.0, 4.0, 0.0)];
+        assert_eq!(eyes, expected.map(Point3::from).to_vec());
+
+        assert_eq!(export.progress(), (5, 5));
+        assert_eq!(export.finish().unwrap(), format!("Saved 5 frames as {}", dir.join("clip_00000.png").display()));
+        for index in 0..5 {
+            assert_eq!(image::open(frame_path(&file, index)).unwrap().to_rgba8(), frame);
+        }
+        assert!(!frame_path(&file, 5).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bad_settings_are_refused_before_anything_is_written() {
+        let file = std::env::temp_dir().join(format!("rusty-engine-refused-{}", std::process::id())).join("clip.png");
+        assert!(VideoExport::start(&file, 0, ExportSpan::Seconds(1.0), (64, 64)).is_err());
+        assert!(VideoExport::start(&file, 30, ExportSpan::Seconds(1.0), (0, 64)).is_err());
+        assert!(VideoExport::start(&file, 30, ExportSpan::Seconds(1.0), (64, MAX_SIZE + 1)).is_err());
+        assert!(VideoExport::start(&file, 30, ExportSpan::Seconds(-1.0), (64, 64)).is_err());
+        assert!(VideoExport::start(Path::new(""), 30, ExportSpan::Seconds(1.0), (64, 64)).is_err());
+        assert!(!file.parent().unwrap().exists());
+    }
+}
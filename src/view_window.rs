@@ -0,0 +1,927 @@
+/*
+Purpose: Everything that belongs to a single OS window
+Responsibilities:
+    - Own the surface, surface config, and depth texture for one window
+    - Own that window's camera, controller, and egui renderer
+    - Handle resizing and per-window input independently of other windows
+    - ex: a pane of glass looking into the shared scene
+*/
+
+use crate::{gpu_debug::debug_label, camera::{Camera, Camera2D, CameraFlight, CameraFollow, CameraUniform, Controller, Projection}, camera_controller::{self, CameraController, ControllerProfile, ControllerTunables}, depth_debug::DepthDebugBindings, diagnostics::SurfaceDiagnostics, edge_outline::{OutlineSettings, OutlineTargets}, hiz::HiZTargets, frame_graph::{FrameGraph, TransientStats, Transients}, frame_pacer::{self, FramePacer}, gizmo::{self, CameraSnap, GizmoRect, ViewGizmo}, gpu_memory::{self, Tracked}, gpu_timer::GpuTimer, input_map::Action, math::{Frustum, Ray}, picking::{PickDraw, PickResult, PickTargets}, hdr::{HdrSettings, HdrTargets}, motion_blur::{MotionBlurSettings, MotionBlurTargets, MotionBlurTransients, Reprojection}, particles::ParticleViewBindings, quad_2d::{QuadBatcher, ViewQuads}, render_context::RenderContext, ssao::{SsaoSettings, SsaoTargets, SsaoTransients}, stereo::{AnaglyphTargets, AnaglyphTransients, Eye, EyeCameras, StereoMode, StereoSettings}, taa::{self, TaaSettings, TaaTargets, TaaTransients}, texture, title_bar::TITLE_BAR_HEIGHT, ui_theme::{self, EngineTheme}, units::SceneUnits};
+use cgmath::SquareMatrix;
+use std::sync::Arc;
+use winit::{event::{MouseButton, MouseScrollDelta, WindowEvent}, window::Window};
+use egui::Context;
+use egui_wgpu::wgpu::{CommandEncoder, StoreOp, TextureView};
+use egui_wgpu::{Renderer, ScreenDescriptor};
+use egui_winit::State as EguiState;
+
+// How far (physical pixels) the cursor may move between press and release for a click
+const CLICK_SLOP: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewKind {
+    // The main window, closing it exits the app
+    Primary,
+    // A secondary window (asset inspector) showing an alternate view
+    Inspector,
+}
+
+pub struct ViewWindow {
+    pub kind: ViewKind,
+    // Created without OS decorations, the title bar is drawn with egui (title_bar.rs)
+    pub custom_title_bar: bool,
+    // Holds frames back to the active FPS cap
+    pub pacer: FramePacer,
+    pub window: Arc<Window>,
+    surface: wgpu::Surface<'static>, // The surface (connection between window & GPU)
+    pub config: wgpu::SurfaceConfiguration, // How the surface is configured (size, format, etc.)
+    // What the surface supports on this adapter, kept for the diagnostics report
+    surface_caps: wgpu::SurfaceCapabilities,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    is_surface_configured: bool,
+    pub depth_texture: texture::Texture,
+    // The depth texture and camera basis as the particle shader reads them
+    particle_bindings: ParticleViewBindings,
+    // The depth texture as the depth thumbnail and capture read it
+    depth_debug_bindings: DepthDebugBindings,
+    // Multisampled color target, only present when MSAA is enabled
+    msaa_texture: Option<Tracked<wgpu::TextureView>>,
+    // The offscreen passes' textures, shared where their lifetimes allow. Rebuilt on resize and
+    // when a pass is turned on or off, see prepare_transients.
+    transients: Option<Transients>,
+    // SSAO, motion blur, TAA and anaglyph stereo, what `transients` was built for
+    transient_passes: (bool, bool, bool, bool),
+    // Present while SSAO is on in this window
+    ssao_targets: Option<SsaoTargets>,
+    // Scene target and exposure state, only present when HDR is on
+    hdr_targets: Option<HdrTargets>,
+    // Present while motion blur or TAA is on in this window, both read its velocity
+    motion_blur_targets: Option<MotionBlurTargets>,
+    // Present while TAA is on in this window
+    taa_targets: Option<TaaTargets>,
+    // Present while stereo is on in this window, see stereo.rs
+    eye_cameras: Option<EyeCameras>,
+    // Present while anaglyph stereo is on in this window
+    anaglyph_targets: Option<AnaglyphTargets>,
+    // GPU time of this window's scene passes, None without timestamp query support
+    gpu_timer: Option<GpuTimer>,
+    // ID target and readback for clicking on instances, created on the first pick
+    pick_targets: Option<PickTargets>,
+    // Present while the screen-space outline is on in this window, it shares pick_targets' IDs
+    outline_targets: Option<OutlineTargets>,
+    // Present while occlusion culling is on in this window, built from depth_texture
+    hiz_targets: Option<HiZTargets>,
+    pub camera: Camera,
+    pub projection: Projection,
+    // Pixels of this window for the 2D pass, follows its size
+    pub camera_2d: Camera2D,
+    quads: ViewQuads,
+    // The control scheme State picked, see set_controller_profile
+    pub controller: Box<dyn CameraController>,
+    controller_tunables: ControllerTunables,
+    // Scene units per meter, for the schemes' tunables
+    units_per_meter: f32,
+    camera_uniform: CameraUniform,
+    camera_buffer: Tracked<wgpu::Buffer>,
+    pub camera_bind_group: wgpu::BindGroup,
+    pub mouse_pressed: bool,
+    // Right-dragging also turns the camera, and the scroll wheel then sets the fly speed
+    right_pressed: bool,
+    // Set by the app while the cursor is grabbed (L), turning the camera then hides the cursor
+    pub cursor_grabbed: bool,
+    // Last known cursor position in physical pixels
+    cursor_position: Option<(f32, f32)>,
+    // Where the left button went down, a release close by counts as a click
+    press_position: Option<(f32, f32)>,
+    // The same for the right button
+    right_press_position: Option<(f32, f32)>,
+    gizmo: ViewGizmo,
+    // Set while the camera swings to a face clicked on the gizmo
+    camera_snap: Option<CameraSnap>,
+    // Set while the camera moves to frame something, see State::frame_model
+    camera_flight: Option<CameraFlight>,
+    // Set while the camera follows an instance, see State::set_camera_follow
+    camera_follow: Option<CameraFollow>,
+    last_frame: std::time::Instant,
+    scale_factor: f32,
+    egui_state: EguiState,
+    egui_renderer: Renderer,
+    egui_frame_started: bool,
+    // When egui wants to be drawn again (animations, tooltips), None while it is idle
+    egui_repaint_at: Option<std::time::Instant>,
+    // Last theme pushed into this window's egui context, reapplied only when it changes
+    applied_theme: Option<EngineTheme>,
+}
+
+impl ViewWindow {
+    pub fn new(
+        context: &RenderContext,
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        kind: ViewKind,
+        camera: Camera,
+    ) -> Self {
+        let size = window.inner_size();
+
+        // Configure the surface with width, height, format, and presentation mode
+        let surface_caps = surface.get_capabilities(&context.adapter);
+        if !surface_caps.formats.contains(&context.surface_format) {
+            log::warn!("Surface does not list {:?} as supported, configuring it anyway", context.surface_format);
+        }
+        // Copying out of the frame is only needed for screenshots, and not every surface allows it
+        let mut usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        if surface_caps.usages.contains(wgpu::TextureUsages::COPY_SRC) {
+            usage |= wgpu::TextureUsages::COPY_SRC;
+        }
+        let config = wgpu::SurfaceConfiguration {
+            usage,
+            format: context.surface_format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: context.settings.present_mode(),
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        surface.configure(&context.device, &config);
+
+        let egui_state = egui_winit::State::new(
+            Context::default(),
+            egui::viewport::ViewportId::ROOT,
+            &window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(2 * 1024), // default dimension is 2048
+        );
+        ui_theme::install_custom_font(egui_state.egui_ctx());
+        let egui_renderer = Renderer::new(
+            &context.device,
+            config.format,
+            None,
+            1,
+            true,
+        );
+
+        // Setup Camera uniform buffer and bind group
+        // State::render applies the configured units every frame
+        let units = SceneUnits::default();
+        let (znear, zfar) = units.depth_range();
+        let projection = Projection::new(config.width, config.height, cgmath::Deg(45.0), znear, zfar);
+        let camera_2d = Camera2D::new(config.width, config.height);
+        let controller_tunables = ControllerTunables::default();
+        let controller = camera_controller::new_controller(
+            ControllerProfile::FreeFly,
+            Controller::new(units.camera_speed(), 1.0),
+            &camera,
+            &controller_tunables,
+            units.units_per_meter(),
+        );
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, &projection);
+
+        let camera_buffer = context.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: debug_label!("{:?} Camera Buffer", kind).as_deref(),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &context.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: debug_label!("{:?} Camera Bind Group", kind).as_deref(),
+        });
+
+        let sample_count = context.settings.msaa_samples;
+        let depth_texture = texture::Texture::create_depth_texture(&context.device, &config, sample_count, "depth_texture");
+        let msaa_texture = (sample_count > 1)
+            .then(|| texture::Texture::create_msaa_texture(&context.device, &config, context.scene_format, sample_count));
+        let particle_bindings = ParticleViewBindings::new(&context.device, &context.particle_pipeline, &depth_texture.texture);
+        let depth_debug_bindings = DepthDebugBindings::new(&context.device, &context.depth_debug, &depth_texture.texture);
+        let hdr_targets = context
+            .hdr
+            .as_ref()
+            .map(|pipelines| HdrTargets::new(&context.device, pipelines, config.width, config.height));
+
+        Self {
+            kind,
+            custom_title_bar: false,
+            pacer: FramePacer::new(frame_pacer::monitor_refresh_rate(&window)),
+            window,
+            surface,
+            config,
+            surface_caps,
+            size,
+            is_surface_configured: false,
+            depth_texture,
+            particle_bindings,
+            depth_debug_bindings,
+            msaa_texture,
+            transients: None,
+            transient_passes: (false, false, false, false),
+            ssao_targets: None,
+            hdr_targets,
+            motion_blur_targets: None,
+            taa_targets: None,
+            eye_cameras: None,
+            anaglyph_targets: None,
+            gpu_timer: GpuTimer::new(&context.device, &context.queue),
+            pick_targets: None,
+            outline_targets: None,
+            hiz_targets: None,
+            camera,
+            camera_2d,
+            quads: ViewQuads::new(&context.device, &context.quad_2d),
+            projection,
+            controller,
+            controller_tunables,
+            units_per_meter: units.units_per_meter(),
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            mouse_pressed: false,
+            right_pressed: false,
+            cursor_grabbed: false,
+            cursor_position: None,
+            press_position: None,
+            right_press_position: None,
+            gizmo: ViewGizmo::new(&context.device, &context.gizmo_pipeline),
+            camera_snap: None,
+            camera_flight: None,
+            camera_follow: None,
+            last_frame: std::time::Instant::now(),
+            scale_factor: 1.0,
+            egui_state,
+            egui_renderer,
+            egui_frame_started: false,
+            egui_repaint_at: None,
+            applied_theme: None,
+        }
+    }
+
+    pub fn surface_diagnostics(&self) -> SurfaceDiagnostics<'_> {
+        SurfaceDiagnostics { capabilities: &self.surface_caps, config: &self.config }
+    }
+
+    pub fn window(&self) -> &Window {
+        self.window.as_ref()
+    }
+
+    // Called when this window resizes, other windows are untouched
+    pub fn resize(&mut self, context: &RenderContext, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            let device = &context.device;
+            let sample_count = context.settings.msaa_samples;
+            self.size = winit::dpi::PhysicalSize::new(width, height);
+            self.projection.resize(width, height);
+            self.camera_2d.resize(width, height);
+            self.config.width = width;
+            self.config.height = height;
+            self.surface.configure(device, &self.config);
+            self.is_surface_configured = true;
+            self.depth_texture = texture::Texture::create_depth_texture(device, &self.config, sample_count, "depth_texture");
+            self.particle_bindings = ParticleViewBindings::new(device, &context.particle_pipeline, &self.depth_texture.texture);
+            self.depth_debug_bindings = DepthDebugBindings::new(device, &context.depth_debug, &self.depth_texture.texture);
+            self.msaa_texture = (sample_count > 1)
+                .then(|| texture::Texture::create_msaa_texture(device, &self.config, context.scene_format, sample_count));
+            if let Some(pipelines) = context.hdr.as_ref() {
+                self.hdr_targets = Some(HdrTargets::new(device, pipelines, width, height));
+            }
+            if self.transients.is_some() {
+                self.rebuild_transients(context);
+            }
+            if self.pick_targets.is_some() {
+                self.pick_targets = Some(self.new_pick_targets(context));
+            }
+            if self.outline_targets.is_some() {
+                self.outline_targets = None;
+                self.prepare_outline(context, true);
+            }
+            if self.hiz_targets.is_some() {
+                self.hiz_targets = None;
+                self.prepare_hiz(context, true);
+            }
+        }
+    }
+
+    // Declares this frame's offscreen passes and reallocates their textures when the set of
+    // passes changed. Motion blur and TAA only count when their pipelines exist (HDR on).
+    pub fn prepare_transients(&mut self, context: &RenderContext, ssao: bool, motion_blur: bool, taa: bool, anaglyph: bool) {
+        let taa = taa && context.taa.is_some() && context.motion_blur.is_some();
+        let passes = (ssao, motion_blur && context.motion_blur.is_some(), taa, anaglyph);
+        if self.transients.is_none() || passes != self.transient_passes {
+            self.transient_passes = passes;
+            self.rebuild_transients(context);
+        }
+    }
+
+    fn rebuild_transients(&mut self, context: &RenderContext) {
+        let (ssao, motion_blur, taa, anaglyph) = self.transient_passes;
+        let mut graph = FrameGraph::default();
+        let ssao_ids = ssao.then(|| SsaoTransients::declare(&mut graph, &self.config));
+        let anaglyph_ids = anaglyph.then(|| AnaglyphTransients::declare(&mut graph, &self.config, context.scene_format));
+        if anaglyph_ids.is_none() {
+            graph.pass("main", &[], &[]);
+        }
+        if let Some(ids) = &ssao_ids {
+            ids.declare_composite(&mut graph);
+        }
+        // TAA resolves the jittered frame before the blur smears it
+        let mut motion_blur_ids = (motion_blur || taa).then(|| MotionBlurTransients::declare(&mut graph, &self.config));
+        let taa_ids = motion_blur_ids.as_ref().filter(|_| taa).map(|velocity| TaaTransients::declare(&mut graph, &self.config, velocity));
+        if let Some(ids) = motion_blur_ids.as_mut().filter(|_| motion_blur) {
+            ids.declare_blur(&mut graph, &self.config);
+        }
+        // The old textures go first, so the peak doesn't hold both sets
+        self.ssao_targets = None;
+        self.motion_blur_targets = None;
+        self.taa_targets = None;
+        self.anaglyph_targets = None;
+        let transients = Transients::new(&context.device, &graph);
+        self.anaglyph_targets = anaglyph_ids.map(|ids| AnaglyphTargets::new(&context.device, &context.anaglyph, &transients, &ids));
+        self.ssao_targets = ssao_ids.map(|ids| SsaoTargets::new(&context.device, &context.ssao, &transients, &ids));
+        self.taa_targets = taa_ids
+            .zip(motion_blur_ids.as_ref())
+            .zip(context.taa.as_ref())
+            .map(|((ids, velocity), pipeline)| TaaTargets::new(&context.device, pipeline, &transients, &ids, velocity, &self.config));
+        self.motion_blur_targets = motion_blur_ids
+            .zip(context.motion_blur.as_ref())
+            .map(|(ids, pipelines)| MotionBlurTargets::new(&context.device, pipelines, &transients, &ids));
+        self.transients = Some(transients);
+    }
+
+    // Bytes of the offscreen passes' textures with and without sharing, None before the first frame
+    pub fn transient_stats(&self) -> Option<TransientStats> {
+        self.transients.as_ref().map(Transients::stats)
+    }
+
+    // Upload this frame's SSAO parameters, the targets come from prepare_transients
+    pub fn prepare_ssao(&mut self, context: &RenderContext, settings: &SsaoSettings) {
+        if let Some(targets) = &self.ssao_targets {
+            targets.update(&context.queue, &context.ssao, settings, &self.camera, &self.projection);
+        }
+    }
+
+    // Upload this frame's eye cameras, dropped again when stereo is off. Returns the frustum
+    // holding both eyes, None while stereo is off.
+    pub fn prepare_stereo(&mut self, context: &RenderContext, settings: &StereoSettings, units_per_meter: f32) -> Option<Frustum> {
+        if settings.mode == StereoMode::Off {
+            self.eye_cameras = None;
+            return None;
+        }
+        let cameras = self.eye_cameras.get_or_insert_with(|| EyeCameras::new(context));
+        cameras.update(&context.queue, settings, units_per_meter, &self.camera, &self.projection)
+    }
+
+    pub fn eye_camera_bind_group(&self, eye: Eye) -> Option<&wgpu::BindGroup> {
+        self.eye_cameras.as_ref().map(|cameras| cameras.bind_group(eye))
+    }
+
+    pub fn anaglyph_targets(&self) -> Option<&AnaglyphTargets> {
+        self.anaglyph_targets.as_ref()
+    }
+
+    pub fn ssao_targets(&self) -> Option<&SsaoTargets> {
+        self.ssao_targets.as_ref()
+    }
+
+    // Upload this frame's cameras, the targets come from prepare_transients. None when there is
+    // no velocity this frame (blur, TAA or HDR off, the first frame, or the camera jumped).
+    pub fn prepare_velocity(&mut self, context: &RenderContext, settings: &MotionBlurSettings) -> Option<Reprojection> {
+        self.motion_blur_targets.as_mut()?.update(&context.queue, settings, &self.camera, &self.projection)
+    }
+
+    // Jitter this frame's camera and upload the resolve parameters, after update_camera and
+    // before the passes drawing with the camera. Does nothing while TAA is off.
+    pub fn prepare_taa(&mut self, context: &RenderContext, settings: &TaaSettings, reprojection: Option<&Reprojection>) {
+        let Some(targets) = self.taa_targets.as_mut() else {
+            return;
+        };
+        let jitter = targets.next_jitter(settings);
+        targets.update(&context.queue, settings, reprojection);
+        self.camera_uniform.jitter(taa::jitter_matrix(jitter, (self.config.width, self.config.height)));
+        context.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    }
+
+    // Blend the HDR frame with the history, before the motion blur
+    pub fn encode_taa(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder) {
+        if let (Some(targets), Some(hdr), Some(pipeline)) = (&self.taa_targets, &self.hdr_targets, context.taa.as_ref()) {
+            targets.encode_resolve(encoder, pipeline, hdr.color_texture(), hdr.color_view());
+        }
+    }
+
+    pub fn motion_blur_targets(&self) -> Option<&MotionBlurTargets> {
+        self.motion_blur_targets.as_ref()
+    }
+
+    // Blur the HDR frame along the velocity the prepass wrote
+    pub fn encode_motion_blur(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder) {
+        if let (Some(targets), Some(hdr), Some(pipelines)) = (&self.motion_blur_targets, &self.hdr_targets, context.motion_blur.as_ref()) {
+            targets.encode_blur(encoder, pipelines, hdr.color_texture(), hdr.color_view());
+        }
+    }
+
+    // The finished scene goes here: the HDR target when HDR is on, otherwise the swapchain itself
+    pub fn scene_target<'a>(&'a self, surface_view: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        match &self.hdr_targets {
+            Some(targets) => targets.color_view(),
+            None => surface_view,
+        }
+    }
+
+    // Tonemap the HDR scene into the swapchain, does nothing when HDR is off
+    pub fn encode_tonemap(&mut self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder, settings: &HdrSettings, surface_view: &wgpu::TextureView) {
+        if let (Some(targets), Some(pipelines)) = (self.hdr_targets.as_mut(), context.hdr.as_ref()) {
+            targets.encode_tonemap(encoder, &context.queue, pipelines, settings, surface_view);
+        }
+    }
+
+    pub fn gpu_timer(&self) -> Option<&GpuTimer> {
+        self.gpu_timer.as_ref()
+    }
+
+    pub fn gpu_timer_mut(&mut self) -> Option<&mut GpuTimer> {
+        self.gpu_timer.as_mut()
+    }
+
+    fn new_pick_targets(&self, context: &RenderContext) -> PickTargets {
+        PickTargets::new(&context.device, &context.picking, &self.config, context.settings.msaa_samples > 1)
+    }
+
+    // The instance at `position` (physical pixels), None if no instance covers it. Blocks until
+    // the GPU has drawn it, meant for clicks rather than every frame.
+    pub fn pick_instance(&mut self, context: &RenderContext, draws: &[PickDraw], position: (f32, f32)) -> Option<PickResult> {
+        if !self.is_surface_configured {
+            return None;
+        }
+        if self.pick_targets.is_none() {
+            self.pick_targets = Some(self.new_pick_targets(context));
+        }
+        let targets = self.pick_targets.as_mut()?;
+        // The scene depth can be tested against directly unless it is multisampled
+        let scene_depth = (context.settings.msaa_samples == 1).then_some(&self.depth_texture.view);
+        let pixel = (position.0.max(0.0) as u32, position.1.max(0.0) as u32);
+        targets.pick(context, scene_depth, &self.camera_bind_group, draws, pixel)
+    }
+
+    // Makes the outline's targets, and the pick targets whose IDs it reads, when `enabled` and
+    // they don't exist yet. Off, they are dropped and clicks draw their own pixel again.
+    pub fn prepare_outline(&mut self, context: &RenderContext, enabled: bool) {
+        if !enabled {
+            self.outline_targets = None;
+            if let Some(targets) = self.pick_targets.as_mut() {
+                targets.forget_frame();
+            }
+            return;
+        }
+        if self.outline_targets.is_some() {
+            return;
+        }
+        let pick_targets = match self.pick_targets.take() {
+            Some(targets) => targets,
+            None => self.new_pick_targets(context),
+        };
+        self.outline_targets = Some(OutlineTargets::new(&context.device, &context.outline, &self.config, &pick_targets));
+        self.pick_targets = Some(pick_targets);
+    }
+
+    // Makes the depth pyramid when `enabled` and the adapter can cull on the GPU, drops it otherwise
+    pub fn prepare_hiz(&mut self, context: &RenderContext, enabled: bool) {
+        let pipelines = context.hiz.as_ref().zip(context.instance_cull.as_ref()).filter(|_| enabled);
+        match pipelines {
+            Some((hiz, cull)) if self.hiz_targets.is_none() => {
+                self.hiz_targets = Some(HiZTargets::new(&context.device, hiz, cull, &self.depth_texture.texture));
+            }
+            Some(_) => {}
+            None => self.hiz_targets = None,
+        }
+    }
+
+    // Builds the pyramid from the depth pre-pass's depth, None before prepare_hiz made it
+    pub fn encode_hiz(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder) -> Option<&HiZTargets> {
+        let (targets, pipelines) = self.hiz_targets.as_ref().zip(context.hiz.as_ref())?;
+        targets.encode(encoder, pipelines);
+        Some(targets)
+    }
+
+    // The frame's IDs, normals and depth, after the scene pass. Does nothing before
+    // prepare_outline turned the outline on.
+    pub fn encode_outline_ids(&mut self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder, draws: &[PickDraw]) {
+        let (Some(pick_targets), Some(outline_targets)) = (self.pick_targets.as_mut(), &self.outline_targets) else {
+            return;
+        };
+        let scene_depth = (context.settings.msaa_samples == 1).then_some(&self.depth_texture.view);
+        pick_targets.encode_frame(context, encoder, scene_depth, outline_targets.normal_depth_view(), &self.camera_bind_group, draws);
+    }
+
+    // The outline over the tonemapped frame, from the IDs encode_outline_ids drew
+    pub fn encode_outline(
+        &self,
+        context: &RenderContext,
+        encoder: &mut wgpu::CommandEncoder,
+        settings: &OutlineSettings,
+        selected_id: Option<u32>,
+        target: &wgpu::TextureView,
+    ) {
+        if let Some(targets) = &self.outline_targets {
+            targets.write(&context.queue, settings, selected_id);
+            targets.encode(encoder, &context.outline, target);
+        }
+    }
+
+    pub fn particle_bindings(&self) -> &ParticleViewBindings {
+        &self.particle_bindings
+    }
+
+    // Where the scene pass should draw, and what (if anything) it resolves into
+    pub fn color_attachment<'a>(&'a self, surface_view: &'a wgpu::TextureView) -> (&'a wgpu::TextureView, Option<&'a wgpu::TextureView>) {
+        match &self.msaa_texture {
+            Some(msaa_view) => (msaa_view, Some(surface_view)),
+            None => (surface_view, None),
+        }
+    }
+
+    pub fn handle_move(&mut self, action: Action, is_pressed: bool) -> bool {
+        self.controller.handle_key(action, is_pressed)
+    }
+
+    pub fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        match button {
+            MouseButton::Left => {
+                self.mouse_pressed = pressed;
+                if pressed {
+                    self.press_position = self.cursor_position;
+                }
+            }
+            MouseButton::Right => {
+                self.right_pressed = pressed;
+                if pressed {
+                    self.right_press_position = self.cursor_position;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Either button held turns the camera with the mouse
+    pub fn is_dragging(&self) -> bool {
+        self.mouse_pressed || self.right_pressed
+    }
+
+    // Key releases that happen while the window can't see them never arrive, so drop held input
+    pub fn release_input(&mut self) {
+        self.controller.input_mut().reset_input();
+        self.mouse_pressed = false;
+        self.right_pressed = false;
+    }
+
+    pub fn handle_cursor_moved(&mut self, x: f64, y: f64) {
+        self.cursor_position = Some((x as f32, y as f32));
+    }
+
+    // Ray through the pixel under the cursor, None until the cursor has been over the window
+    pub fn cursor_ray(&self) -> Option<Ray> {
+        self.ray_through(self.cursor_position?)
+    }
+
+    // Ray through a pixel of this window, in physical pixels
+    pub fn ray_through(&self, position: (f32, f32)) -> Option<Ray> {
+        let inv_view_proj = (self.projection.calc_matrix() * self.camera.calc_matrix()).invert()?;
+        Ray::from_screen(position, (self.config.width as f32, self.config.height as f32), inv_view_proj)
+    }
+
+    // Physical pixels, None before the cursor first entered the window
+    pub fn cursor_position(&self) -> Option<(f32, f32)> {
+        self.cursor_position
+    }
+
+    // On a left release: where the click was, if the cursor stayed put since the press.
+    // A release after dragging the camera around is no click.
+    pub fn take_click(&mut self) -> Option<(f32, f32)> {
+        let press = self.press_position.take();
+        self.click_from(press)
+    }
+
+    // The same for the right button
+    pub fn take_right_click(&mut self) -> Option<(f32, f32)> {
+        let press = self.right_press_position.take();
+        self.click_from(press)
+    }
+
+    fn click_from(&self, press: Option<(f32, f32)>) -> Option<(f32, f32)> {
+        let (px, py) = press?;
+        let (x, y) = self.cursor_position?;
+        ((x - px).hypot(y - py) <= CLICK_SLOP).then_some((x, y))
+    }
+
+    pub fn gizmo_rect(&self) -> GizmoRect {
+        let top_inset = if self.custom_title_bar { TITLE_BAR_HEIGHT } else { 0.0 };
+        GizmoRect::for_window(self.config.width, self.config.height, self.window.scale_factor() as f32, top_inset)
+    }
+
+    // Start swinging the camera if the cursor is over a gizmo face. Returns true if the click was used.
+    pub fn click_gizmo(&mut self) -> bool {
+        let Some((x, y)) = self.cursor_position else {
+            return false;
+        };
+        let rect = self.gizmo_rect();
+        if !rect.contains(x, y) {
+            return false;
+        }
+        if let Some(normal) = gizmo::pick_face(&self.camera, rect, x, y) {
+            self.camera_snap = Some(CameraSnap::towards_face(&self.camera, normal));
+        }
+        true
+    }
+
+    // Move the camera to `position` over the next frames, keeping its orientation
+    pub fn fly_to(&mut self, position: cgmath::Point3<f32>) {
+        self.camera_flight = Some(CameraFlight::new(&self.camera, position));
+    }
+
+    // The camera eases over from where it is to `target + offset`
+    pub fn start_following(&mut self, target: cgmath::Point3<f32>, offset: cgmath::Vector3<f32>, stiffness: f32) {
+        self.camera_flight = None;
+        self.camera_follow = Some(CameraFollow::new(target, offset, stiffness));
+    }
+
+    // Where the followed target is this frame. False once following stopped (movement keys).
+    pub fn follow_target(&mut self, target: cgmath::Point3<f32>, stiffness: f32) -> bool {
+        let Some(follow) = self.camera_follow.as_mut() else {
+            return false;
+        };
+        follow.set_target(target);
+        follow.stiffness = stiffness;
+        true
+    }
+
+    // Back to free flight, the camera stays where it is
+    pub fn stop_following(&mut self) {
+        self.camera_follow = None;
+    }
+
+    // Put the camera at `eye` looking at `target` outright, ending any flight, snap or follow
+    pub fn place_camera(&mut self, eye: cgmath::Point3<f32>, target: cgmath::Point3<f32>) {
+        self.camera_snap = None;
+        self.camera_flight = None;
+        self.camera_follow = None;
+        self.camera.position = eye;
+        self.camera.look_at(target);
+    }
+
+    // Linearized depth of the last scene pass in the bottom left corner of `target`
+    pub fn draw_depth_thumbnail(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let size = (self.config.width, self.config.height);
+        self.depth_debug_bindings.draw_thumbnail(context, encoder, target, size, &self.projection);
+    }
+
+    // Depth of the last frame as a grayscale image, blocks until the GPU has copied it back
+    pub fn capture_depth(&self, context: &RenderContext) -> anyhow::Result<image::GrayImage> {
+        self.depth_debug_bindings.capture(context, &self.depth_texture.texture, &self.projection)
+    }
+
+    // The finished frame as an RGBA image, blocks until the GPU has copied it back
+    pub fn capture_frame(context: &RenderContext, frame: &wgpu::Texture) -> anyhow::Result<image::RgbaImage> {
+        if !frame.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            anyhow::bail!("this window's surface can't be copied from");
+        }
+        let swap_red_blue = match frame.format() {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            other => anyhow::bail!("can't save a {:?} surface", other),
+        };
+        let device = &context.device;
+        let (width, height) = (frame.width(), frame.height());
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * 4).div_ceil(align) * align;
+        let readback_buffer = gpu_memory::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Frame Capture Readback Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Frame Capture Encoder") });
+        encoder.copy_texture_to_buffer(
+            frame.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+        context.queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()??;
+
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in mapped.chunks_exact(bytes_per_row as usize) {
+            for texel in row[..width as usize * 4].chunks_exact(4) {
+                let [r, g, b] = if swap_red_blue { [texel[2], texel[1], texel[0]] } else { [texel[0], texel[1], texel[2]] };
+                // The surface's alpha is whatever the compositor ignores, the saved image is opaque
+                pixels.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        drop(mapped);
+        readback_buffer.unmap();
+        image::RgbaImage::from_raw(width, height, pixels).ok_or_else(|| anyhow::anyhow!("frame capture has the wrong size"))
+    }
+
+    pub fn draw_quads(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder, quads: &QuadBatcher, target: &wgpu::TextureView) {
+        quads.draw(&context.queue, encoder, &context.quad_2d, &self.quads, &self.camera_2d, target);
+    }
+
+    pub fn draw_gizmo(&self, context: &RenderContext, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        self.gizmo.draw(&context.queue, encoder, &context.gizmo_pipeline, target, &self.camera, self.gizmo_rect());
+    }
+
+    pub fn handle_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
+        if self.right_pressed {
+            self.controller.input_mut().handle_speed_scroll(delta);
+        } else {
+            self.controller.handle_scroll(delta);
+        }
+    }
+
+    // Fly speed and near/far planes for the scene's units, the speed multiplier is kept
+    pub fn apply_units(&mut self, units: SceneUnits) {
+        self.controller.input_mut().set_base_speed(units.camera_speed());
+        self.projection.set_depth_range(units.depth_range());
+        self.units_per_meter = units.units_per_meter();
+    }
+
+    // Switch to the scheme for `profile` when it isn't the current one. The new scheme starts from
+    // the camera as it is and keeps the held keys and speed.
+    pub fn set_controller_profile(&mut self, profile: ControllerProfile, tunables: &ControllerTunables) {
+        self.controller_tunables = *tunables;
+        if self.controller.profile() == profile {
+            return;
+        }
+        // The old scheme is dropped right after, what it is left with doesn't matter
+        let input = std::mem::replace(self.controller.input_mut(), Controller::new(0.0, 1.0));
+        self.controller = camera_controller::new_controller(profile, input, &self.camera, tunables, self.units_per_meter);
+        // A framing flight or follow would fight the new scheme for the camera
+        self.camera_flight = None;
+        self.camera_follow = None;
+    }
+
+    // Level the horizon at once, auto-level eases there instead
+    pub fn reset_roll(&mut self) {
+        self.camera.set_roll(cgmath::Rad(0.0));
+        self.window().request_redraw();
+    }
+
+    // Move this window's camera and push the result to its uniform buffer
+    pub fn update_camera(&mut self, queue: &wgpu::Queue) {
+        let now = std::time::Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        if let Some(snap) = self.camera_snap.as_mut()
+            && !snap.update(&mut self.camera, dt) {
+                self.camera_snap = None;
+            }
+        // Flying yourself takes over from the framing flight
+        if self.controller.input().is_moving() {
+            self.camera_flight = None;
+        }
+        // So do the movement keys from following, the mouse and scroll wheel orbit the target
+        if self.controller.input().is_translating() {
+            self.camera_follow = None;
+        }
+        if let Some(follow) = self.camera_follow.as_mut() {
+            follow.update(&mut self.camera, self.controller.input_mut(), dt);
+        }
+        if let Some(flight) = self.camera_flight.as_mut()
+            && !flight.update(&mut self.camera, dt)
+        {
+            self.camera_flight = None;
+        }
+        self.controller.update_camera(&mut self.camera, &self.controller_tunables, self.units_per_meter, dt);
+        self.camera_uniform.update_view_proj(&self.camera, &self.projection);
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        self.particle_bindings.update(queue, &self.camera, &self.projection);
+    }
+
+    pub fn current_texture(&mut self, context: &RenderContext) -> Result<wgpu::SurfaceTexture, wgpu::SurfaceError> {
+        if !self.is_surface_configured {
+            self.resize(context, self.size.width, self.size.height);
+        }
+        self.surface.get_current_texture()
+    }
+
+    pub fn screen_descriptor(&self) -> ScreenDescriptor {
+        ScreenDescriptor {
+            size_in_pixels: [self.config.width, self.config.height],
+            pixels_per_point: self.window.scale_factor() as f32 * self.scale_factor,
+        }
+    }
+
+    // Something in this window is still moving: the camera, or egui asked to be drawn right away
+    pub fn needs_redraw(&self) -> bool {
+        self.controller.input().is_moving()
+            || camera_controller::is_leveling(&self.camera, &self.controller_tunables)
+            || self.camera_snap.is_some()
+            || self.camera_flight.is_some()
+            || self.camera_follow.is_some()
+            || self.egui_repaint_at.is_some_and(|at| at <= std::time::Instant::now())
+    }
+
+    // egui asked to be drawn again later, wake the event loop then
+    pub fn egui_repaint_at(&self) -> Option<std::time::Instant> {
+        self.egui_repaint_at
+    }
+
+    pub fn egui_context(&self) -> Context {
+        self.egui_state.egui_ctx().clone()
+    }
+
+    pub fn handle_input(&mut self, event: &WindowEvent) -> bool {
+        let response = self.egui_state.on_window_event(&self.window, event);
+        response.consumed
+    }
+
+    pub fn ppp(&mut self, v: f32) {
+        self.egui_context().set_pixels_per_point(v);
+    }
+
+    pub fn begin_frame(&mut self, theme: &EngineTheme) {
+        if self.applied_theme.as_ref() != Some(theme) {
+            theme.apply(self.egui_state.egui_ctx());
+            self.applied_theme = Some(*theme);
+        }
+        let raw_input = self.egui_state.take_egui_input(&self.window);
+        self.egui_state.egui_ctx().begin_pass(raw_input);
+        self.egui_frame_started = true;
+    }
+
+    pub fn end_frame_and_draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut CommandEncoder,
+        window_surface_view: &TextureView,
+        screen_descriptor: ScreenDescriptor,
+    ) {
+        if !self.egui_frame_started {
+            panic!("begin_frame must be called before end_frame_and_draw can be called!");
+        }
+
+        self.ppp(screen_descriptor.pixels_per_point);
+
+        let full_output = self.egui_state.egui_ctx().end_pass();
+        let repaint_delay = full_output
+            .viewport_output
+            .get(&egui::viewport::ViewportId::ROOT)
+            .map_or(std::time::Duration::MAX, |viewport| viewport.repaint_delay);
+        self.egui_repaint_at = std::time::Instant::now().checked_add(repaint_delay);
+
+        self.egui_state
+            .handle_platform_output(&self.window, full_output.platform_output);
+
+        let tris = self
+            .egui_state
+            .egui_ctx()
+            .tessellate(full_output.shapes, self.egui_state.egui_ctx().pixels_per_point());
+        for (id, image_delta) in &full_output.textures_delta.set {
+            self.egui_renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+        self.egui_renderer
+            .update_buffers(device, queue, encoder, &tris, &screen_descriptor);
+        let rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: window_surface_view,
+                resolve_target: None,
+                ops: egui_wgpu::wgpu::Operations {
+                    load: egui_wgpu::wgpu::LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            label: Some("egui main render pass"),
+            occlusion_query_set: None,
+        });
+
+        self.egui_renderer
+            .render(&mut rpass.forget_lifetime(), &tris, &screen_descriptor);
+        for x in &full_output.textures_delta.free {
+            self.egui_renderer.free_texture(x)
+        }
+
+        self.egui_frame_started = false;
+    }
+}